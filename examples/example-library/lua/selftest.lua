@@ -0,0 +1,16 @@
+-- Calls every function of the `example` library and checks the results.
+
+local sum, product = example.Add(6, 7)
+assert(sum == 13, "Add: wrong sum")
+assert(product == 42, "Add: wrong product")
+
+assert(example.Greet("Garry") == "Hello, Garry!", "Greet: wrong greeting")
+assert(example.Greet("Garry", true) == "HELLO, GARRY!", "Greet: wrong loud greeting")
+assert(not pcall(example.Greet), "Greet: accepted a missing name")
+
+local ok = example.ParsePort("27015")
+assert(ok.ok and ok.value == 27015, "ParsePort: didn't parse a valid port")
+local err = example.ParsePort("http")
+assert(not err.ok and err.code == "INVALID_INPUT", "ParsePort: wrong error for an invalid port")
+
+print("[example] self test passed")
@@ -0,0 +1,64 @@
+//! A complete binary module registering an `example` library. See the README for how to try it in game.
+
+#[macro_use]
+extern crate gmod;
+
+use gmod::{
+    error::{ErrorCode, ErrorTable},
+    lua::{LuaReg, State},
+    scripts::Bundle,
+};
+
+static SCRIPTS: Bundle = include_lua_dir!("lua/");
+
+/// `example.Add(a, b)` returns both the sum and the product.
+#[lua_function]
+fn add(_lua: State, a: f64, b: f64) -> (f64, f64) {
+    (a + b, a * b)
+}
+
+/// `example.Greet(name, loud?)`
+#[lua_function]
+fn greet(_lua: State, name: String, loud: Option<bool>) -> (String,) {
+    let greeting = format!("Hello, {name}!");
+    if loud.unwrap_or(false) {
+        (greeting.to_uppercase(),)
+    } else {
+        (greeting,)
+    }
+}
+
+/// `example.ParsePort(str)` returns `{ ok = true, value = port }` or `{ ok = false, code = "INVALID_INPUT", message }`.
+#[lua_function(result_table)]
+fn parse_port(_lua: State, port: String) -> Result<u16, ErrorTable> {
+    port.trim()
+        .parse()
+        .map_err(|err| ErrorTable::new(ErrorCode::InvalidInput, format!("invalid port: {err}")))
+}
+
+/// `example.SelfTest()` runs the embedded `selftest.lua`.
+#[lua_function]
+fn self_test(lua: State) -> anyhow::Result<()> {
+    SCRIPTS.run(lua, "selftest.lua")
+}
+
+#[gmod13_open]
+fn gmod13_open(lua: State) -> i32 {
+    lua.register(
+        c"example".as_ptr(),
+        lua_regs![
+            "Add" => add,
+            "Greet" => greet,
+            "ParsePort" => parse_port,
+            "SelfTest" => self_test,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+    0
+}
+
+#[gmod13_close]
+fn gmod13_close(lua: State) -> i32 {
+    0
+}
@@ -35,7 +35,10 @@ fn parse_lua_ident(input: &syn::FnArg) -> syn::Ident {
 fn check_lua_function(input: &mut ItemFn) {
     assert!(input.sig.asyncness.is_none(), "Cannot be async");
     assert!(input.sig.constness.is_none(), "Cannot be const");
-    assert!(input.sig.inputs.len() == 1, "There can only be one argument, and it should be a pointer to the Lua state (gmod::lua::State)");
+    assert!(
+        !input.sig.inputs.is_empty(),
+        "The first argument should be a pointer to the Lua state (gmod::lua::State)"
+    );
     assert!(
         input.sig.abi.is_none()
             || input
@@ -50,7 +53,19 @@ fn check_lua_function(input: &mut ItemFn) {
     input.sig.abi = Some(syn::parse_quote!(extern "C-unwind"));
 }
 
-fn genericify_return(item_fn: &mut ItemFn) -> proc_macro2::TokenStream {
+fn check_single_argument(input: &ItemFn) {
+    assert!(input.sig.inputs.len() == 1, "There can only be one argument, and it should be a pointer to the Lua state (gmod::lua::State)");
+}
+
+/// `prelude` runs in the exported function before the arguments are checked, and can `return` an `i32` early. `epilogue` runs after the function returned (unless it raised a Lua error).
+///
+/// With `result_table`, the returned `Result` is pushed as an `{ ok = ... }` table through `ResultTable` instead of raising errors.
+fn genericify_return(
+    item_fn: &mut ItemFn,
+    prelude: proc_macro2::TokenStream,
+    epilogue: proc_macro2::TokenStream,
+    result_table: bool,
+) -> proc_macro2::TokenStream {
     // let stmts = std::mem::take(&mut item_fn.block.stmts);
     // let output = std::mem::replace(&mut item_fn.sig.output, parse_quote!(-> i32));
 
@@ -73,16 +88,57 @@ fn genericify_return(item_fn: &mut ItemFn) -> proc_macro2::TokenStream {
         syn::ReturnType::Type(_, ty) => quote!(#ty),
     };
 
-    let lua_ident = parse_lua_ident(&inputs[0]);
+    let lua_arg = &inputs[0];
+    let lua_ident = parse_lua_ident(lua_arg);
+
+    // Every argument after the Lua state is checked with `LuaCheck`, starting at argument #1
+    let (arg_idents, arg_checks): (Vec<_>, Vec<_>) = inputs
+        .iter()
+        .skip(1)
+        .enumerate()
+        .map(|(i, input)| {
+            let ty = match input {
+                syn::FnArg::Typed(arg) => &arg.ty,
+                syn::FnArg::Receiver(_) => panic!("Can't use self for lua functions!"),
+            };
+            let ident = syn::Ident::new(&format!("__arg{}", i + 1), proc_macro2::Span::call_site());
+            let index = proc_macro2::Literal::i32_unsuffixed(i as i32 + 1);
+            let check = quote! {
+                let #ident = match <#ty as ::gmod::lua::LuaCheck>::lua_check(#lua_ident, #index) {
+                    Ok(value) => value,
+                    Err(err) => return Err::<i32, _>(err).handle_result(#lua_ident),
+                };
+            };
+            (ident, check)
+        })
+        .unzip();
 
     let internal_name = syn::Ident::new(
         &format!("__{name}_internal__"),
         proc_macro2::Span::call_site(),
     );
 
+    let profile = if cfg!(feature = "profile") {
+        quote!(let __profile = ::gmod::profile::scope(concat!(module_path!(), "::", stringify!(#name)));)
+    } else {
+        quote!()
+    };
+
+    let (checked_type, call) = if result_table {
+        (
+            quote!(::gmod::lua::ResultTable<#return_type>),
+            quote!(::gmod::lua::ResultTable(#internal_name(#lua_ident, #(#arg_idents),*))),
+        )
+    } else {
+        (
+            quote!(#return_type),
+            quote!(#internal_name(#lua_ident, #(#arg_idents),*)),
+        )
+    };
+
     let output = quote! {
         #(#attrs)*
-        #vis extern "C-unwind" fn #name(#inputs) -> i32
+        #vis extern "C-unwind" fn #name(#lua_arg) -> i32
         {
             #(#attrs)*
             #[inline]
@@ -95,10 +151,18 @@ fn genericify_return(item_fn: &mut ItemFn) -> proc_macro2::TokenStream {
             {
                 fn assert_send<T: ::gmod::lua::HandleLuaFunctionReturn>() {}
                 fn assert() {
-                    assert_send::<#return_type>();
+                    assert_send::<#checked_type>();
                 }
             }
-            #internal_name(#lua_ident).handle_result(#lua_ident)
+            #prelude
+            #profile
+            #(#arg_checks)*
+            let __ret = match ::gmod::panic::catch(|| #call) {
+                Ok(ret) => ret.handle_result(#lua_ident),
+                Err(panic) => Err::<i32, _>(panic).handle_result(#lua_ident),
+            };
+            #epilogue
+            __ret
         }
     };
 
@@ -112,26 +176,64 @@ pub fn gmod13_open(_attr: TokenStream, tokens: TokenStream) -> TokenStream {
 
         // Make sure it's valid
         check_lua_function(&mut input);
+        assert!(
+            input.sig.inputs.len() <= 2,
+            "gmod13_open takes the Lua state (gmod::lua::State), and optionally a gmod::lifecycle::ReloadContext"
+        );
 
         let lua_ident = parse_lua_ident(&input.sig.inputs[0]);
 
-        let block = input.block;
-        input.block = syn::parse2(quote! {{
-            #[allow(unused_unsafe)]
-            unsafe {
-                ::gmod::lua::load()
+        // The context is passed by an inner function, as the exported function only takes the Lua state
+        if input.sig.inputs.len() == 2 {
+            let inputs = input.sig.inputs.clone();
+            let output = &input.sig.output;
+            let block = &input.block;
+            input.block = syn::parse2(quote! {{
+                #[inline]
+                fn __gmod13_open_ctx__(#inputs) #output #block
+                __gmod13_open_ctx__(#lua_ident, ::gmod::lifecycle::reload_context())
+            }})
+            .unwrap();
+            input.sig.inputs.pop();
+            input.sig.inputs.pop_punct();
+        }
+
+        let register_exports = if cfg!(feature = "export") {
+            quote!(::gmod::export::register(#lua_ident);)
+        } else {
+            quote!()
+        };
+
+        // Nothing can be done without lua_shared, so the module stays inert instead of crashing the game
+        // When another state already opened the module, only the parts that belong to this state are set up
+        let prelude = quote! {
+            if ::gmod::lifecycle::open_realms().is_empty() {
+                ::gmod::lifecycle::set_state(::gmod::lifecycle::ModuleState::Loading);
+
+                #[allow(unused_unsafe)]
+                if let Err(err) = unsafe { ::gmod::lua::load() } {
+                    eprintln!("[gmod-rs] {err}");
+                    ::gmod::lifecycle::set_state(::gmod::lifecycle::ModuleState::Closed);
+                    return 0;
+                }
             }
 
+            ::gmod::lua::set_main_thread();
             ::gmod::lua::task_queue::load(#lua_ident);
-
-            #block
-        }})
-        .unwrap();
+            ::gmod::lifecycle::opened(#lua_ident);
+            #register_exports
+        };
 
         // No mangling
         input.attrs.push(parse_quote!(#[no_mangle]));
 
-        Ok(genericify_return(&mut input).into())
+        Ok(genericify_return(
+            &mut input,
+            prelude,
+            quote!(::gmod::lifecycle::set_state(::gmod::lifecycle::ModuleState::Running);),
+            false,
+        )
+        .into())
     })
 }
 
@@ -142,6 +244,7 @@ pub fn gmod13_close(_attr: TokenStream, tokens: TokenStream) -> TokenStream {
 
         // Make sure it's valid
         check_lua_function(&mut input);
+        check_single_argument(&input);
 
         // No mangling
         input.attrs.push(parse_quote!(#[no_mangle]));
@@ -149,21 +252,133 @@ pub fn gmod13_close(_attr: TokenStream, tokens: TokenStream) -> TokenStream {
         let lua_ident = parse_lua_ident(&input.sig.inputs[0]);
 
         let block = input.block;
+
+        let ipc_unload = if cfg!(feature = "ipc") {
+            quote!(::gmod::ipc::unload();)
+        } else {
+            quote!()
+        };
+
+        let fswatch_unload = if cfg!(feature = "fswatch") {
+            quote!(::gmod::fswatch::unload();)
+        } else {
+            quote!()
+        };
+
+        let oauth_unload = if cfg!(feature = "oauth") {
+            quote!(::gmod::oauth::unload();)
+        } else {
+            quote!()
+        };
+
+        let detour_unload = if cfg!(feature = "detour") {
+            quote! {
+                ::gmod::vtable::unload();
+                ::gmod::detour::unload();
+            }
+        } else {
+            quote!()
+        };
+
+        // The task queue, module data, HTTP callbacks and interned strings belong to this state, the rest is only torn down with the last state, as the library stays loaded until then
+        // Deferred calls run last to first
         input.block = syn::parse2(quote! {{
-            ::gmod::defer!(unsafe { ::gmod::lua::unload() });
+            let __gmod_last_state__ = ::gmod::lifecycle::closing(#lua_ident);
+            ::gmod::defer!(if __gmod_last_state__ {
+                unsafe { ::gmod::lua::unload() };
+                ::gmod::panic::uninstall();
+            });
             ::gmod::defer!(::gmod::lua::task_queue::unload(#lua_ident)); // we should be the last thing to run
+            ::gmod::defer!(if __gmod_last_state__ {
+                drop(::gmod::shutdown::run());
+            });
+            ::gmod::defer!(if __gmod_last_state__ {
+                ::gmod::lua::unload_closures(#lua_ident);
+                ::gmod::userdata::unload(#lua_ident);
+                ::gmod::net::unload(#lua_ident);
+                ::gmod::concommand::unload(#lua_ident);
+                ::gmod::convar::unload(#lua_ident);
+                ::gmod::timer::unload(#lua_ident);
+                ::gmod::hook::unload(#lua_ident);
+            });
+            ::gmod::defer!({
+                ::gmod::lua::intern::clear(#lua_ident);
+                ::gmod::http::unload(#lua_ident);
+                ::gmod::lua::unload_module_data(#lua_ident);
+            });
+            ::gmod::defer!(if __gmod_last_state__ {
+                #detour_unload
+                #oauth_unload
+                #fswatch_unload
+                #ipc_unload
+                ::gmod::proc::unload();
+                ::gmod::lua::debug_hook::clear(#lua_ident);
+            });
 
             #block
         }})
         .unwrap();
 
+        // `gmod13_open` failed to load lua_shared, so there's nothing to unload
+        let prelude = quote! {
+            if !::gmod::lua::is_loaded() {
+                return 0;
+            }
+            if ::gmod::lifecycle::open_realms().len() <= 1 {
+                ::gmod::lifecycle::set_state(::gmod::lifecycle::ModuleState::Closing);
+            }
+        };
+        let epilogue = quote! {
+            if ::gmod::lifecycle::open_realms().is_empty() {
+                ::gmod::lifecycle::set_state(::gmod::lifecycle::ModuleState::Closed);
+            }
+        };
+
         // Make the return type nice and dynamic
-        Ok(genericify_return(&mut input).into())
+        Ok(genericify_return(&mut input, prelude, epilogue, false).into())
+    })
+}
+
+/// Generates `gmod13_open` and `gmod13_close` for a struct implementing `gmod::module::GmodModule`, see `gmod::module`.
+#[proc_macro_attribute]
+pub fn gmod_module(_attr: TokenStream, tokens: TokenStream) -> TokenStream {
+    wrap_compile_error!(tokens, {
+        let input = syn::parse::<syn::ItemStruct>(tokens)?;
+        assert!(
+            input.generics.params.is_empty(),
+            "The module's struct can't be generic"
+        );
+
+        let ident = &input.ident;
+
+        Ok(quote! {
+            #input
+
+            #[::gmod::gmod13_open]
+            fn gmod13_open(lua: ::gmod::lua::State) -> ::gmod::module::OpenResult {
+                ::gmod::module::open::<#ident>(lua)
+            }
+
+            #[::gmod::gmod13_close]
+            fn gmod13_close(lua: ::gmod::lua::State) {
+                ::gmod::module::close::<#ident>(lua)
+            }
+        }
+        .into())
     })
 }
 
+/// Turns a function into a Lua C function. Arguments after the Lua state are checked with `LuaCheck`.
+///
+/// With `#[lua_function(result_table)]`, the function returns a `Result<T, E>` where `E: Into<ErrorTable>`, which is returned to Lua as `{ ok = true, value = ... }` or `{ ok = false, code = ..., message = ... }` instead of raising errors.
 #[proc_macro_attribute]
-pub fn lua_function(_attr: TokenStream, tokens: TokenStream) -> TokenStream {
+pub fn lua_function(attr: TokenStream, tokens: TokenStream) -> TokenStream {
+    let result_table = match attr.to_string().as_str() {
+        "" => false,
+        "result_table" => true,
+        other => panic!("Unknown #[lua_function] option `{other}`, expected `result_table`"),
+    };
+
     wrap_compile_error!(tokens, {
         let mut input = syn::parse::<ItemFn>(tokens)?;
 
@@ -171,6 +386,197 @@ pub fn lua_function(_attr: TokenStream, tokens: TokenStream) -> TokenStream {
         check_lua_function(&mut input);
 
         // Make the return type nice and dynamic
-        Ok(genericify_return(&mut input).into())
+        Ok(genericify_return(&mut input, quote!(), quote!(), result_table).into())
+    })
+}
+
+/// Same as `#[lua_function]`, and registers the function into a global table by `gmod13_open`, see `gmod::export`.
+///
+/// Takes `namespace = "..."`, and optionally `name = "..."` when the Lua name differs from the Rust one.
+#[proc_macro_attribute]
+pub fn lua_export(attr: TokenStream, tokens: TokenStream) -> TokenStream {
+    use syn::parse::Parser;
+
+    let options = syn::punctuated::Punctuated::<syn::MetaNameValue, Token![,]>::parse_terminated
+        .parse(attr)
+        .unwrap_or_else(|err| panic!("Invalid #[lua_export] options: {err}"));
+
+    let mut namespace = None;
+    let mut name = None;
+    for option in options {
+        let value = match &option.value {
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(value),
+                ..
+            }) => value.clone(),
+            _ => panic!("#[lua_export] options must be string literals"),
+        };
+        if option.path.is_ident("namespace") {
+            namespace = Some(value);
+        } else if option.path.is_ident("name") {
+            name = Some(value);
+        } else {
+            panic!("Unknown #[lua_export] option, expected `namespace` or `name`");
+        }
+    }
+    let namespace = namespace.expect("#[lua_export] needs a `namespace = \"...\"`");
+
+    wrap_compile_error!(tokens, {
+        let input = syn::parse::<ItemFn>(tokens)?;
+        let ident = &input.sig.ident;
+        let name = name.unwrap_or_else(|| syn::LitStr::new(&ident.to_string(), ident.span()));
+
+        Ok(quote! {
+            #[::gmod::lua_function]
+            #input
+
+            ::gmod::export::inventory::submit! {
+                ::gmod::export::Export {
+                    namespace: #namespace,
+                    name: #name,
+                    func: #ident,
+                }
+            }
+        }
+        .into())
+    })
+}
+
+/// Embeds every `.lua` file under a directory (relative to the crate's `Cargo.toml`) as a `gmod::scripts::Bundle`, recursively and sorted by path.
+///
+/// Each file is embedded with `include_str!`, so editing one rebuilds the crate. Adding or removing files isn't noticed until something else triggers a rebuild.
+#[proc_macro]
+pub fn include_lua_dir(tokens: TokenStream) -> TokenStream {
+    let dir = parse_macro_input!(tokens as syn::LitStr);
+    match include_lua_dir_impl(&dir) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn include_lua_dir_impl(dir: &syn::LitStr) -> Result<proc_macro2::TokenStream, syn::Error> {
+    fn collect(
+        root: &std::path::Path,
+        dir: &std::path::Path,
+        files: &mut Vec<(String, std::path::PathBuf)>,
+    ) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                collect(root, &path, files)?;
+            } else if path.extension().is_some_and(|ext| ext == "lua") {
+                let relative = path
+                    .strip_prefix(root)
+                    .unwrap()
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push((relative, path));
+            }
+        }
+        Ok(())
+    }
+
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map_err(|_| syn::Error::new(dir.span(), "CARGO_MANIFEST_DIR isn't set"))?;
+    let root = std::path::Path::new(&manifest_dir).join(dir.value());
+
+    let mut files = Vec::new();
+    collect(&root, &root, &mut files).map_err(|err| {
+        syn::Error::new(
+            dir.span(),
+            format!("couldn't read {}: {err}", root.display()),
+        )
+    })?;
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let scripts = files.iter().map(|(relative, path)| {
+        let path = path.to_string_lossy();
+        quote!(::gmod::scripts::Script { path: #relative, source: include_str!(#path) })
+    });
+
+    Ok(quote! {
+        ::gmod::scripts::Bundle::new(env!("CARGO_PKG_NAME"), &[#(#scripts),*])
+    })
+}
+
+/// Implements `gmod::config::GmodConfig` for a struct with named fields, each of a type implementing `gmod::config::ConfigValue`, see `gmod::config`.
+///
+/// Fields take `#[config(name = "...")]` when their convar or JSON key differs from the Rust name, and `#[config(help = "...")]` for the convar's help text, which defaults to the field's doc comment.
+#[proc_macro_derive(GmodConfig, attributes(config))]
+pub fn derive_gmod_config(tokens: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(tokens as syn::DeriveInput);
+    match derive_gmod_config_impl(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn derive_gmod_config_impl(input: &syn::DeriveInput) -> Result<proc_macro2::TokenStream, syn::Error> {
+    let syn::Data::Struct(syn::DataStruct {
+        fields: syn::Fields::Named(fields),
+        ..
+    }) = &input.data
+    else {
+        return Err(syn::Error::new_spanned(
+            input,
+            "GmodConfig can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut field_infos = Vec::new();
+    let mut idents = Vec::new();
+    for field in &fields.named {
+        let ident = field.ident.as_ref().unwrap();
+        let mut name = ident.to_string();
+        let mut help = None;
+        let mut doc = Vec::new();
+        for attr in &field.attrs {
+            if attr.path().is_ident("doc") {
+                if let syn::Meta::NameValue(syn::MetaNameValue {
+                    value:
+                        syn::Expr::Lit(syn::ExprLit {
+                            lit: syn::Lit::Str(line),
+                            ..
+                        }),
+                    ..
+                }) = &attr.meta
+                {
+                    doc.push(line.value().trim().to_string());
+                }
+            } else if attr.path().is_ident("config") {
+                attr.parse_nested_meta(|meta| {
+                    let value = meta.value()?.parse::<syn::LitStr>()?.value();
+                    if meta.path.is_ident("name") {
+                        name = value;
+                    } else if meta.path.is_ident("help") {
+                        help = Some(value);
+                    } else {
+                        return Err(meta.error("expected `name` or `help`"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        let help = help.unwrap_or_else(|| doc.join(" ").trim().to_string());
+        field_infos.push(quote!(::gmod::config::Field { name: #name, help: #help }));
+        idents.push(ident);
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::gmod::config::GmodConfig for #ident #ty_generics #where_clause {
+            const FIELDS: &'static [::gmod::config::Field] = &[#(#field_infos),*];
+
+            fn values(&self) -> ::std::vec::Vec<&dyn ::gmod::config::ConfigValue> {
+                ::std::vec![#(&self.#idents as &dyn ::gmod::config::ConfigValue),*]
+            }
+
+            fn values_mut(&mut self) -> ::std::vec::Vec<&mut dyn ::gmod::config::ConfigValue> {
+                ::std::vec![#(&mut self.#idents as &mut dyn ::gmod::config::ConfigValue),*]
+            }
+        }
     })
 }
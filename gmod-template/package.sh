@@ -0,0 +1,40 @@
+#!/usr/bin/env bash
+# Builds the module in release mode and copies it to dist/ under the name Garry's Mod loads it by,
+# e.g. gm{{realm}}_{{crate_name}}_win64.dll.
+#
+# Usage: ./package.sh [platform...]
+# Platforms: win32 win64 linux linux64 (default: every one the installed toolchains support)
+
+set -euo pipefail
+cd "$(dirname "$0")"
+
+declare -A TARGETS=(
+	[win32]=i686-pc-windows-msvc
+	[win64]=x86_64-pc-windows-msvc
+	[linux]=i686-unknown-linux-gnu
+	[linux64]=x86_64-unknown-linux-gnu
+)
+
+platforms=("$@")
+if [ ${#platforms[@]} -eq 0 ]; then
+	installed=$(rustup target list --installed)
+	for platform in win32 win64 linux linux64; do
+		if grep -qx "${TARGETS[$platform]}" <<<"$installed"; then
+			platforms+=("$platform")
+		fi
+	done
+fi
+
+mkdir -p dist
+for platform in "${platforms[@]}"; do
+	target=${TARGETS[$platform]:?unknown platform $platform}
+	cargo build --release --target "$target"
+
+	case $target in
+		*windows*) built="target/$target/release/{{crate_name}}.dll" ;;
+		*) built="target/$target/release/lib{{crate_name}}.so" ;;
+	esac
+	# GMod expects .dll on every platform
+	cp "$built" "dist/gm{{realm}}_{{crate_name}}_$platform.dll"
+	echo "dist/gm{{realm}}_{{crate_name}}_$platform.dll"
+done
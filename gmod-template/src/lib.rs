@@ -0,0 +1,51 @@
+#[macro_use]
+extern crate gmod;
+
+use std::time::Duration;
+
+use gmod::lua::{FunctionRef, LuaReg, State};
+
+/// `{{crate_name}}.Add(a, b)`
+#[lua_function]
+fn add(_lua: State, a: f64, b: f64) -> (f64,) {
+    (a + b,)
+}
+
+/// `{{crate_name}}.Later(seconds, callback)` calls `callback(message)` from a background thread, through the task queue.
+#[lua_function]
+fn later(_lua: State, seconds: f64, callback: FunctionRef) -> anyhow::Result<()> {
+    let delay = Duration::try_from_secs_f64(seconds)?;
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        // only the Lua thread can touch the Lua state, so the callback runs on the next tick
+        let _ = gmod::wait_lua_tick(String::new(), move |l| {
+            callback.push(l);
+            l.push_string("hello from another thread");
+            l.pcall_ignore(1, 0);
+        });
+    });
+    Ok(())
+}
+
+#[gmod13_open]
+fn gmod13_open(lua: State) -> i32 {
+    lua.register(
+        c"{{crate_name}}".as_ptr(),
+        lua_regs![
+            "Add" => add,
+            "Later" => later,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+
+    // println! doesn't reach the game console on clients, Lua's print does
+    let _ = lua.call_global("print").arg("{{project-name}} loaded").call::<()>();
+    0
+}
+
+#[gmod13_close]
+fn gmod13_close(lua: State) -> i32 {
+    let _ = lua.call_global("print").arg("{{project-name}} unloaded").call::<()>();
+    0
+}
@@ -0,0 +1,24 @@
+-- The `harness` library available to test scripts, on top of the GMod stand-ins of gmod::testing.
+
+local test = _GMOD_RS_TEST
+
+-- `harness.sleep(seconds)` is registered by the harness itself, as Lua can't sleep.
+
+-- Advances the game time by `seconds`, running due timers (including the module's task queue) and the Think hooks.
+function harness.tick(seconds)
+	test.tick(seconds or 0.015)
+end
+
+-- Ticks until `condition()` returns a truthy value, for work done by the module's background threads. Fails after `timeout` seconds (5 by default).
+function harness.wait_until(condition, timeout)
+	timeout = timeout or 5
+	local waited = 0
+	while not condition() do
+		if waited >= timeout then
+			error("harness.wait_until timed out after " .. timeout .. "s", 2)
+		end
+		harness.sleep(0.01)
+		harness.tick(0.01)
+		waited = waited + 0.01
+	end
+end
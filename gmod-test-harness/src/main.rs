@@ -0,0 +1,193 @@
+//! Loads a binary module built with gmod-rs into a standalone Lua state, like `require` does in game, and runs Lua test scripts against it. See the README.
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use gmod::{lua::State, testing::TestState};
+
+/// Where gmod-rs looks for lua_shared, relative to the game's directory.
+#[cfg(all(target_os = "windows", target_pointer_width = "64"))]
+const LUA_SHARED_PATH: &str = "bin/win64/lua_shared.dll";
+#[cfg(all(target_os = "windows", target_pointer_width = "32"))]
+const LUA_SHARED_PATH: &str = "bin/lua_shared.dll";
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+const LUA_SHARED_PATH: &str = "bin/linux64/lua_shared.so";
+#[cfg(all(target_os = "linux", target_pointer_width = "32"))]
+const LUA_SHARED_PATH: &str = "garrysmod/bin/lua_shared_srv.so";
+#[cfg(all(target_os = "macos", target_pointer_width = "64"))]
+const LUA_SHARED_PATH: &str = "GarrysMod_Signed.app/Contents/MacOS/lua_shared.dylib";
+#[cfg(all(target_os = "macos", target_pointer_width = "32"))]
+const LUA_SHARED_PATH: &str = "garrysmod/bin/lua_shared.dylib";
+
+const USAGE: &str =
+    "usage: gmod-test-harness [--lua-shared PATH] [--client] [--allow-errors] MODULE SCRIPT...
+
+  --lua-shared PATH  LuaJIT library to run the module with (defaults to $GMOD_RS_LUA_SHARED)
+  --client           run as the clientside realm (CLIENT = true, SERVER = false)
+  --allow-errors     don't fail scripts that report errors through ErrorNoHalt";
+
+type ModuleFunction = unsafe extern "C-unwind" fn(State) -> i32;
+
+struct Args {
+    lua_shared: PathBuf,
+    module: PathBuf,
+    scripts: Vec<PathBuf>,
+    client: bool,
+    allow_errors: bool,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut lua_shared = std::env::var_os("GMOD_RS_LUA_SHARED").map(PathBuf::from);
+    let mut client = false;
+    let mut allow_errors = false;
+    let mut paths = Vec::new();
+
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--lua-shared") => {
+                lua_shared = Some(args.next().context("--lua-shared needs a path")?.into())
+            }
+            Some("--client") => client = true,
+            Some("--allow-errors") => allow_errors = true,
+            Some("-h" | "--help") => bail!(""),
+            Some(flag) if flag.starts_with("--") => bail!("unknown option {flag}"),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let lua_shared =
+        lua_shared.context("no LuaJIT library, pass --lua-shared or set GMOD_RS_LUA_SHARED")?;
+    if paths.len() < 2 {
+        bail!("expected a module and at least one script");
+    }
+    let module = paths.remove(0);
+    Ok(Args {
+        // the paths are used after moving to the game directory
+        lua_shared: absolute(&lua_shared)?,
+        module: absolute(&module)?,
+        scripts: paths
+            .iter()
+            .map(|path| absolute(path))
+            .collect::<Result<_>>()?,
+        client,
+        allow_errors,
+    })
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    path.canonicalize()
+        .with_context(|| format!("{} doesn't exist", path.display()))
+}
+
+/// Creates a directory laid out like the game's, with lua_shared where the module looks for it, and moves into it. The harness loads lua_shared from there too, so that both use the same library.
+fn setup_game_dir(lua_shared: &Path) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("gmod-test-harness-{}", std::process::id()));
+    let target = dir.join(LUA_SHARED_PATH);
+    std::fs::create_dir_all(target.parent().unwrap())?;
+    std::fs::copy(lua_shared, &target)
+        .with_context(|| format!("couldn't copy {}", lua_shared.display()))?;
+    std::env::set_current_dir(&dir)?;
+    std::env::set_var("GMOD_RS_LUA_SHARED", &target);
+    Ok(dir)
+}
+
+unsafe extern "C-unwind" fn sleep(lua: State) -> i32 {
+    let seconds = lua.to_number(1).max(0.0);
+    std::thread::sleep(Duration::from_secs_f64(seconds));
+    0
+}
+
+fn setup_harness_library(test: &TestState, client: bool) -> Result<()> {
+    let lua = test.lua();
+    lua.create_table(0, 3);
+    lua.push_function(sleep);
+    lua.set_field(-2, c"sleep");
+    lua.set_global(c"harness");
+    test.exec(include_str!("harness.lua"))?;
+    if client {
+        test.exec("SERVER = false CLIENT = true")?;
+    }
+    Ok(())
+}
+
+fn run(args: Args) -> Result<bool> {
+    let test = TestState::new()?;
+    setup_harness_library(&test, args.client)?;
+
+    let module = unsafe { gmod::libloading::Library::new(&args.module) }
+        .with_context(|| format!("couldn't load {}", args.module.display()))?;
+    let open = unsafe { module.get::<ModuleFunction>(b"gmod13_open\0") }
+        .map_err(|_| anyhow!("{} doesn't export gmod13_open", args.module.display()))?;
+    unsafe { open(test.lua()) };
+    let mut passed = report("gmod13_open", Ok(()), test.errors(), args.allow_errors);
+
+    for script in &args.scripts {
+        let result = test.exec_file(script);
+        passed &= report(
+            &script.display().to_string(),
+            result,
+            test.errors(),
+            args.allow_errors,
+        );
+    }
+
+    if let Ok(close) = unsafe { module.get::<ModuleFunction>(b"gmod13_close\0") } {
+        unsafe { close(test.lua()) };
+        passed &= report("gmod13_close", Ok(()), test.errors(), args.allow_errors);
+    }
+
+    // the Lua state must be closed while the module's `__gc` metamethods still exist
+    drop(test);
+    drop(module);
+    Ok(passed)
+}
+
+fn report(name: &str, result: Result<()>, errors: Vec<String>, allow_errors: bool) -> bool {
+    let passed = result.is_ok() && (allow_errors || errors.is_empty());
+    println!("{} {name}", if passed { "PASS" } else { "FAIL" });
+    if let Err(err) = result {
+        println!("  {err:#}");
+    }
+    for error in errors {
+        println!("  error reported: {}", error.replace('\n', "\n  "));
+    }
+    passed
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            if !err.to_string().is_empty() {
+                eprintln!("{err}\n");
+            }
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let game_dir = match setup_game_dir(&args.lua_shared) {
+        Ok(dir) => dir,
+        Err(err) => {
+            eprintln!("{err:#}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = run(args);
+    let _ = std::fs::remove_dir_all(game_dir);
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{err:#}");
+            ExitCode::from(2)
+        }
+    }
+}
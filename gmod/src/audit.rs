@@ -0,0 +1,535 @@
+//! Append-only, tamper-evident audit log for admin actions.
+//!
+//! Every record is hashed together with the hash of the record before it, so editing, removing or reordering records breaks the chain from that point on, which `verify` reports. This doesn't stop someone with access to the files from rewriting the whole chain, but it makes quiet edits to individual records evident; copy the latest hash somewhere else (e.g. a Discord webhook) to pin it.
+//!
+//! Records are stored one per line in `audit-<n>.log` files, and a new file is started once the current one reaches the rotation size. Files are never deleted.
+//!
+//! If the newest file ends in a damaged record, e.g. after a crash while it was written, logging continues in a new file from the last well-formed record, and `verify` keeps reporting the damage.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::lua::{self, HandleLuaFunctionReturn, LuaReg, State};
+
+/// Where the default audit log, used by the module-level functions, is stored.
+pub const DIR: &str = "garrysmod/data/gmod_rs/audit";
+
+/// Size at which the default audit log starts a new file.
+pub const DEFAULT_ROTATE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// How many records `Query` returns by default.
+pub const DEFAULT_QUERY_LIMIT: usize = 100;
+
+/// The "previous hash" of the very first record.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A record of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// The position of the record in the log, starting at 1.
+    pub seq: u64,
+    /// Unix timestamp (seconds) of when the record was written.
+    pub timestamp: u64,
+    /// Who did it, e.g. a SteamID64 or `"Console"`.
+    pub actor: String,
+    /// What was done, e.g. `"ban"`.
+    pub action: String,
+    /// Who or what it was done to.
+    pub target: String,
+    /// Free-form details, e.g. the ban reason and length.
+    pub details: String,
+    /// The hash of this record, chained to the previous one.
+    pub hash: String,
+}
+
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+impl Record {
+    /// The line without its hash, which is what gets hashed.
+    fn body(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.seq,
+            self.timestamp,
+            escape(&self.actor),
+            escape(&self.action),
+            escape(&self.target),
+            escape(&self.details)
+        )
+    }
+
+    fn compute_hash(&self, prev_hash: &str) -> String {
+        let digest = Sha256::digest(format!("{prev_hash}\t{}", self.body()).as_bytes());
+        format!("{digest:x}")
+    }
+
+    fn parse(line: &str) -> Option<Record> {
+        let mut fields = line.split('\t');
+        let record = Record {
+            seq: fields.next()?.parse().ok()?,
+            timestamp: fields.next()?.parse().ok()?,
+            actor: unescape(fields.next()?),
+            action: unescape(fields.next()?),
+            target: unescape(fields.next()?),
+            details: unescape(fields.next()?),
+            hash: fields.next()?.to_string(),
+        };
+        fields.next().is_none().then_some(record)
+    }
+}
+
+/// Which records `AuditLog::query` returns. Every set field must match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    /// Only records written at or after this Unix timestamp.
+    pub since: Option<u64>,
+    /// Only records written at or before this Unix timestamp.
+    pub until: Option<u64>,
+    /// How many records to return at most, newest first.
+    pub limit: usize,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Self {
+            actor: None,
+            action: None,
+            target: None,
+            since: None,
+            until: None,
+            limit: DEFAULT_QUERY_LIMIT,
+        }
+    }
+}
+
+impl Query {
+    fn matches(&self, record: &Record) -> bool {
+        self.actor
+            .as_ref()
+            .is_none_or(|actor| *actor == record.actor)
+            && self
+                .action
+                .as_ref()
+                .is_none_or(|action| *action == record.action)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| *target == record.target)
+            && self.since.is_none_or(|since| record.timestamp >= since)
+            && self.until.is_none_or(|until| record.timestamp <= until)
+    }
+}
+
+struct Writer {
+    file: File,
+    file_index: u64,
+    size: u64,
+    seq: u64,
+    last_hash: String,
+}
+
+/// An audit log stored in a directory.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::audit::{AuditLog, Query};
+///
+/// # let dir = std::env::temp_dir().join(format!("gmod_rs_audit_doctest_{}", std::process::id()));
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let log = AuditLog::new(&dir);
+/// log.log("76561197960287930", "ban", "76561197960287931", "cheating (1 week)").unwrap();
+/// log.log("Console", "map", "gm_construct", "").unwrap();
+///
+/// let bans = log.query(&Query { action: Some("ban".into()), ..Default::default() }).unwrap();
+/// assert_eq!(bans.len(), 1);
+/// assert_eq!(bans[0].details, "cheating (1 week)");
+///
+/// assert_eq!(log.verify().unwrap(), 2);
+///
+/// // editing a record breaks the chain
+/// let file = dir.join("audit-1.log");
+/// let contents = std::fs::read_to_string(&file).unwrap();
+/// std::fs::write(&file, contents.replace("1 week", "1 day")).unwrap();
+/// assert!(log.verify().is_err());
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct AuditLog {
+    dir: PathBuf,
+    rotate_size: u64,
+    writer: Mutex<Option<Writer>>,
+}
+
+impl AuditLog {
+    /// Opens (or creates, on the first write) the audit log in `dir`, rotating files at `DEFAULT_ROTATE_SIZE`.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self::with_rotate_size(dir, DEFAULT_ROTATE_SIZE)
+    }
+
+    /// Same as `new`, but starts a new file once the current one reaches `rotate_size` bytes.
+    pub fn with_rotate_size<P: AsRef<Path>>(dir: P, rotate_size: u64) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            rotate_size,
+            writer: Mutex::new(None),
+        }
+    }
+
+    fn file_path(&self, index: u64) -> PathBuf {
+        self.dir.join(format!("audit-{index}.log"))
+    }
+
+    /// Returns the indices of the log's files, oldest first.
+    fn file_indices(&self) -> Result<Vec<u64>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut indices: Vec<u64> = entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?
+                    .strip_prefix("audit-")?
+                    .strip_suffix(".log")?
+                    .parse()
+                    .ok()
+            })
+            .collect();
+        indices.sort_unstable();
+        Ok(indices)
+    }
+
+    fn read_file(&self, index: u64) -> Result<Vec<Record>> {
+        let path = self.file_path(index);
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("failed to read {path:?}"))?;
+        contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                Record::parse(line)
+                    .with_context(|| format!("malformed record in {path:?}: {line:?}"))
+            })
+            .collect()
+    }
+
+    /// Reads the well-formed records of a file, skipping the lines that aren't, and a last line without its newline. Also returns whether anything was skipped.
+    fn read_file_lossy(&self, index: u64) -> Result<(Vec<Record>, bool)> {
+        let path = self.file_path(index);
+        let contents = fs::read(&path).with_context(|| format!("failed to read {path:?}"))?;
+
+        // a crash while writing a record leaves it without its newline
+        let complete = match contents.iter().rposition(|&b| b == b'\n') {
+            Some(end) => &contents[..=end],
+            None => &[],
+        };
+        let mut damaged = complete.len() != contents.len();
+        let mut records = Vec::new();
+        for line in complete
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+        {
+            match std::str::from_utf8(line).ok().and_then(Record::parse) {
+                Some(record) => records.push(record),
+                None => damaged = true,
+            }
+        }
+        Ok((records, damaged))
+    }
+
+    fn open_file(&self, index: u64) -> Result<File> {
+        let path = self.file_path(index);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {path:?}"))
+    }
+
+    fn writer(&self) -> Result<MutexGuard<'_, Option<Writer>>> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if writer.is_none() {
+            fs::create_dir_all(&self.dir)
+                .with_context(|| format!("failed to create {:?}", self.dir))?;
+
+            let indices = self.file_indices()?;
+            let mut file_index = indices.last().copied().unwrap_or(1);
+
+            // continue the chain from the last well-formed record, in a new file if the newest one is damaged, which `verify` keeps reporting
+            let mut last = None;
+            for &index in indices.iter().rev() {
+                let (records, damaged) = self.read_file_lossy(index)?;
+                if damaged && index == file_index {
+                    eprintln!(
+                        "[gmod-rs] {:?} is damaged, continuing the audit log in audit-{}.log",
+                        self.file_path(index),
+                        index + 1
+                    );
+                    file_index += 1;
+                }
+                if let Some(record) = records.into_iter().last() {
+                    last = Some(record);
+                    break;
+                }
+            }
+            let (seq, last_hash) = match last {
+                Some(record) => (record.seq, record.hash),
+                None => (0, GENESIS_HASH.to_string()),
+            };
+
+            let file = self.open_file(file_index)?;
+            *writer = Some(Writer {
+                size: file.metadata()?.len(),
+                file,
+                file_index,
+                seq,
+                last_hash,
+            });
+        }
+        Ok(writer)
+    }
+
+    /// Appends a record, returning its sequence number.
+    pub fn log(&self, actor: &str, action: &str, target: &str, details: &str) -> Result<u64> {
+        let mut guard = self.writer()?;
+        let writer = guard.as_mut().unwrap();
+
+        if writer.size >= self.rotate_size {
+            writer.file = self.open_file(writer.file_index + 1)?;
+            writer.file_index += 1;
+            writer.size = 0;
+        }
+
+        let mut record = Record {
+            seq: writer.seq + 1,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            details: details.to_string(),
+            hash: String::new(),
+        };
+        record.hash = record.compute_hash(&writer.last_hash);
+
+        let line = format!("{}\t{}\n", record.body(), record.hash);
+        writer.file.write_all(line.as_bytes())?;
+        writer.file.flush()?;
+
+        writer.size += line.len() as u64;
+        writer.seq = record.seq;
+        writer.last_hash = record.hash;
+        Ok(record.seq)
+    }
+
+    /// Returns the records matching `query`, newest first. Lines that aren't well-formed records are skipped.
+    pub fn query(&self, query: &Query) -> Result<Vec<Record>> {
+        // hold the writer lock so we don't read a half-written line
+        let _writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut records = Vec::new();
+        for index in self.file_indices()?.into_iter().rev() {
+            for record in self.read_file_lossy(index)?.0.into_iter().rev() {
+                if records.len() >= query.limit {
+                    return Ok(records);
+                }
+                if query.matches(&record) {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Checks the hash chain of every record, returning how many records there are. Fails at the first record that was modified, removed or inserted.
+    pub fn verify(&self) -> Result<u64> {
+        let _writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut count = 0;
+        for index in self.file_indices()? {
+            for record in self.read_file(index)? {
+                if record.seq != count + 1 {
+                    bail!(
+                        "record {} is out of sequence in audit-{index}.log (expected {})",
+                        record.seq,
+                        count + 1
+                    );
+                }
+                if record.compute_hash(&prev_hash) != record.hash {
+                    bail!(
+                        "record {} in audit-{index}.log does not match its hash",
+                        record.seq
+                    );
+                }
+                prev_hash = record.hash;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+static DEFAULT: std::sync::LazyLock<AuditLog> = std::sync::LazyLock::new(|| AuditLog::new(DIR));
+
+/// Appends a record to the default audit log (in `DIR`), returning its sequence number.
+pub fn log(actor: &str, action: &str, target: &str, details: &str) -> Result<u64> {
+    DEFAULT.log(actor, action, target, details)
+}
+
+/// Returns the records of the default audit log matching `query`, newest first.
+pub fn query(query: &Query) -> Result<Vec<Record>> {
+    DEFAULT.query(query)
+}
+
+/// Checks the hash chain of the default audit log. See `AuditLog::verify`.
+pub fn verify() -> Result<u64> {
+    DEFAULT.verify()
+}
+
+/// Registers the functions of the default audit log into a global table named `libname`:
+///
+/// - `Log(actor, action, target, details)` returns the record's sequence number
+/// - `Query([filter])` returns the matching records, newest first, as tables with the fields `seq`, `time`, `actor`, `action`, `target`, `details` and `hash`. `filter` can have the fields `actor`, `action`, `target`, `since`, `until` and `limit`.
+/// - `Verify()` returns the number of records, or `false` and the reason if the chain is broken
+pub fn register(lua: State, libname: lua::LuaCStr) {
+    lua.register(
+        libname.as_ptr(),
+        crate::lua_regs![
+            "Log" => lua_log,
+            "Query" => lua_query,
+            "Verify" => lua_verify,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+}
+
+extern "C-unwind" fn lua_log(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let actor = lua.check_string(1)?;
+        let action = lua.check_string(2)?;
+        let target = lua.check_string(3)?;
+        let details = if lua.is_none_or_nil(4) {
+            Default::default()
+        } else {
+            lua.check_string(4)?
+        };
+        let seq = log(&actor, &action, &target, &details)?;
+        lua.push_number(seq);
+        Ok(1)
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_query(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let mut filter = Query::default();
+        if !lua.is_none_or_nil(1) {
+            lua.check_table(1)?;
+            for (name, field) in [
+                (c"actor", &mut filter.actor),
+                (c"action", &mut filter.action),
+                (c"target", &mut filter.target),
+            ] {
+                if lua.get_field_type_or_nil(1, name, lua::LUA_TSTRING)? {
+                    *field = lua.get_string(-1).map(|s| s.into_owned());
+                    lua.pop();
+                }
+            }
+            for (name, field) in [(c"since", &mut filter.since), (c"until", &mut filter.until)] {
+                if lua.get_field_type_or_nil(1, name, lua::LUA_TNUMBER)? {
+                    *field = Some(lua.to_number(-1) as u64);
+                    lua.pop();
+                }
+            }
+            if lua.get_field_type_or_nil(1, c"limit", lua::LUA_TNUMBER)? {
+                filter.limit = lua.to_number(-1) as usize;
+                lua.pop();
+            }
+        }
+
+        let records = query(&filter)?;
+        lua.create_table(records.len() as i32, 0);
+        for (i, record) in records.iter().enumerate() {
+            lua.create_table(0, 7);
+            lua.push_number(record.seq);
+            lua.set_field(-2, c"seq");
+            lua.push_number(record.timestamp);
+            lua.set_field(-2, c"time");
+            for (name, value) in [
+                (c"actor", &record.actor),
+                (c"action", &record.action),
+                (c"target", &record.target),
+                (c"details", &record.details),
+                (c"hash", &record.hash),
+            ] {
+                lua.push_string(value);
+                lua.set_field(-2, name);
+            }
+            lua.raw_seti(-2, i as i32 + 1);
+        }
+        Ok(1)
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_verify(lua: State) -> i32 {
+    match verify() {
+        Ok(count) => {
+            lua.push_number(count);
+            1
+        }
+        Err(err) => {
+            lua.push_boolean(false);
+            lua.push_string(&format!("{err:#}"));
+            2
+        }
+    }
+}
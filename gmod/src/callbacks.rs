@@ -0,0 +1,136 @@
+//! Rust callbacks registered in Lua by `timer`, `convar`, `concommand` and `net`, kept on the Lua thread, and the handles that remove them.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    hash::Hash,
+};
+
+use crate::lua::{task_queue, HandleLuaFunctionReturn, State};
+
+/// The callbacks a module registered, by the key their Lua trampoline is given as an upvalue, usually an id from `next_id`.
+pub(crate) struct Registry<T, K = u64> {
+    entries: RefCell<HashMap<K, T>>,
+    next_id: Cell<u64>,
+}
+
+impl<T> Registry<T> {
+    pub fn next_id(&self) -> u64 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+}
+
+impl<T, K: Eq + Hash + Clone> Registry<T, K> {
+    pub fn new() -> Self {
+        Self {
+            entries: RefCell::new(HashMap::new()),
+            next_id: Cell::new(0),
+        }
+    }
+
+    pub fn with<R>(&self, f: impl FnOnce(&mut HashMap<K, T>) -> R) -> R {
+        f(&mut self.entries.borrow_mut())
+    }
+
+    pub fn insert(&self, id: K, entry: T) {
+        self.with(|entries| entries.insert(id, entry));
+    }
+
+    pub fn remove(&self, id: K) -> Option<T> {
+        self.with(|entries| entries.remove(&id))
+    }
+
+    pub fn ids(&self) -> Vec<K> {
+        self.with(|entries| entries.keys().cloned().collect())
+    }
+
+    /// Takes the callback in `slot` out of the entry `id` while running `f` with it, so that `f` can reach the registry, and puts it back afterwards, even if `f` unwinds. Returns `None` if there's no such entry, or its callback is already running.
+    ///
+    /// Panics in `f` are caught, to be raised as Lua errors with `raise`.
+    pub fn run<C, R>(
+        &self,
+        id: K,
+        slot: fn(&mut T) -> &mut Option<C>,
+        f: impl FnOnce(&mut C) -> R,
+    ) -> Option<Result<R, crate::panic::Panic>> {
+        struct Restore<'a, T, K: Eq + Hash, C> {
+            registry: &'a Registry<T, K>,
+            id: K,
+            slot: fn(&mut T) -> &mut Option<C>,
+            callback: Option<C>,
+        }
+
+        impl<T, K: Eq + Hash, C> Drop for Restore<'_, T, K, C> {
+            fn drop(&mut self) {
+                // unless it was removed or replaced while running
+                if let Some(entry) = self.registry.entries.borrow_mut().get_mut(&self.id) {
+                    let slot = (self.slot)(entry);
+                    if slot.is_none() {
+                        *slot = self.callback.take();
+                    }
+                }
+            }
+        }
+
+        let callback = self.with(|entries| slot(entries.get_mut(&id)?).take())?;
+        let mut restore = Restore {
+            registry: self,
+            id,
+            slot,
+            callback: Some(callback),
+        };
+        let callback = restore.callback.as_mut()?;
+        Some(crate::panic::catch(|| f(callback)))
+    }
+}
+
+/// Raises a panic caught by `Registry::run` as a Lua error, once the callback was put back.
+pub(crate) fn raise(lua: State, panic: crate::panic::Panic) -> i32 {
+    Err::<i32, _>(panic).handle_result(lua)
+}
+
+/// Removes a callback when dropped, unless it's detached. Embedded in the handles returned for callbacks.
+///
+/// Can be dropped from any thread; the removal is then done on the next Lua tick.
+#[derive(Debug)]
+pub(crate) struct Removal {
+    id: u64,
+    remove: fn(State, u64),
+    detached: bool,
+}
+
+impl Removal {
+    pub fn new(id: u64, remove: fn(State, u64)) -> Self {
+        Self {
+            id,
+            remove,
+            detached: false,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Removes the callback immediately. Must be called from the Lua thread.
+    pub fn remove(mut self, lua: State) {
+        self.detached = true;
+        (self.remove)(lua, self.id);
+    }
+
+    pub fn detach(mut self) {
+        self.detached = true;
+    }
+}
+
+impl Drop for Removal {
+    fn drop(&mut self) {
+        if self.detached {
+            return;
+        }
+        let (id, remove) = (self.id, self.remove);
+        let _ = task_queue::wait_lua_tick(String::new(), move |l| remove(l, id));
+    }
+}
@@ -0,0 +1,194 @@
+//! Running Lua code on clients from the server, for modules that ship clientside visuals without an addon.
+//!
+//! `ply:SendLua` is limited to 254 bytes, so it's only used once per player, to install a small receiver. The code itself is compressed with `util.Compress` and sent as a `net::stream`, split into as many messages as needed, then run by the receiver with `RunString`.
+//!
+//! Serverside only.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! static CLIENT: gmod::scripts::Bundle = gmod::include_lua_dir!("lua/client/");
+//!
+//! gmod::client_lua::broadcast(lua, "hook.Add('HUDPaint', 'my_module', function() end)")?;
+//!
+//! // every script of the bundle, in path order
+//! gmod::client_lua::send(lua, &[ply], &CLIENT)?;
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashSet, VecDeque},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    lua::{LuaRef, State},
+    net::{self, stream},
+    player::{self, Player},
+    scripts::Bundle,
+};
+
+/// The network string the code is streamed over.
+pub const NETWORK_STRING: &str = "gmod_rs_lua";
+
+/// The receiver installed on clients with `ply:SendLua`, so it must fit in 254 bytes. It reads the chunks written by `net::stream`, and runs the code once every chunk arrived (they arrive in order, on the reliable channel).
+const BOOTSTRAP: &str = r#"local p,R={},net.ReadUInt net.Receive("gmod_rs_lua",function()local i,n,c=R(32),R(32),R(32)R(8)local t=p[i]or{}p[i]=t t[n+1]=net.ReadData(R(32))if#t==c then p[i]=nil local a,b=util.Decompress(table.concat(t)):match"^(.-)\n(.*)"RunString(b,a)end end)"#;
+
+/// Name the code of `&str` and `String` payloads runs under.
+const DEFAULT_NAME: &str = "gmod_rs/client_lua";
+
+thread_local! {
+    /// User IDs of the players the receiver was sent to. User IDs aren't reused until the map changes, which also reloads the module.
+    static BOOTSTRAPPED: RefCell<HashSet<i32>> = RefCell::new(HashSet::new());
+
+    /// Streams waiting for the one being sent, so code runs in the order it was sent. Streams are sent over their own timers, which don't run in a set order.
+    static QUEUE: RefCell<VecDeque<(Vec<u8>, LuaRef)>> = const { RefCell::new(VecDeque::new()) };
+    static SENDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Lua code to run on clients: a chunk of code, or every script of an embedded `Bundle`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Code {
+    name: String,
+    source: String,
+}
+
+impl Code {
+    /// A chunk of code, with the name that errors it raises are prefixed with.
+    pub fn new(name: impl Into<String>, source: impl Into<String>) -> Self {
+        Self {
+            // the name ends at the first newline on the client
+            name: name.into().replace('\n', " "),
+            source: source.into(),
+        }
+    }
+}
+
+impl From<&str> for Code {
+    fn from(source: &str) -> Self {
+        Self::new(DEFAULT_NAME, source)
+    }
+}
+
+impl From<String> for Code {
+    fn from(source: String) -> Self {
+        Self::new(DEFAULT_NAME, source)
+    }
+}
+
+/// Runs every script of the bundle in path order, each under its own name, like `Bundle::run_all`. A script failing doesn't stop the next ones.
+impl From<&Bundle> for Code {
+    fn from(bundle: &Bundle) -> Self {
+        let mut source = String::new();
+        for script in bundle.scripts {
+            let name = format!("{}/{}", bundle.name, script.path);
+            source += &format!(
+                "RunString({}, {})\n",
+                long_string(script.source),
+                quote(&name)
+            );
+        }
+        Self::new(bundle.name, source)
+    }
+}
+
+/// Quotes `str` as a Lua long string, with a level of `=` signs that `str` doesn't close.
+fn long_string(str: &str) -> String {
+    let level = (0..)
+        .find(|&n| !str.contains(&format!("]{}]", "=".repeat(n))))
+        .unwrap();
+    let equals = "=".repeat(level);
+    // the newline after the opening bracket is skipped, and the one before the closing bracket keeps a trailing `]` from closing the string early
+    format!("[{equals}[\n{str}\n]{equals}]")
+}
+
+/// Quotes `str` as a Lua string literal on a single line.
+fn quote(str: &str) -> String {
+    let mut quoted = String::from("\"");
+    for byte in str.bytes() {
+        match byte {
+            b'"' | b'\\' => quoted.extend(['\\', byte as char]),
+            b' '..=b'~' => quoted.push(byte as char),
+            _ => quoted += &format!("\\{byte:03}"),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Runs `code` on the clients of `players`, installing the receiver on the ones that don't have it yet. Disconnected players are skipped.
+pub fn send(lua: State, players: &[Player], code: impl Into<Code>) -> Result<()> {
+    let players: Vec<(&Player, i32)> = players
+        .iter()
+        .filter_map(|ply| Some((ply, ply.userid(lua)?)))
+        .collect();
+    if players.is_empty() {
+        return Ok(());
+    }
+
+    let code = code.into();
+    let data = compress(lua, format!("{}\n{}", code.name, code.source).as_bytes())?;
+
+    net::add_network_strings(lua, &[NETWORK_STRING]);
+    BOOTSTRAPPED.with_borrow_mut(|bootstrapped| {
+        for &(ply, userid) in &players {
+            if !bootstrapped.contains(&userid) && ply.send_lua(lua, BOOTSTRAP) {
+                bootstrapped.insert(userid);
+            }
+        }
+    });
+
+    lua.create_table(players.len() as i32, 0);
+    for (i, (ply, _)) in players.iter().enumerate() {
+        ply.push(lua);
+        lua.raw_seti(-2, i as i32 + 1);
+    }
+    let recipients = LuaRef::new(lua);
+    QUEUE.with_borrow_mut(|queue| queue.push_back((data, recipients)));
+    send_next(lua);
+    Ok(())
+}
+
+/// Starts sending the next queued stream, unless one is being sent.
+fn send_next(lua: State) {
+    if SENDING.get() {
+        return;
+    }
+    let Some((data, recipients)) = QUEUE.with_borrow_mut(VecDeque::pop_front) else {
+        return;
+    };
+    SENDING.set(true);
+    recipients.push(lua);
+    stream::send(NETWORK_STRING, data)
+        .on_sent(|lua| {
+            SENDING.set(false);
+            send_next(lua);
+        })
+        .send(lua, -1);
+    lua.pop();
+    recipients.release(lua);
+}
+
+/// Same as `send`, to every connected player.
+pub fn broadcast(lua: State, code: impl Into<Code>) -> Result<()> {
+    send(lua, &player::all(lua), code)
+}
+
+/// Compresses `data` with `util.Compress`, which clients can decompress with `util.Decompress`.
+fn compress(lua: State, data: &[u8]) -> Result<Vec<u8>> {
+    let top = lua.get_top();
+    lua.get_global(c"util");
+    lua.get_field(-1, c"Compress");
+    lua.push_binary_string(data);
+    let compressed = lua
+        .pcall(1, 1, 0)
+        .map_err(|err| anyhow!("util.Compress failed: {err}"))
+        .and_then(|()| {
+            lua.get_binary_string(-1)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| anyhow!("util.Compress returned nothing"))
+        });
+    lua.set_top(top);
+    compressed
+}
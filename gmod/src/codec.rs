@@ -0,0 +1,430 @@
+//! Encoding Lua values as MessagePack, a compact binary format, to send structured data through net messages instead of `net.WriteTable`.
+//!
+//! Tables whose keys are `1` to `n` are encoded as arrays and other tables as maps, with keys of any type. Integers take as few bytes as they need, and `Vector`s and `Angle`s are extension types `1` and `2`, holding their 3 components as big-endian `f32`s. Functions, userdata and threads can't be encoded.
+//!
+//! The payload comes from the other side of the network, so `decode` enforces `Limits` on its size, depth and number of values, and never allocates more than what was actually received.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::codec::{self, Limits};
+//!
+//! let data = codec::encode(lua, 1, &Limits::default())?;
+//! gmod::net::start("my_addon_state").write_bytes(data).broadcast(lua)?;
+//!
+//! gmod::net::receive_with(lua, "my_addon_state", |reader| {
+//!     let data = reader.read_bytes();
+//!     if codec::decode(reader.lua(), &data, &Limits::default()).is_ok() {
+//!         // the table is on the stack
+//!     }
+//! });
+//! ```
+
+use anyhow::{bail, Result};
+
+use crate::{
+    lua::{
+        self, StackIndex, State, LUA_NUMBER_MAX_SAFE_INTEGER, LUA_TBOOLEAN, LUA_TNIL, LUA_TNONE,
+        LUA_TNUMBER, LUA_TSTRING, LUA_TTABLE,
+    },
+    userdata::{Angle, Vector},
+};
+
+/// The extension type of `Vector`s.
+pub const EXT_VECTOR: i8 = 1;
+/// The extension type of `Angle`s.
+pub const EXT_ANGLE: i8 = 2;
+
+/// Bounds on what `encode` produces and `decode` accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// How deep tables can be nested. Also stops tables containing themselves.
+    pub max_depth: usize,
+    /// The largest payload, in bytes. Defaults to 64 KiB, the most a net message can hold.
+    pub max_bytes: usize,
+    /// How many values a payload can hold, counting tables, keys and values.
+    pub max_values: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 32,
+            max_bytes: 64 * 1024,
+            max_values: 16 * 1024,
+        }
+    }
+}
+
+/// Encodes the value at `index`.
+pub fn encode(lua: State, index: impl Into<StackIndex>, limits: &Limits) -> Result<Vec<u8>> {
+    let index = index.into().abs(lua).0;
+    let top = lua.get_top();
+    let mut encoder = Encoder {
+        lua,
+        limits,
+        out: Vec::new(),
+        values: 0,
+    };
+    let result = encoder.value(index, 0);
+    lua.set_top(top);
+    result.map(|()| encoder.out)
+}
+
+/// Decodes a payload and pushes its value. Nothing is pushed if it fails.
+pub fn decode(lua: State, bytes: &[u8], limits: &Limits) -> Result<()> {
+    if bytes.len() > limits.max_bytes {
+        bail!(
+            "the payload is {} bytes long, more than the limit of {}",
+            bytes.len(),
+            limits.max_bytes
+        );
+    }
+    let top = lua.get_top();
+    let mut decoder = Decoder {
+        lua,
+        limits,
+        bytes,
+        pos: 0,
+        values: 0,
+    };
+    let result = decoder.value(0).and_then(|()| {
+        if decoder.pos != bytes.len() {
+            bail!(
+                "{} bytes are left after the value",
+                bytes.len() - decoder.pos
+            );
+        }
+        Ok(())
+    });
+    if result.is_err() {
+        lua.set_top(top);
+    }
+    result
+}
+
+struct Encoder<'a> {
+    lua: State,
+    limits: &'a Limits,
+    out: Vec<u8>,
+    values: usize,
+}
+
+impl Encoder<'_> {
+    /// Encodes the value at `index`, an absolute index.
+    fn value(&mut self, index: i32, depth: usize) -> Result<()> {
+        self.values += 1;
+        if self.values > self.limits.max_values {
+            bail!(
+                "the value holds more than {} values",
+                self.limits.max_values
+            );
+        }
+
+        let lua = self.lua;
+        match lua.lua_type(index) {
+            LUA_TNIL | LUA_TNONE => self.out.push(0xc0),
+            LUA_TBOOLEAN => self
+                .out
+                .push(if lua.get_boolean(index) { 0xc3 } else { 0xc2 }),
+            LUA_TNUMBER => self.number(lua.to_number(index)),
+            LUA_TSTRING => {
+                let bytes = lua.get_binary_string(index).unwrap_or_default();
+                match std::str::from_utf8(bytes) {
+                    Ok(_) => self.header(bytes.len(), Some((0xa0, 32)), [0xd9, 0xda, 0xdb]),
+                    Err(_) => self.header(bytes.len(), None, [0xc4, 0xc5, 0xc6]),
+                }
+                self.out.extend_from_slice(bytes);
+            }
+            LUA_TTABLE => self.table(index, depth)?,
+            _ => {
+                if let Some(vector) = lua.get_vector(index) {
+                    self.ext(EXT_VECTOR, [vector.x, vector.y, vector.z]);
+                } else if let Some(angle) = lua.get_angle(index) {
+                    self.ext(EXT_ANGLE, [angle.p, angle.y, angle.r]);
+                } else {
+                    bail!(
+                        "a {} can't be encoded",
+                        lua.lua_type_name(lua.lua_type(index))
+                    );
+                }
+            }
+        }
+
+        if self.out.len() > self.limits.max_bytes {
+            bail!("the value takes more than {} bytes", self.limits.max_bytes);
+        }
+        Ok(())
+    }
+
+    fn number(&mut self, number: f64) {
+        if number.fract() == 0.0 && number.abs() <= LUA_NUMBER_MAX_SAFE_INTEGER as f64 {
+            self.integer(number as i64);
+        } else if (number as f32) as f64 == number || number.is_nan() {
+            self.out.push(0xca);
+            self.out.extend_from_slice(&(number as f32).to_be_bytes());
+        } else {
+            self.out.push(0xcb);
+            self.out.extend_from_slice(&number.to_be_bytes());
+        }
+    }
+
+    fn integer(&mut self, int: i64) {
+        let out = &mut self.out;
+        match int {
+            0..=0x7f => out.push(int as u8),
+            -32..=-1 => out.push(int as i8 as u8),
+            0x80..=0xff => out.extend_from_slice(&[0xcc, int as u8]),
+            0x100..=0xffff => {
+                out.push(0xcd);
+                out.extend_from_slice(&(int as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                out.push(0xce);
+                out.extend_from_slice(&(int as u32).to_be_bytes());
+            }
+            0x1_0000_0000.. => {
+                out.push(0xcf);
+                out.extend_from_slice(&(int as u64).to_be_bytes());
+            }
+            -0x80..=-33 => out.extend_from_slice(&[0xd0, int as i8 as u8]),
+            -0x8000..=-0x81 => {
+                out.push(0xd1);
+                out.extend_from_slice(&(int as i16).to_be_bytes());
+            }
+            -0x8000_0000..=-0x8001 => {
+                out.push(0xd2);
+                out.extend_from_slice(&(int as i32).to_be_bytes());
+            }
+            _ => {
+                out.push(0xd3);
+                out.extend_from_slice(&int.to_be_bytes());
+            }
+        }
+    }
+
+    /// Writes the header of a string, binary, array or map of `len` elements: `fix` is the first byte of the short form and its exclusive maximum length, and `sized` the markers with 8, 16 and 32-bit lengths (arrays and maps have no 8-bit form, so it's the 16-bit one twice).
+    fn header(&mut self, len: usize, fix: Option<(u8, usize)>, sized: [u8; 3]) {
+        match fix {
+            Some((marker, max)) if len < max => self.out.push(marker | len as u8),
+            _ if len <= 0xff && sized[0] != sized[1] => {
+                self.out.extend_from_slice(&[sized[0], len as u8])
+            }
+            _ if len <= 0xffff => {
+                self.out.push(sized[1]);
+                self.out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            _ => {
+                self.out.push(sized[2]);
+                self.out.extend_from_slice(&(len as u32).to_be_bytes());
+            }
+        }
+    }
+
+    fn ext(&mut self, ty: i8, components: [f32; 3]) {
+        self.out.extend_from_slice(&[0xc7, 12, ty as u8]);
+        for component in components {
+            self.out.extend_from_slice(&component.to_be_bytes());
+        }
+    }
+
+    fn table(&mut self, index: i32, depth: usize) -> Result<()> {
+        let lua = self.lua;
+        if depth >= self.limits.max_depth || !lua.check_stack(3) {
+            bail!(
+                "tables are nested more than {} deep, or contain themselves",
+                self.limits.max_depth
+            );
+        }
+
+        let len = lua.len(index).max(0) as usize;
+        let mut keys = 0;
+        let mut sequence = true;
+        lua.push_nil();
+        while unsafe { lua.next(index) } != 0 {
+            keys += 1;
+            sequence = sequence && lua::is_sequence_key(lua, len);
+            lua.pop();
+        }
+
+        if sequence && keys == len {
+            self.header(len, Some((0x90, 16)), [0xdc, 0xdc, 0xdd]);
+            for i in 1..=len {
+                lua.raw_geti(index, i as i32);
+                self.value(lua.get_top(), depth + 1)?;
+                lua.pop();
+            }
+        } else {
+            self.header(keys, Some((0x80, 16)), [0xde, 0xde, 0xdf]);
+            lua.push_nil();
+            while unsafe { lua.next(index) } != 0 {
+                let top = lua.get_top();
+                // the key is copied, as `get_binary_string` converts numbers in place, which breaks `next`
+                lua.push_value(top - 1);
+                self.value(top + 1, depth + 1)?;
+                self.value(top, depth + 1)?;
+                lua.pop_n(2);
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Decoder<'a> {
+    lua: State,
+    limits: &'a Limits,
+    bytes: &'a [u8],
+    pos: usize,
+    values: usize,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        match self.bytes.get(self.pos..self.pos.saturating_add(len)) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => bail!("the payload is truncated"),
+        }
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    /// Reads a big-endian length of `size` bytes.
+    fn len(&mut self, size: usize) -> Result<usize> {
+        Ok(match size {
+            1 => self.take_array::<1>()?[0] as usize,
+            2 => u16::from_be_bytes(self.take_array()?) as usize,
+            _ => u32::from_be_bytes(self.take_array()?) as usize,
+        })
+    }
+
+    /// Decodes a value and pushes it.
+    fn value(&mut self, depth: usize) -> Result<()> {
+        self.values += 1;
+        if self.values > self.limits.max_values {
+            bail!(
+                "the payload holds more than {} values",
+                self.limits.max_values
+            );
+        }
+        if !self.lua.check_stack(3) {
+            bail!("the payload is nested too deeply");
+        }
+
+        let lua = self.lua;
+        let marker = self.take_array::<1>()?[0];
+        match marker {
+            0x00..=0x7f => lua.push_number(marker as f64),
+            0xe0..=0xff => lua.push_number(marker as i8 as f64),
+            0xc0 => lua.push_nil(),
+            0xc2 => lua.push_boolean(false),
+            0xc3 => lua.push_boolean(true),
+            0xcc => lua.push_number(self.take_array::<1>()?[0] as f64),
+            0xcd => lua.push_number(u16::from_be_bytes(self.take_array()?) as f64),
+            0xce => lua.push_number(u32::from_be_bytes(self.take_array()?) as f64),
+            0xcf => lua.push_number(u64::from_be_bytes(self.take_array()?) as f64),
+            0xd0 => lua.push_number(self.take_array::<1>()?[0] as i8 as f64),
+            0xd1 => lua.push_number(i16::from_be_bytes(self.take_array()?) as f64),
+            0xd2 => lua.push_number(i32::from_be_bytes(self.take_array()?) as f64),
+            0xd3 => lua.push_number(i64::from_be_bytes(self.take_array()?) as f64),
+            0xca => lua.push_number(f32::from_be_bytes(self.take_array()?) as f64),
+            0xcb => lua.push_number(f64::from_be_bytes(self.take_array()?)),
+            0xa0..=0xbf => self.string((marker & 0x1f) as usize)?,
+            0xd9 | 0xc4 => {
+                let len = self.len(1)?;
+                self.string(len)?
+            }
+            0xda | 0xc5 => {
+                let len = self.len(2)?;
+                self.string(len)?
+            }
+            0xdb | 0xc6 => {
+                let len = self.len(4)?;
+                self.string(len)?
+            }
+            0x90..=0x9f => self.array((marker & 0x0f) as usize, depth)?,
+            0xdc => {
+                let len = self.len(2)?;
+                self.array(len, depth)?
+            }
+            0xdd => {
+                let len = self.len(4)?;
+                self.array(len, depth)?
+            }
+            0x80..=0x8f => self.map((marker & 0x0f) as usize, depth)?,
+            0xde => {
+                let len = self.len(2)?;
+                self.map(len, depth)?
+            }
+            0xdf => {
+                let len = self.len(4)?;
+                self.map(len, depth)?
+            }
+            0xc7 => {
+                let len = self.len(1)?;
+                let ty = self.take_array::<1>()?[0] as i8;
+                let data = self.take(len)?;
+                if len != 12 || !matches!(ty, EXT_VECTOR | EXT_ANGLE) {
+                    bail!("unknown extension type {ty} of {len} bytes");
+                }
+                let component =
+                    |i: usize| f32::from_be_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+                let (a, b, c) = (component(0), component(1), component(2));
+                if ty == EXT_VECTOR {
+                    lua.push_vector(Vector { x: a, y: b, z: c });
+                } else {
+                    lua.push_angle(Angle { p: a, y: b, r: c });
+                }
+            }
+            _ => bail!("unsupported MessagePack marker {marker:#04x}"),
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, len: usize) -> Result<()> {
+        let lua = self.lua;
+        lua.push_binary_string(self.take(len)?);
+        Ok(())
+    }
+
+    /// Checks a container of `len` elements, each taking at least a byte, fits in what's left of the payload.
+    fn container(&self, len: usize, depth: usize) -> Result<()> {
+        if depth >= self.limits.max_depth {
+            bail!("tables are nested more than {} deep", self.limits.max_depth);
+        }
+        if len > self.bytes.len() - self.pos {
+            bail!("the payload is truncated");
+        }
+        Ok(())
+    }
+
+    fn array(&mut self, len: usize, depth: usize) -> Result<()> {
+        self.container(len, depth)?;
+        self.lua.create_table(len as i32, 0);
+        for i in 1..=len {
+            self.value(depth + 1)?;
+            self.lua.raw_seti(-2, i as i32);
+        }
+        Ok(())
+    }
+
+    fn map(&mut self, len: usize, depth: usize) -> Result<()> {
+        self.container(len.saturating_mul(2), depth)?;
+        let lua = self.lua;
+        lua.create_table(0, len as i32);
+        for _ in 0..len {
+            self.value(depth + 1)?;
+            // `lua_settable` raises an error for these
+            if lua.is_nil(-1) || (lua.lua_type(-1) == LUA_TNUMBER && lua.to_number(-1).is_nan()) {
+                bail!("a table key can't be nil or NaN");
+            }
+            self.value(depth + 1)?;
+            lua.set_table(-3);
+        }
+        Ok(())
+    }
+}
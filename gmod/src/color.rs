@@ -0,0 +1,203 @@
+//! RGBA colors, pushed as real `Color` tables, and parsed from hex strings.
+//!
+//! Colors in GMod are tables with `r`, `g`, `b` and `a` fields, and the `Color` metatable. `push_color` sets that metatable (when the game registered it), so `IsColor` and the color methods work on pushed colors. `check_color` and `get_color` take any table with numeric `r`, `g` and `b` fields, like the game's functions do.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::color::{palette, Color};
+//!
+//! let accent: Color = "#6ebeff".parse()?;
+//! lua.push_color(accent);
+//! gmod::console::print_colored(lua, &[(palette::ORANGE, "[my_module] "), (Color::WHITE, "loaded\n")]);
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, Result};
+
+use crate::lua::{LuaCheck, LuaPush, StackIndex, State, LUA_REGISTRYINDEX};
+
+/// An RGBA color.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const RED: Color = Color::rgb(255, 0, 0);
+    pub const GREEN: Color = Color::rgb(0, 255, 0);
+    pub const BLUE: Color = Color::rgb(0, 0, 255);
+    pub const YELLOW: Color = Color::rgb(255, 255, 0);
+
+    /// The color of serverside `print`s.
+    pub const SERVER: Color = Color::rgb(156, 241, 255);
+    /// The color of clientside `print`s.
+    pub const CLIENT: Color = Color::rgb(255, 241, 122);
+    /// The color of `gmod_warn!`.
+    pub const WARN: Color = Color::rgb(255, 200, 80);
+    /// The color of `gmod_error!`, close to the game's own Lua errors.
+    pub const ERROR: Color = Color::rgb(255, 90, 90);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b, a: 255 }
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// Returns the same color with another alpha.
+    pub const fn with_alpha(self, a: u8) -> Self {
+        Self { a, ..self }
+    }
+
+    /// Parses `RGB`, `RGBA`, `RRGGBB` or `RRGGBBAA` hex digits, optionally preceded by `#`.
+    ///
+    /// ```
+    /// use gmod::color::Color;
+    ///
+    /// assert_eq!(Color::from_hex("#6ebeff").unwrap(), Color::rgb(110, 190, 255));
+    /// assert_eq!(Color::from_hex("f008").unwrap(), Color::rgba(255, 0, 0, 136));
+    /// assert!(Color::from_hex("#12345").is_err());
+    /// ```
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("{hex:?} isn't a hex color");
+        }
+        let channel = |i: usize, width: usize| {
+            let value = u8::from_str_radix(&digits[i * width..(i + 1) * width], 16).unwrap();
+            // a single digit is repeated, like in CSS
+            if width == 1 {
+                value * 0x11
+            } else {
+                value
+            }
+        };
+        Ok(match digits.len() {
+            3 => Self::rgb(channel(0, 1), channel(1, 1), channel(2, 1)),
+            4 => Self::rgba(channel(0, 1), channel(1, 1), channel(2, 1), channel(3, 1)),
+            6 => Self::rgb(channel(0, 2), channel(1, 2), channel(2, 2)),
+            8 => Self::rgba(channel(0, 2), channel(1, 2), channel(2, 2), channel(3, 2)),
+            _ => bail!("{hex:?} isn't a hex color"),
+        })
+    }
+
+    /// Returns the color as `#rrggbb`, or `#rrggbbaa` if it's not opaque.
+    pub fn to_hex(self) -> String {
+        if self.a == 255 {
+            format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", self.r, self.g, self.b, self.a)
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(hex: &str) -> Result<Self> {
+        Self::from_hex(hex)
+    }
+}
+
+/// Colors the game defines, and a few common ones.
+pub mod palette {
+    use super::Color;
+
+    /// `color_white`
+    pub const WHITE: Color = Color::WHITE;
+    /// `color_black`
+    pub const BLACK: Color = Color::BLACK;
+    /// `color_transparent`
+    pub const TRANSPARENT: Color = Color::rgba(255, 255, 255, 0);
+    pub const RED: Color = Color::RED;
+    pub const GREEN: Color = Color::GREEN;
+    pub const BLUE: Color = Color::BLUE;
+    pub const YELLOW: Color = Color::YELLOW;
+    pub const ORANGE: Color = Color::rgb(255, 165, 0);
+    pub const CYAN: Color = Color::rgb(0, 255, 255);
+    pub const MAGENTA: Color = Color::rgb(255, 0, 255);
+    pub const GRAY: Color = Color::rgb(128, 128, 128);
+    /// The color of the default chat text.
+    pub const CHAT: Color = Color::rgb(230, 230, 230);
+    /// The color of the default HUD's text and numbers.
+    pub const HUD: Color = Color::rgb(255, 235, 20);
+}
+
+impl State {
+    /// Pushes a `Color` table, with the `Color` metatable if the game registered it.
+    pub fn push_color(&self, color: Color) {
+        self.create_table(0, 4);
+        for (key, value) in [
+            (c"r", color.r),
+            (c"g", color.g),
+            (c"b", color.b),
+            (c"a", color.a),
+        ] {
+            self.push_number(value);
+            self.set_field(-2, key);
+        }
+        self.get_field(LUA_REGISTRYINDEX, c"Color");
+        if self.is_table(-1) {
+            unsafe { self.set_metatable(-2) };
+        } else {
+            self.pop();
+        }
+    }
+
+    /// Returns the color at the given stack index, or `None` if the value isn't a table with numeric `r`, `g` and `b` fields. `a` defaults to 255, and fields are clamped between 0 and 255.
+    pub fn get_color(&self, index: impl Into<StackIndex>) -> Option<Color> {
+        let index = index.into().abs(*self);
+        if !self.is_table(index) {
+            return None;
+        }
+        let mut channels = [255; 4];
+        for (channel, key) in channels.iter_mut().zip([c"r", c"g", c"b", c"a"]) {
+            self.get_field(index, key);
+            let is_number = self.is_number(-1);
+            if is_number {
+                *channel = self.to_number(-1).clamp(0.0, 255.0) as u8;
+            }
+            self.pop();
+            if !is_number && key != c"a" {
+                return None;
+            }
+        }
+        let [r, g, b, a] = channels;
+        Some(Color { r, g, b, a })
+    }
+
+    pub fn check_color(&self, arg: impl Into<StackIndex>) -> Result<Color> {
+        let arg = arg.into().0;
+        match self.get_color(arg) {
+            Some(color) => Ok(color),
+            None => bail!(self.type_error(arg, "Color")),
+        }
+    }
+}
+
+impl LuaCheck for Color {
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        l.check_color(arg)
+    }
+}
+
+impl LuaPush for Color {
+    fn lua_push(self, l: State) {
+        l.push_color(self);
+    }
+}
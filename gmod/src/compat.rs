@@ -0,0 +1,111 @@
+//! Shims for modules written against the upstream [WilliamVenner/gmod-rs](https://github.com/WilliamVenner/gmod-rs) API, enabled by the `compat-legacy` feature.
+//!
+//! Most of the upstream API is unchanged in this fork (`gmod::lua::State`, `lua_regs!`, `lua_stack_guard!`, `#[lua_function]`, `#[gmod13_open]`, `#[gmod13_close]`, `open_library!`...). This module covers what was renamed or removed, so that an existing module only needs to import the prelude to build:
+//!
+//! ```no_run
+//! use gmod::compat::prelude::*;
+//!
+//! #[lua_function]
+//! unsafe fn div(lua: gmod::lua::State) -> i32 {
+//!     let a = lua.to_integer(1);
+//!     let b = lua.to_integer(2);
+//!     if b == 0 {
+//!         lua.error("division by zero");
+//!     }
+//!     lua.push_integer(a / b);
+//!     1
+//! }
+//!
+//! #[gmod13_open]
+//! unsafe fn gmod13_open(lua: gmod::lua::State) -> i32 {
+//!     lua.new_table();
+//!     lua.push_function(div);
+//!     lua.set_field(-2, lua_string!("Div"));
+//!     lua.set_global(lua_string!("math_ext"));
+//!     0
+//! }
+//! ```
+//!
+//! ## What still needs changes
+//!
+//! Methods that kept their name but changed their signature can't be shimmed, as the new method always takes precedence:
+//!
+//! | upstream | this fork |
+//! |---|---|
+//! | `lua.check_string(1)` and the other `check_*` methods raise a Lua error | they return a `Result`, use `?` in a `#[lua_function]` returning one |
+//! | `lua.check_integer(1)` returns a `LuaInt` | generic over the integer type, e.g. `lua.check_integer::<i64>(1)?` |
+//! | `lua.register(lua_string!("lib"), ...)` | `register` takes a raw pointer: `lua_string!("lib").as_ptr()` |
+//! | `gmod::gmcl`, `gmod::msgc`, `gmod::hax` | not available |
+
+use std::ffi::c_void;
+
+use crate::lua::{LuaCStr, State};
+
+/// The integer type of upstream's `to_integer`, `push_integer` and `check_integer`.
+pub type LuaInt = isize;
+
+/// Returns a `&'static CStr` of a string literal, for the methods that take one (`get_field`, `set_global`, `load_string`...).
+///
+/// Upstream's version returned a raw pointer; calls that still take one need `.as_ptr()`.
+#[macro_export]
+macro_rules! lua_string {
+    ($str:literal) => {
+        $crate::lua_cstr!($str)
+    };
+}
+
+/// Upstream `State` methods that were removed.
+pub trait LegacyState {
+    /// Pushes an integer, as a number.
+    fn push_integer(&self, int: LuaInt);
+
+    /// Returns the number at the given stack index truncated to an integer, or 0 if it isn't a number.
+    fn to_integer(&self, index: i32) -> LuaInt;
+
+    /// Raises a Lua error. Rust destructors between here and the enclosing `pcall` don't run, so prefer returning a `Result` from `#[lua_function]`s.
+    fn error<S: AsRef<str>>(&self, msg: S) -> !;
+
+    /// Returns whether the value at the given stack index is a userdata with the metatable registered as `name`.
+    fn test_userdata(&self, index: i32, name: LuaCStr) -> bool;
+
+    /// Returns the userdata at the given stack index if it has the metatable registered as `name`, or raises an argument error.
+    fn check_userdata(&self, arg: i32, name: LuaCStr) -> *mut c_void;
+}
+
+impl LegacyState for State {
+    #[inline(always)]
+    fn push_integer(&self, int: LuaInt) {
+        self.push_number(int as f64);
+    }
+
+    #[inline(always)]
+    fn to_integer(&self, index: i32) -> LuaInt {
+        self.to_number(index) as LuaInt
+    }
+
+    #[inline(always)]
+    fn error<S: AsRef<str>>(&self, msg: S) -> ! {
+        State::error(self, msg)
+    }
+
+    #[inline(always)]
+    fn test_userdata(&self, index: i32, name: LuaCStr) -> bool {
+        self.is_userdata_of(index, name)
+    }
+
+    fn check_userdata(&self, arg: i32, name: LuaCStr) -> *mut c_void {
+        if !self.is_userdata_of(arg, name) {
+            State::error(self, self.type_error(arg, &name.to_string_lossy()))
+        }
+        self.to_userdata(arg)
+    }
+}
+
+/// Everything needed to build a module written against the upstream API.
+pub mod prelude {
+    pub use super::{LegacyState, LuaInt};
+    pub use crate::lua::{LuaReg, State};
+    pub use crate::{
+        gmod13_close, gmod13_open, lua_function, lua_regs, lua_stack_guard, lua_string,
+    };
+}
@@ -0,0 +1,465 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    callbacks::{self, Registry, Removal},
+    lua::State,
+};
+
+/// `FCVAR_*` flags accepted by `Builder::flags`.
+pub use crate::convar::flags;
+
+/// The player that ran a console command, at the first argument of the command callback.
+#[derive(Clone, Copy)]
+pub struct Caller {
+    lua: State,
+}
+
+impl Caller {
+    /// Returns the Lua state the command is running in.
+    pub fn lua(&self) -> State {
+        self.lua
+    }
+
+    /// Returns whether the command was run from the server console rather than by a player.
+    pub fn is_console(&self) -> bool {
+        let lua = self.lua;
+        lua.get_global(c"IsValid");
+        lua.push_value(1);
+        if !lua.pcall_ignore(1, 1) {
+            return true;
+        }
+        let valid = lua.get_boolean(-1);
+        lua.pop();
+        !valid
+    }
+
+    /// Pushes the player (or a NULL entity for the console) onto the stack.
+    pub fn push(&self) {
+        self.lua.push_value(1);
+    }
+
+    /// Returns the SteamID64 of the player, or `None` for the console.
+    pub fn steamid64(&self) -> Option<u64> {
+        if self.is_console() {
+            return None;
+        }
+        let lua = self.lua;
+        lua.push_value(1);
+        lua.get_field(-1, c"SteamID64");
+        lua.insert(-2);
+        if !lua.pcall_ignore(1, 1) {
+            return None;
+        }
+        let steamid64 = lua.get_string(-1).and_then(|s| s.parse().ok());
+        lua.pop();
+        steamid64
+    }
+}
+
+/// The arguments a console command was run with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Args {
+    args: Vec<String>,
+    raw: String,
+}
+
+impl Args {
+    /// Returns the number of arguments.
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    /// Returns whether no arguments were given.
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Returns an argument by its position, starting from 0.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.args.get(index).map(String::as_str)
+    }
+
+    /// Returns the arguments as a slice.
+    pub fn as_slice(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Returns the arguments as a single string, as typed after the command name.
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// Parses the arguments into a typed tuple (or `Vec`).
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use gmod::concommand::Args;
+    /// let args = Args::from(vec!["kick".to_string(), "5".to_string()]);
+    /// let (action, amount, reason): (String, u32, Option<String>) = args.parse().unwrap();
+    ///
+    /// assert_eq!(action, "kick");
+    /// assert_eq!(amount, 5);
+    /// assert_eq!(reason, None);
+    /// assert!(args.parse::<(String, bool)>().is_err());
+    /// ```
+    pub fn parse<T: FromArgs>(&self) -> Result<T> {
+        T::from_args(&self.args)
+    }
+}
+
+impl From<Vec<String>> for Args {
+    fn from(args: Vec<String>) -> Self {
+        let raw = args.join(" ");
+        Self { args, raw }
+    }
+}
+
+/// Converts a single console command argument. `None` means the argument was not given.
+pub trait FromArg: Sized {
+    fn from_arg(arg: Option<&str>) -> Result<Self>;
+}
+
+fn parse_arg<T: FromStr>(arg: Option<&str>, expected: &str) -> Result<T> {
+    let arg = arg.ok_or_else(|| anyhow!("{expected} expected, got nothing"))?;
+    arg.trim()
+        .parse()
+        .map_err(|_| anyhow!("{expected} expected, got {arg:?}"))
+}
+
+macro_rules! impl_from_arg {
+    ($($ty:ty => $expected:literal),*) => {
+        $(impl FromArg for $ty {
+            fn from_arg(arg: Option<&str>) -> Result<Self> {
+                parse_arg(arg, $expected)
+            }
+        })*
+    };
+}
+
+impl_from_arg!(
+    i8 => "number", i16 => "number", i32 => "number", i64 => "number", isize => "number",
+    u8 => "number", u16 => "number", u32 => "number", u64 => "number", usize => "number",
+    f32 => "number", f64 => "number", char => "character"
+);
+
+impl FromArg for String {
+    fn from_arg(arg: Option<&str>) -> Result<Self> {
+        arg.map(str::to_string)
+            .ok_or_else(|| anyhow!("string expected, got nothing"))
+    }
+}
+
+impl FromArg for bool {
+    fn from_arg(arg: Option<&str>) -> Result<Self> {
+        match arg.map(|arg| arg.trim().to_ascii_lowercase()).as_deref() {
+            Some("1" | "true" | "yes" | "on") => Ok(true),
+            Some("0" | "false" | "no" | "off") => Ok(false),
+            Some(arg) => Err(anyhow!("boolean expected, got {arg:?}")),
+            None => Err(anyhow!("boolean expected, got nothing")),
+        }
+    }
+}
+
+impl<T: FromArg> FromArg for Option<T> {
+    fn from_arg(arg: Option<&str>) -> Result<Self> {
+        arg.map(|arg| T::from_arg(Some(arg))).transpose()
+    }
+}
+
+/// Converts every argument of a console command at once. Implemented for tuples of `FromArg` types, and `Vec`s.
+pub trait FromArgs: Sized {
+    fn from_args(args: &[String]) -> Result<Self>;
+}
+
+impl<T: FromArg> FromArgs for Vec<T> {
+    fn from_args(args: &[String]) -> Result<Self> {
+        args.iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                T::from_arg(Some(arg)).map_err(|err| anyhow!("bad argument #{}: {err}", i + 1))
+            })
+            .collect()
+    }
+}
+
+macro_rules! impl_from_args {
+    ($($ty:ident),+) => {
+        impl<$($ty: FromArg),+> FromArgs for ($($ty,)+) {
+            fn from_args(args: &[String]) -> Result<Self> {
+                let mut args = args.iter().map(String::as_str);
+                let mut i = 0;
+                Ok(($({
+                    i += 1;
+                    $ty::from_arg(args.next()).map_err(|err| anyhow!("bad argument #{i}: {err}"))?
+                },)+))
+            }
+        }
+    };
+}
+
+impl_from_args!(A);
+impl_from_args!(A, B);
+impl_from_args!(A, B, C);
+impl_from_args!(A, B, C, D);
+impl_from_args!(A, B, C, D, E);
+impl_from_args!(A, B, C, D, E, F);
+impl_from_args!(A, B, C, D, E, F, G);
+impl_from_args!(A, B, C, D, E, F, G, H);
+
+type Callback = Box<dyn FnMut(Caller, &str, Args)>;
+type AutoComplete = Box<dyn FnMut(&str, &str) -> Vec<String>>;
+
+struct Command {
+    name: String,
+    /// Taken out while the callback is running.
+    callback: Option<Callback>,
+    /// Taken out while the callback is running.
+    autocomplete: Option<AutoComplete>,
+}
+
+thread_local! {
+    static COMMANDS: Registry<Command> = Registry::new();
+}
+
+/// Builder for a console command, created with `build`.
+#[must_use = "the command is only registered by calling `register`"]
+pub struct Builder {
+    name: String,
+    help: Option<String>,
+    flags: i32,
+    autocomplete: Option<AutoComplete>,
+}
+
+/// Prepares a console command with a help text, flags or autocompletion.
+///
+/// ## Example
+///
+/// ```ignore
+/// let handle = gmod::concommand::build("my_addon_give")
+///     .help("Gives an item to yourself")
+///     .autocomplete(|cmd, partial| {
+///         ["weapon_crowbar", "weapon_pistol"]
+///             .into_iter()
+///             .filter(|item| item.starts_with(partial.trim()))
+///             .map(|item| format!("{cmd} {item}"))
+///             .collect()
+///     })
+///     .register(lua, |ply, cmd, args| {
+///         let Ok((item,)) = args.parse::<(String,)>() else {
+///             return;
+///         };
+///         // ...
+///     });
+/// ```
+pub fn build(name: &str) -> Builder {
+    Builder {
+        name: name.to_string(),
+        help: None,
+        flags: 0,
+        autocomplete: None,
+    }
+}
+
+impl Builder {
+    /// Sets the help text shown by the `help` console command.
+    pub fn help(mut self, help: &str) -> Self {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    /// Sets the `FCVAR_*` flags of the command.
+    pub fn flags(mut self, flags: i32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Sets the autocompletion callback, called with the command name and the arguments typed so far.
+    ///
+    /// It must return the full suggested lines, including the command name.
+    pub fn autocomplete<F>(mut self, autocomplete: F) -> Self
+    where
+        F: FnMut(&str, &str) -> Vec<String> + 'static,
+    {
+        self.autocomplete = Some(Box::new(autocomplete));
+        self
+    }
+
+    /// Registers the command with `concommand.Add`. `callback` is called with the calling player, the command name and its arguments.
+    ///
+    /// The command is removed when the returned handle is dropped, or when the module is closed.
+    pub fn register<F>(self, lua: State, callback: F) -> ConCommandHandle
+    where
+        F: FnMut(Caller, &str, Args) + 'static,
+    {
+        let id = COMMANDS.with(Registry::next_id);
+
+        let has_autocomplete = self.autocomplete.is_some();
+
+        // re-registering a command replaces it in Lua, so forget about the previous one
+        COMMANDS.with(|commands| {
+            commands.with(|commands| commands.retain(|_, cmd| cmd.name != self.name));
+            commands.insert(
+                id,
+                Command {
+                    name: self.name.clone(),
+                    callback: Some(Box::new(callback)),
+                    autocomplete: self.autocomplete,
+                },
+            )
+        });
+
+        lua.get_global(c"concommand");
+        lua.get_field(-1, c"Add");
+        lua.push_string(&self.name);
+        lua.push_number(id);
+        lua.push_closure(command_callback, 1);
+        if has_autocomplete {
+            lua.push_number(id);
+            lua.push_closure(autocomplete_callback, 1);
+        } else {
+            lua.push_nil();
+        }
+        match &self.help {
+            Some(help) => lua.push_string(help),
+            None => lua.push_nil(),
+        }
+        lua.push_number(self.flags);
+        if !lua.pcall_ignore(5, 0) {
+            COMMANDS.with(|commands| commands.remove(id));
+        }
+        lua.pop();
+
+        ConCommandHandle {
+            removal: Removal::new(id, remove_by_id),
+            name: self.name,
+        }
+    }
+}
+
+/// Registers a console command with `concommand.Add`. `callback` is called with the calling player, the command name and its arguments.
+///
+/// The command is removed when the returned handle is dropped, or when the module is closed. Use `build` to set a help text, flags or autocompletion.
+///
+/// ## Example
+///
+/// ```ignore
+/// let handle = gmod::concommand::add(lua, "my_addon_kick", |ply, cmd, args| {
+///     match args.parse::<(u32, Option<String>)>() {
+///         Ok((userid, reason)) => { /* ... */ }
+///         Err(err) => println!("{cmd}: {err}"),
+///     }
+/// });
+/// ```
+pub fn add<F>(lua: State, name: &str, callback: F) -> ConCommandHandle
+where
+    F: FnMut(Caller, &str, Args) + 'static,
+{
+    build(name).register(lua, callback)
+}
+
+/// A handle to a console command registered with `add`. The command is removed when the handle is dropped, unless it is detached.
+#[must_use = "the command is removed as soon as the handle is dropped"]
+#[derive(Debug)]
+pub struct ConCommandHandle {
+    removal: Removal,
+    name: String,
+}
+
+impl ConCommandHandle {
+    /// Returns the name of the command.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Removes the command immediately. Must be called from the Lua thread.
+    pub fn remove(self, lua: State) {
+        self.removal.remove(lua);
+    }
+
+    /// Keeps the command registered after the handle is dropped, until the module is closed.
+    pub fn detach(self) {
+        self.removal.detach();
+    }
+}
+
+fn remove_by_id(lua: State, id: u64) {
+    if let Some(command) = COMMANDS.with(|commands| commands.remove(id)) {
+        lua.get_global(c"concommand");
+        lua.get_field(-1, c"Remove");
+        lua.push_string(&command.name);
+        lua.pcall_ignore(1, 0);
+        lua.pop();
+    }
+}
+
+/// Removes every console command registered through this module. This is called for you by `#[gmod13_close]`.
+pub fn unload(lua: State) {
+    for id in COMMANDS.with(Registry::ids) {
+        remove_by_id(lua, id);
+    }
+}
+
+fn read_args(lua: State, index: i32) -> Vec<String> {
+    if !lua.is_table(index) {
+        return Vec::new();
+    }
+    (1..=lua.len(index))
+        .map(|i| {
+            lua.raw_geti(index, i);
+            let arg = lua.get_string(-1).unwrap_or_default().into_owned();
+            lua.pop();
+            arg
+        })
+        .collect()
+}
+
+extern "C-unwind" fn command_callback(lua: State) -> i32 {
+    let id = lua.to_number(lua.upvalue_index(1)) as u64;
+
+    let cmd = lua.get_string(2).unwrap_or_default().into_owned();
+    let args = Args {
+        args: read_args(lua, 3),
+        raw: lua.get_string(4).unwrap_or_default().into_owned(),
+    };
+    let result = COMMANDS.with(|commands| {
+        commands.run(
+            id,
+            |command| &mut command.callback,
+            |callback| callback(Caller { lua }, &cmd, args),
+        )
+    });
+
+    match result {
+        Some(Err(panic)) => callbacks::raise(lua, panic),
+        _ => 0,
+    }
+}
+
+extern "C-unwind" fn autocomplete_callback(lua: State) -> i32 {
+    let id = lua.to_number(lua.upvalue_index(1)) as u64;
+
+    let cmd = lua.get_string(1).unwrap_or_default().into_owned();
+    let partial = lua.get_string(2).unwrap_or_default().into_owned();
+    let result = COMMANDS.with(|commands| {
+        commands.run(
+            id,
+            |command| &mut command.autocomplete,
+            |autocomplete| autocomplete(&cmd, &partial),
+        )
+    });
+
+    let suggestions = match result {
+        Some(Ok(suggestions)) => suggestions,
+        Some(Err(panic)) => return callbacks::raise(lua, panic),
+        None => return 0,
+    };
+    lua.create_table(suggestions.len() as i32, 0);
+    for (i, suggestion) in suggestions.iter().enumerate() {
+        lua.push_string(suggestion);
+        lua.raw_seti(-2, i as i32 + 1);
+    }
+    1
+}
@@ -0,0 +1,479 @@
+//! Module settings kept in a Rust struct, backed by convars or by a JSON file under `data/`.
+//!
+//! `#[derive(GmodConfig)]` lists the fields of a struct, which must implement `Default` (for the default values) and `Clone`. A `Config` then keeps a copy of the struct in sync with where the settings are stored:
+//!
+//! - `Config::convars` creates a convar per field, named `<prefix>_<field>`. Changing a convar from the console updates the struct.
+//! - `Config::json` reads `data/<prefix>/config.json`, writing it with the default values if it doesn't exist.
+//!
+//! Both register a `<prefix>_reload_config` console command, which loads the settings again (serverside, only from the server console). The struct can be read from any thread, and `on_change` callbacks run on the Lua tick after a change.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::config::{Config, GmodConfig};
+//!
+//! #[derive(GmodConfig, Default, Clone)]
+//! struct Settings {
+//!     /// Enables the module
+//!     enabled: bool,
+//!     #[config(help = "Seconds between two saves")]
+//!     save_interval: u32,
+//!     webhook: String,
+//! }
+//!
+//! // mymod_enabled, mymod_save_interval and mymod_webhook, and mymod_reload_config
+//! let config = Config::<Settings>::convars(lua, "mymod", gmod::convar::flags::ARCHIVE);
+//! config.on_change(|lua, settings| gmod::gmod_print!(lua, "enabled: {}", settings.enabled));
+//!
+//! std::thread::spawn(move || {
+//!     if config.read(|settings| settings.enabled) {
+//!         // ...
+//!     }
+//! });
+//! ```
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::{anyhow, bail, Result};
+
+pub use gmod_macros::GmodConfig;
+
+use crate::{
+    concommand,
+    convar::{self, ConVar},
+    file,
+    lua::{task_queue, State},
+    realm::Realm,
+};
+
+/// A field of a `GmodConfig` struct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    /// The convar suffix and JSON key of the field.
+    pub name: &'static str,
+    /// The help text of the convar.
+    pub help: &'static str,
+}
+
+/// A struct of settings, implemented with `#[derive(GmodConfig)]`.
+pub trait GmodConfig: Default + Clone + Send + Sync + 'static {
+    const FIELDS: &'static [Field];
+
+    /// The values of the fields, in the order of `FIELDS`.
+    fn values(&self) -> Vec<&dyn ConfigValue>;
+
+    /// Same as `values`, mutably.
+    fn values_mut(&mut self) -> Vec<&mut dyn ConfigValue>;
+}
+
+/// A type a `GmodConfig` field can have: a boolean, a number or a string.
+pub trait ConfigValue {
+    /// Returns the value as a convar string.
+    fn to_convar(&self) -> String;
+
+    /// Parses a convar string. Returns false, leaving the value unchanged, if it isn't valid.
+    fn set_convar(&mut self, value: &str) -> bool;
+
+    /// Pushes the value, to be written as JSON.
+    fn push(&self, lua: State);
+
+    /// Reads the Lua value at `index`, read from JSON. Returns false, leaving the value unchanged, if it isn't valid.
+    fn set_lua(&mut self, lua: State, index: i32) -> bool;
+}
+
+impl ConfigValue for bool {
+    fn to_convar(&self) -> String {
+        if *self { "1" } else { "0" }.to_string()
+    }
+
+    fn set_convar(&mut self, value: &str) -> bool {
+        // convars are true if they're a non-zero number, like `ConVar:GetBool`
+        match value.trim() {
+            "true" => *self = true,
+            "false" => *self = false,
+            value => match value.parse::<f64>() {
+                Ok(number) => *self = number != 0.0,
+                Err(_) => return false,
+            },
+        }
+        true
+    }
+
+    fn push(&self, lua: State) {
+        lua.push_boolean(*self);
+    }
+
+    fn set_lua(&mut self, lua: State, index: i32) -> bool {
+        if !lua.is_boolean(index) {
+            return false;
+        }
+        *self = lua.get_boolean(index);
+        true
+    }
+}
+
+macro_rules! impl_config_value_integer {
+    ($($ty:ty),*) => {
+        $(impl ConfigValue for $ty {
+            fn to_convar(&self) -> String {
+                self.to_string()
+            }
+
+            fn set_convar(&mut self, value: &str) -> bool {
+                match value.trim().parse() {
+                    Ok(value) => {
+                        *self = value;
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+
+            fn push(&self, lua: State) {
+                lua.push_number(*self);
+            }
+
+            fn set_lua(&mut self, lua: State, index: i32) -> bool {
+                if !lua.is_number(index) {
+                    return false;
+                }
+                let number = lua.to_number(index);
+                if number.fract() != 0.0 || number < <$ty>::MIN as f64 || number > <$ty>::MAX as f64 {
+                    return false;
+                }
+                *self = number as $ty;
+                true
+            }
+        })*
+    };
+}
+impl_config_value_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_config_value_float {
+    ($($ty:ty),*) => {
+        $(impl ConfigValue for $ty {
+            fn to_convar(&self) -> String {
+                self.to_string()
+            }
+
+            fn set_convar(&mut self, value: &str) -> bool {
+                match value.trim().parse() {
+                    Ok(value) => {
+                        *self = value;
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+
+            fn push(&self, lua: State) {
+                lua.push_number(*self);
+            }
+
+            fn set_lua(&mut self, lua: State, index: i32) -> bool {
+                if !lua.is_number(index) {
+                    return false;
+                }
+                *self = lua.to_number(index) as $ty;
+                true
+            }
+        })*
+    };
+}
+impl_config_value_float!(f32, f64);
+
+impl ConfigValue for String {
+    fn to_convar(&self) -> String {
+        self.clone()
+    }
+
+    fn set_convar(&mut self, value: &str) -> bool {
+        value.clone_into(self);
+        true
+    }
+
+    fn push(&self, lua: State) {
+        lua.push_string(self);
+    }
+
+    fn set_lua(&mut self, lua: State, index: i32) -> bool {
+        if !lua.is_string(index) {
+            return false;
+        }
+        match lua.get_string(index) {
+            Some(value) => {
+                *self = value.into_owned();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Where a `Config` is stored.
+enum Storage {
+    ConVars,
+    Json { path: String },
+}
+
+type Listener<T> = Box<dyn FnMut(State, &T) + Send>;
+
+struct Shared<T> {
+    prefix: String,
+    storage: Storage,
+    value: RwLock<T>,
+    /// Taken out while they run, so they can register more listeners.
+    listeners: Mutex<Vec<Listener<T>>>,
+}
+
+/// Settings of type `T`, see the module documentation. Clones share the same settings.
+pub struct Config<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Config<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// Returns the convar strings of the fields, to tell whether they changed.
+fn snapshot<T: GmodConfig>(value: &T) -> Vec<String> {
+    value
+        .values()
+        .iter()
+        .map(|value| value.to_convar())
+        .collect()
+}
+
+impl<T: GmodConfig> Config<T> {
+    /// Creates a convar per field, named `<prefix>_<field>` with `flags`, and the `<prefix>_reload_config` command. Convars that already exist (e.g. archived ones) keep their value, which is loaded.
+    pub fn convars(lua: State, prefix: &str, flags: i32) -> Self {
+        let config = Self::new(lua, prefix, Storage::ConVars);
+        let defaults = T::default();
+        for (i, (field, value)) in T::FIELDS.iter().zip(defaults.values()).enumerate() {
+            let name = format!("{prefix}_{}", field.name);
+            convar::create(lua, &name, &value.to_convar(), flags, field.help);
+
+            let shared = config.shared.clone();
+            convar::on_change(lua, &name, move |lua, _, new| {
+                let changed = {
+                    let mut value = shared.value.write().unwrap_or_else(|e| e.into_inner());
+                    let before = value.values()[i].to_convar();
+                    value.values_mut()[i].set_convar(new) && value.values()[i].to_convar() != before
+                };
+                if changed {
+                    notify(lua, &shared);
+                }
+            })
+            .detach();
+        }
+        let _ = config.load_inner(lua);
+        config
+    }
+
+    /// Loads the settings from `data/<prefix>/config.json`, writing it with the default values if it doesn't exist, and creates the `<prefix>_reload_config` command.
+    ///
+    /// Keys missing from the file keep their default value, and keys the file doesn't know are added the next time it's saved.
+    pub fn json(lua: State, prefix: &str) -> Result<Self> {
+        let path = format!("data/{prefix}/config.json");
+        let config = Self::new(lua, prefix, Storage::Json { path: path.clone() });
+        if file::exists(lua, &path) {
+            config.load_inner(lua)?;
+        } else {
+            config.save(lua)?;
+        }
+        Ok(config)
+    }
+
+    fn new(lua: State, prefix: &str, storage: Storage) -> Self {
+        let config = Self {
+            shared: Arc::new(Shared {
+                prefix: prefix.to_string(),
+                storage,
+                value: RwLock::new(T::default()),
+                listeners: Mutex::new(Vec::new()),
+            }),
+        };
+
+        let reload = config.clone();
+        concommand::build(&format!("{prefix}_reload_config"))
+            .help("Reloads the settings of the module")
+            .register(lua, move |caller, cmd, _| {
+                let lua = caller.lua();
+                if lua.realm() == Realm::Server && !caller.is_console() {
+                    crate::gmod_warn!(lua, "{cmd} can only be run from the server console");
+                    return;
+                }
+                match reload.load(lua) {
+                    Ok(true) => crate::gmod_print!(lua, "{cmd}: settings reloaded"),
+                    Ok(false) => crate::gmod_print!(lua, "{cmd}: no settings changed"),
+                    Err(err) => crate::gmod_error!(lua, "{cmd}: {err:#}"),
+                }
+            })
+            .detach();
+        config
+    }
+
+    /// The prefix of the convars, command and directory of the settings.
+    pub fn prefix(&self) -> &str {
+        &self.shared.prefix
+    }
+
+    /// Returns a copy of the settings. Can be called from any thread.
+    pub fn get(&self) -> T {
+        self.read(T::clone)
+    }
+
+    /// Calls `f` with the settings. Can be called from any thread.
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.shared.value.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Loads the settings again from the convars or the file, and returns whether they changed. Values that aren't valid for their field are ignored.
+    pub fn load(&self, lua: State) -> Result<bool> {
+        let changed = self.load_inner(lua)?;
+        if changed {
+            notify(lua, &self.shared);
+        }
+        Ok(changed)
+    }
+
+    fn load_inner(&self, lua: State) -> Result<bool> {
+        let mut value = self.read(T::clone);
+        let before = snapshot(&value);
+        match &self.shared.storage {
+            Storage::ConVars => {
+                for (field, value) in T::FIELDS.iter().zip(value.values_mut()) {
+                    let name = format!("{}_{}", self.shared.prefix, field.name);
+                    if let Some(convar) =
+                        ConVar::find(lua, &name).and_then(|convar| convar.get_string(lua))
+                    {
+                        value.set_convar(&convar);
+                    }
+                }
+            }
+            Storage::Json { path } => {
+                let json = file::read_to_string(lua, path)?;
+                let top = lua.get_top();
+                let result = read_json(lua, &json, &mut value);
+                lua.set_top(top);
+                result.map_err(|err| anyhow!("couldn't load {path}: {err}"))?;
+            }
+        }
+        let changed = snapshot(&value) != before;
+        *self.shared.value.write().unwrap_or_else(|e| e.into_inner()) = value;
+        Ok(changed)
+    }
+
+    /// Writes the settings to the convars or the file.
+    pub fn save(&self, lua: State) -> Result<()> {
+        let value = self.get();
+        match &self.shared.storage {
+            Storage::ConVars => {
+                for (field, value) in T::FIELDS.iter().zip(value.values()) {
+                    let name = format!("{}_{}", self.shared.prefix, field.name);
+                    let Some(convar) = ConVar::find(lua, &name) else {
+                        bail!("the convar {name} doesn't exist");
+                    };
+                    convar.set_string(lua, &value.to_convar());
+                }
+            }
+            Storage::Json { path } => {
+                let top = lua.get_top();
+                let json = write_json(lua, &value);
+                lua.set_top(top);
+                file::write(lua, path, json?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the settings with `f` and saves them.
+    pub fn set(&self, lua: State, f: impl FnOnce(&mut T)) -> Result<()> {
+        let changed = {
+            let mut value = self.shared.value.write().unwrap_or_else(|e| e.into_inner());
+            let before = snapshot(&*value);
+            f(&mut value);
+            snapshot(&*value) != before
+        };
+        self.save(lua)?;
+        if changed {
+            notify(lua, &self.shared);
+        }
+        Ok(())
+    }
+
+    /// Calls `callback` with the new settings on the Lua tick after they change, until the module is closed.
+    pub fn on_change<F>(&self, callback: F)
+    where
+        F: FnMut(State, &T) + Send + 'static,
+    {
+        self.shared
+            .listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Box::new(callback));
+    }
+}
+
+/// Runs the listeners on the next tick, with the settings as they are then.
+fn notify<T: GmodConfig>(lua: State, shared: &Arc<Shared<T>>) {
+    let shared = shared.clone();
+    let _ = task_queue::wait_lua_tick_in(lua.realm(), String::new(), move |lua| {
+        let value = shared
+            .value
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let mut listeners =
+            std::mem::take(&mut *shared.listeners.lock().unwrap_or_else(|e| e.into_inner()));
+        for listener in &mut listeners {
+            listener(lua, &value);
+        }
+        let mut slot = shared.listeners.lock().unwrap_or_else(|e| e.into_inner());
+        listeners.append(&mut slot);
+        *slot = listeners;
+    });
+}
+
+/// Reads the fields of `value` from a JSON object with `util.JSONToTable`.
+fn read_json<T: GmodConfig>(lua: State, json: &str, value: &mut T) -> Result<()> {
+    lua.get_global(c"util");
+    lua.get_field(-1, c"JSONToTable");
+    lua.push_string(json);
+    lua.pcall(1, 1, 0).map_err(|err| anyhow!("{err}"))?;
+    if !lua.is_table(-1) {
+        bail!("it isn't a JSON object");
+    }
+    let table = lua.get_top();
+    for (field, value) in T::FIELDS.iter().zip(value.values_mut()) {
+        lua.push_string(field.name);
+        lua.get_table(table);
+        if !lua.is_nil(-1) {
+            value.set_lua(lua, -1);
+        }
+        lua.pop();
+    }
+    Ok(())
+}
+
+/// Writes the fields of `value` as a JSON object with `util.TableToJSON`.
+fn write_json<T: GmodConfig>(lua: State, value: &T) -> Result<String> {
+    lua.get_global(c"util");
+    lua.get_field(-1, c"TableToJSON");
+    lua.create_table(0, T::FIELDS.len() as i32);
+    for (field, value) in T::FIELDS.iter().zip(value.values()) {
+        lua.push_string(field.name);
+        value.push(lua);
+        lua.set_table(-3);
+    }
+    // pretty printed
+    lua.push_boolean(true);
+    lua.pcall(2, 1, 0).map_err(|err| anyhow!("{err}"))?;
+    lua.get_string(-1)
+        .map(|json| json.into_owned())
+        .ok_or_else(|| anyhow!("util.TableToJSON returned nothing"))
+}
@@ -0,0 +1,92 @@
+//! Printing to the game console through `MsgC` and `Msg`.
+//!
+//! `println!` writes to the process' stdout, which isn't the game console on Windows, nor on listen servers and clients on any platform. These functions go through Lua instead, so they must be called on the main thread; from other threads, queue them with `wait_lua_tick` (or use `gmod::log`).
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::console::{self, Color};
+//!
+//! console::print_colored(lua, &[(Color::rgb(110, 190, 255), "[my_module] "), (Color::WHITE, "loaded\n")]);
+//!
+//! gmod::gmod_print!(lua, "{} players online", count);
+//! gmod::gmod_warn!(lua, "config missing, using defaults");
+//! ```
+
+pub use crate::color::Color;
+use crate::lua::State;
+
+/// Returns the color `print` uses in this realm.
+pub fn realm_color(lua: State) -> Color {
+    if unsafe { lua.is_client() } {
+        Color::CLIENT
+    } else {
+        Color::SERVER
+    }
+}
+
+/// Prints every segment in its color with `MsgC`, without adding a newline. Falls back to `Msg` without colors where `MsgC` doesn't exist (e.g. the menu state), then to stdout.
+pub fn print_colored(lua: State, segments: &[(Color, &str)]) {
+    if lua.ensure_stack(segments.len() as i32 * 2 + 1).is_ok() {
+        lua.get_global(c"MsgC");
+        if lua.is_function(-1) {
+            for &(color, text) in segments {
+                lua.push_color(color);
+                lua.push_string(text);
+            }
+            if lua.pcall(segments.len() as i32 * 2, 0, 0).is_ok() {
+                return;
+            }
+        }
+        lua.pop();
+    }
+
+    let text: String = segments.iter().map(|(_, text)| *text).collect();
+    print(lua, &text);
+}
+
+/// Prints `text` with `Msg`, without adding a newline. Falls back to stdout where `Msg` doesn't exist.
+pub fn print(lua: State, text: &str) {
+    lua.get_global(c"Msg");
+    if lua.is_function(-1) {
+        lua.push_string(text);
+        if lua.pcall(1, 0, 0).is_ok() {
+            return;
+        }
+    }
+    lua.pop();
+
+    use std::io::Write;
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(text.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// Prints a line in the color of this realm's `print`s, like `print` does. Must be called on the main thread.
+///
+/// ```ignore
+/// gmod::gmod_print!(lua, "loaded {} entities", count);
+/// ```
+#[macro_export]
+macro_rules! gmod_print {
+    ($lua:expr, $($arg:tt)*) => {{
+        let lua: $crate::lua::State = $lua;
+        $crate::console::print_colored(lua, &[($crate::console::realm_color(lua), &format!("{}\n", format_args!($($arg)*)))]);
+    }};
+}
+
+/// Prints a warning line, in yellow. Must be called on the main thread.
+#[macro_export]
+macro_rules! gmod_warn {
+    ($lua:expr, $($arg:tt)*) => {
+        $crate::console::print_colored($lua, &[($crate::console::Color::WARN, &format!("{}\n", format_args!($($arg)*)))])
+    };
+}
+
+/// Prints an error line, in red, without raising a Lua error. Must be called on the main thread.
+#[macro_export]
+macro_rules! gmod_error {
+    ($lua:expr, $($arg:tt)*) => {
+        $crate::console::print_colored($lua, &[($crate::console::Color::ERROR, &format!("{}\n", format_args!($($arg)*)))])
+    };
+}
@@ -0,0 +1,320 @@
+use std::iter::repeat_with;
+
+use crate::{
+    callbacks::{self, Registry, Removal},
+    lua::{LuaCStr, State},
+};
+
+/// `FCVAR_*` flags accepted by `create`.
+pub mod flags {
+    pub const NONE: i32 = 0;
+    pub const GAMEDLL: i32 = 1 << 2;
+    pub const CLIENTDLL: i32 = 1 << 3;
+    pub const PROTECTED: i32 = 1 << 5;
+    pub const SPONLY: i32 = 1 << 6;
+    pub const ARCHIVE: i32 = 1 << 7;
+    pub const NOTIFY: i32 = 1 << 8;
+    pub const USERINFO: i32 = 1 << 9;
+    pub const PRINTABLEONLY: i32 = 1 << 10;
+    pub const UNLOGGED: i32 = 1 << 11;
+    pub const NEVER_AS_STRING: i32 = 1 << 12;
+    pub const REPLICATED: i32 = 1 << 13;
+    pub const CHEAT: i32 = 1 << 14;
+    pub const DEMO: i32 = 1 << 16;
+    pub const DONTRECORD: i32 = 1 << 17;
+    pub const LUA_CLIENT: i32 = 1 << 18;
+    pub const LUA_SERVER: i32 = 1 << 19;
+    pub const SERVER_CAN_EXECUTE: i32 = 1 << 28;
+    pub const CLIENTCMD_CAN_EXECUTE: i32 = 1 << 30;
+}
+
+/// A console variable, looked up by name with `GetConVar` whenever it is accessed.
+///
+/// ## Example
+///
+/// ```ignore
+/// let cvar = gmod::convar::create(lua, "my_addon_enabled", "1", flags::ARCHIVE | flags::NOTIFY, "Enables my addon");
+/// if cvar.get_bool(lua) {
+///     // ...
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConVar {
+    name: String,
+}
+
+impl ConVar {
+    /// Returns the console variable with this name, if it exists.
+    pub fn find(lua: State, name: &str) -> Option<Self> {
+        lua.get_global(c"ConVarExists");
+        lua.push_string(name);
+        if !lua.pcall_ignore(1, 1) {
+            return None;
+        }
+        let exists = lua.get_boolean(-1);
+        lua.pop();
+        exists.then(|| Self {
+            name: name.to_string(),
+        })
+    }
+
+    /// Returns the name of the console variable.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Calls a method of the `ConVar` object with `nargs` arguments pushed by `push_args`.
+    ///
+    /// On success, `nresults` results are left on the stack.
+    fn call_method(
+        &self,
+        lua: State,
+        method: LuaCStr,
+        nargs: i32,
+        push_args: impl FnOnce(),
+        nresults: i32,
+    ) -> bool {
+        lua.get_global(c"GetConVar");
+        lua.push_string(&self.name);
+        if !lua.pcall_ignore(1, 1) {
+            return false;
+        }
+        if lua.is_nil(-1) {
+            lua.pop();
+            return false;
+        }
+        lua.get_field(-1, method);
+        lua.insert(-2);
+        push_args();
+        lua.pcall_ignore(nargs + 1, nresults)
+    }
+
+    /// Returns the value as a string, or `None` if the console variable doesn't exist.
+    pub fn get_string(&self, lua: State) -> Option<String> {
+        if !self.call_method(lua, c"GetString", 0, || {}, 1) {
+            return None;
+        }
+        let value = lua.get_string(-1).map(|s| s.into_owned());
+        lua.pop();
+        value
+    }
+
+    /// Returns the value as an integer, or `0` if the console variable doesn't exist.
+    pub fn get_int(&self, lua: State) -> i32 {
+        self.get_number(lua, c"GetInt") as i32
+    }
+
+    /// Returns the value as a float, or `0.0` if the console variable doesn't exist.
+    pub fn get_float(&self, lua: State) -> f64 {
+        self.get_number(lua, c"GetFloat")
+    }
+
+    fn get_number(&self, lua: State, method: LuaCStr) -> f64 {
+        if !self.call_method(lua, method, 0, || {}, 1) {
+            return 0.0;
+        }
+        let value = lua.to_number(-1);
+        lua.pop();
+        value
+    }
+
+    /// Returns the value as a boolean, or `false` if the console variable doesn't exist.
+    pub fn get_bool(&self, lua: State) -> bool {
+        if !self.call_method(lua, c"GetBool", 0, || {}, 1) {
+            return false;
+        }
+        let value = lua.get_boolean(-1);
+        lua.pop();
+        value
+    }
+
+    /// Sets the value. Returns whether the console variable exists.
+    pub fn set_string(&self, lua: State, value: &str) -> bool {
+        self.call_method(lua, c"SetString", 1, || lua.push_string(value), 0)
+    }
+
+    /// Sets the value. Returns whether the console variable exists.
+    pub fn set_int(&self, lua: State, value: i32) -> bool {
+        self.call_method(lua, c"SetInt", 1, || lua.push_number(value), 0)
+    }
+
+    /// Sets the value. Returns whether the console variable exists.
+    pub fn set_float(&self, lua: State, value: f64) -> bool {
+        self.call_method(lua, c"SetFloat", 1, || lua.push_number(value), 0)
+    }
+
+    /// Sets the value. Returns whether the console variable exists.
+    pub fn set_bool(&self, lua: State, value: bool) -> bool {
+        self.call_method(lua, c"SetBool", 1, || lua.push_boolean(value), 0)
+    }
+
+    /// Registers a callback called with the old and new value whenever this console variable changes.
+    ///
+    /// See `on_change`.
+    pub fn on_change<F>(&self, lua: State, callback: F) -> ChangeCallbackHandle
+    where
+        F: FnMut(State, &str, &str) + 'static,
+    {
+        on_change(lua, &self.name, callback)
+    }
+}
+
+/// Creates a console variable with `CreateConVar`, or returns the existing one with this name.
+pub fn create(lua: State, name: &str, default: &str, flags: i32, help: &str) -> ConVar {
+    create_inner(lua, name, default, flags, help, None)
+}
+
+/// Same as `create`, but the value is clamped between `min` and `max`.
+pub fn create_clamped(
+    lua: State,
+    name: &str,
+    default: &str,
+    flags: i32,
+    help: &str,
+    min: f64,
+    max: f64,
+) -> ConVar {
+    create_inner(lua, name, default, flags, help, Some((min, max)))
+}
+
+fn create_inner(
+    lua: State,
+    name: &str,
+    default: &str,
+    flags: i32,
+    help: &str,
+    bounds: Option<(f64, f64)>,
+) -> ConVar {
+    lua.get_global(c"CreateConVar");
+    lua.push_string(name);
+    lua.push_string(default);
+    lua.push_number(flags);
+    lua.push_string(help);
+    let nargs = match bounds {
+        Some((min, max)) => {
+            lua.push_number(min);
+            lua.push_number(max);
+            6
+        }
+        None => 4,
+    };
+    lua.pcall_ignore(nargs, 0);
+
+    ConVar {
+        name: name.to_string(),
+    }
+}
+
+type Callback = Box<dyn FnMut(State, &str, &str)>;
+
+struct ChangeCallback {
+    convar: String,
+    identifier: String,
+    /// Taken out while the callback is running.
+    callback: Option<Callback>,
+}
+
+thread_local! {
+    static CALLBACKS: Registry<ChangeCallback> = Registry::new();
+}
+
+/// A handle to a change callback registered with `on_change`. The callback is removed when the handle is dropped, unless it is detached.
+#[must_use = "the callback is removed as soon as the handle is dropped"]
+#[derive(Debug)]
+pub struct ChangeCallbackHandle {
+    removal: Removal,
+}
+
+impl ChangeCallbackHandle {
+    /// Removes the callback immediately. Must be called from the Lua thread.
+    pub fn remove(self, lua: State) {
+        self.removal.remove(lua);
+    }
+
+    /// Keeps the callback registered after the handle is dropped, until the module is closed.
+    pub fn detach(self) {
+        self.removal.detach();
+    }
+}
+
+fn remove_by_id(lua: State, id: u64) {
+    if let Some(callback) = CALLBACKS.with(|callbacks| callbacks.remove(id)) {
+        lua.get_global(c"cvars");
+        lua.get_field(-1, c"RemoveChangeCallback");
+        lua.push_string(&callback.convar);
+        lua.push_string(&callback.identifier);
+        lua.pcall_ignore(2, 0);
+        lua.pop();
+    }
+}
+
+/// Registers a callback with `cvars.AddChangeCallback`, called with the old and new value whenever the console variable changes.
+///
+/// The callback is removed when the returned handle is dropped, or when the module is closed.
+///
+/// ## Example
+///
+/// ```ignore
+/// let handle = gmod::convar::on_change(lua, "sv_gravity", |lua, old, new| {
+///     println!("sv_gravity changed from {old} to {new}");
+/// });
+/// ```
+pub fn on_change<F>(lua: State, name: &str, callback: F) -> ChangeCallbackHandle
+where
+    F: FnMut(State, &str, &str) + 'static,
+{
+    let id = CALLBACKS.with(Registry::next_id);
+
+    let random_str: String = repeat_with(fastrand::alphanumeric).take(10).collect();
+    let identifier = format!("gmod_rs_convar_{id}_{random_str}");
+
+    lua.get_global(c"cvars");
+    lua.get_field(-1, c"AddChangeCallback");
+    lua.push_string(name);
+    lua.push_number(id);
+    lua.push_closure(change_callback, 1);
+    lua.push_string(&identifier);
+    if lua.pcall_ignore(3, 0) {
+        CALLBACKS.with(|callbacks| {
+            callbacks.insert(
+                id,
+                ChangeCallback {
+                    convar: name.to_string(),
+                    identifier,
+                    callback: Some(Box::new(callback)),
+                },
+            )
+        });
+    }
+    lua.pop();
+
+    ChangeCallbackHandle {
+        removal: Removal::new(id, remove_by_id),
+    }
+}
+
+/// Removes every change callback registered through this module. This is called for you by `#[gmod13_close]`.
+pub fn unload(lua: State) {
+    for id in CALLBACKS.with(Registry::ids) {
+        remove_by_id(lua, id);
+    }
+}
+
+extern "C-unwind" fn change_callback(lua: State) -> i32 {
+    let id = lua.to_number(lua.upvalue_index(1)) as u64;
+
+    let old = lua.get_string(2).unwrap_or_default().into_owned();
+    let new = lua.get_string(3).unwrap_or_default().into_owned();
+    let result = CALLBACKS.with(|callbacks| {
+        callbacks.run(
+            id,
+            |entry| &mut entry.callback,
+            |callback| callback(lua, &old, &new),
+        )
+    });
+
+    match result {
+        Some(Err(panic)) => callbacks::raise(lua, panic),
+        _ => 0,
+    }
+}
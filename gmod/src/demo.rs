@@ -0,0 +1,545 @@
+//! Reading Source demo files (`.dem`), e.g. the ones recorded with SourceTV or `record`.
+//!
+//! `parse_header` reads the header, `frames` iterates over the raw frames, and `summarize` extracts what moderation tooling usually wants (players and chat) in one pass.
+//!
+//! Packets (the network messages inside `Command::Packet` frames) are not decoded: players are taken from the string table snapshots and chat from console commands (`say`, `say_team`), which covers client-recorded demos and every player present when the recording started.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::lua::{self, task_queue, HandleLuaFunctionReturn, LuaRef, LuaReg, State};
+
+const MAGIC: &[u8; 8] = b"HL2DEMO\0";
+
+/// Size of the view information before each packet.
+const CMD_INFO_SIZE: usize = 76;
+
+/// Largest frame payload accepted, so corrupt files can't make us allocate gigabytes.
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// The header at the start of every demo file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub demo_protocol: i32,
+    pub network_protocol: i32,
+    pub server_name: String,
+    pub client_name: String,
+    pub map_name: String,
+    pub game_directory: String,
+    /// Length of the demo, in seconds.
+    pub playback_time: f32,
+    pub ticks: i32,
+    pub frames: i32,
+    pub signon_length: i32,
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> io::Result<i32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_fixed_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut buf = [0; 260];
+    reader.read_exact(&mut buf)?;
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+impl Header {
+    fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("not a Source demo file");
+        }
+
+        Ok(Self {
+            demo_protocol: read_i32(reader)?,
+            network_protocol: read_i32(reader)?,
+            server_name: read_fixed_string(reader)?,
+            client_name: read_fixed_string(reader)?,
+            map_name: read_fixed_string(reader)?,
+            game_directory: read_fixed_string(reader)?,
+            playback_time: f32::from_bits(read_i32(reader)? as u32),
+            ticks: read_i32(reader)?,
+            frames: read_i32(reader)?,
+            signon_length: read_i32(reader)?,
+        })
+    }
+}
+
+/// Reads the header of a demo file.
+pub fn parse_header<P: AsRef<Path>>(path: P) -> Result<Header> {
+    let path = path.as_ref();
+    let mut file = File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+    Header::read(&mut file)
+}
+
+/// The kind of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Network messages sent while connecting.
+    SignOn,
+    /// Network messages sent during the game.
+    Packet,
+    SyncTick,
+    /// A console command run by the recording client.
+    ConsoleCmd,
+    UserCmd,
+    DataTables,
+    /// The end of the demo.
+    Stop,
+    /// A snapshot of the string tables.
+    StringTables,
+    Unknown(u8),
+}
+
+impl From<u8> for Command {
+    fn from(id: u8) -> Self {
+        match id {
+            1 => Command::SignOn,
+            2 => Command::Packet,
+            3 => Command::SyncTick,
+            4 => Command::ConsoleCmd,
+            5 => Command::UserCmd,
+            6 => Command::DataTables,
+            7 => Command::Stop,
+            8 => Command::StringTables,
+            id => Command::Unknown(id),
+        }
+    }
+}
+
+/// A frame of a demo. `data` is the frame's payload, without the view information of packets or the sequence number of user commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub command: Command,
+    pub tick: i32,
+    pub data: Vec<u8>,
+}
+
+/// Iterator over the frames of a demo, created with `frames`. Ends after the `Stop` frame, at the end of the file, or at the first error.
+pub struct Frames<R> {
+    reader: R,
+    demo_protocol: i32,
+    done: bool,
+}
+
+impl<R: Read> Frames<R> {
+    fn read_data(&mut self) -> Result<Vec<u8>> {
+        let len = read_i32(&mut self.reader)?;
+        if len < 0 || len as usize > MAX_FRAME_SIZE {
+            bail!("invalid frame size {len}");
+        }
+        let mut data = vec![0; len as usize];
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        io::copy(&mut (&mut self.reader).take(n as u64), &mut io::sink())?;
+        Ok(())
+    }
+
+    fn read_frame(&mut self, command: u8) -> Result<Frame> {
+        let command = Command::from(command);
+        let tick = read_i32(&mut self.reader)?;
+        if self.demo_protocol >= 4 {
+            read_u8(&mut self.reader)?; // player slot
+        }
+
+        let data = match command {
+            Command::SyncTick | Command::Stop => Vec::new(),
+            Command::SignOn | Command::Packet => {
+                // view information, then the incoming and outgoing sequence numbers
+                self.skip(CMD_INFO_SIZE + 8)?;
+                self.read_data()?
+            }
+            Command::UserCmd => {
+                read_i32(&mut self.reader)?; // outgoing sequence number
+                self.read_data()?
+            }
+            Command::ConsoleCmd | Command::DataTables | Command::StringTables => {
+                self.read_data()?
+            }
+            Command::Unknown(id) => bail!("unknown demo command {id} at tick {tick}"),
+        };
+
+        Ok(Frame {
+            command,
+            tick,
+            data,
+        })
+    }
+}
+
+impl<R: Read> Iterator for Frames<R> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        // demos that are still being recorded (or whose server crashed) have no `Stop` frame
+        let mut command = [0; 1];
+        match self.reader.read(&mut command) {
+            Ok(0) => {
+                self.done = true;
+                return None;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err.into()));
+            }
+        }
+
+        let frame = self.read_frame(command[0]);
+        match &frame {
+            Ok(frame) if frame.command == Command::Stop => self.done = true,
+            Ok(_) => {}
+            Err(_) => self.done = true,
+        }
+        Some(frame)
+    }
+}
+
+/// Opens a demo file, returning its header and an iterator over its frames.
+///
+/// ## Example
+///
+/// ```ignore
+/// let (header, frames) = gmod::demo::frames("garrysmod/demos/match.dem")?;
+/// for frame in frames {
+///     let frame = frame?;
+///     if frame.command == Command::ConsoleCmd {
+///         println!("{}: {}", frame.tick, String::from_utf8_lossy(&frame.data));
+///     }
+/// }
+/// ```
+pub fn frames<P: AsRef<Path>>(path: P) -> Result<(Header, Frames<BufReader<File>>)> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+    let mut reader = BufReader::new(file);
+    let header = Header::read(&mut reader)?;
+    let frames = Frames {
+        reader,
+        demo_protocol: header.demo_protocol,
+        done: false,
+    };
+    Ok((header, frames))
+}
+
+/// A player found in the demo's `userinfo` string table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Player {
+    pub user_id: i32,
+    pub name: String,
+    /// The player's SteamID (`STEAM_0:X:Y`), or `BOT`.
+    pub steamid: String,
+    /// The account ID part of the player's SteamID64.
+    pub account_id: u32,
+    pub is_bot: bool,
+}
+
+impl Player {
+    /// Parses a `player_info_t`.
+    fn parse(data: &[u8]) -> Option<Player> {
+        if data.len() < 110 {
+            return None;
+        }
+        let string = |bytes: &[u8]| {
+            let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..len]).into_owned()
+        };
+        Some(Player {
+            name: string(&data[0..32]),
+            user_id: i32::from_le_bytes(data[32..36].try_into().unwrap()),
+            steamid: string(&data[36..69]),
+            account_id: u32::from_le_bytes(data[72..76].try_into().unwrap()),
+            is_bot: data[108] != 0,
+        })
+    }
+}
+
+/// A chat message, from a `say` or `say_team` console command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    pub tick: i32,
+    pub text: String,
+    pub team: bool,
+}
+
+/// What `summarize` extracts from a demo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub header: Header,
+    /// The tick of the last frame, which is more reliable than the header's tick count (that is only written when the recording stops cleanly).
+    pub last_tick: i32,
+    /// How many packets the demo has.
+    pub packets: usize,
+    pub players: Vec<Player>,
+    pub chat: Vec<ChatMessage>,
+}
+
+/// Reads bits the way Source's `bf_read` writes them: least significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = (byte >> (self.pos % 8)) & 1 != 0;
+        self.pos += 1;
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            value |= (self.read_bit()? as u32) << i;
+        }
+        Some(value)
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        self.read_bits(8).map(|b| b as u8)
+    }
+
+    fn read_u16(&mut self) -> Option<u16> {
+        self.read_bits(16).map(|b| b as u16)
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Option<Vec<u8>> {
+        (0..n).map(|_| self.read_u8()).collect()
+    }
+
+    fn read_string(&mut self) -> Option<String> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.read_u8()? {
+                0 => break,
+                byte => bytes.push(byte),
+            }
+        }
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// Reads a string table snapshot, calling `f` with the table name, string and user data of every entry.
+fn read_string_tables(data: &[u8], mut f: impl FnMut(&str, &str, &[u8])) -> Option<()> {
+    let mut reader = BitReader { data, pos: 0 };
+    let tables = reader.read_u8()?;
+    for _ in 0..tables {
+        let name = reader.read_string()?;
+        let mut read_entries = |reader: &mut BitReader| -> Option<()> {
+            let entries = reader.read_u16()?;
+            for _ in 0..entries {
+                let string = reader.read_string()?;
+                let data = if reader.read_bit()? {
+                    let len = reader.read_u16()? as usize;
+                    reader.read_bytes(len)?
+                } else {
+                    Vec::new()
+                };
+                f(&name, &string, &data);
+            }
+            Some(())
+        };
+        read_entries(&mut reader)?;
+        // client-side entries
+        if reader.read_bit()? {
+            read_entries(&mut reader)?;
+        }
+    }
+    Some(())
+}
+
+/// Reads a whole demo, collecting its players and chat. This reads the entire file: use `summarize_async` on the Lua thread.
+///
+/// ## Example
+///
+/// ```
+/// # let path = std::env::temp_dir().join(format!("gmod_rs_demo_doctest_{}.dem", std::process::id()));
+/// # let mut demo = b"HL2DEMO\0".to_vec();
+/// # demo.extend(3i32.to_le_bytes());
+/// # demo.extend(24i32.to_le_bytes());
+/// # for name in ["My Server", "SourceTV", "gm_construct", "garrysmod"] {
+/// #     let mut field = [0u8; 260];
+/// #     field[..name.len()].copy_from_slice(name.as_bytes());
+/// #     demo.extend(field);
+/// # }
+/// # demo.extend(1.5f32.to_le_bytes());
+/// # demo.extend([100i32, 3, 0].iter().flat_map(|n| n.to_le_bytes()));
+/// # let cmd = b"say \"hello world\"\0";
+/// # demo.push(4);
+/// # demo.extend(66i32.to_le_bytes());
+/// # demo.extend((cmd.len() as i32).to_le_bytes());
+/// # demo.extend(cmd);
+/// # demo.push(7);
+/// # demo.extend(100i32.to_le_bytes());
+/// # std::fs::write(&path, demo).unwrap();
+/// let summary = gmod::demo::summarize(&path).unwrap();
+///
+/// assert_eq!(summary.header.map_name, "gm_construct");
+/// assert_eq!(summary.last_tick, 100);
+/// assert_eq!(summary.chat[0].text, "hello world");
+/// assert_eq!(summary.chat[0].tick, 66);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn summarize<P: AsRef<Path>>(path: P) -> Result<Summary> {
+    let (header, frames) = frames(path)?;
+    let mut summary = Summary {
+        header,
+        last_tick: 0,
+        packets: 0,
+        players: Vec::new(),
+        chat: Vec::new(),
+    };
+
+    for frame in frames {
+        let frame = frame?;
+        summary.last_tick = summary.last_tick.max(frame.tick);
+        match frame.command {
+            Command::Packet => summary.packets += 1,
+            Command::StringTables => {
+                let players = &mut summary.players;
+                read_string_tables(&frame.data, |table, _, data| {
+                    if table != "userinfo" {
+                        return;
+                    }
+                    if let Some(player) = Player::parse(data) {
+                        if !players.iter().any(|p| p.user_id == player.user_id) {
+                            players.push(player);
+                        }
+                    }
+                })
+                .context("malformed string tables")?;
+            }
+            Command::ConsoleCmd => {
+                let command = String::from_utf8_lossy(&frame.data);
+                let command = command.trim_end_matches('\0').trim();
+                let (team, text) = if let Some(text) = command.strip_prefix("say_team ") {
+                    (true, text)
+                } else if let Some(text) = command.strip_prefix("say ") {
+                    (false, text)
+                } else {
+                    continue;
+                };
+                summary.chat.push(ChatMessage {
+                    tick: frame.tick,
+                    text: text.trim_matches('"').to_string(),
+                    team,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Runs `summarize` on a background thread, then calls `callback` on the Lua thread with the result.
+pub fn summarize_async<F>(path: PathBuf, callback: F)
+where
+    F: FnOnce(State, Result<Summary>) + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let summary = summarize(&path);
+        let _ = task_queue::wait_lua_tick(String::new(), move |l| callback(l, summary));
+    });
+}
+
+/// Registers the demo functions into a global table named `libname`:
+///
+/// - `ParseDemo(path, callback)` reads the demo at `path` (relative to the server's root directory, e.g. `garrysmod/demos/match.dem`) on a background thread, then calls `callback(summary, err)`. `summary` has the fields `map`, `server`, `duration`, `ticks`, `packets`, `players` (a list of `{ userid, name, steamid, bot }`) and `chat` (a list of `{ tick, text, team }`).
+pub fn register(lua: State, libname: lua::LuaCStr) {
+    lua.register(
+        libname.as_ptr(),
+        crate::lua_regs![
+            "ParseDemo" => lua_parse_demo,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+}
+
+fn push_summary(lua: State, summary: &Summary) {
+    lua.create_table(0, 7);
+    lua.push_string(&summary.header.map_name);
+    lua.set_field(-2, c"map");
+    lua.push_string(&summary.header.server_name);
+    lua.set_field(-2, c"server");
+    lua.push_number(summary.header.playback_time);
+    lua.set_field(-2, c"duration");
+    lua.push_number(summary.last_tick);
+    lua.set_field(-2, c"ticks");
+    lua.push_number(summary.packets);
+    lua.set_field(-2, c"packets");
+
+    lua.create_table(summary.players.len() as i32, 0);
+    for (i, player) in summary.players.iter().enumerate() {
+        lua.create_table(0, 4);
+        lua.push_number(player.user_id);
+        lua.set_field(-2, c"userid");
+        lua.push_string(&player.name);
+        lua.set_field(-2, c"name");
+        lua.push_string(&player.steamid);
+        lua.set_field(-2, c"steamid");
+        lua.push_boolean(player.is_bot);
+        lua.set_field(-2, c"bot");
+        lua.raw_seti(-2, i as i32 + 1);
+    }
+    lua.set_field(-2, c"players");
+
+    lua.create_table(summary.chat.len() as i32, 0);
+    for (i, message) in summary.chat.iter().enumerate() {
+        lua.create_table(0, 3);
+        lua.push_number(message.tick);
+        lua.set_field(-2, c"tick");
+        lua.push_string(&message.text);
+        lua.set_field(-2, c"text");
+        lua.push_boolean(message.team);
+        lua.set_field(-2, c"team");
+        lua.raw_seti(-2, i as i32 + 1);
+    }
+    lua.set_field(-2, c"chat");
+}
+
+extern "C-unwind" fn lua_parse_demo(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let path = PathBuf::from(lua.check_string(1)?.into_owned());
+        lua.check_function(2)?;
+        let callback = LuaRef::from_index(lua, 2);
+        summarize_async(path, move |l, summary| {
+            callback.push(l);
+            match summary {
+                Ok(summary) => {
+                    push_summary(l, &summary);
+                    l.push_nil();
+                }
+                Err(err) => {
+                    l.push_nil();
+                    l.push_string(&format!("{err:#}"));
+                }
+            }
+            l.pcall_ignore(2, 0);
+        });
+        Ok(0)
+    })()
+    .handle_result(lua)
+}
@@ -0,0 +1,384 @@
+//! Hooking of native functions, e.g. engine functions found with `open_library!`.
+//!
+//! Enabling a detour overwrites the first bytes of the target function with a jump to the detour. The original is called through `Detour::call_original`, which puts the overwritten bytes back for the duration of the call. This needs no disassembler, but means that:
+//!
+//! - The target must be at least `PATCH_LEN` bytes long. Exported functions always are in practice, tiny helpers may not be.
+//! - Calls made to the target from other threads while `call_original` runs aren't detoured. Engine functions are almost always called on the main thread, like Lua.
+//!
+//! Detours are removed when their handle is dropped, and all of them are removed by `#[gmod13_close]`, including those whose handle lives in a `static` that's never dropped: a jump left behind into an unloaded module would crash the game.
+//!
+//! Only x86 and x86-64 are supported, which is every platform GMod runs on.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::detour::Detour;
+//!
+//! static SET_MAX_PLAYERS: OnceLock<Detour<unsafe extern "C" fn(*mut c_void, c_int)>> = OnceLock::new();
+//!
+//! unsafe extern "C" fn set_max_players(this: *mut c_void, max: c_int) {
+//!     SET_MAX_PLAYERS.get().unwrap().call_original(|original| original(this, max.min(64)))
+//! }
+//!
+//! let (engine, _) = gmod::open_library_srv!("engine")?;
+//! let detour = Detour::from_symbol(&engine, "_ZN11CGameServer14SetMaxClientsEi", set_max_players)?;
+//! detour.enable()?;
+//! SET_MAX_PLAYERS.set(detour);
+//! ```
+
+use std::{collections::HashMap, ffi::c_void, sync::Mutex};
+
+use anyhow::{anyhow, bail, Result};
+
+/// How many bytes of the target are overwritten.
+#[cfg(target_arch = "x86_64")]
+pub const PATCH_LEN: usize = 14;
+/// How many bytes of the target are overwritten.
+#[cfg(not(target_arch = "x86_64"))]
+pub const PATCH_LEN: usize = 5;
+
+/// A function pointer type that can be detoured.
+///
+/// # Safety
+/// Implementors must be function pointers.
+pub unsafe trait Function: Copy + 'static {
+    fn addr(self) -> *const ();
+
+    /// # Safety
+    /// `addr` must be the address of a function with this signature.
+    unsafe fn from_addr(addr: *const ()) -> Self;
+}
+
+macro_rules! impl_function {
+    ($($arg:ident),*) => {
+        impl_function!(@abi ("C") $($arg),*);
+        impl_function!(@abi ("C-unwind") $($arg),*);
+        impl_function!(@abi ("system") $($arg),*);
+    };
+    (@abi ($abi:literal) $($arg:ident),*) => {
+        unsafe impl<R: 'static, $($arg: 'static),*> Function for extern $abi fn($($arg),*) -> R {
+            fn addr(self) -> *const () {
+                self as *const ()
+            }
+
+            unsafe fn from_addr(addr: *const ()) -> Self {
+                std::mem::transmute::<*const (), Self>(addr)
+            }
+        }
+
+        unsafe impl<R: 'static, $($arg: 'static),*> Function for unsafe extern $abi fn($($arg),*) -> R {
+            fn addr(self) -> *const () {
+                self as *const ()
+            }
+
+            unsafe fn from_addr(addr: *const ()) -> Self {
+                std::mem::transmute::<*const (), Self>(addr)
+            }
+        }
+    };
+}
+
+impl_function!();
+impl_function!(A);
+impl_function!(A, B);
+impl_function!(A, B, C);
+impl_function!(A, B, C, D);
+impl_function!(A, B, C, D, E);
+impl_function!(A, B, C, D, E, F);
+impl_function!(A, B, C, D, E, F, G);
+impl_function!(A, B, C, D, E, F, G, H);
+
+struct Patch {
+    /// The bytes the jump overwrote.
+    original: [u8; PATCH_LEN],
+    jump: [u8; PATCH_LEN],
+    enabled: bool,
+}
+
+/// The detours created and not removed yet, by target address.
+static PATCHES: Mutex<Option<HashMap<usize, Patch>>> = Mutex::new(None);
+
+/// A detour of a function, removed when dropped.
+#[must_use = "the detour is removed as soon as it's dropped"]
+pub struct Detour<T: Function> {
+    target: T,
+}
+
+impl<T: Function> std::fmt::Debug for Detour<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Detour")
+            .field("target", &self.target.addr())
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+impl<T: Function> Detour<T> {
+    /// Creates a detour of `target` to `detour`, disabled until `enable` is called. Fails if `target` is already detoured.
+    ///
+    /// # Safety
+    /// `target` must be a function at least `PATCH_LEN` bytes long, whose first `PATCH_LEN` bytes aren't jumped into.
+    pub unsafe fn new(target: T, detour: T) -> Result<Self> {
+        let addr = target.addr() as usize;
+        let mut patches = PATCHES.lock().unwrap_or_else(|e| e.into_inner());
+        let patches = patches.get_or_insert_with(HashMap::new);
+        if patches.contains_key(&addr) {
+            bail!("{addr:#x} is already detoured");
+        }
+        let mut original = [0; PATCH_LEN];
+        std::ptr::copy_nonoverlapping(addr as *const u8, original.as_mut_ptr(), PATCH_LEN);
+        patches.insert(
+            addr,
+            Patch {
+                original,
+                jump: jump(addr, detour.addr() as usize)?,
+                enabled: false,
+            },
+        );
+        Ok(Self { target })
+    }
+
+    /// Creates a detour of the function exported as `symbol` by `library`, e.g. one opened with `open_library_srv!`.
+    ///
+    /// # Safety
+    /// The symbol must be a function with the signature `T`, see `new`.
+    pub unsafe fn from_symbol(
+        library: &libloading::Library,
+        symbol: &str,
+        detour: T,
+    ) -> Result<Self> {
+        let target = library
+            .get::<*const ()>(symbol.as_bytes())
+            .map_err(|err| anyhow!("couldn't find {symbol}: {err}"))?;
+        Self::new(T::from_addr(*target), detour)
+    }
+
+    /// Returns the detoured function, which must only be called through `call_original` while the detour is enabled.
+    pub fn target(&self) -> T {
+        self.target
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        with_patch(self.addr(), |patch| patch.enabled).unwrap_or(false)
+    }
+
+    /// Makes calls to the target go to the detour. Fails if the detour was removed by `unload`.
+    pub fn enable(&self) -> Result<()> {
+        self.set_enabled(true)
+    }
+
+    /// Makes calls to the target run the original again.
+    pub fn disable(&self) -> Result<()> {
+        self.set_enabled(false)
+    }
+
+    fn set_enabled(&self, enabled: bool) -> Result<()> {
+        let addr = self.addr();
+        with_patch(addr, |patch| {
+            if patch.enabled != enabled {
+                let bytes = if enabled { patch.jump } else { patch.original };
+                unsafe { write_code(addr, &bytes)? };
+                patch.enabled = enabled;
+            }
+            Ok(())
+        })
+        .unwrap_or_else(|| Err(anyhow!("the detour of {addr:#x} was removed")))
+    }
+
+    /// Runs `f` with the original function, which can be called while it runs. The detour is disabled meanwhile, so the original can be called from the detour without calling the detour again.
+    pub fn call_original<R>(&self, f: impl FnOnce(T) -> R) -> R {
+        let was_enabled = self.is_enabled();
+        if was_enabled {
+            self.disable().expect("couldn't disable the detour");
+        }
+        // re-enabled even if `f` unwinds, as long as the module is still loaded
+        let _enable = defer::defer(|| {
+            if was_enabled {
+                let _ = self.enable();
+            }
+        });
+        f(self.target)
+    }
+
+    fn addr(&self) -> usize {
+        self.target.addr() as usize
+    }
+}
+
+impl<T: Function> Drop for Detour<T> {
+    fn drop(&mut self) {
+        let addr = self.addr();
+        let _ = self.disable();
+        if let Some(patches) = PATCHES.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            patches.remove(&addr);
+        }
+    }
+}
+
+fn with_patch<R>(addr: usize, f: impl FnOnce(&mut Patch) -> R) -> Option<R> {
+    PATCHES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_mut()?
+        .get_mut(&addr)
+        .map(f)
+}
+
+/// Removes every detour, restoring the original functions. This is called for you by `#[gmod13_close]`.
+pub fn unload() {
+    let patches = PATCHES.lock().unwrap_or_else(|e| e.into_inner()).take();
+    for (addr, patch) in patches.into_iter().flatten() {
+        if patch.enabled {
+            let _ = unsafe { write_code(addr, &patch.original) };
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn jump(_from: usize, to: usize) -> Result<[u8; PATCH_LEN]> {
+    // jmp [rip + 0], followed by the address: doesn't clobber any register
+    let mut jump = [0xFF, 0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    jump[6..].copy_from_slice(&(to as u64).to_le_bytes());
+    Ok(jump)
+}
+
+#[cfg(target_arch = "x86")]
+fn jump(from: usize, to: usize) -> Result<[u8; PATCH_LEN]> {
+    // jmp rel32, relative to the next instruction
+    let offset = (to as i32).wrapping_sub(from as i32 + PATCH_LEN as i32);
+    let mut jump = [0xE9, 0, 0, 0, 0];
+    jump[1..].copy_from_slice(&offset.to_le_bytes());
+    Ok(jump)
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+fn jump(_from: usize, _to: usize) -> Result<[u8; PATCH_LEN]> {
+    bail!("detours are only supported on x86 and x86-64")
+}
+
+/// Overwrites code, making its pages writable meanwhile.
+unsafe fn write_code(addr: usize, bytes: &[u8]) -> Result<()> {
+    let _protection = unprotect(addr, bytes.len(), true)?;
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, bytes.len());
+    Ok(())
+}
+
+/// Overwrites read-only data, such as a vtable, making its pages writable meanwhile.
+pub(crate) unsafe fn write_read_only(addr: usize, bytes: &[u8]) -> Result<()> {
+    let _protection = unprotect(addr, bytes.len(), false)?;
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, bytes.len());
+    Ok(())
+}
+
+/// Makes the pages of `addr..addr + len` writable until dropped, and executable if `code` is set. Their original protection is restored afterwards.
+#[cfg(unix)]
+unsafe fn unprotect(addr: usize, len: usize, code: bool) -> Result<impl Drop> {
+    let exec = if code { libc::PROT_EXEC } else { 0 };
+    let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+    let start = addr & !(page_size - 1);
+    let end = (addr + len).next_multiple_of(page_size);
+    let protect = move |(start, end, protection): (usize, usize, i32)| {
+        if libc::mprotect(start as *mut c_void, end - start, protection) != 0 {
+            bail!(
+                "couldn't change the protection of {addr:#x}: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    };
+
+    let original = protections(start, end, code)?;
+    for (i, &(start, end, protection)) in original.iter().enumerate() {
+        if let Err(err) = protect((start, end, protection | libc::PROT_WRITE | exec)) {
+            for &range in &original[..i] {
+                let _ = protect(range);
+            }
+            return Err(err);
+        }
+    }
+    Ok(defer::defer(move || {
+        for &range in &original {
+            let _ = protect(range);
+        }
+    }))
+}
+
+/// The protection of the pages of `start..end`, as `(start, end, protection)` ranges, read from `/proc/self/maps`.
+#[cfg(target_os = "linux")]
+fn protections(start: usize, end: usize, _code: bool) -> Result<Vec<(usize, usize, i32)>> {
+    let maps = std::fs::read_to_string("/proc/self/maps")?;
+    let mut ranges = Vec::new();
+    for line in maps.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(range), Some(perms)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Some((from, to)) = range.split_once('-') else {
+            continue;
+        };
+        let (Ok(from), Ok(to)) = (
+            usize::from_str_radix(from, 16),
+            usize::from_str_radix(to, 16),
+        ) else {
+            continue;
+        };
+        if to <= start || from >= end {
+            continue;
+        }
+
+        let mut protection = libc::PROT_NONE;
+        for (flag, bit) in [
+            (b'r', libc::PROT_READ),
+            (b'w', libc::PROT_WRITE),
+            (b'x', libc::PROT_EXEC),
+        ] {
+            if perms.as_bytes().contains(&flag) {
+                protection |= bit;
+            }
+        }
+        ranges.push((from.max(start), to.min(end), protection));
+    }
+
+    let mapped: usize = ranges.iter().map(|(from, to, _)| to - from).sum();
+    if mapped != end - start {
+        bail!("{start:#x}..{end:#x} isn't entirely mapped");
+    }
+    Ok(ranges)
+}
+
+/// Without `/proc/self/maps`, the pages are assumed to be read-only, and executable if they hold code.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn protections(start: usize, end: usize, code: bool) -> Result<Vec<(usize, usize, i32)>> {
+    let exec = if code { libc::PROT_EXEC } else { 0 };
+    Ok(vec![(start, end, libc::PROT_READ | exec)])
+}
+
+#[cfg(windows)]
+unsafe fn unprotect(addr: usize, len: usize, code: bool) -> Result<impl Drop> {
+    const PAGE_READWRITE: u32 = 0x04;
+    const PAGE_EXECUTE_READWRITE: u32 = 0x40;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn VirtualProtect(address: *mut c_void, size: usize, new: u32, old: *mut u32) -> i32;
+        fn FlushInstructionCache(process: *mut c_void, address: *const c_void, size: usize) -> i32;
+        fn GetCurrentProcess() -> *mut c_void;
+    }
+
+    let protection = if code {
+        PAGE_EXECUTE_READWRITE
+    } else {
+        PAGE_READWRITE
+    };
+    let mut old = 0;
+    if VirtualProtect(addr as *mut c_void, len, protection, &mut old) == 0 {
+        bail!(
+            "couldn't change the protection of {addr:#x}: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(defer::defer(move || {
+        VirtualProtect(addr as *mut c_void, len, old, &mut old);
+        FlushInstructionCache(GetCurrentProcess(), addr as *const c_void, len);
+    }))
+}
@@ -0,0 +1,288 @@
+use std::{ffi::c_void, ptr::NonNull};
+
+use crate::{
+    lua::{LuaCStr, LuaError, LuaRef, StackIndex, State},
+    userdata::{Angle, EntityHandle, TaggedUserData, UserData, Vector},
+};
+
+/// An owned reference to an entity, stored in the Lua registry.
+///
+/// Every method takes the Lua state and must be called from the Lua thread. The handle itself can be sent to other threads, and is released on the next Lua tick when dropped (see `LuaRef`).
+///
+/// The entity can be removed while the handle still exists: methods then return `None` (or `false`), like they would for a NULL entity.
+///
+/// ## Example
+///
+/// ```ignore
+/// let ent = Entity::from_index(lua, 1)?;
+/// if ent.get_class(lua).as_deref() == Some("prop_physics") {
+///     let mut pos = ent.get_pos(lua).unwrap_or_default();
+///     pos.z += 10.0;
+///     ent.set_pos(lua, pos);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Entity {
+    r#ref: LuaRef,
+}
+
+impl Entity {
+    /// Stores the entity at the given stack index. Fails if the value is not an entity (any entity type, including players, weapons and NULL).
+    pub fn from_index(lua: State, index: impl Into<StackIndex>) -> anyhow::Result<Self> {
+        let index = index.into().abs(lua);
+        lua.push_value(index);
+        lua.get_global(c"isentity");
+        lua.insert(-2);
+        let is_entity = match lua.pcall(1, 1, 0) {
+            Ok(()) => lua.get_boolean(-1),
+            Err(_) => false,
+        };
+        lua.pop();
+
+        if !is_entity {
+            anyhow::bail!(lua.type_error(index.0, "Entity"));
+        }
+
+        Ok(Self {
+            r#ref: LuaRef::from_index(lua, index),
+        })
+    }
+
+    /// Returns the entity with the given index, like `Entity(index)`. The handle may refer to a NULL entity.
+    pub fn by_index(lua: State, index: i32) -> Self {
+        lua.get_global(c"Entity");
+        lua.push_number(index);
+        if lua.pcall(1, 1, 0).is_err() {
+            lua.pop();
+            lua.get_global(c"NULL");
+        }
+        Self {
+            r#ref: LuaRef::new(lua),
+        }
+    }
+
+    /// Pushes the entity onto the stack.
+    pub fn push(&self, lua: State) {
+        self.r#ref.push(lua);
+    }
+
+    /// Creates a new handle to the same entity.
+    pub fn try_clone(&self, lua: State) -> Self {
+        Self {
+            r#ref: self.r#ref.try_clone(lua),
+        }
+    }
+
+    /// Returns the underlying registry reference.
+    pub fn lua_ref(&self) -> &LuaRef {
+        &self.r#ref
+    }
+
+    /// Returns whether the entity still exists, like `IsValid(ent)`.
+    pub fn is_valid(&self, lua: State) -> bool {
+        lua.get_global(c"IsValid");
+        self.push(lua);
+        if lua.pcall(1, 1, 0).is_err() {
+            lua.pop();
+            return false;
+        }
+        let valid = lua.get_boolean(-1);
+        lua.pop();
+        valid
+    }
+
+    /// Calls `ent:<name>(...)` with the arguments pushed by `push_args`, which returns how many it pushed.
+    ///
+    /// On success, `nresults` results are left on the stack.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// ent.call_method(lua, c"SetHealth", |lua| { lua.push_number(50); 1 }, 0)?;
+    /// ```
+    pub fn call_method(
+        &self,
+        lua: State,
+        name: LuaCStr,
+        push_args: impl FnOnce(State) -> i32,
+        nresults: i32,
+    ) -> Result<(), LuaError> {
+        self.push(lua);
+        lua.get_field(-1, name);
+        lua.insert(-2);
+        let nargs = push_args(lua);
+        lua.pcall(nargs + 1, nresults, 0).inspect_err(|_| lua.pop())
+    }
+
+    /// Calls a method without arguments and converts its single result with `read`. Returns `None` if the call failed (e.g. the entity is NULL).
+    pub(crate) fn get<T>(
+        &self,
+        lua: State,
+        name: LuaCStr,
+        read: impl FnOnce(State) -> Option<T>,
+    ) -> Option<T> {
+        self.call_method(lua, name, |_| 0, 1).ok()?;
+        let value = read(lua);
+        lua.pop();
+        value
+    }
+
+    /// Calls a method with arguments, discarding its results. Returns whether the call succeeded.
+    pub(crate) fn set(
+        &self,
+        lua: State,
+        name: LuaCStr,
+        push_args: impl FnOnce(State) -> i32,
+    ) -> bool {
+        self.call_method(lua, name, push_args, 0).is_ok()
+    }
+
+    /// Returns the entity with the given handle, or `None` if that entity doesn't exist anymore, e.g. because another entity took its index.
+    pub fn from_handle(lua: State, handle: EntityHandle) -> Option<Self> {
+        if !handle.is_valid() {
+            return None;
+        }
+        let ent = Self::by_index(lua, handle.entry_index() as i32);
+        (ent.handle(lua) == Some(handle)).then_some(ent)
+    }
+
+    /// Returns the engine's handle to the entity, read from its userdata, or `None` for NULL entities.
+    pub fn handle(&self, lua: State) -> Option<EntityHandle> {
+        self.push(lua);
+        // every entity userdata is tagged `Entity` and points to a `CBaseHandle`, whatever its metatable
+        let ud = lua.to_userdata(-1) as *const TaggedUserData;
+        lua.pop();
+        let handle = unsafe {
+            if ud.is_null() || (*ud).r#type != UserData::Entity || (*ud).data.is_null() {
+                return None;
+            }
+            *((*ud).data as *const EntityHandle)
+        };
+        handle.is_valid().then_some(handle)
+    }
+
+    /// Returns the entity's `CBaseEntity`, or `None` for NULL and removed entities.
+    ///
+    /// # Safety
+    /// The pointer is only valid until the entity is removed.
+    pub unsafe fn base_entity(&self, lua: State, list: &EntityList) -> Option<NonNull<c_void>> {
+        list.lookup(self.handle(lua)?)
+    }
+
+    /// Returns the entity's index, like `ent:EntIndex()`.
+    pub fn ent_index(&self, lua: State) -> Option<i32> {
+        self.get(lua, c"EntIndex", |lua| Some(lua.to_number(-1) as i32))
+    }
+
+    /// Returns the entity's class name, like `ent:GetClass()`.
+    pub fn get_class(&self, lua: State) -> Option<String> {
+        self.get(lua, c"GetClass", |lua| {
+            lua.get_string(-1).map(|s| s.into_owned())
+        })
+    }
+
+    /// Returns the entity's model, like `ent:GetModel()`.
+    pub fn get_model(&self, lua: State) -> Option<String> {
+        self.get(lua, c"GetModel", |lua| {
+            lua.get_string(-1).map(|s| s.into_owned())
+        })
+    }
+
+    /// Returns the entity's position, like `ent:GetPos()`.
+    pub fn get_pos(&self, lua: State) -> Option<Vector> {
+        self.get(lua, c"GetPos", |lua| lua.get_vector(-1))
+    }
+
+    /// Sets the entity's position, like `ent:SetPos(pos)`. Returns whether the call succeeded.
+    pub fn set_pos(&self, lua: State, pos: Vector) -> bool {
+        self.set(lua, c"SetPos", |lua| {
+            lua.push_vector(pos);
+            1
+        })
+    }
+
+    /// Returns the entity's angles, like `ent:GetAngles()`.
+    pub fn get_angles(&self, lua: State) -> Option<Angle> {
+        self.get(lua, c"GetAngles", |lua| lua.get_angle(-1))
+    }
+
+    /// Sets the entity's angles, like `ent:SetAngles(ang)`. Returns whether the call succeeded.
+    pub fn set_angles(&self, lua: State, ang: Angle) -> bool {
+        self.set(lua, c"SetAngles", |lua| {
+            lua.push_angle(ang);
+            1
+        })
+    }
+
+    /// Returns the entity's velocity, like `ent:GetVelocity()`.
+    pub fn get_velocity(&self, lua: State) -> Option<Vector> {
+        self.get(lua, c"GetVelocity", |lua| lua.get_vector(-1))
+    }
+
+    /// Returns the entity's health, like `ent:Health()`.
+    pub fn health(&self, lua: State) -> Option<i32> {
+        self.get(lua, c"Health", |lua| Some(lua.to_number(-1) as i32))
+    }
+
+    /// Sets the entity's health, like `ent:SetHealth(health)`. Returns whether the call succeeded.
+    pub fn set_health(&self, lua: State, health: i32) -> bool {
+        self.set(lua, c"SetHealth", |lua| {
+            lua.push_number(health);
+            1
+        })
+    }
+
+    /// Returns whether the entity is a player, like `ent:IsPlayer()`.
+    pub fn is_player(&self, lua: State) -> bool {
+        self.get(lua, c"IsPlayer", |lua| Some(lua.get_boolean(-1)))
+            .unwrap_or(false)
+    }
+
+    /// Removes the entity, like `ent:Remove()`. Returns whether the call succeeded.
+    pub fn remove(&self, lua: State) -> bool {
+        self.set(lua, c"Remove", |_| 0)
+    }
+}
+
+/// The engine's entity list (`CBaseEntityList`), which resolves handles to `CBaseEntity` pointers.
+///
+/// Its address isn't exported by the game's libraries: modules usually find it with `sigscan`, or read it from a function that uses it.
+#[derive(Debug, Clone, Copy)]
+pub struct EntityList(NonNull<c_void>);
+
+unsafe impl Send for EntityList {}
+unsafe impl Sync for EntityList {}
+
+/// An entry of `CBaseEntityList::m_EntPtrArray`.
+#[repr(C)]
+struct EntInfo {
+    entity: *mut c_void,
+    serial_number: i32,
+    prev: *mut EntInfo,
+    next: *mut EntInfo,
+}
+
+impl EntityList {
+    /// # Safety
+    /// `ptr` must point to the game's `CBaseEntityList`, such as the server's `g_pEntityList`.
+    pub unsafe fn from_ptr(ptr: *mut c_void) -> Option<Self> {
+        NonNull::new(ptr).map(Self)
+    }
+
+    /// Returns the `CBaseEntity` with the given handle, if it still exists.
+    pub fn lookup(&self, handle: EntityHandle) -> Option<NonNull<c_void>> {
+        if !handle.is_valid() {
+            return None;
+        }
+        unsafe {
+            // the entries follow the vtable pointer
+            let entries =
+                self.0.as_ptr().add(std::mem::size_of::<*const c_void>()) as *const EntInfo;
+            let info = &*entries.add(handle.entry_index() as usize);
+            if info.serial_number as u32 != handle.serial_number() {
+                return None;
+            }
+            NonNull::new(info.entity)
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Error codes shared by every subsystem, so errors can be handled programmatically instead of by matching messages.
+//!
+//! Errors are tagged with a code by returning an `Error`, or by calling `WithCode::code` on any `Result`. `code_of` recovers the code from an `anyhow::Error` anywhere up its chain, and also understands untagged `std::io::Error`s, `LuaError`s and `ClosedError`s.
+//!
+//! Lua receives errors as `{ ok = false, code = "NET_TIMEOUT", message = "..." }` tables (see `push` and `ErrorTable`), with codes as the strings returned by `ErrorCode::as_str`.
+//!
+//! ## Example
+//!
+//! ```
+//! use gmod::error::{self, ErrorCategory, ErrorCode, WithCode};
+//!
+//! fn read_config() -> anyhow::Result<String> {
+//!     let raw = std::fs::read_to_string("does/not/exist.toml")?;
+//!     let port = raw.parse::<u16>().code(ErrorCode::ConfigParse)?;
+//!     Ok(port.to_string())
+//! }
+//!
+//! let err = read_config().unwrap_err();
+//! assert_eq!(error::code_of(&err), ErrorCode::IoNotFound);
+//! assert_eq!(error::code_of(&err).category(), ErrorCategory::Io);
+//! assert_eq!(ErrorCode::IoNotFound.as_str(), "IO_NOT_FOUND");
+//! ```
+
+use std::{borrow::Cow, fmt, io};
+
+use crate::{
+    lifecycle::ClosedError,
+    lua::{LuaError, State},
+};
+
+/// The subsystem an `ErrorCode` belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    Io,
+    Net,
+    Db,
+    Lua,
+    Config,
+    Other,
+}
+
+macro_rules! error_codes {
+    ($($category:ident { $($(#[$meta:meta])* $code:ident = $name:literal,)+ })+) => {
+        /// What went wrong, independently of the error message.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[non_exhaustive]
+        pub enum ErrorCode {
+            $($($(#[$meta])* $code,)+)+
+        }
+
+        impl ErrorCode {
+            /// Every code, in declaration order.
+            pub const ALL: &'static [ErrorCode] = &[$($(ErrorCode::$code,)+)+];
+
+            /// The name Lua sees, e.g. `"NET_TIMEOUT"`.
+            pub const fn as_str(self) -> &'static str {
+                match self {
+                    $($(ErrorCode::$code => $name,)+)+
+                }
+            }
+
+            pub const fn category(self) -> ErrorCategory {
+                match self {
+                    $($(ErrorCode::$code => ErrorCategory::$category,)+)+
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    Io {
+        /// A file or directory doesn't exist.
+        IoNotFound = "IO_NOT_FOUND",
+        /// The process isn't allowed to access a file or directory.
+        IoPermissionDenied = "IO_PERMISSION_DENIED",
+        /// A file or directory already exists.
+        IoAlreadyExists = "IO_ALREADY_EXISTS",
+        /// Any other I/O error.
+        Io = "IO_ERROR",
+    }
+    Net {
+        /// A network operation timed out.
+        NetTimeout = "NET_TIMEOUT",
+        /// The remote host refused the connection.
+        NetConnectionRefused = "NET_CONNECTION_REFUSED",
+        /// The connection was reset or aborted.
+        NetConnectionReset = "NET_CONNECTION_RESET",
+        /// The remote host or network can't be reached.
+        NetUnreachable = "NET_UNREACHABLE",
+        /// Any other network error, e.g. an unexpected response.
+        Net = "NET_ERROR",
+    }
+    Db {
+        /// A query violated a constraint, e.g. a unique key.
+        DbConstraint = "DB_CONSTRAINT",
+        /// The database is locked or has no free connections.
+        DbBusy = "DB_BUSY",
+        /// A row the operation needed doesn't exist.
+        DbNotFound = "DB_NOT_FOUND",
+        /// Any other database error.
+        Db = "DB_ERROR",
+    }
+    Lua {
+        /// Lua code raised an error.
+        LuaRuntime = "LUA_RUNTIME",
+        /// Lua code couldn't be compiled.
+        LuaSyntax = "LUA_SYNTAX",
+        /// Lua ran out of memory.
+        LuaMemory = "LUA_MEMORY",
+        /// A function was called from Lua with an invalid argument.
+        LuaBadArgument = "LUA_BAD_ARGUMENT",
+        /// Any other Lua error.
+        Lua = "LUA_ERROR",
+    }
+    Config {
+        /// A config file couldn't be parsed.
+        ConfigParse = "CONFIG_PARSE",
+        /// A config value is out of range or inconsistent with others.
+        ConfigInvalid = "CONFIG_INVALID",
+        /// A required config value isn't set.
+        ConfigMissing = "CONFIG_MISSING",
+    }
+    Other {
+        /// Input data is malformed.
+        InvalidInput = "INVALID_INPUT",
+        /// The operation isn't supported on this platform, branch or build.
+        Unsupported = "UNSUPPORTED",
+        /// The module, or the subsystem that was asked to do something, is shutting down.
+        Closed = "CLOSED",
+        /// The error wasn't tagged with a code.
+        Unknown = "UNKNOWN",
+    }
+}
+
+impl ErrorCode {
+    /// Parses a code from its `as_str` name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|code| code.as_str() == name)
+    }
+
+    /// Maps an I/O error kind, including the network ones, to a code.
+    pub fn from_io_kind(kind: io::ErrorKind) -> Self {
+        use io::ErrorKind::*;
+        match kind {
+            NotFound => ErrorCode::IoNotFound,
+            PermissionDenied => ErrorCode::IoPermissionDenied,
+            AlreadyExists => ErrorCode::IoAlreadyExists,
+            TimedOut | WouldBlock => ErrorCode::NetTimeout,
+            ConnectionRefused => ErrorCode::NetConnectionRefused,
+            ConnectionReset | ConnectionAborted | BrokenPipe | NotConnected => {
+                ErrorCode::NetConnectionReset
+            }
+            HostUnreachable | NetworkUnreachable | AddrNotAvailable => ErrorCode::NetUnreachable,
+            InvalidInput | InvalidData => ErrorCode::InvalidInput,
+            Unsupported => ErrorCode::Unsupported,
+            _ => ErrorCode::Io,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&LuaError> for ErrorCode {
+    fn from(err: &LuaError) -> Self {
+        match err {
+            LuaError::RuntimeError(_) => ErrorCode::LuaRuntime,
+            LuaError::SyntaxError(_) => ErrorCode::LuaSyntax,
+            LuaError::MemoryAllocationError => ErrorCode::LuaMemory,
+            LuaError::FileError(_) => ErrorCode::Io,
+            LuaError::ErrorHandlerError | LuaError::Unknown(_) => ErrorCode::Lua,
+        }
+    }
+}
+
+/// An error tagged with an `ErrorCode`. Its message includes the whole chain of the error it wraps.
+#[derive(Debug)]
+pub struct Error {
+    code: ErrorCode,
+    message: Cow<'static, str>,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, message: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Tags the error of a `Result` with an `ErrorCode`.
+pub trait WithCode<T> {
+    fn code(self, code: ErrorCode) -> Result<T, Error>;
+}
+
+impl<T, E: Into<anyhow::Error>> WithCode<T> for Result<T, E> {
+    fn code(self, code: ErrorCode) -> Result<T, Error> {
+        self.map_err(|err| Error::new(code, format!("{:#}", err.into())))
+    }
+}
+
+/// Returns the code of an error, looking through its whole chain. Untagged errors are `ErrorCode::Unknown`.
+pub fn code_of(err: &anyhow::Error) -> ErrorCode {
+    if let Some(err) = err.downcast_ref::<Error>() {
+        return err.code;
+    }
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<Error>() {
+            return err.code;
+        }
+        if let Some(err) = cause.downcast_ref::<io::Error>() {
+            return ErrorCode::from_io_kind(err.kind());
+        }
+        if let Some(err) = cause.downcast_ref::<LuaError>() {
+            return err.into();
+        }
+        if cause.is::<ClosedError>() {
+            return ErrorCode::Closed;
+        }
+    }
+    ErrorCode::Unknown
+}
+
+/// Pushes `{ ok = false, code = string, message = string }` for an error. The message includes the whole chain.
+pub fn push(l: State, err: &anyhow::Error) {
+    l.push_err(code_of(err), &format!("{err:#}"));
+}
+
+/// An error returned to Lua as `{ ok = false, code = string, message = string }` by `#[lua_function(result_table)]` functions, instead of being raised.
+///
+/// Any `anyhow::Error`, `Error`, `std::io::Error` or `LuaError` converts into one with `?`, keeping its code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorTable {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorTable {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ErrorTable {
+    fn from(err: anyhow::Error) -> Self {
+        Self::new(code_of(&err), format!("{err:#}"))
+    }
+}
+
+impl From<Error> for ErrorTable {
+    fn from(err: Error) -> Self {
+        Self::new(err.code, err.message)
+    }
+}
+
+impl From<io::Error> for ErrorTable {
+    fn from(err: io::Error) -> Self {
+        Self::new(ErrorCode::from_io_kind(err.kind()), err.to_string())
+    }
+}
+
+impl From<LuaError> for ErrorTable {
+    fn from(err: LuaError) -> Self {
+        Self::new((&err).into(), err.to_string())
+    }
+}
+
+/// An error that `#[lua_function]`s report with `ErrorNoHaltWithStack` instead of raising, see `non_fatal`.
+#[derive(Debug)]
+pub struct NonFatal(pub anyhow::Error);
+
+impl fmt::Display for NonFatal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for NonFatal {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Marks an error as non-fatal: returned from a `#[lua_function]`, it's printed with `ErrorNoHaltWithStack` and the function returns nothing instead of raising a Lua error. The mark survives `context` added on top of it.
+///
+/// ```no_run
+/// #[gmod::lua_function]
+/// fn refresh_cache(lua: gmod::lua::State) -> anyhow::Result<()> {
+///     std::fs::read("data/cache.bin").map_err(gmod::error::non_fatal)?;
+///     Ok(())
+/// }
+/// ```
+pub fn non_fatal(err: impl Into<anyhow::Error>) -> anyhow::Error {
+    NonFatal(err.into()).into()
+}
@@ -0,0 +1,231 @@
+//! Capturing the Lua errors raised by scripts, to collect, aggregate or forward them (to Sentry, a Discord webhook, a log file...).
+//!
+//! Errors are received through the `OnLuaError` hook, which the game runs for every script error in the realms that support it, with the stack and the addon the error came from. Errors the game doesn't run the hook for aren't captured.
+//!
+//! Not to be confused with `gmod::error`, the error codes of gmod-rs itself.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! let id = gmod::errors::subscribe(lua, |lua, err| {
+//!     let addon = err.addon.as_ref().map_or("unknown addon", |addon| &addon.title);
+//!     eprintln!("[{addon}] {} ({:016x})", err.message, err.fingerprint());
+//!     for frame in &err.stack {
+//!         eprintln!("  {frame}");
+//!     }
+//! });
+//!
+//! // later
+//! gmod::errors::unsubscribe(lua, id);
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use crate::{hook, lua::State};
+
+/// The hook errors are received through.
+const HOOK_EVENT: &str = "OnLuaError";
+const HOOK_IDENTIFIER: &str = "gmod_rs_errors";
+
+/// A Lua error raised by a script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    /// The error message, usually prefixed with the file and line it was raised at.
+    pub message: String,
+    /// The realm the error was raised in: `"server"`, `"client"` or `"menu"`.
+    pub realm: String,
+    /// The stack when the error was raised, innermost call first.
+    pub stack: Vec<StackFrame>,
+    /// The addon the error came from, if it can be told.
+    pub addon: Option<Addon>,
+}
+
+/// A function call on the stack of a `ScriptError`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StackFrame {
+    /// The file the function is defined in, e.g. `addons/my_addon/lua/autorun/init.lua`.
+    pub source: String,
+    /// The name of the function, empty if it has none.
+    pub function: String,
+    /// The line being run, or -1 for C functions.
+    pub line: i32,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let function = if self.function.is_empty() {
+            "unknown"
+        } else {
+            &self.function
+        };
+        write!(f, "{function} - {}:{}", self.source, self.line)
+    }
+}
+
+/// The addon a `ScriptError` came from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Addon {
+    /// The title of the addon, or the name of its folder under `addons/` for legacy addons.
+    pub title: String,
+    /// The Workshop ID of the addon, for Workshop addons.
+    pub workshop_id: Option<u64>,
+}
+
+impl ScriptError {
+    /// Returns a hash of the message and the innermost frame, the same for every occurrence of an error, to count or deduplicate them.
+    ///
+    /// It's stable for the lifetime of the process, not across builds.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.message.hash(&mut hasher);
+        self.stack.first().hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// Tells the addon a file belongs to from its path, for legacy addons (`addons/<folder>/lua/...`).
+///
+/// ```
+/// use gmod::errors::addon_of_path;
+///
+/// assert_eq!(addon_of_path("addons/my_addon/lua/autorun/init.lua").as_deref(), Some("my_addon"));
+/// assert_eq!(addon_of_path("lua/autorun/init.lua"), None);
+/// ```
+pub fn addon_of_path(path: &str) -> Option<String> {
+    let rest = path.strip_prefix("addons/")?;
+    let (folder, _) = rest.split_once('/')?;
+    Some(folder.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Subscriber = Box<dyn FnMut(State, &ScriptError)>;
+
+thread_local! {
+    /// Taken out while running, so subscribers can subscribe and unsubscribe.
+    static SUBSCRIBERS: RefCell<Vec<(SubscriptionId, Option<Subscriber>)>> = const { RefCell::new(Vec::new()) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Calls `callback` with every Lua error raised by scripts from now on, until `unsubscribe` is called or the module is closed.
+pub fn subscribe<F>(lua: State, callback: F) -> SubscriptionId
+where
+    F: FnMut(State, &ScriptError) + 'static,
+{
+    let id = SubscriptionId(NEXT_ID.get());
+    NEXT_ID.set(id.0 + 1);
+
+    let first = SUBSCRIBERS.with_borrow_mut(|subscribers| {
+        subscribers.push((id, Some(Box::new(callback))));
+        subscribers.len() == 1
+    });
+    if first {
+        hook::add(lua, HOOK_EVENT, HOOK_IDENTIFIER, on_lua_error);
+    }
+    id
+}
+
+/// Removes a subscriber. Returns whether it was subscribed.
+pub fn unsubscribe(lua: State, id: SubscriptionId) -> bool {
+    let (removed, last) = SUBSCRIBERS.with_borrow_mut(|subscribers| {
+        let len = subscribers.len();
+        subscribers.retain(|(sub_id, _)| *sub_id != id);
+        (subscribers.len() != len, subscribers.is_empty())
+    });
+    if removed && last {
+        hook::remove(lua, HOOK_EVENT, HOOK_IDENTIFIER);
+    }
+    removed
+}
+
+/// Reads a string argument, which may be missing.
+fn string_arg(lua: State, arg: i32) -> String {
+    if lua.is_string(arg) {
+        lua.get_string(arg).unwrap_or_default().into_owned()
+    } else {
+        String::new()
+    }
+}
+
+/// Reads the stack table, `{ { File = ..., Function = ..., Line = ... }, ... }`.
+fn read_stack(lua: State, arg: i32) -> Vec<StackFrame> {
+    let mut stack = Vec::new();
+    if !lua.is_table(arg) {
+        return stack;
+    }
+    for i in 1..=lua.len(arg) {
+        lua.raw_geti(arg, i);
+        if lua.is_table(-1) {
+            let top = lua.get_top();
+            lua.get_field(top, c"File");
+            lua.get_field(top, c"Function");
+            lua.get_field(top, c"Line");
+            stack.push(StackFrame {
+                source: string_arg(lua, top + 1),
+                function: string_arg(lua, top + 2),
+                line: if lua.is_number(top + 3) {
+                    lua.to_number(top + 3) as i32
+                } else {
+                    -1
+                },
+            });
+            lua.pop_n(3);
+        }
+        lua.pop();
+    }
+    stack
+}
+
+/// `OnLuaError(error, realm, stack, addon title, addon Workshop ID)`
+extern "C-unwind" fn on_lua_error(lua: State) -> i32 {
+    let stack = read_stack(lua, 3);
+    let title = string_arg(lua, 4);
+    let addon = if title.is_empty() {
+        stack
+            .iter()
+            .find_map(|frame| addon_of_path(&frame.source))
+            .map(|title| Addon {
+                title,
+                workshop_id: None,
+            })
+    } else {
+        Some(Addon {
+            title,
+            workshop_id: string_arg(lua, 5).parse().ok().filter(|&id| id != 0),
+        })
+    };
+    let error = ScriptError {
+        message: string_arg(lua, 1),
+        realm: string_arg(lua, 2),
+        stack,
+        addon,
+    };
+
+    let ids: Vec<SubscriptionId> =
+        SUBSCRIBERS.with_borrow(|subscribers| subscribers.iter().map(|(id, _)| *id).collect());
+    for id in ids {
+        let Some(mut callback) = SUBSCRIBERS.with_borrow_mut(|subscribers| {
+            subscribers
+                .iter_mut()
+                .find(|(sub_id, _)| *sub_id == id)
+                .and_then(|(_, callback)| callback.take())
+        }) else {
+            continue;
+        };
+        let top = lua.get_top();
+        callback(lua, &error);
+        lua.set_top(top);
+        SUBSCRIBERS.with_borrow_mut(|subscribers| {
+            if let Some((_, slot @ None)) = subscribers.iter_mut().find(|(sub_id, _)| *sub_id == id)
+            {
+                *slot = Some(callback);
+            }
+        });
+    }
+    0
+}
@@ -0,0 +1,245 @@
+use std::{
+    collections::VecDeque,
+    ffi::c_void,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::Result;
+
+use crate::lua::{self, CoroutinePool, HandleLuaFunctionReturn, State};
+
+/// An event that can be recorded in an [`EventLog`].
+pub trait Event: Clone + Send + 'static {
+    /// The name of the event, passed to Lua subscribers as the second argument (after the sequence number).
+    fn name(&self) -> &str;
+
+    /// Pushes the payload of the event onto the stack for Lua subscribers, returning how many values were pushed.
+    fn push_lua(&self, _lua: State) -> i32 {
+        0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Subscriber<T> = Arc<dyn Fn(u64, &T) + Send + Sync>;
+
+struct Inner<T> {
+    capacity: usize,
+    pool: Option<&'static CoroutinePool>,
+    next_seq: u64,
+    next_subscription: u64,
+    events: VecDeque<(u64, T)>,
+    subscribers: Vec<(SubscriptionId, Subscriber<T>)>,
+}
+
+/// A bounded, in-memory log of recent events.
+///
+/// Subscribers are replayed every buffered event when they subscribe, so late subscribers (such as Lua scripts loaded by autorefresh) don't miss anything that is still in the log.
+///
+/// Every event is assigned a sequence number, starting at 1.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::eventlog::{Event, EventLog};
+///
+/// #[derive(Clone)]
+/// struct PlayerJoined(u64);
+/// impl Event for PlayerJoined {
+///     fn name(&self) -> &str {
+///         "PlayerJoined"
+///     }
+/// }
+///
+/// static JOINS: EventLog<PlayerJoined> = EventLog::new(2);
+///
+/// JOINS.publish(PlayerJoined(1));
+/// JOINS.publish(PlayerJoined(2));
+/// JOINS.publish(PlayerJoined(3));
+///
+/// let seqs: Vec<u64> = JOINS.recent().into_iter().map(|(seq, _)| seq).collect();
+/// assert_eq!(seqs, [2, 3]);
+/// ```
+pub struct EventLog<T> {
+    inner: Mutex<Inner<T>>,
+    /// Held while events are delivered, so every subscriber sees them in order, backlog included.
+    delivery: Mutex<()>,
+}
+
+impl<T: Event> EventLog<T> {
+    /// Creates an event log that holds at most `capacity` events.
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            delivery: Mutex::new(()),
+            inner: Mutex::new(Inner {
+                capacity,
+                pool: None,
+                next_seq: 1,
+                next_subscription: 0,
+                events: VecDeque::new(),
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner<T>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records an event and notifies every subscriber, returning the event's sequence number.
+    ///
+    /// Subscribers are called on the publishing thread, one event at a time, so they must not publish to or subscribe to the same log.
+    pub fn publish(&self, event: T) -> u64 {
+        let _delivery = self.delivery.lock().unwrap_or_else(|e| e.into_inner());
+        let (seq, subscribers) = {
+            let mut inner = self.lock();
+
+            let seq = inner.next_seq;
+            inner.next_seq += 1;
+
+            if inner.capacity > 0 {
+                if inner.events.len() >= inner.capacity {
+                    inner.events.pop_front();
+                }
+                inner.events.push_back((seq, event.clone()));
+            }
+
+            let subscribers: Vec<_> = inner.subscribers.iter().map(|(_, f)| f.clone()).collect();
+            (seq, subscribers)
+        };
+
+        for subscriber in subscribers {
+            subscriber(seq, &event);
+        }
+
+        seq
+    }
+
+    /// Subscribes to new events, after replaying every event currently in the log. Events published meanwhile are delivered after the replay.
+    pub fn subscribe<F>(&self, f: F) -> SubscriptionId
+    where
+        F: Fn(u64, &T) + Send + Sync + 'static,
+    {
+        let f: Subscriber<T> = Arc::new(f);
+        let _delivery = self.delivery.lock().unwrap_or_else(|e| e.into_inner());
+
+        let (id, backlog) = {
+            let mut inner = self.lock();
+            let id = SubscriptionId(inner.next_subscription);
+            inner.next_subscription += 1;
+            inner.subscribers.push((id, f.clone()));
+            (id, inner.events.iter().cloned().collect::<Vec<_>>())
+        };
+
+        for (seq, event) in backlog {
+            f(seq, &event);
+        }
+
+        id
+    }
+
+    /// Removes a subscriber. Returns whether it was subscribed.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut inner = self.lock();
+        let len = inner.subscribers.len();
+        inner.subscribers.retain(|(sub_id, _)| *sub_id != id);
+        inner.subscribers.len() != len
+    }
+
+    /// Returns every event currently in the log, oldest first.
+    pub fn recent(&self) -> Vec<(u64, T)> {
+        self.lock().events.iter().cloned().collect()
+    }
+
+    /// Returns every event in the log with a sequence number greater than `seq`, oldest first.
+    pub fn since(&self, seq: u64) -> Vec<(u64, T)> {
+        self.lock()
+            .events
+            .iter()
+            .filter(|(event_seq, _)| *event_seq > seq)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the sequence number of the most recently published event, or 0 if nothing has been published yet.
+    pub fn last_seq(&self) -> u64 {
+        self.lock().next_seq - 1
+    }
+
+    /// Removes every event from the log. Sequence numbers are not reset.
+    pub fn clear(&self) {
+        self.lock().events.clear();
+    }
+
+    /// Makes `Replay` call its callback inside a coroutine from `pool`, or directly again if `None`.
+    ///
+    /// A callback that yields is left suspended and the replay moves on to the next event.
+    pub fn set_coroutine_pool(&self, pool: Option<&'static CoroutinePool>) {
+        self.lock().pool = pool;
+    }
+
+    /// Creates a global Lua table named `libname` for inspecting and replaying this log:
+    ///
+    /// * `Replay(callback, since)` calls `callback(seq, name, ...)` for every event in the log after sequence number `since` (or all of them), and returns the last sequence number.
+    /// * `LastSequence()` returns the sequence number of the most recently published event.
+    pub fn register(&'static self, lua: State, libname: lua::LuaCStr) {
+        lua.create_table(0, 2);
+
+        lua.push_lightuserdata(self as *const Self as *mut c_void);
+        lua.push_closure(lua_replay::<T>, 1);
+        lua.set_field(-2, c"Replay");
+
+        lua.push_lightuserdata(self as *const Self as *mut c_void);
+        lua.push_closure(lua_last_seq::<T>, 1);
+        lua.set_field(-2, c"LastSequence");
+
+        lua.set_global(libname);
+    }
+}
+
+unsafe fn log_upvalue<'a, T: Event>(lua: State) -> &'a EventLog<T> {
+    lua.push_closure_arg(1);
+    let log = lua.to_userdata(-1) as *const EventLog<T>;
+    lua.pop();
+    &*log
+}
+
+extern "C-unwind" fn lua_replay<T: Event>(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        lua.check_function(1)?;
+        let since = if lua.is_none_or_nil(2) {
+            0
+        } else {
+            lua.check_number(2)? as u64
+        };
+
+        let log = unsafe { log_upvalue::<T>(lua) };
+        let pool = log.lock().pool;
+        let mut last = since;
+        for (seq, event) in log.since(since) {
+            lua.push_value(1);
+            lua.push_number(seq);
+            lua.push_string(event.name());
+            let nargs = event.push_lua(lua);
+            let ok = match pool {
+                Some(pool) => pool.call_ignore(lua, 2 + nargs, 0),
+                None => lua.pcall_ignore(2 + nargs, 0),
+            };
+            if !ok {
+                break;
+            }
+            last = seq;
+        }
+
+        lua.push_number(last);
+        Ok(1)
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_last_seq<T: Event>(lua: State) -> i32 {
+    let log = unsafe { log_upvalue::<T>(lua) };
+    lua.push_number(log.last_seq());
+    1
+}
@@ -0,0 +1,62 @@
+//! Lua functions registered into global tables by `#[gmod13_open]`, without a `lua_regs!` table to keep in sync.
+//!
+//! `#[lua_export(namespace = "mymod")]` is `#[lua_function]`, plus an `Export` collected at link time: `gmod13_open` calls `register` before its body, which sets every exported function into its namespace, a global table created if missing. Namespaces can be nested with dots, like `"mymod.util"`. The function is named like the Rust function unless `name = "..."` is given.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! /// `mymod.Greet(name)`
+//! #[lua_export(namespace = "mymod", name = "Greet")]
+//! fn greet(_lua: State, name: String) -> (String,) {
+//!     (format!("Hello, {name}!"),)
+//! }
+//! ```
+
+use crate::lua::{cstr, LuaFunction, State};
+
+#[doc(hidden)]
+pub use inventory;
+
+/// A function exported with `#[lua_export]`.
+#[derive(Debug, Clone, Copy)]
+pub struct Export {
+    /// The global table it's set into, like `"mymod"` or `"mymod.util"`.
+    pub namespace: &'static str,
+    pub name: &'static str,
+    pub func: LuaFunction,
+}
+
+inventory::collect!(Export);
+
+/// Returns every exported function, sorted by namespace and name.
+pub fn exports() -> Vec<&'static Export> {
+    let mut exports: Vec<_> = inventory::iter::<Export>.into_iter().collect();
+    exports.sort_by_key(|export| (export.namespace, export.name));
+    exports
+}
+
+/// Sets every exported function into its namespace. Called by `#[gmod13_open]`.
+pub fn register(lua: State) {
+    for export in exports() {
+        push_namespace(lua, export.namespace);
+        lua.push_function(export.func);
+        lua.set_field(-2, &cstr::cached(export.name));
+        lua.pop();
+    }
+}
+
+/// Pushes the global table at `namespace`, creating the missing tables along the way. Tables replace any other value.
+fn push_namespace(lua: State, namespace: &str) {
+    unsafe { lua.push_globals() };
+    for part in namespace.split('.') {
+        let part = cstr::cached(part);
+        lua.get_field(-1, &part);
+        if !lua.is_table(-1) {
+            lua.pop();
+            lua.new_table();
+            lua.push_value(-1);
+            lua.set_field(-3, &part);
+        }
+        unsafe { lua.remove(-2) };
+    }
+}
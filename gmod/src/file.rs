@@ -0,0 +1,152 @@
+//! Files of the game's virtual filesystem, through GMod's `file` library.
+//!
+//! Unlike `std::fs`, which only sees the files on disk next to the game, reads go through the `GAME` search path, which includes mounted games, addons and workshop content. Paths are relative to the game's `garrysmod` directory, e.g. `data/my_module/config.json` or `materials/icon16/star.png`.
+//!
+//! GMod only lets Lua write under `data/`, and only files with a whitelisted extension (`.txt`, `.json`, `.dat`, `.csv`, `.png`...). Writing anywhere else fails.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::file;
+//!
+//! if !file::exists(lua, "data/my_module/config.json") {
+//!     file::write(lua, "data/my_module/config.json", DEFAULT_CONFIG)?;
+//! }
+//! let config = file::read_to_string(lua, "data/my_module/config.json")?;
+//! ```
+
+use anyhow::{bail, Result};
+
+use crate::lua::{LuaCStr, State};
+
+/// Calls `file[name]` with `args`, leaving `nresults` results on the stack.
+fn call(lua: State, name: LuaCStr, args: &[&[u8]], nresults: i32) -> Result<()> {
+    let top = lua.get_top();
+    lua.get_global(c"file");
+    if !lua.is_table(-1) {
+        lua.set_top(top);
+        bail!("the file library isn't available");
+    }
+    lua.get_field(-1, name);
+    unsafe { lua.remove(-2) };
+    for arg in args {
+        lua.push_binary_string(arg);
+    }
+    if let Err(err) = lua.pcall(args.len() as i32, nresults, 0) {
+        lua.set_top(top);
+        bail!("file.{} failed: {err}", name.to_string_lossy());
+    }
+    Ok(())
+}
+
+/// Calls `file[name]` and converts its result with `read`, restoring the stack.
+fn call_with_result<R>(
+    lua: State,
+    name: LuaCStr,
+    args: &[&[u8]],
+    read: impl FnOnce(State) -> R,
+) -> Result<R> {
+    let top = lua.get_top();
+    call(lua, name, args, 1)?;
+    let result = read(lua);
+    lua.set_top(top);
+    Ok(result)
+}
+
+/// Returns the path relative to `data/`, which is how `file.Write` and friends take it.
+fn data_path(path: &str) -> Result<String> {
+    let path = path.replace('\\', "/");
+    let Some(relative) = path.strip_prefix("data/") else {
+        bail!("can't write to {path}, only files under data/ can be written");
+    };
+    if relative.split('/').any(|component| component == "..") {
+        bail!("can't write to {path}, it leaves data/");
+    }
+    Ok(relative.to_owned())
+}
+
+/// Reads a whole file from the `GAME` search path.
+pub fn read(lua: State, path: &str) -> Result<Vec<u8>> {
+    read_from(lua, path, "GAME")
+}
+
+/// Reads a whole file from a search path (`GAME`, `DATA`, `LUA`, `MOD`, `WORKSHOP`, `BSP`, a mounted game's name...), relative to it.
+pub fn read_from(lua: State, path: &str, search_path: &str) -> Result<Vec<u8>> {
+    let contents = call_with_result(
+        lua,
+        c"Read",
+        &[path.as_bytes(), search_path.as_bytes()],
+        |lua| lua.get_binary_string(-1).map(<[u8]>::to_vec),
+    )?;
+    match contents {
+        Some(contents) => Ok(contents),
+        None => bail!("{path} doesn't exist in {search_path}, or can't be read"),
+    }
+}
+
+/// Reads a whole file from the `GAME` search path as UTF-8.
+pub fn read_to_string(lua: State, path: &str) -> Result<String> {
+    match String::from_utf8(read(lua, path)?) {
+        Ok(contents) => Ok(contents),
+        Err(_) => bail!("{path} isn't valid UTF-8"),
+    }
+}
+
+/// Returns whether a file or directory exists in the `GAME` search path.
+pub fn exists(lua: State, path: &str) -> bool {
+    call_with_result(lua, c"Exists", &[path.as_bytes(), b"GAME"], |lua| {
+        lua.get_boolean(-1)
+    })
+    .unwrap_or(false)
+}
+
+/// Returns whether a directory exists in the `GAME` search path.
+pub fn is_dir(lua: State, path: &str) -> bool {
+    call_with_result(lua, c"IsDir", &[path.as_bytes(), b"GAME"], |lua| {
+        lua.get_boolean(-1)
+    })
+    .unwrap_or(false)
+}
+
+/// Creates a directory under `data/`, along with its missing parents.
+pub fn create_dir(lua: State, path: &str) -> Result<()> {
+    let relative = data_path(path)?;
+    call(lua, c"CreateDir", &[relative.as_bytes()], 0)?;
+    if !exists(lua, path) {
+        bail!("couldn't create {path}");
+    }
+    Ok(())
+}
+
+/// Writes a whole file under `data/`, replacing it if it exists, and creating its missing parent directories.
+pub fn write(lua: State, path: &str, contents: impl AsRef<[u8]>) -> Result<()> {
+    write_with(lua, c"Write", path, contents.as_ref())
+}
+
+/// Appends to a file under `data/`, creating it and its missing parent directories if needed.
+pub fn append(lua: State, path: &str, contents: impl AsRef<[u8]>) -> Result<()> {
+    write_with(lua, c"Append", path, contents.as_ref())
+}
+
+fn write_with(lua: State, name: LuaCStr, path: &str, contents: &[u8]) -> Result<()> {
+    let relative = data_path(path)?;
+    if let Some((parent, _)) = relative.rsplit_once('/') {
+        call(lua, c"CreateDir", &[parent.as_bytes()], 0)?;
+    }
+    call(lua, name, &[relative.as_bytes(), contents], 0)?;
+    // both fail silently, e.g. for an extension that isn't whitelisted
+    if !exists(lua, path) {
+        bail!("couldn't write to {path}, GMod only allows some extensions (.txt, .json, .dat...)");
+    }
+    Ok(())
+}
+
+/// Deletes a file or empty directory under `data/`.
+pub fn delete(lua: State, path: &str) -> Result<()> {
+    let relative = data_path(path)?;
+    call(lua, c"Delete", &[relative.as_bytes()], 0)?;
+    if exists(lua, path) {
+        bail!("couldn't delete {path}");
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! File system watching, with changes delivered on the Lua tick.
+//!
+//! Events are debounced: editors tend to write a file in several steps, so changes are collected until nothing happened for `DEBOUNCE`, then delivered as a single batch.
+
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use globset::GlobBuilder;
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::lua::{task_queue, State};
+
+/// How long the file system has to be quiet before a batch of changes is delivered.
+pub const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// What happened to a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A change to a file matching a watched pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+/// Watchers that are still running, stopped by `unload`.
+static WATCHERS: Mutex<Option<HashMap<u64, notify::RecommendedWatcher>>> = Mutex::new(None);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A handle to a watcher created with `watch`. Watching stops when the handle is dropped, or when the module is closed.
+#[must_use = "watching stops as soon as the handle is dropped"]
+#[derive(Debug)]
+pub struct WatchHandle {
+    id: u64,
+}
+
+impl WatchHandle {
+    /// Returns whether the watcher is still running (it can be stopped by the module closing).
+    pub fn is_active(&self) -> bool {
+        WATCHERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .is_some_and(|watchers| watchers.contains_key(&self.id))
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        let watcher = WATCHERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+            .and_then(|watchers| watchers.remove(&self.id));
+        drop(watcher);
+    }
+}
+
+fn is_glob(component: &Component) -> bool {
+    matches!(component, Component::Normal(part) if part.to_string_lossy().contains(['*', '?', '[', '{']))
+}
+
+/// Splits a glob pattern into the directory to watch (everything before the first component containing a glob metacharacter), the rest of the pattern, and whether the rest can match in subdirectories.
+fn split_pattern(pattern: &Path) -> (PathBuf, PathBuf, bool) {
+    let components: Vec<Component> = pattern.components().collect();
+    let split = components
+        .iter()
+        .position(is_glob)
+        .unwrap_or(components.len().saturating_sub(1)); // a plain path: watch the file's parent directory so the file can be recreated
+
+    let base: PathBuf = components[..split].iter().collect();
+    let rest = &components[split..];
+    let recursive = rest.len() > 1
+        || rest
+            .iter()
+            .any(|component| component.as_os_str().to_string_lossy().contains("**"));
+
+    (base, rest.iter().collect(), recursive)
+}
+
+fn merge(previous: ChangeKind, next: ChangeKind) -> Option<ChangeKind> {
+    use ChangeKind::*;
+    match (previous, next) {
+        (Created, Removed) => None,
+        (Created, _) => Some(Created),
+        (Removed, Created) => Some(Modified),
+        (_, next) => Some(next),
+    }
+}
+
+/// Watches files matching `pattern` (e.g. `garrysmod/data/my_addon/**/*.json`), calling `callback` on the Lua thread with every batch of changes.
+///
+/// A pattern without glob metacharacters watches a single file.
+///
+/// ## Example
+///
+/// ```ignore
+/// let handle = gmod::fswatch::watch("garrysmod/data/my_addon/*.json", |lua, changes| {
+///     for change in changes {
+///         println!("{:?} {}", change.kind, change.path.display());
+///     }
+/// })?;
+/// ```
+pub fn watch<F>(pattern: &str, callback: F) -> Result<WatchHandle>
+where
+    F: FnMut(State, Vec<Change>) + Send + 'static,
+{
+    let (base, rest, recursive) = split_pattern(Path::new(pattern));
+    let base = if base.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        base
+    };
+    let base = base
+        .canonicalize()
+        .with_context(|| format!("failed to watch {pattern:?}: {base:?} does not exist"))?;
+
+    // match against canonical paths, as that's what the watcher reports
+    let matcher = GlobBuilder::new(&base.join(rest).to_string_lossy())
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("invalid pattern {pattern:?}"))?
+        .compile_matcher();
+
+    let (tx, rx) = flume::unbounded();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })?;
+    watcher.watch(
+        &base,
+        if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        },
+    )?;
+
+    let callback = Arc::new(Mutex::new(callback));
+
+    // the watcher's event handler (and with it the channel) is dropped when the watcher is, which ends this thread
+    std::thread::spawn(move || {
+        while let Ok(event) = rx.recv() {
+            let mut pending: HashMap<PathBuf, ChangeKind> = HashMap::new();
+            let mut next = Ok(event);
+            loop {
+                let event = match next {
+                    Ok(Ok(event)) => event,
+                    Ok(Err(err)) => {
+                        eprintln!("[gmod-rs] File watcher error: {err}");
+                        next = rx.recv_timeout(DEBOUNCE);
+                        continue;
+                    }
+                    Err(flume::RecvTimeoutError::Timeout) => break,
+                    Err(flume::RecvTimeoutError::Disconnected) => return,
+                };
+
+                let kind = match event.kind {
+                    EventKind::Create(_) => Some(ChangeKind::Created),
+                    EventKind::Remove(_) => Some(ChangeKind::Removed),
+                    // reading a file isn't a change
+                    EventKind::Access(_) => None,
+                    _ => Some(ChangeKind::Modified),
+                };
+
+                if let Some(kind) = kind {
+                    for path in event
+                        .paths
+                        .into_iter()
+                        .filter(|path| matcher.is_match(path))
+                    {
+                        let kind = match pending.remove(&path) {
+                            Some(previous) => merge(previous, kind),
+                            None => Some(kind),
+                        };
+                        if let Some(kind) = kind {
+                            pending.insert(path, kind);
+                        }
+                    }
+                }
+
+                next = rx.recv_timeout(DEBOUNCE);
+            }
+
+            if pending.is_empty() {
+                continue;
+            }
+
+            let mut changes: Vec<Change> = pending
+                .into_iter()
+                .map(|(path, kind)| Change { path, kind })
+                .collect();
+            changes.sort_by(|a, b| a.path.cmp(&b.path));
+
+            let callback = callback.clone();
+            let _ = task_queue::wait_lua_tick(String::new(), move |l| {
+                (callback.lock().unwrap_or_else(|e| e.into_inner()))(l, changes)
+            });
+        }
+    });
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    WATCHERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .insert(id, watcher);
+
+    Ok(WatchHandle { id })
+}
+
+/// Stops every watcher created through this module. This is called for you by `#[gmod13_close]`.
+pub fn unload() {
+    let watchers = WATCHERS.lock().unwrap_or_else(|e| e.into_inner()).take();
+    drop(watchers);
+}
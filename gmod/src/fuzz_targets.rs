@@ -0,0 +1,146 @@
+//! Entry points for fuzzing the decoders that handle data coming from clients, used by the `cargo fuzz` targets in `gmod/fuzz`.
+//!
+//! Each target takes arbitrary bytes and must never panic, hang or allocate without bound, whatever the input. The targets converting to Lua values run on a `TestState`, so `GMOD_RS_LUA_SHARED` must point to a LuaJIT library (see `gmod::testing`).
+//!
+//! ```sh
+//! cd gmod
+//! cargo +nightly fuzz run net_stream
+//! GMOD_RS_LUA_SHARED=/path/to/libluajit.so cargo +nightly fuzz run codec_decode
+//! ```
+
+use crate::net::stream::{self, Chunk, Compression, Pending};
+
+/// Largest stream the `net_stream` target accepts, kept low so the fuzzer doesn't spend its time allocating.
+const NET_STREAM_MAX_SIZE: usize = 1024 * 1024;
+
+/// Feeds chunks decoded from `data` to the net stream reassembly, then decompresses the streams that complete.
+///
+/// Each chunk is encoded as a stream id (1 byte, so streams collide often), index and count (2 bytes each, little-endian), compression id (1 byte), data length (2 bytes) and the data, which is cut short at the end of the input.
+///
+/// ## Example
+///
+/// ```
+/// let mut input = Vec::new();
+/// for index in 0..2u16 {
+///     input.push(7); // stream id
+///     input.extend_from_slice(&index.to_le_bytes());
+///     input.extend_from_slice(&2u16.to_le_bytes()); // count
+///     input.push(0); // no compression
+///     input.extend_from_slice(&5u16.to_le_bytes());
+///     input.extend_from_slice(b"hello");
+/// }
+/// assert_eq!(gmod::fuzz_targets::net_stream(&input), vec![b"hellohello".to_vec()]);
+/// ```
+pub fn net_stream(mut data: &[u8]) -> Vec<Vec<u8>> {
+    let mut take = |n: usize| -> Option<&[u8]> {
+        let n = n.min(data.len());
+        let (head, rest) = data.split_at(n);
+        data = rest;
+        (!head.is_empty()).then_some(head)
+    };
+
+    let mut streams: Vec<Pending> = Vec::new();
+    let mut completed = Vec::new();
+    while let Some(header) = take(8) {
+        let Ok(header) = <[u8; 8]>::try_from(header) else {
+            break;
+        };
+        let Ok(compression) = Compression::from_id(header[5]) else {
+            continue;
+        };
+        let len = u16::from_le_bytes([header[6], header[7]]) as usize;
+        let chunk = Chunk {
+            id: header[0] as u32,
+            index: u16::from_le_bytes([header[1], header[2]]) as usize,
+            count: u16::from_le_bytes([header[3], header[4]]) as usize,
+            compression,
+            data: take(len).unwrap_or_default().to_vec(),
+        };
+
+        if let Ok(Some(data)) = stream::reassemble(&mut streams, chunk, NET_STREAM_MAX_SIZE) {
+            if let Ok(data) = compression.decompress(data, NET_STREAM_MAX_SIZE) {
+                assert!(data.len() <= NET_STREAM_MAX_SIZE);
+                completed.push(data);
+            }
+        }
+    }
+    completed
+}
+
+/// Renders `data` as markdown with every extension enabled.
+#[cfg(feature = "markdown")]
+pub fn markdown(data: &[u8]) -> String {
+    let options = crate::markdown::MarkdownOptions {
+        tables: true,
+        strikethrough: true,
+        task_lists: true,
+        images: true,
+        links: true,
+    };
+    crate::markdown::to_html(&String::from_utf8_lossy(data), &options)
+}
+
+/// Runs `data` through the HTML sanitizer and the URL validator.
+#[cfg(feature = "sanitize")]
+pub fn sanitize(data: &[u8]) {
+    use crate::sanitize::{escape_html, sanitize_html, validate_url, UrlPolicy};
+
+    let text = String::from_utf8_lossy(data);
+    escape_html(&text);
+    sanitize_html(&text);
+    if let Ok(url) = validate_url(&text, &UrlPolicy::default()) {
+        // a validated URL must stay valid when parsed again
+        assert!(validate_url(url.as_str(), &UrlPolicy::default()).is_ok());
+    }
+}
+
+#[cfg(feature = "testing")]
+thread_local! {
+    static TEST_STATE: crate::testing::TestState =
+        crate::testing::TestState::new().expect("the Lua targets need a test state");
+}
+
+/// Runs `f` on this thread's test state, checking it leaves the stack as it found it.
+#[cfg(feature = "testing")]
+fn with_test_state<R>(f: impl FnOnce(crate::lua::State) -> R) -> R {
+    TEST_STATE.with(|test| {
+        let lua = test.lua();
+        lua.set_top(0);
+        let result = f(lua);
+        assert_eq!(lua.get_top(), 0, "the stack was left unbalanced");
+        result
+    })
+}
+
+/// Decodes `data` as a `codec` payload, with the default limits. Returns whether it decoded.
+#[cfg(feature = "testing")]
+pub fn codec_decode(data: &[u8]) -> bool {
+    use crate::codec::{self, Limits};
+
+    with_test_state(|lua| match codec::decode(lua, data, &Limits::default()) {
+        Ok(()) => {
+            assert_eq!(lua.get_top(), 1, "a decoded payload must push one value");
+            lua.pop();
+            true
+        }
+        Err(_) => false,
+    })
+}
+
+/// Parses `data` as JSON, pushes it as a Lua value, then converts that value back to JSON. Returns whether it parsed.
+#[cfg(all(feature = "testing", feature = "json"))]
+pub fn json(data: &[u8]) -> bool {
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(data) else {
+        return false;
+    };
+
+    with_test_state(|lua| {
+        if lua.push_json(&value).is_err() {
+            return true;
+        }
+        assert_eq!(lua.get_top(), 1, "a pushed JSON value must be one value");
+        let _ = lua.table_to_json(-1);
+        lua.pop();
+        true
+    })
+}
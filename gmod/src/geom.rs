@@ -0,0 +1,440 @@
+//! Geometry routines on `Vector`: ray casts against boxes and spheres, overlap tests, polygon containment, convex hulls and closest points.
+//!
+//! Boxes follow GMod's conventions: an AABB is a `mins`/`maxs` pair, and an OBB is an AABB in the local space of a position and an angle (like an entity's collision bounds).
+
+use anyhow::Result;
+
+use crate::{
+    lua::{self, HandleLuaFunctionReturn, LuaReg, State},
+    userdata::{Angle, Vector},
+};
+
+/// A ray (or segment, with `max_distance`) starting at `origin` and going along `dir`, which doesn't need to be normalized: distances are in multiples of its length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vector,
+    pub dir: Vector,
+}
+
+impl Ray {
+    pub fn new(origin: Vector, dir: Vector) -> Self {
+        Self { origin, dir }
+    }
+
+    /// A ray going from `start` to `end`, so hits are in `[0, 1]` when they are on the segment.
+    pub fn between(start: Vector, end: Vector) -> Self {
+        Self::new(start, end - start)
+    }
+
+    /// Returns the point at `t` along the ray.
+    pub fn at(&self, t: f32) -> Vector {
+        self.origin + self.dir * t
+    }
+
+    /// Returns the distance along the ray at which it enters the box, or `None` if it misses. Returns 0 if the ray starts inside the box.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use gmod::{geom::Ray, userdata::Vector};
+    ///
+    /// let ray = Ray::new(Vector::new(-10.0, 0.0, 0.0), Vector::new(1.0, 0.0, 0.0));
+    /// let hit = ray.intersect_aabb(Vector::new(-1.0, -1.0, -1.0), Vector::new(1.0, 1.0, 1.0));
+    /// assert_eq!(hit, Some(9.0));
+    /// ```
+    pub fn intersect_aabb(&self, mins: Vector, maxs: Vector) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::INFINITY;
+
+        let origin: [f32; 3] = self.origin.into();
+        let dir: [f32; 3] = self.dir.into();
+        let mins: [f32; 3] = mins.into();
+        let maxs: [f32; 3] = maxs.into();
+
+        for axis in 0..3 {
+            if dir[axis] == 0.0 {
+                if origin[axis] < mins[axis] || origin[axis] > maxs[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1.0 / dir[axis];
+            let mut t1 = (mins[axis] - origin[axis]) * inv;
+            let mut t2 = (maxs[axis] - origin[axis]) * inv;
+            if t1 > t2 {
+                std::mem::swap(&mut t1, &mut t2);
+            }
+            t_min = t_min.max(t1);
+            t_max = t_max.min(t2);
+            if t_min > t_max {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+
+    /// Same as `intersect_aabb`, for a box with bounds `mins`/`maxs` in the local space of `pos` and `ang`.
+    pub fn intersect_obb(
+        &self,
+        pos: Vector,
+        ang: Angle,
+        mins: Vector,
+        maxs: Vector,
+    ) -> Option<f32> {
+        Ray::new(
+            world_to_local(self.origin, pos, ang),
+            rotate_to_local(self.dir, ang),
+        )
+        .intersect_aabb(mins, maxs)
+    }
+
+    /// Returns the distance along the ray at which it enters the sphere, or `None` if it misses. Returns 0 if the ray starts inside the sphere.
+    pub fn intersect_sphere(&self, center: Vector, radius: f32) -> Option<f32> {
+        let offset = self.origin - center;
+        let a = self.dir.length_sqr();
+        let c = offset.length_sqr() - radius * radius;
+        if c <= 0.0 {
+            return Some(0.0);
+        }
+        if a == 0.0 {
+            return None;
+        }
+
+        let b = offset.dot(self.dir);
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let t = (-b - discriminant.sqrt()) / a;
+        (t >= 0.0).then_some(t)
+    }
+}
+
+/// Converts a direction from world space to the local space of `ang` (forward is +x, left is +y, up is +z).
+fn rotate_to_local(dir: Vector, ang: Angle) -> Vector {
+    Vector::new(
+        dir.dot(ang.forward()),
+        -dir.dot(ang.right()),
+        dir.dot(ang.up()),
+    )
+}
+
+/// Converts a position from world space to the local space of `pos` and `ang`, like `WorldToLocal`.
+pub fn world_to_local(point: Vector, pos: Vector, ang: Angle) -> Vector {
+    rotate_to_local(point - pos, ang)
+}
+
+/// Converts a position from the local space of `pos` and `ang` to world space, like `LocalToWorld`.
+pub fn local_to_world(point: Vector, pos: Vector, ang: Angle) -> Vector {
+    pos + ang.forward() * point.x - ang.right() * point.y + ang.up() * point.z
+}
+
+/// Returns whether two AABBs overlap (touching counts).
+pub fn aabb_intersects(mins_a: Vector, maxs_a: Vector, mins_b: Vector, maxs_b: Vector) -> bool {
+    mins_a.x <= maxs_b.x
+        && maxs_a.x >= mins_b.x
+        && mins_a.y <= maxs_b.y
+        && maxs_a.y >= mins_b.y
+        && mins_a.z <= maxs_b.z
+        && maxs_a.z >= mins_b.z
+}
+
+/// Returns whether a sphere overlaps an AABB.
+pub fn sphere_intersects_aabb(center: Vector, radius: f32, mins: Vector, maxs: Vector) -> bool {
+    closest_point_on_aabb(center, mins, maxs).distance_sqr(center) <= radius * radius
+}
+
+/// Returns the point of the AABB closest to `point` (`point` itself if it's inside).
+pub fn closest_point_on_aabb(point: Vector, mins: Vector, maxs: Vector) -> Vector {
+    Vector::new(
+        point.x.clamp(mins.x, maxs.x),
+        point.y.clamp(mins.y, maxs.y),
+        point.z.clamp(mins.z, maxs.z),
+    )
+}
+
+/// Returns the point of the OBB closest to `point`.
+pub fn closest_point_on_obb(
+    point: Vector,
+    pos: Vector,
+    ang: Angle,
+    mins: Vector,
+    maxs: Vector,
+) -> Vector {
+    let local = closest_point_on_aabb(world_to_local(point, pos, ang), mins, maxs);
+    local_to_world(local, pos, ang)
+}
+
+/// Returns the point of the segment from `a` to `b` closest to `point`.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::{geom::closest_point_on_segment, userdata::Vector};
+///
+/// let a = Vector::new(0.0, 0.0, 0.0);
+/// let b = Vector::new(10.0, 0.0, 0.0);
+/// assert_eq!(closest_point_on_segment(Vector::new(3.0, 5.0, 0.0), a, b), Vector::new(3.0, 0.0, 0.0));
+/// assert_eq!(closest_point_on_segment(Vector::new(-3.0, 5.0, 0.0), a, b), a);
+/// ```
+pub fn closest_point_on_segment(point: Vector, a: Vector, b: Vector) -> Vector {
+    let ab = b - a;
+    let length_sqr = ab.length_sqr();
+    if length_sqr == 0.0 {
+        return a;
+    }
+    let t = ((point - a).dot(ab) / length_sqr).clamp(0.0, 1.0);
+    a + ab * t
+}
+
+/// Returns whether `point` is inside the polygon, looking from above: only `x` and `y` are used, which is what zone checks want. The polygon can be concave, and its winding doesn't matter.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::{geom::point_in_polygon, userdata::Vector};
+///
+/// // an L shape
+/// let polygon = [
+///     Vector::new(0.0, 0.0, 0.0),
+///     Vector::new(10.0, 0.0, 0.0),
+///     Vector::new(10.0, 5.0, 0.0),
+///     Vector::new(5.0, 5.0, 0.0),
+///     Vector::new(5.0, 10.0, 0.0),
+///     Vector::new(0.0, 10.0, 0.0),
+/// ];
+/// assert!(point_in_polygon(Vector::new(2.0, 8.0, 100.0), &polygon));
+/// assert!(!point_in_polygon(Vector::new(8.0, 8.0, 0.0), &polygon));
+/// ```
+pub fn point_in_polygon(point: Vector, polygon: &[Vector]) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[j];
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < (b.x - a.x) * (point.y - a.y) / (b.y - a.y) + a.x
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Returns the convex hull of the points, looking from above (only `x` and `y` are used), in counter-clockwise order. Collinear points on the hull's edges are left out.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::{geom::convex_hull, userdata::Vector};
+///
+/// let points = [
+///     Vector::new(0.0, 0.0, 0.0),
+///     Vector::new(2.0, 0.0, 0.0),
+///     Vector::new(1.0, 1.0, 0.0), // inside
+///     Vector::new(2.0, 2.0, 0.0),
+///     Vector::new(0.0, 2.0, 0.0),
+/// ];
+/// assert_eq!(convex_hull(&points).len(), 4);
+/// ```
+pub fn convex_hull(points: &[Vector]) -> Vec<Vector> {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    points.dedup_by(|a, b| a.x == b.x && a.y == b.y);
+    if points.len() < 3 {
+        return points;
+    }
+
+    let cross =
+        |o: Vector, a: Vector, b: Vector| (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x);
+
+    // Andrew's monotone chain: the lower hull, then the upper hull
+    let mut hull: Vec<Vector> = Vec::with_capacity(points.len() * 2);
+    for pass in [
+        points.as_slice(),
+        &points.iter().rev().copied().collect::<Vec<_>>(),
+    ] {
+        let start = hull.len();
+        for &point in pass {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        // the last point is the first of the next pass
+        hull.pop();
+    }
+    hull
+}
+
+/// Registers the geometry functions into a global table named `libname`. Dotted names create nested tables, e.g. `c"mylib.Geom"`:
+///
+/// - `IntersectRayAABB(origin, dir, mins, maxs)`, `IntersectRayOBB(origin, dir, pos, ang, mins, maxs)` and `IntersectRaySphere(origin, dir, center, radius)` return the hit position and the distance along `dir` (in multiples of its length), or nil
+/// - `AABBIntersects(mins1, maxs1, mins2, maxs2)` and `SphereIntersectsAABB(center, radius, mins, maxs)`
+/// - `ClosestPointOnAABB(point, mins, maxs)`, `ClosestPointOnOBB(point, pos, ang, mins, maxs)` and `ClosestPointOnSegment(point, a, b)`
+/// - `PointInPolygon(point, polygon)` and `ConvexHull(points)`, where polygons and point sets are lists of vectors
+pub fn register(lua: State, libname: lua::LuaCStr) {
+    lua.register(
+        libname.as_ptr(),
+        crate::lua_regs![
+            "IntersectRayAABB" => lua_intersect_ray_aabb,
+            "IntersectRayOBB" => lua_intersect_ray_obb,
+            "IntersectRaySphere" => lua_intersect_ray_sphere,
+            "AABBIntersects" => lua_aabb_intersects,
+            "SphereIntersectsAABB" => lua_sphere_intersects_aabb,
+            "ClosestPointOnAABB" => lua_closest_point_on_aabb,
+            "ClosestPointOnOBB" => lua_closest_point_on_obb,
+            "ClosestPointOnSegment" => lua_closest_point_on_segment,
+            "PointInPolygon" => lua_point_in_polygon,
+            "ConvexHull" => lua_convex_hull,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+}
+
+fn push_hit(lua: State, ray: Ray, hit: Option<f32>) -> i32 {
+    match hit {
+        Some(t) => {
+            lua.push_vector(ray.at(t));
+            lua.push_number(t);
+            2
+        }
+        None => {
+            lua.push_nil();
+            1
+        }
+    }
+}
+
+fn check_vectors(lua: State, arg: i32) -> Result<Vec<Vector>> {
+    lua.check_table(arg)?;
+    let len = lua.len(arg);
+    let mut vectors = Vec::with_capacity(len.max(0) as usize);
+    for i in 1..=len {
+        lua.raw_geti(arg, i);
+        let vector = lua.get_vector(-1);
+        lua.pop();
+        match vector {
+            Some(vector) => vectors.push(vector),
+            None => anyhow::bail!(lua.err_argmsg(arg, &format!("element {i} is not a Vector"))),
+        }
+    }
+    Ok(vectors)
+}
+
+extern "C-unwind" fn lua_intersect_ray_aabb(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let ray = Ray::new(lua.check_vector(1)?, lua.check_vector(2)?);
+        let hit = ray.intersect_aabb(lua.check_vector(3)?, lua.check_vector(4)?);
+        Ok(push_hit(lua, ray, hit))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_intersect_ray_obb(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let ray = Ray::new(lua.check_vector(1)?, lua.check_vector(2)?);
+        let hit = ray.intersect_obb(
+            lua.check_vector(3)?,
+            lua.check_angle(4)?,
+            lua.check_vector(5)?,
+            lua.check_vector(6)?,
+        );
+        Ok(push_hit(lua, ray, hit))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_intersect_ray_sphere(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let ray = Ray::new(lua.check_vector(1)?, lua.check_vector(2)?);
+        let hit = ray.intersect_sphere(lua.check_vector(3)?, lua.check_number(4)? as f32);
+        Ok(push_hit(lua, ray, hit))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_aabb_intersects(lua: State) -> i32 {
+    (|| -> Result<(bool,)> {
+        Ok((aabb_intersects(
+            lua.check_vector(1)?,
+            lua.check_vector(2)?,
+            lua.check_vector(3)?,
+            lua.check_vector(4)?,
+        ),))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_sphere_intersects_aabb(lua: State) -> i32 {
+    (|| -> Result<(bool,)> {
+        Ok((sphere_intersects_aabb(
+            lua.check_vector(1)?,
+            lua.check_number(2)? as f32,
+            lua.check_vector(3)?,
+            lua.check_vector(4)?,
+        ),))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_closest_point_on_aabb(lua: State) -> i32 {
+    (|| -> Result<(Vector,)> {
+        Ok((closest_point_on_aabb(
+            lua.check_vector(1)?,
+            lua.check_vector(2)?,
+            lua.check_vector(3)?,
+        ),))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_closest_point_on_obb(lua: State) -> i32 {
+    (|| -> Result<(Vector,)> {
+        Ok((closest_point_on_obb(
+            lua.check_vector(1)?,
+            lua.check_vector(2)?,
+            lua.check_angle(3)?,
+            lua.check_vector(4)?,
+            lua.check_vector(5)?,
+        ),))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_closest_point_on_segment(lua: State) -> i32 {
+    (|| -> Result<(Vector,)> {
+        Ok((closest_point_on_segment(
+            lua.check_vector(1)?,
+            lua.check_vector(2)?,
+            lua.check_vector(3)?,
+        ),))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_point_in_polygon(lua: State) -> i32 {
+    (|| -> Result<(bool,)> {
+        let point = lua.check_vector(1)?;
+        let polygon = check_vectors(lua, 2)?;
+        Ok((point_in_polygon(point, &polygon),))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_convex_hull(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let hull = convex_hull(&check_vectors(lua, 1)?);
+        lua.create_table(hull.len() as i32, 0);
+        for (i, point) in hull.into_iter().enumerate() {
+            lua.push_vector(point);
+            lua.raw_seti(-2, i as i32 + 1);
+        }
+        Ok(1)
+    })()
+    .handle_result(lua)
+}
@@ -0,0 +1,132 @@
+use std::{iter::repeat_with, sync::Mutex};
+
+use crate::lua::{CoroutinePool, LuaFunction, PooledCall, State};
+
+/// Hooks added through this module, removed automatically by `#[gmod13_close]`.
+static HOOKS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// The pool `call` runs `hook.Run` in, if any.
+static POOL: Mutex<Option<&'static CoroutinePool>> = Mutex::new(None);
+
+fn track(event: &str, identifier: &str) {
+    let mut hooks = HOOKS.lock().unwrap_or_else(|e| e.into_inner());
+    if !hooks.iter().any(|(e, i)| e == event && i == identifier) {
+        hooks.push((event.to_string(), identifier.to_string()));
+    }
+}
+
+fn untrack(event: &str, identifier: &str) {
+    HOOKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|(e, i)| e != event || i != identifier);
+}
+
+/// Adds a hook with `hook.Add(event, identifier, func)`.
+///
+/// The hook is removed automatically when the module is closed.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[lua_function]
+/// fn think(lua: gmod::lua::State) {
+///     // ...
+/// }
+///
+/// gmod::hook::add(lua, "Think", "MyAddon", think);
+/// ```
+pub fn add(lua: State, event: &str, identifier: &str, func: LuaFunction) {
+    lua.get_global(c"hook");
+    lua.get_field(-1, c"Add");
+    lua.push_string(event);
+    lua.push_string(identifier);
+    lua.push_function(func);
+    if lua.pcall_ignore(3, 0) {
+        track(event, identifier);
+    }
+    lua.pop();
+}
+
+/// Same as `add`, but generates a unique identifier for the hook, which is returned.
+pub fn add_unique(lua: State, event: &str, func: LuaFunction) -> String {
+    let random_str: String = repeat_with(fastrand::alphanumeric).take(10).collect();
+    let identifier = format!("gmod_rs_{event}_{random_str}");
+    add(lua, event, &identifier, func);
+    identifier
+}
+
+/// Removes a hook with `hook.Remove(event, identifier)`.
+pub fn remove(lua: State, event: &str, identifier: &str) {
+    untrack(event, identifier);
+
+    lua.get_global(c"hook");
+    lua.get_field(-1, c"Remove");
+    lua.push_string(event);
+    lua.push_string(identifier);
+    lua.pcall_ignore(2, 0);
+    lua.pop();
+}
+
+/// Makes `call` run `hook.Run` inside a coroutine from `pool`, or directly again if `None`.
+///
+/// A hook that yields then no longer unwinds the calling stack; `call` pushes `nil` for its results instead.
+///
+/// ## Example
+///
+/// ```ignore
+/// static POOL: CoroutinePool = CoroutinePool::new(8);
+///
+/// gmod::hook::set_coroutine_pool(Some(&POOL));
+/// ```
+pub fn set_coroutine_pool(pool: Option<&'static CoroutinePool>) {
+    *POOL.lock().unwrap_or_else(|e| e.into_inner()) = pool;
+}
+
+/// Runs a hook with `hook.Run(event, ...)`.
+///
+/// Like `pcall`, the `nargs` arguments must be pushed onto the stack beforehand, and they are popped. On success, `nresults` results are pushed onto the stack.
+///
+/// Returns whether the execution was successful.
+pub fn call(lua: State, event: &str, nargs: i32, nresults: i32) -> bool {
+    lua.get_global(c"hook");
+    lua.get_field(-1, c"Run");
+    unsafe { lua.remove(-2) };
+    lua.push_string(event);
+
+    // move hook.Run and the event name below the arguments
+    lua.insert(-(nargs + 2));
+    lua.insert(-(nargs + 2));
+
+    let pool = *POOL.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(pool) = pool else {
+        return lua.pcall_ignore(nargs + 1, nresults);
+    };
+
+    match pool.call(lua, nargs + 1, nresults) {
+        Ok(PooledCall::Returned(_)) => true,
+        Ok(PooledCall::Yielded) => {
+            for _ in 0..nresults {
+                lua.push_nil();
+            }
+            true
+        }
+        Err(err) => {
+            lua.error_no_halt(&err.to_string(), None);
+            false
+        }
+    }
+}
+
+/// Returns every hook added through this module that hasn't been removed yet, as `(event, identifier)` pairs.
+pub fn added() -> Vec<(String, String)> {
+    HOOKS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Removes every hook added through this module. This is called for you by `#[gmod13_close]`.
+pub fn unload(lua: State) {
+    let hooks = std::mem::take(&mut *HOOKS.lock().unwrap_or_else(|e| e.into_inner()));
+    for (event, identifier) in hooks {
+        remove(lua, &event, &identifier);
+    }
+}
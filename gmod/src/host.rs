@@ -0,0 +1,87 @@
+//! Service manager integration for dedicated servers.
+//!
+//! On Linux this speaks the systemd `sd_notify` protocol over `$NOTIFY_SOCKET`, so a unit with `Type=notify` and `WatchdogSec=` can detect a hung srcds. On other platforms (and when not started by a service manager) every function is a no-op returning `Ok(false)`.
+//!
+//! On Windows, run srcds as a service through a wrapper such as [NSSM](https://nssm.cc) or [WinSW](https://github.com/winsw/winsw), which talks to the service control manager itself. A module can't: the service control manager expects that from the process within about 30 seconds of it starting, long before modules are loaded.
+
+use std::{io, time::Duration};
+
+use crate::{
+    lua::State,
+    timer::{self, TimerHandle},
+};
+
+/// Sends a raw notification (e.g. `"READY=1"`) to the service manager.
+///
+/// Returns whether a service manager was listening.
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) -> io::Result<bool> {
+    use std::os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    };
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+
+    let addr = match path.as_encoded_bytes() {
+        [b'@', name @ ..] => SocketAddr::from_abstract_name(name)?,
+        _ => SocketAddr::from_pathname(&path)?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+/// Sends a raw notification (e.g. `"READY=1"`) to the service manager.
+///
+/// Returns whether a service manager was listening.
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Tells the service manager that the server has finished starting up.
+pub fn notify_ready() -> io::Result<bool> {
+    notify("READY=1")
+}
+
+/// Tells the service manager that the server is shutting down.
+pub fn notify_stopping() -> io::Result<bool> {
+    notify("STOPPING=1")
+}
+
+/// Sets the free-form status shown by the service manager (e.g. in `systemctl status`).
+pub fn notify_status(status: &str) -> io::Result<bool> {
+    notify(&format!("STATUS={}", status.replace('\n', " ")))
+}
+
+/// Sends a single watchdog keep-alive to the service manager.
+pub fn notify_watchdog() -> io::Result<bool> {
+    notify("WATCHDOG=1")
+}
+
+/// Returns the watchdog timeout the service manager expects keep-alives within, if it enabled one for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Starts sending watchdog keep-alives from the Lua tick at half the watchdog interval, so a hung server stops sending them.
+///
+/// Returns `None` if the service manager didn't enable a watchdog. The keep-alives stop when the returned handle is dropped or the module is closed.
+pub fn start_watchdog(lua: State) -> Option<TimerHandle> {
+    let interval = watchdog_interval()? / 2;
+    Some(timer::create(lua, interval, 0, |_| {
+        if let Err(err) = notify_watchdog() {
+            eprintln!("[gmod-rs] Failed to notify watchdog: {err}");
+        }
+    }))
+}
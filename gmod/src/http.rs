@@ -0,0 +1,351 @@
+//! HTTP requests through the game's own `HTTP` function, as futures.
+//!
+//! Requests made this way go through the engine's HTTP stack, so they follow the server's settings (e.g. the `-allowlocalhttp` flag and the HTTP whitelist of clients) without pulling a native HTTP client into the module. They're started on the main thread, and the returned future resolves when the game calls back, a few ticks later at best: it can be awaited from any thread or executor.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::http::{self, Request};
+//!
+//! let response = http::fetch(lua, Request::get("https://api.example.com/status").header("Accept", "application/json"));
+//! std::thread::spawn(move || {
+//!     let response = pollster::block_on(response)?;
+//!     println!("{}: {}", response.status, response.text()?);
+//!     anyhow::Ok(())
+//! });
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    lua::{task_queue, LuaReference, State},
+    realm::Realm,
+};
+
+/// Makes the callbacks given to `HTTP`, which only call back into the module while `dispatch.done` is set, so that requests finishing after `gmod13_close` don't call into an unloaded library.
+const CALLBACKS: &str = r#"
+local dispatch, id = ...
+return function(code, body, headers)
+    local done = dispatch.done
+    if done then done(id, code, body, headers) end
+end, function(reason)
+    local done = dispatch.done
+    if done then done(id, nil, reason) end
+end
+"#;
+
+thread_local! {
+    /// The requests waiting for the game to call back, by id, with the realm they were made in.
+    static PENDING: RefCell<HashMap<u64, (Realm, Arc<Slot>)>> = RefCell::new(HashMap::new());
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+    /// The `dispatch` table of `CALLBACKS` of each realm, with the loaded chunk as `make`.
+    static DISPATCH: Cell<[Option<LuaReference>; 3]> = const { Cell::new([None; 3]) };
+}
+
+/// An HTTP request, built like `Request::post(url).header(...).body(...)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    /// Form parameters, sent in the query string for `GET` and `HEAD`, and in the body otherwise. Ignored if there's a body.
+    pub parameters: Vec<(String, String)>,
+    /// The content type and the body.
+    pub body: Option<(String, Vec<u8>)>,
+    /// How long the game waits for a response, 60 seconds by default.
+    pub timeout: Option<Duration>,
+}
+
+impl Request {
+    pub fn new(method: &str, url: &str) -> Self {
+        Self {
+            method: method.to_owned(),
+            url: url.to_owned(),
+            headers: Vec::new(),
+            parameters: Vec::new(),
+            body: None,
+            timeout: None,
+        }
+    }
+
+    pub fn get(url: &str) -> Self {
+        Self::new("GET", url)
+    }
+
+    pub fn post(url: &str) -> Self {
+        Self::new("POST", url)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn param(mut self, name: &str, value: &str) -> Self {
+        self.parameters.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Some((content_type.to_owned(), body.into()));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// The response to a request, whatever its status code.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Returns whether the status code is 2xx.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Returns the value of a header, whatever its case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the body as UTF-8.
+    pub fn text(&self) -> Result<&str> {
+        std::str::from_utf8(&self.body).map_err(|_| anyhow!("the response body isn't valid UTF-8"))
+    }
+}
+
+#[derive(Default)]
+struct SlotState {
+    result: Option<Result<Response>>,
+    waker: Option<Waker>,
+}
+
+struct Slot {
+    url: String,
+    state: Mutex<SlotState>,
+}
+
+impl Slot {
+    fn complete(&self, result: Result<Response>) {
+        let waker = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.result = Some(result);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// The response to a request started with `fetch`. The request goes on if this is dropped, but its response is thrown away.
+#[must_use = "the request is sent either way, but its response is lost"]
+pub struct Fetch(Arc<Slot>);
+
+impl Future for Fetch {
+    type Output = Result<Response>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Sends a request with `HTTP`. Must be called on the main thread; the returned future can be awaited anywhere.
+///
+/// The future fails if the request couldn't be made (unreachable host, timeout, refused by the game...), or if the module is unloaded before the response came, but not on error status codes.
+pub fn fetch(lua: State, request: Request) -> Fetch {
+    let slot = Arc::new(Slot {
+        url: request.url.clone(),
+        state: Mutex::new(SlotState::default()),
+    });
+    let top = lua.get_top();
+    if let Err(err) = send(lua, &request, &slot) {
+        slot.complete(Err(anyhow!(
+            "HTTP request to {} failed: {err}",
+            request.url
+        )));
+    }
+    lua.set_top(top);
+    Fetch(slot)
+}
+
+fn send(lua: State, request: &Request, slot: &Arc<Slot>) -> Result<()> {
+    lua.ensure_stack(8)?;
+    lua.get_global(c"HTTP");
+    if !lua.is_function(-1) {
+        bail!("HTTP isn't available");
+    }
+
+    let id = NEXT_ID.get();
+    NEXT_ID.set(id + 1);
+    push_dispatch(lua)?;
+    lua.get_field(-1, c"make");
+    lua.push_value(-2);
+    lua.push_number(id as f64);
+    lua.pcall(2, 2, 0)?;
+
+    lua.create_table(0, 9);
+    lua.push_value(-3);
+    lua.set_field(-2, c"success");
+    lua.push_value(-2);
+    lua.set_field(-2, c"failed");
+    lua.push_string(&request.url);
+    lua.set_field(-2, c"url");
+    lua.push_string(&request.method);
+    lua.set_field(-2, c"method");
+    push_string_map(lua, &request.headers);
+    lua.set_field(-2, c"headers");
+    push_string_map(lua, &request.parameters);
+    lua.set_field(-2, c"parameters");
+    if let Some((content_type, body)) = &request.body {
+        lua.push_string(content_type);
+        lua.set_field(-2, c"type");
+        lua.push_binary_string(body);
+        lua.set_field(-2, c"body");
+    }
+    if let Some(timeout) = request.timeout {
+        lua.push_number(timeout.as_secs_f64());
+        lua.set_field(-2, c"timeout");
+    }
+
+    // HTTP(table) is below the dispatch table and the callbacks
+    unsafe {
+        lua.remove(-2);
+        lua.remove(-2);
+        lua.remove(-2);
+    }
+    let realm = task_queue::realm_of(lua);
+    PENDING.with_borrow_mut(|pending| pending.insert(id, (realm, slot.clone())));
+    let sent = lua.pcall(1, 1, 0).map(|()| lua.get_boolean(-1));
+    if !matches!(sent, Ok(true)) {
+        PENDING.with_borrow_mut(|pending| pending.remove(&id));
+    }
+    match sent? {
+        true => Ok(()),
+        false => bail!("HTTP refused it (it can't be used before the first tick)"),
+    }
+}
+
+/// Pushes the `dispatch` table of the realm of `lua`, creating it on the first request.
+fn push_dispatch(lua: State) -> Result<()> {
+    let realm = task_queue::realm_of(lua) as usize;
+    let mut dispatches = DISPATCH.get();
+    if let Some(dispatch) = dispatches[realm] {
+        lua.from_reference(dispatch);
+        return Ok(());
+    }
+    lua.create_table(0, 2);
+    unsafe { lua.load_buffer(CALLBACKS.as_bytes(), c"=gmod::http")? };
+    lua.set_field(-2, c"make");
+    lua.push_function(done);
+    lua.set_field(-2, c"done");
+    lua.push_value(-1);
+    dispatches[realm] = Some(lua.reference());
+    DISPATCH.set(dispatches);
+    Ok(())
+}
+
+fn push_string_map(lua: State, pairs: &[(String, String)]) {
+    lua.create_table(0, pairs.len() as i32);
+    for (key, value) in pairs {
+        lua.push_string(key);
+        lua.push_string(value);
+        lua.set_table(-3);
+    }
+}
+
+/// `dispatch.done(id, code, body, headers)` on success, `dispatch.done(id, nil, reason)` on failure.
+extern "C-unwind" fn done(lua: State) -> i32 {
+    let id = lua.to_number(1) as u64;
+    let Some((_, slot)) = PENDING.with_borrow_mut(|pending| pending.remove(&id)) else {
+        return 0;
+    };
+
+    if lua.is_nil(2) {
+        let reason = lua
+            .get_string(3)
+            .map_or_else(|| "unknown".into(), |reason| reason.into_owned());
+        slot.complete(Err(anyhow!(
+            "HTTP request to {} failed: {reason}",
+            slot.url
+        )));
+        return 0;
+    }
+
+    let mut response = Response {
+        status: lua.to_number(2) as u16,
+        body: lua
+            .get_binary_string(3)
+            .map(<[u8]>::to_vec)
+            .unwrap_or_default(),
+        headers: HashMap::new(),
+    };
+    if lua.is_table(4) {
+        lua.push_nil();
+        while unsafe { lua.next(4) } != 0 {
+            if let (Some(name), Some(value)) = (lua.get_string(-2), lua.get_string(-1)) {
+                response
+                    .headers
+                    .insert(name.into_owned(), value.into_owned());
+            }
+            lua.pop();
+        }
+    }
+    slot.complete(Ok(response));
+    0
+}
+
+/// Fails the requests made in the realm of `lua` that are still waiting for a response, and stops the game from calling back once they finish. This is called for you by `#[gmod13_close]`.
+pub fn unload(lua: State) {
+    let realm = task_queue::realm_of(lua);
+    let mut dispatches = DISPATCH.get();
+    let dispatch = dispatches[realm as usize].take();
+    DISPATCH.set(dispatches);
+    if let Some(dispatch) = dispatch {
+        lua.from_reference(dispatch);
+        lua.push_nil();
+        lua.set_field(-2, c"done");
+        lua.pop();
+        lua.dereference(dispatch);
+    }
+    let pending: Vec<Arc<Slot>> = PENDING.with_borrow_mut(|pending| {
+        pending
+            .extract_if(|_, (r, _)| *r == realm)
+            .map(|(_, (_, slot))| slot)
+            .collect()
+    });
+    for slot in pending {
+        slot.complete(Err(anyhow!(
+            "HTTP request to {} failed: the module was unloaded",
+            slot.url
+        )));
+    }
+}
@@ -0,0 +1,191 @@
+//! Typed data shared between Rust binary modules loaded into the same Lua state, for module suites that need to cooperate.
+//!
+//! Each module has its own copy of every static, so they can't share data through Rust alone. A published value is stored behind a lightuserdata in the Lua registry, prefixed by a versioned header that lets `get` check that the value was published by a compatible gmod-rs version and has the expected type.
+//!
+//! ## What can be shared
+//!
+//! The value is read directly by modules compiled separately, so its type must have the same layout and behaviour in all of them:
+//!
+//! - mark it `#[repr(C)]` and define it in a crate that every module depends on
+//! - build every module with the same compiler version, as types are matched by their `std::any::type_name`, size and alignment
+//! - stick to plain data, atomics and locks: anything pointing into a module's code (trait objects, function pointers, closures) dangles once that module unloads
+//!
+//! Published values are never freed, as another module may still hold a reference. Unpublishing or republishing a key leaks the previous value.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! // in a crate shared by the whole suite
+//! #[repr(C)]
+//! pub struct Economy {
+//!     pub tax_rate: AtomicU32,
+//! }
+//!
+//! // in the module that owns the data
+//! interop::publish(lua, "suite.economy", Economy { tax_rate: AtomicU32::new(5) });
+//!
+//! // in any other module of the suite
+//! let economy = interop::get::<Economy>(lua, "suite.economy")?;
+//! economy.tax_rate.load(Ordering::Relaxed);
+//! ```
+
+use std::{ffi::c_void, fmt};
+
+use crate::lua::State;
+
+/// Registry field holding the table of published values.
+const REGISTRY_KEY: crate::lua::LuaCStr = c"gmod-rs.interop";
+
+/// Identifies values published by gmod-rs, `"GMODRSIO"` in ASCII.
+const MAGIC: u64 = u64::from_be_bytes(*b"GMODRSIO");
+
+/// Version of `Header` and of the publishing scheme. Bumped whenever either changes incompatibly.
+pub const ABI_VERSION: u32 = 1;
+
+/// Prefix of every published value. `magic` and `abi_version` must stay first in every ABI version, so mismatches can be detected.
+#[repr(C)]
+struct Header {
+    magic: u64,
+    abi_version: u32,
+    _reserved: u32,
+    type_hash: u64,
+    size: usize,
+    align: usize,
+}
+
+#[repr(C)]
+struct Entry<T> {
+    header: Header,
+    value: T,
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is guaranteed to give the same result in every module.
+fn type_hash<T>() -> u64 {
+    std::any::type_name::<T>()
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Why `get` couldn't return a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InteropError {
+    /// Nothing was published under the key.
+    NotFound(String),
+    /// The value wasn't published by gmod-rs, or by a version with a different ABI.
+    AbiMismatch { key: String, found: Option<u32> },
+    /// The value was published with a different type, or the same type with a different layout.
+    TypeMismatch { key: String, expected: &'static str },
+}
+
+impl fmt::Display for InteropError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InteropError::NotFound(key) => write!(f, "nothing is published under {key:?}"),
+            InteropError::AbiMismatch {
+                key,
+                found: Some(found),
+            } => write!(
+                f,
+                "{key:?} was published with interop ABI version {found}, expected {ABI_VERSION}"
+            ),
+            InteropError::AbiMismatch { key, found: None } => {
+                write!(f, "{key:?} wasn't published by gmod::interop")
+            }
+            InteropError::TypeMismatch { key, expected } => {
+                write!(f, "{key:?} wasn't published as a {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InteropError {}
+
+/// Pushes the table of published values, creating it if needed.
+fn push_table(lua: State) {
+    lua.get_field(crate::lua::LUA_REGISTRYINDEX, REGISTRY_KEY);
+    if !lua.is_table(-1) {
+        lua.pop();
+        lua.new_table();
+        lua.push_value(-1);
+        lua.set_field(crate::lua::LUA_REGISTRYINDEX, REGISTRY_KEY);
+    }
+}
+
+/// Publishes `value` under `key`, replacing (and leaking) any value published under it before. Returns a reference to the published value.
+pub fn publish<T: Send + Sync + 'static>(lua: State, key: &str, value: T) -> &'static T {
+    let entry: &'static Entry<T> = Box::leak(Box::new(Entry {
+        header: Header {
+            magic: MAGIC,
+            abi_version: ABI_VERSION,
+            _reserved: 0,
+            type_hash: type_hash::<T>(),
+            size: std::mem::size_of::<T>(),
+            align: std::mem::align_of::<T>(),
+        },
+        value,
+    }));
+
+    push_table(lua);
+    lua.push_string(key);
+    lua.push_lightuserdata(entry as *const Entry<T> as *mut c_void);
+    lua.set_table(-3);
+    lua.pop();
+
+    &entry.value
+}
+
+/// Removes the value published under `key`, returning whether there was one. The value itself is leaked, as other modules may still reference it.
+pub fn unpublish(lua: State, key: &str) -> bool {
+    let _guard = lua.guard();
+    push_table(lua);
+    lua.push_string(key);
+    lua.get_table(-2);
+    let found = !lua.is_nil(-1);
+    lua.pop();
+    lua.push_string(key);
+    lua.push_nil();
+    lua.set_table(-3);
+    found
+}
+
+/// Returns the value published under `key`, checking that it was published as a `T` by a compatible gmod-rs version.
+pub fn get<T: Send + Sync + 'static>(lua: State, key: &str) -> Result<&'static T, InteropError> {
+    let ptr = {
+        let _guard = lua.guard();
+        push_table(lua);
+        lua.push_string(key);
+        lua.get_table(-2);
+        if lua.lua_type(-1) != crate::lua::LUA_TLIGHTUSERDATA {
+            return Err(InteropError::NotFound(key.to_owned()));
+        }
+        lua.to_userdata(-1) as *const Header
+    };
+
+    let header = unsafe { &*ptr };
+    if header.magic != MAGIC {
+        return Err(InteropError::AbiMismatch {
+            key: key.to_owned(),
+            found: None,
+        });
+    }
+    if header.abi_version != ABI_VERSION {
+        return Err(InteropError::AbiMismatch {
+            key: key.to_owned(),
+            found: Some(header.abi_version),
+        });
+    }
+    if header.type_hash != type_hash::<T>()
+        || header.size != std::mem::size_of::<T>()
+        || header.align != std::mem::align_of::<T>()
+    {
+        return Err(InteropError::TypeMismatch {
+            key: key.to_owned(),
+            expected: std::any::type_name::<T>(),
+        });
+    }
+
+    // the header matches, so this is the `Entry<T>` leaked by `publish`
+    Ok(unsafe { &(*(ptr as *const Entry<T>)).value })
+}
@@ -0,0 +1,819 @@
+//! Local IPC endpoint for sidecar processes (bots, web panels, ...).
+//!
+//! The server listens on a unix socket on Linux and on a named pipe on Windows, so no TCP port has to be opened. Messages are JSON values, framed either one per line or with a 4-byte little-endian length prefix. They are delivered to a Rust callback and/or a Lua hook on the Lua tick.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde_json::Value;
+
+use crate::{
+    hook,
+    lua::{task_queue, State},
+};
+
+/// Largest message accepted, to protect against garbage length prefixes and peers that never end their line.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// How messages are delimited on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// One JSON value per line (`\n`-terminated).
+    #[default]
+    Lines,
+
+    /// Each JSON value is preceded by its length in bytes, as a little-endian `u32`.
+    LengthPrefixed,
+}
+
+impl Framing {
+    fn read(self, reader: &mut BufReader<sys::Stream>) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Framing::Lines => {
+                let mut line = Vec::new();
+                // one more byte for the newline
+                let limit = MAX_MESSAGE_SIZE as u64 + 1;
+                if reader.by_ref().take(limit).read_until(b'\n', &mut line)? == 0 {
+                    return Ok(None);
+                }
+                if line.len() as u64 == limit && line.last() != Some(&b'\n') {
+                    return Err(too_large(line.len()));
+                }
+                Ok(Some(line))
+            }
+
+            Framing::LengthPrefixed => {
+                let mut len = [0u8; 4];
+                match reader.read_exact(&mut len) {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(err) => return Err(err),
+                }
+
+                let len = u32::from_le_bytes(len) as usize;
+                if len > MAX_MESSAGE_SIZE {
+                    return Err(too_large(len));
+                }
+
+                let mut message = vec![0; len];
+                reader.read_exact(&mut message)?;
+                Ok(Some(message))
+            }
+        }
+    }
+
+    fn encode(self, message: &Value) -> Vec<u8> {
+        let json = message.to_string();
+        match self {
+            Framing::Lines => {
+                let mut buf = json.into_bytes();
+                buf.push(b'\n');
+                buf
+            }
+
+            Framing::LengthPrefixed => {
+                let mut buf = Vec::with_capacity(json.len() + 4);
+                buf.extend_from_slice(&(json.len() as u32).to_le_bytes());
+                buf.extend_from_slice(json.as_bytes());
+                buf
+            }
+        }
+    }
+}
+
+fn too_large(len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("message of {len} bytes exceeds the limit of {MAX_MESSAGE_SIZE} bytes"),
+    )
+}
+
+struct Connection {
+    id: u64,
+    stream: Mutex<sys::Stream>,
+}
+
+/// A client connected to an IPC server. Can be sent to other threads to reply later.
+#[derive(Clone)]
+pub struct Peer {
+    connection: Arc<Connection>,
+    framing: Framing,
+}
+
+impl Peer {
+    /// Returns the identifier of this connection, unique for the lifetime of the module.
+    pub fn id(&self) -> u64 {
+        self.connection.id
+    }
+
+    /// Sends a message to this client.
+    pub fn send(&self, message: &Value) -> io::Result<()> {
+        let buf = self.framing.encode(message);
+        let mut stream = self
+            .connection
+            .stream
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        stream.write_all(&buf)?;
+        stream.flush()
+    }
+}
+
+impl std::fmt::Debug for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Peer").field("id", &self.id()).finish()
+    }
+}
+
+type Handler = Arc<Mutex<Box<dyn FnMut(State, Peer, Value) + Send>>>;
+
+struct Server {
+    name: String,
+    framing: Framing,
+    listener: sys::Listener,
+    stopped: AtomicBool,
+    connections: Mutex<Vec<Arc<Connection>>>,
+}
+
+impl Server {
+    fn stop(&self) {
+        if self.stopped.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.listener.wake();
+        for connection in
+            std::mem::take(&mut *self.connections.lock().unwrap_or_else(|e| e.into_inner()))
+        {
+            sys::close(&connection.stream.lock().unwrap_or_else(|e| e.into_inner()));
+        }
+    }
+}
+
+/// IPC servers that are still running, stopped by `unload`.
+static SERVERS: Mutex<Vec<Arc<Server>>> = Mutex::new(Vec::new());
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Builder for an IPC server, created with `listen`.
+#[must_use = "the server is only started by calling `start`"]
+pub struct Builder {
+    name: String,
+    framing: Framing,
+    handler: Option<Handler>,
+    hook: Option<String>,
+}
+
+/// Prepares an IPC server.
+///
+/// On Linux, `name` is the path of the unix socket, or a file name placed in the temporary directory if it contains no `/`. The socket can only be connected to by the user running the game (its mode is `0600`). A file already at that path is only replaced if it's a socket no one listens on anymore.
+///
+/// On Windows, it is the name of the pipe, created as `\\.\pipe\<name>`. Only local processes of the same user (and administrators) can connect.
+///
+/// ## Example
+///
+/// ```ignore
+/// let server = gmod::ipc::listen("my_addon")
+///     .framing(Framing::Lines)
+///     .on_message(|lua, peer, message| {
+///         if message["type"] == "ping" {
+///             let _ = peer.send(&serde_json::json!({ "type": "pong" }));
+///         }
+///     })
+///     .hook("MyAddonIPC") // hook.Add("MyAddonIPC", "...", function(peerId, message) end)
+///     .start()?;
+/// ```
+pub fn listen(name: &str) -> Builder {
+    Builder {
+        name: name.to_string(),
+        framing: Framing::default(),
+        handler: None,
+        hook: None,
+    }
+}
+
+impl Builder {
+    /// Sets how messages are delimited. Defaults to `Framing::Lines`.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Calls `f` on the Lua thread for every message received.
+    pub fn on_message<F: FnMut(State, Peer, Value) + Send + 'static>(mut self, f: F) -> Self {
+        self.handler = Some(Arc::new(Mutex::new(Box::new(f))));
+        self
+    }
+
+    /// Runs `hook.Run(event, peerId, message)` for every message received, with the message converted by `util.JSONToTable`.
+    ///
+    /// Messages that are not JSON objects or arrays are wrapped in a `{ value = ... }` table first.
+    pub fn hook(mut self, event: &str) -> Self {
+        self.hook = Some(event.to_string());
+        self
+    }
+
+    /// Starts listening on a background thread.
+    pub fn start(self) -> io::Result<IpcHandle> {
+        let server = Arc::new(Server {
+            listener: sys::Listener::bind(&self.name)?,
+            name: self.name,
+            framing: self.framing,
+            stopped: AtomicBool::new(false),
+            connections: Mutex::new(Vec::new()),
+        });
+        SERVERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(server.clone());
+
+        let handler = self.handler;
+        let hook = self.hook.map(Arc::new);
+        let accepting = server.clone();
+        std::thread::spawn(move || loop {
+            let stream = accepting.listener.accept();
+            if accepting.stopped.load(Ordering::Acquire) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!(
+                        "[gmod-rs] IPC server {:?} failed to accept a connection: {err}",
+                        accepting.name
+                    );
+                    continue;
+                }
+            };
+
+            let reader = match sys::try_clone(&stream) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    eprintln!(
+                        "[gmod-rs] IPC server {:?} failed to accept a connection: {err}",
+                        accepting.name
+                    );
+                    continue;
+                }
+            };
+
+            let connection = Arc::new(Connection {
+                id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+                stream: Mutex::new(stream),
+            });
+            accepting
+                .connections
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(connection.clone());
+
+            let server = accepting.clone();
+            let handler = handler.clone();
+            let hook = hook.clone();
+            std::thread::spawn(move || {
+                let peer = Peer {
+                    connection,
+                    framing: server.framing,
+                };
+                serve(&server, reader, &peer, handler, hook);
+                server
+                    .connections
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .retain(|c| !Arc::ptr_eq(c, &peer.connection));
+            });
+        });
+
+        Ok(IpcHandle { server })
+    }
+}
+
+fn serve(
+    server: &Server,
+    reader: sys::Stream,
+    peer: &Peer,
+    handler: Option<Handler>,
+    hook: Option<Arc<String>>,
+) {
+    let mut reader = BufReader::new(reader);
+    while !server.stopped.load(Ordering::Acquire) {
+        let message = match peer.framing.read(&mut reader) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(err) => {
+                if !server.stopped.load(Ordering::Acquire) {
+                    eprintln!(
+                        "[gmod-rs] IPC connection {} on {:?} closed: {err}",
+                        peer.id(),
+                        server.name
+                    );
+                }
+                break;
+            }
+        };
+
+        if message.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+
+        let message: Value = match serde_json::from_slice(&message) {
+            Ok(message) => message,
+            Err(err) => {
+                eprintln!(
+                    "[gmod-rs] IPC connection {} on {:?} sent invalid JSON: {err}",
+                    peer.id(),
+                    server.name
+                );
+                continue;
+            }
+        };
+
+        let peer = peer.clone();
+        let handler = handler.clone();
+        let hook = hook.clone();
+        let _ = task_queue::wait_lua_tick(String::new(), move |l| {
+            if let Some(hook) = hook {
+                run_hook(l, &hook, &peer, &message);
+            }
+            if let Some(handler) = handler {
+                (handler.lock().unwrap_or_else(|e| e.into_inner()))(l, peer, message);
+            }
+        });
+    }
+}
+
+fn run_hook(lua: State, event: &str, peer: &Peer, message: &Value) {
+    let json = match message {
+        Value::Object(_) | Value::Array(_) => message.to_string(),
+        _ => serde_json::json!({ "value": message }).to_string(),
+    };
+
+    lua.push_number(peer.id() as f64);
+    lua.get_global(c"util");
+    lua.get_field(-1, c"JSONToTable");
+    unsafe { lua.remove(-2) };
+    lua.push_string(&json);
+    if !lua.pcall_ignore(1, 1) {
+        lua.push_nil();
+    }
+    hook::call(lua, event, 2, 0);
+}
+
+/// A handle to a running IPC server. The server is stopped when the handle is dropped, or when the module is closed.
+#[must_use = "the server is stopped as soon as the handle is dropped"]
+pub struct IpcHandle {
+    server: Arc<Server>,
+}
+
+impl IpcHandle {
+    /// Returns the name the server was started with.
+    pub fn name(&self) -> &str {
+        &self.server.name
+    }
+
+    /// Returns the clients currently connected.
+    pub fn peers(&self) -> Vec<u64> {
+        self.server
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|c| c.id)
+            .collect()
+    }
+
+    /// Sends a message to every connected client, framed like the server's messages. Returns how many clients it was sent to.
+    pub fn broadcast(&self, message: &Value) -> usize {
+        let connections = self
+            .server
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        connections
+            .into_iter()
+            .filter(|connection| {
+                Peer {
+                    connection: connection.clone(),
+                    framing: self.server.framing,
+                }
+                .send(message)
+                .is_ok()
+            })
+            .count()
+    }
+}
+
+impl Drop for IpcHandle {
+    fn drop(&mut self) {
+        self.server.stop();
+        SERVERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|s| !Arc::ptr_eq(s, &self.server));
+    }
+}
+
+/// Stops every IPC server started through this module. This is called for you by `#[gmod13_close]`.
+pub fn unload() {
+    let servers = std::mem::take(&mut *SERVERS.lock().unwrap_or_else(|e| e.into_inner()));
+    for server in servers {
+        server.stop();
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{
+        fs::{DirBuilder, Permissions},
+        io,
+        iter::repeat_with,
+        net::Shutdown,
+        os::unix::{
+            fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+        path::{Path, PathBuf},
+    };
+
+    pub type Stream = UnixStream;
+
+    pub struct Listener {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl Listener {
+        pub fn bind(name: &str) -> io::Result<Self> {
+            let path = if name.contains('/') {
+                PathBuf::from(name)
+            } else {
+                std::env::temp_dir().join(format!("{name}.sock"))
+            };
+
+            // a socket left behind by a crashed server would make the bind fail
+            let is_socket = std::fs::symlink_metadata(&path)
+                .is_ok_and(|metadata| metadata.file_type().is_socket());
+            if is_socket && UnixStream::connect(&path).is_err() {
+                let _ = std::fs::remove_file(&path);
+            }
+
+            Ok(Self {
+                listener: bind_private(&path)?,
+                path,
+            })
+        }
+
+        pub fn accept(&self) -> io::Result<Stream> {
+            self.listener.accept().map(|(stream, _)| stream)
+        }
+
+        /// Unblocks `accept` by connecting to ourselves.
+        pub fn wake(&self) {
+            let _ = UnixStream::connect(&self.path);
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    /// Binds the socket in a directory only we can enter, makes it `0600`, then moves it to `path`, so that no one else can connect in between.
+    fn bind_private(path: &Path) -> io::Result<UnixListener> {
+        let parent = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let random: String = repeat_with(fastrand::alphanumeric).take(10).collect();
+        let dir = parent.join(format!(".gmod-rs-ipc-{random}"));
+        DirBuilder::new().mode(0o700).create(&dir)?;
+
+        let bound = (|| {
+            let private = dir.join("socket");
+            let listener = UnixListener::bind(&private)?;
+            std::fs::set_permissions(&private, Permissions::from_mode(0o600))?;
+            if path.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", path.display()),
+                ));
+            }
+            std::fs::rename(&private, path)?;
+            Ok(listener)
+        })();
+        let _ = std::fs::remove_file(dir.join("socket"));
+        let _ = std::fs::remove_dir(&dir);
+        bound
+    }
+
+    pub fn try_clone(stream: &Stream) -> io::Result<Stream> {
+        stream.try_clone()
+    }
+
+    pub fn close(stream: &Stream) {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{
+        ffi::c_void,
+        fs::File,
+        io::{self, Read, Write},
+        os::windows::{
+            ffi::OsStrExt,
+            io::{AsRawHandle, FromRawHandle},
+        },
+        sync::Mutex,
+    };
+
+    const PIPE_ACCESS_DUPLEX: u32 = 0x3;
+    const FILE_FLAG_OVERLAPPED: u32 = 0x4000_0000;
+    const FILE_FLAG_FIRST_PIPE_INSTANCE: u32 = 0x0008_0000;
+    const PIPE_TYPE_BYTE: u32 = 0x0;
+    const PIPE_WAIT: u32 = 0x0;
+    const PIPE_REJECT_REMOTE_CLIENTS: u32 = 0x8;
+    const PIPE_UNLIMITED_INSTANCES: u32 = 255;
+    const BUFFER_SIZE: u32 = 64 * 1024;
+    const ERROR_BROKEN_PIPE: i32 = 109;
+    const ERROR_PIPE_NOT_CONNECTED: i32 = 233;
+    const ERROR_PIPE_CONNECTED: i32 = 535;
+    const ERROR_IO_PENDING: i32 = 997;
+    const INVALID_HANDLE_VALUE: *mut c_void = -1isize as *mut c_void;
+    const SDDL_REVISION_1: u32 = 1;
+    /// Full access for the system, administrators and the owner of the pipe (the user running the game), and no one else.
+    const SECURITY_DESCRIPTOR: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;OW)";
+
+    #[repr(C)]
+    struct SecurityAttributes {
+        length: u32,
+        security_descriptor: *mut c_void,
+        inherit_handle: i32,
+    }
+
+    #[repr(C)]
+    struct Overlapped {
+        internal: usize,
+        internal_high: usize,
+        offset: u32,
+        offset_high: u32,
+        event: *mut c_void,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateNamedPipeW(
+            name: *const u16,
+            open_mode: u32,
+            pipe_mode: u32,
+            max_instances: u32,
+            out_buffer_size: u32,
+            in_buffer_size: u32,
+            default_timeout: u32,
+            security_attributes: *mut SecurityAttributes,
+        ) -> *mut c_void;
+        fn ConnectNamedPipe(pipe: *mut c_void, overlapped: *mut Overlapped) -> i32;
+        fn DisconnectNamedPipe(pipe: *mut c_void) -> i32;
+        fn CreateEventW(
+            attributes: *mut c_void,
+            manual_reset: i32,
+            initial_state: i32,
+            name: *const u16,
+        ) -> *mut c_void;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+        fn ReadFile(
+            file: *mut c_void,
+            buffer: *mut u8,
+            len: u32,
+            read: *mut u32,
+            overlapped: *mut Overlapped,
+        ) -> i32;
+        fn WriteFile(
+            file: *mut c_void,
+            buffer: *const u8,
+            len: u32,
+            written: *mut u32,
+            overlapped: *mut Overlapped,
+        ) -> i32;
+        fn GetOverlappedResult(
+            file: *mut c_void,
+            overlapped: *mut Overlapped,
+            transferred: *mut u32,
+            wait: i32,
+        ) -> i32;
+        fn LocalFree(mem: *mut c_void) -> *mut c_void;
+    }
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl: *const u16,
+            revision: u32,
+            descriptor: *mut *mut c_void,
+            len: *mut u32,
+        ) -> i32;
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        std::ffi::OsStr::new(s)
+            .encode_wide()
+            .chain(Some(0))
+            .collect()
+    }
+
+    /// A pipe opened for overlapped I/O, so that a write doesn't wait behind the read the connection's thread is blocked in, as it would on a synchronous handle.
+    pub struct Stream {
+        pipe: File,
+        /// Signaled when an operation completes. Each `Stream` has its own, and only runs one operation at a time.
+        event: *mut c_void,
+    }
+
+    // the event is only used by the `&mut self` methods
+    unsafe impl Send for Stream {}
+
+    impl Stream {
+        fn new(pipe: File) -> io::Result<Self> {
+            let event = unsafe { CreateEventW(std::ptr::null_mut(), 1, 0, std::ptr::null()) };
+            if event.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { pipe, event })
+        }
+
+        /// Starts an operation with `start` and waits for it to complete, returning how many bytes were transferred.
+        fn wait(&mut self, start: impl FnOnce(*mut Overlapped) -> i32) -> io::Result<usize> {
+            let mut overlapped = Overlapped {
+                internal: 0,
+                internal_high: 0,
+                offset: 0,
+                offset_high: 0,
+                event: self.event,
+            };
+            if start(&mut overlapped) == 0 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() != Some(ERROR_IO_PENDING) {
+                    return Err(err);
+                }
+            }
+            let mut transferred = 0;
+            let handle = self.pipe.as_raw_handle();
+            if unsafe { GetOverlappedResult(handle, &mut overlapped, &mut transferred, 1) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(transferred as usize)
+        }
+    }
+
+    impl Drop for Stream {
+        fn drop(&mut self) {
+            unsafe { CloseHandle(self.event) };
+        }
+    }
+
+    impl Read for Stream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let handle = self.pipe.as_raw_handle();
+            let len = buf.len().min(u32::MAX as usize) as u32;
+            let read = self.wait(|overlapped| unsafe {
+                ReadFile(
+                    handle,
+                    buf.as_mut_ptr(),
+                    len,
+                    std::ptr::null_mut(),
+                    overlapped,
+                )
+            });
+            match read {
+                Err(err)
+                    if matches!(
+                        err.raw_os_error(),
+                        Some(ERROR_BROKEN_PIPE | ERROR_PIPE_NOT_CONNECTED)
+                    ) =>
+                {
+                    Ok(0)
+                }
+                read => read,
+            }
+        }
+    }
+
+    impl Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let handle = self.pipe.as_raw_handle();
+            let len = buf.len().min(u32::MAX as usize) as u32;
+            self.wait(|overlapped| unsafe {
+                WriteFile(handle, buf.as_ptr(), len, std::ptr::null_mut(), overlapped)
+            })
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub struct Listener {
+        path: String,
+        /// Allocated by `ConvertStringSecurityDescriptorToSecurityDescriptorW`, freed with `LocalFree`.
+        security_descriptor: *mut c_void,
+        /// The instance created by `bind`, which owns the name, until `accept` takes it.
+        first: Mutex<Option<Stream>>,
+    }
+
+    // the descriptor is only read after it's created
+    unsafe impl Send for Listener {}
+    unsafe impl Sync for Listener {}
+
+    impl Listener {
+        pub fn bind(name: &str) -> io::Result<Self> {
+            let mut security_descriptor = std::ptr::null_mut();
+            let converted = unsafe {
+                ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                    wide(SECURITY_DESCRIPTOR).as_ptr(),
+                    SDDL_REVISION_1,
+                    &mut security_descriptor,
+                    std::ptr::null_mut(),
+                )
+            };
+            if converted == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let listener = Self {
+                path: format!(r"\\.\pipe\{name}"),
+                security_descriptor,
+                first: Mutex::new(None),
+            };
+            // fails if the name is taken, e.g. by another user squatting it, instead of joining their pipe
+            let first = listener.create(FILE_FLAG_FIRST_PIPE_INSTANCE)?;
+            *listener.first.lock().unwrap_or_else(|e| e.into_inner()) = Some(first);
+            Ok(listener)
+        }
+
+        fn create(&self, flags: u32) -> io::Result<Stream> {
+            let mut attributes = SecurityAttributes {
+                length: std::mem::size_of::<SecurityAttributes>() as u32,
+                security_descriptor: self.security_descriptor,
+                inherit_handle: 0,
+            };
+            let handle = unsafe {
+                CreateNamedPipeW(
+                    wide(&self.path).as_ptr(),
+                    PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED | flags,
+                    PIPE_TYPE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    BUFFER_SIZE,
+                    BUFFER_SIZE,
+                    0,
+                    &mut attributes,
+                )
+            };
+            if handle == INVALID_HANDLE_VALUE {
+                return Err(io::Error::last_os_error());
+            }
+            Stream::new(unsafe { File::from_raw_handle(handle) })
+        }
+
+        pub fn accept(&self) -> io::Result<Stream> {
+            let first = self.first.lock().unwrap_or_else(|e| e.into_inner()).take();
+            let mut pipe = match first {
+                Some(pipe) => pipe,
+                None => self.create(0)?,
+            };
+            let handle = pipe.pipe.as_raw_handle();
+            match pipe.wait(|overlapped| unsafe { ConnectNamedPipe(handle, overlapped) }) {
+                Ok(_) => {}
+                Err(err) if err.raw_os_error() == Some(ERROR_PIPE_CONNECTED) => {}
+                Err(err) => return Err(err),
+            }
+            Ok(pipe)
+        }
+
+        /// Unblocks `accept` by connecting to ourselves.
+        pub fn wake(&self) {
+            let _ = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&self.path);
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            unsafe { LocalFree(self.security_descriptor) };
+        }
+    }
+
+    pub fn try_clone(stream: &Stream) -> io::Result<Stream> {
+        Stream::new(stream.pipe.try_clone()?)
+    }
+
+    pub fn close(stream: &Stream) {
+        unsafe { DisconnectNamedPipe(stream.pipe.as_raw_handle()) };
+    }
+}
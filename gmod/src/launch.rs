@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    path::Path,
+    str::FromStr,
+    sync::LazyLock,
+};
+
+/// The parsed command line and environment of the game/server process.
+///
+/// Parameters are matched case-insensitively and include their prefix, so `-port` and `+maxplayers` are separate namespaces, like the engine treats them.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::launch::LaunchOptions;
+///
+/// let options = LaunchOptions::parse(
+///     ["srcds_linux", "-console", "-port", "27016", "+maxplayers", "32", "+sv_gravity", "-600", "+map", "gm_construct"].map(Into::into),
+/// );
+///
+/// assert!(options.is_dedicated());
+/// assert!(options.has("-console"));
+/// assert_eq!(options.port(), Some(27016));
+/// assert_eq!(options.get::<u32>("+maxplayers"), Some(32));
+/// assert_eq!(options.get::<f32>("+sv_gravity"), Some(-600.0));
+/// assert_eq!(options.value_str("+map").as_deref(), Some("gm_construct"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LaunchOptions {
+    exe: Option<OsString>,
+    args: Vec<OsString>,
+    params: HashMap<String, Option<OsString>>,
+    env: HashMap<OsString, OsString>,
+}
+
+/// Whether `arg` starts a new parameter, rather than being the value of the previous one. Negative numbers, as in `+sv_gravity -600`, are values.
+fn is_param(arg: &OsStr) -> bool {
+    let bytes = arg.as_encoded_bytes();
+    if !matches!(bytes.first(), Some(b'-' | b'+')) {
+        return false;
+    }
+    // `f64` also parses `inf` and `nan`, which could be parameters
+    let number = matches!(bytes.get(1), Some(b'0'..=b'9' | b'.'))
+        && arg.to_str().is_some_and(|arg| arg.parse::<f64>().is_ok());
+    !number
+}
+
+impl LaunchOptions {
+    /// Parses a command line, where the first item is the executable. The environment is not captured.
+    pub fn parse<I: IntoIterator<Item = OsString>>(args: I) -> Self {
+        let mut args = args.into_iter();
+        let exe = args.next();
+        let args: Vec<OsString> = args.collect();
+
+        let mut params = HashMap::new();
+        let mut i = 0;
+        while i < args.len() {
+            let arg = &args[i];
+            i += 1;
+
+            if !is_param(arg) {
+                continue;
+            }
+
+            let value = match args.get(i) {
+                Some(value) if !is_param(value) => {
+                    i += 1;
+                    Some(value.clone())
+                }
+                _ => None,
+            };
+
+            params.insert(arg.to_string_lossy().to_ascii_lowercase(), value);
+        }
+
+        Self {
+            exe,
+            args,
+            params,
+            env: HashMap::new(),
+        }
+    }
+
+    /// Captures the command line and environment of the current process.
+    pub fn from_process() -> Self {
+        let mut options = Self::parse(process_args());
+        options.env = std::env::vars_os().collect();
+        options
+    }
+
+    /// Returns the path of the executable, as given on the command line.
+    pub fn exe(&self) -> Option<&Path> {
+        self.exe.as_deref().map(Path::new)
+    }
+
+    /// Returns every argument after the executable, unparsed.
+    pub fn args(&self) -> &[OsString] {
+        &self.args
+    }
+
+    /// Returns whether the parameter (e.g. `-console` or `+map`) was given.
+    pub fn has(&self, param: &str) -> bool {
+        self.params.contains_key(&param.to_ascii_lowercase())
+    }
+
+    /// Returns the value given to a parameter, e.g. `gm_construct` for `+map gm_construct`.
+    pub fn value(&self, param: &str) -> Option<&OsStr> {
+        self.params.get(&param.to_ascii_lowercase())?.as_deref()
+    }
+
+    /// Same as `value`, but converted to a string, replacing invalid UTF-8 sequences.
+    pub fn value_str(&self, param: &str) -> Option<std::borrow::Cow<'_, str>> {
+        self.value(param).map(OsStr::to_string_lossy)
+    }
+
+    /// Parses the value given to a parameter.
+    pub fn get<T: FromStr>(&self, param: &str) -> Option<T> {
+        self.value(param)?.to_str()?.trim().parse().ok()
+    }
+
+    /// Returns an environment variable of the process.
+    pub fn env<K: AsRef<OsStr>>(&self, key: K) -> Option<&OsStr> {
+        self.env.get(key.as_ref()).map(OsString::as_os_str)
+    }
+
+    /// Returns whether this is a dedicated server (srcds).
+    pub fn is_dedicated(&self) -> bool {
+        if self.has("-dedicated") {
+            return true;
+        }
+        self.exe()
+            .and_then(Path::file_stem)
+            .map(|stem| {
+                stem.to_string_lossy()
+                    .to_ascii_lowercase()
+                    .starts_with("srcds")
+            })
+            .unwrap_or(false)
+    }
+
+    /// Returns the port the server was told to listen on, from `-port` or `+hostport`.
+    pub fn port(&self) -> Option<u16> {
+        self.get("-port").or_else(|| self.get("+hostport"))
+    }
+
+    /// Returns whether this is a peer-to-peer listen server (`+p2p_enabled 1` or `-p2p`).
+    pub fn is_p2p(&self) -> bool {
+        if self.is_dedicated() {
+            return false;
+        }
+        self.has("-p2p") || self.get::<i32>("+p2p_enabled").is_some_and(|v| v != 0)
+    }
+
+    /// Returns the map the server was started on, from `+map`.
+    pub fn map(&self) -> Option<std::borrow::Cow<'_, str>> {
+        self.value_str("+map")
+    }
+
+    /// Returns the gamemode the server was started with, from `+gamemode`.
+    pub fn gamemode(&self) -> Option<std::borrow::Cow<'_, str>> {
+        self.value_str("+gamemode")
+    }
+
+    /// Returns the max player count the server was started with, from `+maxplayers`.
+    pub fn max_players(&self) -> Option<u32> {
+        self.get("+maxplayers")
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_args() -> Vec<OsString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    // std::env::args_os can be empty when we're loaded as a shared library on some libc implementations
+    let args: Vec<OsString> = std::env::args_os().collect();
+    if !args.is_empty() {
+        return args;
+    }
+
+    std::fs::read("/proc/self/cmdline")
+        .map(|cmdline| {
+            cmdline
+                .split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| OsStr::from_bytes(arg).to_os_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_args() -> Vec<OsString> {
+    std::env::args_os().collect()
+}
+
+static OPTIONS: LazyLock<LaunchOptions> = LazyLock::new(LaunchOptions::from_process);
+
+/// Returns the launch options of the current process. They are captured the first time this is called.
+pub fn options() -> &'static LaunchOptions {
+    &OPTIONS
+}
+
+/// Shortcut for `options().is_dedicated()`
+pub fn is_dedicated() -> bool {
+    options().is_dedicated()
+}
+
+/// Shortcut for `options().port()`
+pub fn port() -> Option<u16> {
+    options().port()
+}
+
+/// Shortcut for `options().is_p2p()`
+pub fn is_p2p() -> bool {
+    options().is_p2p()
+}
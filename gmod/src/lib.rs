@@ -12,17 +12,233 @@ compile_error!("Unsupported platform");
 pub use gmod_macros::*;
 pub use libloading;
 
+/// Runs validation from `lua::strict` when the `strict` feature is enabled.
+macro_rules! strict {
+    ($check:expr) => {
+        #[cfg(feature = "strict")]
+        $check;
+    };
+}
+
+macro_rules! record {
+    ($lua:expr, $op:expr) => {
+        #[cfg(feature = "record")]
+        $crate::record::record($lua, || {
+            use $crate::record::Op::*;
+            $op
+        });
+    };
+}
+
+/// Evaluates the call `$call`, recording `$op` before it and whether `$ok` holds for its result after it when the `record` feature is enabled.
+macro_rules! record_call {
+    ($lua:expr, $op:expr, $call:expr, $ok:expr) => {{
+        #[cfg(feature = "record")]
+        let result = $crate::record::call(
+            $lua,
+            || {
+                use $crate::record::Op::*;
+                $op
+            },
+            || $call,
+            $ok,
+        );
+        #[cfg(not(feature = "record"))]
+        let result = $call;
+        result
+    }};
+}
+
 /// Lua interface
 pub mod lua;
-pub use lua::task_queue::wait_lua_tick;
+
+/// Module lifecycle state
+pub mod lifecycle;
+
+/// The Lua states a module can be loaded into
+pub mod realm;
+
+/// Modules written as a struct with `#[gmod_module]`
+pub mod module;
+
+/// Lua functions registered automatically with `#[lua_export]`
+#[cfg(feature = "export")]
+pub mod export;
+
+/// Stopping background threads before the module unloads
+pub mod shutdown;
+
+/// Error codes shared by every subsystem
+pub mod error;
+
+/// Capturing the Lua errors raised by scripts
+pub mod errors;
+
+/// Conversion of panics in Lua functions into Lua errors
+pub mod panic;
+pub use lua::task_queue::{wait_lua_tick, wait_lua_tick_result, wait_lua_tick_with_priority};
 pub use lua::*;
 
+/// Time budgets for calls into Lua
+pub mod watchdog;
+
+/// RGBA colors and `Color` tables
+pub mod color;
+
+/// Printing to the game console
+pub mod console;
+
+/// Logging to the console and files, as a `log` crate backend
+pub mod log;
+
+/// Files of the game's virtual filesystem
+pub mod file;
+
+/// HTTP requests through the game's `HTTP`, as futures
+pub mod http;
+
+/// Source engine interfaces through `CreateInterface`
+pub mod source;
+
+/// Queries to the game's SQLite database
+#[cfg(feature = "sql")]
+pub mod sql;
+
+/// Conversion between serde types and Lua values
+#[cfg(feature = "serde")]
+pub mod serde;
+
 /// Userdata types
 pub mod userdata;
 
 /// Net library helpers
 pub mod net;
 
+/// MessagePack encoding of Lua values, for net messages
+pub mod codec;
+
+/// Entity handles
+pub mod entity;
+
+/// Player lookup and helpers
+pub mod player;
+
+/// Steam ID conversions
+pub mod steamid;
+
+/// Player session and playtime tracking
+pub mod sessions;
+
+/// In-memory event log with replay for late subscribers
+pub mod eventlog;
+
+/// Tamper-evident audit log for admin actions
+pub mod audit;
+
+/// Source demo file parsing
+pub mod demo;
+
+#[cfg(feature = "record")]
+pub mod record;
+
+/// Command line and environment of the game/server process
+pub mod launch;
+
+/// Hook library helpers
+pub mod hook;
+
+/// Callbacks registered in Lua by the timer, convar and concommand helpers
+mod callbacks;
+
+/// Timer library helpers
+pub mod timer;
+
+/// Service manager notifications for dedicated servers
+pub mod host;
+
+/// Child process management
+pub mod proc;
+
+/// Console variables
+pub mod convar;
+
+/// Console commands
+pub mod concommand;
+
+/// Module settings backed by convars or a JSON file
+pub mod config;
+
+/// Lua scripts embedded at compile time
+pub mod scripts;
+
+/// Running Lua code on clients from the server
+pub mod client_lua;
+
+/// Typed data shared between binary modules
+pub mod interop;
+
+/// Shims for the upstream gmod-rs API
+#[cfg(feature = "compat-legacy")]
+pub mod compat;
+
+/// Local IPC endpoint for sidecar processes
+#[cfg(feature = "ipc")]
+pub mod ipc;
+
+/// File system watching
+#[cfg(feature = "fswatch")]
+pub mod fswatch;
+
+/// Archive extraction
+#[cfg(feature = "unzip")]
+pub mod unzip;
+
+/// Template rendering
+#[cfg(feature = "template")]
+pub mod template;
+
+/// Markdown rendering
+#[cfg(feature = "markdown")]
+pub mod markdown;
+
+/// HTML sanitizing and URL validation
+#[cfg(feature = "sanitize")]
+pub mod sanitize;
+
+/// Account linking with OAuth2 and Steam OpenID
+#[cfg(feature = "oauth")]
+pub mod oauth;
+
+/// Hooking of native functions
+#[cfg(feature = "detour")]
+pub mod detour;
+
+/// Inspecting and swapping the virtual methods of C++ objects
+#[cfg(feature = "detour")]
+pub mod vtable;
+
+/// Finding unexported functions by signature
+#[cfg(feature = "sigscan")]
+pub mod sigscan;
+
+/// Geometry and intersection math
+pub mod geom;
+
+/// Perlin and simplex noise
+pub mod noise;
+
+/// Timing of Lua functions and task queue callbacks
+#[cfg(feature = "profile")]
+pub mod profile;
+
+/// Entry points for cargo fuzz
+#[cfg(feature = "fuzz")]
+pub mod fuzz_targets;
+
+/// Standalone Lua states for tests
+#[cfg(feature = "testing")]
+pub mod testing;
+
 pub use ::defer::defer;
 
 /// Returns whether this client is running the x86-64 branch
@@ -92,7 +308,7 @@ pub fn is_x86_64() -> bool {
 /// # Example
 /// ```no_run
 /// // This would only work on Windows x86-64 branch in 64-bit mode
-/// let (engine, engine_path): (gmod::libloading::Library, &'static str) = open_library_srv!("bin/win64/engine.dll").expect("Failed to open engine.dll!");
+/// let (engine, engine_path): (gmod::libloading::Library, &'static str) = unsafe { gmod::open_library_raw!("bin/win64/engine.dll") }.expect("Failed to open engine.dll!");
 /// println!("Opened engine.dll from: {}", engine_path);
 /// ```
 #[macro_export]
@@ -111,7 +327,7 @@ macro_rules! open_library_raw {
 ///
 /// # Example
 /// ```no_run
-/// let (engine, engine_path): (gmod::libloading::Library, &'static str) = open_library_srv!("engine").expect("Failed to open engine.dll!");
+/// let (engine, engine_path): (gmod::libloading::Library, &'static str) = unsafe { gmod::open_library_srv!("engine") }.expect("Failed to open engine.dll!");
 /// println!("Opened engine.dll from: {}", engine_path);
 /// ```
 #[macro_export]
@@ -178,7 +394,7 @@ macro_rules! open_library_srv {
 ///
 /// # Example
 /// ```no_run
-/// let (engine, engine_path): (gmod::libloading::Library, &'static str) = open_library!("engine").expect("Failed to open engine.dll!");
+/// let (engine, engine_path): (gmod::libloading::Library, &'static str) = unsafe { gmod::open_library!("engine") }.expect("Failed to open engine.dll!");
 /// println!("Opened engine.dll from: {}", engine_path);
 /// ```
 #[macro_export]
@@ -286,6 +502,24 @@ macro_rules! rstr {
     }};
 }
 
+/// Returns a `&'static CStr` of a string literal, built at compile time, for the methods that take a `LuaCStr` (`get_field`, `set_global`...). A literal containing a NUL byte doesn't compile.
+///
+/// C string literals (`c"name"`) do the same; this is for macros that get a `&str` literal. For strings only known at runtime, see `lua::cstr::cached`.
+///
+/// ```
+/// assert_eq!(gmod::lua_cstr!("SetHealth"), c"SetHealth");
+/// ```
+#[macro_export]
+macro_rules! lua_cstr {
+    ($str:literal) => {{
+        const CSTR: &::std::ffi::CStr = match ::std::ffi::CStr::from_bytes_with_nul(concat!($str, "\0").as_bytes()) {
+            Ok(cstr) => cstr,
+            Err(_) => panic!("lua_cstr! can't contain NUL bytes"),
+        };
+        CSTR
+    }};
+}
+
 #[macro_export]
 macro_rules! lua_regs {
 	() => {
@@ -304,7 +538,7 @@ macro_rules! lua_regs {
         &[
             $(
                 LuaReg {
-                    name: concat!($name, "\0").as_ptr() as *const i8,
+                    name: $crate::lua_cstr!($name).as_ptr(),
                     func: Some($func),
                 }
             ),*,
@@ -316,6 +550,60 @@ macro_rules! lua_regs {
     };
 }
 
+/// Sets functions into a Lua table, for defining the methods of an object or a library in one go.
+///
+/// `lua_methods!(lua, index => { ... })` sets them into the table at `index`, and `lua_methods!(lua => { ... })` into a new table, left on the stack. Every entry is a name and either a Lua C function (such as a `#[lua_function]`) or a closure that captures nothing, optionally preceded by expressions in brackets that each push an upvalue, which the function reads with `push_closure_arg`.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[lua_function]
+/// unsafe fn counter_start(lua: gmod::lua::State) -> i32 {
+///     lua.push_closure_arg(1);
+///     1
+/// }
+///
+/// lua.new_metatable(c"Counter");
+/// gmod::lua_methods!(lua, -1 => {
+///     "__tostring" => |lua| { lua.push_string("Counter"); 1 },
+///     "Start" [lua.push_number(1.0)] => counter_start,
+/// });
+/// ```
+#[macro_export]
+macro_rules! lua_methods {
+    ($lua:expr => { $($entries:tt)* }) => {{
+        let lua: $crate::lua::State = $lua;
+        lua.new_table();
+        $crate::lua_methods!(@entries lua, -1, $($entries)*);
+    }};
+    ($lua:expr, $index:expr => { $($entries:tt)* }) => {{
+        let lua: $crate::lua::State = $lua;
+        $crate::lua_methods!(@entries lua, $index, $($entries)*);
+    }};
+
+    (@entries $lua:ident, $index:expr, $($entries:tt)*) => {
+        let table = $crate::lua::StackIndex::from($index).abs($lua);
+        $crate::lua_methods!(@entry $lua, table, $($entries)*);
+    };
+
+    (@entry $lua:ident, $table:ident, $(,)?) => {};
+    (@entry $lua:ident, $table:ident, $name:literal $([$($upvalue:expr),* $(,)?])? => |$arg:ident $(: $ty:ty)?| $body:expr $(, $($rest:tt)*)?) => {
+        $crate::lua_methods!(@set $lua, $table, $name, [$($($upvalue),*)?], $crate::lua::function_from_closure(|$arg $(: $ty)?| $body));
+        $crate::lua_methods!(@entry $lua, $table, $($($rest)*)?);
+    };
+    (@entry $lua:ident, $table:ident, $name:literal $([$($upvalue:expr),* $(,)?])? => $func:expr $(, $($rest:tt)*)?) => {
+        $crate::lua_methods!(@set $lua, $table, $name, [$($($upvalue),*)?], $func);
+        $crate::lua_methods!(@entry $lua, $table, $($($rest)*)?);
+    };
+
+    (@set $lua:ident, $table:ident, $name:literal, [$($upvalue:expr),*], $func:expr) => {
+        $($upvalue;)*
+        $lua.push_closure($func, 0 $(+ { let _ = stringify!($upvalue); 1 })*);
+        $lua.set_field($table, $crate::lua_cstr!($name));
+    };
+}
+
+/// Allocates a `CString`, panicking on NUL bytes. Names used over and over are better off with `lua_cstr!` or `lua::cstr::cached`, which don't allocate every time.
 pub fn cstring(s: &str) -> std::ffi::CString {
     std::ffi::CString::new(s).expect("Failed to create CString")
 }
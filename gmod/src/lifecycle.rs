@@ -0,0 +1,161 @@
+//! The module's lifecycle, from `gmod13_open` to `gmod13_close`, readable from any thread.
+//!
+//! Background threads can check it to stop producing work once the module is shutting down. `wait_lua_tick` also refuses new callbacks from then on, returning a `ClosedError`.
+//!
+//! ## Reloading
+//!
+//! The game doesn't always unload the library when a module is closed, e.g. on map change, so `gmod13_open` can run again with the statics of the previous load still around. gmod-rs resets its own state, and modules can tell a reload apart with `ReloadContext` to reset theirs:
+//!
+//! ```ignore
+//! #[gmod13_open]
+//! fn gmod13_open(lua: State, ctx: ReloadContext) -> i32 {
+//!     if ctx.is_reload() {
+//!         CACHE.lock().unwrap().clear();
+//!     }
+//!     0
+//! }
+//! ```
+//!
+//! Code that can't reach `gmod13_open` can register a callback with `on_reload` instead.
+//!
+//! ## Several states
+//!
+//! When the module is loaded into several Lua states at once (see `gmod::realm`), the lifecycle is the module's as a whole: it's `Loading` while the first state opens it, and `Closing` while the last state closes it. `open_realms` tells which states have it open.
+
+use std::sync::{
+    atomic::{AtomicU32, AtomicU8, Ordering},
+    Mutex,
+};
+
+use crate::{lua::State, realm::Realm};
+
+/// A phase of the module's lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ModuleState {
+    /// `gmod13_open` is running, for the first state.
+    Loading,
+    /// `gmod13_open` returned, and `gmod13_close` wasn't called yet for every state.
+    Running,
+    /// `gmod13_close` is running, for the last state. Callbacks can't be queued anymore.
+    Closing,
+    /// The module isn't loaded, either because `gmod13_open` wasn't called yet or because `gmod13_close` returned for every state.
+    Closed,
+}
+
+static STATE: AtomicU8 = AtomicU8::new(ModuleState::Closed as u8);
+
+/// Returns the current phase of the module's lifecycle.
+pub fn state() -> ModuleState {
+    match STATE.load(Ordering::Acquire) {
+        0 => ModuleState::Loading,
+        1 => ModuleState::Running,
+        2 => ModuleState::Closing,
+        _ => ModuleState::Closed,
+    }
+}
+
+/// Returns whether the module is loaded and not shutting down.
+pub fn is_running() -> bool {
+    matches!(state(), ModuleState::Loading | ModuleState::Running)
+}
+
+/// Sets the current phase. Called by `#[gmod13_open]` and `#[gmod13_close]`.
+pub fn set_state(state: ModuleState) {
+    STATE.store(state as u8, Ordering::Release);
+}
+
+/// Returned when work is submitted to the Lua thread while the module is shutting down or unloaded. The work was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClosedError;
+
+impl std::fmt::Display for ClosedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the module is shutting down or unloaded")
+    }
+}
+
+impl std::error::Error for ClosedError {}
+
+/// One bit per `Realm` the module is open in.
+static OPEN_REALMS: AtomicU8 = AtomicU8::new(0);
+
+/// Returns the realms the module is open in, between `gmod13_open` and `gmod13_close`.
+pub fn open_realms() -> Vec<Realm> {
+    let open = OPEN_REALMS.load(Ordering::Acquire);
+    Realm::ALL
+        .into_iter()
+        .filter(|&realm| open & (1 << realm as u8) != 0)
+        .collect()
+}
+
+pub fn is_open_in(realm: Realm) -> bool {
+    OPEN_REALMS.load(Ordering::Acquire) & (1 << realm as u8) != 0
+}
+
+/// Marks the realm of `lua` as closed, and returns whether it was the last one open. Called by `#[gmod13_close]`, which only tears gmod-rs down after the last one.
+pub fn closing(lua: State) -> bool {
+    let bit = 1 << lua.realm() as u8;
+    OPEN_REALMS.fetch_and(!bit, Ordering::AcqRel) & !bit == 0
+}
+
+/// How many times `gmod13_open` ran in this process for a first state, including the current load.
+static LOAD_COUNT: AtomicU32 = AtomicU32::new(0);
+
+type ReloadCallback = Box<dyn FnOnce(State, ReloadContext) + Send>;
+
+static RELOAD_CALLBACKS: Mutex<Vec<ReloadCallback>> = Mutex::new(Vec::new());
+
+/// Whether the module is loaded for the first time in this process. Can be taken as a second argument by `#[gmod13_open]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReloadContext {
+    /// How many times the module was opened in this process, including this time.
+    pub load_count: u32,
+}
+
+impl ReloadContext {
+    /// Whether the module was opened before in this process, so statics may hold state from the previous load.
+    pub fn is_reload(&self) -> bool {
+        self.load_count > 1
+    }
+}
+
+/// Returns the context of the current load.
+pub fn reload_context() -> ReloadContext {
+    ReloadContext {
+        load_count: LOAD_COUNT.load(Ordering::Acquire),
+    }
+}
+
+/// Registers a callback to run the next time the module is opened again in this process, before `gmod13_open`'s body. Callbacks run once, so register them again on every load if needed.
+pub fn on_reload<F>(callback: F)
+where
+    F: FnOnce(State, ReloadContext) + Send + 'static,
+{
+    RELOAD_CALLBACKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Box::new(callback));
+}
+
+/// Marks the realm of `lua` as open. If no other realm is, counts a load, and resets gmod-rs' state and runs the `on_reload` callbacks if it's a reload. Called by `#[gmod13_open]`.
+pub fn opened(lua: State) -> ReloadContext {
+    let bit = 1 << lua.realm() as u8;
+    if OPEN_REALMS.fetch_or(bit, Ordering::AcqRel) & !bit != 0 {
+        // another state has the module open, it's the same load
+        return reload_context();
+    }
+
+    LOAD_COUNT.fetch_add(1, Ordering::AcqRel);
+    let ctx = reload_context();
+    if ctx.is_reload() {
+        crate::lua::task_queue::reset();
+
+        let callbacks =
+            std::mem::take(&mut *RELOAD_CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()));
+        for callback in callbacks {
+            callback(lua, ctx);
+        }
+    }
+    ctx
+}
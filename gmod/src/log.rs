@@ -0,0 +1,184 @@
+//! Logging to the game console, in color, and optionally to a file under `garrysmod/data/`.
+//!
+//! This is a backend for the [`log`](https://docs.rs/log) crate: once `init` is called, `log::info!` and friends, including those of dependencies, show up in the console. Messages can be logged from any thread, and are printed with `MsgC` on the next tick. Before `gmod13_open` and after `gmod13_close`, when Lua can't be reached, they're printed to stdout instead.
+//!
+//! Console lines look like `[my_module] WARN  my_module::db: connection lost`: the prefix set with `set_prefix`, the level, and the target of the message (its module path), unless it's a crate root.
+//!
+//! ## Example
+//!
+//! ```
+//! gmod::log::init();
+//! gmod::log::set_prefix("my_module");
+//! gmod::log::set_level(gmod::log::LevelFilter::Debug);
+//! # let _ = || -> std::io::Result<()> {
+//! gmod::log::log_to_file("my_module/log.txt")?;
+//! # Ok(()) };
+//!
+//! log::info!("loaded");
+//! ```
+
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub use ::log::{Level, LevelFilter};
+
+use crate::{
+    console::{self, Color},
+    lua::State,
+};
+
+/// The directory `log_to_file` paths are relative to.
+pub const DATA_DIR: &str = "garrysmod/data";
+
+static PREFIX: Mutex<Option<String>> = Mutex::new(None);
+static FILE: Mutex<Option<LineWriter<File>>> = Mutex::new(None);
+static LOGGER: Logger = Logger;
+
+struct Logger;
+
+impl ::log::Log for Logger {
+    fn enabled(&self, metadata: &::log::Metadata) -> bool {
+        metadata.level() <= ::log::max_level()
+    }
+
+    fn log(&self, record: &::log::Record) {
+        if self.enabled(record.metadata()) {
+            write(record.level(), record.target(), &record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = FILE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Makes this the backend of the `log` crate, with the `Info` level. Returns false if another backend was already installed, e.g. when the module is reloaded without being unloaded, in which case the settings of the previous call are kept.
+pub fn init() -> bool {
+    if ::log::set_logger(&LOGGER).is_err() {
+        return false;
+    }
+    ::log::set_max_level(LevelFilter::Info);
+    true
+}
+
+/// Sets the most verbose level that gets logged.
+pub fn set_level(level: LevelFilter) {
+    ::log::set_max_level(level);
+}
+
+pub fn level() -> LevelFilter {
+    ::log::max_level()
+}
+
+/// Sets the name shown in brackets before every console line, usually the module's name.
+pub fn set_prefix(prefix: &str) {
+    *PREFIX.lock().unwrap_or_else(|e| e.into_inner()) = Some(prefix.to_owned());
+}
+
+/// Also appends every message to `garrysmod/data/<path>`, with a timestamp, creating the file and its directories if needed. Replaces the previous file, if any.
+pub fn log_to_file(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = Path::new(DATA_DIR).join(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(LineWriter::new(file));
+    Ok(())
+}
+
+/// Stops writing to the file set with `log_to_file`.
+pub fn stop_logging_to_file() {
+    drop(FILE.lock().unwrap_or_else(|e| e.into_inner()).take());
+}
+
+/// Logs a message, without going through the `log` crate. `target` is usually a module path.
+pub fn write(level: Level, target: &str, msg: &str) {
+    // the crate root is the module itself, or a dependency that didn't bother with modules
+    let target = if target.contains("::") { target } else { "" };
+    let line = line(level, target, msg);
+
+    if let Some(file) = FILE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        let _ = writeln!(file, "{} {line}", timestamp());
+    }
+
+    let prefix = PREFIX.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let queued = crate::wait_lua_tick(String::new(), {
+        let (prefix, line) = (prefix.clone(), line.clone());
+        move |lua| print_console(lua, level, prefix.as_deref(), &line)
+    });
+    if queued.is_err() {
+        print_stdout(prefix.as_deref(), &line);
+    }
+}
+
+fn line(level: Level, target: &str, msg: &str) -> String {
+    let mut line = format!("{level:<5} ");
+    if !target.is_empty() {
+        let _ = write!(line, "{target}: ");
+    }
+    line.push_str(msg);
+    line
+}
+
+fn color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::ERROR,
+        Level::Warn => Color::WARN,
+        Level::Info => Color::rgb(230, 230, 230),
+        Level::Debug | Level::Trace => Color::rgb(150, 150, 150),
+    }
+}
+
+const PREFIX_COLOR: Color = Color::rgb(110, 190, 255);
+
+fn print_console(lua: State, level: Level, prefix: Option<&str>, line: &str) {
+    let prefix = prefix.map(|prefix| format!("[{prefix}] "));
+    let line = format!("{line}\n");
+    match &prefix {
+        Some(prefix) => {
+            console::print_colored(lua, &[(PREFIX_COLOR, prefix), (color(level), &line)])
+        }
+        None => console::print_colored(lua, &[(color(level), &line)]),
+    }
+}
+
+fn print_stdout(prefix: Option<&str>, line: &str) {
+    match prefix {
+        Some(prefix) => println!("[{prefix}] {line}"),
+        None => println!("{line}"),
+    }
+}
+
+/// The current UTC time as `YYYY-MM-DD HH:MM:SS`.
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
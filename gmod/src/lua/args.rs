@@ -0,0 +1,108 @@
+use anyhow::Result;
+
+use super::{number::LuaCheckNumber, LuaRef, State};
+use crate::userdata::{Angle, Vector};
+
+/// Values that can be read from a function argument, which lets `#[lua_function]` take them as parameters.
+///
+/// Every parameter after the Lua state is checked in order, starting at argument #1. A bad argument raises the usual `bad argument #n to 'f' (...)` error.
+///
+/// ```no_run
+/// use gmod::lua::{State, TableRef};
+///
+/// #[gmod::lua_function]
+/// fn give_ammo(lua: State, steamid64: u64, count: u32, opts: Option<TableRef>) -> anyhow::Result<(bool,)> {
+///     Ok((count > 0,))
+/// }
+/// ```
+pub trait LuaCheck: Sized {
+    fn lua_check(l: State, arg: i32) -> Result<Self>;
+}
+
+/// Integers must be integral and fit in the type, see `LuaState::check_integer`.
+impl<N: LuaCheckNumber> LuaCheck for N {
+    #[inline(always)]
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        l.check_integer(arg)
+    }
+}
+
+impl LuaCheck for bool {
+    #[inline(always)]
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        l.check_boolean(arg)
+    }
+}
+
+impl LuaCheck for String {
+    #[inline(always)]
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        Ok(l.check_string(arg)?.into_owned())
+    }
+}
+
+impl LuaCheck for Vector {
+    #[inline(always)]
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        l.check_vector(arg)
+    }
+}
+
+impl LuaCheck for Angle {
+    #[inline(always)]
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        l.check_angle(arg)
+    }
+}
+
+impl LuaCheck for TableRef {
+    #[inline(always)]
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        l.check_table(arg)?;
+        Ok(Self(LuaRef::from_index(l, arg)))
+    }
+}
+
+impl LuaCheck for FunctionRef {
+    #[inline(always)]
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        l.check_function(arg)?;
+        Ok(Self(LuaRef::from_index(l, arg)))
+    }
+}
+
+/// Missing arguments and `nil` are `None`.
+impl<T: LuaCheck> LuaCheck for Option<T> {
+    #[inline(always)]
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        if l.is_none_or_nil(arg) {
+            Ok(None)
+        } else {
+            T::lua_check(l, arg).map(Some)
+        }
+    }
+}
+
+/// A reference to a table argument.
+#[derive(Debug)]
+pub struct TableRef(pub LuaRef);
+
+/// A reference to a function argument.
+#[derive(Debug)]
+pub struct FunctionRef(pub LuaRef);
+
+impl std::ops::Deref for TableRef {
+    type Target = LuaRef;
+
+    fn deref(&self) -> &LuaRef {
+        &self.0
+    }
+}
+
+impl std::ops::Deref for FunctionRef {
+    type Target = LuaRef;
+
+    fn deref(&self) -> &LuaRef {
+        &self.0
+    }
+}
@@ -0,0 +1,271 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::{LuaCStr, LuaCheck, LuaPush, LuaRef, State};
+
+/// A call to a Lua function being built, created by `State::func` or `State::call_global`.
+///
+/// Arguments are pushed as they're added, and the stack is restored to its original size once the call is done (or the builder is dropped without calling).
+///
+/// ## Example
+///
+/// ```ignore
+/// let clamped = lua.func(c"math", c"Clamp").arg(15.0).arg(0.0).arg(10.0).call::<f64>()?;
+///
+/// // results are read as any `LuaCheck` type, or a tuple of them
+/// let (x, y) = lua.call_global("util.ScreenToVector").arg(0).arg(0).call::<(f64, Option<f64>)>()?;
+///
+/// lua.call_global("hook.Run").arg("MyModuleReady").call::<()>()?;
+/// ```
+#[must_use = "the function isn't called until `call` is"]
+pub struct LuaCall {
+    lua: State,
+    top: i32,
+    // shared with `CachedLuaFunction`, which doesn't allocate every call
+    path: Arc<str>,
+    nargs: i32,
+    error: Option<anyhow::Error>,
+}
+
+impl LuaCall {
+    fn new(lua: State, path: Arc<str>) -> Self {
+        Self {
+            lua,
+            top: lua.get_top(),
+            path,
+            nargs: 0,
+            error: None,
+        }
+    }
+
+    /// Pushes an argument.
+    pub fn arg<T: LuaPush>(mut self, value: T) -> Self {
+        if self.error.is_none() {
+            if let Err(err) = self.lua.ensure_stack(1) {
+                self.error = Some(err.context(format!("too many arguments to {}", self.path)));
+            }
+        }
+        if self.error.is_some() {
+            return self;
+        }
+        value.lua_push(self.lua);
+        self.nargs += 1;
+        self
+    }
+
+    /// Calls the function in protected mode, and converts its results.
+    pub fn call<R: LuaCallResults>(mut self) -> Result<R> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        let lua = self.lua;
+        if let Err(err) = lua.pcall(self.nargs, R::COUNT, 0) {
+            bail!("{} failed: {err}", self.path);
+        }
+        R::read(lua, self.top + 1, &self.path)
+    }
+}
+
+impl Drop for LuaCall {
+    fn drop(&mut self) {
+        self.lua.set_top(self.top);
+    }
+}
+
+impl State {
+    /// Starts a call to `table.name`, where `table` is a global.
+    pub fn func(&self, table: LuaCStr, name: LuaCStr) -> LuaCall {
+        let mut call = LuaCall::new(
+            *self,
+            format!("{}.{}", table.to_string_lossy(), name.to_string_lossy()).into(),
+        );
+        self.get_global(table);
+        if self.is_table(-1) {
+            self.get_field(-1, name);
+            unsafe { self.remove(-2) };
+        }
+        if !self.is_function(-1) {
+            call.error = Some(anyhow!("{} isn't a function", call.path));
+        }
+        call
+    }
+
+    /// Starts a call to the function at a dotted path from the globals, e.g. `"hook.Run"` or `"GAMEMODE.PlayerSpawn"`.
+    pub fn call_global(&self, path: &str) -> LuaCall {
+        let mut call = LuaCall::new(*self, path.into());
+        if let Err(err) = self.push_path(path) {
+            call.error = Some(err);
+        } else if !self.is_function(-1) {
+            call.error = Some(anyhow!("{path} isn't a function"));
+        }
+        call
+    }
+
+    /// Runs a chunk of Lua code in protected mode, and converts the values it returns like `LuaCall::call` does. Errors, from compiling or running the chunk, name it `eval`.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let two = lua.eval::<f64>("return 1 + 1")?;
+    /// let (name, players) = lua.eval::<(String, f64)>("return GetHostName(), player.GetCount()")?;
+    /// lua.eval::<()>("hook.Run('MyModuleReady')")?;
+    /// ```
+    pub fn eval<R: LuaCallResults>(&self, code: &str) -> Result<R> {
+        self.eval_named(code.as_bytes(), c"=eval", "eval")
+    }
+
+    /// Same as `eval`, but for a Lua file read from disk (not from the game's filesystem). Errors name the file like `include` would.
+    pub fn eval_file<R: LuaCallResults>(&self, path: impl AsRef<Path>) -> Result<R> {
+        let path = path.as_ref();
+        let code = std::fs::read(path)
+            .map_err(|err| anyhow!("couldn't read {}: {err}", path.display()))?;
+        let name = path.display().to_string();
+        self.eval_named(&code, &crate::cstring(&format!("@{name}")), &name)
+    }
+
+    pub(crate) fn eval_named<R: LuaCallResults>(
+        &self,
+        code: &[u8],
+        chunkname: LuaCStr,
+        path: &str,
+    ) -> Result<R> {
+        let top = self.get_top();
+        let result = unsafe { self.load_buffer(code, chunkname) }
+            .and_then(|()| self.pcall(0, R::COUNT, 0))
+            .map_err(|err| anyhow!("{err}"))
+            .and_then(|()| R::read(*self, top + 1, path));
+        self.set_top(top);
+        result
+    }
+
+    /// Pushes the value at a dotted path from the globals, failing if an intermediate value isn't a table.
+    fn push_path(&self, path: &str) -> Result<()> {
+        unsafe { self.push_globals() };
+        for (i, segment) in path.split('.').enumerate() {
+            if !self.is_table(-1) {
+                self.pop();
+                let parent = path.split('.').take(i).collect::<Vec<_>>().join(".");
+                bail!("{parent} isn't a table");
+            }
+            self.push_string(segment);
+            self.get_table(-2);
+            unsafe { self.remove(-2) };
+        }
+        Ok(())
+    }
+}
+
+/// A global function resolved once and kept in the registry, for hot code calling the same function every frame, like a `Think` hook calling `hook.Run`.
+///
+/// Calling it skips the global and field lookups of `call_global`. Since it keeps calling the function it resolved, addons replacing the function afterwards (as some do with `hook.Call`) aren't seen until `refresh` is called.
+///
+/// ## Example
+///
+/// ```ignore
+/// let hook_run = CachedLuaFunction::new(lua, &["hook", "Run"])?;
+///
+/// // every tick
+/// hook_run.call(lua).arg("MyModuleTick").arg(delta).call::<()>()?;
+/// ```
+#[derive(Debug)]
+pub struct CachedLuaFunction {
+    func: LuaRef,
+    path: Arc<str>,
+}
+
+impl CachedLuaFunction {
+    /// Resolves the function at `path` from the globals, e.g. `&["hook", "Run"]`.
+    pub fn new(lua: State, path: &[&str]) -> Result<Self> {
+        let path = path.join(".");
+        let func = Self::resolve(lua, &path)?;
+        Ok(Self {
+            func,
+            path: path.into(),
+        })
+    }
+
+    fn resolve(lua: State, path: &str) -> Result<LuaRef> {
+        lua.push_path(path)?;
+        if !lua.is_function(-1) {
+            lua.pop();
+            bail!("{path} isn't a function");
+        }
+        Ok(LuaRef::new(lua))
+    }
+
+    /// Resolves the function again, for when it may have been replaced. Keeps the previous one if it can't be found anymore.
+    pub fn refresh(&mut self, lua: State) -> Result<()> {
+        let func = Self::resolve(lua, &self.path)?;
+        std::mem::replace(&mut self.func, func).release(lua);
+        Ok(())
+    }
+
+    /// The dotted path the function was resolved from.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Starts a call to the function, see `LuaCall`.
+    pub fn call(&self, lua: State) -> LuaCall {
+        let call = LuaCall::new(lua, self.path.clone());
+        self.func.push(lua);
+        call
+    }
+}
+
+/// Results of a `LuaCall`: `()`, any `LuaCheck` type, or a tuple of them.
+pub trait LuaCallResults: Sized {
+    /// How many results are requested from the call.
+    const COUNT: i32;
+
+    /// Reads the results, the first one being at stack index `first`.
+    fn read(l: State, first: i32, path: &str) -> Result<Self>;
+}
+
+impl LuaCallResults for () {
+    const COUNT: i32 = 0;
+
+    #[inline(always)]
+    fn read(_l: State, _first: i32, _path: &str) -> Result<Self> {
+        Ok(())
+    }
+}
+
+fn read_result<T: LuaCheck>(l: State, index: i32, n: usize, path: &str) -> Result<T> {
+    T::lua_check(l, index).map_err(|_| {
+        anyhow!(
+            "result #{n} of {path} should be a {}, got {}",
+            std::any::type_name::<T>(),
+            l.lua_type_name(l.lua_type(index))
+        )
+    })
+}
+
+impl<T: LuaCheck> LuaCallResults for T {
+    const COUNT: i32 = 1;
+
+    #[inline(always)]
+    fn read(l: State, first: i32, path: &str) -> Result<Self> {
+        read_result(l, first, 1, path)
+    }
+}
+
+macro_rules! impl_call_results {
+    ($count:literal; $($name:ident $i:literal),+) => {
+        impl<$($name: LuaCheck),+> LuaCallResults for ($($name,)+) {
+            const COUNT: i32 = $count;
+
+            #[inline(always)]
+            fn read(l: State, first: i32, path: &str) -> Result<Self> {
+                Ok(($(read_result::<$name>(l, first + $i, $i + 1, path)?,)+))
+            }
+        }
+    };
+}
+impl_call_results!(1; A 0);
+impl_call_results!(2; A 0, B 1);
+impl_call_results!(3; A 0, B 1, C 2);
+impl_call_results!(4; A 0, B 1, C 2, D 3);
+impl_call_results!(5; A 0, B 1, C 2, D 3, E 4);
+impl_call_results!(6; A 0, B 1, C 2, D 3, E 4, F 5);
@@ -0,0 +1,125 @@
+use std::{ffi::c_void, ptr::NonNull};
+
+use super::{HandleLuaFunctionReturn, LuaCStr, LuaFunction, State};
+
+/// Turns a closure that captures nothing into a Lua C function, e.g. to register it with `lua_methods!` or push it with `push_function`.
+///
+/// Closures that capture something don't compile; state goes in upvalues instead (see `push_closure`).
+///
+/// ```
+/// use gmod::lua::{function_from_closure, State};
+///
+/// let double = function_from_closure(|lua: State| {
+///     lua.push_number(lua.to_number(1) * 2.0);
+///     1
+/// });
+/// ```
+pub fn function_from_closure<F>(f: F) -> LuaFunction
+where
+    F: Fn(State) -> i32 + Copy + 'static,
+{
+    const {
+        assert!(
+            std::mem::size_of::<F>() == 0,
+            "function_from_closure only takes closures that capture nothing"
+        )
+    };
+    let _ = f;
+    trampoline::<F>
+}
+
+extern "C-unwind" fn trampoline<F>(lua: State) -> i32
+where
+    F: Fn(State) -> i32 + Copy + 'static,
+{
+    // `F` has no data and was handed to `function_from_closure`, so any instance of it is that closure
+    let f = unsafe { NonNull::<F>::dangling().read() };
+    f(lua)
+}
+
+/// Metatable of the userdata holding the closures pushed by `push_rust_closure`.
+const CLOSURE_METATABLE: LuaCStr = c"gmod_rs_closure";
+
+/// A closure stored in userdata. `drop` comes first, so the `__gc` shared by every closure type can find it.
+#[repr(C)]
+struct RustClosure<F> {
+    drop: unsafe fn(*mut c_void),
+    f: F,
+}
+
+unsafe fn drop_closure<F>(ptr: *mut c_void) {
+    std::ptr::drop_in_place(ptr as *mut RustClosure<F>);
+}
+
+impl State {
+    /// Pushes a Rust closure as a Lua function. Unlike `function_from_closure`, the closure can capture values, which are dropped once Lua garbage collects the function.
+    ///
+    /// The closure returns anything a `#[lua_function]` can, and panics are caught the same way. It's `Fn` because Lua can call it again while it runs, so state that changes goes in a `Cell` or `RefCell`.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let prefix = format!("[{}] ", module_name);
+    /// let count = Cell::new(0);
+    /// lua.push_rust_closure(move |lua| -> anyhow::Result<i32> {
+    ///     count.set(count.get() + 1);
+    ///     println!("{prefix}{} ({})", lua.check_string(1)?, count.get());
+    ///     Ok(0)
+    /// });
+    /// lua.set_global(c"MyModuleLog");
+    /// ```
+    pub fn push_rust_closure<F, R>(&self, f: F)
+    where
+        F: Fn(State) -> R + 'static,
+        R: HandleLuaFunctionReturn,
+    {
+        self.new_userdata(
+            RustClosure {
+                drop: drop_closure::<F>,
+                f,
+            },
+            None,
+        );
+        // true if it already existed
+        if !self.new_metatable(CLOSURE_METATABLE) {
+            self.push_function(closure_gc);
+            self.set_field(-2, c"__gc");
+        }
+        unsafe { self.set_metatable(-2) };
+        self.push_closure(closure_trampoline::<F, R>, 1);
+    }
+}
+
+extern "C-unwind" fn closure_trampoline<F, R>(lua: State) -> i32
+where
+    F: Fn(State) -> R + 'static,
+    R: HandleLuaFunctionReturn,
+{
+    // the userdata is an upvalue of the function being called, so it's alive
+    let closure = unsafe { &*(lua.to_userdata(lua.upvalue_index(1)) as *const RustClosure<F>) };
+    match crate::panic::catch(|| (closure.f)(lua)) {
+        Ok(ret) => ret.handle_result(lua),
+        Err(panic) => Err::<i32, _>(panic).handle_result(lua),
+    }
+}
+
+extern "C-unwind" fn closure_gc(lua: State) -> i32 {
+    let ptr = lua.to_userdata(1);
+    if !ptr.is_null() {
+        unsafe {
+            let drop = (*(ptr as *const RustClosure<()>)).drop;
+            drop(ptr);
+        }
+    }
+    0
+}
+
+/// Stops Lua from dropping the closures pushed by `push_rust_closure`, whose `__gc` would call into the module after it's unloaded. The closures still alive are leaked. This is called for you by `#[gmod13_close]`.
+pub fn unload_closures(lua: State) {
+    lua.get_metatable_name(CLOSURE_METATABLE);
+    if lua.is_table(-1) {
+        lua.push_nil();
+        lua.set_field(-2, c"__gc");
+    }
+    lua.pop();
+}
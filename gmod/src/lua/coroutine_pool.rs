@@ -0,0 +1,156 @@
+use std::sync::Mutex;
+
+use super::{LuaError, LuaReference, State, LUA_MULTRET, LUA_OK, LUA_YIELD};
+
+/// The outcome of a call made through a [`CoroutinePool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PooledCall {
+    /// The function returned, and this many results were pushed onto the calling stack.
+    Returned(i32),
+
+    /// The function yielded. The coroutine is detached from the pool and left to whoever resumes it.
+    Yielded,
+}
+
+struct PooledThread {
+    thread_ref: LuaReference,
+    thread: usize,
+}
+
+/// A pool of reusable Lua coroutines for running callbacks in isolation from the calling stack.
+///
+/// A callback that yields or errors only affects its own coroutine; errored coroutines are discarded and yielded ones are detached from the pool.
+///
+/// The pool must only be used from the Lua thread, and should be cleared with `clear` before the module is closed.
+///
+/// `hook::call` and `EventLog` replays can be made to dispatch through a pool with `hook::set_coroutine_pool` and `EventLog::set_coroutine_pool`.
+///
+/// ## Example
+///
+/// ```ignore
+/// static POOL: CoroutinePool = CoroutinePool::new(8);
+///
+/// lua.get_global(c"print");
+/// lua.push_string("Hello from a coroutine!");
+/// POOL.call(lua, 1, 0)?;
+/// ```
+pub struct CoroutinePool {
+    max_size: usize,
+    threads: Mutex<Vec<PooledThread>>,
+}
+
+impl CoroutinePool {
+    /// Creates a pool that keeps at most `max_size` idle coroutines around for reuse.
+    pub const fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            threads: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the number of idle coroutines in the pool.
+    pub fn idle(&self) -> usize {
+        self.threads.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    fn acquire(&self, lua: State) -> PooledThread {
+        if let Some(thread) = self.threads.lock().unwrap_or_else(|e| e.into_inner()).pop() {
+            return thread;
+        }
+
+        let thread = lua.coroutine_new();
+        PooledThread {
+            thread_ref: lua.reference(),
+            thread: thread.0 as usize,
+        }
+    }
+
+    fn release(&self, lua: State, thread: PooledThread) {
+        let mut threads = self.threads.lock().unwrap_or_else(|e| e.into_inner());
+        if threads.len() < self.max_size {
+            threads.push(thread);
+        } else {
+            drop(threads);
+            lua.dereference(thread.thread_ref);
+        }
+    }
+
+    /// Calls a function inside a pooled coroutine.
+    ///
+    /// Like `pcall`, the function and then its `nargs` arguments must be pushed onto the stack beforehand, and they are popped.
+    ///
+    /// If the function returns, `nresults` results are pushed onto the stack (or all of them, if `nresults` is `LUA_MULTRET`).
+    pub fn call(&self, lua: State, nargs: i32, nresults: i32) -> Result<PooledCall, LuaError> {
+        let pooled = self.acquire(lua);
+        let thread = State(pooled.thread as *mut _);
+        if !thread.check_stack(nargs + 1) {
+            lua.pop_n(nargs + 1);
+            self.release(lua, pooled);
+            return Err(LuaError::RuntimeError(Some("stack overflow".to_owned())));
+        }
+
+        lua.coroutine_exchange(thread, nargs + 1);
+
+        match thread.coroutine_resume(nargs) {
+            LUA_OK => {
+                let returned = thread.get_top();
+                let nresults = if nresults == LUA_MULTRET {
+                    returned
+                } else {
+                    nresults
+                };
+
+                if returned > nresults {
+                    thread.pop_n(returned - nresults);
+                }
+                if !lua.check_stack(nresults) {
+                    thread.set_top(0);
+                    self.release(lua, pooled);
+                    return Err(LuaError::RuntimeError(Some(
+                        "stack overflow (no room for the results)".to_owned(),
+                    )));
+                }
+                let moved = returned.min(nresults);
+                thread.coroutine_exchange(lua, moved);
+                for _ in moved..nresults {
+                    lua.push_nil();
+                }
+
+                self.release(lua, pooled);
+                Ok(PooledCall::Returned(nresults))
+            }
+
+            LUA_YIELD => {
+                lua.dereference(pooled.thread_ref);
+                Ok(PooledCall::Yielded)
+            }
+
+            err => {
+                let err = LuaError::from_lua_state(thread, err);
+                lua.dereference(pooled.thread_ref);
+                Err(err)
+            }
+        }
+    }
+
+    /// Same as `call`, but ignores any runtime error and calls `ErrorNoHaltWithStack` instead with the error message.
+    ///
+    /// Returns whether the execution was successful.
+    pub fn call_ignore(&self, lua: State, nargs: i32, nresults: i32) -> bool {
+        match self.call(lua, nargs, nresults) {
+            Ok(_) => true,
+            Err(err) => {
+                lua.error_no_halt(&err.to_string(), None);
+                false
+            }
+        }
+    }
+
+    /// Releases every idle coroutine in the pool.
+    pub fn clear(&self, lua: State) {
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap_or_else(|e| e.into_inner()));
+        for thread in threads {
+            lua.dereference(thread.thread_ref);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! C strings for names only known at runtime, such as field names read from a recording or a config.
+//!
+//! For literals, use `lua_cstr!`, which builds the `CStr` at compile time. For other strings, `cached` keeps short ones in an arena, so that a name used over and over is only allocated once. Long strings and strings past the arena's capacity are allocated every time, so that it can't grow without bound.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::{CStr, CString},
+    sync::Mutex,
+};
+
+/// The longest string kept in the arena, in bytes.
+pub const MAX_CACHED_LEN: usize = 64;
+/// How many strings the arena keeps at most.
+pub const MAX_CACHED: usize = 4096;
+
+/// The arena's strings are leaked, and live until the module is unloaded.
+static ARENA: Mutex<Option<HashMap<Box<str>, &'static CStr>>> = Mutex::new(None);
+
+/// Returns `str` as a C string, cut at its first NUL byte if it has one.
+///
+/// ```
+/// use gmod::lua::cstr;
+///
+/// let name = String::from("SetHealth");
+/// assert_eq!(&*cstr::cached(&name), c"SetHealth");
+/// assert!(matches!(cstr::cached(&name), std::borrow::Cow::Borrowed(_)));
+/// assert_eq!(&*cstr::cached("a\0b"), c"a");
+/// ```
+pub fn cached(str: &str) -> Cow<'static, CStr> {
+    let str = str.split('\0').next().unwrap_or_default();
+    if str.len() > MAX_CACHED_LEN {
+        return Cow::Owned(CString::new(str).expect("NUL bytes were cut"));
+    }
+
+    let mut arena = ARENA.lock().unwrap_or_else(|e| e.into_inner());
+    let arena = arena.get_or_insert_with(HashMap::new);
+    if let Some(cstr) = arena.get(str) {
+        return Cow::Borrowed(cstr);
+    }
+    let cstring = CString::new(str).expect("NUL bytes were cut");
+    if arena.len() >= MAX_CACHED {
+        return Cow::Owned(cstring);
+    }
+    let cstr: &'static CStr = Box::leak(cstring.into_boxed_c_str());
+    arena.insert(str.into(), cstr);
+    Cow::Borrowed(cstr)
+}
@@ -0,0 +1,175 @@
+//! Debug hooks (`lua_sethook`) running Rust code on Lua calls, returns, new lines or every N instructions, e.g. for watchdogs aborting runaway Lua.
+//!
+//! A Lua state has a single debug hook, shared by all its coroutines and with `debug.sethook`, so installing one replaces whatever was there. The hook is removed by `#[gmod13_close]`.
+//!
+//! LuaJIT doesn't run line and count hooks inside compiled code, so a loop that was already compiled won't be interrupted. Flush it first with `jit::flush` (or turn the compiler off) when this matters.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::lua::debug_hook::{self, HookAction, HookEvents};
+//!
+//! let deadline = Instant::now() + Duration::from_secs(1);
+//! debug_hook::set(lua, HookEvents::count(10_000), move |_, _| {
+//!     if Instant::now() > deadline {
+//!         HookAction::Error("script took too long".to_owned())
+//!     } else {
+//!         HookAction::Continue
+//!     }
+//! })?;
+//! lua.call_global("RunUntrustedScript").call::<()>();
+//! debug_hook::clear(lua);
+//! ```
+
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+
+use super::{
+    lua_shared, LuaDebug, LuaHook, State, LUA_HOOKCALL, LUA_HOOKCOUNT, LUA_HOOKLINE, LUA_HOOKRET,
+    LUA_HOOKTAILRET, LUA_MASKCALL, LUA_MASKCOUNT, LUA_MASKLINE, LUA_MASKRET, LUA_SHARED,
+};
+
+type HookCallback = Box<dyn FnMut(State, HookEvent) -> HookAction + Send>;
+
+static CALLBACK: Mutex<Option<HookCallback>> = Mutex::new(None);
+
+/// The events a hook runs on. Combine them with `|`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HookEvents {
+    mask: i32,
+    count: i32,
+}
+
+impl HookEvents {
+    /// When a function is called.
+    pub const CALL: Self = Self {
+        mask: LUA_MASKCALL,
+        count: 0,
+    };
+
+    /// When a function returns.
+    pub const RETURN: Self = Self {
+        mask: LUA_MASKRET,
+        count: 0,
+    };
+
+    /// When a new line of code is about to run.
+    pub const LINE: Self = Self {
+        mask: LUA_MASKLINE,
+        count: 0,
+    };
+
+    /// Every `instructions` VM instructions.
+    pub const fn count(instructions: u32) -> Self {
+        Self {
+            mask: LUA_MASKCOUNT,
+            count: if instructions > i32::MAX as u32 {
+                i32::MAX
+            } else {
+                instructions as i32
+            },
+        }
+    }
+}
+
+impl std::ops::BitOr for HookEvents {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self {
+            mask: self.mask | rhs.mask,
+            count: self.count.max(rhs.count),
+        }
+    }
+}
+
+/// What triggered the hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Call,
+    /// Also sent for tail calls returning.
+    Return,
+    /// The line about to run.
+    Line(i32),
+    Count,
+}
+
+/// What to do once the hook returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookAction {
+    Continue,
+    /// Raises a Lua error where the hook ran, aborting the running code up to the nearest `pcall`.
+    Error(String),
+}
+
+/// Installs `callback` as the state's debug hook, replacing the current one. Fails if lua_shared doesn't export `lua_sethook`.
+pub fn set<F>(lua: State, events: HookEvents, callback: F) -> Result<()>
+where
+    F: FnMut(State, HookEvent) -> HookAction + Send + 'static,
+{
+    let Some(sethook) = (unsafe { &lua_shared().lua_sethook }) else {
+        bail!("lua_shared doesn't export lua_sethook");
+    };
+    *CALLBACK.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(callback));
+    unsafe { sethook(lua, Some(trampoline), events.mask, events.count) };
+    Ok(())
+}
+
+/// Removes the state's debug hook, if it's one installed with `set`. Called by `#[gmod13_close]`.
+pub fn clear(lua: State) {
+    if !is_set(lua) {
+        return;
+    }
+    if let Some(sethook) = unsafe { &lua_shared().lua_sethook } {
+        unsafe { sethook(lua, None, 0, 0) };
+    }
+    drop(CALLBACK.lock().unwrap_or_else(|e| e.into_inner()).take());
+}
+
+/// Returns whether the state's debug hook is one installed with `set`.
+pub fn is_set(lua: State) -> bool {
+    match unsafe { &lua_shared().lua_gethook } {
+        Some(gethook) => unsafe { gethook(lua) }
+            .is_some_and(|hook| std::ptr::fn_addr_eq(hook, trampoline as LuaHook)),
+        None => CALLBACK.lock().unwrap_or_else(|e| e.into_inner()).is_some(),
+    }
+}
+
+/// Returns the events the state's debug hook runs on, whoever installed it.
+pub fn events(lua: State) -> Option<HookEvents> {
+    unsafe {
+        let mask = (lua_shared().lua_gethookmask.as_ref()?)(lua);
+        let count = (lua_shared().lua_gethookcount.as_ref()?)(lua);
+        Some(HookEvents { mask, count })
+    }
+}
+
+/// Runs the callback, returning the message of the error to raise, if any.
+fn run_callback(lua: State, ar: *mut LuaDebug) -> Option<String> {
+    let ar = unsafe { &*ar };
+    let event = match ar.event {
+        LUA_HOOKCALL => HookEvent::Call,
+        LUA_HOOKRET | LUA_HOOKTAILRET => HookEvent::Return,
+        LUA_HOOKLINE => HookEvent::Line(ar.currentline),
+        LUA_HOOKCOUNT => HookEvent::Count,
+        _ => return None,
+    };
+
+    let mut callback = CALLBACK.lock().unwrap_or_else(|e| e.into_inner());
+    let callback = callback.as_mut()?;
+    match crate::panic::catch(|| callback(lua, event)) {
+        Ok(HookAction::Continue) => None,
+        Ok(HookAction::Error(msg)) => Some(msg),
+        Err(panic) => Some(format!("debug hook {panic}")),
+    }
+}
+
+unsafe extern "C-unwind" fn trampoline(lua: State, ar: *mut LuaDebug) {
+    // nothing may be left to drop once the error unwinds through Lua
+    if let Some(msg) = run_callback(lua, ar) {
+        lua.push_string(&msg);
+        drop(msg);
+        (LUA_SHARED.lua_error)(lua);
+    }
+}
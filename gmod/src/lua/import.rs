@@ -7,12 +7,20 @@ use std::{cell::UnsafeCell, ffi::c_void, mem::MaybeUninit, thread};
 use libloading::{Library, Symbol};
 
 use super::{LuaDebug, LuaError, State as LuaState};
+use crate::OpenGmodLibraryErrs;
 
 pub type LuaSize = usize;
 pub type LuaString = *const std::os::raw::c_char;
 pub type LuaFunction = unsafe extern "C-unwind" fn(state: LuaState) -> i32;
 pub type LuaNumber = f64;
 pub type LuaReference = i32;
+pub type LuaHook = unsafe extern "C-unwind" fn(state: LuaState, ar: *mut LuaDebug);
+pub type LuaWriter = unsafe extern "C-unwind" fn(
+    state: LuaState,
+    p: *const c_void,
+    size: usize,
+    ud: *mut c_void,
+) -> i32;
 
 pub const LUA_REGISTRYINDEX: i32 = -10000;
 pub const LUA_ENVIRONINDEX: i32 = -10001;
@@ -47,6 +55,26 @@ pub const LUA_ERRFILE: i32 = LUA_ERRERR + 1;
 
 pub const LUA_IDSIZE: usize = 60;
 
+pub const LUA_GCSTOP: i32 = 0;
+pub const LUA_GCRESTART: i32 = 1;
+pub const LUA_GCCOLLECT: i32 = 2;
+pub const LUA_GCCOUNT: i32 = 3;
+pub const LUA_GCCOUNTB: i32 = 4;
+pub const LUA_GCSTEP: i32 = 5;
+pub const LUA_GCSETPAUSE: i32 = 6;
+pub const LUA_GCSETSTEPMUL: i32 = 7;
+
+pub const LUA_HOOKCALL: i32 = 0;
+pub const LUA_HOOKRET: i32 = 1;
+pub const LUA_HOOKLINE: i32 = 2;
+pub const LUA_HOOKCOUNT: i32 = 3;
+pub const LUA_HOOKTAILRET: i32 = 4;
+
+pub const LUA_MASKCALL: i32 = 1 << LUA_HOOKCALL;
+pub const LUA_MASKRET: i32 = 1 << LUA_HOOKRET;
+pub const LUA_MASKLINE: i32 = 1 << LUA_HOOKLINE;
+pub const LUA_MASKCOUNT: i32 = 1 << LUA_HOOKCOUNT;
+
 #[repr(C)]
 pub struct LuaReg {
     pub name: LuaString,
@@ -93,16 +121,43 @@ impl LuaSharedInterface {
 		);
     }
 
-    pub(super) unsafe fn load(&self) {
+    pub(super) unsafe fn load(&self) -> Result<(), ImportError> {
         if !(*self.0.get()).is_null() {
             eprintln!("The Lua state has already been initialized!");
-            return;
+            return Ok(());
         }
-        *self.0.get() = Box::into_raw(Box::new(LuaShared::import()));
+        *self.0.get() = Box::into_raw(Box::new(LuaShared::import()?));
         #[cfg(debug_assertions)]
         {
             *self.1.get() = Box::leak(Box::new(thread::current().id()));
         }
+        Ok(())
+    }
+
+    #[cfg(feature = "testing")]
+    pub(crate) unsafe fn load_from(&self, path: &std::ffi::OsStr) -> Result<(), ImportError> {
+        if !(*self.0.get()).is_null() {
+            return Ok(());
+        }
+        *self.0.get() = Box::into_raw(Box::new(LuaShared::import_from(path)?));
+        self.rebind_thread();
+        Ok(())
+    }
+
+    /// Makes the current thread the one allowed to use lua_shared, for tests that each run on their own thread.
+    #[cfg(feature = "testing")]
+    pub(crate) unsafe fn rebind_thread(&self) {
+        #[cfg(debug_assertions)]
+        {
+            if !(*self.1.get()).is_null() {
+                drop(Box::from_raw(*self.1.get()));
+            }
+            *self.1.get() = Box::leak(Box::new(thread::current().id()));
+        }
+    }
+
+    pub(super) fn is_loaded(&self) -> bool {
+        unsafe { !(*self.0.get()).is_null() }
     }
 
     pub(super) unsafe fn unload(&self) {
@@ -151,8 +206,15 @@ pub static mut LUA_SHARED: LuaSharedInterface = LuaSharedInterface(
     UnsafeCell::new(std::ptr::null_mut()),
 );
 
+/// Borrows `LUA_SHARED` without a reference to the `static mut` itself. Loading and unloading only go through the `UnsafeCell`s inside.
+#[inline(always)]
+pub(crate) fn lua_shared() -> &'static LuaSharedInterface {
+    unsafe { &*std::ptr::addr_of!(LUA_SHARED) }
+}
+
 pub struct LuaShared {
     pub(crate) library: &'static libloading::Library,
+    missing: Vec<&'static str>,
     pub lual_newstate: Symbol<'static, unsafe extern "C-unwind" fn() -> LuaState>,
     pub lual_openlibs: Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState)>,
     pub lual_register: Symbol<
@@ -205,6 +267,10 @@ pub struct LuaShared {
     >,
     pub lua_remove: Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, index: i32)>,
     pub lua_gettop: Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> i32>,
+    pub lua_gc:
+        Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, what: i32, data: i32) -> i32>,
+    pub lua_checkstack:
+        Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, extra: i32) -> i32>,
     pub lua_type: Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, index: i32) -> i32>,
     pub lua_typename: Symbol<
         'static,
@@ -287,20 +353,49 @@ pub struct LuaShared {
         Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, size: usize) -> *mut c_void>,
     pub lual_newmetatable:
         Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, name: LuaString) -> i32>,
-    pub lua_resume: Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, narg: i32) -> i32>,
-    pub lua_newthread: Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> LuaState>,
+    pub lua_dump: Symbol<
+        'static,
+        unsafe extern "C-unwind" fn(state: LuaState, writer: LuaWriter, data: *mut c_void) -> i32,
+    >,
+    pub lua_resume:
+        Option<Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, narg: i32) -> i32>>,
+    pub lua_newthread:
+        Option<Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> LuaState>>,
     pub lua_yield:
-        Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, nresults: i32) -> i32>,
-    pub lua_pushthread: Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> i32>,
-    pub lua_tothread:
+        Option<Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, nresults: i32) -> i32>>,
+    pub lua_pushthread:
+        Option<Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> i32>>,
+    pub lua_tothread: Option<
         Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState, index: i32) -> LuaState>,
-    pub lua_status: Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> i32>,
-    pub lua_xmove:
+    >,
+    pub lua_status: Option<Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> i32>>,
+    pub lua_xmove: Option<
         Symbol<'static, unsafe extern "C-unwind" fn(thread1: LuaState, thread2: LuaState, n: i32)>,
-    pub lua_equal: Symbol<
-        'static,
-        unsafe extern "C-unwind" fn(state: LuaState, index1: i32, index2: i32) -> i32,
     >,
+    pub lua_equal: Option<
+        Symbol<
+            'static,
+            unsafe extern "C-unwind" fn(state: LuaState, index1: i32, index2: i32) -> i32,
+        >,
+    >,
+    pub lua_close: Option<Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState)>>,
+    pub lua_sethook: Option<
+        Symbol<
+            'static,
+            unsafe extern "C-unwind" fn(
+                state: LuaState,
+                func: Option<LuaHook>,
+                mask: i32,
+                count: i32,
+            ) -> i32,
+        >,
+    >,
+    pub lua_gethook:
+        Option<Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> Option<LuaHook>>>,
+    pub lua_gethookmask:
+        Option<Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> i32>>,
+    pub lua_gethookcount:
+        Option<Symbol<'static, unsafe extern "C-unwind" fn(state: LuaState) -> i32>>,
 }
 
 unsafe impl Sync for LuaShared {}
@@ -309,141 +404,251 @@ static mut LIBLOADING_LIBRARY: MaybeUninit<Library> = MaybeUninit::uninit();
 impl LuaShared {
     fn unload() {
         unsafe {
-            LIBLOADING_LIBRARY.assume_init_read(); // Drop the library
+            (&raw const LIBLOADING_LIBRARY).cast::<Library>().read(); // Drop the library
         }
     }
 
-    fn import() -> Self {
+    fn import() -> Result<Self, ImportError> {
+        let (library, _path) = unsafe { Self::find_lua_shared() }.map_err(ImportError::Library)?;
+        Self::import_library(library)
+    }
+
+    /// Imports the functions from the library at `path` instead of the game's lua_shared, e.g. a stock LuaJIT build in tests.
+    #[cfg(feature = "testing")]
+    fn import_from(path: &std::ffi::OsStr) -> Result<Self, ImportError> {
+        let library = unsafe { Library::new(path) }.map_err(|err| {
+            let mut errors = crate::OpenGmodLibraryErrs::default();
+            errors.0.insert(
+                Box::leak(path.to_string_lossy().into_owned().into_boxed_str()),
+                err,
+            );
+            ImportError::Library(errors)
+        })?;
+        Self::import_library(library)
+    }
+
+    fn import_library(library: Library) -> Result<Self, ImportError> {
         unsafe {
             let library = {
-                let (library, path) = Self::find_lua_shared();
-                unsafe {
-                    LIBLOADING_LIBRARY.write(library);
-                }
-                LIBLOADING_LIBRARY.assume_init_ref()
+                (&raw mut LIBLOADING_LIBRARY).write(MaybeUninit::new(library));
+                &*(&raw const LIBLOADING_LIBRARY).cast::<Library>()
             };
 
-            macro_rules! find_symbol {
-                ( $symbol:literal ) => {
-                    Self::find_symbol(library, concat!($symbol, "\0").as_bytes())
-                };
+            macro_rules! import {
+                (
+                    required { $($field:ident = $symbol:literal,)+ }
+                    optional { $($optional_field:ident = [$($optional_symbol:literal),+],)+ }
+                ) => {{
+                    $(let $field = Self::find_symbol(library, concat!($symbol, "\0").as_bytes());)+
+                    let missing: Vec<&'static str> = [$(($symbol, $field.is_none())),+]
+                        .into_iter()
+                        .filter_map(|(symbol, missing)| missing.then_some(symbol))
+                        .collect();
+                    if !missing.is_empty() {
+                        Self::unload();
+                        return Err(ImportError::MissingSymbols(missing));
+                    }
+
+                    let mut missing = Vec::new();
+                    $(
+                        // the first name found wins, e.g. GMod's `lua_resume_real` over the stock `lua_resume`
+                        let $optional_field = None$(.or_else(|| Self::find_symbol(library, concat!($optional_symbol, "\0").as_bytes())))+;
+                        if $optional_field.is_none() {
+                            missing.push([$($optional_symbol),+][0]);
+                        }
+                    )+
+
+                    Ok(Self {
+                        $($field: $field.unwrap(),)+
+                        $($optional_field,)+
+                        library,
+                        missing,
+                    })
+                }};
             }
 
-            Self {
-                lual_newstate: find_symbol!("luaL_newstate"),
-                lual_openlibs: find_symbol!("luaL_openlibs"),
-                lual_register: find_symbol!("luaL_register"),
-                lua_pushlightuserdata: find_symbol!("lua_pushlightuserdata"),
-                lual_checktype: find_symbol!("luaL_checktype"),
-                lual_loadfile: find_symbol!("luaL_loadfile"),
-                lual_loadstring: find_symbol!("luaL_loadstring"),
-                lual_loadbuffer: find_symbol!("luaL_loadbuffer"),
-                lual_traceback: find_symbol!("luaL_traceback"),
-                lua_getfield: find_symbol!("lua_getfield"),
-                lua_pushvalue: find_symbol!("lua_pushvalue"),
-                lua_pushboolean: find_symbol!("lua_pushboolean"),
-                lua_tolstring: find_symbol!("lua_tolstring"),
-                lua_pcall: find_symbol!("lua_pcall"),
-                lua_cpcall: find_symbol!("lua_cpcall"),
-                lua_remove: find_symbol!("lua_remove"),
-                lua_gettop: find_symbol!("lua_gettop"),
-                lua_type: find_symbol!("lua_type"),
-                lua_typename: find_symbol!("lua_typename"),
-                lua_setfield: find_symbol!("lua_setfield"),
-                lua_call: find_symbol!("lua_call"),
-                lua_createtable: find_symbol!("lua_createtable"),
-                lua_settop: find_symbol!("lua_settop"),
-                lua_replace: find_symbol!("lua_replace"),
-                lua_pushlstring: find_symbol!("lua_pushlstring"),
-                lua_pushcclosure: find_symbol!("lua_pushcclosure"),
-                lua_settable: find_symbol!("lua_settable"),
-                lua_gettable: find_symbol!("lua_gettable"),
-                lua_error: find_symbol!("lua_error"),
-                lua_insert: find_symbol!("lua_insert"),
-                lual_checklstring: find_symbol!("luaL_checklstring"),
-                lua_toboolean: find_symbol!("lua_toboolean"),
-                lua_pushnumber: find_symbol!("lua_pushnumber"),
-                lua_pushnil: find_symbol!("lua_pushnil"),
-                lual_checknumber: find_symbol!("luaL_checknumber"),
-                lua_tonumber: find_symbol!("lua_tonumber"),
-                lual_checkudata: find_symbol!("luaL_checkudata"),
-                lual_ref: find_symbol!("luaL_ref"),
-                lual_unref: find_symbol!("luaL_unref"),
-                lua_setmetatable: find_symbol!("lua_setmetatable"),
-                lua_objlen: find_symbol!("lua_objlen"),
-                lua_rawgeti: find_symbol!("lua_rawgeti"),
-                lua_rawseti: find_symbol!("lua_rawseti"),
-                lua_getmetatable: find_symbol!("lua_getmetatable"),
-                lua_rawequal: find_symbol!("lua_rawequal"),
-                lua_touserdata: find_symbol!("lua_touserdata"),
-                lua_getinfo: find_symbol!("lua_getinfo"),
-                lua_getstack: find_symbol!("lua_getstack"),
-                lua_next: find_symbol!("lua_next"),
-                lua_topointer: find_symbol!("lua_topointer"),
-                lua_newuserdata: find_symbol!("lua_newuserdata"),
-                lual_newmetatable: find_symbol!("luaL_newmetatable"),
-                lua_resume: find_symbol!("lua_resume_real"),
-                lua_newthread: find_symbol!("lua_newthread"),
-                lua_yield: find_symbol!("lua_yield"),
-                lua_pushthread: find_symbol!("lua_pushthread"),
-                lua_tothread: find_symbol!("lua_tothread"),
-                lua_status: find_symbol!("lua_status"),
-                lua_xmove: find_symbol!("lua_xmove"),
-                lua_equal: find_symbol!("lua_equal"),
-                library,
+            import! {
+                required {
+                    lual_newstate = "luaL_newstate",
+                    lual_openlibs = "luaL_openlibs",
+                    lual_register = "luaL_register",
+                    lua_pushlightuserdata = "lua_pushlightuserdata",
+                    lual_checktype = "luaL_checktype",
+                    lual_loadfile = "luaL_loadfile",
+                    lual_loadstring = "luaL_loadstring",
+                    lual_loadbuffer = "luaL_loadbuffer",
+                    lual_traceback = "luaL_traceback",
+                    lua_getfield = "lua_getfield",
+                    lua_pushvalue = "lua_pushvalue",
+                    lua_pushboolean = "lua_pushboolean",
+                    lua_tolstring = "lua_tolstring",
+                    lua_pcall = "lua_pcall",
+                    lua_cpcall = "lua_cpcall",
+                    lua_remove = "lua_remove",
+                    lua_gettop = "lua_gettop",
+                    lua_checkstack = "lua_checkstack",
+                    lua_gc = "lua_gc",
+                    lua_type = "lua_type",
+                    lua_typename = "lua_typename",
+                    lua_setfield = "lua_setfield",
+                    lua_call = "lua_call",
+                    lua_createtable = "lua_createtable",
+                    lua_settop = "lua_settop",
+                    lua_replace = "lua_replace",
+                    lua_pushlstring = "lua_pushlstring",
+                    lua_pushcclosure = "lua_pushcclosure",
+                    lua_settable = "lua_settable",
+                    lua_gettable = "lua_gettable",
+                    lua_error = "lua_error",
+                    lua_insert = "lua_insert",
+                    lual_checklstring = "luaL_checklstring",
+                    lua_toboolean = "lua_toboolean",
+                    lua_pushnumber = "lua_pushnumber",
+                    lua_pushnil = "lua_pushnil",
+                    lual_checknumber = "luaL_checknumber",
+                    lua_tonumber = "lua_tonumber",
+                    lual_checkudata = "luaL_checkudata",
+                    lual_ref = "luaL_ref",
+                    lual_unref = "luaL_unref",
+                    lua_setmetatable = "lua_setmetatable",
+                    lua_objlen = "lua_objlen",
+                    lua_rawgeti = "lua_rawgeti",
+                    lua_rawseti = "lua_rawseti",
+                    lua_getmetatable = "lua_getmetatable",
+                    lua_rawequal = "lua_rawequal",
+                    lua_touserdata = "lua_touserdata",
+                    lua_getinfo = "lua_getinfo",
+                    lua_getstack = "lua_getstack",
+                    lua_next = "lua_next",
+                    lua_topointer = "lua_topointer",
+                    lua_newuserdata = "lua_newuserdata",
+                    lual_newmetatable = "luaL_newmetatable",
+                    lua_dump = "lua_dump",
+                }
+                optional {
+                    lua_resume = ["lua_resume_real", "lua_resume"],
+                    lua_newthread = ["lua_newthread"],
+                    lua_yield = ["lua_yield"],
+                    lua_pushthread = ["lua_pushthread"],
+                    lua_tothread = ["lua_tothread"],
+                    lua_status = ["lua_status"],
+                    lua_xmove = ["lua_xmove"],
+                    lua_equal = ["lua_equal"],
+                    lua_close = ["lua_close"],
+                    lua_sethook = ["lua_sethook"],
+                    lua_gethook = ["lua_gethook"],
+                    lua_gethookmask = ["lua_gethookmask"],
+                    lua_gethookcount = ["lua_gethookcount"],
+                }
             }
         }
     }
 
-    unsafe fn find_symbol<T>(library: &'static Library, name: &[u8]) -> Symbol<'static, T> {
-        match library.get(name) {
-            Ok(symbol) => symbol,
-            Err(err) => panic!(
-                "Failed to find symbol \"{}\"\n{:#?}",
-                String::from_utf8_lossy(name),
-                err
-            ),
-        }
+    /// Returns the optional functions that lua_shared doesn't export.
+    pub fn missing_symbols(&self) -> &[&'static str] {
+        &self.missing
+    }
+
+    /// Whether `lua_xmove` is available, which `State::coroutine_exchange` needs.
+    pub fn supports_xmove(&self) -> bool {
+        self.lua_xmove.is_some()
+    }
+
+    /// Whether `lua_equal` is available, which `State::equal` needs.
+    pub fn supports_equal(&self) -> bool {
+        self.lua_equal.is_some()
+    }
+
+    /// Whether the coroutine functions are available, which `State::coroutine_*`, `State::yieldable` and `CoroutinePool` need.
+    pub fn supports_coroutines(&self) -> bool {
+        self.lua_resume.is_some()
+            && self.lua_newthread.is_some()
+            && self.lua_yield.is_some()
+            && self.lua_pushthread.is_some()
+            && self.lua_tothread.is_some()
+            && self.lua_status.is_some()
+            && self.lua_xmove.is_some()
+    }
+
+    unsafe fn find_symbol<T>(library: &'static Library, name: &[u8]) -> Option<Symbol<'static, T>> {
+        library.get(name).ok()
     }
 
     #[cfg(all(target_os = "windows", target_pointer_width = "64"))]
-    pub unsafe fn find_lua_shared() -> (Library, &'static str) {
-        crate::open_library_raw!("bin/win64/lua_shared.dll").expect("Failed to load lua_shared.dll")
+    pub unsafe fn find_lua_shared() -> Result<(Library, &'static str), OpenGmodLibraryErrs> {
+        crate::__private__gmod_rs__try_chained_open! {
+            crate::open_library_raw!("bin/win64/lua_shared.dll")
+        }
     }
 
     #[cfg(all(target_os = "windows", target_pointer_width = "32"))]
-    pub unsafe fn find_lua_shared() -> (Library, &'static str) {
+    pub unsafe fn find_lua_shared() -> Result<(Library, &'static str), OpenGmodLibraryErrs> {
         crate::__private__gmod_rs__try_chained_open! {
             crate::open_library_raw!("garrysmod/bin/lua_shared.dll"),
             crate::open_library_raw!("bin/lua_shared.dll")
         }
-        .expect("Failed to load lua_shared.dll")
     }
 
     #[cfg(all(target_os = "linux", target_pointer_width = "32"))]
-    pub unsafe fn find_lua_shared() -> (Library, &'static str) {
+    pub unsafe fn find_lua_shared() -> Result<(Library, &'static str), OpenGmodLibraryErrs> {
         crate::__private__gmod_rs__try_chained_open! {
             crate::open_library_raw!("garrysmod/bin/lua_shared_srv.so"),
             crate::open_library_raw!("bin/linux32/lua_shared.so"),
             crate::open_library_raw!("garrysmod/bin/lua_shared.so")
         }
-        .expect("Failed to find lua_shared.so or lua_shared_srv.so")
     }
 
     #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
-    pub unsafe fn find_lua_shared() -> (Library, &'static str) {
-        crate::open_library_raw!("bin/linux64/lua_shared.so").expect("Failed to find lua_shared.so")
+    pub unsafe fn find_lua_shared() -> Result<(Library, &'static str), OpenGmodLibraryErrs> {
+        crate::__private__gmod_rs__try_chained_open! {
+            crate::open_library_raw!("bin/linux64/lua_shared.so")
+        }
     }
 
     #[cfg(all(target_os = "macos", target_pointer_width = "32"))]
-    pub unsafe fn find_lua_shared() -> (Library, &'static str) {
-        crate::open_library_raw!("garrysmod/bin/lua_shared.dylib")
-            .expect("Failed to find lua_shared.dylib")
+    pub unsafe fn find_lua_shared() -> Result<(Library, &'static str), OpenGmodLibraryErrs> {
+        crate::__private__gmod_rs__try_chained_open! {
+            crate::open_library_raw!("garrysmod/bin/lua_shared.dylib")
+        }
     }
 
     #[cfg(all(target_os = "macos", target_pointer_width = "64"))]
-    pub unsafe fn find_lua_shared() -> (Library, &'static str) {
-        crate::open_library_raw!("GarrysMod_Signed.app/Contents/MacOS/lua_shared.dylib")
-            .expect("Failed to find lua_shared.dylib")
+    pub unsafe fn find_lua_shared() -> Result<(Library, &'static str), OpenGmodLibraryErrs> {
+        crate::__private__gmod_rs__try_chained_open! {
+            crate::open_library_raw!("GarrysMod_Signed.app/Contents/MacOS/lua_shared.dylib")
+        }
+    }
+}
+
+/// Calls an optional lua_shared function, panicking with a clear message if it isn't exported. See the `LuaShared::supports_*` functions.
+macro_rules! optional_symbol {
+    ($name:ident) => {
+        match &lua_shared().$name {
+            Some(symbol) => symbol,
+            None => panic!(concat!("lua_shared doesn't export ", stringify!($name))),
+        }
+    };
+}
+pub(crate) use optional_symbol;
+
+/// Why lua_shared couldn't be imported.
+#[derive(Debug)]
+pub enum ImportError {
+    /// The library couldn't be opened.
+    Library(OpenGmodLibraryErrs),
+    /// The library doesn't export functions this crate can't work without.
+    MissingSymbols(Vec<&'static str>),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ImportError::Library(errs) => write!(f, "Failed to load lua_shared: {errs}"),
+            ImportError::MissingSymbols(symbols) => {
+                write!(f, "lua_shared doesn't export {}", symbols.join(", "))
+            }
+        }
     }
 }
+
+impl std::error::Error for ImportError {}
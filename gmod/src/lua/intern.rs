@@ -0,0 +1,94 @@
+//! A cache of Lua strings for names pushed over and over, see `State::push_interned`.
+//!
+//! Every `lua_pushlstring` hashes the whole string to find it in Lua's string table. Interned strings are kept alive in the registry instead, and pushed back from there by their reference, which costs the same however long the string is.
+//!
+//! The references belong to the Lua state, so there's a cache per realm, each cleared by `#[gmod13_close]` of its state.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use super::{task_queue, LuaReference, State};
+
+type Cache = HashMap<(usize, usize), LuaReference>;
+
+/// Per realm. Keyed by the string's address and length rather than its contents, so that finding it doesn't hash it either. The same literal at two addresses is just cached twice.
+static CACHE: Mutex<[Option<Cache>; 3]> = Mutex::new([None, None, None]);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Counters of the interned string cache, returned by `stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InternStats {
+    /// Strings currently cached, in every realm.
+    pub entries: usize,
+    /// Pushes served from the cache.
+    pub hits: u64,
+    /// Pushes that had to create the string, and cache it.
+    pub misses: u64,
+}
+
+pub fn stats() -> InternStats {
+    InternStats {
+        entries: CACHE
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .flatten()
+            .map(HashMap::len)
+            .sum(),
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+    }
+}
+
+/// Releases the strings cached in the realm of `lua`, and resets the counters once no realm has any. Called by `#[gmod13_close]`.
+pub fn clear(lua: State) {
+    let realm = task_queue::realm_of(lua);
+    let (cache, empty) = {
+        let mut caches = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        let cache = caches[realm as usize].take();
+        (cache, caches.iter().all(Option::is_none))
+    };
+    for r#ref in cache.into_iter().flat_map(HashMap::into_values) {
+        lua.dereference(r#ref);
+    }
+    if empty {
+        HITS.store(0, Ordering::Relaxed);
+        MISSES.store(0, Ordering::Relaxed);
+    }
+}
+
+impl State {
+    /// Pushes a string, cached in the registry after the first push. Meant for the names hot paths push thousands of times per second, like field and hook names; anything else should use `push_string`.
+    ///
+    /// Must only be called on the Lua thread.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// lua.push_interned("PlayerTick");
+    /// ```
+    pub fn push_interned(&self, str: &'static str) {
+        let key = (str.as_ptr() as usize, str.len());
+        let realm = task_queue::realm_of(*self);
+        let mut caches = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        let cache = caches[realm as usize].get_or_insert_with(HashMap::new);
+        match cache.get(&key) {
+            Some(&r#ref) => {
+                HITS.fetch_add(1, Ordering::Relaxed);
+                self.from_reference(r#ref);
+            }
+            None => {
+                MISSES.fetch_add(1, Ordering::Relaxed);
+                self.push_string(str);
+                self.push_value(-1);
+                cache.insert(key, self.reference());
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Control of LuaJIT through the `jit` library: turning the compiler on and off (globally or for single functions), flushing traces, and querying the version, architecture and FFI availability.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::lua::jit;
+//!
+//! // this function is called rarely and hits NYI paths, don't bother tracing it
+//! lua.get_global(c"my_slow_function");
+//! jit::off_function(lua, -1, true)?;
+//! lua.pop();
+//!
+//! println!("{} on {}", jit::version(lua)?, jit::arch(lua)?);
+//! ```
+
+use anyhow::{bail, Result};
+
+use super::{LuaCStr, StackIndex, State};
+
+/// Pushes `jit[name]`, failing if the `jit` library or the function is missing.
+fn push_jit_function(lua: State, name: LuaCStr) -> Result<()> {
+    lua.get_global(c"jit");
+    if !lua.is_table(-1) {
+        lua.pop();
+        bail!("the jit library isn't available");
+    }
+    lua.get_field(-1, name);
+    unsafe { lua.remove(-2) };
+    if !lua.is_function(-1) {
+        lua.pop();
+        bail!("jit.{} isn't available", name.to_string_lossy());
+    }
+    Ok(())
+}
+
+/// Calls `jit[name]` with the values pushed by `push_args`, leaving `nresults` results on the stack.
+fn call_jit(
+    lua: State,
+    name: LuaCStr,
+    nresults: i32,
+    push_args: impl FnOnce() -> i32,
+) -> Result<()> {
+    push_jit_function(lua, name)?;
+    let nargs = push_args();
+    if let Err(err) = lua.pcall(nargs, nresults, 0) {
+        lua.pop();
+        bail!("jit.{} failed: {err}", name.to_string_lossy());
+    }
+    Ok(())
+}
+
+/// Calls `jit[name](func, recursive)` for the function at `func`.
+fn call_with_function(lua: State, name: LuaCStr, func: StackIndex, recursive: bool) -> Result<()> {
+    let func = func.abs(lua);
+    if !lua.is_function(func) {
+        bail!(
+            "expected a function, got {}",
+            lua.lua_type_name(lua.lua_type(func))
+        );
+    }
+    call_jit(lua, name, 0, || {
+        lua.push_value(func);
+        lua.push_boolean(recursive);
+        2
+    })
+}
+
+/// Turns the JIT compiler on (`jit.on()`).
+pub fn on(lua: State) -> Result<()> {
+    call_jit(lua, c"on", 0, || 0)
+}
+
+/// Turns the JIT compiler off (`jit.off()`). Already compiled code keeps running until flushed.
+pub fn off(lua: State) -> Result<()> {
+    call_jit(lua, c"off", 0, || 0)
+}
+
+/// Flushes the whole cache of compiled code (`jit.flush()`).
+pub fn flush(lua: State) -> Result<()> {
+    call_jit(lua, c"flush", 0, || 0)
+}
+
+/// Enables compilation of the function at stack index `func` (`jit.on(func, recursive)`). With `recursive`, the functions it defines are enabled too.
+pub fn on_function(lua: State, func: impl Into<StackIndex>, recursive: bool) -> Result<()> {
+    call_with_function(lua, c"on", func.into(), recursive)
+}
+
+/// Disables compilation of the function at stack index `func` and flushes its code (`jit.off(func, recursive)`). With `recursive`, the functions it defines are disabled too.
+pub fn off_function(lua: State, func: impl Into<StackIndex>, recursive: bool) -> Result<()> {
+    call_with_function(lua, c"off", func.into(), recursive)
+}
+
+/// Flushes the compiled code of the function at stack index `func` (`jit.flush(func, recursive)`).
+pub fn flush_function(lua: State, func: impl Into<StackIndex>, recursive: bool) -> Result<()> {
+    call_with_function(lua, c"flush", func.into(), recursive)
+}
+
+/// Returns whether the JIT compiler is on (the first result of `jit.status()`).
+pub fn is_enabled(lua: State) -> Result<bool> {
+    call_jit(lua, c"status", 1, || 0)?;
+    let enabled = lua.get_boolean(-1);
+    lua.pop();
+    Ok(enabled)
+}
+
+/// Reads a string field of the `jit` library.
+fn jit_string(lua: State, field: LuaCStr) -> Result<String> {
+    let _guard = lua.guard();
+    lua.get_global(c"jit");
+    if !lua.is_table(-1) {
+        bail!("the jit library isn't available");
+    }
+    lua.get_field(-1, field);
+    match lua.get_string(-1) {
+        Some(value) => Ok(value.into_owned()),
+        None => bail!("jit.{} isn't available", field.to_string_lossy()),
+    }
+}
+
+/// Returns the LuaJIT version string (`jit.version`), e.g. `"LuaJIT 2.1.0-beta3"`.
+pub fn version(lua: State) -> Result<String> {
+    jit_string(lua, c"version")
+}
+
+/// Returns the LuaJIT version number (`jit.version_num`), e.g. `20100`.
+pub fn version_num(lua: State) -> Result<u32> {
+    let _guard = lua.guard();
+    lua.get_global(c"jit");
+    if !lua.is_table(-1) {
+        bail!("the jit library isn't available");
+    }
+    lua.get_field(-1, c"version_num");
+    if !lua.is_number(-1) {
+        bail!("jit.version_num isn't available");
+    }
+    Ok(lua.to_number(-1) as u32)
+}
+
+/// Returns the target architecture (`jit.arch`), e.g. `"x64"` or `"x86"`.
+pub fn arch(lua: State) -> Result<String> {
+    jit_string(lua, c"arch")
+}
+
+/// Returns the target OS (`jit.os`), e.g. `"Linux"` or `"Windows"`.
+pub fn os(lua: State) -> Result<String> {
+    jit_string(lua, c"os")
+}
+
+/// Returns whether the FFI library is reachable from Lua, either as the global `ffi` or in `package.loaded`. GMod doesn't expose it to Lua in regular builds, so don't rely on it.
+pub fn has_ffi(lua: State) -> bool {
+    let _guard = lua.guard();
+    lua.get_global(c"ffi");
+    if lua.is_table(-1) {
+        return true;
+    }
+    lua.get_global(c"package");
+    if !lua.is_table(-1) {
+        return false;
+    }
+    lua.get_field(-1, c"loaded");
+    if !lua.is_table(-1) {
+        return false;
+    }
+    lua.get_field(-1, c"ffi");
+    lua.is_table(-1)
+}
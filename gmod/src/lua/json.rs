@@ -0,0 +1,146 @@
+//! Converting between JSON and Lua values in Rust, instead of through `util.JSONToTable` and `util.TableToJSON`, which are slower on large payloads and guess at arrays and numbers.
+
+use anyhow::{bail, Result};
+use serde_json::{Map, Number, Value};
+
+use super::{
+    StackIndex, State, LUA_NUMBER_MAX_SAFE_INTEGER, LUA_TBOOLEAN, LUA_TNIL, LUA_TNONE, LUA_TNUMBER,
+    LUA_TSTRING, LUA_TTABLE,
+};
+
+/// How deep values are converted, so a table containing itself isn't converted forever.
+const MAX_DEPTH: usize = 128;
+
+impl State {
+    /// Pushes a JSON value: arrays become sequences, objects tables with string keys, and `null` is `nil`, which leaves a hole in arrays and drops the key from objects.
+    ///
+    /// Fails, pushing nothing, if the value is nested too deeply for the stack.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// lua.push_json(&serde_json::json!({ "players": [1, 2, 3] }))?;
+    /// ```
+    pub fn push_json(&self, value: &Value) -> Result<()> {
+        let top = self.get_top();
+        push_json(*self, value, 0).inspect_err(|_| self.set_top(top))
+    }
+
+    /// Converts the value at `index` to JSON.
+    ///
+    /// Tables with the keys `1` to `n` only are arrays, and other tables are objects, with their number and boolean keys written as strings; an empty table is an empty array. Numbers are integers when they have no fractional part.
+    ///
+    /// Fails for values JSON can't hold: functions, userdata, NaN and infinite numbers, strings that aren't UTF-8, and tables containing themselves.
+    pub fn table_to_json(&self, index: impl Into<StackIndex>) -> Result<Value> {
+        let index = index.into().abs(*self).0;
+        let top = self.get_top();
+        let value = to_json(*self, index, 0);
+        self.set_top(top);
+        value
+    }
+}
+
+fn push_json(lua: State, value: &Value, depth: usize) -> Result<()> {
+    if depth >= MAX_DEPTH || !lua.check_stack(3) {
+        bail!("the JSON value is nested too deeply");
+    }
+    match value {
+        Value::Null => lua.push_nil(),
+        Value::Bool(bool) => lua.push_boolean(*bool),
+        Value::Number(number) => lua.push_number(number.as_f64().unwrap_or(f64::NAN)),
+        Value::String(str) => lua.push_string(str),
+        Value::Array(array) => {
+            lua.create_table(array.len().min(i32::MAX as usize) as i32, 0);
+            for (i, value) in array.iter().enumerate() {
+                push_json(lua, value, depth + 1)?;
+                lua.raw_seti(-2, i as i32 + 1);
+            }
+        }
+        Value::Object(object) => {
+            lua.create_table(0, object.len().min(i32::MAX as usize) as i32);
+            for (key, value) in object {
+                // set with `set_table`, as keys can contain NULs
+                lua.push_string(key);
+                push_json(lua, value, depth + 1)?;
+                lua.set_table(-3);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Converts the value at `index`, an absolute index.
+fn to_json(lua: State, index: i32, depth: usize) -> Result<Value> {
+    Ok(match lua.lua_type(index) {
+        LUA_TNIL | LUA_TNONE => Value::Null,
+        LUA_TBOOLEAN => Value::Bool(lua.get_boolean(index)),
+        LUA_TNUMBER => number_to_json(lua.to_number(index))?,
+        LUA_TSTRING => Value::String(string_to_json(lua, index)?),
+        LUA_TTABLE => {
+            if depth >= MAX_DEPTH || !lua.check_stack(3) {
+                bail!("the table is nested too deeply, or contains itself");
+            }
+            table_to_json(lua, index, depth)?
+        }
+        other => bail!("a {} can't be converted to JSON", lua.lua_type_name(other)),
+    })
+}
+
+fn number_to_json(number: f64) -> Result<Value> {
+    if number.fract() == 0.0 && number.abs() <= LUA_NUMBER_MAX_SAFE_INTEGER as f64 {
+        return Ok(Value::Number(Number::from(number as i64)));
+    }
+    match Number::from_f64(number) {
+        Some(number) => Ok(Value::Number(number)),
+        None => bail!("{number} can't be converted to JSON"),
+    }
+}
+
+/// Only called for strings, as `get_binary_string` would convert numbers in place, which breaks `next`.
+fn string_to_json(lua: State, index: i32) -> Result<String> {
+    let bytes = lua.get_binary_string(index).unwrap_or_default();
+    match std::str::from_utf8(bytes) {
+        Ok(str) => Ok(str.to_owned()),
+        Err(_) => bail!("a string that isn't UTF-8 can't be converted to JSON"),
+    }
+}
+
+fn table_to_json(lua: State, index: i32, depth: usize) -> Result<Value> {
+    let len = lua.len(index).max(0) as usize;
+    let mut keys = 0;
+    let mut sequence = true;
+    lua.push_nil();
+    while unsafe { lua.next(index) } != 0 {
+        keys += 1;
+        sequence = sequence && super::is_sequence_key(lua, len);
+        lua.pop();
+    }
+
+    if sequence && keys == len {
+        let mut array = Vec::with_capacity(len);
+        for i in 1..=len {
+            lua.raw_geti(index, i as i32);
+            array.push(to_json(lua, lua.get_top(), depth + 1)?);
+            lua.pop();
+        }
+        return Ok(Value::Array(array));
+    }
+
+    let mut object = Map::new();
+    lua.push_nil();
+    while unsafe { lua.next(index) } != 0 {
+        let top = lua.get_top();
+        let key = match lua.lua_type(top - 1) {
+            LUA_TSTRING => string_to_json(lua, top - 1)?,
+            LUA_TNUMBER => number_to_json(lua.to_number(top - 1))?.to_string(),
+            LUA_TBOOLEAN => lua.get_boolean(top - 1).to_string(),
+            other => bail!(
+                "a table with a {} key can't be converted to JSON",
+                lua.lua_type_name(other)
+            ),
+        };
+        object.insert(key, to_json(lua, top, depth + 1)?);
+        lua.pop();
+    }
+    Ok(Value::Object(object))
+}
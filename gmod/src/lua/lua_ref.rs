@@ -0,0 +1,114 @@
+use super::{task_queue, LuaReference, StackIndex, State, LUA_NOREF, LUA_REFNIL};
+use crate::realm::Realm;
+
+/// An owned reference to a value in the Lua registry.
+///
+/// The reference is released automatically when dropped. Since that requires access to the Lua state, dropping schedules the release on the next Lua tick via the task queue of the realm it was created in, which makes it safe to drop from any thread.
+///
+/// ## Example
+///
+/// ```ignore
+/// let callback = {
+///     lua.check_function(1)?;
+///     LuaRef::from_index(lua, 1)
+/// };
+///
+/// std::thread::spawn(move || {
+///     gmod::wait_lua_tick(String::new(), move |l| {
+///         callback.push(l);
+///         l.pcall_ignore(0, 0);
+///     });
+/// });
+/// ```
+#[derive(Debug)]
+pub struct LuaRef {
+    r#ref: LuaReference,
+    realm: Realm,
+}
+
+impl LuaRef {
+    /// Pops the value on top of the stack and stores it in the registry.
+    #[inline(always)]
+    pub fn new(lua: State) -> Self {
+        Self {
+            r#ref: lua.reference(),
+            realm: task_queue::realm_of(lua),
+        }
+    }
+
+    /// Stores the value at the given index in the registry, leaving the stack untouched.
+    #[inline(always)]
+    pub fn from_index(lua: State, index: impl Into<StackIndex>) -> Self {
+        lua.push_value(index);
+        Self::new(lua)
+    }
+
+    /// Takes ownership of a raw reference created with `State::reference`.
+    ///
+    /// # Safety
+    /// The reference must be a valid registry reference of `lua` that isn't owned by anything else.
+    #[inline(always)]
+    pub unsafe fn from_raw(lua: State, r#ref: LuaReference) -> Self {
+        Self {
+            r#ref,
+            realm: task_queue::realm_of(lua),
+        }
+    }
+
+    /// Releases ownership of the raw reference without dereferencing it.
+    #[inline(always)]
+    pub fn into_raw(self) -> LuaReference {
+        let r#ref = self.r#ref;
+        std::mem::forget(self);
+        r#ref
+    }
+
+    /// Returns the raw registry reference.
+    #[inline(always)]
+    pub fn raw(&self) -> LuaReference {
+        self.r#ref
+    }
+
+    /// Returns the realm of the state whose registry holds the reference.
+    #[inline(always)]
+    pub fn realm(&self) -> Realm {
+        self.realm
+    }
+
+    /// Returns whether this references `nil`.
+    #[inline(always)]
+    pub fn is_nil(&self) -> bool {
+        self.r#ref == LUA_REFNIL || self.r#ref == LUA_NOREF
+    }
+
+    /// Pushes the referenced value onto the stack. `nil` is pushed if this references `nil`.
+    #[inline(always)]
+    pub fn push(&self, lua: State) {
+        if !lua.from_reference(self.r#ref) {
+            lua.push_nil();
+        }
+    }
+
+    /// Creates a new reference to the same value.
+    pub fn try_clone(&self, lua: State) -> Self {
+        self.push(lua);
+        Self::new(lua)
+    }
+
+    /// Releases the reference immediately instead of on the next Lua tick. Must be called from the Lua thread.
+    #[inline(always)]
+    pub fn release(self, lua: State) {
+        lua.dereference(self.into_raw());
+    }
+}
+
+impl Drop for LuaRef {
+    fn drop(&mut self) {
+        if self.is_nil() {
+            return;
+        }
+        let r#ref = self.r#ref;
+        let _ =
+            task_queue::wait_lua_tick_in(self.realm, String::new(), move |l| l.dereference(r#ref));
+    }
+}
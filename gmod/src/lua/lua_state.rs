@@ -1,10 +1,14 @@
-use std::{backtrace, borrow::Cow, ffi::c_void, mem::MaybeUninit};
+use std::{backtrace, borrow::Cow, ffi::c_void, mem::MaybeUninit, ops::RangeInclusive};
 
 use anyhow::{anyhow, bail, Result};
 use gmod_macros::lua_function;
-use number::LuaPushNumber;
+use number::{LuaCheckNumber, LuaPushNumber};
 
-use crate::{lua::*, rstr, userdata::TaggedUserData};
+use crate::{
+    lua::*,
+    rstr,
+    userdata::{Angle, CoercibleUserData, TaggedUserData, Vector},
+};
 
 pub type LuaCStr<'a> = &'a std::ffi::CStr;
 
@@ -54,7 +58,8 @@ impl LuaState {
     /// Returns the Lua string as a slice of bytes.
     ///
     /// Returns None if the value at the given index is not convertible to a string.
-    pub fn get_binary_string(&self, index: i32) -> Option<&[u8]> {
+    pub fn get_binary_string(&self, index: impl Into<StackIndex>) -> Option<&[u8]> {
+        let index = index.into().0;
         if !self.is_string(index) {
             return None;
         }
@@ -75,18 +80,22 @@ impl LuaState {
     /// This is a lossy operation, and will replace any invalid UTF-8 sequences with the Unicode replacement character. See the documentation for `String::from_utf8_lossy` for more information.
     ///
     /// If you need raw data, use `get_binary_string`.
-    pub fn get_string(&self, index: i32) -> Option<Cow<'_, str>> {
+    pub fn get_string(&self, index: impl Into<StackIndex>) -> Option<Cow<'_, str>> {
+        let index = index.into().0;
         let str = self.get_binary_string(index)?;
         Some(String::from_utf8_lossy(str))
     }
 
-    pub fn get_string_unchecked(&self, index: i32) -> Cow<'_, str> {
+    pub fn get_string_unchecked(&self, index: impl Into<StackIndex>) -> Cow<'_, str> {
+        let index = index.into().0;
+        strict!(strict::string(*self, "get_string_unchecked", index));
         let str = self.get_binary_string(index).unwrap();
         String::from_utf8_lossy(str)
     }
 
     /// Returns the name of the type of the value at the given index.
-    pub unsafe fn get_type(&self, index: i32) -> &str {
+    pub unsafe fn get_type(&self, index: impl Into<StackIndex>) -> &str {
+        let index = index.into().0;
         let lua_type = (LUA_SHARED.lua_type)(*self, index);
         let lua_type_str_ptr = (LUA_SHARED.lua_typename)(*self, lua_type);
         let lua_type_str = std::ffi::CStr::from_ptr(lua_type_str_ptr);
@@ -98,8 +107,73 @@ impl LuaState {
         unsafe { (LUA_SHARED.lua_gettop)(*self) }
     }
 
+    /// Grows the stack to fit `extra` more values, returning false if it can't grow that much (LuaJIT stops at about 8000 values).
+    ///
+    /// A function called by Lua only has room for 20 values (`LUA_MINSTACK`) beyond its arguments, and pushing past the end of the stack corrupts memory instead of raising an error. Code pushing an unbounded number of values, like the elements of a nested table, must check first.
     #[inline(always)]
-    pub fn get_userdata<'a, T>(&self, idx: i32, meta_name: Option<LuaCStr>) -> Result<&'a mut T> {
+    pub fn check_stack(&self, extra: i32) -> bool {
+        unsafe { (LUA_SHARED.lua_checkstack)(*self, extra) != 0 }
+    }
+
+    /// Same as `check_stack`, but returns an error saying how full the stack is.
+    pub fn ensure_stack(&self, extra: i32) -> Result<()> {
+        if self.check_stack(extra) {
+            Ok(())
+        } else {
+            bail!(
+                "stack overflow (couldn't fit {extra} more values on a stack of {})",
+                self.get_top()
+            )
+        }
+    }
+
+    /// Runs a full garbage collection cycle.
+    pub fn gc_collect(&self) {
+        unsafe { (LUA_SHARED.lua_gc)(*self, LUA_GCCOLLECT, 0) };
+    }
+
+    /// Runs an incremental step of garbage collection, as if `kb` kilobytes were allocated (0 for a single basic step). Returns whether the step finished a cycle.
+    pub fn gc_step(&self, kb: i32) -> bool {
+        unsafe { (LUA_SHARED.lua_gc)(*self, LUA_GCSTEP, kb) != 0 }
+    }
+
+    /// Returns the memory used by Lua, in kilobytes.
+    pub fn gc_count_kb(&self) -> f64 {
+        let (kb, bytes) = unsafe {
+            (
+                (LUA_SHARED.lua_gc)(*self, LUA_GCCOUNT, 0),
+                (LUA_SHARED.lua_gc)(*self, LUA_GCCOUNTB, 0),
+            )
+        };
+        kb as f64 + bytes as f64 / 1024.0
+    }
+
+    /// Stops the garbage collector until `gc_restart`. Memory keeps growing in the meantime.
+    pub fn gc_stop(&self) {
+        unsafe { (LUA_SHARED.lua_gc)(*self, LUA_GCSTOP, 0) };
+    }
+
+    pub fn gc_restart(&self) {
+        unsafe { (LUA_SHARED.lua_gc)(*self, LUA_GCRESTART, 0) };
+    }
+
+    /// Sets how long the collector waits before starting a new cycle, as a percentage of the memory in use after the last one (200 waits for it to double). Returns the previous value.
+    pub fn gc_set_pause(&self, percent: i32) -> i32 {
+        unsafe { (LUA_SHARED.lua_gc)(*self, LUA_GCSETPAUSE, percent) }
+    }
+
+    /// Sets how fast the collector runs relative to allocations, as a percentage (200 collects twice as fast as memory is allocated). Returns the previous value.
+    pub fn gc_set_stepmul(&self, percent: i32) -> i32 {
+        unsafe { (LUA_SHARED.lua_gc)(*self, LUA_GCSETSTEPMUL, percent) }
+    }
+
+    #[inline(always)]
+    pub fn get_userdata<'a, T>(
+        &self,
+        idx: impl Into<StackIndex>,
+        meta_name: Option<LuaCStr>,
+    ) -> Result<&'a mut T> {
+        let idx = idx.into().0;
         if !self.is_userdata(idx) {
             bail!(
                 "expected a userdata{}",
@@ -130,7 +204,7 @@ impl LuaState {
         }
 
         let alignment = std::mem::align_of::<T>();
-        if (ud as usize) % alignment != 0 {
+        if !(ud as usize).is_multiple_of(alignment) {
             bail!("invalid userdata pointer alignment");
         }
 
@@ -144,7 +218,9 @@ impl LuaState {
     ///
     /// Use `dereference` to free the reference from the registry table.
     pub fn reference(&self) -> LuaReference {
-        unsafe { (LUA_SHARED.lual_ref)(*self, LUA_REGISTRYINDEX) }
+        let r#ref = unsafe { (LUA_SHARED.lual_ref)(*self, LUA_REGISTRYINDEX) };
+        record!(*self, Reference(r#ref));
+        r#ref
     }
 
     #[inline(always)]
@@ -152,6 +228,7 @@ impl LuaState {
         if r#ref == LUA_REFNIL || r#ref == LUA_NOREF {
             return;
         }
+        record!(*self, Unreference(r#ref));
         unsafe { (LUA_SHARED.lual_unref)(*self, LUA_REGISTRYINDEX, r#ref) }
     }
 
@@ -166,37 +243,44 @@ impl LuaState {
 
     #[inline(always)]
     /// You may be looking for `is_none_or_nil`
-    pub fn is_nil(&self, index: i32) -> bool {
+    pub fn is_nil(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_type)(*self, index) == LUA_TNIL }
     }
 
     #[inline(always)]
-    pub fn is_none(&self, index: i32) -> bool {
+    pub fn is_none(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_type)(*self, index) == LUA_TNONE }
     }
 
     #[inline(always)]
-    pub fn is_none_or_nil(&self, index: i32) -> bool {
+    pub fn is_none_or_nil(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe { self.is_nil(index) || self.is_none(index) }
     }
 
     #[inline(always)]
-    pub fn is_function(&self, index: i32) -> bool {
+    pub fn is_function(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_type)(*self, index) == LUA_TFUNCTION }
     }
 
     #[inline(always)]
-    pub fn is_table(&self, index: i32) -> bool {
+    pub fn is_table(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_type)(*self, index) == LUA_TTABLE }
     }
 
     #[inline(always)]
-    pub fn is_boolean(&self, index: i32) -> bool {
+    pub fn is_boolean(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_type)(*self, index) == LUA_TBOOLEAN }
     }
 
     #[inline(always)]
-    pub fn is_userdata(&self, index: i32) -> bool {
+    pub fn is_userdata(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe {
             let ty = (LUA_SHARED.lua_type)(*self, index);
             ty == LUA_TUSERDATA || ty == LUA_TLIGHTUSERDATA
@@ -204,37 +288,76 @@ impl LuaState {
     }
 
     #[inline(always)]
-    pub fn is_string(&self, index: i32) -> bool {
+    pub fn is_string(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_type)(*self, index) == LUA_TSTRING }
     }
 
     #[inline(always)]
-    pub fn is_number(&self, index: i32) -> bool {
+    pub fn is_number(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_type)(*self, index) == LUA_TNUMBER }
     }
 
     #[inline(always)]
-    pub unsafe fn remove(&self, index: i32) {
+    pub unsafe fn remove(&self, index: impl Into<StackIndex>) {
+        let index = index.into().0;
+        record!(*self, Remove(index));
+        strict!(strict::index(*self, "remove", index));
         (LUA_SHARED.lua_remove)(*self, index)
     }
 
     #[inline(always)]
-    pub fn push_value(&self, index: i32) {
+    pub fn push_value(&self, index: impl Into<StackIndex>) {
+        let index = index.into().0;
+        record!(*self, PushValue(index));
+        strict!(strict::index(*self, "push_value", index));
         unsafe { (LUA_SHARED.lua_pushvalue)(*self, index) }
     }
 
     #[inline(always)]
     pub fn push_lightuserdata(&self, data: *mut c_void) {
+        record!(*self, PushLightUserdata);
         unsafe { (LUA_SHARED.lua_pushlightuserdata)(*self, data) }
     }
 
     #[inline(always)]
-    pub fn get_field(&self, index: i32, k: LuaCStr) {
+    pub fn get_field(&self, index: impl Into<StackIndex>, k: LuaCStr) {
+        let index = index.into().0;
+        record!(*self, GetField(index, k.to_string_lossy().into_owned()));
+        strict!(strict::index(*self, "get_field", index));
         unsafe { (LUA_SHARED.lua_getfield)(*self, index, k.as_ptr()) };
     }
 
+    /// Pushes any value that implements `LuaPush` (numbers, strings, booleans, vectors, angles, options).
+    #[inline(always)]
+    pub fn push<T: LuaPush>(&self, value: T) {
+        value.lua_push(*self);
+    }
+
+    /// Pushes `{ ok = true, value = value }`, the success half of the result table convention. See `push_err`.
+    pub fn push_ok<T: LuaPush>(&self, value: T) {
+        self.create_table(0, 2);
+        self.push_boolean(true);
+        self.set_field(-2, c"ok");
+        value.lua_push(*self);
+        self.set_field(-2, c"value");
+    }
+
+    /// Pushes `{ ok = false, code = string, message = string }`, for functions that return errors to Lua instead of raising them. See `gmod::error`.
+    pub fn push_err(&self, code: crate::error::ErrorCode, message: &str) {
+        self.create_table(0, 3);
+        self.push_boolean(false);
+        self.set_field(-2, c"ok");
+        self.push_string(code.as_str());
+        self.set_field(-2, c"code");
+        self.push_string(message);
+        self.set_field(-2, c"message");
+    }
+
     #[inline(always)]
     pub fn push_boolean(&self, boolean: bool) {
+        record!(*self, PushBool(boolean));
         unsafe { (LUA_SHARED.lua_pushboolean)(*self, if boolean { 1 } else { 0 }) }
     }
 
@@ -253,27 +376,42 @@ impl LuaState {
 
     #[inline(always)]
     pub fn lua_push_number(&self, num: LuaNumber) {
+        record!(*self, PushNumber(num));
         unsafe { (LUA_SHARED.lua_pushnumber)(*self, num) }
     }
 
     #[inline(always)]
     pub fn push_nil(&self) {
+        record!(*self, PushNil);
         unsafe { (LUA_SHARED.lua_pushnil)(*self) }
     }
 
     #[inline(always)]
     pub fn push_thread(&self) -> i32 {
-        unsafe { (LUA_SHARED.lua_pushthread)(*self) }
+        record!(*self, PushThread);
+        unsafe { (optional_symbol!(lua_pushthread))(*self) }
     }
 
     #[inline(always)]
-    pub fn to_thread(&self, index: i32) -> State {
-        unsafe { (LUA_SHARED.lua_tothread)(*self, index) }
+    pub fn to_thread(&self, index: impl Into<StackIndex>) -> State {
+        let index = index.into().0;
+        unsafe { (optional_symbol!(lua_tothread))(*self, index) }
     }
 
     #[inline(always)]
     pub fn pcall(&self, nargs: i32, nresults: i32, errfunc: i32) -> Result<(), LuaError> {
-        let lua_error_code = unsafe { (LUA_SHARED.lua_pcall)(*self, nargs, nresults, errfunc) };
+        strict!({
+            strict::call(*self, "pcall", nargs);
+            if errfunc != 0 {
+                strict::lua_type(*self, "pcall", errfunc, LUA_TFUNCTION);
+            }
+        });
+        let lua_error_code = record_call!(
+            *self,
+            PCall(nargs, nresults, errfunc),
+            unsafe { (LUA_SHARED.lua_pcall)(*self, nargs, nresults, errfunc) },
+            |code| *code == 0
+        );
         if lua_error_code == 0 {
             Ok(())
         } else {
@@ -349,7 +487,12 @@ impl LuaState {
 
     #[inline(always)]
     pub fn cpcall(&self, func: LuaFunction, ud: *mut c_void) -> Result<(), LuaError> {
-        let lua_error_code = unsafe { (LUA_SHARED.lua_cpcall)(*self, func, ud) };
+        let lua_error_code = record_call!(
+            *self,
+            CPCall,
+            unsafe { (LUA_SHARED.lua_cpcall)(*self, func, ud) },
+            |code| *code == 0
+        );
         if lua_error_code == 0 {
             Ok(())
         } else {
@@ -374,6 +517,7 @@ impl LuaState {
 
     pub unsafe fn load_string(&self, src: LuaCStr) -> Result<(), LuaError> {
         let lua_error_code = (LUA_SHARED.lual_loadstring)(*self, src.as_ptr());
+        record!(*self, LoadString(src.to_bytes().to_vec(), lua_error_code == 0));
         if lua_error_code == 0 {
             Ok(())
         } else {
@@ -388,6 +532,7 @@ impl LuaState {
             buff.len(),
             name.as_ptr(),
         );
+        record!(*self, LoadBuffer(buff.to_vec(), name.to_string_lossy().into_owned(), lua_error_code == 0));
         if lua_error_code == 0 {
             Ok(())
         } else {
@@ -396,6 +541,7 @@ impl LuaState {
     }
 
     pub fn lual_traceback(&self, state1: State, level: i32) {
+        record!(*self, Traceback(level));
         unsafe { (LUA_SHARED.lual_traceback)(*self, state1, std::ptr::null(), level) }
     }
 
@@ -410,6 +556,7 @@ impl LuaState {
 
     pub unsafe fn load_file(&self, path: LuaCStr) -> Result<(), LuaError> {
         let lua_error_code = (LUA_SHARED.lual_loadfile)(*self, path.as_ptr());
+        record!(*self, LoadFile(path.to_string_lossy().into_owned(), lua_error_code == 0));
         if lua_error_code == 0 {
             Ok(())
         } else {
@@ -417,6 +564,61 @@ impl LuaState {
         }
     }
 
+    /// Compiles the Lua function at `index` to LuaJIT bytecode, the same as `string.dump`. Returns `None` for C functions and non-function values, which can't be dumped.
+    ///
+    /// The bytecode can be loaded back with `load_bytecode`, but only by the same LuaJIT version on the same architecture (the x86 and x86-64 branches of GMod produce incompatible bytecode). Upvalues aren't saved.
+    pub fn dump_function(&self, index: impl Into<StackIndex>) -> Option<Vec<u8>> {
+        let index = index.into().0;
+        unsafe extern "C-unwind" fn writer(
+            _state: LuaState,
+            p: *const c_void,
+            size: usize,
+            ud: *mut c_void,
+        ) -> i32 {
+            if size != 0 {
+                let buf = &mut *(ud as *mut Vec<u8>);
+                buf.extend_from_slice(std::slice::from_raw_parts(p as *const u8, size));
+            }
+            0
+        }
+
+        if !self.is_function(index) {
+            return None;
+        }
+
+        let mut buf = Vec::new();
+        self.push_value(index);
+        let status = unsafe {
+            (LUA_SHARED.lua_dump)(*self, writer, &mut buf as *mut Vec<u8> as *mut c_void)
+        };
+        self.pop();
+
+        (status == 0).then_some(buf)
+    }
+
+    /// Loads a chunk of LuaJIT bytecode produced by `dump_function` or `string.dump` and pushes it as a function. Plain source code is rejected with a `SyntaxError`, use `load_buffer` for it.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// // compiled at build time with `luajit -b init.lua init.luac`
+    /// static INIT: &[u8] = include_bytes!("../lua/init.luac");
+    ///
+    /// unsafe { lua.load_bytecode(INIT, c"@mymodule/init.lua")? };
+    /// lua.call(0, 0);
+    /// ```
+    ///
+    /// # Safety
+    /// LuaJIT doesn't verify bytecode, so malformed or malicious bytecode can corrupt memory. Only load bytecode produced by the same LuaJIT build from trusted sources, e.g. embedded in the module at compile time.
+    pub unsafe fn load_bytecode(&self, bytecode: &[u8], name: LuaCStr) -> Result<(), LuaError> {
+        if !bytecode.starts_with(LUAJIT_BYTECODE_HEADER) {
+            return Err(LuaError::SyntaxError(Some(
+                "not a LuaJIT bytecode chunk".to_owned(),
+            )));
+        }
+        self.load_buffer(bytecode, name)
+    }
+
     #[inline(always)]
     pub fn pop(&self) {
         self.pop_n(1);
@@ -424,16 +626,20 @@ impl LuaState {
 
     #[inline(always)]
     pub fn pop_n(&self, count: i32) {
+        strict!(strict::values(*self, "pop_n", count));
         self.set_top(-count - 1);
     }
 
     #[inline(always)]
-    pub fn set_top(&self, index: i32) {
+    pub fn set_top(&self, index: impl Into<StackIndex>) {
+        let index = index.into().0;
+        record!(*self, SetTop(index));
         unsafe { (LUA_SHARED.lua_settop)(*self, index) }
     }
 
     #[inline(always)]
-    pub fn lua_type(&self, index: i32) -> i32 {
+    pub fn lua_type(&self, index: impl Into<StackIndex>) -> i32 {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_type)(*self, index) }
     }
 
@@ -447,32 +653,43 @@ impl LuaState {
     }
 
     #[inline(always)]
-    pub unsafe fn replace(&self, index: i32) {
+    pub unsafe fn replace(&self, index: impl Into<StackIndex>) {
+        let index = index.into().0;
+        record!(*self, Replace(index));
+        strict!({
+            strict::index(*self, "replace", index);
+            strict::values(*self, "replace", 1);
+        });
         (LUA_SHARED.lua_replace)(*self, index)
     }
 
     #[inline(always)]
     pub unsafe fn push_globals(&self) {
+        record!(*self, PushValue(LUA_GLOBALSINDEX));
         (LUA_SHARED.lua_pushvalue)(*self, LUA_GLOBALSINDEX)
     }
 
     #[inline(always)]
     pub unsafe fn push_registry(&self) {
+        record!(*self, PushValue(LUA_REGISTRYINDEX));
         (LUA_SHARED.lua_pushvalue)(*self, LUA_REGISTRYINDEX)
     }
 
     #[inline(always)]
     pub fn push_string(&self, data: &str) {
+        record!(*self, PushString(data.as_bytes().to_vec()));
         unsafe { (LUA_SHARED.lua_pushlstring)(*self, data.as_ptr() as LuaString, data.len()) }
     }
 
     #[inline(always)]
     pub fn push_binary_string(&self, data: &[u8]) {
+        record!(*self, PushString(data.to_vec()));
         unsafe { (LUA_SHARED.lua_pushlstring)(*self, data.as_ptr() as LuaString, data.len()) }
     }
 
     #[inline(always)]
     pub fn push_function(&self, func: LuaFunction) {
+        record!(*self, PushFunction(0));
         unsafe { (LUA_SHARED.lua_pushcclosure)(*self, func, 0) }
     }
 
@@ -497,6 +714,7 @@ impl LuaState {
             n <= 255,
             "Can't push more than 255 arguments into a closure"
         );
+        record!(*self, PushFunction(n));
         unsafe { (LUA_SHARED.lua_pushcclosure)(*self, func, n) }
     }
 
@@ -527,12 +745,24 @@ impl LuaState {
     }
 
     #[inline(always)]
-    pub fn set_table(&self, index: i32) {
+    pub fn set_table(&self, index: impl Into<StackIndex>) {
+        let index = index.into().0;
+        record!(*self, SetTable(index));
+        strict!({
+            strict::index(*self, "set_table", index);
+            strict::values(*self, "set_table", 2);
+        });
         unsafe { (LUA_SHARED.lua_settable)(*self, index) }
     }
 
     #[inline(always)]
-    pub fn set_field(&self, index: i32, k: LuaCStr) {
+    pub fn set_field(&self, index: impl Into<StackIndex>, k: LuaCStr) {
+        let index = index.into().0;
+        record!(*self, SetField(index, k.to_string_lossy().into_owned()));
+        strict!({
+            strict::index(*self, "set_field", index);
+            strict::values(*self, "set_field", 1);
+        });
         unsafe { (LUA_SHARED.lua_setfield)(*self, index, k.as_ptr()) }
     }
 
@@ -551,11 +781,29 @@ impl LuaState {
     ///
     /// To workaround this, use `pcall_ignore`, which will call `ErrorNoHaltWithStack` instead and allow your code to continue executing.
     pub unsafe fn call(&self, nargs: i32, nresults: i32) {
+        strict!(strict::call(*self, "call", nargs));
+        #[cfg(feature = "record")]
+        if crate::record::is_recording() {
+            // made with pcall while recording, so that an error is recorded before it's raised further
+            let ok = record_call!(
+                *self,
+                Call(nargs, nresults),
+                (LUA_SHARED.lua_pcall)(*self, nargs, nresults, 0) == 0,
+                |ok| *ok
+            );
+            if !ok {
+                (LUA_SHARED.lua_error)(*self);
+            }
+            return;
+        }
         (LUA_SHARED.lua_call)(*self, nargs, nresults)
     }
 
     #[inline(always)]
-    pub fn insert(&self, index: i32) {
+    pub fn insert(&self, index: impl Into<StackIndex>) {
+        let index = index.into().0;
+        record!(*self, Insert(index));
+        strict!(strict::index(*self, "insert", index));
         unsafe { (LUA_SHARED.lua_insert)(*self, index) }
     }
 
@@ -565,6 +813,7 @@ impl LuaState {
     /// Lua may use these hints to preallocate memory.
     #[inline(always)]
     pub fn create_table(&self, seq_n: i32, hash_n: i32) {
+        record!(*self, CreateTable(seq_n, hash_n));
         unsafe { (LUA_SHARED.lua_createtable)(*self, seq_n, hash_n) }
     }
 
@@ -572,22 +821,31 @@ impl LuaState {
     /// Equivalent to `create_table(0, 0)`
     #[inline(always)]
     pub fn new_table(&self) {
+        record!(*self, CreateTable(0, 0));
         unsafe { (LUA_SHARED.lua_createtable)(*self, 0, 0) }
     }
 
     #[inline(always)]
-    pub fn get_table(&self, index: i32) {
+    pub fn get_table(&self, index: impl Into<StackIndex>) {
+        let index = index.into().0;
+        record!(*self, GetTable(index));
+        strict!({
+            strict::index(*self, "get_table", index);
+            strict::values(*self, "get_table", 1);
+        });
         unsafe { (LUA_SHARED.lua_gettable)(*self, index) }
     }
 
-    pub unsafe fn check_binary_string(&self, arg: i32) -> Result<&[u8]> {
+    pub unsafe fn check_binary_string(&self, arg: impl Into<StackIndex>) -> Result<&[u8]> {
+        let arg = arg.into().0;
         match self.get_binary_string(arg) {
             Some(s) => Ok(s),
             None => bail!(self.tag_error(arg, LUA_TSTRING)),
         }
     }
 
-    pub fn check_string(&self, arg: i32) -> Result<Cow<'_, str>> {
+    pub fn check_string(&self, arg: impl Into<StackIndex>) -> Result<Cow<'_, str>> {
+        let arg = arg.into().0;
         match self.get_string(arg) {
             Some(s) => Ok(s),
             None => bail!(self.tag_error(arg, LUA_TSTRING)),
@@ -616,22 +874,28 @@ impl LuaState {
     // }
 
     #[inline(always)]
-    pub fn raw_equal(&self, a: i32, b: i32) -> bool {
+    pub fn raw_equal(&self, a: impl Into<StackIndex>, b: impl Into<StackIndex>) -> bool {
+        let a = a.into().0;
+        let b = b.into().0;
         unsafe { (LUA_SHARED.lua_rawequal)(*self, a, b) == 1 }
     }
 
     #[inline(always)]
     pub fn get_metatable_name(&self, name: LuaCStr) {
+        record!(*self, GetField(LUA_REGISTRYINDEX, name.to_string_lossy().into_owned()));
         unsafe { (LUA_SHARED.lua_getfield)(*self, LUA_REGISTRYINDEX, name.as_ptr()) }
     }
 
     #[inline(always)]
-    pub fn get_metatable(&self, idx: i32) -> i32 {
+    pub fn get_metatable(&self, idx: impl Into<StackIndex>) -> i32 {
+        let idx = idx.into().0;
+        record!(*self, GetMetatable(idx));
         unsafe { (LUA_SHARED.lua_getmetatable)(*self, idx) }
     }
 
     #[inline(always)]
-    pub fn check_table(&self, arg: i32) -> Result<()> {
+    pub fn check_table(&self, arg: impl Into<StackIndex>) -> Result<()> {
+        let arg = arg.into().0;
         if self.is_table(arg) {
             Ok(())
         } else {
@@ -640,7 +904,8 @@ impl LuaState {
     }
 
     #[inline(always)]
-    pub fn check_function(&self, arg: i32) -> Result<()> {
+    pub fn check_function(&self, arg: impl Into<StackIndex>) -> Result<()> {
+        let arg = arg.into().0;
         if self.is_function(arg) {
             Ok(())
         } else {
@@ -649,7 +914,8 @@ impl LuaState {
     }
 
     #[inline(always)]
-    pub fn check_number(&self, arg: i32) -> Result<f64> {
+    pub fn check_number(&self, arg: impl Into<StackIndex>) -> Result<f64> {
+        let arg = arg.into().0;
         if self.is_number(arg) {
             Ok(self.to_number(arg))
         } else {
@@ -657,8 +923,130 @@ impl LuaState {
         }
     }
 
+    /// Checks that the argument is an integral number that fits in `T`, e.g. `lua.check_integer::<u16>(1)`.
+    pub fn check_integer<T: LuaCheckNumber>(&self, arg: impl Into<StackIndex>) -> Result<T> {
+        self.check_number_in_range(arg, T::MIN..=T::MAX)
+    }
+
+    /// Checks that the argument is a number within `range`. For integer types, the number must also be integral.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let slot = lua.check_number_in_range(1, 1..=6)?; // "bad argument #1 to 'f' (number must be between 1 and 6, got 7)"
+    /// ```
+    pub fn check_number_in_range<T: LuaCheckNumber>(
+        &self,
+        arg: impl Into<StackIndex>,
+        range: RangeInclusive<T>,
+    ) -> Result<T> {
+        let arg = arg.into().0;
+        let n = self.check_number(arg)?;
+        match T::from_lua_number(n).filter(|value| range.contains(value)) {
+            Some(value) => Ok(value),
+            None if T::INTEGER && n.fract() != 0.0 => bail!(self.err_argmsg(
+                arg,
+                &format!("number has no integer representation, got {n}")
+            )),
+            None => bail!(self.err_argmsg(
+                arg,
+                &format!(
+                    "number must be between {} and {}, got {n}",
+                    range.start(),
+                    range.end()
+                )
+            )),
+        }
+    }
+
+    pub fn check_i8(&self, arg: impl Into<StackIndex>) -> Result<i8> {
+        self.check_integer(arg)
+    }
+
+    pub fn check_i16(&self, arg: impl Into<StackIndex>) -> Result<i16> {
+        self.check_integer(arg)
+    }
+
+    pub fn check_i32(&self, arg: impl Into<StackIndex>) -> Result<i32> {
+        self.check_integer(arg)
+    }
+
+    pub fn check_i64(&self, arg: impl Into<StackIndex>) -> Result<i64> {
+        self.check_integer(arg)
+    }
+
+    pub fn check_isize(&self, arg: impl Into<StackIndex>) -> Result<isize> {
+        self.check_integer(arg)
+    }
+
+    pub fn check_u8(&self, arg: impl Into<StackIndex>) -> Result<u8> {
+        self.check_integer(arg)
+    }
+
+    pub fn check_u16(&self, arg: impl Into<StackIndex>) -> Result<u16> {
+        self.check_integer(arg)
+    }
+
+    pub fn check_u32(&self, arg: impl Into<StackIndex>) -> Result<u32> {
+        self.check_integer(arg)
+    }
+
+    pub fn check_u64(&self, arg: impl Into<StackIndex>) -> Result<u64> {
+        self.check_integer(arg)
+    }
+
+    pub fn check_usize(&self, arg: impl Into<StackIndex>) -> Result<usize> {
+        self.check_integer(arg)
+    }
+
+    /// Checks that the argument is one of the strings in `options`, returning its index, like `luaL_checkoption`. If the argument is nil or missing and `default` is given, `default` is looked up instead.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// let mode = lua.check_option(1, Some("read"), &["read", "write", "append"])?; // "bad argument #1 to 'f' (invalid option 'delete')"
+    /// ```
+    pub fn check_option(
+        &self,
+        arg: impl Into<StackIndex>,
+        default: Option<&str>,
+        options: &[&str],
+    ) -> Result<usize> {
+        let arg = arg.into().0;
+        let name = match default {
+            Some(default) if self.is_none_or_nil(arg) => Cow::Borrowed(default),
+            _ => self.check_string(arg)?,
+        };
+        match options.iter().position(|option| *option == name) {
+            Some(index) => Ok(index),
+            None => bail!(self.err_argmsg(arg, &format!("invalid option '{name}'"))),
+        }
+    }
+
+    /// Same as `check_option`, but returns the value paired with the matching name.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// #[derive(Clone, Copy)]
+    /// enum Mode { Read, Write }
+    ///
+    /// let mode = lua.check_enum(1, None, &[("read", Mode::Read), ("write", Mode::Write)])?;
+    /// ```
+    pub fn check_enum<T: Copy>(
+        &self,
+        arg: impl Into<StackIndex>,
+        default: Option<&str>,
+        variants: &[(&str, T)],
+    ) -> Result<T> {
+        let names: Vec<&str> = variants.iter().map(|(name, _)| *name).collect();
+        let index = self.check_option(arg, default, &names)?;
+        Ok(variants[index].1)
+    }
+
     #[inline(always)]
-    pub fn check_boolean(&self, arg: i32) -> Result<bool> {
+    pub fn check_boolean(&self, arg: impl Into<StackIndex>) -> Result<bool> {
+        let arg = arg.into().0;
         if self.is_boolean(arg) {
             Ok(self.get_boolean(arg))
         } else {
@@ -666,55 +1054,179 @@ impl LuaState {
         }
     }
 
+    /// Returns whether the value at the given stack index is a userdata whose metatable is the one registered as `name` (e.g. `c"Vector"`).
+    pub fn is_userdata_of(&self, index: impl Into<StackIndex>, name: LuaCStr) -> bool {
+        let index = index.into().0;
+        if !self.is_userdata(index) || self.get_metatable(index) == 0 {
+            return false;
+        }
+        self.get_metatable_name(name);
+        let res = self.raw_equal(-1, -2);
+        self.pop_n(2);
+        res
+    }
+
+    fn get_tagged_userdata<T: CoercibleUserData + Copy>(
+        &self,
+        index: i32,
+        name: LuaCStr,
+    ) -> Option<T> {
+        if !self.is_userdata_of(index, name) {
+            return None;
+        }
+        let ud = self.to_userdata(index) as *const TaggedUserData;
+        if ud.is_null() {
+            return None;
+        }
+        strict!(strict::aligned(*self, "get_tagged_userdata", ud));
+        unsafe { (*ud).coerce::<T>().ok().copied() }
+    }
+
+    /// Returns the engine object behind the GMod userdata at the given stack index, e.g. the `CUserCmd` given to a `StartCommand` hook, or `None` if the value isn't a `T`.
+    ///
+    /// ## Safety
+    /// The reference must not be used once the object is freed, which happens as soon as the hook or function it was given to returns for most of them.
+    pub unsafe fn get_userdata_object<'a, T: CoercibleUserData>(
+        &self,
+        index: impl Into<StackIndex>,
+    ) -> Option<&'a mut T> {
+        let index = index.into().0;
+        if !self.is_userdata_of(index, T::METATABLE) {
+            return None;
+        }
+        let ud = self.to_userdata(index) as *const TaggedUserData;
+        if ud.is_null() {
+            return None;
+        }
+        (*ud).coerce::<T>().ok()
+    }
+
+    /// Pushes a GMod userdata created by calling the global constructor `name` with `args`, or nil if that fails.
+    fn push_constructed(&self, name: LuaCStr, args: [f32; 3]) {
+        self.get_global(name);
+        for arg in args {
+            self.push_number(arg);
+        }
+        if self.pcall(3, 1, 0).is_err() {
+            self.pop();
+            self.push_nil();
+        }
+    }
+
+    /// Returns the `Vector` at the given stack index, or `None` if the value isn't one.
+    pub fn get_vector(&self, index: impl Into<StackIndex>) -> Option<Vector> {
+        let index = index.into().0;
+        self.get_tagged_userdata(index, c"Vector")
+    }
+
+    pub fn check_vector(&self, arg: impl Into<StackIndex>) -> Result<Vector> {
+        let arg = arg.into().0;
+        match self.get_vector(arg) {
+            Some(vec) => Ok(vec),
+            None => bail!(self.type_error(arg, "Vector")),
+        }
+    }
+
+    /// Pushes a real `Vector` userdata, created with the `Vector` function.
+    pub fn push_vector(&self, vec: Vector) {
+        self.push_constructed(c"Vector", vec.into());
+    }
+
+    /// Returns the `Angle` at the given stack index, or `None` if the value isn't one.
+    pub fn get_angle(&self, index: impl Into<StackIndex>) -> Option<Angle> {
+        let index = index.into().0;
+        self.get_tagged_userdata(index, c"Angle")
+    }
+
+    pub fn check_angle(&self, arg: impl Into<StackIndex>) -> Result<Angle> {
+        let arg = arg.into().0;
+        match self.get_angle(arg) {
+            Some(ang) => Ok(ang),
+            None => bail!(self.type_error(arg, "Angle")),
+        }
+    }
+
+    /// Pushes a real `Angle` userdata, created with the `Angle` function.
+    pub fn push_angle(&self, ang: Angle) {
+        self.push_constructed(c"Angle", ang.into());
+    }
+
     #[inline(always)]
-    pub fn to_number(&self, index: i32) -> f64 {
+    pub fn to_number(&self, index: impl Into<StackIndex>) -> f64 {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_tonumber)(*self, index) }
     }
 
     #[inline(always)]
-    pub fn get_boolean(&self, index: i32) -> bool {
+    pub fn get_boolean(&self, index: impl Into<StackIndex>) -> bool {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_toboolean)(*self, index) == 1 }
     }
 
     #[inline(always)]
-    pub unsafe fn set_metatable(&self, index: i32) -> i32 {
+    pub unsafe fn set_metatable(&self, index: impl Into<StackIndex>) -> i32 {
+        let index = index.into().0;
+        strict!({
+            strict::index(*self, "set_metatable", index);
+            strict::values(*self, "set_metatable", 1);
+        });
+        record!(*self, SetMetatable(index));
         (LUA_SHARED.lua_setmetatable)(*self, index)
     }
 
     #[inline(always)]
     #[allow(clippy::len_without_is_empty)]
-    pub fn len(&self, index: i32) -> i32 {
+    pub fn len(&self, index: impl Into<StackIndex>) -> i32 {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_objlen)(*self, index) }
     }
 
     #[inline(always)]
-    pub fn raw_geti(&self, t: i32, index: i32) {
+    pub fn raw_geti(&self, t: impl Into<StackIndex>, index: i32) {
+        let t = t.into().0;
+        record!(*self, RawGetI(t, index));
+        strict!(strict::index(*self, "raw_geti", t));
         unsafe { (LUA_SHARED.lua_rawgeti)(*self, t, index) };
     }
 
     #[inline(always)]
-    pub fn raw_seti(&self, t: i32, index: i32) {
+    pub fn raw_seti(&self, t: impl Into<StackIndex>, index: i32) {
+        let t = t.into().0;
+        record!(*self, RawSetI(t, index));
+        strict!({
+            strict::index(*self, "raw_seti", t);
+            strict::values(*self, "raw_seti", 1);
+        });
         unsafe { (LUA_SHARED.lua_rawseti)(*self, t, index) }
     }
 
     #[inline(always)]
-    pub unsafe fn next(&self, index: i32) -> i32 {
+    pub unsafe fn next(&self, index: impl Into<StackIndex>) -> i32 {
+        let index = index.into().0;
+        strict!({
+            strict::lua_type(*self, "next", index, LUA_TTABLE);
+            strict::values(*self, "next", 1);
+        });
+        record!(*self, Next(index));
         (LUA_SHARED.lua_next)(*self, index)
     }
 
     #[inline(always)]
-    pub unsafe fn to_pointer(&self, index: i32) -> *const c_void {
+    pub unsafe fn to_pointer(&self, index: impl Into<StackIndex>) -> *const c_void {
+        let index = index.into().0;
         (LUA_SHARED.lua_topointer)(*self, index)
     }
 
     #[inline(always)]
-    pub fn to_userdata(&self, index: i32) -> *mut c_void {
+    pub fn to_userdata(&self, index: impl Into<StackIndex>) -> *mut c_void {
+        let index = index.into().0;
         unsafe { (LUA_SHARED.lua_touserdata)(*self, index) }
     }
 
     #[inline(always)]
     pub fn coroutine_new(&self) -> LuaState {
-        unsafe { (LUA_SHARED.lua_newthread)(*self) }
+        record!(*self, NewThread);
+        unsafe { (optional_symbol!(lua_newthread))(*self) }
     }
 
     #[inline(always)]
@@ -722,19 +1234,26 @@ impl LuaState {
     ///
     /// This function pops `n` values from the stack `self`, and pushes them onto the stack `target_thread`.
     pub fn coroutine_exchange(&self, target_thread: LuaState, n: i32) {
-        unsafe { (LUA_SHARED.lua_xmove)(*self, target_thread, n) }
+        record!(*self, XMove(n));
+        record!(target_thread, Received(n));
+        unsafe { (optional_symbol!(lua_xmove))(*self, target_thread, n) }
     }
 
     #[inline(always)]
     #[must_use]
     pub fn coroutine_yield(&self, nresults: i32) -> i32 {
-        unsafe { (LUA_SHARED.lua_yield)(*self, nresults) }
+        unsafe { (optional_symbol!(lua_yield))(*self, nresults) }
     }
 
     #[inline(always)]
     #[must_use]
     pub fn coroutine_resume(&self, narg: i32) -> i32 {
-        unsafe { (LUA_SHARED.lua_resume)(*self, narg) }
+        record_call!(
+            *self,
+            Resume(narg),
+            unsafe { (optional_symbol!(lua_resume))(*self, narg) },
+            |status| matches!(*status, LUA_OK | LUA_YIELD)
+        )
     }
 
     #[inline(always)]
@@ -769,12 +1288,14 @@ impl LuaState {
 
     #[inline(always)]
     pub fn coroutine_status(&self) -> i32 {
-        unsafe { (LUA_SHARED.lua_status)(*self) }
+        unsafe { (optional_symbol!(lua_status))(*self) }
     }
 
     #[inline(always)]
-    pub fn equal(&self, index1: i32, index2: i32) -> bool {
-        unsafe { (LUA_SHARED.lua_equal)(*self, index1, index2) == 1 }
+    pub fn equal(&self, index1: impl Into<StackIndex>, index2: impl Into<StackIndex>) -> bool {
+        let index1 = index1.into().0;
+        let index2 = index2.into().0;
+        unsafe { (optional_symbol!(lua_equal))(*self, index1, index2) == 1 }
     }
 
     /// Creates a new table in the registry with the given `name` as the key if it doesn't already exist, and pushes it onto the stack.
@@ -782,12 +1303,15 @@ impl LuaState {
     /// Returns if the metatable was already present in the registry.
     #[inline(always)]
     pub fn new_metatable(&self, name: LuaCStr) -> bool {
+        record!(*self, NewMetatable(name.to_string_lossy().into_owned()));
         unsafe { (LUA_SHARED.lual_newmetatable)(*self, name.as_ptr()) == 0 }
     }
 
     pub fn new_userdata<T: Sized>(&self, data: T, metatable: Option<LuaCStr>) -> *mut T {
+        record!(*self, NewUserdata(std::mem::size_of::<T>()));
         unsafe {
             let ptr = (LUA_SHARED.lua_newuserdata)(*self, std::mem::size_of::<T>()) as *mut T;
+            strict!(strict::aligned(*self, "new_userdata", ptr));
 
             debug_assert_eq!(
                 ptr as usize % std::mem::align_of::<T>(),
@@ -797,6 +1321,7 @@ impl LuaState {
 
             if let Some(metatable) = metatable {
                 self.get_metatable_name(metatable);
+                crate::userdata::track_new(*self, metatable);
                 self.set_metatable(-2);
             }
 
@@ -889,7 +1414,8 @@ impl LuaState {
         println!();
     }
 
-    pub unsafe fn dump_val(&self, index: i32) -> String {
+    pub unsafe fn dump_val(&self, index: impl Into<StackIndex>) -> String {
+        let index = index.into().0;
         let lua_type_name = self.lua_type_name(self.lua_type(index));
         match lua_type_name.as_ref() {
             "string" => {
@@ -914,7 +1440,13 @@ impl LuaState {
         }
     }
 
-    pub fn get_field_type_or_nil(&self, idx: i32, name: LuaCStr, ty: i32) -> Result<bool> {
+    pub fn get_field_type_or_nil(
+        &self,
+        idx: impl Into<StackIndex>,
+        name: LuaCStr,
+        ty: i32,
+    ) -> Result<bool> {
+        let idx = idx.into().0;
         self.get_field(idx, name);
 
         if self.is_none_or_nil(-1) {
@@ -948,6 +1480,29 @@ impl LuaState {
         self.type_error(narg, &self.lua_type_name(tag))
     }
 
+    /// Returns the position of the function at the given call stack level as `chunkname:line: `, like `luaL_where`, or an empty string if it isn't known (e.g. for Rust functions). Level 0 is the running function, level 1 is the function that called it.
+    pub fn location(&self, level: i32) -> String {
+        match self.debug_getinfo_at(level, c"Sl") {
+            Some(ar) if ar.currentline > 0 => {
+                format!(
+                    "{}:{}: ",
+                    unsafe { std::ffi::CStr::from_ptr(ar.short_src.as_ptr()) }.to_string_lossy(),
+                    ar.currentline
+                )
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Raises a Lua error prefixed with the file and line of the Lua code calling this function, like the errors raised by Lua's own functions.
+    ///
+    /// Rust destructors between here and the enclosing `pcall` don't run, so prefer returning a `Result` from `#[lua_function]`s, whose errors get the same prefix through `err_argmsg` and the `check_*` methods.
+    pub fn error_with_location<S: AsRef<str>>(&self, msg: S) -> ! {
+        let msg = format!("{}{}", self.location(1), msg.as_ref());
+        self.error(msg)
+    }
+
+    /// Formats an argument error like `luaL_argerror`, prefixed with the file and line of the Lua code calling this function.
     pub fn err_argmsg(&self, mut narg: i32, msg: &str) -> String {
         let mut fname = "?";
         let mut namewhat: Option<&str> = None;
@@ -970,11 +1525,22 @@ impl LuaState {
                 narg -= 1;
                 narg == 0
             } {
-                return format!("bad self parameter in method '{}' ({})", fname, msg);
+                return format!(
+                    "{}bad self parameter in method '{}' ({})",
+                    self.location(1),
+                    fname,
+                    msg
+                );
             }
         }
 
-        format!("bad argument #{} to '{}' ({})", narg, fname, msg)
+        format!(
+            "{}bad argument #{} to '{}' ({})",
+            self.location(1),
+            narg,
+            fname,
+            msg
+        )
     }
 
     pub fn error_no_halt(&self, err: &str, traceback: Option<&str>) {
@@ -0,0 +1,140 @@
+use std::{
+    marker::PhantomData,
+    sync::OnceLock,
+    thread::{self, ThreadId},
+};
+
+use super::State;
+use crate::{lifecycle::ClosedError, realm::Realm};
+
+/// The thread `gmod13_open` ran on, which is the only thread allowed to use Lua.
+static MAIN_THREAD: OnceLock<ThreadId> = OnceLock::new();
+
+/// Records the calling thread as the main thread. Called by `#[gmod13_open]`.
+pub fn set_main_thread() {
+    let _ = MAIN_THREAD.set(thread::current().id());
+}
+
+/// Returns whether this is the thread Lua runs on. Always false before `gmod13_open`.
+pub fn is_main_thread() -> bool {
+    MAIN_THREAD.get() == Some(&thread::current().id())
+}
+
+/// Proof that the current thread is the main thread, which can't leave it as it's neither `Send` nor `Sync`.
+#[derive(Debug, Clone, Copy)]
+pub struct MainThreadToken {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl MainThreadToken {
+    /// Returns a token if this is the main thread.
+    pub fn get() -> Option<Self> {
+        if is_main_thread() {
+            Some(Self {
+                _not_send: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a token, panicking if this isn't the main thread.
+    #[track_caller]
+    pub fn assert() -> Self {
+        match Self::get() {
+            Some(token) => token,
+            None => panic!(
+                "Lua was accessed from {:?}, which isn't the main thread",
+                thread::current().name().unwrap_or("an unnamed thread")
+            ),
+        }
+    }
+
+    /// Returns a token without checking the current thread, for code that knows better, e.g. `lua_function`s called by Lua.
+    pub unsafe fn new_unchecked() -> Self {
+        Self {
+            _not_send: PhantomData,
+        }
+    }
+}
+
+/// A `State` that can be moved to other threads, but only used again on the main thread.
+///
+/// `State` isn't `Send`, as calling Lua from a worker thread corrupts the Lua state and crashes the game, usually much later and far from the culprit. Instead of wrapping it in a type that unsafely implements `Send`, keep a `SendableState`: worker threads can hold it and queue work with `wait_lua_tick`, and getting the `State` back checks the current thread.
+///
+/// It remembers the realm of the state, so that `wait_lua_tick` queues to that realm even when the module is loaded into several states.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::lua::{is_main_thread, set_main_thread, SendableState, State};
+///
+/// # let lua = State(std::ptr::null_mut());
+/// set_main_thread();
+/// let sendable = SendableState::new(lua);
+/// assert_eq!(sendable.assert_main_thread(), lua);
+///
+/// std::thread::spawn(move || {
+///     assert!(!is_main_thread());
+///     assert!(sendable.try_get().is_none());
+///     // sendable.wait_lua_tick(String::new(), |lua| { ... })
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendableState(State, Realm);
+
+// only handed back on the main thread
+unsafe impl Send for SendableState {}
+unsafe impl Sync for SendableState {}
+
+impl SendableState {
+    pub fn new(lua: State) -> Self {
+        Self(lua, super::task_queue::realm_of(lua))
+    }
+
+    /// Returns the realm of the state.
+    pub fn realm(&self) -> Realm {
+        self.1
+    }
+
+    /// Returns the `State`, which the token proves is safe to use here.
+    pub fn get(&self, _token: MainThreadToken) -> State {
+        self.0
+    }
+
+    /// Returns the `State` if this is the main thread.
+    pub fn try_get(&self) -> Option<State> {
+        MainThreadToken::get().map(|token| self.get(token))
+    }
+
+    /// Returns the `State`, panicking if this isn't the main thread.
+    #[track_caller]
+    pub fn assert_main_thread(&self) -> State {
+        self.get(MainThreadToken::assert())
+    }
+
+    /// Runs `callback` on the main thread on the next tick, with the state of the same realm. Can be called from any thread, see `gmod::lua::task_queue::wait_lua_tick_in`.
+    ///
+    /// The callback gets the state the task queue runs in rather than the stored one, which may be a coroutine that's gone by then. It's dropped without running if the realm's state closes before the next tick.
+    pub fn wait_lua_tick<F>(&self, traceback: String, callback: F) -> Result<(), ClosedError>
+    where
+        F: FnOnce(State) + Send + 'static,
+    {
+        super::task_queue::wait_lua_tick_in(self.1, traceback, callback)
+    }
+}
+
+impl From<State> for SendableState {
+    fn from(lua: State) -> Self {
+        Self::new(lua)
+    }
+}
+
+impl State {
+    /// Returns a copy of this state that can be moved to other threads, see `SendableState`.
+    pub fn sendable(&self) -> SendableState {
+        SendableState::new(*self)
+    }
+}
@@ -14,15 +14,74 @@ pub use lua_state::LuaCStr;
 pub use lua_state::LuaState as State;
 
 mod returns;
-pub use returns::HandleLuaFunctionReturn;
+pub use returns::{HandleLuaFunctionReturn, LuaPush, ResultTable};
+
+mod args;
+pub use args::{FunctionRef, LuaCheck, TableRef};
+
+mod call;
+pub use call::{CachedLuaFunction, LuaCall, LuaCallResults};
 
 mod number;
 
+#[cfg(feature = "strict")]
+mod strict;
+
 pub mod task_queue;
 
+pub mod intern;
+
+pub mod cstr;
+
+pub mod debug_hook;
+
+#[cfg(feature = "json")]
+mod json;
+
+mod main_thread;
+pub use main_thread::{is_main_thread, set_main_thread, MainThreadToken, SendableState};
+
 mod raw_bind;
 
-pub const LUA_NUMBER_MAX_SAFE_INTEGER: i64 = (2 ^ 53) - 1;
+mod closure_fn;
+pub use closure_fn::{function_from_closure, unload_closures};
+
+mod module_data;
+pub use module_data::unload_module_data;
+
+mod yieldable;
+pub use yieldable::Resumer;
+
+mod coroutine_pool;
+pub use coroutine_pool::{CoroutinePool, PooledCall};
+
+mod stack_guard;
+pub use stack_guard::StackGuard;
+
+mod stack_index;
+pub use stack_index::StackIndex;
+
+mod lua_ref;
+pub use lua_ref::LuaRef;
+
+pub mod jit;
+
+/// The largest integer that a Lua number, and every integer closer to zero, can represent exactly.
+pub const LUA_NUMBER_MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// Whether the key below the value on top of the stack is an integer in `1..=len`.
+///
+/// With `len` such keys and no others, the table is exactly the sequence `1..=len`, even when `#t` is fooled by holes.
+pub(crate) fn is_sequence_key(lua: State, len: usize) -> bool {
+    if lua.lua_type(-2) != LUA_TNUMBER {
+        return false;
+    }
+    let key = lua.to_number(-2);
+    key.fract() == 0.0 && key >= 1.0 && key <= len as f64
+}
+
+/// The first bytes of every LuaJIT bytecode chunk (`ESC 'L' 'J'`).
+pub const LUAJIT_BYTECODE_HEADER: &[u8] = b"\x1bLJ";
 
 #[derive(Debug, Clone)]
 pub enum LuaError {
@@ -81,10 +140,10 @@ impl std::error::Error for LuaError {}
 ///
 /// # Example
 ///
-/// ```rust,norun
+/// ```ignore
 /// lua_stack_guard!(lua => {
-///     lua.get_global(lua_string!("hook"));
-///     lua.get_field(-1, lua_string!("Add"));
+///     lua.get_global(c"hook");
+///     lua.get_field(-1, c"Add");
 ///     lua.push_string("PlayerInitialSpawn");
 ///     lua.push_string("RustHook");
 ///     lua.push_function(player_initial_spawn);
@@ -154,11 +213,19 @@ pub struct LuaDebug {
 
 #[inline(always)]
 /// Loads lua_shared and imports all functions. This is already done for you if you add `#[gmod::gmod13_open]` to your `gmod13_open` function.
-pub unsafe fn load() {
-    import::LUA_SHARED.load()
+///
+/// Fails if lua_shared can't be found or doesn't export a required function. Missing optional functions are listed by `LUA_SHARED.missing_symbols()`.
+pub unsafe fn load() -> Result<(), ImportError> {
+    import::lua_shared().load()
+}
+
+/// Returns whether lua_shared was loaded with `load`.
+#[inline(always)]
+pub fn is_loaded() -> bool {
+    unsafe { import::lua_shared().is_loaded() }
 }
 
 #[inline(always)]
 pub unsafe fn unload() {
-    import::LUA_SHARED.unload()
+    import::lua_shared().unload()
 }
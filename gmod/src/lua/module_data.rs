@@ -0,0 +1,156 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    ffi::c_void,
+    sync::Mutex,
+};
+
+use super::{LuaCStr, State, LUA_REGISTRYINDEX};
+
+/// Metatable of the userdata holding module data.
+const MODULE_DATA_METATABLE: LuaCStr = c"gmod_rs_module_data";
+
+type Slot = Option<Box<dyn Any>>;
+
+/// The registry key of each type stored so far: the address of a byte leaked for it, so it can't collide with anything else in the registry.
+static KEYS: Mutex<Option<HashMap<TypeId, usize>>> = Mutex::new(None);
+
+fn key_of<T: 'static>() -> *mut c_void {
+    let mut keys = KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    *keys
+        .get_or_insert_with(HashMap::new)
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| Box::leak(Box::new(0u8)) as *mut u8 as usize) as *mut c_void
+}
+
+impl State {
+    /// Returns the slot of `T` in the registry of this state, creating it if `create` is set. The registry keeps the userdata it lives in alive.
+    fn module_data_slot<T: 'static>(&self, create: bool) -> Option<*mut Slot> {
+        let key = key_of::<T>();
+        self.push_lightuserdata(key);
+        self.get_table(LUA_REGISTRYINDEX);
+        if self.is_userdata(-1) {
+            let slot = self.to_userdata(-1) as *mut Slot;
+            self.pop();
+            return Some(slot);
+        }
+        self.pop();
+        if !create {
+            return None;
+        }
+
+        let slot = self.new_userdata::<Slot>(None, None);
+        // true if it already existed
+        if !self.new_metatable(MODULE_DATA_METATABLE) {
+            self.push_function(module_data_gc);
+            self.set_field(-2, c"__gc");
+        }
+        unsafe { self.set_metatable(-2) };
+        self.push_lightuserdata(key);
+        self.push_value(-2);
+        self.set_table(LUA_REGISTRYINDEX);
+        self.pop();
+        Some(slot)
+    }
+
+    /// Stores `value` in this state, replacing the `T` stored before, which is returned.
+    ///
+    /// Module data is a safe alternative to `static mut` globals: it's kept per Lua state, so a module loaded in several states (e.g. the client and the menu) has one value in each, and it's dropped when the module is closed.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// struct Config {
+    ///     verbose: bool,
+    /// }
+    ///
+    /// #[gmod13_open]
+    /// fn gmod13_open(lua: State) -> i32 {
+    ///     lua.set_module_data(Rc::new(Config { verbose: false }));
+    ///     0
+    /// }
+    ///
+    /// #[lua_function]
+    /// fn log(lua: State) -> anyhow::Result<()> {
+    ///     let config = lua.get_module_data::<Rc<Config>>().unwrap();
+    ///     if config.verbose {
+    ///         println!("{}", lua.check_string(1)?);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_module_data<T: 'static>(&self, value: T) -> Option<T> {
+        let slot = self.module_data_slot::<T>(true)?;
+        let previous = unsafe { (*slot).replace(Box::new(value)) };
+        previous.and_then(|previous| previous.downcast().ok().map(|previous| *previous))
+    }
+
+    /// Returns a clone of the `T` stored in this state with `set_module_data`. Data that's shared or changed through clones goes in an `Rc`, with a `Cell` or `RefCell` if needed.
+    ///
+    /// Returns `None` if no `T` is stored, or while it's borrowed by `with_module_data`.
+    pub fn get_module_data<T: Clone + 'static>(&self) -> Option<T> {
+        self.with_module_data(|value: &mut T| value.clone())
+    }
+
+    /// Calls `f` with the `T` stored in this state with `set_module_data`, and returns what it returns.
+    ///
+    /// The value is taken out while `f` runs, so `get_module_data` and `with_module_data` return `None` for `T` until it returns. Returns `None` if no `T` is stored.
+    pub fn with_module_data<T: 'static, R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let slot = self.module_data_slot::<T>(false)?;
+        let mut value = unsafe { (*slot).take() }?;
+        let ret = value.downcast_mut().map(f);
+        // `f` may have stored another value
+        let slot = self.module_data_slot::<T>(true)?;
+        unsafe {
+            if (*slot).is_none() {
+                *slot = Some(value);
+            }
+        }
+        ret
+    }
+
+    /// Removes the `T` stored in this state with `set_module_data`, and returns it.
+    pub fn take_module_data<T: 'static>(&self) -> Option<T> {
+        let slot = self.module_data_slot::<T>(false)?;
+        let value = unsafe { (*slot).take() }?;
+        value.downcast().ok().map(|value| *value)
+    }
+}
+
+extern "C-unwind" fn module_data_gc(lua: State) -> i32 {
+    let ptr = lua.to_userdata(1) as *mut Slot;
+    if !ptr.is_null() {
+        unsafe { std::ptr::drop_in_place(ptr) };
+    }
+    0
+}
+
+/// Drops the data stored with `set_module_data` in this state, and stops Lua from calling into the module to drop it later. This is called for you by `#[gmod13_close]`.
+pub fn unload_module_data(lua: State) {
+    let keys: Vec<usize> = KEYS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .flat_map(|keys| keys.values().copied())
+        .collect();
+    for key in keys {
+        lua.push_lightuserdata(key as *mut c_void);
+        lua.get_table(LUA_REGISTRYINDEX);
+        if lua.is_userdata(-1) {
+            let slot = lua.to_userdata(-1) as *mut Slot;
+            drop(unsafe { (*slot).take() });
+        }
+        lua.pop();
+
+        lua.push_lightuserdata(key as *mut c_void);
+        lua.push_nil();
+        lua.set_table(LUA_REGISTRYINDEX);
+    }
+
+    lua.get_metatable_name(MODULE_DATA_METATABLE);
+    if lua.is_table(-1) {
+        lua.push_nil();
+        lua.set_field(-2, c"__gc");
+    }
+    lua.pop();
+}
@@ -103,3 +103,51 @@ impl LuaPushNumber for f64 {
         l.lua_push_number(self as LuaNumber);
     }
 }
+
+/// Numbers that can be read from Lua with `check_integer` and `check_number_in_range`.
+pub trait LuaCheckNumber: Sized + Copy + PartialOrd + std::fmt::Display {
+    /// Whether the Lua number must be integral.
+    const INTEGER: bool;
+    const MIN: Self;
+    const MAX: Self;
+
+    /// Converts the Lua number, or returns `None` if it's not integral (for integer types) or out of range.
+    fn from_lua_number(n: LuaNumber) -> Option<Self>;
+}
+
+macro_rules! impl_check_integer {
+    ($($ty:ty),+) => {
+        $(impl LuaCheckNumber for $ty {
+            const INTEGER: bool = true;
+            const MIN: Self = <$ty>::MIN;
+            const MAX: Self = <$ty>::MAX;
+
+            fn from_lua_number(n: LuaNumber) -> Option<Self> {
+                // MAX doesn't always fit in a double, but MAX + 1 rounds to the next power of two, which is still out of range
+                (n.fract() == 0.0 && n >= <$ty>::MIN as LuaNumber && n < <$ty>::MAX as LuaNumber + 1.0)
+                    .then_some(n as $ty)
+            }
+        })+
+    };
+}
+impl_check_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl LuaCheckNumber for f32 {
+    const INTEGER: bool = false;
+    const MIN: Self = f32::MIN;
+    const MAX: Self = f32::MAX;
+
+    fn from_lua_number(n: LuaNumber) -> Option<Self> {
+        (!n.is_finite() || n.abs() <= f32::MAX as LuaNumber).then_some(n as f32)
+    }
+}
+
+impl LuaCheckNumber for f64 {
+    const INTEGER: bool = false;
+    const MIN: Self = f64::MIN;
+    const MAX: Self = f64::MAX;
+
+    fn from_lua_number(n: LuaNumber) -> Option<Self> {
+        Some(n)
+    }
+}
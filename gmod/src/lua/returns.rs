@@ -1,6 +1,8 @@
-use std::{borrow::Cow, num::NonZeroI32};
+use std::{any::Any, backtrace::BacktraceStatus, borrow::Cow, fmt::Write, num::NonZeroI32};
 
-use super::State;
+use super::{number::LuaPushNumber, State};
+use crate::error::{ErrorTable, NonFatal};
+use crate::userdata::{Angle, Vector};
 
 pub trait HandleLuaFunctionReturn {
     fn handle_result(self, l: State) -> i32;
@@ -18,7 +20,7 @@ impl<E: DisplayLuaError> HandleLuaFunctionReturn for Result<i32, E> {
     fn handle_result(self, l: State) -> i32 {
         match self {
             Ok(vals) => vals,
-            Err(err) => unsafe { l.error(err.display_lua_error().as_ref()) },
+            Err(err) => raise_error(l, err),
         }
     }
 }
@@ -28,17 +30,230 @@ impl<E: DisplayLuaError> HandleLuaFunctionReturn for Result<(), E> {
     fn handle_result(self, l: State) -> i32 {
         match self {
             Ok(_) => 0,
-            Err(err) => unsafe { l.error(err.display_lua_error().as_ref()) },
+            Err(err) => raise_error(l, err),
         }
     }
 }
 
+impl HandleLuaFunctionReturn for () {
+    #[inline(always)]
+    fn handle_result(self, _l: State) -> i32 {
+        0
+    }
+}
+
+/// Values that can be pushed onto the stack with `LuaState::push`, and returned from a `#[lua_function]` inside a tuple.
+pub trait LuaPush {
+    fn lua_push(self, l: State);
+}
+
+impl<N: LuaPushNumber> LuaPush for N {
+    #[inline(always)]
+    fn lua_push(self, l: State) {
+        l.push_number(self);
+    }
+}
+
+impl LuaPush for bool {
+    #[inline(always)]
+    fn lua_push(self, l: State) {
+        l.push_boolean(self);
+    }
+}
+
+impl LuaPush for &str {
+    #[inline(always)]
+    fn lua_push(self, l: State) {
+        l.push_string(self);
+    }
+}
+
+impl LuaPush for String {
+    #[inline(always)]
+    fn lua_push(self, l: State) {
+        l.push_string(&self);
+    }
+}
+
+impl LuaPush for Cow<'_, str> {
+    #[inline(always)]
+    fn lua_push(self, l: State) {
+        l.push_string(&self);
+    }
+}
+
+impl LuaPush for Vector {
+    #[inline(always)]
+    fn lua_push(self, l: State) {
+        l.push_vector(self);
+    }
+}
+
+impl LuaPush for Angle {
+    #[inline(always)]
+    fn lua_push(self, l: State) {
+        l.push_angle(self);
+    }
+}
+
+/// Pushed as nil, so functions returning `Result<(), E>` fit where a value is expected (e.g. `ResultTable`).
+impl LuaPush for () {
+    #[inline(always)]
+    fn lua_push(self, l: State) {
+        l.push_nil();
+    }
+}
+
+/// `None` is pushed as nil.
+impl<T: LuaPush> LuaPush for Option<T> {
+    #[inline(always)]
+    fn lua_push(self, l: State) {
+        match self {
+            Some(value) => value.lua_push(l),
+            None => l.push_nil(),
+        }
+    }
+}
+
+/// Tuples are returned as multiple values, e.g. `-> (f64, Option<String>)` or `-> Result<(bool,), E>`. A lone `i32` is the number of values already pushed, so wrap it in a tuple (`(i32,)`) to return it as a number.
+///
+/// ```no_run
+/// use gmod::lua::State;
+///
+/// #[gmod::lua_function]
+/// fn player_info(lua: State) -> (i32, String, bool) {
+///     (1, "Garry".to_string(), true)
+/// }
+///
+/// #[gmod::lua_function]
+/// fn parse(lua: State) -> anyhow::Result<(f64, Option<String>)> {
+///     let n: f64 = lua.check_string(1)?.trim().parse()?;
+///     Ok((n, None))
+/// }
+/// ```
+macro_rules! impl_multi_return {
+	($($name:ident),+) => {
+		impl<$($name: LuaPush),+> HandleLuaFunctionReturn for ($($name,)+) {
+			#[inline(always)]
+			#[allow(non_snake_case)]
+			fn handle_result(self, l: State) -> i32 {
+				let ($($name,)+) = self;
+				if let Err(err) = l.ensure_stack([$(stringify!($name)),+].len() as i32) {
+					return raise_error(l, err);
+				}
+				let mut count = 0;
+				$(
+					$name.lua_push(l);
+					count += 1;
+				)+
+				count
+			}
+		}
+
+		impl<$($name: LuaPush),+, E: DisplayLuaError> HandleLuaFunctionReturn for Result<($($name,)+), E> {
+			#[inline(always)]
+			fn handle_result(self, l: State) -> i32 {
+				match self {
+					Ok(values) => values.handle_result(l),
+					Err(err) => raise_error(l, err),
+				}
+			}
+		}
+	};
+}
+impl_multi_return!(A);
+impl_multi_return!(A, B);
+impl_multi_return!(A, B, C);
+impl_multi_return!(A, B, C, D);
+impl_multi_return!(A, B, C, D, F);
+impl_multi_return!(A, B, C, D, F, G);
+impl_multi_return!(A, B, C, D, F, G, H);
+impl_multi_return!(A, B, C, D, F, G, H, I);
+
+/// The return value of a `#[lua_function(result_table)]`, pushed with `State::push_ok` or `State::push_err` instead of raising errors.
+///
+/// ```no_run
+/// use gmod::{error::{ErrorCode, ErrorTable}, lua::State};
+///
+/// #[gmod::lua_function(result_table)]
+/// fn read_motd(lua: State) -> Result<String, ErrorTable> {
+///     let motd = std::fs::read_to_string("garrysmod/data/motd.txt")?;
+///     if motd.is_empty() {
+///         return Err(ErrorTable::new(ErrorCode::ConfigMissing, "the MOTD is empty"));
+///     }
+///     Ok(motd)
+/// }
+/// ```
+///
+/// ```lua
+/// local result = mymodule.ReadMOTD()
+/// if result.ok then print(result.value) elseif result.code == "IO_NOT_FOUND" then ... end
+/// ```
+#[derive(Debug)]
+pub struct ResultTable<R>(pub R);
+
+impl<T: LuaPush, E: Into<ErrorTable>> HandleLuaFunctionReturn for ResultTable<Result<T, E>> {
+    #[inline(always)]
+    fn handle_result(self, l: State) -> i32 {
+        match self.0 {
+            Ok(value) => l.push_ok(value),
+            Err(err) => {
+                let err = err.into();
+                l.push_err(err.code, &err.message);
+            }
+        }
+        1
+    }
+}
+
+/// Raises `err` as a Lua error, or reports it with `ErrorNoHaltWithStack` and returns no values if it's non-fatal.
+#[cold]
+fn raise_error<E: DisplayLuaError>(l: State, err: E) -> i32 {
+    let msg = err.display_lua_error().into_owned();
+    if err.is_non_fatal() {
+        l.error_no_halt(&msg, None);
+        return 0;
+    }
+    // the error unwinds through Lua, which won't run destructors
+    drop(err);
+    l.error(msg)
+}
+
+/// How an error returned from a `#[lua_function]` is shown to Lua.
+///
+/// `anyhow::Error`s show their whole chain, one cause per line, followed by the Rust backtrace if one was captured (see `RUST_BACKTRACE`). Other errors are shown with their `Debug` representation.
 pub trait DisplayLuaError {
     fn display_lua_error(&self) -> Cow<'_, str>;
+
+    /// Whether the error is reported with `ErrorNoHaltWithStack` instead of raised, see `gmod::error::non_fatal`.
+    fn is_non_fatal(&self) -> bool {
+        false
+    }
 }
-impl<E: std::fmt::Debug> DisplayLuaError for E {
+impl<E: std::fmt::Debug + 'static> DisplayLuaError for E {
     #[inline(always)]
     fn display_lua_error(&self) -> Cow<'_, str> {
-        Cow::Owned(format!("{:?}", self))
+        match (self as &dyn Any).downcast_ref::<anyhow::Error>() {
+            Some(err) => Cow::Owned(display_anyhow(err)),
+            None => Cow::Owned(format!("{:?}", self)),
+        }
+    }
+
+    fn is_non_fatal(&self) -> bool {
+        (self as &dyn Any)
+            .downcast_ref::<anyhow::Error>()
+            .is_some_and(|err| err.downcast_ref::<NonFatal>().is_some())
+    }
+}
+
+fn display_anyhow(err: &anyhow::Error) -> String {
+    let mut msg = err.to_string();
+    for cause in err.chain().skip(1) {
+        let _ = write!(msg, "\ncaused by: {cause}");
+    }
+    let backtrace = err.backtrace();
+    if backtrace.status() == BacktraceStatus::Captured {
+        let _ = write!(msg, "\n\nRust backtrace:\n{backtrace}");
     }
+    msg
 }
@@ -0,0 +1,92 @@
+use super::State;
+
+/// Restores the Lua stack to the size it had when the guard was created once it is dropped.
+///
+/// Created by `State::guard` and `State::guard_strict`. Unlike `lua_stack_guard!`, this allows early returns and `?` without leaking stack slots.
+///
+/// ## Example
+///
+/// ```ignore
+/// fn get_hook_table(lua: gmod::lua::State) -> anyhow::Result<usize> {
+///     let _guard = lua.guard();
+///     lua.get_global(c"hook");
+///     lua.check_table(-1)?; // the stack is cleaned up even if this fails
+///     lua.get_field(-1, c"GetTable");
+///     unsafe { lua.call(0, 1) };
+///     Ok(lua.len(-1) as usize)
+/// }
+/// ```
+#[must_use = "the stack is restored as soon as the guard is dropped"]
+pub struct StackGuard {
+    lua: State,
+    top: i32,
+    strict: bool,
+}
+
+impl StackGuard {
+    /// Returns the stack size that will be restored.
+    #[inline(always)]
+    pub fn top(&self) -> i32 {
+        self.top
+    }
+
+    /// Drops the guard without touching the stack.
+    #[inline(always)]
+    pub fn disarm(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl std::ops::Deref for StackGuard {
+    type Target = State;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.lua
+    }
+}
+
+impl Drop for StackGuard {
+    fn drop(&mut self) {
+        let top = self.lua.get_top();
+        if top == self.top {
+            return;
+        }
+
+        #[cfg(debug_assertions)]
+        if self.strict && !std::thread::panicking() {
+            self.lua.dump_stack();
+            self.lua.set_top(self.top);
+            panic!(
+                "Stack is dirty! Expected the stack to have {} elements, but it has {}!",
+                self.top, top
+            );
+        }
+
+        if top > self.top {
+            self.lua.set_top(self.top);
+        }
+    }
+}
+
+impl State {
+    /// Returns a guard that truncates the stack back to its current size when dropped.
+    #[inline(always)]
+    pub fn guard(&self) -> StackGuard {
+        StackGuard {
+            lua: *self,
+            top: self.get_top(),
+            strict: false,
+        }
+    }
+
+    /// Same as `guard`, but in debug builds panics on drop if the stack size changed, after restoring it.
+    #[inline(always)]
+    pub fn guard_strict(&self) -> StackGuard {
+        StackGuard {
+            lua: *self,
+            top: self.get_top(),
+            strict: true,
+        }
+    }
+}
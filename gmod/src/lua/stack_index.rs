@@ -0,0 +1,128 @@
+use std::ops::{Add, Sub};
+
+use super::{State, LUA_ENVIRONINDEX, LUA_GLOBALSINDEX, LUA_REGISTRYINDEX};
+
+/// A position on the Lua stack: absolute (`1` is the bottom), relative to the top (`-1` is the top), or a pseudo-index (registry, globals, environment, upvalues).
+///
+/// Every `State` method that takes a stack index accepts `impl Into<StackIndex>`, so plain `i32`s keep working. Relative indices go stale as soon as values are pushed or popped, which is where most index bugs come from; either convert them to absolute indices with `abs` first, or shift them with `after_push`/`after_pop`.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::lua::StackIndex;
+///
+/// let table = StackIndex::TOP;
+/// // after pushing a key and a value, the table is 3 slots from the top
+/// assert_eq!(table.after_push(2), StackIndex(-3));
+///
+/// // absolute indices and pseudo-indices don't move
+/// assert_eq!(StackIndex(2).after_push(2), StackIndex(2));
+/// assert_eq!(StackIndex::REGISTRY.after_push(2), StackIndex::REGISTRY);
+///
+/// assert!(StackIndex::upvalue(1).is_upvalue());
+/// assert!(StackIndex::GLOBALS.is_pseudo());
+/// assert!(StackIndex::TOP.is_relative());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct StackIndex(pub i32);
+
+impl StackIndex {
+    /// The value on top of the stack.
+    pub const TOP: Self = Self(-1);
+    pub const REGISTRY: Self = Self(LUA_REGISTRYINDEX);
+    pub const ENVIRON: Self = Self(LUA_ENVIRONINDEX);
+    pub const GLOBALS: Self = Self(LUA_GLOBALSINDEX);
+
+    /// The pseudo-index of the `n`th upvalue of the running C closure, starting at 1.
+    #[inline(always)]
+    pub const fn upvalue(n: i32) -> Self {
+        Self(LUA_GLOBALSINDEX - n)
+    }
+
+    #[inline(always)]
+    pub const fn raw(self) -> i32 {
+        self.0
+    }
+
+    /// Whether this is a pseudo-index (registry, environment, globals or an upvalue), which doesn't refer to a stack slot.
+    #[inline(always)]
+    pub const fn is_pseudo(self) -> bool {
+        self.0 <= LUA_REGISTRYINDEX
+    }
+
+    #[inline(always)]
+    pub const fn is_upvalue(self) -> bool {
+        self.0 < LUA_GLOBALSINDEX
+    }
+
+    /// Whether this is relative to the top of the stack, and so moves when values are pushed or popped.
+    #[inline(always)]
+    pub const fn is_relative(self) -> bool {
+        self.0 < 0 && !self.is_pseudo()
+    }
+
+    #[inline(always)]
+    pub const fn is_absolute(self) -> bool {
+        self.0 > 0
+    }
+
+    /// Converts a relative index to the absolute index it currently refers to. Absolute indices and pseudo-indices are returned as-is.
+    #[inline(always)]
+    pub fn abs(self, lua: State) -> Self {
+        if self.is_relative() {
+            Self(lua.get_top() + self.0 + 1)
+        } else {
+            self
+        }
+    }
+
+    /// Returns the index that refers to the same value after `n` more values were pushed.
+    #[inline(always)]
+    pub const fn after_push(self, n: i32) -> Self {
+        if self.is_relative() {
+            Self(self.0 - n)
+        } else {
+            self
+        }
+    }
+
+    /// Returns the index that refers to the same value after `n` values above it were popped.
+    #[inline(always)]
+    pub const fn after_pop(self, n: i32) -> Self {
+        self.after_push(-n)
+    }
+}
+
+impl From<i32> for StackIndex {
+    #[inline(always)]
+    fn from(index: i32) -> Self {
+        Self(index)
+    }
+}
+
+impl From<StackIndex> for i32 {
+    #[inline(always)]
+    fn from(index: StackIndex) -> Self {
+        index.0
+    }
+}
+
+/// Moves an absolute index up, or a relative index towards the top.
+impl Add<i32> for StackIndex {
+    type Output = Self;
+
+    #[inline(always)]
+    fn add(self, rhs: i32) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+
+impl Sub<i32> for StackIndex {
+    type Output = Self;
+
+    #[inline(always)]
+    fn sub(self, rhs: i32) -> Self {
+        Self(self.0 - rhs)
+    }
+}
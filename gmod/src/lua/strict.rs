@@ -0,0 +1,106 @@
+//! Runtime validation done by the `strict` feature, turning misuse of the Lua API that would otherwise be undefined behaviour into Lua errors.
+//!
+//! These checks run before the Lua C API is called and raise with `lua_error`, so they're meant for development only: destructors of the Rust frames between the check and the enclosing `pcall` don't run.
+
+use super::{State, LUA_REGISTRYINDEX};
+
+#[cold]
+fn fail(l: State, op: &str, msg: String) -> ! {
+    l.error(format!("[strict] {op}: {msg}"))
+}
+
+fn describe(l: State, index: i32) -> String {
+    l.lua_type_name(l.lua_type(index)).into_owned()
+}
+
+/// Checks that `index` refers to a value on the stack, or is a pseudo-index (registry, globals, environment, upvalues).
+pub(crate) fn index(l: State, op: &str, index: i32) {
+    if index <= LUA_REGISTRYINDEX {
+        return;
+    }
+    let top = l.get_top();
+    if index == 0 || index.unsigned_abs() > top as u32 {
+        fail(
+            l,
+            op,
+            format!("stack index {index} is out of bounds (the stack has {top} values)"),
+        );
+    }
+}
+
+/// Checks that the stack has at least `n` values.
+pub(crate) fn values(l: State, op: &str, n: i32) {
+    let top = l.get_top();
+    if n < 0 || n > top {
+        fail(
+            l,
+            op,
+            format!("needs {n} values on the stack, but it has {top}"),
+        );
+    }
+}
+
+/// Checks that the value at `index` is of type `ty`.
+pub(crate) fn lua_type(l: State, op: &str, index: i32, ty: i32) {
+    self::index(l, op, index);
+    if l.lua_type(index) != ty {
+        fail(
+            l,
+            op,
+            format!(
+                "expected a {} at stack index {index}, got {}",
+                l.lua_type_name(ty),
+                describe(l, index)
+            ),
+        );
+    }
+}
+
+/// Checks that the value at `index` is a function, with `nargs` values pushed after it.
+pub(crate) fn call(l: State, op: &str, nargs: i32) {
+    values(l, op, nargs + 1);
+    let func = -(nargs + 1);
+    if !l.is_function(func) {
+        fail(
+            l,
+            op,
+            format!(
+                "expected a function at stack index {func}, got {}",
+                describe(l, func)
+            ),
+        );
+    }
+}
+
+/// Checks that `ptr` is a non-null, aligned pointer to a `T`.
+pub(crate) fn aligned<T>(l: State, op: &str, ptr: *const T) {
+    if ptr.is_null() {
+        fail(l, op, "userdata pointer is null".to_owned());
+    }
+    let align = std::mem::align_of::<T>();
+    if !(ptr as usize).is_multiple_of(align) {
+        fail(
+            l,
+            op,
+            format!(
+                "userdata pointer {ptr:p} isn't aligned to {align} bytes for {}",
+                std::any::type_name::<T>()
+            ),
+        );
+    }
+}
+
+/// Checks that the value at `index` is a string or a number, which `lua_tolstring` converts.
+pub(crate) fn string(l: State, op: &str, index: i32) {
+    self::index(l, op, index);
+    if !l.is_string(index) {
+        fail(
+            l,
+            op,
+            format!(
+                "expected a string at stack index {index}, got {}",
+                describe(l, index)
+            ),
+        );
+    }
+}
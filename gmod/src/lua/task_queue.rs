@@ -1,19 +1,23 @@
-use std::borrow::Borrow;
-use std::iter::repeat_with;
 use std::{
+    backtrace::Backtrace,
     borrow::Cow,
     ffi::c_void,
-    mem::MaybeUninit,
+    future::Future,
+    iter::repeat_with,
+    pin::Pin,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, RwLock,
     },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
-use gmod_macros::lua_function;
-
 use super::State;
-use crate as gmod;
+use crate::{
+    lifecycle::{self, ClosedError, ModuleState},
+    realm::Realm,
+};
 
 type CallbackBoxed = Box<dyn FnOnce(State) + Send>;
 
@@ -21,38 +25,289 @@ type CallbackBoxed = Box<dyn FnOnce(State) + Send>;
 struct CallbackCtx<'a> {
     callback: CallbackBoxed,
     traceback: Cow<'a, str>,
+    origin: Option<Box<Origin>>,
+    #[cfg(feature = "profile")]
+    name: &'static str,
+}
+
+/// Where a callback was queued from, captured when `set_capture_tracebacks` is on.
+struct Origin {
+    /// Only captured when queued from the Lua thread.
+    lua: Option<String>,
+    /// Symbols are only resolved when it's printed.
+    rust: Backtrace,
 }
 
-pub struct TaskQueue {
-    sender: flume::Sender<CallbackCtx<'static>>,
-    receiver: flume::Receiver<CallbackCtx<'static>>,
+impl Origin {
+    fn capture(realm: Realm) -> Option<Box<Self>> {
+        if !CAPTURE_TRACEBACKS.load(Ordering::Relaxed) {
+            return None;
+        }
+        let lua = MAIN_STATES[realm as usize].load(Ordering::Acquire);
+        let lua = (!lua.is_null() && super::is_main_thread()).then(|| {
+            let lua = State(lua);
+            lua.lual_traceback(lua, 1);
+            let traceback = lua.get_string(-1).unwrap_or_default().into_owned();
+            lua.pop();
+            traceback
+        });
+        Some(Box::new(Self {
+            lua,
+            rust: Backtrace::force_capture(),
+        }))
+    }
 }
 
-impl Default for TaskQueue {
-    fn default() -> Self {
-        let (tx, rx) = flume::unbounded();
-        Self {
-            sender: tx,
-            receiver: rx,
+/// Combines the traceback given to `wait_lua_tick` and the captured origin, for printing with an error. Empty if there's neither.
+fn format_trace(traceback: &str, origin: Option<&Origin>) -> String {
+    let mut trace = traceback.trim_end().to_string();
+    if let Some(origin) = origin {
+        if let Some(lua) = &origin.lua {
+            trace += &format!("\nqueued from Lua, {}", lua.trim_end());
         }
+        trace += &format!("\nqueued from Rust, stack traceback:\n{}", origin.rust);
+    }
+    trace.trim_start().to_string()
+}
+
+static CAPTURE_TRACEBACKS: AtomicBool = AtomicBool::new(false);
+
+/// The states given to `load`, per realm, for capturing Lua tracebacks.
+static MAIN_STATES: [AtomicPtr<c_void>; 3] = [
+    AtomicPtr::new(std::ptr::null_mut()),
+    AtomicPtr::new(std::ptr::null_mut()),
+    AtomicPtr::new(std::ptr::null_mut()),
+];
+
+/// Captures where every callback is queued from: a Rust backtrace, and a Lua traceback when queued from the Lua thread. When a callback raises a Lua error, they're printed with it, after the traceback given to `wait_lua_tick`. Off by default, as capturing a backtrace for every callback adds up.
+///
+/// Only Rust backtraces of builds with debug info have file names and line numbers.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[gmod13_open]
+/// fn gmod13_open(lua: gmod::lua::State) {
+///     #[cfg(debug_assertions)]
+///     gmod::lua::task_queue::set_capture_tracebacks(true);
+/// }
+///
+/// // errors raised by the callback now print where it was queued from
+/// gmod::wait_lua_tick(String::new(), |lua| lua.error_with_location("oops"));
+/// ```
+pub fn set_capture_tracebacks(capture: bool) {
+    CAPTURE_TRACEBACKS.store(capture, Ordering::Relaxed);
+}
+
+pub fn capture_tracebacks() -> bool {
+    CAPTURE_TRACEBACKS.load(Ordering::Relaxed)
+}
+
+/// One channel per `Priority`, highest first.
+struct TaskQueue {
+    senders: [flume::Sender<CallbackCtx<'static>>; 3],
+    receivers: [flume::Receiver<CallbackCtx<'static>>; 3],
+}
+
+/// Which callbacks run first when several are waiting. Callbacks of the same priority run in the order they were queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(u8)]
+pub enum Priority {
+    High,
+    /// Used by `wait_lua_tick`.
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    /// Every priority, highest first.
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+}
+
+/// Limits on how much of the queue runs in a single tick. The callbacks left over stay queued for the next tick, ahead of the ones queued after them.
+///
+/// At least one callback runs per tick whatever the limits, so the queue always makes progress. Under a sustained flood, lower priority callbacks can wait for a long time.
+///
+/// ## Example
+///
+/// ```
+/// use std::time::Duration;
+/// use gmod::lua::task_queue::{self, TickBudget};
+///
+/// // never spend more than 2ms of a server frame on queued callbacks
+/// task_queue::set_tick_budget(TickBudget {
+///     max_callbacks: None,
+///     max_time: Some(Duration::from_millis(2)),
+/// });
+/// # task_queue::set_tick_budget(TickBudget::UNLIMITED);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TickBudget {
+    pub max_callbacks: Option<usize>,
+    /// Checked after every callback, so a slow callback can overrun it.
+    pub max_time: Option<Duration>,
+}
+
+impl TickBudget {
+    /// Runs every callback that was queued before the tick. This is the default.
+    pub const UNLIMITED: TickBudget = TickBudget {
+        max_callbacks: None,
+        max_time: None,
+    };
+
+    fn exhausted(&self, ran: usize, started: Instant) -> bool {
+        self.max_callbacks.is_some_and(|max| ran >= max)
+            || self.max_time.is_some_and(|max| started.elapsed() >= max)
     }
 }
 
-pub static COUNTER: AtomicUsize = AtomicUsize::new(0);
-pub static mut TASK_QUEUE: MaybeUninit<TaskQueue> = MaybeUninit::uninit();
-static mut GMOD_CLOSED: bool = false;
+static TICK_BUDGET: Mutex<TickBudget> = Mutex::new(TickBudget::UNLIMITED);
 
-pub fn read<'a>() -> &'a TaskQueue {
-    unsafe { TASK_QUEUE.assume_init_ref() }
+/// Sets how much of the queue may run per tick.
+pub fn set_tick_budget(budget: TickBudget) {
+    *TICK_BUDGET.lock().unwrap_or_else(|e| e.into_inner()) = budget;
+}
+
+pub fn tick_budget() -> TickBudget {
+    *TICK_BUDGET.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Runs incremental garbage collection steps between queued callbacks, for modules whose callbacks create a lot of garbage (e.g. userdata) that Lua's allocation-driven collector is slow to reclaim.
+///
+/// ```
+/// use gmod::lua::task_queue::{self, GcStepping};
+///
+/// // a 64 KB step after every 100 callbacks
+/// task_queue::set_gc_stepping(Some(GcStepping { every: 100, step_kb: 64 }));
+/// # task_queue::set_gc_stepping(None);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcStepping {
+    /// How many callbacks run between two steps.
+    pub every: usize,
+    /// The size of each step, see `State::gc_step`.
+    pub step_kb: i32,
+}
+
+static GC_STEPPING: Mutex<Option<GcStepping>> = Mutex::new(None);
+
+/// Callbacks run since the last garbage collection step.
+static SINCE_GC_STEP: AtomicUsize = AtomicUsize::new(0);
+
+/// Enables or disables garbage collection steps between queued callbacks. Disabled by default.
+pub fn set_gc_stepping(stepping: Option<GcStepping>) {
+    *GC_STEPPING.lock().unwrap_or_else(|e| e.into_inner()) = stepping;
+    SINCE_GC_STEP.store(0, Ordering::Relaxed);
+}
+
+pub fn gc_stepping() -> Option<GcStepping> {
+    *GC_STEPPING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The queues, one per realm, which only exist between `open` and `close`. Threads queueing callbacks only hold the read lock for as long as it takes to send, so closing can never free a queue under them.
+static QUEUES: RwLock<[Option<TaskQueue>; 3]> = RwLock::new([None, None, None]);
+
+/// The realm `wait_lua_tick` queues to: the first realm that was opened, or the next one open once it's closed.
+static DEFAULT_REALM: AtomicU8 = AtomicU8::new(Realm::Server as u8);
+
+/// Incremented whenever the queue of a realm is closed, so a batch being run can notice that the state unloaded under it.
+static GENERATIONS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+/// Names of the timers created by `load`, per realm, removed by `unload` so they don't call into the unloaded module.
+static TIMERS: Mutex<[Option<String>; 3]> = Mutex::new([None, None, None]);
+
+/// What `#[gmod13_close]` does with the callbacks that are still queued. See `set_drain_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum DrainPolicy {
+    /// Drop them without running. This is the default.
+    #[default]
+    Drop,
+    /// Run each of them once, synchronously, while the module is in the `Closing` state. Callbacks they queue are refused.
+    RunOnce,
+    /// Hand them to the handlers registered with `add_orphan_handler`. Callbacks no handler claims are dropped.
+    Orphan,
+}
+
+static DRAIN_POLICY: AtomicU8 = AtomicU8::new(DrainPolicy::Drop as u8);
+
+type OrphanHandler = Arc<dyn Fn(State, PendingCallback) -> Option<PendingCallback> + Send + Sync>;
+
+static ORPHAN_HANDLERS: Mutex<Vec<OrphanHandler>> = Mutex::new(Vec::new());
+
+/// Sets what happens to the callbacks still queued when the module unloads.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::lua::task_queue::{self, DrainPolicy};
+///
+/// // acknowledgements queued by the database thread must still reach Lua
+/// task_queue::set_drain_policy(DrainPolicy::RunOnce);
+/// assert_eq!(task_queue::drain_policy(), DrainPolicy::RunOnce);
+/// ```
+pub fn set_drain_policy(policy: DrainPolicy) {
+    DRAIN_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn drain_policy() -> DrainPolicy {
+    match DRAIN_POLICY.load(Ordering::Relaxed) {
+        1 => DrainPolicy::RunOnce,
+        2 => DrainPolicy::Orphan,
+        _ => DrainPolicy::Drop,
+    }
 }
 
+/// Registers a handler for the callbacks left when the module unloads with `DrainPolicy::Orphan`.
+///
+/// Handlers are tried in the order they were added. A handler claims a callback by consuming it (running it, or moving it somewhere that outlives the queue) and returning `None`, or passes it on to the next handler by returning it.
+pub fn add_orphan_handler<F>(handler: F)
+where
+    F: Fn(State, PendingCallback) -> Option<PendingCallback> + Send + Sync + 'static,
+{
+    ORPHAN_HANDLERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Arc::new(handler));
+}
+
+/// A callback that was still queued when the module unloaded, handed to the orphan handlers.
+pub struct PendingCallback(CallbackCtx<'static>);
+
+impl PendingCallback {
+    /// The traceback given to `wait_lua_tick` when the callback was queued.
+    pub fn traceback(&self) -> &str {
+        &self.0.traceback
+    }
+
+    /// Runs the callback, catching Lua errors like the queue does.
+    pub fn run(self, l: State) {
+        process_callback(l, self.0);
+    }
+}
+
+impl std::fmt::Debug for PendingCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PendingCallback")
+            .field("traceback", &self.0.traceback)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Opens the queue of the realm of `l`, and creates the timer that runs its callbacks every tick. Called by `#[gmod13_open]`.
+///
+/// The first realm opened becomes the default realm, that `wait_lua_tick` queues to.
 pub fn load(l: State) {
-    unsafe {
-        TASK_QUEUE.write(TaskQueue::default());
+    let realm = l.realm();
+    if open_realms().is_empty() {
+        DEFAULT_REALM.store(realm as u8, Ordering::Release);
     }
+    open_in(realm);
+    MAIN_STATES[realm as usize].store(l.0, Ordering::Release);
 
     let random_str: String = repeat_with(fastrand::alphanumeric).take(10).collect();
-    let timer_name = format!("_GOOBIE_LUA_THINK_{random_str}_{:p}", Box::new(read()));
+    let timer_name = format!("_GOOBIE_LUA_THINK_{random_str}");
 
     l.get_global(c"timer");
     {
@@ -67,49 +322,603 @@ pub fn load(l: State) {
     }
     l.pop();
 
-    unsafe {
-        GMOD_CLOSED = false;
-    }
+    TIMERS.lock().unwrap_or_else(|e| e.into_inner())[realm as usize] = Some(timer_name);
 }
 
+/// Closes the queue of the realm of `l` and handles the callbacks that didn't run according to the drain policy. Called by `#[gmod13_close]`.
+///
+/// If it was the default realm, the next realm still open becomes the default.
 pub fn unload(l: State) {
-    unsafe { GMOD_CLOSED = true };
-    unsafe { TASK_QUEUE.assume_init_read() };
+    let realm = l.realm();
+    let pending = take_pending(realm);
+    match drain_policy() {
+        DrainPolicy::Drop => drop(pending),
+        DrainPolicy::RunOnce => {
+            for callback_ctx in pending {
+                process_callback(l, callback_ctx);
+            }
+        }
+        DrainPolicy::Orphan => {
+            // copied out, so handlers can add handlers without deadlocking
+            let handlers = ORPHAN_HANDLERS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone();
+            for callback_ctx in pending {
+                let mut pending = Some(PendingCallback(callback_ctx));
+                for handler in handlers.iter() {
+                    pending = handler(l, pending.take().unwrap());
+                    if pending.is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    let timer_name = TIMERS.lock().unwrap_or_else(|e| e.into_inner())[realm as usize].take();
+    if let Some(timer_name) = timer_name {
+        l.get_global(c"timer");
+        l.get_field(-1, c"Remove");
+        l.push_string(&timer_name);
+        l.pcall_ignore(1, 0);
+        l.pop();
+    }
+    MAIN_STATES[realm as usize].store(std::ptr::null_mut(), Ordering::Release);
+
+    if default_realm() == realm {
+        if let Some(&next) = open_realms().first() {
+            DEFAULT_REALM.store(next as u8, Ordering::Release);
+        }
+    }
+}
+
+/// Returns the realm `wait_lua_tick` queues to: the first realm that was opened, or the next one open once it's closed. The server if no realm was opened yet.
+pub fn default_realm() -> Realm {
+    Realm::from_u8(DEFAULT_REALM.load(Ordering::Acquire)).unwrap_or(Realm::Server)
 }
 
-pub fn wait_lua_tick<F>(traceback: String, callback: F)
+/// Returns the realms whose queue is open.
+pub fn open_realms() -> Vec<Realm> {
+    let queues = QUEUES.read().unwrap_or_else(|e| e.into_inner());
+    Realm::ALL
+        .into_iter()
+        .filter(|&realm| queues[realm as usize].is_some())
+        .collect()
+}
+
+/// Opens the queue of the default realm without creating the think timer, for hosts that call `run_callbacks` themselves (e.g. tests). Callbacks queued while the queue is closed are dropped.
+pub fn open() {
+    open_in(default_realm());
+}
+
+/// Same as `open`, for the queue of `realm`.
+pub fn open_in(realm: Realm) {
+    let [(high_tx, high_rx), (normal_tx, normal_rx), (low_tx, low_rx)] =
+        [(); 3].map(|_| flume::unbounded());
+    let previous =
+        QUEUES.write().unwrap_or_else(|e| e.into_inner())[realm as usize].replace(TaskQueue {
+            senders: [high_tx, normal_tx, low_tx],
+            receivers: [high_rx, normal_rx, low_rx],
+        });
+    if previous.is_some() {
+        GENERATIONS[realm as usize].fetch_add(1, Ordering::AcqRel);
+    }
+    // dropped outside of the lock, as dropping callbacks can queue more callbacks (e.g. `LuaRef`)
+    drop(previous);
+}
+
+/// Restores the settings of a fresh load: the orphan handlers, drain policy, tick budget, GC stepping and traceback capture of the previous load are forgotten. Called when the module is reloaded.
+pub(crate) fn reset() {
+    set_drain_policy(DrainPolicy::default());
+    set_capture_tracebacks(false);
+    set_tick_budget(TickBudget::UNLIMITED);
+    set_gc_stepping(None);
+    let handlers = std::mem::take(&mut *ORPHAN_HANDLERS.lock().unwrap_or_else(|e| e.into_inner()));
+    drop(handlers);
+}
+
+/// Closes the queue of every realm, returning how many callbacks were dropped without running. Unlike `unload`, this ignores the drain policy.
+pub fn close() -> usize {
+    Realm::ALL
+        .into_iter()
+        .map(|realm| take_pending(realm).len())
+        .sum()
+}
+
+/// Closes the queue of `realm` and returns the callbacks that didn't run. Callbacks queued to it from now on, including by the returned ones, are refused.
+fn take_pending(realm: Realm) -> Vec<CallbackCtx<'static>> {
+    let queue = QUEUES.write().unwrap_or_else(|e| e.into_inner())[realm as usize].take();
+    GENERATIONS[realm as usize].fetch_add(1, Ordering::AcqRel);
+
+    // dropped outside of the lock, as dropping callbacks can queue more callbacks
+    let scheduled: Vec<ScheduledTask> = SCHEDULED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .extract_if(.., |task| task.realm == realm)
+        .collect();
+    drop(scheduled);
+
+    let Some(queue) = queue else {
+        return Vec::new();
+    };
+    queue
+        .receivers
+        .iter()
+        .flat_map(|receiver| receiver.drain())
+        .collect()
+}
+
+/// Returns whether callbacks can be queued with `wait_lua_tick`, which is the case while the module is loaded.
+pub fn is_open() -> bool {
+    is_open_in(default_realm())
+}
+
+/// Returns whether callbacks can be queued to `realm` with `wait_lua_tick_in`, which is the case while the module is loaded in it.
+pub fn is_open_in(realm: Realm) -> bool {
+    QUEUES.read().unwrap_or_else(|e| e.into_inner())[realm as usize].is_some()
+}
+
+/// Returns the realm of `l`, for values that belong to the state they were created in (registry references, caches...). Only looks at the globals of `l` when several realms are open: with a single one, that's where it runs, and with none, the default realm is as good as any.
+pub(crate) fn realm_of(l: State) -> Realm {
+    let open = {
+        let queues = QUEUES.read().unwrap_or_else(|e| e.into_inner());
+        let mut open = Realm::ALL
+            .into_iter()
+            .filter(|&realm| queues[realm as usize].is_some());
+        (open.next(), open.next())
+    };
+    match open {
+        (None, _) => default_realm(),
+        (Some(realm), None) => realm,
+        (Some(_), Some(_)) => l.realm(),
+    }
+}
+
+/// Runs `callback` on the Lua thread on the next tick, in the default realm (see `default_realm`). Can be called from any thread.
+///
+/// Fails once the module is shutting down (see `gmod::lifecycle`), in which case the callback is dropped without running. It's also dropped if the module unloads before the next tick.
+pub fn wait_lua_tick<F>(traceback: String, callback: F) -> Result<(), ClosedError>
 where
     F: FnOnce(State) + Send + 'static,
 {
-    if unsafe { GMOD_CLOSED } {
-        return;
+    wait_lua_tick_with_priority(Priority::Normal, traceback, callback)
+}
+
+/// Same as `wait_lua_tick`, but runs `callback` with the state of `realm`, for modules loaded into several states at once. Fails if the module isn't open in `realm`.
+///
+/// ## Example
+///
+/// ```ignore
+/// // a clientside module loaded into both the client and the menu state
+/// gmod::lua::task_queue::wait_lua_tick_in(Realm::Menu, String::new(), |lua| {
+///     let _ = lua.call_global("RunGameUICommand").arg("disconnect").call::<()>();
+/// })?;
+/// ```
+pub fn wait_lua_tick_in<F>(realm: Realm, traceback: String, callback: F) -> Result<(), ClosedError>
+where
+    F: FnOnce(State) + Send + 'static,
+{
+    send(realm, Priority::Normal, traceback, callback)
+}
+
+/// Same as `wait_lua_tick`, but runs `callback` before the callbacks of lower priority. With a `TickBudget`, it may run on a later tick if the queue is flooded.
+///
+/// ## Example
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use gmod::lua::{task_queue::{self, Priority, TickBudget}, State};
+///
+/// task_queue::open();
+/// task_queue::set_tick_budget(TickBudget { max_callbacks: Some(2), max_time: None });
+///
+/// let order = Arc::new(Mutex::new(Vec::new()));
+/// for (priority, name) in [(Priority::Low, "low"), (Priority::Normal, "normal"), (Priority::High, "high")] {
+///     let order = order.clone();
+///     task_queue::wait_lua_tick_with_priority(priority, String::new(), move |_| order.lock().unwrap().push(name)).unwrap();
+/// }
+///
+/// let lua = State(std::ptr::null_mut());
+/// task_queue::run_callbacks_unprotected(lua);
+/// assert_eq!(*order.lock().unwrap(), ["high", "normal"]);
+///
+/// // the rest carries over to the next tick
+/// task_queue::run_callbacks_unprotected(lua);
+/// assert_eq!(*order.lock().unwrap(), ["high", "normal", "low"]);
+/// ```
+pub fn wait_lua_tick_with_priority<F>(
+    priority: Priority,
+    traceback: String,
+    callback: F,
+) -> Result<(), ClosedError>
+where
+    F: FnOnce(State) + Send + 'static,
+{
+    send(default_realm(), priority, traceback, callback)
+}
+
+fn send<F>(
+    realm: Realm,
+    priority: Priority,
+    traceback: String,
+    callback: F,
+) -> Result<(), ClosedError>
+where
+    F: FnOnce(State) + Send + 'static,
+{
+    let queues = QUEUES.read().unwrap_or_else(|e| e.into_inner());
+    let Some(queue) = queues[realm as usize].as_ref() else {
+        return Err(ClosedError);
+    };
+    if lifecycle::state() == ModuleState::Closing {
+        return Err(ClosedError);
     }
 
-    read().sender.send(CallbackCtx {
-        callback: Box::new(callback),
-        traceback: Cow::Owned(traceback),
+    queue.senders[priority as usize]
+        .send(CallbackCtx {
+            callback: Box::new(callback),
+            traceback: Cow::Owned(traceback),
+            origin: Origin::capture(realm),
+            #[cfg(feature = "profile")]
+            name: std::any::type_name::<F>(),
+        })
+        .map_err(|_| ClosedError)
+}
+
+/// Same as `wait_lua_tick`, but returns the value returned by `callback`, for worker threads that need something from Lua.
+///
+/// The result can be waited for with `recv` on a worker thread, or `.await`ed. Waiting on the Lua thread itself would never end, so `recv` panics there.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::lua::{task_queue, State};
+///
+/// # task_queue::open();
+/// let worker = std::thread::spawn(|| {
+///     // e.g. `lua.call_global("game.GetMap").call::<String>()`
+///     gmod::wait_lua_tick_result(String::new(), |_lua| 40 + 2).recv()
+/// });
+///
+/// // what the Lua thread does every tick
+/// while !worker.is_finished() {
+///     task_queue::run_callbacks_unprotected(State(std::ptr::null_mut()));
+/// }
+/// assert_eq!(worker.join().unwrap(), Ok(42));
+/// ```
+pub fn wait_lua_tick_result<R, F>(traceback: String, callback: F) -> LuaTickResult<R>
+where
+    R: Send + 'static,
+    F: FnOnce(State) -> R + Send + 'static,
+{
+    let shared = Arc::new(ResultSlot {
+        state: Mutex::new(ResultState {
+            value: None,
+            done: false,
+            waker: None,
+        }),
+        ready: Condvar::new(),
     });
-    COUNTER.fetch_add(1, Ordering::Release);
+    let sender = ResultSender(shared.clone());
+    // if the callback is dropped without running, dropping `sender` wakes the receiver
+    let _ = wait_lua_tick(traceback, move |l| sender.send(callback(l)));
+    LuaTickResult(shared)
 }
 
-pub fn run_callbacks(l: State) {
-    if unsafe { GMOD_CLOSED } {
-        return;
+struct ResultState<R> {
+    value: Option<R>,
+    /// Set once the callback ran, or was dropped without running.
+    done: bool,
+    waker: Option<Waker>,
+}
+
+struct ResultSlot<R> {
+    state: Mutex<ResultState<R>>,
+    ready: Condvar,
+}
+
+impl<R> ResultSlot<R> {
+    fn lock(&self) -> MutexGuard<'_, ResultState<R>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct ResultSender<R>(Arc<ResultSlot<R>>);
+
+impl<R> ResultSender<R> {
+    fn send(self, value: R) {
+        self.0.lock().value = Some(value);
+    }
+}
+
+impl<R> Drop for ResultSender<R> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.0.lock();
+            state.done = true;
+            state.waker.take()
+        };
+        self.0.ready.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// The value a callback queued with `wait_lua_tick_result` will return. Resolves to a `ClosedError` if the callback was dropped without running, e.g. because the module unloaded.
+#[must_use = "the callback runs either way, but its result is lost"]
+pub struct LuaTickResult<R>(Arc<ResultSlot<R>>);
+
+impl<R> LuaTickResult<R> {
+    /// Blocks until the callback ran.
+    ///
+    /// ## Panics
+    ///
+    /// On the Lua thread, which would wait for itself forever.
+    #[track_caller]
+    pub fn recv(self) -> Result<R, ClosedError> {
+        assert!(
+            !super::is_main_thread(),
+            "waiting for a Lua tick on the Lua thread would never end"
+        );
+        let mut state = self.0.lock();
+        while !state.done {
+            state = self.0.ready.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.value.take().ok_or(ClosedError)
+    }
+
+    /// Same as `recv`, but gives up after `timeout`, returning `Ok(None)`. It can be called again later.
+    #[track_caller]
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<R>, ClosedError> {
+        assert!(
+            !super::is_main_thread(),
+            "waiting for a Lua tick on the Lua thread would never end"
+        );
+        let (mut state, _) = self
+            .0
+            .ready
+            .wait_timeout_while(self.0.lock(), timeout, |state| !state.done)
+            .unwrap_or_else(|e| e.into_inner());
+        Self::take(&mut state)
+    }
+
+    /// Returns the result if the callback already ran, or `Ok(None)`.
+    pub fn try_recv(&mut self) -> Result<Option<R>, ClosedError> {
+        Self::take(&mut self.0.lock())
+    }
+
+    fn take(state: &mut ResultState<R>) -> Result<Option<R>, ClosedError> {
+        match (state.done, state.value.take()) {
+            (_, Some(value)) => Ok(Some(value)),
+            (true, None) => Err(ClosedError),
+            (false, None) => Ok(None),
+        }
+    }
+}
+
+impl<R> Future for LuaTickResult<R> {
+    type Output = Result<R, ClosedError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.lock();
+        match Self::take(&mut state) {
+            Ok(Some(value)) => Poll::Ready(Ok(value)),
+            Err(err) => Poll::Ready(Err(err)),
+            Ok(None) => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+type ScheduledCallback = Arc<Mutex<dyn FnMut(State) + Send>>;
+
+struct ScheduledTask {
+    realm: Realm,
+    due: Instant,
+    /// `None` for tasks that run once.
+    interval: Option<Duration>,
+    callback: ScheduledCallback,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Tasks waiting for their time to come, queued by `run_callbacks` once due. Cleared when the queue of their realm closes.
+static SCHEDULED: Mutex<Vec<ScheduledTask>> = Mutex::new(Vec::new());
+
+/// Cancels a task created by `schedule` or `schedule_repeating`. Dropping it doesn't cancel the task.
+#[derive(Debug, Clone)]
+pub struct TaskHandle(Arc<AtomicBool>);
+
+impl TaskHandle {
+    /// Stops the task from running again. A run that's already queued for this tick is skipped too.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
     }
 
-    if is_empty() {
-        return;
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
     }
+}
+
+/// Runs `callback` on the Lua thread once `delay` has passed, on the first tick after that, in the default realm. Can be called from any thread.
+///
+/// Like `wait_lua_tick`, this fails once the module is shutting down, and the task is dropped if the module unloads before it runs.
+pub fn schedule<F>(delay: Duration, callback: F) -> Result<TaskHandle, ClosedError>
+where
+    F: FnOnce(State) + Send + 'static,
+{
+    let mut callback = Some(callback);
+    add_scheduled(delay, None, move |l| {
+        if let Some(callback) = callback.take() {
+            callback(l)
+        }
+    })
+}
+
+/// Runs `callback` on the Lua thread every `interval`, starting `interval` from now, until the returned handle is cancelled or the module unloads, in the default realm. Can be called from any thread.
+///
+/// Ticks don't line up with the interval, so each run happens on the first tick after it's due. Runs that were missed, e.g. during a hitch, are skipped rather than run in a burst.
+///
+/// ## Example
+///
+/// ```ignore
+/// let autosave = task_queue::schedule_repeating(Duration::from_secs(300), |lua| {
+///     let _ = lua.call_global("MyGamemode.Save").call::<()>();
+/// })?;
+///
+/// // later, e.g. when the feature is turned off
+/// autosave.cancel();
+/// ```
+pub fn schedule_repeating<F>(interval: Duration, callback: F) -> Result<TaskHandle, ClosedError>
+where
+    F: FnMut(State) + Send + 'static,
+{
+    add_scheduled(interval, Some(interval), callback)
+}
 
-    let task_queue = read();
-    while let Ok(callback_ctx) = task_queue.receiver.try_recv() {
-        COUNTER.fetch_sub(1, Ordering::Release);
-        process_callback(l, callback_ctx);
+fn add_scheduled(
+    delay: Duration,
+    interval: Option<Duration>,
+    callback: impl FnMut(State) + Send + 'static,
+) -> Result<TaskHandle, ClosedError> {
+    let realm = default_realm();
+    if !is_open_in(realm) || lifecycle::state() == ModuleState::Closing {
+        return Err(ClosedError);
     }
+    let cancelled = Arc::new(AtomicBool::new(false));
+    SCHEDULED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(ScheduledTask {
+            realm,
+            due: Instant::now() + delay,
+            interval,
+            callback: Arc::new(Mutex::new(callback)),
+            cancelled: cancelled.clone(),
+        });
+    Ok(TaskHandle(cancelled))
 }
 
+/// Moves the scheduled tasks of `realm` that are due into its queue, in the order they were due.
+fn queue_due_tasks(realm: Realm) {
+    let now = Instant::now();
+    let mut due = Vec::new();
+    {
+        let mut scheduled = SCHEDULED.lock().unwrap_or_else(|e| e.into_inner());
+        scheduled.retain_mut(|task| {
+            if task.realm != realm {
+                return true;
+            }
+            if task.cancelled.load(Ordering::Acquire) {
+                return false;
+            }
+            if task.due > now {
+                return true;
+            }
+            due.push((task.due, task.callback.clone(), task.cancelled.clone()));
+            let Some(interval) = task.interval else {
+                return false;
+            };
+            task.due += interval;
+            if task.due <= now {
+                task.due = now + interval;
+            }
+            true
+        });
+    }
+
+    due.sort_by_key(|(due, ..)| *due);
+    for (_, callback, cancelled) in due {
+        let _ = send(realm, Priority::Normal, String::new(), move |l| {
+            if !cancelled.load(Ordering::Acquire) {
+                (callback.lock().unwrap_or_else(|e| e.into_inner()))(l);
+            }
+        });
+    }
+}
+
+/// Runs the callbacks queued to the realm of `l` before this call, highest priority first and within the `TickBudget`. Callbacks queued while running are left for the next call, so a callback that queues itself can't hang the game.
+pub fn run_callbacks(l: State) {
+    run_batch(l.realm(), l, process_callback);
+}
+
+/// Same as `run_callbacks`, for the default realm, but calls the callbacks directly instead of through `lua_cpcall`, so `l` is never used by the queue itself. A panic in a callback isn't caught.
+///
+/// This is meant for running the queue without a real Lua state, e.g. in tests, where `l` can be any value the callbacks accept.
+pub fn run_callbacks_unprotected(l: State) {
+    run_batch(default_realm(), l, |l, callback_ctx| {
+        (callback_ctx.callback)(l)
+    });
+}
+
+fn run_batch(realm: Realm, l: State, mut process: impl FnMut(State, CallbackCtx<'static>)) {
+    queue_due_tasks(realm);
+
+    let generation = &GENERATIONS[realm as usize];
+    let (receivers, generation_before) = {
+        let queues = QUEUES.read().unwrap_or_else(|e| e.into_inner());
+        let Some(queue) = queues[realm as usize].as_ref() else {
+            return;
+        };
+        (queue.receivers.clone(), generation.load(Ordering::Acquire))
+    };
+
+    let budget = tick_budget();
+    let gc_stepping = gc_stepping();
+    let started = Instant::now();
+    let mut ran = 0;
+
+    // only what was queued before the batch started runs
+    let batch = receivers.each_ref().map(|receiver| receiver.len());
+    'batch: for (receiver, len) in receivers.iter().zip(batch) {
+        for _ in 0..len {
+            // a callback unloaded the module, the rest is dropped with `receivers`
+            if generation.load(Ordering::Acquire) != generation_before {
+                break 'batch;
+            }
+            if ran > 0 && budget.exhausted(ran, started) {
+                break 'batch;
+            }
+            let Ok(callback_ctx) = receiver.try_recv() else {
+                break;
+            };
+            process(l, callback_ctx);
+            ran += 1;
+
+            if let Some(stepping) = gc_stepping {
+                if SINCE_GC_STEP.fetch_add(1, Ordering::Relaxed) + 1 >= stepping.every {
+                    SINCE_GC_STEP.store(0, Ordering::Relaxed);
+                    l.gc_step(stepping.step_kb);
+                }
+            }
+        }
+    }
+
+    if generation.load(Ordering::Acquire) != generation_before {
+        // the queue was closed, nothing else must run
+        for receiver in receivers {
+            receiver.drain().for_each(drop);
+        }
+    }
+}
+
+/// Returns how many callbacks are waiting for the next tick, in every realm.
 pub fn len() -> usize {
-    COUNTER.load(Ordering::Acquire)
+    Priority::ALL.into_iter().map(len_of).sum()
+}
+
+/// Returns how many callbacks of the given priority are waiting, in every realm.
+pub fn len_of(priority: Priority) -> usize {
+    QUEUES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .flatten()
+        .map(|queue| queue.receivers[priority as usize].len())
+        .sum()
 }
 
 pub fn is_empty() -> bool {
@@ -118,18 +927,26 @@ pub fn is_empty() -> bool {
 
 fn process_callback(l: State, mut callback_ctx: CallbackCtx) {
     let traceback = std::mem::replace(&mut callback_ctx.traceback, Cow::Borrowed(""));
+    let origin = callback_ctx.origin.take();
 
+    let top = l.get_top();
     let callback_ctx_ptr: *mut c_void = Box::into_raw(Box::new(callback_ctx)) as *mut c_void;
-    l.cpcall_ignore(handle_task_queue, callback_ctx_ptr, Some(&traceback));
+    if let Err(err) = l.cpcall(handle_task_queue, callback_ctx_ptr) {
+        let trace = format_trace(&traceback, origin.as_deref());
+        l.error_no_halt(&err.to_string(), (!trace.is_empty()).then_some(&trace));
+    }
+    l.set_top(top);
 }
 
 extern "C-unwind" fn handle_task_queue(l: State) -> i32 {
     let callback_ctx_ptr = l.to_userdata(1);
     let callback_ctx = unsafe { Box::from_raw(callback_ctx_ptr as *mut CallbackCtx) };
 
-    let traceback = callback_ctx.traceback;
     let callback = callback_ctx.callback;
 
+    #[cfg(feature = "profile")]
+    let _profile = crate::profile::scope(callback_ctx.name);
+
     callback(l);
     // Box::from_raw will automatically drop the callback
 
@@ -0,0 +1,97 @@
+use anyhow::{bail, Result};
+
+use super::{task_queue, LuaReference, State, LUA_NOREF};
+
+/// A handle to a coroutine suspended by [`State::yieldable`].
+///
+/// Can be sent to another thread and used to resume the coroutine later on the Lua thread.
+///
+/// If it is dropped without being resumed, the coroutine stays suspended forever and is released for garbage collection.
+pub struct Resumer {
+    thread_ref: LuaReference,
+    thread: usize,
+}
+
+unsafe impl Send for Resumer {}
+
+impl Resumer {
+    /// Resumes the coroutine on the next Lua tick.
+    ///
+    /// `push` is called with the coroutine's state and should push the values that the yielding call returns to Lua, returning how many values were pushed.
+    pub fn resume<F>(self, push: F)
+    where
+        F: FnOnce(State) -> i32 + Send + 'static,
+    {
+        let _ = task_queue::wait_lua_tick(String::new(), move |l| self.resume_now(l, push));
+    }
+
+    /// Resumes the coroutine immediately. Must be called from the Lua thread.
+    ///
+    /// See `resume`
+    pub fn resume_now<F>(mut self, l: State, push: F)
+    where
+        F: FnOnce(State) -> i32,
+    {
+        let thread = State(self.thread as *mut _);
+        let thread_ref = std::mem::replace(&mut self.thread_ref, LUA_NOREF);
+
+        let narg = push(thread);
+        let _ = thread.coroutine_resume_ignore(narg, None);
+        // Any values yielded or returned by the coroutine from here on have no receiver
+        thread.set_top(0);
+
+        l.dereference(thread_ref);
+    }
+}
+
+impl Drop for Resumer {
+    fn drop(&mut self) {
+        let thread_ref = self.thread_ref;
+        if thread_ref != LUA_NOREF {
+            let _ = task_queue::wait_lua_tick(String::new(), move |l| l.dereference(thread_ref));
+        }
+    }
+}
+
+impl State {
+    /// Suspends the running coroutine, handing a [`Resumer`] to `f` that can resume it later (for example, once a background thread finishes its work).
+    ///
+    /// The returned value must be returned from your Lua function.
+    ///
+    /// Fails if the function was not called from within a coroutine.
+    ///
+    /// ## Example
+    ///
+    /// ```ignore
+    /// #[lua_function]
+    /// fn sleep(lua: gmod::lua::State) -> anyhow::Result<i32> {
+    ///     let secs = lua.check_number(1)?;
+    ///     lua.yieldable(move |_, resumer| {
+    ///         std::thread::spawn(move || {
+    ///             std::thread::sleep(std::time::Duration::from_secs_f64(secs));
+    ///             resumer.resume(|l| {
+    ///                 l.push_bool(true);
+    ///                 1
+    ///             });
+    ///         });
+    ///     })
+    /// }
+    /// ```
+    pub fn yieldable<F>(&self, f: F) -> Result<i32>
+    where
+        F: FnOnce(State, Resumer),
+    {
+        if self.push_thread() == 1 {
+            self.pop();
+            bail!("attempt to yield from outside a coroutine");
+        }
+
+        let resumer = Resumer {
+            thread_ref: self.reference(),
+            thread: self.0 as usize,
+        };
+        f(*self, resumer);
+
+        Ok(self.coroutine_yield(0))
+    }
+}
@@ -0,0 +1,117 @@
+//! Markdown to HTML conversion for in-game HTML panels (`DHTML`), e.g. help screens and changelogs.
+//!
+//! The generated HTML is sanitized with [ammonia](https://docs.rs/ammonia), so markdown written by players (or fetched from the web) can't run scripts in the panel. Pairs well with `gmod::template` to wrap the output in a page.
+
+use anyhow::Result;
+use pulldown_cmark::{html, Options, Parser};
+
+use crate::lua::{self, HandleLuaFunctionReturn, LuaReg, State, LUA_TBOOLEAN};
+
+/// Which markdown extensions and HTML elements `to_html` allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MarkdownOptions {
+    /// GitHub-style tables.
+    pub tables: bool,
+    /// `~~strikethrough~~`.
+    pub strikethrough: bool,
+    /// `- [x] task` lists, rendered as disabled checkboxes.
+    pub task_lists: bool,
+    /// Keeps images. Off by default, as loading remote images leaks the player's IP address to whoever wrote the markdown.
+    pub images: bool,
+    /// Keeps links. Only `http`, `https` and `mailto` links are kept either way.
+    pub links: bool,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        Self {
+            tables: true,
+            strikethrough: true,
+            task_lists: true,
+            images: false,
+            links: true,
+        }
+    }
+}
+
+/// Converts markdown to sanitized HTML.
+///
+/// Raw HTML in the markdown is kept only if it's harmless: scripts, styles, event handlers, iframes and the like are removed.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::markdown::{to_html, MarkdownOptions};
+///
+/// let html = to_html("# Rules\n\nBe **nice**<script>alert(1)</script>", &MarkdownOptions::default());
+///
+/// assert_eq!(html, "<h1>Rules</h1>\n<p>Be <strong>nice</strong></p>\n");
+/// ```
+pub fn to_html(markdown: &str, options: &MarkdownOptions) -> String {
+    let mut extensions = Options::empty();
+    extensions.set(Options::ENABLE_TABLES, options.tables);
+    extensions.set(Options::ENABLE_STRIKETHROUGH, options.strikethrough);
+    extensions.set(Options::ENABLE_TASKLISTS, options.task_lists);
+
+    let mut unsafe_html = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, extensions));
+
+    let mut sanitizer = ammonia::Builder::default();
+    sanitizer.url_schemes(["http", "https", "mailto"].into());
+    if !options.images {
+        sanitizer.rm_tags(["img"]);
+    }
+    if !options.links {
+        sanitizer.rm_tags(["a"]);
+    }
+    if options.task_lists {
+        // the only inputs kept are the read-only checkboxes of task lists
+        sanitizer
+            .add_tags(["input"])
+            .add_tag_attributes("input", ["checked"])
+            .set_tag_attribute_value("input", "type", "checkbox")
+            .set_tag_attribute_value("input", "disabled", "");
+    }
+    sanitizer.clean(&unsafe_html).to_string()
+}
+
+/// Registers the markdown functions into a global table named `libname`:
+///
+/// - `MarkdownToHTML(markdown[, options])`, where `options` is a table with the boolean fields of `MarkdownOptions` (`tables`, `strikethrough`, `task_lists`, `images`, `links`). Missing fields keep their default.
+pub fn register(lua: State, libname: lua::LuaCStr) {
+    lua.register(
+        libname.as_ptr(),
+        crate::lua_regs![
+            "MarkdownToHTML" => lua_to_html,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+}
+
+extern "C-unwind" fn lua_to_html(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let markdown = lua.check_string(1)?;
+
+        let mut options = MarkdownOptions::default();
+        if !lua.is_none_or_nil(2) {
+            lua.check_table(2)?;
+            for (name, option) in [
+                (c"tables", &mut options.tables),
+                (c"strikethrough", &mut options.strikethrough),
+                (c"task_lists", &mut options.task_lists),
+                (c"images", &mut options.images),
+                (c"links", &mut options.links),
+            ] {
+                if lua.get_field_type_or_nil(2, name, LUA_TBOOLEAN)? {
+                    *option = lua.get_boolean(-1);
+                    lua.pop();
+                }
+            }
+        }
+
+        lua.push_string(&to_html(&markdown, &options));
+        Ok(1)
+    })()
+    .handle_result(lua)
+}
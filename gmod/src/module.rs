@@ -0,0 +1,134 @@
+//! Binary modules written as a struct holding their state, instead of free functions and statics.
+//!
+//! `#[gmod_module]` on a struct implementing `GmodModule` (and `Default`) generates `gmod13_open` and `gmod13_close`: the struct is created and opened when the module is loaded, kept until it's closed, and can be reached from Lua functions with `with`. Everything `#[gmod13_open]` and `#[gmod13_close]` do is done as well, plus installing the panic hook (see `gmod::panic`).
+//!
+//! A module loaded into several states (see `gmod::realm`) has one struct per state, reached with `with_in`.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! #[gmod_module]
+//! #[derive(Default)]
+//! struct Counter {
+//!     ticks: u64,
+//! }
+//!
+//! impl GmodModule for Counter {
+//!     const THINK: bool = true;
+//!
+//!     fn open(&mut self, lua: State) -> anyhow::Result<()> {
+//!         lua.push_function(ticks);
+//!         lua.set_global(c"CounterTicks");
+//!         Ok(())
+//!     }
+//!
+//!     fn think(&mut self, _lua: State) {
+//!         self.ticks += 1;
+//!     }
+//! }
+//!
+//! #[lua_function]
+//! fn ticks(_lua: State) -> (f64,) {
+//!     (gmod::module::with(|counter: &mut Counter| counter.ticks).unwrap_or(0) as f64,)
+//! }
+//! ```
+
+use std::{any::Any, cell::RefCell};
+
+use crate::{
+    lua::{task_queue, HandleLuaFunctionReturn, State},
+    realm::Realm,
+};
+
+/// The identifier of the `Think` hook added for `GmodModule::think`.
+const THINK_HOOK: &str = "gmod_rs_module";
+
+thread_local! {
+    /// The module's struct of each realm, while it's open and not lent to one of its methods.
+    static MODULES: RefCell<[Option<Box<dyn Any>>; 3]> = const { RefCell::new([None, None, None]) };
+}
+
+/// What `GmodModule::open` returns.
+pub type OpenResult = anyhow::Result<()>;
+
+/// A binary module, see the module documentation.
+pub trait GmodModule: Default + 'static {
+    /// Whether `think` is called every tick, from a `Think` hook.
+    const THINK: bool = false;
+
+    /// Called by `gmod13_open`. An error is raised to the Lua code that loaded the module, and the module isn't opened.
+    fn open(&mut self, lua: State) -> OpenResult;
+
+    /// Called by `gmod13_close`, before gmod-rs unloads its own state, so hooks, timers and the task queue can still be used.
+    fn close(&mut self, lua: State) {
+        let _ = lua;
+    }
+
+    /// Called every tick if `THINK` is set.
+    fn think(&mut self, lua: State) {
+        let _ = lua;
+    }
+}
+
+/// Calls `f` with the module's struct, of the default realm (see `task_queue::default_realm`) when the module is loaded into several states.
+///
+/// Returns `None` if the module isn't open, isn't a `T`, or is already lent: while one of its methods runs (so also from Lua functions they call), or from within `f`.
+pub fn with<T: GmodModule, R>(f: impl FnOnce(&mut T) -> R) -> Option<R> {
+    with_in(task_queue::default_realm(), f)
+}
+
+/// Same as `with`, with the struct of the state of `realm`.
+pub fn with_in<T: GmodModule, R>(realm: Realm, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+    let mut lent = Lent(
+        realm,
+        MODULES.with_borrow_mut(|modules| modules[realm as usize].take()),
+    );
+    lent.1.as_mut()?.downcast_mut::<T>().map(f)
+}
+
+/// A struct taken out of `MODULES`, put back when dropped, even if the borrower panicked.
+struct Lent(Realm, Option<Box<dyn Any>>);
+
+impl Drop for Lent {
+    fn drop(&mut self) {
+        if let Some(module) = self.1.take() {
+            MODULES.with_borrow_mut(|modules| modules[self.0 as usize] = Some(module));
+        }
+    }
+}
+
+/// Creates and opens the module. Called by `#[gmod_module]`'s `gmod13_open`.
+#[doc(hidden)]
+pub fn open<T: GmodModule>(lua: State) -> OpenResult {
+    crate::panic::install();
+
+    let mut module = T::default();
+    module.open(lua)?;
+    MODULES.with_borrow_mut(|modules| modules[lua.realm() as usize] = Some(Box::new(module)));
+    if T::THINK {
+        crate::hook::add(lua, "Think", THINK_HOOK, think::<T>);
+    }
+    Ok(())
+}
+
+/// Closes and drops the module, if it was opened. Called by `#[gmod_module]`'s `gmod13_close`.
+#[doc(hidden)]
+pub fn close<T: GmodModule>(lua: State) {
+    if T::THINK {
+        crate::hook::remove(lua, "Think", THINK_HOOK);
+    }
+    if let Some(mut module) =
+        MODULES.with_borrow_mut(|modules| modules[lua.realm() as usize].take())
+    {
+        if let Some(module) = module.downcast_mut::<T>() {
+            module.close(lua);
+        }
+    }
+}
+
+extern "C-unwind" fn think<T: GmodModule>(lua: State) -> i32 {
+    match crate::panic::catch(|| with_in(lua.realm(), |module: &mut T| module.think(lua))) {
+        Ok(_) => 0,
+        Err(panic) => Err::<i32, _>(panic).handle_result(lua),
+    }
+}
@@ -1,27 +1,154 @@
-use crate::lua::{self, LuaFunction};
+use std::{cell::RefCell, collections::HashMap, panic::Location};
 
-#[inline(always)]
-pub unsafe fn add_network_strings<S: AsRef<str>>(lua: lua::State, network_strings: &[S]) {
-    match network_strings.len() {
-        0 => {}
-        1 => {
-            lua.get_global(c"util");
-            lua.get_field(-1, c"AddNetworkString");
-            lua.push_string(network_strings[0].as_ref());
-            lua.call(1, 0);
-            lua.pop();
+use anyhow::{anyhow, Result};
+
+use crate::{
+    callbacks::{self, Registry},
+    lua::{self, LuaFunction, LuaRef, StackIndex},
+    player::Player,
+    realm::Realm,
+    userdata::Vector,
+};
+
+/// Chunked transfers of byte buffers larger than a single net message
+pub mod stream;
+
+/// Adds network strings with `util.AddNetworkString`, through the `NetRegistry`. Serverside only.
+#[track_caller]
+pub fn add_network_strings<S: AsRef<str>>(lua: lua::State, network_strings: &[S]) {
+    NetRegistry::add(lua, network_strings);
+}
+
+/// A network string added through the `NetRegistry`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkString {
+    /// The name, as it was first given.
+    pub name: String,
+    /// Where it was first added from.
+    pub location: &'static Location<'static>,
+    /// Whether `util.AddNetworkString` was called for it. Strings added with `NetRegistry::add_lazy` aren't until a message is sent with them.
+    pub added: bool,
+}
+
+thread_local! {
+    /// By lowercase name, as the game compares network strings without case.
+    static NETWORK_STRINGS: RefCell<HashMap<String, NetworkString>> = RefCell::new(HashMap::new());
+}
+
+/// The network strings added by the module, to catch two parts of it (or two modules sharing a prefix) using the same string for different messages, and to list them when debugging.
+///
+/// Adding a string that was already added from another place in the code prints a warning. Adding it again from the same place, e.g. every time a function runs, doesn't.
+///
+/// ## Example
+///
+/// ```ignore
+/// use gmod::net::NetRegistry;
+///
+/// NetRegistry::add(lua, &["my_addon_update", "my_addon_request"]);
+/// // only added if a message is sent with it
+/// NetRegistry::add_lazy(&["my_addon_debug"]);
+///
+/// for string in NetRegistry::entries() {
+///     println!("{} (added at {}, {})", string.name, string.location, if string.added { "sent" } else { "lazy" });
+/// }
+/// ```
+pub struct NetRegistry;
+
+impl NetRegistry {
+    /// Adds network strings with `util.AddNetworkString`. Serverside only.
+    #[track_caller]
+    pub fn add<S: AsRef<str>>(lua: lua::State, network_strings: &[S]) {
+        let location = Location::caller();
+        for network_string in network_strings {
+            let name = network_string.as_ref();
+            let added = Self::track(name, location, true, |warning| {
+                crate::gmod_warn!(lua, "{warning}")
+            });
+            if !added {
+                Self::add_now(lua, name);
+            }
+        }
+    }
+
+    /// Records network strings to add the first time a message is sent with them, so strings of features that aren't used don't take up room in the string table. Serverside only.
+    ///
+    /// The client can't receive a message before the string was added, so this is only for messages sent by the server first.
+    #[track_caller]
+    pub fn add_lazy<S: AsRef<str>>(network_strings: &[S]) {
+        let location = Location::caller();
+        for network_string in network_strings {
+            Self::track(network_string.as_ref(), location, false, |warning| {
+                eprintln!("[gmod-rs] {warning}")
+            });
+        }
+    }
+
+    /// Records `name`, warning if it was recorded from another place, and returns whether it was added already. With `add`, it's marked as added.
+    fn track(
+        name: &str,
+        location: &'static Location<'static>,
+        add: bool,
+        warn: impl FnOnce(String),
+    ) -> bool {
+        let mut warning = None;
+        let added = NETWORK_STRINGS.with_borrow_mut(|strings| {
+            let entry = strings
+                .entry(name.to_lowercase())
+                .or_insert_with(|| NetworkString {
+                    name: name.to_string(),
+                    location,
+                    added: false,
+                });
+            if entry.location != location {
+                warning = Some(format!(
+                    "network string {name:?} was already added at {}, and is added again at {location}",
+                    entry.location
+                ));
+            }
+            let added = entry.added;
+            entry.added |= add;
+            added
+        });
+        if let Some(warning) = warning {
+            warn(warning);
+        }
+        added
+    }
+
+    fn add_now(lua: lua::State, name: &str) {
+        lua.get_global(c"util");
+        lua.get_field(-1, c"AddNetworkString");
+        lua.push_string(name);
+        lua.pcall_ignore(1, 0);
+        lua.pop();
+    }
+
+    /// Adds `name` if it was recorded by `add_lazy` and isn't added yet. Called before sending a message.
+    fn add_pending(lua: lua::State, name: &str) {
+        if lua.realm() != Realm::Server {
+            return;
         }
-        _ => {
-            lua.get_global(c"util");
-            lua.get_field(-1, c"AddNetworkString");
-            for network_string in network_strings {
-                lua.push_value(-1);
-                lua.push_string(network_string.as_ref());
-                lua.call(1, 0);
-            }
-            lua.pop_n(2);
+        let pending = NETWORK_STRINGS.with_borrow_mut(|strings| {
+            let entry = strings.get_mut(&name.to_lowercase())?;
+            (!entry.added).then(|| entry.added = true)
+        });
+        if pending.is_some() {
+            Self::add_now(lua, name);
         }
     }
+
+    /// Returns whether `name` was added, or recorded by `add_lazy`.
+    pub fn contains(name: &str) -> bool {
+        NETWORK_STRINGS.with_borrow(|strings| strings.contains_key(&name.to_lowercase()))
+    }
+
+    /// Returns the network strings added or recorded by the module, sorted by name.
+    pub fn entries() -> Vec<NetworkString> {
+        let mut entries: Vec<NetworkString> =
+            NETWORK_STRINGS.with_borrow(|strings| strings.values().cloned().collect());
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    }
 }
 
 #[inline(always)]
@@ -35,3 +162,544 @@ pub fn receive<S: AsRef<str>>(lua: lua::State, network_string: S, func: LuaFunct
     };
     lua.pop();
 }
+
+/// A value queued by `NetMessage`, written with the matching `net.Write*` function when the message is sent.
+#[derive(Debug, Clone, PartialEq)]
+enum NetWrite {
+    UInt(u64, u32),
+    Int(i64, u32),
+    Bool(bool),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Data(Vec<u8>),
+}
+
+impl NetWrite {
+    fn write(&self, lua: lua::State) -> Result<(), lua::LuaError> {
+        let func = match self {
+            NetWrite::UInt(..) => c"WriteUInt",
+            NetWrite::Int(..) => c"WriteInt",
+            NetWrite::Bool(_) => c"WriteBool",
+            NetWrite::Float(_) => c"WriteFloat",
+            NetWrite::Double(_) => c"WriteDouble",
+            NetWrite::String(_) => c"WriteString",
+            NetWrite::Data(_) => c"WriteData",
+        };
+        call_net(lua, func, || match self {
+            NetWrite::UInt(value, bits) => {
+                lua.push_number(*value);
+                lua.push_number(*bits);
+                2
+            }
+            NetWrite::Int(value, bits) => {
+                lua.push_number(*value);
+                lua.push_number(*bits);
+                2
+            }
+            NetWrite::Bool(value) => {
+                lua.push_boolean(*value);
+                1
+            }
+            NetWrite::Float(value) => {
+                lua.push_number(*value);
+                1
+            }
+            NetWrite::Double(value) => {
+                lua.push_number(*value);
+                1
+            }
+            NetWrite::String(value) => {
+                lua.push_string(value);
+                1
+            }
+            NetWrite::Data(value) => {
+                lua.push_binary_string(value);
+                lua.push_number(value.len());
+                2
+            }
+        })
+    }
+}
+
+/// Calls `net.<func>` with the arguments pushed by `push_args`, catching the Lua error it may raise (e.g. for an unknown network string or an invalid player) instead of letting it unwind through Rust frames.
+fn call_net(
+    lua: lua::State,
+    func: lua::LuaCStr,
+    push_args: impl FnOnce() -> i32,
+) -> Result<(), lua::LuaError> {
+    lua.get_global(c"net");
+    lua.get_field(-1, func);
+    unsafe { lua.remove(-2) };
+    let nargs = push_args();
+    let result = lua.pcall(nargs, 0, 0);
+    if result.is_err() {
+        lua.pop();
+    }
+    result
+}
+
+/// A net message being built, created with `start`.
+///
+/// The writes are queued and only performed (between `net.Start` and the send function) when the message is sent, so a message can be built without access to the Lua state.
+///
+/// ## Example
+///
+/// ```ignore
+/// gmod::net::start("my_addon_update")
+///     .write_u32(5)
+///     .write_string("hi")
+///     .send_to_server(lua)?;
+/// ```
+#[must_use = "the message is only sent by calling one of the send functions"]
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetMessage {
+    name: String,
+    unreliable: bool,
+    writes: Vec<NetWrite>,
+}
+
+/// Who a net message is sent to. See `send` and `NetMessage::send_to`.
+#[derive(Debug, Clone, Copy)]
+pub enum Targets<'a> {
+    /// The server, like `net.SendToServer`. Clientside only.
+    Server,
+    /// Every player, like `net.Broadcast`.
+    All,
+    /// A single player, like `net.Send(ply)`.
+    Player(&'a Player),
+    /// Several players, like `net.Send` with a table of players.
+    Players(&'a [Player]),
+    /// Every player except these, like `net.SendOmit`.
+    AllExcept(&'a [Player]),
+    /// The players that could see the position, like `net.SendPVS`.
+    Pvs(Vector),
+    /// The players that could hear the position, like `net.SendPAS`.
+    Pas(Vector),
+}
+
+/// Builds a net message with `write` and sends it to `targets`. Every target but `Targets::Server` is serverside only.
+///
+/// Fails if the net library raised an error, e.g. because the network string wasn't added or a player is no longer valid.
+///
+/// ## Example
+///
+/// ```ignore
+/// use gmod::net::{self, Targets};
+///
+/// net::send(lua, "my_addon_update", Targets::Player(&ply), |msg| {
+///     msg.write_u32(5).write_string("hi")
+/// })?;
+/// net::send(lua, "my_addon_explosion", Targets::Pas(pos), |msg| msg.write_float(radius))?;
+/// ```
+pub fn send<S: AsRef<str>>(
+    lua: lua::State,
+    network_string: S,
+    targets: Targets,
+    write: impl FnOnce(NetMessage) -> NetMessage,
+) -> Result<()> {
+    write(start(network_string)).send_to(lua, targets)
+}
+
+/// Starts building a net message. The network string must have been added with `add_network_strings` (or `NetRegistry::add_lazy`) on the server.
+pub fn start<S: AsRef<str>>(network_string: S) -> NetMessage {
+    NetMessage {
+        name: network_string.as_ref().to_string(),
+        unreliable: false,
+        writes: Vec::new(),
+    }
+}
+
+impl NetMessage {
+    /// Sends the message unreliably: it can be dropped or arrive out of order, but is faster to send.
+    pub fn unreliable(mut self) -> Self {
+        self.unreliable = true;
+        self
+    }
+
+    /// Writes an unsigned integer using `bits` bits (1 to 32).
+    pub fn write_uint(mut self, value: u32, bits: u32) -> Self {
+        self.writes.push(NetWrite::UInt(value as u64, bits));
+        self
+    }
+
+    /// Writes a signed integer using `bits` bits (2 to 32).
+    pub fn write_int(mut self, value: i32, bits: u32) -> Self {
+        self.writes.push(NetWrite::Int(value as i64, bits));
+        self
+    }
+
+    pub fn write_u8(self, value: u8) -> Self {
+        self.write_uint(value as u32, 8)
+    }
+
+    pub fn write_u16(self, value: u16) -> Self {
+        self.write_uint(value as u32, 16)
+    }
+
+    pub fn write_u32(self, value: u32) -> Self {
+        self.write_uint(value, 32)
+    }
+
+    pub fn write_i8(self, value: i8) -> Self {
+        self.write_int(value as i32, 8)
+    }
+
+    pub fn write_i16(self, value: i16) -> Self {
+        self.write_int(value as i32, 16)
+    }
+
+    pub fn write_i32(self, value: i32) -> Self {
+        self.write_int(value, 32)
+    }
+
+    /// Writes a 64-bit unsigned integer as two 32-bit halves, low half first. Read it back with `NetReader::read_u64`.
+    pub fn write_u64(self, value: u64) -> Self {
+        self.write_u32(value as u32).write_u32((value >> 32) as u32)
+    }
+
+    pub fn write_bool(mut self, value: bool) -> Self {
+        self.writes.push(NetWrite::Bool(value));
+        self
+    }
+
+    pub fn write_float(mut self, value: f32) -> Self {
+        self.writes.push(NetWrite::Float(value));
+        self
+    }
+
+    pub fn write_double(mut self, value: f64) -> Self {
+        self.writes.push(NetWrite::Double(value));
+        self
+    }
+
+    /// Writes a null-terminated string. Use `write_data` for binary data.
+    pub fn write_string<S: AsRef<str>>(mut self, value: S) -> Self {
+        self.writes
+            .push(NetWrite::String(value.as_ref().to_string()));
+        self
+    }
+
+    /// Writes raw bytes. The reader needs to know the length, so it is usually written first.
+    pub fn write_data<B: AsRef<[u8]>>(mut self, value: B) -> Self {
+        self.writes.push(NetWrite::Data(value.as_ref().to_vec()));
+        self
+    }
+
+    /// Same as `write_data`, preceded by the length as a `u32`. Read it back with `NetReader::read_bytes`.
+    pub fn write_bytes<B: AsRef<[u8]>>(self, value: B) -> Self {
+        let value = value.as_ref();
+        self.write_u32(value.len() as u32).write_data(value)
+    }
+
+    fn send_with(
+        self,
+        lua: lua::State,
+        send: lua::LuaCStr,
+        push_target: impl FnOnce() -> i32,
+    ) -> Result<()> {
+        NetRegistry::add_pending(lua, &self.name);
+
+        let sent = (|| {
+            call_net(lua, c"Start", || {
+                lua.push_string(&self.name);
+                lua.push_boolean(self.unreliable);
+                2
+            })?;
+            for write in &self.writes {
+                write.write(lua)?;
+            }
+            call_net(lua, send, push_target)
+        })();
+        sent.map_err(|err| anyhow!("sending net message {:?} failed: {err}", self.name))
+    }
+
+    /// Sends the message to `targets`. Every target but `Targets::Server` is serverside only.
+    ///
+    /// Fails if the net library raised an error, e.g. because the network string wasn't added or a player is no longer valid.
+    pub fn send_to(self, lua: lua::State, targets: Targets) -> Result<()> {
+        let push_players = |players: &[Player]| {
+            lua.create_table(players.len() as i32, 0);
+            for (i, ply) in players.iter().enumerate() {
+                ply.push(lua);
+                lua.raw_seti(-2, i as i32 + 1);
+            }
+            1
+        };
+        match targets {
+            Targets::Server => self.send_to_server(lua),
+            Targets::All => self.broadcast(lua),
+            Targets::Player(ply) => self.send_with(lua, c"Send", || {
+                ply.push(lua);
+                1
+            }),
+            Targets::Players(players) => self.send_with(lua, c"Send", || push_players(players)),
+            Targets::AllExcept(players) => {
+                self.send_with(lua, c"SendOmit", || push_players(players))
+            }
+            Targets::Pvs(pos) => self.send_with(lua, c"SendPVS", || {
+                lua.push_vector(pos);
+                1
+            }),
+            Targets::Pas(pos) => self.send_with(lua, c"SendPAS", || {
+                lua.push_vector(pos);
+                1
+            }),
+        }
+    }
+
+    /// Sends the message to the server. Clientside only.
+    pub fn send_to_server(self, lua: lua::State) -> Result<()> {
+        self.send_with(lua, c"SendToServer", || 0)
+    }
+
+    /// Sends the message to every player. Serverside only.
+    pub fn broadcast(self, lua: lua::State) -> Result<()> {
+        self.send_with(lua, c"Broadcast", || 0)
+    }
+
+    /// Sends the message to the player, table of players or `CRecipientFilter` at the given stack index. Serverside only.
+    pub fn send(self, lua: lua::State, recipients: impl Into<StackIndex>) -> Result<()> {
+        // the index would shift while the message is being written
+        let recipients = LuaRef::from_index(lua, recipients);
+        let sent = self.send_with(lua, c"Send", || {
+            recipients.push(lua);
+            1
+        });
+        recipients.release(lua);
+        sent
+    }
+
+    /// Sends the message to every player except the player, table of players or `CRecipientFilter` at the given stack index. Serverside only.
+    pub fn send_omit(self, lua: lua::State, recipients: impl Into<StackIndex>) -> Result<()> {
+        let recipients = LuaRef::from_index(lua, recipients);
+        let sent = self.send_with(lua, c"SendOmit", || {
+            recipients.push(lua);
+            1
+        });
+        recipients.release(lua);
+        sent
+    }
+}
+
+/// Reads the net message currently being received. Passed to `receive_with` callbacks.
+///
+/// Values must be read in the order they were written. Reading past the end of the message returns zeroes and empty strings, like the `net.Read*` functions.
+pub struct NetReader {
+    lua: lua::State,
+    len: u32,
+}
+
+impl NetReader {
+    /// Returns the Lua state the message is received in.
+    pub fn lua(&self) -> lua::State {
+        self.lua
+    }
+
+    /// Returns the length of the message, in bits.
+    pub fn len_bits(&self) -> u32 {
+        self.len
+    }
+
+    /// Pushes the player that sent the message onto the stack, or nil when received clientside.
+    pub fn push_sender(&self) {
+        self.lua.push_value(2);
+    }
+
+    /// Calls `net.<func>` with the arguments pushed by `push_args`, leaving the single result on the stack.
+    fn read(&self, func: lua::LuaCStr, push_args: impl FnOnce() -> i32) {
+        let lua = self.lua;
+        lua.get_global(c"net");
+        lua.get_field(-1, func);
+        unsafe { lua.remove(-2) };
+        let nargs = push_args();
+        unsafe { lua.call(nargs, 1) };
+    }
+
+    fn read_number(&self, func: lua::LuaCStr, push_args: impl FnOnce() -> i32) -> f64 {
+        self.read(func, push_args);
+        let value = self.lua.to_number(-1);
+        self.lua.pop();
+        value
+    }
+
+    /// Reads an unsigned integer written using `bits` bits.
+    pub fn read_uint(&mut self, bits: u32) -> u32 {
+        self.read_number(c"ReadUInt", || {
+            self.lua.push_number(bits);
+            1
+        }) as u32
+    }
+
+    /// Reads a signed integer written using `bits` bits.
+    pub fn read_int(&mut self, bits: u32) -> i32 {
+        self.read_number(c"ReadInt", || {
+            self.lua.push_number(bits);
+            1
+        }) as i32
+    }
+
+    pub fn read_u8(&mut self) -> u8 {
+        self.read_uint(8) as u8
+    }
+
+    pub fn read_u16(&mut self) -> u16 {
+        self.read_uint(16) as u16
+    }
+
+    pub fn read_u32(&mut self) -> u32 {
+        self.read_uint(32)
+    }
+
+    pub fn read_i8(&mut self) -> i8 {
+        self.read_int(8) as i8
+    }
+
+    pub fn read_i16(&mut self) -> i16 {
+        self.read_int(16) as i16
+    }
+
+    pub fn read_i32(&mut self) -> i32 {
+        self.read_int(32)
+    }
+
+    /// Reads a 64-bit unsigned integer written with `NetMessage::write_u64`.
+    pub fn read_u64(&mut self) -> u64 {
+        let low = self.read_u32() as u64;
+        let high = self.read_u32() as u64;
+        low | (high << 32)
+    }
+
+    pub fn read_bool(&mut self) -> bool {
+        self.read(c"ReadBool", || 0);
+        let value = self.lua.get_boolean(-1);
+        self.lua.pop();
+        value
+    }
+
+    pub fn read_float(&mut self) -> f32 {
+        self.read_number(c"ReadFloat", || 0) as f32
+    }
+
+    pub fn read_double(&mut self) -> f64 {
+        self.read_number(c"ReadDouble", || 0)
+    }
+
+    /// Reads a null-terminated string, replacing invalid UTF-8 sequences.
+    pub fn read_string(&mut self) -> String {
+        self.read(c"ReadString", || 0);
+        let value = self.lua.get_string(-1).unwrap_or_default().into_owned();
+        self.lua.pop();
+        value
+    }
+
+    /// Reads `len` raw bytes.
+    pub fn read_data(&mut self, len: usize) -> Vec<u8> {
+        self.read(c"ReadData", || {
+            self.lua.push_number(len);
+            1
+        });
+        let value = self
+            .lua
+            .get_binary_string(-1)
+            .map(<[u8]>::to_vec)
+            .unwrap_or_default();
+        self.lua.pop();
+        value
+    }
+
+    /// Returns how many bytes of the message are left to read, from `net.BytesLeft`.
+    pub fn bytes_left(&self) -> usize {
+        self.read_number(c"BytesLeft", || 0) as usize
+    }
+
+    /// Reads bytes written with `NetMessage::write_bytes`.
+    ///
+    /// The length prefix is capped at what's left of the message, so a bogus prefix reads the rest of the message instead of asking `net.ReadData` for more than it has.
+    pub fn read_bytes(&mut self) -> Vec<u8> {
+        let len = self.read_u32() as usize;
+        self.read_data(len.min(self.bytes_left()))
+    }
+}
+
+type ReceiveCallback = Box<dyn FnMut(&mut NetReader)>;
+
+thread_local! {
+    /// Callbacks registered with `receive_with`, by lowercase network string (like `net.Receivers`). Taken out while running.
+    static RECEIVERS: Registry<Option<ReceiveCallback>, String> = Registry::new();
+}
+
+/// Same as `receive`, but calls a Rust closure with a `NetReader` for the message.
+///
+/// The receiver is removed when the module is closed.
+///
+/// ## Example
+///
+/// ```ignore
+/// gmod::net::receive_with(lua, "my_addon_update", |net| {
+///     let count = net.read_u32();
+///     let message = net.read_string();
+/// });
+/// ```
+pub fn receive_with<S, F>(lua: lua::State, network_string: S, callback: F)
+where
+    S: AsRef<str>,
+    F: FnMut(&mut NetReader) + 'static,
+{
+    let name = network_string.as_ref().to_lowercase();
+    RECEIVERS.with(|receivers| receivers.insert(name.clone(), Some(Box::new(callback))));
+
+    lua.get_global(c"net");
+    lua.get_field(-1, c"Receive");
+    lua.push_string(&name);
+    lua.push_string(&name);
+    lua.push_closure(receive_callback, 1);
+    unsafe {
+        lua.call(2, 0);
+    };
+    lua.pop();
+}
+
+/// Removes every receiver registered with `receive_with`, and forgets the network strings of the `NetRegistry`, which the game resets along with the map. This is called for you by `#[gmod13_close]`.
+pub fn unload(lua: lua::State) {
+    NETWORK_STRINGS.with_borrow_mut(HashMap::clear);
+
+    let names: Vec<String> = RECEIVERS.with(|receivers| {
+        receivers.with(|receivers| receivers.drain().map(|(name, _)| name).collect())
+    });
+    if names.is_empty() {
+        return;
+    }
+
+    lua.get_global(c"net");
+    lua.get_field(-1, c"Receivers");
+    if lua.is_table(-1) {
+        for name in names {
+            lua.push_string(&name);
+            lua.push_nil();
+            lua.set_table(-3);
+        }
+    }
+    lua.pop_n(2);
+}
+
+extern "C-unwind" fn receive_callback(lua: lua::State) -> i32 {
+    let Some(name) = lua
+        .get_string(lua.upvalue_index(1))
+        .map(|name| name.into_owned())
+    else {
+        return 0;
+    };
+
+    let mut reader = NetReader {
+        lua,
+        len: lua.to_number(1) as u32,
+    };
+    let result = RECEIVERS
+        .with(|receivers| receivers.run(name, |slot| slot, |callback| callback(&mut reader)));
+
+    match result {
+        Some(Err(panic)) => callbacks::raise(lua, panic),
+        _ => 0,
+    }
+}
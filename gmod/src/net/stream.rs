@@ -0,0 +1,428 @@
+//! Sending byte buffers of any size over the net library.
+//!
+//! A single net message is limited to 64KB, and the reliable channel overflows (kicking the player) if too much is sent in one tick. Streams split the data into chunks sent over several ticks, optionally compressed, and reassemble them on the receiving side.
+//!
+//! Both sides must use the same network string, added with `add_network_strings` on the server.
+
+use std::{cell::RefCell, collections::HashMap, time::Duration};
+
+use anyhow::{bail, Result};
+
+use super::{receive_with, start, NetReader};
+use crate::{
+    hook,
+    lua::{LuaRef, StackIndex, State},
+    timer,
+};
+
+/// Size of the data carried by a single chunk. The rest of the 64KB limit is left for the chunk header and the net library's own overhead.
+pub const CHUNK_SIZE: usize = 60 * 1024;
+
+/// Largest stream accepted by `receive` by default, after decompression. See `receive_with_limit`.
+pub const DEFAULT_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+/// How many incomplete streams a single sender can have at once. The oldest is dropped when a new one starts.
+const MAX_PENDING_PER_SENDER: usize = 4;
+
+/// Identifier of the `PlayerDisconnected` hook forgetting the incomplete streams of players that left.
+const HOOK_ID: &str = "gmod_rs_net_stream";
+
+/// How the data is compressed before being split into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+
+    #[cfg(feature = "lz4")]
+    Lz4,
+
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => 1,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Self> {
+        Ok(match id {
+            0 => Compression::None,
+            #[cfg(feature = "lz4")]
+            1 => Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            2 => Compression::Zstd,
+            _ => bail!("unsupported compression {id} (is the matching feature enabled?)"),
+        })
+    }
+
+    fn compress(self, data: Vec<u8>) -> Vec<u8> {
+        match self {
+            Compression::None => data,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4_flex::compress_prepend_size(&data),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(&data, 0).expect("zstd compression failed"),
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn decompress(self, data: Vec<u8>, max_size: usize) -> Result<Vec<u8>> {
+        Ok(match self {
+            Compression::None => data,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                let Some(len) = data.get(..4) else {
+                    bail!("truncated lz4 data");
+                };
+                let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+                if len > max_size {
+                    bail!("stream of {len} bytes exceeds the limit of {max_size} bytes");
+                }
+                lz4_flex::decompress_size_prepended(&data)?
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                use std::io::Read;
+
+                // streamed, as `zstd::bulk` allocates `max_size` up front
+                let mut decompressed = Vec::new();
+                zstd::stream::read::Decoder::new(data.as_slice())?
+                    .take(max_size as u64 + 1)
+                    .read_to_end(&mut decompressed)?;
+                if decompressed.len() > max_size {
+                    bail!("stream exceeds the limit of {max_size} bytes");
+                }
+                decompressed
+            }
+        })
+    }
+}
+
+thread_local! {
+    static NEXT_ID: std::cell::Cell<u32> = const { std::cell::Cell::new(0) };
+}
+
+enum Target {
+    Server,
+    Broadcast,
+    Recipients(LuaRef),
+}
+
+/// A stream being built, created with `send`.
+#[must_use = "the stream is only sent by calling one of the send functions"]
+pub struct Stream {
+    name: String,
+    data: Vec<u8>,
+    compression: Compression,
+    chunks_per_tick: usize,
+    on_sent: Option<Box<dyn FnOnce(State)>>,
+}
+
+/// Prepares a stream of `data` over the network string `network_string`.
+///
+/// ## Example
+///
+/// ```ignore
+/// gmod::net::stream::send("my_addon_blob", bytes)
+///     .compression(Compression::Zstd)
+///     .on_sent(|lua| println!("sent!"))
+///     .broadcast(lua);
+///
+/// // on the receiving side
+/// gmod::net::stream::receive(lua, "my_addon_blob", |net, data| {
+///     println!("received {} bytes", data.len());
+/// });
+/// ```
+pub fn send<S: AsRef<str>>(network_string: S, data: Vec<u8>) -> Stream {
+    Stream {
+        name: network_string.as_ref().to_string(),
+        data,
+        compression: Compression::None,
+        chunks_per_tick: 1,
+        on_sent: None,
+    }
+}
+
+impl Stream {
+    /// Compresses the data before sending it.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sends up to `n` chunks per tick instead of one. Sending too much at once overflows the reliable channel.
+    pub fn chunks_per_tick(mut self, n: usize) -> Self {
+        self.chunks_per_tick = n.max(1);
+        self
+    }
+
+    /// Calls `f` once the last chunk has been handed to the net library, or once sending failed.
+    pub fn on_sent<F: FnOnce(State) + 'static>(mut self, f: F) -> Self {
+        self.on_sent = Some(Box::new(f));
+        self
+    }
+
+    /// Sends the stream to the server. Clientside only.
+    pub fn to_server(self, lua: State) {
+        self.start(lua, Target::Server);
+    }
+
+    /// Sends the stream to every player. Serverside only.
+    pub fn broadcast(self, lua: State) {
+        self.start(lua, Target::Broadcast);
+    }
+
+    /// Sends the stream to the player, table of players or `CRecipientFilter` at the given stack index. Serverside only.
+    pub fn send(self, lua: State, recipients: impl Into<StackIndex>) {
+        let recipients = LuaRef::from_index(lua, recipients);
+        self.start(lua, Target::Recipients(recipients));
+    }
+
+    fn start(self, lua: State, target: Target) {
+        let id = NEXT_ID.get();
+        NEXT_ID.set(id.wrapping_add(1));
+
+        let compression = self.compression;
+        let data = compression.compress(self.data);
+        let chunks: Vec<Vec<u8>> = if data.is_empty() {
+            vec![Vec::new()]
+        } else {
+            data.chunks(CHUNK_SIZE).map(<[u8]>::to_vec).collect()
+        };
+        let count = chunks.len() as u32;
+
+        let name = self.name;
+        let chunks_per_tick = self.chunks_per_tick;
+        let mut on_sent = self.on_sent;
+        let mut chunks = chunks.into_iter().enumerate();
+        let ticks = (count as usize).div_ceil(chunks_per_tick) as u32;
+
+        timer::create(lua, Duration::ZERO, ticks, move |lua| {
+            for (index, chunk) in chunks.by_ref().take(chunks_per_tick) {
+                let message = start(&name)
+                    .write_u32(id)
+                    .write_u32(index as u32)
+                    .write_u32(count)
+                    .write_u8(compression.id())
+                    .write_bytes(chunk);
+
+                let sent = match &target {
+                    Target::Server => message.send_to_server(lua),
+                    Target::Broadcast => message.broadcast(lua),
+                    Target::Recipients(recipients) => {
+                        recipients.push(lua);
+                        let sent = message.send(lua, -1);
+                        lua.pop();
+                        sent
+                    }
+                };
+                // the receiver can't reassemble a stream with a missing chunk
+                if let Err(err) = sent {
+                    crate::gmod_error!(lua, "[gmod-rs] Dropped net stream {name:?}: {err}");
+                    chunks.by_ref().for_each(drop);
+                    break;
+                }
+            }
+
+            if chunks.len() == 0 {
+                if let Some(on_sent) = on_sent.take() {
+                    on_sent(lua);
+                }
+            }
+        })
+        .detach();
+    }
+}
+
+pub(crate) struct Pending {
+    id: u32,
+    compression: Compression,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    size: usize,
+}
+
+type Callback = Box<dyn FnMut(&mut NetReader, Vec<u8>)>;
+
+thread_local! {
+    /// Incomplete streams by network string, then by sender (`UserID`, or -1 for the server).
+    static PENDING: RefCell<HashMap<String, HashMap<i64, Vec<Pending>>>> = RefCell::new(HashMap::new());
+}
+
+/// Receives streams sent with `send` over the network string `network_string`, calling `callback` with the full data once every chunk arrived.
+///
+/// Streams larger than `DEFAULT_MAX_SIZE` are dropped.
+pub fn receive<S, F>(lua: State, network_string: S, callback: F)
+where
+    S: AsRef<str>,
+    F: FnMut(&mut NetReader, Vec<u8>) + 'static,
+{
+    receive_with_limit(lua, network_string, DEFAULT_MAX_SIZE, callback)
+}
+
+/// Same as `receive`, but with a custom size limit. Keep it low when receiving from clients, as incomplete streams are kept in memory.
+pub fn receive_with_limit<S, F>(lua: State, network_string: S, max_size: usize, callback: F)
+where
+    S: AsRef<str>,
+    F: FnMut(&mut NetReader, Vec<u8>) + 'static,
+{
+    let name = network_string.as_ref().to_lowercase();
+    let mut callback: Callback = Box::new(callback);
+    hook::add(lua, "PlayerDisconnected", HOOK_ID, player_disconnected);
+    receive_with(lua, network_string, move |net| {
+        if let Err(err) = receive_chunk(net, &name, max_size, &mut callback) {
+            eprintln!("[gmod-rs] Dropped net stream {name:?}: {err}");
+        }
+    });
+}
+
+extern "C-unwind" fn player_disconnected(lua: State) -> i32 {
+    lua.get_field(1, c"UserID");
+    lua.push_value(1);
+    if lua.pcall_ignore(1, 1) {
+        let key = lua.to_number(-1) as i64;
+        lua.pop();
+        PENDING.with_borrow_mut(|pending| {
+            for senders in pending.values_mut() {
+                senders.remove(&key);
+            }
+        });
+    }
+    0
+}
+
+fn sender_key(net: &NetReader) -> i64 {
+    let lua = net.lua();
+    net.push_sender();
+    if lua.is_nil(-1) {
+        lua.pop();
+        return -1;
+    }
+    lua.get_field(-1, c"UserID");
+    lua.insert(-2);
+    if !lua.pcall_ignore(1, 1) {
+        return -1;
+    }
+    let key = lua.to_number(-1) as i64;
+    lua.pop();
+    key
+}
+
+/// A chunk of a stream, as sent over the network.
+pub(crate) struct Chunk {
+    pub(crate) id: u32,
+    pub(crate) index: usize,
+    pub(crate) count: usize,
+    pub(crate) compression: Compression,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Chunk {
+    fn read(net: &mut NetReader) -> Result<Self> {
+        Ok(Self {
+            id: net.read_u32(),
+            index: net.read_u32() as usize,
+            count: net.read_u32() as usize,
+            compression: Compression::from_id(net.read_u8())?,
+            data: net.read_bytes(),
+        })
+    }
+}
+
+/// Adds a chunk to the incomplete streams of its sender, returning the stream's data (still compressed) once every chunk arrived.
+pub(crate) fn reassemble(
+    streams: &mut Vec<Pending>,
+    chunk: Chunk,
+    max_size: usize,
+) -> Result<Option<Vec<u8>>> {
+    let Chunk {
+        id,
+        index,
+        count,
+        compression,
+        data: chunk,
+    } = chunk;
+
+    if count == 0 || index >= count {
+        bail!("invalid chunk {index}/{count}");
+    }
+    if count.saturating_mul(CHUNK_SIZE) > max_size + CHUNK_SIZE {
+        bail!("stream of {count} chunks exceeds the limit of {max_size} bytes");
+    }
+
+    let position = match streams.iter().position(|stream| stream.id == id) {
+        Some(position) => position,
+        None => {
+            if streams.len() >= MAX_PENDING_PER_SENDER {
+                streams.remove(0);
+            }
+            streams.push(Pending {
+                id,
+                compression,
+                chunks: vec![None; count],
+                received: 0,
+                size: 0,
+            });
+            streams.len() - 1
+        }
+    };
+
+    let stream = &mut streams[position];
+    if stream.chunks.len() != count || stream.compression != compression {
+        streams.remove(position);
+        bail!("chunk {index} doesn't match the rest of stream {id}");
+    }
+
+    if stream.chunks[index].is_none() {
+        stream.size += chunk.len();
+        stream.received += 1;
+        stream.chunks[index] = Some(chunk);
+    }
+
+    if stream.size > max_size {
+        streams.remove(position);
+        bail!("stream exceeds the limit of {max_size} bytes");
+    }
+
+    if stream.received < count {
+        return Ok(None);
+    }
+
+    let stream = streams.remove(position);
+    Ok(Some(
+        stream.chunks.into_iter().flatten().flatten().collect(),
+    ))
+}
+
+fn receive_chunk(
+    net: &mut NetReader,
+    name: &str,
+    max_size: usize,
+    callback: &mut Callback,
+) -> Result<()> {
+    let chunk = Chunk::read(net)?;
+    let compression = chunk.compression;
+
+    let sender = sender_key(net);
+    let data = PENDING.with_borrow_mut(|pending| {
+        let streams = pending
+            .entry(name.to_string())
+            .or_default()
+            .entry(sender)
+            .or_default();
+        reassemble(streams, chunk, max_size)
+    })?;
+
+    if let Some(data) = data {
+        callback(net, compression.decompress(data, max_size)?);
+    }
+
+    Ok(())
+}
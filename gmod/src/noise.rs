@@ -0,0 +1,545 @@
+//! Seedable gradient noise (Perlin and simplex) in 2D and 3D, with fractal Brownian motion (FBM) and domain warping, for procedural decoration (foliage, rocks, props) where noise written in Lua is too slow.
+//!
+//! Output is roughly in `[-1, 1]`, and the same seed always gives the same values, on every platform.
+
+use std::cell::RefCell;
+
+use anyhow::Result;
+
+use crate::lua::{self, HandleLuaFunctionReturn, LuaReg, State, LUA_TNUMBER, LUA_TSTRING};
+
+/// The noise algorithm to sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoiseKind {
+    /// Improved Perlin noise. Always 0 on integer coordinates, so scale coordinates with `Fbm::frequency`.
+    #[default]
+    Perlin,
+    /// Simplex noise, which has fewer directional artifacts than Perlin noise.
+    Simplex,
+}
+
+/// A noise generator, which is a permutation table shuffled from the seed.
+#[derive(Clone)]
+pub struct Noise {
+    seed: u32,
+    perm: [u8; 512],
+}
+
+impl std::fmt::Debug for Noise {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Noise").field("seed", &self.seed).finish()
+    }
+}
+
+const GRAD3: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+#[inline]
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[inline]
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+#[inline]
+fn grad2(hash: u8, x: f64, y: f64) -> f64 {
+    match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+#[inline]
+fn grad3(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Splits a coordinate into its lattice cell (wrapped to the permutation table) and the offset inside it.
+#[inline]
+fn cell(x: f64) -> (usize, f64) {
+    let floor = x.floor();
+    ((floor as i64 & 255) as usize, x - floor)
+}
+
+impl Noise {
+    pub fn new(seed: u32) -> Self {
+        let mut table: [u8; 256] = std::array::from_fn(|i| i as u8);
+
+        // splitmix64, so the shuffle doesn't depend on a RNG crate's stability
+        let mut state = seed as u64;
+        let mut next = || {
+            state = state.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            z ^ (z >> 31)
+        };
+        for i in (1..table.len()).rev() {
+            table.swap(i, (next() % (i as u64 + 1)) as usize);
+        }
+
+        let mut perm = [0; 512];
+        perm[..256].copy_from_slice(&table);
+        perm[256..].copy_from_slice(&table);
+        Self { seed, perm }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn perlin2(&self, x: f64, y: f64) -> f64 {
+        let p = &self.perm;
+        let (xi, x) = cell(x);
+        let (yi, y) = cell(y);
+        let (u, v) = (fade(x), fade(y));
+
+        let a = p[xi] as usize + yi;
+        let b = p[xi + 1] as usize + yi;
+
+        lerp(
+            v,
+            lerp(u, grad2(p[a], x, y), grad2(p[b], x - 1.0, y)),
+            lerp(
+                u,
+                grad2(p[a + 1], x, y - 1.0),
+                grad2(p[b + 1], x - 1.0, y - 1.0),
+            ),
+        )
+    }
+
+    pub fn perlin3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let p = &self.perm;
+        let (xi, x) = cell(x);
+        let (yi, y) = cell(y);
+        let (zi, z) = cell(z);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let a = p[xi] as usize + yi;
+        let aa = p[a] as usize + zi;
+        let ab = p[a + 1] as usize + zi;
+        let b = p[xi + 1] as usize + yi;
+        let ba = p[b] as usize + zi;
+        let bb = p[b + 1] as usize + zi;
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad3(p[aa], x, y, z), grad3(p[ba], x - 1.0, y, z)),
+                lerp(
+                    u,
+                    grad3(p[ab], x, y - 1.0, z),
+                    grad3(p[bb], x - 1.0, y - 1.0, z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad3(p[aa + 1], x, y, z - 1.0),
+                    grad3(p[ba + 1], x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad3(p[ab + 1], x, y - 1.0, z - 1.0),
+                    grad3(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+
+    pub fn simplex2(&self, x: f64, y: f64) -> f64 {
+        const F2: f64 = 0.366_025_403_784_438_6; // (√3 - 1) / 2
+        const G2: f64 = 0.211_324_865_405_187_1; // (3 - √3) / 6
+
+        let p = &self.perm;
+        let s = (x + y) * F2;
+        let (i, j) = ((x + s).floor(), (y + s).floor());
+        let t = (i + j) * G2;
+        let (x0, y0) = (x - (i - t), y - (j - t));
+
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+        let (x1, y1) = (x0 - i1 as f64 + G2, y0 - j1 as f64 + G2);
+        let (x2, y2) = (x0 - 1.0 + 2.0 * G2, y0 - 1.0 + 2.0 * G2);
+
+        let (ii, jj) = ((i as i64 & 255) as usize, (j as i64 & 255) as usize);
+        let corners = [
+            (p[ii + p[jj] as usize], x0, y0),
+            (p[ii + i1 + p[jj + j1] as usize], x1, y1),
+            (p[ii + 1 + p[jj + 1] as usize], x2, y2),
+        ];
+
+        let sum: f64 = corners
+            .into_iter()
+            .map(|(hash, x, y)| {
+                let t = 0.5 - x * x - y * y;
+                if t < 0.0 {
+                    0.0
+                } else {
+                    let g = GRAD3[hash as usize % 12];
+                    t.powi(4) * (g[0] * x + g[1] * y)
+                }
+            })
+            .sum();
+        70.0 * sum
+    }
+
+    pub fn simplex3(&self, x: f64, y: f64, z: f64) -> f64 {
+        const F3: f64 = 1.0 / 3.0;
+        const G3: f64 = 1.0 / 6.0;
+
+        let p = &self.perm;
+        let s = (x + y + z) * F3;
+        let (i, j, k) = ((x + s).floor(), (y + s).floor(), (z + s).floor());
+        let t = (i + j + k) * G3;
+        let (x0, y0, z0) = (x - (i - t), y - (j - t), z - (k - t));
+
+        // which simplex of the cube we're in
+        let ((i1, j1, k1), (i2, j2, k2)) = if x0 >= y0 {
+            if y0 >= z0 {
+                ((1, 0, 0), (1, 1, 0))
+            } else if x0 >= z0 {
+                ((1, 0, 0), (1, 0, 1))
+            } else {
+                ((0, 0, 1), (1, 0, 1))
+            }
+        } else if y0 < z0 {
+            ((0, 0, 1), (0, 1, 1))
+        } else if x0 < z0 {
+            ((0, 1, 0), (0, 1, 1))
+        } else {
+            ((0, 1, 0), (1, 1, 0))
+        };
+
+        let (ii, jj, kk) = (
+            (i as i64 & 255) as usize,
+            (j as i64 & 255) as usize,
+            (k as i64 & 255) as usize,
+        );
+        let hash = |di: usize, dj: usize, dk: usize| {
+            p[ii + di + p[jj + dj + p[kk + dk] as usize] as usize]
+        };
+
+        let corners = [
+            (hash(0, 0, 0), x0, y0, z0),
+            (
+                hash(i1, j1, k1),
+                x0 - i1 as f64 + G3,
+                y0 - j1 as f64 + G3,
+                z0 - k1 as f64 + G3,
+            ),
+            (
+                hash(i2, j2, k2),
+                x0 - i2 as f64 + 2.0 * G3,
+                y0 - j2 as f64 + 2.0 * G3,
+                z0 - k2 as f64 + 2.0 * G3,
+            ),
+            (
+                hash(1, 1, 1),
+                x0 - 1.0 + 3.0 * G3,
+                y0 - 1.0 + 3.0 * G3,
+                z0 - 1.0 + 3.0 * G3,
+            ),
+        ];
+
+        let sum: f64 = corners
+            .into_iter()
+            .map(|(hash, x, y, z)| {
+                let t = 0.6 - x * x - y * y - z * z;
+                if t < 0.0 {
+                    0.0
+                } else {
+                    let g = GRAD3[hash as usize % 12];
+                    t.powi(4) * (g[0] * x + g[1] * y + g[2] * z)
+                }
+            })
+            .sum();
+        32.0 * sum
+    }
+
+    pub fn sample2(&self, kind: NoiseKind, x: f64, y: f64) -> f64 {
+        match kind {
+            NoiseKind::Perlin => self.perlin2(x, y),
+            NoiseKind::Simplex => self.simplex2(x, y),
+        }
+    }
+
+    pub fn sample3(&self, kind: NoiseKind, x: f64, y: f64, z: f64) -> f64 {
+        match kind {
+            NoiseKind::Perlin => self.perlin3(x, y, z),
+            NoiseKind::Simplex => self.simplex3(x, y, z),
+        }
+    }
+}
+
+/// Fractal Brownian motion: several octaves of noise at increasing frequency and decreasing amplitude, optionally domain warped.
+///
+/// The default is a single octave of Perlin noise at frequency 1, which is the same as sampling the noise directly.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::noise::{Fbm, Noise, NoiseKind};
+///
+/// let noise = Noise::new(1337);
+/// let fbm = Fbm {
+///     kind: NoiseKind::Simplex,
+///     octaves: 5,
+///     frequency: 1.0 / 512.0, // one feature every ~512 units
+///     ..Default::default()
+/// };
+///
+/// let height = fbm.sample2(&noise, 1024.0, -300.0);
+/// assert!((-1.0..=1.0).contains(&height));
+/// assert_eq!(height, fbm.sample2(&Noise::new(1337), 1024.0, -300.0));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fbm {
+    pub kind: NoiseKind,
+    pub octaves: u32,
+    /// Scale applied to the coordinates of the first octave.
+    pub frequency: f64,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f64,
+    /// Amplitude multiplier between octaves.
+    pub gain: f64,
+    /// Domain warping strength, in the noise's own (frequency scaled) units. The coordinates are offset by the FBM itself before sampling, giving swirly, eroded-looking shapes. 0 disables it.
+    pub warp: f64,
+}
+
+impl Default for Fbm {
+    fn default() -> Self {
+        Self {
+            kind: NoiseKind::Perlin,
+            octaves: 1,
+            frequency: 1.0,
+            lacunarity: 2.0,
+            gain: 0.5,
+            warp: 0.0,
+        }
+    }
+}
+
+// arbitrary offsets so the warp components aren't correlated with each other
+const WARP_OFFSETS: [f64; 3] = [5.2, 1.3, 9.7];
+
+impl Fbm {
+    fn octaves(&self, mut sample: impl FnMut(f64) -> f64) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut total_amplitude = 0.0;
+        let mut frequency = self.frequency;
+        for _ in 0..self.octaves.max(1) {
+            sum += sample(frequency) * amplitude;
+            total_amplitude += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        sum / total_amplitude
+    }
+
+    fn unwarped2(&self, noise: &Noise, x: f64, y: f64) -> f64 {
+        self.octaves(|f| noise.sample2(self.kind, x * f, y * f))
+    }
+
+    fn unwarped3(&self, noise: &Noise, x: f64, y: f64, z: f64) -> f64 {
+        self.octaves(|f| noise.sample3(self.kind, x * f, y * f, z * f))
+    }
+
+    pub fn sample2(&self, noise: &Noise, x: f64, y: f64) -> f64 {
+        if self.warp == 0.0 {
+            return self.unwarped2(noise, x, y);
+        }
+        let offset = self.warp / self.frequency;
+        let [ox, oy, _] = WARP_OFFSETS.map(|o| o / self.frequency);
+        let qx = self.unwarped2(noise, x, y);
+        let qy = self.unwarped2(noise, x + ox, y + oy);
+        self.unwarped2(noise, x + qx * offset, y + qy * offset)
+    }
+
+    pub fn sample3(&self, noise: &Noise, x: f64, y: f64, z: f64) -> f64 {
+        if self.warp == 0.0 {
+            return self.unwarped3(noise, x, y, z);
+        }
+        let offset = self.warp / self.frequency;
+        let [ox, oy, oz] = WARP_OFFSETS.map(|o| o / self.frequency);
+        let qx = self.unwarped3(noise, x, y, z);
+        let qy = self.unwarped3(noise, x + ox, y + oy, z + oz);
+        let qz = self.unwarped3(noise, x + oy, y + oz, z + ox);
+        self.unwarped3(noise, x + qx * offset, y + qy * offset, z + qz * offset)
+    }
+
+    /// Samples a `width` by `height` grid starting at `(x, y)`, with `step` units between samples, in row-major order.
+    pub fn grid2(
+        &self,
+        noise: &Noise,
+        x: f64,
+        y: f64,
+        width: usize,
+        height: usize,
+        step: f64,
+    ) -> Vec<f64> {
+        let mut grid = Vec::with_capacity(width * height);
+        for row in 0..height {
+            for column in 0..width {
+                grid.push(self.sample2(noise, x + column as f64 * step, y + row as f64 * step));
+            }
+        }
+        grid
+    }
+}
+
+/// Registers the noise functions into a global table named `libname`:
+///
+/// - `Noise2(seed, x, y[, options])` and `Noise3(seed, x, y, z[, options])` sample a single point
+/// - `Grid2(seed, x, y, width, height, step[, options])` returns `height` rows of `width` samples, indexed as `grid[row][column]`
+///
+/// `options` is a table of `Fbm` fields: `kind` (`"perlin"` or `"simplex"`), `octaves`, `frequency`, `lacunarity`, `gain` and `warp`.
+pub fn register(lua: State, libname: lua::LuaCStr) {
+    lua.register(
+        libname.as_ptr(),
+        crate::lua_regs![
+            "Noise2" => lua_noise2,
+            "Noise3" => lua_noise3,
+            "Grid2" => lua_grid2,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+}
+
+thread_local! {
+    // Lua calls are usually made with the same seed over and over, so keep the last table around
+    static LAST_NOISE: RefCell<Option<Noise>> = const { RefCell::new(None) };
+}
+
+fn with_noise<R>(seed: u32, f: impl FnOnce(&Noise) -> R) -> R {
+    LAST_NOISE.with_borrow_mut(|last| {
+        let noise = match last {
+            Some(noise) if noise.seed == seed => noise,
+            _ => last.insert(Noise::new(seed)),
+        };
+        f(noise)
+    })
+}
+
+fn check_options(lua: State, arg: i32) -> Result<Fbm> {
+    let mut fbm = Fbm::default();
+    if lua.is_none_or_nil(arg) {
+        return Ok(fbm);
+    }
+    lua.check_table(arg)?;
+
+    if lua.get_field_type_or_nil(arg, c"kind", LUA_TSTRING)? {
+        let kind = lua.get_string_unchecked(-1).into_owned();
+        lua.pop();
+        fbm.kind = match kind.as_str() {
+            "perlin" => NoiseKind::Perlin,
+            "simplex" => NoiseKind::Simplex,
+            _ => anyhow::bail!(lua.err_argmsg(arg, &format!("invalid noise kind '{kind}'"))),
+        };
+    }
+
+    if lua.get_field_type_or_nil(arg, c"octaves", LUA_TNUMBER)? {
+        let octaves = lua.to_number(-1);
+        lua.pop();
+        if !(1.0..=16.0).contains(&octaves) {
+            anyhow::bail!(lua.err_argmsg(arg, "octaves must be between 1 and 16"));
+        }
+        fbm.octaves = octaves as u32;
+    }
+
+    for (name, option) in [
+        (c"frequency", &mut fbm.frequency),
+        (c"lacunarity", &mut fbm.lacunarity),
+        (c"gain", &mut fbm.gain),
+        (c"warp", &mut fbm.warp),
+    ] {
+        if lua.get_field_type_or_nil(arg, name, LUA_TNUMBER)? {
+            *option = lua.to_number(-1);
+            lua.pop();
+        }
+    }
+
+    Ok(fbm)
+}
+
+extern "C-unwind" fn lua_noise2(lua: State) -> i32 {
+    (|| -> Result<(f64,)> {
+        let seed = lua.check_u32(1)?;
+        let (x, y) = (lua.check_number(2)?, lua.check_number(3)?);
+        let fbm = check_options(lua, 4)?;
+        Ok((with_noise(seed, |noise| fbm.sample2(noise, x, y)),))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_noise3(lua: State) -> i32 {
+    (|| -> Result<(f64,)> {
+        let seed = lua.check_u32(1)?;
+        let (x, y, z) = (
+            lua.check_number(2)?,
+            lua.check_number(3)?,
+            lua.check_number(4)?,
+        );
+        let fbm = check_options(lua, 5)?;
+        Ok((with_noise(seed, |noise| fbm.sample3(noise, x, y, z)),))
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_grid2(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let seed = lua.check_u32(1)?;
+        let (x, y) = (lua.check_number(2)?, lua.check_number(3)?);
+        let width = lua.check_number_in_range(4, 1..=4096usize)?;
+        let height = lua.check_number_in_range(5, 1..=4096usize)?;
+        let step = lua.check_number(6)?;
+        let fbm = check_options(lua, 7)?;
+
+        let grid = with_noise(seed, |noise| fbm.grid2(noise, x, y, width, height, step));
+
+        lua.create_table(height as i32, 0);
+        for (row, samples) in grid.chunks(width).enumerate() {
+            lua.create_table(width as i32, 0);
+            for (column, sample) in samples.iter().enumerate() {
+                lua.push_number(*sample);
+                lua.raw_seti(-2, column as i32 + 1);
+            }
+            lua.raw_seti(-2, row as i32 + 1);
+        }
+        Ok(1)
+    })()
+    .handle_result(lua)
+}
@@ -0,0 +1,608 @@
+//! Account linking ("link your Discord/forum account") with OAuth2 and Steam OpenID.
+//!
+//! 1. `OAuthHandle::login_url` creates a single-use login for a player and returns the URL they should open (e.g. with `gui.OpenURL`).
+//! 2. The provider redirects the player's browser back to the callback listener started with `listen`, which should sit behind a reverse proxy that terminates HTTPS.
+//! 3. The provider's `verify` function turns the callback parameters into a verified `Identity` on a background thread, and the result is delivered on the Lua tick.
+//!
+//! There is no HTTP client in this crate, so `verify` is where you exchange the OAuth2 code for a token (or check a Steam OpenID response with `steam_check_authentication_body`), using the client your module already has. Never trust the callback parameters without verifying them: anyone can open the callback URL.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use url::Url;
+
+use crate::{
+    hook,
+    lua::{task_queue, State},
+};
+
+/// How long a login URL stays valid by default.
+pub const DEFAULT_LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How long a browser has to send its request before the connection is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request line accepted. Callback URLs are short, anything longer is garbage.
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
+
+const STEAM_OPENID_URL: &str = "https://steamcommunity.com/openid/login";
+const STEAM_CLAIMED_ID_PREFIX: &str = "https://steamcommunity.com/openid/id/";
+const OPENID_NS: &str = "http://specs.openid.net/auth/2.0";
+const OPENID_IDENTIFIER_SELECT: &str = "http://specs.openid.net/auth/2.0/identifier_select";
+
+/// An account on a provider, as returned by a provider's `verify` function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// The account's unique identifier on the provider (e.g. a Discord user ID).
+    pub id: String,
+    /// The account's display name, if the provider has one.
+    pub name: Option<String>,
+}
+
+/// The query parameters the provider redirected the player's browser with.
+#[derive(Debug, Clone)]
+pub struct CallbackParams {
+    provider: String,
+    redirect_uri: String,
+    params: HashMap<String, String>,
+}
+
+impl CallbackParams {
+    /// Returns the name of the provider, as given to `Builder::oauth2` (or `"steam"`).
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    /// Returns the callback URL given to the provider. OAuth2 token exchanges must send it again.
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// Returns a query parameter, e.g. `"code"` for OAuth2.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params.get(key).map(String::as_str)
+    }
+
+    /// Returns every query parameter.
+    pub fn params(&self) -> &HashMap<String, String> {
+        &self.params
+    }
+}
+
+/// The outcome of a login, delivered to `Builder::on_login`.
+#[derive(Debug)]
+pub struct Login {
+    /// The player who requested the login URL.
+    pub steamid64: u64,
+    /// The name of the provider.
+    pub provider: String,
+    /// The verified account, or why the login failed.
+    pub result: Result<Identity>,
+}
+
+/// An OAuth2 provider using the authorization code flow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OAuth2 {
+    pub authorize_url: String,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+}
+
+impl OAuth2 {
+    /// Discord, with the `identify` scope.
+    pub fn discord(client_id: &str) -> Self {
+        Self {
+            authorize_url: "https://discord.com/oauth2/authorize".to_string(),
+            client_id: client_id.to_string(),
+            scopes: vec!["identify".to_string()],
+        }
+    }
+}
+
+enum Kind {
+    OAuth2(OAuth2),
+    Steam,
+}
+
+type Verify = Arc<dyn Fn(&CallbackParams) -> Result<Identity> + Send + Sync>;
+
+struct Provider {
+    kind: Kind,
+    verify: Verify,
+}
+
+struct Pending {
+    steamid64: u64,
+    provider: String,
+    expires: Instant,
+}
+
+type Handler = Arc<Mutex<Box<dyn FnMut(State, Login) + Send>>>;
+
+struct Server {
+    listener: TcpListener,
+    addr: SocketAddr,
+    public_url: String,
+    providers: HashMap<String, Provider>,
+    ttl: Duration,
+    pending: Mutex<HashMap<String, Pending>>,
+    stopped: AtomicBool,
+}
+
+impl Server {
+    fn stop(&self) {
+        if self.stopped.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // wake up the accepting thread so it notices
+        let mut addr = self.addr;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => [127, 0, 0, 1].into(),
+                SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+    }
+
+    fn callback_url(&self, provider: &str) -> String {
+        format!("{}/callback/{provider}", self.public_url)
+    }
+}
+
+/// OAuth listeners that are still running, stopped by `unload`.
+static SERVERS: Mutex<Vec<Arc<Server>>> = Mutex::new(Vec::new());
+
+/// Builder for the callback listener, created with `listen`.
+#[must_use = "the listener is only started by calling `start`"]
+pub struct Builder {
+    addr: String,
+    public_url: String,
+    providers: HashMap<String, Provider>,
+    ttl: Duration,
+    handler: Option<Handler>,
+    hook: Option<String>,
+}
+
+/// Prepares a callback listener bound to `addr` (e.g. `127.0.0.1:8090`), reachable from browsers at `public_url` (e.g. `https://link.example.com`).
+///
+/// Providers redirect to `<public_url>/callback/<provider>`, which must be registered as the redirect URI on the provider's side.
+///
+/// ## Example
+///
+/// ```ignore
+/// let oauth = gmod::oauth::listen("127.0.0.1:8090", "https://link.example.com")
+///     .oauth2("discord", OAuth2::discord("1234"), |params| {
+///         let code = params.get("code").context("no code")?;
+///         // exchange `code` for a token and fetch the user with your HTTP client
+///         Ok(Identity { id: user.id, name: Some(user.username) })
+///     })
+///     .hook("AccountLinked") // hook.Add("AccountLinked", "...", function(steamid64, provider, id, name, err) end)
+///     .start()?;
+///
+/// let url = oauth.login_url("discord", steamid64)?;
+/// ```
+pub fn listen(addr: &str, public_url: &str) -> Builder {
+    Builder {
+        addr: addr.to_string(),
+        public_url: public_url.trim_end_matches('/').to_string(),
+        providers: HashMap::new(),
+        ttl: DEFAULT_LOGIN_TTL,
+        handler: None,
+        hook: None,
+    }
+}
+
+impl Builder {
+    /// Adds an OAuth2 provider. `verify` is called on a background thread with the callback parameters (`code`), and must exchange the code for the account it belongs to.
+    pub fn oauth2<F>(mut self, name: &str, provider: OAuth2, verify: F) -> Self
+    where
+        F: Fn(&CallbackParams) -> Result<Identity> + Send + Sync + 'static,
+    {
+        self.providers.insert(
+            name.to_string(),
+            Provider {
+                kind: Kind::OAuth2(provider),
+                verify: Arc::new(verify),
+            },
+        );
+        self
+    }
+
+    /// Adds Steam OpenID as the `"steam"` provider, to link another Steam account (e.g. the one used on a web panel).
+    ///
+    /// The response's format is checked before `verify` is called, but its signature can only be checked by Steam: `verify` must POST `steam_check_authentication_body(params)` to `https://steamcommunity.com/openid/login` and check that the response contains `is_valid:true`. It can then use `steam_claimed_id` for the account.
+    pub fn steam<F>(mut self, verify: F) -> Self
+    where
+        F: Fn(&CallbackParams) -> Result<Identity> + Send + Sync + 'static,
+    {
+        self.providers.insert(
+            "steam".to_string(),
+            Provider {
+                kind: Kind::Steam,
+                verify: Arc::new(verify),
+            },
+        );
+        self
+    }
+
+    /// Sets how long login URLs stay valid. Defaults to `DEFAULT_LOGIN_TTL`.
+    pub fn login_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Calls `f` on the Lua thread with the outcome of every login.
+    pub fn on_login<F: FnMut(State, Login) + Send + 'static>(mut self, f: F) -> Self {
+        self.handler = Some(Arc::new(Mutex::new(Box::new(f))));
+        self
+    }
+
+    /// Runs `hook.Run(event, steamid64, provider, id, name, err)` with the outcome of every login. `steamid64` is a string; `id` and `name` are nil if the login failed, and `err` is nil if it succeeded.
+    pub fn hook(mut self, event: &str) -> Self {
+        self.hook = Some(event.to_string());
+        self
+    }
+
+    /// Starts listening on a background thread.
+    pub fn start(self) -> io::Result<OAuthHandle> {
+        let listener = TcpListener::bind(&self.addr)?;
+        let server = Arc::new(Server {
+            addr: listener.local_addr()?,
+            listener,
+            public_url: self.public_url,
+            providers: self.providers,
+            ttl: self.ttl,
+            pending: Mutex::new(HashMap::new()),
+            stopped: AtomicBool::new(false),
+        });
+        SERVERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(server.clone());
+
+        let handler = self.handler;
+        let hook = self.hook.map(Arc::new);
+        let accepting = server.clone();
+        std::thread::spawn(move || loop {
+            let stream = accepting.listener.accept();
+            if accepting.stopped.load(Ordering::Acquire) {
+                break;
+            }
+            let stream = match stream {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    eprintln!("[gmod-rs] OAuth listener failed to accept a connection: {err}");
+                    continue;
+                }
+            };
+
+            let server = accepting.clone();
+            let handler = handler.clone();
+            let hook = hook.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = serve(&server, stream, handler, hook) {
+                    eprintln!("[gmod-rs] OAuth callback failed: {err}");
+                }
+            });
+        });
+
+        Ok(OAuthHandle { server })
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, message: &str) -> io::Result<()> {
+    let body = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{message}</title></head><body><p>{message}</p></body></html>"
+    );
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+fn serve(
+    server: &Server,
+    mut stream: TcpStream,
+    handler: Option<Handler>,
+    hook: Option<Arc<String>>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut line = String::new();
+    BufReader::new((&stream).take(MAX_REQUEST_LINE)).read_line(&mut line)?;
+
+    // GET /callback/<provider>?<query> HTTP/1.1
+    let mut parts = line.split_ascii_whitespace();
+    let (Some("GET"), Some(target)) = (parts.next(), parts.next()) else {
+        return respond(&mut stream, "400 Bad Request", "Bad request.");
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let Some((_, provider_name)) = path.rsplit_once("/callback/") else {
+        return respond(&mut stream, "404 Not Found", "Not found.");
+    };
+    let Some(provider) = server.providers.get(provider_name) else {
+        return respond(&mut stream, "404 Not Found", "Not found.");
+    };
+
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+
+    // logins are single-use: taking it out also stops replays of the same callback
+    let pending = params.get("state").and_then(|state| {
+        server
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(state)
+    });
+    let pending = match pending {
+        Some(pending) if pending.provider == provider_name && pending.expires > Instant::now() => {
+            pending
+        }
+        _ => {
+            return respond(
+                &mut stream,
+                "400 Bad Request",
+                "This login link has expired, please request a new one in game.",
+            )
+        }
+    };
+
+    respond(
+        &mut stream,
+        "200 OK",
+        "Login received, you can close this window and return to the game.",
+    )?;
+    drop(stream);
+
+    let params = CallbackParams {
+        provider: provider_name.to_string(),
+        redirect_uri: server.callback_url(provider_name),
+        params,
+    };
+    let result = match &provider.kind {
+        Kind::OAuth2(_) => check_oauth2(&params),
+        Kind::Steam => check_steam(&params),
+    }
+    .and_then(|()| (provider.verify)(&params));
+
+    let login = Login {
+        steamid64: pending.steamid64,
+        provider: pending.provider,
+        result,
+    };
+    let _ = task_queue::wait_lua_tick(String::new(), move |l| {
+        if let Some(hook) = hook {
+            run_hook(l, &hook, &login);
+        }
+        if let Some(handler) = handler {
+            (handler.lock().unwrap_or_else(|e| e.into_inner()))(l, login);
+        }
+    });
+
+    Ok(())
+}
+
+fn check_oauth2(params: &CallbackParams) -> Result<()> {
+    if let Some(error) = params.get("error") {
+        match params.get("error_description") {
+            Some(description) => bail!("provider returned {error}: {description}"),
+            None => bail!("provider returned {error}"),
+        }
+    }
+    if params.get("code").is_none() {
+        bail!("callback has no authorization code");
+    }
+    Ok(())
+}
+
+fn check_steam(params: &CallbackParams) -> Result<()> {
+    match params.get("openid.mode") {
+        Some("id_res") => {}
+        Some("cancel") => bail!("login was cancelled"),
+        mode => bail!("unexpected OpenID mode {mode:?}"),
+    }
+    if params.get("openid.op_endpoint") != Some(STEAM_OPENID_URL) {
+        bail!("response is not from Steam");
+    }
+    let return_to = params.get("openid.return_to").unwrap_or_default();
+    if !return_to.starts_with(params.redirect_uri()) {
+        bail!("response was meant for another site");
+    }
+    steam_claimed_id(params)?;
+    Ok(())
+}
+
+/// Returns the SteamID64 of the account a Steam OpenID response claims to be. The claim must still be checked with Steam, see `Builder::steam`.
+pub fn steam_claimed_id(params: &CallbackParams) -> Result<u64> {
+    params
+        .get("openid.claimed_id")
+        .and_then(|id| id.strip_prefix(STEAM_CLAIMED_ID_PREFIX))
+        .and_then(|id| id.parse().ok())
+        .context("response has no valid claimed id")
+}
+
+/// Returns the `application/x-www-form-urlencoded` body to POST to `https://steamcommunity.com/openid/login` to check a Steam OpenID response. The response is valid if Steam answers with a line reading `is_valid:true`.
+pub fn steam_check_authentication_body(params: &CallbackParams) -> String {
+    let mut body = url::form_urlencoded::Serializer::new(String::new());
+    for (key, value) in &params.params {
+        if key.starts_with("openid.") && key != "openid.mode" {
+            body.append_pair(key, value);
+        }
+    }
+    body.append_pair("openid.mode", "check_authentication");
+    body.finish()
+}
+
+fn run_hook(lua: State, event: &str, login: &Login) {
+    lua.push_string(&login.steamid64.to_string());
+    lua.push_string(&login.provider);
+    match &login.result {
+        Ok(identity) => {
+            lua.push_string(&identity.id);
+            match &identity.name {
+                Some(name) => lua.push_string(name),
+                None => lua.push_nil(),
+            }
+            lua.push_nil();
+        }
+        Err(err) => {
+            lua.push_nil();
+            lua.push_nil();
+            lua.push_string(&format!("{err:#}"));
+        }
+    }
+    hook::call(lua, event, 5, 0);
+}
+
+/// A handle to a running callback listener. The listener is stopped when the handle is dropped, or when the module is closed.
+#[must_use = "the listener is stopped as soon as the handle is dropped"]
+pub struct OAuthHandle {
+    server: Arc<Server>,
+}
+
+impl OAuthHandle {
+    /// Returns the address the listener is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.addr
+    }
+
+    /// Creates a single-use login for the player with the given SteamID64, and returns the URL they should open to log in with `provider`.
+    ///
+    /// A previous login URL the player requested for the same provider stops working.
+    pub fn login_url(&self, provider: &str, steamid64: u64) -> Result<String> {
+        let Some(kind) = self.server.providers.get(provider).map(|p| &p.kind) else {
+            bail!("unknown provider {provider:?}");
+        };
+
+        let state = random_token().context("failed to generate a login token")?;
+        let callback_url = self.server.callback_url(provider);
+
+        let url = match kind {
+            Kind::OAuth2(oauth2) => Url::parse_with_params(
+                &oauth2.authorize_url,
+                [
+                    ("response_type", "code"),
+                    ("client_id", &oauth2.client_id),
+                    ("redirect_uri", &callback_url),
+                    ("scope", &oauth2.scopes.join(" ")),
+                    ("state", &state),
+                ],
+            ),
+            Kind::Steam => {
+                let return_to = Url::parse_with_params(&callback_url, [("state", &state)])
+                    .context("invalid public URL")?;
+                let realm = return_to.origin().ascii_serialization();
+                Url::parse_with_params(
+                    STEAM_OPENID_URL,
+                    [
+                        ("openid.ns", OPENID_NS),
+                        ("openid.mode", "checkid_setup"),
+                        ("openid.return_to", return_to.as_str()),
+                        ("openid.realm", &realm),
+                        ("openid.identity", OPENID_IDENTIFIER_SELECT),
+                        ("openid.claimed_id", OPENID_IDENTIFIER_SELECT),
+                    ],
+                )
+            }
+        }
+        .context("invalid provider URL")?;
+
+        let now = Instant::now();
+        let mut pending = self
+            .server
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        pending.retain(|_, login| {
+            login.expires > now && !(login.steamid64 == steamid64 && login.provider == provider)
+        });
+        pending.insert(
+            state,
+            Pending {
+                steamid64,
+                provider: provider.to_string(),
+                expires: now + self.server.ttl,
+            },
+        );
+
+        Ok(url.into())
+    }
+}
+
+impl Drop for OAuthHandle {
+    fn drop(&mut self) {
+        self.server.stop();
+        SERVERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|s| !Arc::ptr_eq(s, &self.server));
+    }
+}
+
+/// Stops every OAuth listener started through this module. This is called for you by `#[gmod13_close]`.
+pub fn unload() {
+    let servers = std::mem::take(&mut *SERVERS.lock().unwrap_or_else(|e| e.into_inner()));
+    for server in servers {
+        server.stop();
+    }
+}
+
+/// Returns 32 random bytes from the operating system, hex encoded. Login tokens must be unguessable, which `fastrand` isn't.
+fn random_token() -> io::Result<String> {
+    let mut bytes = [0u8; 32];
+    sys::fill_random(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{fs::File, io::Read};
+
+    pub fn fill_random(buf: &mut [u8]) -> std::io::Result<()> {
+        File::open("/dev/urandom")?.read_exact(buf)
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::c_void;
+
+    const BCRYPT_USE_SYSTEM_PREFERRED_RNG: u32 = 0x2;
+
+    #[link(name = "bcrypt")]
+    extern "system" {
+        fn BCryptGenRandom(algorithm: *mut c_void, buf: *mut u8, len: u32, flags: u32) -> i32;
+    }
+
+    pub fn fill_random(buf: &mut [u8]) -> std::io::Result<()> {
+        let status = unsafe {
+            BCryptGenRandom(
+                std::ptr::null_mut(),
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+            )
+        };
+        if status < 0 {
+            return Err(std::io::Error::other(format!(
+                "BCryptGenRandom failed with status {status:#x}"
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! Converts Rust panics in `#[lua_function]`s into Lua errors, instead of letting them unwind through LuaJIT and crash the game.
+//!
+//! Once `install` is called, every `#[lua_function]` (including `#[gmod13_open]` and `#[gmod13_close]`) runs inside `catch_unwind`, and a panic is raised as a Lua error like `panicked at src/lib.rs:42:9: index out of bounds`. Panics outside of Lua functions, e.g. in background threads, are still handled by the previous panic hook.
+//!
+//! The hook is removed by `#[gmod13_close]`, as it must not outlive the module's code.
+//!
+//! ## Caveat
+//!
+//! A Lua error can't unwind through `catch_unwind`: the 64-bit branches of GMod raise Lua errors as foreign exceptions, which abort the process if they reach it. While the hook is installed, functions must not let Lua errors escape from Lua code they call, so use `pcall` instead of `call`.
+
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    fmt,
+    panic::{AssertUnwindSafe, PanicHookInfo},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+type PanicHook = Box<dyn Fn(&PanicHookInfo) + Sync + Send + 'static>;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static PREVIOUS_HOOK: Mutex<Option<PanicHook>> = Mutex::new(None);
+
+thread_local! {
+    /// How many `catch` calls are running on this thread.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Location of the last panic caught on this thread, as the payload doesn't include it.
+    static LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Starts converting panics in `#[lua_function]`s into Lua errors. Does nothing if already installed.
+pub fn install() {
+    if INSTALLED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    *PREVIOUS_HOOK.lock().unwrap_or_else(|e| e.into_inner()) = Some(std::panic::take_hook());
+    std::panic::set_hook(Box::new(hook));
+}
+
+/// Restores the panic hook that was set before `install`. Called by `#[gmod13_close]`.
+pub fn uninstall() {
+    if !INSTALLED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    if let Some(previous) = PREVIOUS_HOOK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+    {
+        std::panic::set_hook(previous);
+    }
+}
+
+pub fn is_installed() -> bool {
+    INSTALLED.load(Ordering::Acquire)
+}
+
+fn hook(info: &PanicHookInfo) {
+    if DEPTH.get() == 0 {
+        if let Some(previous) = &*PREVIOUS_HOOK.lock().unwrap_or_else(|e| e.into_inner()) {
+            previous(info);
+        }
+        return;
+    }
+    let location = info.location().map(|location| location.to_string());
+    LOCATION.set(location);
+}
+
+/// Runs `f`, catching panics if the hook is installed. Used by `#[lua_function]`, and by hand-written `extern "C-unwind"` functions that want the same behaviour.
+///
+/// ```
+/// gmod::panic::install();
+///
+/// let panic = gmod::panic::catch(|| -> i32 { panic!("no player with that id") }).unwrap_err();
+/// assert_eq!(panic.message, "no player with that id");
+/// assert!(panic.location.is_some());
+///
+/// gmod::panic::uninstall();
+/// ```
+pub fn catch<R>(f: impl FnOnce() -> R) -> Result<R, Panic> {
+    if !is_installed() {
+        return Ok(f());
+    }
+
+    DEPTH.set(DEPTH.get() + 1);
+    let result = std::panic::catch_unwind(AssertUnwindSafe(f));
+    DEPTH.set(DEPTH.get() - 1);
+
+    result.map_err(|payload| Panic {
+        message: payload_message(&*payload),
+        location: LOCATION.take(),
+    })
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// A panic caught by `catch`, raised as a Lua error.
+pub struct Panic {
+    pub message: String,
+    pub location: Option<String>,
+}
+
+impl fmt::Display for Panic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "panicked at {location}: {}", self.message),
+            None => write!(f, "panicked: {}", self.message),
+        }
+    }
+}
+
+/// Same as `Display`, as `#[lua_function]`s show errors with their `Debug` representation.
+impl fmt::Debug for Panic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Panic {}
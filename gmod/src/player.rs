@@ -0,0 +1,167 @@
+use std::ops::Deref;
+
+use crate::{
+    entity::Entity,
+    lua::{StackIndex, State},
+    steamid::SteamId,
+};
+
+/// An owned reference to a player. Derefs to `Entity`, so every entity accessor is available too.
+///
+/// Like `Entity`, methods must be called from the Lua thread, and return `None` (or `false`) once the player has disconnected.
+///
+/// ## Example
+///
+/// ```ignore
+/// for ply in gmod::player::all(lua) {
+///     if ply.health(lua).unwrap_or(0) <= 0 {
+///         ply.kick(lua, "no health left");
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Player {
+    entity: Entity,
+}
+
+impl Player {
+    /// Stores the player at the given stack index. Fails if the value is not a player.
+    pub fn from_index(lua: State, index: impl Into<StackIndex>) -> anyhow::Result<Self> {
+        let index = index.into().abs(lua);
+        let entity = Entity::from_index(lua, index)?;
+        if !entity.is_player(lua) {
+            anyhow::bail!(lua.type_error(index.0, "Player"));
+        }
+        Ok(Self { entity })
+    }
+
+    /// Wraps an entity, if it is a valid player.
+    pub fn from_entity(lua: State, entity: Entity) -> Option<Self> {
+        (entity.is_valid(lua) && entity.is_player(lua)).then_some(Self { entity })
+    }
+
+    /// Returns the underlying entity handle.
+    pub fn into_entity(self) -> Entity {
+        self.entity
+    }
+
+    /// Creates a new handle to the same player.
+    pub fn try_clone(&self, lua: State) -> Self {
+        Self {
+            entity: self.entity.try_clone(lua),
+        }
+    }
+
+    /// Returns the player's name, like `ply:Nick()`.
+    pub fn nick(&self, lua: State) -> Option<String> {
+        self.get(lua, c"Nick", |lua| {
+            lua.get_string(-1).map(|s| s.into_owned())
+        })
+    }
+
+    /// Returns the player's SteamID64, like `ply:SteamID64()`. Bots and players in singleplayer have none.
+    pub fn steamid64(&self, lua: State) -> Option<u64> {
+        self.get(lua, c"SteamID64", |lua| {
+            lua.get_string(-1).and_then(|s| s.parse().ok())
+        })
+    }
+
+    /// Same as `steamid64`, as a `SteamId` to convert to the other formats.
+    pub fn steam_id(&self, lua: State) -> Option<SteamId> {
+        self.steamid64(lua).and_then(SteamId::from_steamid64)
+    }
+
+    /// Returns the player's user ID for this session, like `ply:UserID()`.
+    pub fn userid(&self, lua: State) -> Option<i32> {
+        self.get(lua, c"UserID", |lua| Some(lua.to_number(-1) as i32))
+    }
+
+    /// Returns whether the player is a bot, like `ply:IsBot()`.
+    pub fn is_bot(&self, lua: State) -> bool {
+        self.get(lua, c"IsBot", |lua| Some(lua.get_boolean(-1)))
+            .unwrap_or(false)
+    }
+
+    /// Runs Lua code on the player's client, like `ply:SendLua(code)`. Serverside only, and limited to 254 bytes of code. Returns whether the call succeeded.
+    pub fn send_lua(&self, lua: State, code: &str) -> bool {
+        self.set(lua, c"SendLua", |lua| {
+            lua.push_string(code);
+            1
+        })
+    }
+
+    /// Kicks the player, like `ply:Kick(reason)`. Serverside only. Returns whether the call succeeded.
+    pub fn kick(&self, lua: State, reason: &str) -> bool {
+        self.set(lua, c"Kick", |lua| {
+            lua.push_string(reason);
+            1
+        })
+    }
+}
+
+impl Deref for Player {
+    type Target = Entity;
+
+    fn deref(&self) -> &Entity {
+        &self.entity
+    }
+}
+
+/// Returns every connected player, like `player.GetAll()`.
+pub fn all(lua: State) -> Vec<Player> {
+    lua.get_global(c"player");
+    lua.get_field(-1, c"GetAll");
+    if lua.pcall(0, 1, 0).is_err() {
+        lua.pop_n(2);
+        return Vec::new();
+    }
+
+    let mut players = Vec::new();
+    if lua.is_table(-1) {
+        for i in 1..=lua.len(-1) {
+            lua.raw_geti(-1, i);
+            if let Ok(player) = Player::from_index(lua, -1) {
+                players.push(player);
+            }
+            lua.pop();
+        }
+    }
+    lua.pop_n(2);
+    players
+}
+
+/// Finds a connected player by SteamID64, like `player.GetBySteamID64(steamid64)`.
+pub fn by_steamid64(lua: State, steamid64: u64) -> Option<Player> {
+    lua.get_global(c"player");
+    lua.get_field(-1, c"GetBySteamID64");
+    lua.push_string(&steamid64.to_string());
+    let player = find(lua);
+    lua.pop();
+    player
+}
+
+/// Same as `by_steamid64`, with a `SteamId`.
+pub fn by_steam_id(lua: State, steam_id: SteamId) -> Option<Player> {
+    by_steamid64(lua, steam_id.steamid64())
+}
+
+/// Finds a connected player by user ID, like `Player(userid)`.
+pub fn by_userid(lua: State, userid: i32) -> Option<Player> {
+    lua.get_global(c"Player");
+    lua.push_number(userid);
+    find(lua)
+}
+
+/// Calls the lookup function pushed with its single argument, and wraps its result if it's a valid player.
+fn find(lua: State) -> Option<Player> {
+    if lua.pcall(1, 1, 0).is_err() {
+        lua.pop();
+        return None;
+    }
+    // the lookup functions return `false` or NULL when nobody matches
+    let player = Entity::from_index(lua, -1)
+        .ok()
+        .and_then(|entity| Player::from_entity(lua, entity));
+    lua.pop();
+    player
+}
@@ -0,0 +1,397 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    lua::{task_queue, State},
+    shutdown,
+};
+
+/// How often the waiter thread checks whether the process exited, timed out or should be killed.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How a spawned process ended.
+#[derive(Debug)]
+pub enum Exit {
+    /// The process exited by itself.
+    Exited(ExitStatus),
+
+    /// The process was killed because it ran longer than its timeout.
+    TimedOut,
+
+    /// The process was killed with `ProcessHandle::kill` or because the module was closed.
+    Killed,
+
+    /// Waiting for the process failed.
+    Error(io::Error),
+}
+
+type LineHandler = Arc<Mutex<Box<dyn FnMut(State, String) + Send>>>;
+type ExitHandler = Box<dyn FnOnce(State, Exit) + Send>;
+
+struct Process {
+    child: Mutex<Child>,
+    kill: AtomicBool,
+    running: AtomicBool,
+}
+
+/// Processes that are still running, killed by `unload`.
+static PROCESSES: Mutex<Vec<Arc<Process>>> = Mutex::new(Vec::new());
+
+/// Builder for a process spawned with `spawn`.
+#[must_use = "the process is only spawned by calling `start`"]
+pub struct Spawn {
+    command: Command,
+    timeout: Option<Duration>,
+    on_stdout: Option<LineHandler>,
+    on_stderr: Option<LineHandler>,
+    on_exit: Option<ExitHandler>,
+}
+
+/// Prepares a child process whose output is streamed line by line to callbacks on the Lua thread.
+///
+/// Spawned processes are killed when the module is closed.
+///
+/// ## Example
+///
+/// ```ignore
+/// let mut cmd = std::process::Command::new("git");
+/// cmd.arg("--version");
+///
+/// gmod::proc::spawn(cmd)
+///     .timeout(Duration::from_secs(5))
+///     .on_stdout(|lua, line| println!("{line}"))
+///     .on_exit(|lua, exit| println!("{exit:?}"))
+///     .start()?;
+/// ```
+pub fn spawn(command: Command) -> Spawn {
+    Spawn {
+        command,
+        timeout: None,
+        on_stdout: None,
+        on_stderr: None,
+        on_exit: None,
+    }
+}
+
+impl Spawn {
+    /// Kills the process if it runs for longer than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Calls `f` on the Lua thread for every line the process writes to stdout.
+    pub fn on_stdout<F: FnMut(State, String) + Send + 'static>(mut self, f: F) -> Self {
+        self.on_stdout = Some(Arc::new(Mutex::new(Box::new(f))));
+        self
+    }
+
+    /// Calls `f` on the Lua thread for every line the process writes to stderr.
+    pub fn on_stderr<F: FnMut(State, String) + Send + 'static>(mut self, f: F) -> Self {
+        self.on_stderr = Some(Arc::new(Mutex::new(Box::new(f))));
+        self
+    }
+
+    /// Calls `f` on the Lua thread once the process has ended, after every line of output was delivered.
+    pub fn on_exit<F: FnOnce(State, Exit) + Send + 'static>(mut self, f: F) -> Self {
+        self.on_exit = Some(Box::new(f));
+        self
+    }
+
+    /// Spawns the process.
+    pub fn start(mut self) -> io::Result<ProcessHandle> {
+        self.command.stdin(Stdio::null());
+        self.command.stdout(if self.on_stdout.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        });
+        self.command.stderr(if self.on_stderr.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        });
+
+        let mut child = self.command.spawn()?;
+        let id = child.id();
+
+        let exited = Arc::new(AtomicBool::new(false));
+        let readers: Vec<JoinHandle<()>> = [
+            child.stdout.take().map(sys::into_file).zip(self.on_stdout),
+            child.stderr.take().map(sys::into_file).zip(self.on_stderr),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|(pipe, handler)| {
+            let pipe = Pipe {
+                pipe,
+                exited: exited.clone(),
+            };
+            std::thread::spawn(move || stream_lines(pipe, handler))
+        })
+        .collect();
+
+        let process = Arc::new(Process {
+            child: Mutex::new(child),
+            kill: AtomicBool::new(false),
+            running: AtomicBool::new(true),
+        });
+        PROCESSES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(process.clone());
+
+        let timeout = self.timeout;
+        let on_exit = self.on_exit;
+        let waiter = process.clone();
+        // the readers are joined by the waiter, so waiting for it on unload waits for them too
+        let handle = std::thread::spawn(move || {
+            let exit = wait(&waiter, timeout);
+            exited.store(true, Ordering::Release);
+            for reader in readers {
+                let _ = reader.join();
+            }
+
+            waiter.running.store(false, Ordering::Release);
+            PROCESSES
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .retain(|p| !Arc::ptr_eq(p, &waiter));
+
+            if let Some(on_exit) = on_exit {
+                let _ = task_queue::wait_lua_tick(String::new(), move |l| on_exit(l, exit));
+            }
+        });
+        shutdown::register_thread("gmod-rs process waiter", handle);
+
+        Ok(ProcessHandle { id, process })
+    }
+}
+
+/// The read end of a child's stdout or stderr, which ends once the child exited and what it wrote was read, rather than when every writer closed it, as a grandchild may inherit it and keep it open.
+struct Pipe {
+    pipe: File,
+    exited: Arc<AtomicBool>,
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // checked before polling, so that what was written before exiting is still read
+            let exited = self.exited.load(Ordering::Acquire);
+            if sys::readable(&self.pipe, POLL_INTERVAL)? {
+                return self.pipe.read(buf);
+            }
+            if exited {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// Sends every line to `handler`, until the pipe ends. Lines that aren't UTF-8 are converted lossily rather than ending the stream, as the pipe must be drained for the child to keep running.
+fn stream_lines(reader: Pipe, handler: LineHandler) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+        if buf.ends_with(b"\n") {
+            buf.pop();
+            if buf.ends_with(b"\r") {
+                buf.pop();
+            }
+        }
+        let line = String::from_utf8_lossy(&buf).into_owned();
+        let handler = handler.clone();
+        let _ = task_queue::wait_lua_tick(String::new(), move |l| {
+            (handler.lock().unwrap_or_else(|e| e.into_inner()))(l, line)
+        });
+    }
+}
+
+fn wait(process: &Process, timeout: Option<Duration>) -> Exit {
+    let started = Instant::now();
+    loop {
+        let mut child = process.child.lock().unwrap_or_else(|e| e.into_inner());
+        match child.try_wait() {
+            Ok(Some(status)) => return Exit::Exited(status),
+            Ok(None) => {}
+            Err(err) => return Exit::Error(err),
+        }
+
+        if process.kill.load(Ordering::Acquire) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Exit::Killed;
+        }
+
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Exit::TimedOut;
+        }
+
+        drop(child);
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// A handle to a process spawned with `spawn`. Dropping it does not kill the process.
+pub struct ProcessHandle {
+    id: u32,
+    process: Arc<Process>,
+}
+
+impl ProcessHandle {
+    /// Returns the OS-assigned process identifier.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns whether the process is still running.
+    pub fn is_running(&self) -> bool {
+        self.process.running.load(Ordering::Acquire)
+    }
+
+    /// Kills the process. Its exit callback receives `Exit::Killed`.
+    pub fn kill(&self) {
+        self.process.kill.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{
+        fs::File,
+        io,
+        os::fd::{AsRawFd, OwnedFd},
+        time::Duration,
+    };
+
+    const POLLIN: i16 = 1;
+
+    #[repr(C)]
+    struct PollFd {
+        fd: i32,
+        events: i16,
+        revents: i16,
+    }
+
+    /// `nfds_t`, an `unsigned long` on Linux (32 bits on 32-bit targets) and an `unsigned int` elsewhere.
+    #[cfg(target_os = "linux")]
+    type NFds = std::ffi::c_ulong;
+    #[cfg(not(target_os = "linux"))]
+    type NFds = std::ffi::c_uint;
+
+    extern "C" {
+        fn poll(fds: *mut PollFd, nfds: NFds, timeout: i32) -> i32;
+    }
+
+    pub fn into_file(pipe: impl Into<OwnedFd>) -> File {
+        File::from(pipe.into())
+    }
+
+    /// Waits until reading `pipe` wouldn't block, either because it has data or every writer closed it.
+    pub fn readable(pipe: &File, timeout: Duration) -> io::Result<bool> {
+        let mut fd = PollFd {
+            fd: pipe.as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        };
+        match unsafe { poll(&mut fd, 1, timeout.as_millis() as i32) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    Ok(false)
+                } else {
+                    Err(err)
+                }
+            }
+            ready => Ok(ready > 0),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{
+        ffi::c_void,
+        fs::File,
+        io,
+        os::windows::io::{AsRawHandle, OwnedHandle},
+        time::Duration,
+    };
+
+    /// Anonymous pipes can't be waited on, so they're peeked at this often.
+    const PEEK_INTERVAL: Duration = Duration::from_millis(5);
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn PeekNamedPipe(
+            pipe: *mut c_void,
+            buffer: *mut c_void,
+            len: u32,
+            read: *mut u32,
+            available: *mut u32,
+            left_this_message: *mut u32,
+        ) -> i32;
+    }
+
+    pub fn into_file(pipe: impl Into<OwnedHandle>) -> File {
+        File::from(pipe.into())
+    }
+
+    /// Waits until reading `pipe` wouldn't block, either because it has data or every writer closed it.
+    pub fn readable(pipe: &File, timeout: Duration) -> io::Result<bool> {
+        let started = std::time::Instant::now();
+        loop {
+            let mut available = 0;
+            let ok = unsafe {
+                PeekNamedPipe(
+                    pipe.as_raw_handle(),
+                    std::ptr::null_mut(),
+                    0,
+                    std::ptr::null_mut(),
+                    &mut available,
+                    std::ptr::null_mut(),
+                )
+            };
+            // fails once the pipe is broken, where reading returns EOF
+            if ok == 0 || available > 0 {
+                return Ok(true);
+            }
+            if started.elapsed() >= timeout {
+                return Ok(false);
+            }
+            std::thread::sleep(PEEK_INTERVAL);
+        }
+    }
+}
+
+/// Kills every process spawned through this module. This is called for you by `#[gmod13_close]`.
+pub fn unload() {
+    let processes = std::mem::take(&mut *PROCESSES.lock().unwrap_or_else(|e| e.into_inner()));
+    for process in processes {
+        process.kill.store(true, Ordering::Release);
+        let _ = process
+            .child
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .kill();
+    }
+}
@@ -0,0 +1,170 @@
+//! Timing of Lua functions and task queue callbacks, to find which parts of a module cause frame hitches.
+//!
+//! With the `profile` feature, every `#[lua_function]` and every callback queued with `wait_lua_tick` is timed automatically, under its Rust path (e.g. `my_module::my_function`). Other code can be timed with `scope` or `time`.
+//!
+//! Profiling can be paused with `set_enabled`, which brings the overhead down to an atomic load per call.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::lua::{self, HandleLuaFunctionReturn, LuaReg, State};
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static STATS: Mutex<Option<HashMap<&'static str, Stats>>> = Mutex::new(None);
+
+/// Aggregated timings of a single function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub calls: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl Stats {
+    pub fn average(&self) -> Duration {
+        if self.calls == 0 {
+            Duration::ZERO
+        } else {
+            self.total.div_f64(self.calls as f64)
+        }
+    }
+}
+
+/// Pauses or resumes profiling. Enabled by default.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Adds a call that took `elapsed` to the stats of `name`.
+pub fn record(name: &'static str, elapsed: Duration) {
+    let mut stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+    let stats = stats
+        .get_or_insert_with(HashMap::new)
+        .entry(name)
+        .or_default();
+    stats.calls += 1;
+    stats.total += elapsed;
+    stats.max = stats.max.max(elapsed);
+}
+
+/// Times the code until the returned guard is dropped. Returns `None` if profiling is paused.
+///
+/// ## Example
+///
+/// ```
+/// {
+///     let _scope = gmod::profile::scope("rebuild_navmesh");
+///     std::thread::sleep(std::time::Duration::from_millis(1));
+/// }
+/// let report = gmod::profile::report();
+/// assert_eq!(report[0].0, "rebuild_navmesh");
+/// assert_eq!(report[0].1.calls, 1);
+/// ```
+#[must_use = "the scope is timed until the guard is dropped"]
+pub fn scope(name: &'static str) -> Option<Scope> {
+    is_enabled().then(|| Scope {
+        name,
+        start: Instant::now(),
+    })
+}
+
+/// Times a closure. See `scope`.
+pub fn time<R>(name: &'static str, f: impl FnOnce() -> R) -> R {
+    let _scope = scope(name);
+    f()
+}
+
+/// A guard returned by `scope`, which records the time since it was created when dropped.
+#[derive(Debug)]
+pub struct Scope {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        record(self.name, self.start.elapsed());
+    }
+}
+
+/// Returns the stats of every timed function, slowest (by total time) first.
+pub fn report() -> Vec<(&'static str, Stats)> {
+    let stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut report: Vec<_> = stats
+        .iter()
+        .flatten()
+        .map(|(name, stats)| (*name, *stats))
+        .collect();
+    report.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+    report
+}
+
+/// Clears the stats.
+pub fn reset() {
+    *STATS.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Registers the profiling functions into a global table named `libname`. Registering into the module's own table (e.g. `c"mymodule"`) adds them next to its other functions:
+///
+/// - `GetProfile()` returns a list of `{ name = string, calls = number, total = number, max = number, average = number }`, slowest first, with times in milliseconds
+/// - `ResetProfile()` clears the stats
+/// - `SetProfiling(enabled)` pauses or resumes profiling
+pub fn register(lua: State, libname: lua::LuaCStr) {
+    lua.register(
+        libname.as_ptr(),
+        crate::lua_regs![
+            "GetProfile" => lua_get_profile,
+            "ResetProfile" => lua_reset_profile,
+            "SetProfiling" => lua_set_profiling,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+}
+
+extern "C-unwind" fn lua_get_profile(lua: State) -> i32 {
+    let report = report();
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+
+    lua.create_table(report.len() as i32, 0);
+    for (i, (name, stats)) in report.into_iter().enumerate() {
+        lua.create_table(0, 5);
+        lua.push_string(name);
+        lua.set_field(-2, c"name");
+        lua.push_number(stats.calls);
+        lua.set_field(-2, c"calls");
+        lua.push_number(ms(stats.total));
+        lua.set_field(-2, c"total");
+        lua.push_number(ms(stats.max));
+        lua.set_field(-2, c"max");
+        lua.push_number(ms(stats.average()));
+        lua.set_field(-2, c"average");
+        lua.raw_seti(-2, i as i32 + 1);
+    }
+    1
+}
+
+extern "C-unwind" fn lua_reset_profile(_lua: State) -> i32 {
+    reset();
+    0
+}
+
+extern "C-unwind" fn lua_set_profiling(lua: State) -> i32 {
+    (|| -> Result<()> {
+        set_enabled(lua.check_boolean(1)?);
+        Ok(())
+    })()
+    .handle_result(lua)
+}
@@ -0,0 +1,57 @@
+//! The Lua states a module can be loaded into.
+//!
+//! A module can be loaded into several states of the same process at once, e.g. a clientside module `require`d by both the client and the menu state, or a module loaded by both realms of a listen server. The library is only loaded once, so its statics are shared by every state: `#[gmod13_open]` and `#[gmod13_close]` run for each state, but gmod-rs only sets itself up on the first open and tears itself down on the last close. Each realm gets its own task queue, see `wait_lua_tick_in`.
+//!
+//! [`wait_lua_tick_in`]: crate::lua::task_queue::wait_lua_tick_in
+
+use std::fmt;
+
+use crate::lua::State;
+
+/// A Lua state of the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Realm {
+    Server,
+    Client,
+    Menu,
+}
+
+impl Realm {
+    /// Every realm.
+    pub const ALL: [Realm; 3] = [Realm::Server, Realm::Client, Realm::Menu];
+
+    /// Returns the realm's name, as `ScriptError::realm` and the game name it: `"server"`, `"client"` or `"menu"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Realm::Server => "server",
+            Realm::Client => "client",
+            Realm::Menu => "menu",
+        }
+    }
+
+    pub(crate) fn from_u8(realm: u8) -> Option<Realm> {
+        Self::ALL.get(realm as usize).copied()
+    }
+}
+
+impl fmt::Display for Realm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl State {
+    /// Returns the realm of this state, from the `MENU_DLL` and `CLIENT` globals. States that define neither are taken as the server.
+    pub fn realm(&self) -> Realm {
+        unsafe {
+            if self.is_menu() {
+                Realm::Menu
+            } else if self.is_client() {
+                Realm::Client
+            } else {
+                Realm::Server
+            }
+        }
+    }
+}
@@ -0,0 +1,525 @@
+//! Records the sequence of stack operations performed through [`State`](crate::lua::State) to a file, so that stack corruption bugs can be reproduced offline by replaying them against another Lua state.
+//!
+//! Only available with the `record` feature. Recording is global and covers every state the module touches. Each operation is tagged with the state it was performed on, numbered in the order the states were first used, so that one of them can be replayed at a time.
+//!
+//! While recording, calls made with `State::call` go through `pcall` so that their outcome can be recorded, and errors are raised again afterwards. Operations performed by Rust functions called from Lua are recorded deeper than the call that reached them, and aren't replayed, as replaying the call runs them again.
+
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::lua::{lua_shared, State, LUA_OK, LUA_REGISTRYINDEX, LUA_YIELD};
+
+/// A recorded stack operation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    PushValue(i32),
+    PushNil,
+    PushBool(bool),
+    PushNumber(f64),
+    PushString(Vec<u8>),
+    /// A C function or closure was pushed. Function pointers can't be replayed, so `nil` is pushed in their place (after popping the upvalues).
+    PushFunction(i32),
+    /// Light userdata can't be replayed, so `nil` is pushed in its place.
+    PushLightUserdata,
+    PushThread,
+    GetField(i32, String),
+    SetField(i32, String),
+    SetTop(i32),
+    Insert(i32),
+    Remove(i32),
+    Replace(i32),
+    CreateTable(i32, i32),
+    GetTable(i32),
+    SetTable(i32),
+    RawGetI(i32, i32),
+    RawSetI(i32, i32),
+    Next(i32),
+    GetMetatable(i32),
+    SetMetatable(i32),
+    NewMetatable(String),
+    /// A full userdata of this size was pushed. Its contents can't be replayed, so it's zeroed.
+    NewUserdata(usize),
+    NewThread,
+    /// Values were moved to another state, which records `Received`.
+    XMove(i32),
+    /// Values were moved from another state. Their source isn't replayed, so `nil` is pushed in their place.
+    Received(i32),
+    /// A value was popped into a registry reference.
+    Reference(i32),
+    Unreference(i32),
+    Traceback(i32),
+    /// A chunk was loaded from a string, and whether it compiled.
+    LoadString(Vec<u8>, bool),
+    /// A chunk was loaded from a buffer with a name, and whether it compiled.
+    LoadBuffer(Vec<u8>, String, bool),
+    /// A chunk was loaded from a file, and whether it compiled.
+    LoadFile(String, bool),
+    Call(i32, i32),
+    PCall(i32, i32, i32),
+    /// A C function was called with `cpcall`. It can't be replayed, so only the error message it may leave is, as `nil`.
+    CPCall,
+    Resume(i32),
+    /// The last call on the state returned, and whether it succeeded.
+    Returned(bool),
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s == "-" {
+        return Some(Vec::new());
+    }
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl Op {
+    /// Encodes the operation as a single line of text.
+    pub fn encode(&self) -> String {
+        match self {
+            Op::PushValue(idx) => format!("push_value {idx}"),
+            Op::PushNil => "push_nil".to_string(),
+            Op::PushBool(b) => format!("push_bool {b}"),
+            Op::PushNumber(n) => format!("push_number {}", n.to_bits()),
+            Op::PushString(s) => format!("push_string {}", hex_encode(s)),
+            Op::PushFunction(upvalues) => format!("push_function {upvalues}"),
+            Op::PushLightUserdata => "push_lightuserdata".to_string(),
+            Op::PushThread => "push_thread".to_string(),
+            Op::GetField(idx, k) => format!("get_field {idx} {}", hex_encode(k.as_bytes())),
+            Op::SetField(idx, k) => format!("set_field {idx} {}", hex_encode(k.as_bytes())),
+            Op::SetTop(idx) => format!("set_top {idx}"),
+            Op::Insert(idx) => format!("insert {idx}"),
+            Op::Remove(idx) => format!("remove {idx}"),
+            Op::Replace(idx) => format!("replace {idx}"),
+            Op::CreateTable(narr, nrec) => format!("create_table {narr} {nrec}"),
+            Op::GetTable(idx) => format!("get_table {idx}"),
+            Op::SetTable(idx) => format!("set_table {idx}"),
+            Op::RawGetI(t, idx) => format!("raw_geti {t} {idx}"),
+            Op::RawSetI(t, idx) => format!("raw_seti {t} {idx}"),
+            Op::Next(idx) => format!("next {idx}"),
+            Op::GetMetatable(idx) => format!("get_metatable {idx}"),
+            Op::SetMetatable(idx) => format!("set_metatable {idx}"),
+            Op::NewMetatable(name) => format!("new_metatable {}", hex_encode(name.as_bytes())),
+            Op::NewUserdata(size) => format!("new_userdata {size}"),
+            Op::NewThread => "new_thread".to_string(),
+            Op::XMove(n) => format!("xmove {n}"),
+            Op::Received(n) => format!("received {n}"),
+            Op::Reference(r) => format!("reference {r}"),
+            Op::Unreference(r) => format!("unreference {r}"),
+            Op::Traceback(level) => format!("traceback {level}"),
+            Op::LoadString(src, ok) => format!("load_string {} {ok}", hex_encode(src)),
+            Op::LoadBuffer(buf, name, ok) => format!(
+                "load_buffer {} {} {ok}",
+                hex_encode(buf),
+                hex_encode(name.as_bytes())
+            ),
+            Op::LoadFile(path, ok) => format!("load_file {} {ok}", hex_encode(path.as_bytes())),
+            Op::Call(nargs, nresults) => format!("call {nargs} {nresults}"),
+            Op::PCall(nargs, nresults, errfunc) => format!("pcall {nargs} {nresults} {errfunc}"),
+            Op::CPCall => "cpcall".to_string(),
+            Op::Resume(narg) => format!("resume {narg}"),
+            Op::Returned(ok) => format!("returned {ok}"),
+        }
+    }
+
+    /// Decodes an operation previously encoded with `encode`.
+    pub fn decode(line: &str) -> Option<Op> {
+        let mut parts = line.split_ascii_whitespace();
+        let name = parts.next()?;
+        let mut int = || parts.next()?.parse::<i32>().ok();
+
+        let op = match name {
+            "push_value" => Op::PushValue(int()?),
+            "push_nil" => Op::PushNil,
+            "push_function" => Op::PushFunction(int()?),
+            "push_lightuserdata" => Op::PushLightUserdata,
+            "push_thread" => Op::PushThread,
+            "set_top" => Op::SetTop(int()?),
+            "insert" => Op::Insert(int()?),
+            "remove" => Op::Remove(int()?),
+            "replace" => Op::Replace(int()?),
+            "create_table" => Op::CreateTable(int()?, int()?),
+            "get_table" => Op::GetTable(int()?),
+            "set_table" => Op::SetTable(int()?),
+            "raw_geti" => Op::RawGetI(int()?, int()?),
+            "raw_seti" => Op::RawSetI(int()?, int()?),
+            "next" => Op::Next(int()?),
+            "get_metatable" => Op::GetMetatable(int()?),
+            "set_metatable" => Op::SetMetatable(int()?),
+            "new_thread" => Op::NewThread,
+            "xmove" => Op::XMove(int()?),
+            "received" => Op::Received(int()?),
+            "reference" => Op::Reference(int()?),
+            "unreference" => Op::Unreference(int()?),
+            "traceback" => Op::Traceback(int()?),
+            "call" => Op::Call(int()?, int()?),
+            "pcall" => Op::PCall(int()?, int()?, int()?),
+            "cpcall" => Op::CPCall,
+            "resume" => Op::Resume(int()?),
+            "push_bool" => Op::PushBool(parts.next()?.parse().ok()?),
+            "returned" => Op::Returned(parts.next()?.parse().ok()?),
+            "push_number" => Op::PushNumber(f64::from_bits(parts.next()?.parse().ok()?)),
+            "push_string" => Op::PushString(hex_decode(parts.next()?)?),
+            "new_userdata" => Op::NewUserdata(parts.next()?.parse().ok()?),
+            "new_metatable" => {
+                Op::NewMetatable(String::from_utf8(hex_decode(parts.next()?)?).ok()?)
+            }
+            "load_string" => Op::LoadString(
+                hex_decode(parts.next()?)?,
+                parts.next()?.parse().ok()?,
+            ),
+            "load_buffer" => Op::LoadBuffer(
+                hex_decode(parts.next()?)?,
+                String::from_utf8(hex_decode(parts.next()?)?).ok()?,
+                parts.next()?.parse().ok()?,
+            ),
+            "load_file" => Op::LoadFile(
+                String::from_utf8(hex_decode(parts.next()?)?).ok()?,
+                parts.next()?.parse().ok()?,
+            ),
+            "get_field" | "set_field" => {
+                let idx = parts.next()?.parse().ok()?;
+                let k = String::from_utf8(hex_decode(parts.next()?)?).ok()?;
+                if name == "get_field" {
+                    Op::GetField(idx, k)
+                } else {
+                    Op::SetField(idx, k)
+                }
+            }
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+/// An operation as it appears in a recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The state the operation was performed on, numbered in the order the states were first used.
+    pub state: u32,
+    /// How many calls recorded on any state were in progress. Only operations at depth 0 are replayed.
+    pub depth: u32,
+    pub op: Op,
+}
+
+impl Entry {
+    /// Encodes the entry as a single line of text.
+    pub fn encode(&self) -> String {
+        format!("{} {} {}", self.state, self.depth, self.op.encode())
+    }
+
+    /// Decodes an entry previously encoded with `encode`.
+    pub fn decode(line: &str) -> Option<Entry> {
+        let mut parts = line.splitn(3, ' ');
+        Some(Entry {
+            state: parts.next()?.parse().ok()?,
+            depth: parts.next()?.parse().ok()?,
+            op: Op::decode(parts.next()?)?,
+        })
+    }
+}
+
+struct Recorder {
+    writer: BufWriter<File>,
+    /// The states seen so far, indexed by their number.
+    states: Vec<usize>,
+}
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+static DEPTH: AtomicU32 = AtomicU32::new(0);
+
+/// Starts recording every stack operation to the file at `path`, truncating it.
+pub fn start<P: AsRef<Path>>(path: P) -> Result<()> {
+    let file = File::create(path)?;
+    *RECORDER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Recorder {
+        writer: BufWriter::new(file),
+        states: Vec::new(),
+    });
+    RECORDING.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Stops recording and flushes the recording to disk.
+pub fn stop() -> Result<()> {
+    RECORDING.store(false, Ordering::Release);
+    if let Some(mut recorder) = RECORDER.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        recorder.writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Returns whether stack operations are currently being recorded.
+pub fn is_recording() -> bool {
+    RECORDING.load(Ordering::Acquire)
+}
+
+#[doc(hidden)]
+pub fn record(lua: State, op: impl FnOnce() -> Op) {
+    if !is_recording() {
+        return;
+    }
+    if let Some(recorder) = RECORDER.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        let ptr = lua.0 as usize;
+        let state = match recorder.states.iter().position(|&s| s == ptr) {
+            Some(state) => state,
+            None => {
+                recorder.states.push(ptr);
+                recorder.states.len() - 1
+            }
+        };
+        let entry = Entry {
+            state: state as u32,
+            depth: DEPTH.load(Ordering::Relaxed),
+            op: op(),
+        };
+        let _ = writeln!(recorder.writer, "{}", entry.encode());
+    }
+}
+
+#[doc(hidden)]
+/// Makes a call, recording `op` before it and whether `ok` holds for its result after it. Operations recorded in between are one call deeper.
+pub fn call<T>(
+    lua: State,
+    op: impl FnOnce() -> Op,
+    call: impl FnOnce() -> T,
+    ok: impl FnOnce(&T) -> bool,
+) -> T {
+    if !is_recording() {
+        return call();
+    }
+    record(lua, op);
+    DEPTH.fetch_add(1, Ordering::Relaxed);
+    let result = call();
+    DEPTH.fetch_sub(1, Ordering::Relaxed);
+    record(lua, || Op::Returned(ok(&result)));
+    result
+}
+
+/// Returns the states that performed operations in a recording, as passed to `replay`.
+pub fn states<P: AsRef<Path>>(path: P) -> Result<Vec<u32>> {
+    let mut states = Vec::new();
+    for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry = Entry::decode(&line)
+            .ok_or_else(|| anyhow!("invalid operation on line {}: {line}", n + 1))?;
+        if !states.contains(&entry.state) {
+            states.push(entry.state);
+        }
+    }
+    Ok(states)
+}
+
+/// Replays the operations a recording has for `state` against `lua`, returning how many operations were replayed.
+///
+/// Calls are replayed with `pcall` so that an error is reported with the offending operation instead of unwinding through Rust. A call that raised an error when it was recorded but not when it was replayed, or the other way around, fails the replay.
+///
+/// # Safety
+/// Replaying a recording against a state that doesn't have the same globals as the recorded one may leave the stack in an invalid state.
+pub unsafe fn replay<P: AsRef<Path>>(lua: State, path: P, state: u32) -> Result<usize> {
+    let was_recording = RECORDING.swap(false, Ordering::AcqRel);
+    let result = replay_file(lua, path.as_ref(), state);
+    RECORDING.store(was_recording, Ordering::Release);
+    result
+}
+
+/// A call that was replayed, waiting for the outcome it had when it was recorded.
+struct Pending {
+    op: Op,
+    /// Whether the replayed call succeeded, if it could be replayed.
+    ok: Option<bool>,
+}
+
+#[derive(Default)]
+struct Replayer {
+    /// Registry references made by the recording, and the ones made in their place.
+    refs: HashMap<i32, i32>,
+    pending: Option<Pending>,
+}
+
+unsafe fn replay_file(lua: State, path: &Path, state: u32) -> Result<usize> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut replayer = Replayer::default();
+    let mut count = 0;
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry = Entry::decode(&line)
+            .ok_or_else(|| anyhow!("invalid operation on line {}: {line}", n + 1))?;
+        if entry.state != state || entry.depth > 0 {
+            continue;
+        }
+        if let Err(err) = replayer.apply(lua, &entry.op) {
+            bail!("{err} (line {}: {line})", n + 1);
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+impl Replayer {
+    fn registry_index(&self, t: i32, idx: i32) -> i32 {
+        if t == LUA_REGISTRYINDEX {
+            self.refs.get(&idx).copied().unwrap_or(idx)
+        } else {
+            idx
+        }
+    }
+
+    unsafe fn apply(&mut self, lua: State, op: &Op) -> Result<()> {
+        let shared = lua_shared();
+        match op {
+            Op::PushValue(idx) => lua.push_value(*idx),
+            Op::PushNil | Op::PushLightUserdata => lua.push_nil(),
+            Op::PushBool(b) => lua.push_boolean(*b),
+            Op::PushNumber(n) => lua.lua_push_number(*n),
+            Op::PushString(s) => lua.push_binary_string(s),
+            Op::PushFunction(upvalues) => {
+                lua.pop_n(*upvalues);
+                lua.push_nil();
+            }
+            Op::PushThread => {
+                lua.push_thread();
+            }
+            Op::GetField(idx, k) => lua.get_field(*idx, &crate::lua::cstr::cached(k)),
+            Op::SetField(idx, k) => lua.set_field(*idx, &crate::lua::cstr::cached(k)),
+            Op::SetTop(idx) => lua.set_top(*idx),
+            Op::Insert(idx) => lua.insert(*idx),
+            Op::Remove(idx) => lua.remove(*idx),
+            Op::Replace(idx) => lua.replace(*idx),
+            Op::CreateTable(narr, nrec) => lua.create_table(*narr, *nrec),
+            Op::GetTable(idx) => lua.get_table(*idx),
+            Op::SetTable(idx) => lua.set_table(*idx),
+            Op::RawGetI(t, idx) => lua.raw_geti(*t, self.registry_index(*t, *idx)),
+            Op::RawSetI(t, idx) => lua.raw_seti(*t, self.registry_index(*t, *idx)),
+            Op::Next(idx) => {
+                lua.next(*idx);
+            }
+            Op::GetMetatable(idx) => {
+                lua.get_metatable(*idx);
+            }
+            Op::SetMetatable(idx) => {
+                lua.set_metatable(*idx);
+            }
+            Op::NewMetatable(name) => {
+                lua.new_metatable(&crate::lua::cstr::cached(name));
+            }
+            Op::NewUserdata(size) => {
+                let ptr = (shared.lua_newuserdata)(lua, *size) as *mut u8;
+                ptr.write_bytes(0, *size);
+            }
+            Op::NewThread => {
+                lua.coroutine_new();
+            }
+            Op::XMove(n) => lua.pop_n(*n),
+            Op::Received(n) => {
+                for _ in 0..*n {
+                    lua.push_nil();
+                }
+            }
+            Op::Reference(r) => {
+                let replayed = lua.reference();
+                self.refs.insert(*r, replayed);
+            }
+            Op::Unreference(r) => lua.dereference(self.refs.remove(r).unwrap_or(*r)),
+            Op::Traceback(level) => lua.lual_traceback(lua, *level),
+            Op::LoadString(src, ok) => {
+                let src = CString::new(src.as_slice())?;
+                check_load(*ok, lua.load_string(&src).is_ok())?;
+            }
+            Op::LoadBuffer(buf, name, ok) => {
+                let name = crate::lua::cstr::cached(name);
+                check_load(*ok, lua.load_buffer(buf, &name).is_ok())?;
+            }
+            Op::LoadFile(path, ok) => {
+                let path = crate::lua::cstr::cached(path);
+                check_load(*ok, lua.load_file(&path).is_ok())?;
+            }
+            Op::Call(nargs, nresults) => self.begin(op, lua.pcall(*nargs, *nresults, 0).is_ok()),
+            Op::PCall(nargs, nresults, errfunc) => {
+                self.begin(op, lua.pcall(*nargs, *nresults, *errfunc).is_ok())
+            }
+            Op::Resume(narg) => self.begin(
+                op,
+                matches!(lua.coroutine_resume(*narg), LUA_OK | LUA_YIELD),
+            ),
+            Op::CPCall => {
+                self.pending = Some(Pending {
+                    op: op.clone(),
+                    ok: None,
+                })
+            }
+            Op::Returned(ok) => {
+                let Some(pending) = self.pending.take() else {
+                    bail!("returned from a call that wasn't replayed");
+                };
+                match (pending.ok, &pending.op) {
+                    (Some(replayed), _) if replayed != *ok => bail!(
+                        "{} {} when it was recorded, but {} when it was replayed",
+                        pending.op.encode(),
+                        outcome(*ok),
+                        outcome(replayed)
+                    ),
+                    // the error was raised out of the function that made the call
+                    (Some(_), Op::Call(..)) if !ok => lua.pop(),
+                    // the error message of a function that can't be replayed
+                    (None, _) if !ok => lua.push_nil(),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn begin(&mut self, op: &Op, ok: bool) {
+        self.pending = Some(Pending {
+            op: op.clone(),
+            ok: Some(ok),
+        });
+    }
+}
+
+fn outcome(ok: bool) -> &'static str {
+    if ok {
+        "succeeded"
+    } else {
+        "raised an error"
+    }
+}
+
+fn check_load(recorded: bool, replayed: bool) -> Result<()> {
+    if recorded != replayed {
+        bail!(
+            "loading the chunk {} when it was recorded, but {} when it was replayed",
+            outcome(recorded),
+            outcome(replayed)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! Lua scripts embedded in the module at compile time with `include_lua_dir!`, for companion Lua code that would otherwise be pasted into string literals.
+//!
+//! Scripts are loaded with the chunk name `@<crate>/<path>`, so errors point at the original file, e.g. `mymodule/net/receivers.lua:12: attempt to index a nil value`.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! // every .lua file under <crate>/lua/, recursively
+//! static SCRIPTS: gmod::scripts::Bundle = gmod::include_lua_dir!("lua/");
+//!
+//! #[gmod13_open]
+//! fn gmod13_open(lua: gmod::lua::State) -> anyhow::Result<()> {
+//!     gmod::scripts::register(&SCRIPTS);
+//!     gmod::scripts::run(lua, "init.lua")?;
+//!     Ok(())
+//! }
+//! ```
+
+use std::{ffi::CString, sync::Mutex};
+
+use anyhow::{bail, Result};
+
+use crate::lua::State;
+
+/// A Lua file embedded by `include_lua_dir!`.
+#[derive(Debug, Clone, Copy)]
+pub struct Script {
+    /// Path relative to the embedded directory, with `/` separators.
+    pub path: &'static str,
+    pub source: &'static str,
+}
+
+/// A directory of Lua files embedded by `include_lua_dir!`, sorted by path.
+#[derive(Debug, Clone, Copy)]
+pub struct Bundle {
+    /// Prefix of the chunk names, the name of the crate that embedded the scripts.
+    pub name: &'static str,
+    pub scripts: &'static [Script],
+}
+
+impl Bundle {
+    pub const fn new(name: &'static str, scripts: &'static [Script]) -> Self {
+        Self { name, scripts }
+    }
+
+    /// Returns the script at `path`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use gmod::scripts::{Bundle, Script};
+    ///
+    /// static SCRIPTS: Bundle = Bundle::new(
+    ///     "mymodule",
+    ///     &[Script { path: "init.lua", source: "print('hi')" }],
+    /// );
+    ///
+    /// assert_eq!(SCRIPTS.get("init.lua").unwrap().source, "print('hi')");
+    /// assert!(SCRIPTS.get("missing.lua").is_none());
+    /// assert_eq!(SCRIPTS.chunk_name("init.lua"), "@mymodule/init.lua");
+    /// ```
+    pub fn get(&self, path: &str) -> Option<&'static Script> {
+        let scripts: &'static [Script] = self.scripts;
+        scripts.iter().find(|script| script.path == path)
+    }
+
+    /// Returns the chunk name scripts at `path` are loaded with.
+    pub fn chunk_name(&self, path: &str) -> String {
+        format!("@{}/{path}", self.name)
+    }
+
+    /// Runs the script at `path`.
+    pub fn run(&self, lua: State, path: &str) -> Result<()> {
+        let Some(script) = self.get(path) else {
+            bail!("{} has no embedded script named {path:?}", self.name);
+        };
+        self.run_script(lua, script)
+    }
+
+    /// Runs every script in path order, stopping at the first one that fails.
+    pub fn run_all(&self, lua: State) -> Result<()> {
+        for script in self.scripts {
+            self.run_script(lua, script)?;
+        }
+        Ok(())
+    }
+
+    fn run_script(&self, lua: State, script: &Script) -> Result<()> {
+        let chunk_name = CString::new(self.chunk_name(script.path))?;
+        let result = unsafe { lua.load_buffer(script.source.as_bytes(), &chunk_name) }
+            .and_then(|_| lua.pcall(0, 0, 0));
+        if let Err(err) = result {
+            // the error message is left on the stack
+            lua.pop();
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+static BUNDLES: Mutex<Vec<&'static Bundle>> = Mutex::new(Vec::new());
+
+/// Makes a bundle's scripts available to `run` and `run_all`. Registering the same bundle twice does nothing.
+pub fn register(bundle: &'static Bundle) {
+    let mut bundles = BUNDLES.lock().unwrap_or_else(|e| e.into_inner());
+    if !bundles
+        .iter()
+        .any(|registered| std::ptr::eq(*registered, bundle))
+    {
+        bundles.push(bundle);
+    }
+}
+
+fn bundles() -> Vec<&'static Bundle> {
+    BUNDLES.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Runs the script at `path` from the first registered bundle that has it.
+pub fn run(lua: State, path: &str) -> Result<()> {
+    for bundle in bundles() {
+        if let Some(script) = bundle.get(path) {
+            return bundle.run_script(lua, script);
+        }
+    }
+    bail!("no embedded script named {path:?}")
+}
+
+/// Runs every script of every registered bundle, in registration then path order, stopping at the first one that fails.
+pub fn run_all(lua: State) -> Result<()> {
+    for bundle in bundles() {
+        bundle.run_all(lua)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,870 @@
+//! Converting serde types to Lua values and back, so the same models can be used with the stack API as with JSON or SQL.
+//!
+//! | Rust | Lua |
+//! |------|-----|
+//! | `bool` | boolean |
+//! | integers, floats | number |
+//! | `String`, `char` | string |
+//! | bytes (`serde_bytes`) | string, which can hold any byte |
+//! | `None`, `()`, unit structs | nil |
+//! | sequences, tuples | array table |
+//! | maps, structs | table |
+//! | unit variants | string with the variant's name |
+//! | other variants | `{ Variant = value }` |
+//!
+//! Other representations of enums (`#[serde(tag = "...")]`...) work as usual. A `None` in a sequence leaves a hole in the table, past which `#` may stop counting, so it's read back shorter; tuples are read up to their length. As every number is a double, integers further than 2^53 - 1 from zero are refused by `to_lua` instead of being rounded, and numbers with a fractional part are refused as integers by `from_lua`.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Settings {
+//!     max_players: u8,
+//!     motd: Option<String>,
+//! }
+//!
+//! #[lua_function]
+//! fn set_settings(lua: State) -> anyhow::Result<()> {
+//!     let settings: Settings = gmod::serde::from_lua(lua, 1)?;
+//!     // ...
+//!     gmod::serde::to_lua(lua, &settings)?;
+//!     Ok(())
+//! }
+//! ```
+
+use std::fmt;
+
+use ::serde::{
+    de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor},
+    ser::{self, Serialize},
+};
+use anyhow::Result;
+
+use crate::lua::{
+    cstr, StackIndex, State, LUA_NUMBER_MAX_SAFE_INTEGER, LUA_TBOOLEAN, LUA_TNIL, LUA_TNONE,
+    LUA_TNUMBER, LUA_TSTRING, LUA_TTABLE,
+};
+
+/// How deep tables are read, so a table containing itself isn't read forever.
+const MAX_DEPTH: usize = 128;
+
+/// Pushes `value` onto the stack. Nothing is pushed if it fails.
+pub fn to_lua<T: Serialize + ?Sized>(lua: State, value: &T) -> Result<()> {
+    let top = lua.get_top();
+    value.serialize(Serializer { lua }).map_err(|err| {
+        lua.set_top(top);
+        err.into()
+    })
+}
+
+/// Reads the value at `index`.
+pub fn from_lua<T: DeserializeOwned>(lua: State, index: impl Into<StackIndex>) -> Result<T> {
+    let index = index.into().abs(lua).0;
+    let top = lua.get_top();
+    let result = T::deserialize(Deserializer {
+        lua,
+        index,
+        depth: 0,
+    });
+    lua.set_top(top);
+    Ok(result?)
+}
+
+/// Why a value couldn't be converted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+/// Pushes one value per call.
+#[derive(Clone, Copy)]
+struct Serializer {
+    lua: State,
+}
+
+impl Serializer {
+    fn push_integer(self, int: i128) -> Result<(), Error> {
+        if int.unsigned_abs() > LUA_NUMBER_MAX_SAFE_INTEGER as u128 {
+            return Err(Error(format!(
+                "{int} can't be represented exactly by a Lua number"
+            )));
+        }
+        self.lua.push_number(int as f64);
+        Ok(())
+    }
+
+    /// Pushes the table that's filled by the returned `SerializeTable`, in a `{ variant = table }` table for variants.
+    fn begin_table(
+        self,
+        len: Option<usize>,
+        array: bool,
+        variant: Option<&'static str>,
+    ) -> Result<SerializeTable, Error> {
+        if !self.lua.check_stack(4) {
+            return Err(Error("the value is nested too deeply".into()));
+        }
+        if variant.is_some() {
+            self.lua.create_table(0, 1);
+        }
+        let len = len.unwrap_or(0).min(i32::MAX as usize) as i32;
+        if array {
+            self.lua.create_table(len, 0);
+        } else {
+            self.lua.create_table(0, len);
+        }
+        Ok(SerializeTable {
+            lua: self.lua,
+            table: self.lua.get_top(),
+            next: 1,
+            variant,
+        })
+    }
+}
+
+macro_rules! serialize_integer {
+    ($($method:ident: $ty:ty),+) => {
+        $(
+            fn $method(self, value: $ty) -> Result<(), Error> {
+                self.push_integer(value as i128)
+            }
+        )+
+    };
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = SerializeTable;
+    type SerializeTuple = SerializeTable;
+    type SerializeTupleStruct = SerializeTable;
+    type SerializeTupleVariant = SerializeTable;
+    type SerializeMap = SerializeTable;
+    type SerializeStruct = SerializeTable;
+    type SerializeStructVariant = SerializeTable;
+
+    fn serialize_bool(self, value: bool) -> Result<(), Error> {
+        self.lua.push_boolean(value);
+        Ok(())
+    }
+
+    serialize_integer!(
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64
+    );
+
+    fn serialize_i128(self, value: i128) -> Result<(), Error> {
+        self.push_integer(value)
+    }
+
+    fn serialize_u128(self, value: u128) -> Result<(), Error> {
+        self.push_integer(i128::try_from(value).unwrap_or(i128::MAX))
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<(), Error> {
+        self.lua.push_number(value as f64);
+        Ok(())
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<(), Error> {
+        self.lua.push_number(value);
+        Ok(())
+    }
+
+    fn serialize_char(self, value: char) -> Result<(), Error> {
+        self.lua.push_string(value.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+
+    fn serialize_str(self, value: &str) -> Result<(), Error> {
+        self.lua.push_string(value);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<(), Error> {
+        self.lua.push_binary_string(value);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.lua.push_nil();
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.lua.push_nil();
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        if !self.lua.check_stack(2) {
+            return Err(Error("the value is nested too deeply".into()));
+        }
+        self.lua.create_table(0, 1);
+        value.serialize(self)?;
+        self.lua.set_field(-2, &cstr::cached(variant));
+        Ok(())
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeTable, Error> {
+        self.begin_table(len, true, None)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeTable, Error> {
+        self.begin_table(Some(len), true, None)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeTable, Error> {
+        self.begin_table(Some(len), true, None)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeTable, Error> {
+        self.begin_table(Some(len), true, Some(variant))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeTable, Error> {
+        self.begin_table(len, false, None)
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeTable, Error> {
+        self.begin_table(Some(len), false, None)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeTable, Error> {
+        self.begin_table(Some(len), false, Some(variant))
+    }
+}
+
+/// Fills the table at `table`, the top of the stack.
+struct SerializeTable {
+    lua: State,
+    table: i32,
+    /// The index of the next element of a sequence.
+    next: i32,
+    variant: Option<&'static str>,
+}
+
+impl SerializeTable {
+    fn push_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(Serializer { lua: self.lua })?;
+        self.lua.raw_seti(self.table, self.next);
+        self.next += 1;
+        Ok(())
+    }
+
+    fn set_field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        value.serialize(Serializer { lua: self.lua })?;
+        self.lua.set_field(self.table, &cstr::cached(key));
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Error> {
+        if let Some(variant) = self.variant {
+            self.lua.set_field(-2, &cstr::cached(variant));
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SerializeTable {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        SerializeTable::end(self)
+    }
+}
+
+impl ser::SerializeTuple for SerializeTable {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        SerializeTable::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeTable {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        SerializeTable::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeTable {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        SerializeTable::end(self)
+    }
+}
+
+impl ser::SerializeMap for SerializeTable {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        key.serialize(Serializer { lua: self.lua })?;
+        // `lua_settable` raises an error for these
+        let lua = self.lua;
+        if lua.is_nil(-1) || (lua.lua_type(-1) == LUA_TNUMBER && lua.to_number(-1).is_nan()) {
+            return Err(Error("a table key can't be nil or NaN".into()));
+        }
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(Serializer { lua: self.lua })?;
+        self.lua.set_table(self.table);
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Error> {
+        SerializeTable::end(self)
+    }
+}
+
+impl ser::SerializeStruct for SerializeTable {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.set_field(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        SerializeTable::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeTable {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.set_field(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        SerializeTable::end(self)
+    }
+}
+
+/// Reads the value at `index`, an absolute index. Values pushed while reading are left to the caller to pop.
+#[derive(Clone, Copy)]
+struct Deserializer {
+    lua: State,
+    index: i32,
+    depth: usize,
+}
+
+impl Deserializer {
+    fn lua_type(&self) -> i32 {
+        self.lua.lua_type(self.index)
+    }
+
+    fn invalid(&self, expected: &str) -> Error {
+        Error(format!(
+            "expected {expected}, got {}",
+            self.lua.lua_type_name(self.lua_type())
+        ))
+    }
+
+    /// Reads the value at `index`, nested in this one.
+    fn child(&self, index: i32) -> Self {
+        Self {
+            lua: self.lua,
+            index,
+            depth: self.depth + 1,
+        }
+    }
+
+    fn number(&self) -> Result<f64, Error> {
+        match self.lua_type() {
+            LUA_TNUMBER => Ok(self.lua.to_number(self.index)),
+            _ => Err(self.invalid("a number")),
+        }
+    }
+
+    fn integer(&self) -> Result<i128, Error> {
+        let number = self.number()?;
+        if number.is_finite() && number.fract() == 0.0 {
+            Ok(number as i128)
+        } else {
+            Err(Error(format!("expected an integer, got {number}")))
+        }
+    }
+
+    /// Checks the value is a table that can be read.
+    fn table(&self) -> Result<(), Error> {
+        if self.lua_type() != LUA_TTABLE {
+            return Err(self.invalid("a table"));
+        }
+        if self.depth >= MAX_DEPTH || !self.lua.check_stack(4) {
+            return Err(Error(
+                "tables are nested too deeply, or contain themselves".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Only checked for strings, as `get_binary_string` would convert numbers in place, which breaks `next`.
+    fn bytes(&self) -> Result<&[u8], Error> {
+        match self.lua_type() {
+            LUA_TSTRING => Ok(self.lua.get_binary_string(self.index).unwrap_or_default()),
+            _ => Err(self.invalid("a string")),
+        }
+    }
+
+    fn str(&self) -> Result<&str, Error> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| Error("expected a UTF-8 string".into()))
+    }
+
+    /// How many keys the table has.
+    fn count_keys(&self) -> usize {
+        let mut count = 0;
+        self.lua.push_nil();
+        while unsafe { self.lua.next(self.index) } != 0 {
+            count += 1;
+            self.lua.pop();
+        }
+        count
+    }
+
+    /// Whether the table only has the keys `1..=#table`, and at least one.
+    fn is_sequence(&self) -> bool {
+        let len = self.lua.len(self.index);
+        len > 0 && self.count_keys() == len as usize
+    }
+
+    /// Reads `table[1]` to `table[len]`, for sequences and tuples.
+    fn read_seq<'de, V: Visitor<'de>>(self, len: i32, visitor: V) -> Result<V::Value, Error> {
+        self.scoped(|| {
+            visitor.visit_seq(SeqAccess {
+                de: self,
+                next: 1,
+                len,
+            })
+        })
+    }
+
+    /// Runs `f` and pops what it pushed.
+    fn scoped<R>(&self, f: impl FnOnce() -> R) -> R {
+        let top = self.lua.get_top();
+        let result = f();
+        self.lua.set_top(top);
+        result
+    }
+}
+
+macro_rules! deserialize_integer {
+    ($($method:ident => $visit:ident: $ty:ty),+) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                let int = self.integer()?;
+                match <$ty>::try_from(int) {
+                    Ok(int) => visitor.$visit(int),
+                    Err(_) => Err(Error(format!("{int} is out of range for {}", stringify!($ty)))),
+                }
+            }
+        )+
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.lua_type() {
+            LUA_TNIL | LUA_TNONE => visitor.visit_unit(),
+            LUA_TBOOLEAN => visitor.visit_bool(self.lua.get_boolean(self.index)),
+            LUA_TNUMBER => {
+                let number = self.lua.to_number(self.index);
+                if number.fract() == 0.0 && number.abs() <= LUA_NUMBER_MAX_SAFE_INTEGER as f64 {
+                    if number < 0.0 {
+                        visitor.visit_i64(number as i64)
+                    } else {
+                        visitor.visit_u64(number as u64)
+                    }
+                } else {
+                    visitor.visit_f64(number)
+                }
+            }
+            LUA_TSTRING => match self.str() {
+                Ok(str) => visitor.visit_str(str),
+                Err(_) => visitor.visit_bytes(self.bytes()?),
+            },
+            LUA_TTABLE => {
+                self.table()?;
+                if self.is_sequence() {
+                    self.deserialize_seq(visitor)
+                } else {
+                    self.deserialize_map(visitor)
+                }
+            }
+            _ => Err(self.invalid("a nil, boolean, number, string or table")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.lua_type() {
+            LUA_TNIL | LUA_TNONE => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.lua_type() {
+            LUA_TBOOLEAN => visitor.visit_bool(self.lua.get_boolean(self.index)),
+            _ => Err(self.invalid("a boolean")),
+        }
+    }
+
+    deserialize_integer!(
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_i128 => visit_i128: i128,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_u128 => visit_u128: u128
+    );
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f32(self.number()? as f32)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(self.number()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut chars = self.str()?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(char), None) => visitor.visit_char(char),
+            _ => Err(Error("expected a single character".into())),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_str(self.str()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.lua_type() {
+            LUA_TTABLE => self.deserialize_seq(visitor),
+            _ => visitor.visit_bytes(self.bytes()?),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.lua_type() {
+            LUA_TNIL | LUA_TNONE => visitor.visit_unit(),
+            _ => Err(self.invalid("nil")),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.table()?;
+        let len = self.lua.len(self.index);
+        self.read_seq(len, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.table()?;
+        self.read_seq(len.min(i32::MAX as usize) as i32, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.table()?;
+        self.scoped(|| {
+            visitor.visit_map(MapAccess {
+                de: self,
+                started: false,
+            })
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.lua_type() {
+            LUA_TSTRING => visitor.visit_enum(self.str()?.into_deserializer()),
+            LUA_TTABLE => {
+                self.table()?;
+                if self.count_keys() != 1 {
+                    return Err(Error(
+                        "expected a table with a single key, the name of the variant".into(),
+                    ));
+                }
+                self.scoped(|| {
+                    self.lua.push_nil();
+                    unsafe { self.lua.next(self.index) };
+                    let top = self.lua.get_top();
+                    visitor.visit_enum(EnumAccess {
+                        variant: self.child(top - 1),
+                        value: self.child(top),
+                    })
+                })
+            }
+            _ => Err(self.invalid("the name of a variant, or a table")),
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+/// Reads `table[1]` to `table[len]`.
+struct SeqAccess {
+    de: Deserializer,
+    next: i32,
+    len: i32,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.next > self.len {
+            return Ok(None);
+        }
+        let lua = self.de.lua;
+        lua.raw_geti(self.de.index, self.next);
+        self.next += 1;
+        let value = seed.deserialize(self.de.child(lua.get_top()));
+        lua.pop();
+        value.map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some((self.len - self.next + 1).max(0) as usize)
+    }
+}
+
+/// Reads the pairs of a table with `next`, which leaves the current key on the stack between calls.
+struct MapAccess {
+    de: Deserializer,
+    started: bool,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let lua = self.de.lua;
+        if !self.started {
+            lua.push_nil();
+            self.started = true;
+        }
+        if unsafe { lua.next(self.de.index) } == 0 {
+            return Ok(None);
+        }
+        seed.deserialize(self.de.child(lua.get_top() - 1)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let lua = self.de.lua;
+        let value = seed.deserialize(self.de.child(lua.get_top()));
+        lua.pop();
+        value
+    }
+}
+
+/// A `{ variant = value }` table, with the pair on the stack.
+struct EnumAccess {
+    variant: Deserializer,
+    value: Deserializer,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = Deserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Deserializer), Error> {
+        Ok((seed.deserialize(self.variant)?, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+use crate::{
+    hook,
+    lua::{self, HandleLuaFunctionReturn, LuaReg, State},
+};
+
+/// Where open sessions are persisted so they survive a changelevel (or a crash).
+pub const PERSIST_PATH: &str = "garrysmod/data/gmod_rs/sessions.txt";
+
+/// How often open sessions are flushed to disk.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a session stays resumable after the player left (or after its last flush, if the server went down).
+///
+/// A player that reconnects within this window (e.g. after a changelevel) continues their previous session. Otherwise the session ends where it was last seen, its playtime is added to the player's total, and a new one starts.
+pub const RESUME_GRACE: Duration = Duration::from_secs(180);
+
+const HOOK_ID: &str = "gmod_rs_sessions";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    pub steamid64: u64,
+    /// Unix timestamp (seconds) of when the session started, or was last resumed.
+    pub joined_at: u64,
+    /// Unix timestamp (seconds) of when the session was last known to be alive.
+    ///
+    /// This is updated on every flush, so after a crash playtime is only counted up to the last flush.
+    pub last_seen: u64,
+    /// Playtime of the session before it was last resumed, in seconds. The time the player spent away isn't counted.
+    pub accumulated: u64,
+}
+
+impl Session {
+    /// Returns the playtime of this session, in seconds.
+    pub fn playtime(&self) -> u64 {
+        self.accumulated + self.last_seen.saturating_sub(self.joined_at)
+    }
+}
+
+#[derive(Default)]
+struct Sessions {
+    /// Sessions of players currently on the server.
+    current: HashMap<u64, Session>,
+    /// Sessions of players that left, or restored from disk, waiting for their player to come back within `RESUME_GRACE`.
+    pending: HashMap<u64, Session>,
+    /// Playtime of the sessions that ended, in seconds, by player.
+    totals: HashMap<u64, u64>,
+}
+
+impl Sessions {
+    /// Whether `session` can still be resumed at `now`.
+    fn resumable(session: &Session, now: u64) -> bool {
+        now.saturating_sub(session.last_seen) <= RESUME_GRACE.as_secs()
+    }
+
+    /// Adds the playtime of a session that won't be resumed to its player's total.
+    fn end(&mut self, session: Session) {
+        *self.totals.entry(session.steamid64).or_default() += session.playtime();
+    }
+
+    /// Ends the pending sessions that can't be resumed anymore.
+    fn expire(&mut self, now: u64) {
+        let expired: Vec<Session> = self
+            .pending
+            .extract_if(|_, session| !Self::resumable(session, now))
+            .map(|(_, session)| session)
+            .collect();
+        for session in expired {
+            self.end(session);
+        }
+    }
+}
+
+static SESSIONS: Mutex<Option<Sessions>> = Mutex::new(None);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn with_sessions<R>(f: impl FnOnce(&mut Sessions) -> R) -> Option<R> {
+    let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+    sessions.as_mut().map(f)
+}
+
+/// Starts tracking sessions.
+///
+/// Restores persisted sessions, hooks `PlayerInitialSpawn`/`PlayerDisconnected` and creates a timer that periodically flushes open sessions to disk.
+pub fn load(lua: State) {
+    let mut sessions = Sessions::default();
+    if let Ok(contents) = std::fs::read_to_string(PERSIST_PATH) {
+        for record in parse(&contents) {
+            match record {
+                Record::Session(session) => {
+                    sessions.pending.insert(session.steamid64, session);
+                }
+                Record::Total(steamid64, playtime) => {
+                    *sessions.totals.entry(steamid64).or_default() += playtime;
+                }
+            }
+        }
+        sessions.expire(now());
+    }
+    *SESSIONS.lock().unwrap_or_else(|e| e.into_inner()) = Some(sessions);
+
+    hook::add(lua, "PlayerInitialSpawn", HOOK_ID, player_initial_spawn);
+    hook::add(lua, "PlayerDisconnected", HOOK_ID, player_disconnected);
+
+    lua.get_global(c"timer");
+    lua.get_field(-1, c"Create");
+    lua.push_string(HOOK_ID);
+    lua.push_number(FLUSH_INTERVAL.as_secs());
+    lua.push_number(0);
+    lua.push_function(flush_timer);
+    lua.pcall_ignore(4, 0);
+    lua.pop();
+}
+
+/// Stops tracking sessions, flushing every open session to disk so it can be resumed after the next load.
+pub fn unload(lua: State) {
+    lua.get_global(c"timer");
+    lua.get_field(-1, c"Remove");
+    lua.push_string(HOOK_ID);
+    lua.pcall_ignore(1, 0);
+    lua.pop();
+
+    hook::remove(lua, "PlayerInitialSpawn", HOOK_ID);
+    hook::remove(lua, "PlayerDisconnected", HOOK_ID);
+
+    if let Err(err) = flush() {
+        eprintln!("[gmod-rs] Failed to persist sessions: {err}");
+    }
+    *SESSIONS.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Writes every open session, the sessions that can still be resumed, and the total playtime of every player to disk.
+pub fn flush() -> Result<()> {
+    let contents = with_sessions(|sessions| {
+        let now = now();
+        sessions.expire(now);
+        for session in sessions.current.values_mut() {
+            session.last_seen = now;
+        }
+
+        let mut contents = String::new();
+        for session in sessions.current.values().chain(sessions.pending.values()) {
+            contents.push_str(&format!(
+                "{} {} {} {}\n",
+                session.steamid64, session.joined_at, session.last_seen, session.accumulated
+            ));
+        }
+        for (steamid64, playtime) in &sessions.totals {
+            contents.push_str(&format!("total {steamid64} {playtime}\n"));
+        }
+        contents
+    });
+
+    let Some(contents) = contents else {
+        return Ok(());
+    };
+
+    let path = Path::new(PERSIST_PATH);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Returns the current session of the given player, if they are on the server.
+pub fn current(steamid64: u64) -> Option<Session> {
+    with_sessions(|sessions| {
+        sessions.current.get(&steamid64).map(|session| Session {
+            last_seen: now(),
+            ..*session
+        })
+    })
+    .flatten()
+}
+
+/// Returns the total playtime of the given player, in seconds: the sessions that ended, plus the current (or resumable) one.
+pub fn total_playtime(steamid64: u64) -> u64 {
+    let now = now();
+    with_sessions(|sessions| {
+        let ended = sessions.totals.get(&steamid64).copied().unwrap_or_default();
+        let ongoing = match sessions.current.get(&steamid64) {
+            Some(session) => Session {
+                last_seen: now,
+                ..*session
+            }
+            .playtime(),
+            None => sessions
+                .pending
+                .get(&steamid64)
+                .map_or(0, Session::playtime),
+        };
+        ended + ongoing
+    })
+    .unwrap_or_default()
+}
+
+/// Returns the current session of every player on the server.
+pub fn all() -> Vec<Session> {
+    let now = now();
+    with_sessions(|sessions| {
+        sessions
+            .current
+            .values()
+            .map(|session| Session {
+                last_seen: now,
+                ..*session
+            })
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Registers the `sessions` Lua library, exposing `GetCurrent(steamid64)`, `GetPlaytime(steamid64)`, `GetTotalPlaytime(steamid64)` and `GetAll()`.
+pub fn register(lua: State, libname: lua::LuaCStr) {
+    lua.register(
+        libname.as_ptr(),
+        crate::lua_regs![
+            "GetCurrent" => lua_get_current,
+            "GetPlaytime" => lua_get_playtime,
+            "GetTotalPlaytime" => lua_get_total_playtime,
+            "GetAll" => lua_get_all,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+}
+
+enum Record {
+    Session(Session),
+    /// SteamID64 and playtime of the sessions that ended.
+    Total(u64, u64),
+}
+
+fn parse(contents: &str) -> impl Iterator<Item = Record> + '_ {
+    contents.lines().filter_map(|line| {
+        if let Some(total) = line.strip_prefix("total ") {
+            let mut parts = total.split_ascii_whitespace().map(str::parse::<u64>);
+            return Some(Record::Total(parts.next()?.ok()?, parts.next()?.ok()?));
+        }
+        let mut parts = line.split_ascii_whitespace().map(str::parse::<u64>);
+        Some(Record::Session(Session {
+            steamid64: parts.next()?.ok()?,
+            joined_at: parts.next()?.ok()?,
+            last_seen: parts.next()?.ok()?,
+            // files written before `accumulated` existed don't have it
+            accumulated: parts.next().unwrap_or(Ok(0)).ok()?,
+        }))
+    })
+}
+
+/// Calls `ply:SteamID64()` on the player at the given (absolute) index.
+fn player_steamid64(lua: State, idx: i32) -> Option<u64> {
+    lua.get_field(idx, c"SteamID64");
+    lua.push_value(idx);
+    if lua.pcall(1, 1, 0).is_err() {
+        lua.pop();
+        return None;
+    }
+    let steamid64 = lua.get_string(-1).and_then(|s| s.parse().ok());
+    lua.pop();
+    steamid64
+}
+
+fn push_session(lua: State, session: &Session) {
+    lua.create_table(0, 4);
+    lua.push_string(&session.steamid64.to_string());
+    lua.set_field(-2, c"steamid64");
+    lua.push_number(session.joined_at);
+    lua.set_field(-2, c"joined");
+    lua.push_number(session.last_seen);
+    lua.set_field(-2, c"last_seen");
+    lua.push_number(session.playtime());
+    lua.set_field(-2, c"playtime");
+}
+
+fn check_steamid64(lua: State, arg: i32) -> Result<u64> {
+    let steamid64 = lua.check_string(arg)?;
+    steamid64
+        .parse()
+        .map_err(|_| anyhow::anyhow!(lua.err_argmsg(arg, "invalid SteamID64")))
+}
+
+extern "C-unwind" fn player_initial_spawn(lua: State) -> i32 {
+    let Some(steamid64) = player_steamid64(lua, 1) else {
+        return 0;
+    };
+    let now = now();
+    with_sessions(|sessions| {
+        let resumed = match sessions.pending.remove(&steamid64) {
+            Some(session) if Sessions::resumable(&session, now) => Some(session),
+            Some(session) => {
+                sessions.end(session);
+                None
+            }
+            None => None,
+        };
+        // a resumed session restarts now, carrying the playtime it had when the player left
+        let accumulated = resumed.as_ref().map_or(0, Session::playtime);
+        sessions.current.insert(
+            steamid64,
+            Session {
+                steamid64,
+                joined_at: now,
+                last_seen: now,
+                accumulated,
+            },
+        );
+    });
+    0
+}
+
+/// Keeps the session of the player that left as pending, ended where they left, so that it's resumed if they come back within `RESUME_GRACE` and counted in their total otherwise.
+extern "C-unwind" fn player_disconnected(lua: State) -> i32 {
+    if let Some(steamid64) = player_steamid64(lua, 1) {
+        let now = now();
+        with_sessions(|sessions| {
+            if let Some(session) = sessions.current.remove(&steamid64) {
+                sessions.pending.insert(
+                    steamid64,
+                    Session {
+                        last_seen: now,
+                        ..session
+                    },
+                );
+            }
+        });
+    }
+    0
+}
+
+extern "C-unwind" fn flush_timer(_lua: State) -> i32 {
+    if let Err(err) = flush() {
+        eprintln!("[gmod-rs] Failed to persist sessions: {err}");
+    }
+    0
+}
+
+extern "C-unwind" fn lua_get_current(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        match current(check_steamid64(lua, 1)?) {
+            Some(session) => push_session(lua, &session),
+            None => lua.push_nil(),
+        }
+        Ok(1)
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_get_playtime(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        match current(check_steamid64(lua, 1)?) {
+            Some(session) => lua.push_number(session.playtime()),
+            None => lua.push_nil(),
+        }
+        Ok(1)
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_get_total_playtime(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        lua.push_number(total_playtime(check_steamid64(lua, 1)?));
+        Ok(1)
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_get_all(lua: State) -> i32 {
+    let sessions = all();
+    lua.create_table(sessions.len() as i32, 0);
+    for (i, session) in sessions.iter().enumerate() {
+        push_session(lua, session);
+        lua.raw_seti(-2, i as i32 + 1);
+    }
+    1
+}
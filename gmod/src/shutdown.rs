@@ -0,0 +1,105 @@
+//! Stops background threads before the module unloads.
+//!
+//! A thread that's still running once `gmod13_close` returned runs code that no longer exists, and crashes the game. Threads spawned with `spawn` (or registered with `register_thread`) are waited for by `#[gmod13_close]`, after running the hooks registered with `on_shutdown`, which should tell them to stop. Threads can also poll `gmod::lifecycle::is_running`.
+//!
+//! Waiting is bounded by `set_timeout`, as a stuck thread shouldn't hang the game. Threads that didn't stop in time are reported on stderr, and left running.
+//!
+//! ## Example
+//!
+//! ```
+//! use std::sync::mpsc;
+//!
+//! let (queries, rx) = mpsc::channel::<String>();
+//! gmod::shutdown::spawn("database", move || {
+//!     // ends once every sender is dropped
+//!     for query in rx {
+//!         // ...
+//!     }
+//! });
+//! gmod::shutdown::on_shutdown("database", move || drop(queries));
+//!
+//! // what `#[gmod13_close]` does
+//! assert!(gmod::shutdown::run().is_empty());
+//! ```
+
+use std::{
+    sync::Mutex,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+type ShutdownHook = Box<dyn FnOnce() + Send>;
+
+static HOOKS: Mutex<Vec<(String, ShutdownHook)>> = Mutex::new(Vec::new());
+static THREADS: Mutex<Vec<(String, JoinHandle<()>)>> = Mutex::new(Vec::new());
+static TIMEOUT: Mutex<Duration> = Mutex::new(DEFAULT_TIMEOUT);
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sets how long `#[gmod13_close]` waits for every registered thread to stop, in total. Defaults to `DEFAULT_TIMEOUT`.
+pub fn set_timeout(timeout: Duration) {
+    *TIMEOUT.lock().unwrap_or_else(|e| e.into_inner()) = timeout;
+}
+
+pub fn timeout() -> Duration {
+    *TIMEOUT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Registers a hook run when the module unloads, before waiting for threads. Hooks run in the reverse order they were registered, and should only signal threads to stop rather than wait for them.
+pub fn on_shutdown<F>(name: &str, hook: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    HOOKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((name.to_owned(), Box::new(hook)));
+}
+
+/// Registers a thread to wait for when the module unloads.
+pub fn register_thread(name: &str, handle: JoinHandle<()>) {
+    THREADS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((name.to_owned(), handle));
+}
+
+/// Spawns a thread named `name`, waited for when the module unloads.
+pub fn spawn<F>(name: &str, f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let handle = thread::Builder::new()
+        .name(name.to_owned())
+        .spawn(f)
+        .expect("failed to spawn thread");
+    register_thread(name, handle);
+}
+
+/// Runs the shutdown hooks, then waits for the registered threads until the timeout. Returns the names of the threads that are still running. Called by `#[gmod13_close]`.
+pub fn run() -> Vec<String> {
+    let hooks = std::mem::take(&mut *HOOKS.lock().unwrap_or_else(|e| e.into_inner()));
+    for (name, hook) in hooks.into_iter().rev() {
+        if let Err(panic) = crate::panic::catch(hook) {
+            eprintln!("[gmod-rs] shutdown hook {name:?} {panic}");
+        }
+    }
+
+    let deadline = Instant::now() + timeout();
+    let mut threads = std::mem::take(&mut *THREADS.lock().unwrap_or_else(|e| e.into_inner()));
+    while !threads.is_empty() && Instant::now() < deadline {
+        threads.retain(|(_, handle)| !handle.is_finished());
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let mut stuck = Vec::new();
+    for (name, handle) in threads {
+        if handle.is_finished() {
+            let _ = handle.join();
+        } else {
+            eprintln!("[gmod-rs] thread {name:?} didn't stop within {:?}, the game may crash once the module unloads", timeout());
+            stuck.push(name);
+        }
+    }
+    stuck
+}
@@ -0,0 +1,239 @@
+//! Finding functions that aren't exported by the game's libraries, by their bytes.
+//!
+//! A signature is the first bytes of a function's code, written in hex, with `?` or `??` for the bytes that change between builds (addresses, offsets...). `find` scans the executable sections of a loaded library for the first match.
+//!
+//! The library is given by its path, e.g. the one `open_library!` returns, or just its file name. It must already be loaded: opening it with `open_library!` first takes care of that.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! let (engine, path) = gmod::open_library_srv!("engine")?;
+//! let function = gmod::sigscan::find(path, "55 8B EC 83 EC ?? 53 8B 5D 08")?.ok_or(anyhow!("signature not found"))?;
+//! ```
+
+use std::{ffi::c_void, path::Path};
+
+use anyhow::{bail, Result};
+
+/// A parsed signature, `None` being a wildcard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern(Vec<Option<u8>>);
+
+impl Pattern {
+    /// Parses a signature like `"55 8B EC ?? ?? 53"`.
+    pub fn parse(signature: &str) -> Result<Self> {
+        let bytes = signature
+            .split_whitespace()
+            .map(|byte| match byte {
+                "?" | "??" => Ok(None),
+                _ if byte.len() == 2 => match u8::from_str_radix(byte, 16) {
+                    Ok(byte) => Ok(Some(byte)),
+                    Err(_) => bail!("{byte} isn't a hex byte or a wildcard"),
+                },
+                _ => bail!("{byte} isn't a hex byte or a wildcard"),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !bytes.iter().any(Option::is_some) {
+            bail!("the signature has no bytes to match");
+        }
+        Ok(Self(bytes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn matches(&self, bytes: &[u8]) -> bool {
+        self.0
+            .iter()
+            .zip(bytes)
+            .all(|(pattern, byte)| pattern.is_none_or(|pattern| pattern == *byte))
+    }
+}
+
+/// Returns the offset of the first match of `pattern` in `haystack`.
+///
+/// ```
+/// use gmod::sigscan::{scan, Pattern};
+///
+/// let code = [0x90, 0x55, 0x8B, 0xEC, 0x83, 0xEC, 0x10, 0x53];
+/// assert_eq!(scan(&code, &Pattern::parse("55 8B EC 83 EC ?? 53").unwrap()), Some(1));
+/// assert_eq!(scan(&code, &Pattern::parse("55 8B EC 83 EC ?? 54").unwrap()), None);
+/// ```
+pub fn scan(haystack: &[u8], pattern: &Pattern) -> Option<usize> {
+    // the first byte that isn't a wildcard is looked for first, which is most of the work
+    let (skip, first) = pattern
+        .0
+        .iter()
+        .enumerate()
+        .find_map(|(i, byte)| Some((i, (*byte)?)))?;
+    let last_start = haystack.len().checked_sub(pattern.len())?;
+    haystack[skip..=last_start + skip]
+        .iter()
+        .enumerate()
+        .filter(|(_, byte)| **byte == first)
+        .map(|(start, _)| start)
+        .find(|&start| pattern.matches(&haystack[start..start + pattern.len()]))
+}
+
+/// Returns the address of the first match of `signature` in the code of the loaded library at `library`.
+///
+/// Fails if `signature` is invalid, see `Pattern::parse`.
+pub fn find(library: impl AsRef<Path>, signature: &str) -> Result<Option<*mut u8>> {
+    Ok(find_pattern(library, &Pattern::parse(signature)?))
+}
+
+/// Same as `find`, with a parsed signature.
+pub fn find_pattern(library: impl AsRef<Path>, pattern: &Pattern) -> Option<*mut u8> {
+    code_ranges(library.as_ref())
+        .into_iter()
+        .find_map(|(start, len)| {
+            let code = unsafe { std::slice::from_raw_parts(start as *const u8, len) };
+            scan(code, pattern).map(|offset| (start as *mut u8).wrapping_add(offset))
+        })
+}
+
+/// Returns whether the path a library was loaded from is `library`, or ends with it.
+#[cfg_attr(windows, allow(dead_code))]
+fn is_library(loaded: &str, library: &Path) -> bool {
+    let loaded = loaded.replace('\\', "/");
+    let library = library.to_string_lossy().replace('\\', "/");
+    let library = library.trim_start_matches("./");
+    loaded == library || loaded.ends_with(&format!("/{library}"))
+}
+
+/// The executable sections of the loaded library at `library`, as `(start, len)`.
+#[cfg(target_os = "linux")]
+fn code_ranges(library: &Path) -> Vec<(usize, usize)> {
+    struct Search<'a> {
+        library: &'a Path,
+        ranges: Vec<(usize, usize)>,
+    }
+
+    unsafe extern "C" fn callback(
+        info: *mut libc::dl_phdr_info,
+        _size: usize,
+        search: *mut c_void,
+    ) -> i32 {
+        let (info, search) = (&*info, &mut *(search as *mut Search));
+        if info.dlpi_name.is_null() {
+            return 0;
+        }
+        let name = std::ffi::CStr::from_ptr(info.dlpi_name).to_string_lossy();
+        if !is_library(&name, search.library) {
+            return 0;
+        }
+        let headers = std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize);
+        for header in headers {
+            if header.p_type == libc::PT_LOAD && header.p_flags & libc::PF_X != 0 {
+                search.ranges.push((
+                    info.dlpi_addr as usize + header.p_vaddr as usize,
+                    header.p_memsz as usize,
+                ));
+            }
+        }
+        1
+    }
+
+    let mut search = Search {
+        library,
+        ranges: Vec::new(),
+    };
+    unsafe { libc::dl_iterate_phdr(Some(callback), &mut search as *mut Search as *mut c_void) };
+    search.ranges
+}
+
+#[cfg(target_os = "macos")]
+fn code_ranges(library: &Path) -> Vec<(usize, usize)> {
+    const LC_SEGMENT_64: u32 = 0x19;
+    const VM_PROT_EXECUTE: i32 = 4;
+
+    extern "C" {
+        fn _dyld_image_count() -> u32;
+        fn _dyld_get_image_name(image: u32) -> *const std::ffi::c_char;
+        fn _dyld_get_image_header(image: u32) -> *const c_void;
+        fn _dyld_get_image_vmaddr_slide(image: u32) -> isize;
+    }
+
+    let mut ranges = Vec::new();
+    unsafe {
+        for image in 0.._dyld_image_count() {
+            let name = _dyld_get_image_name(image);
+            if name.is_null()
+                || !is_library(&std::ffi::CStr::from_ptr(name).to_string_lossy(), library)
+            {
+                continue;
+            }
+            let header = _dyld_get_image_header(image) as *const u8;
+            let slide = _dyld_get_image_vmaddr_slide(image);
+            // mach_header_64 is 32 bytes long, with the number of load commands at 16
+            let ncmds = (header.add(16) as *const u32).read_unaligned();
+            let mut command = header.add(32);
+            for _ in 0..ncmds {
+                let cmd = (command as *const u32).read_unaligned();
+                let cmdsize = (command.add(4) as *const u32).read_unaligned();
+                if cmd == LC_SEGMENT_64 {
+                    let vmaddr = (command.add(24) as *const u64).read_unaligned();
+                    let vmsize = (command.add(32) as *const u64).read_unaligned();
+                    let initprot = (command.add(60) as *const i32).read_unaligned();
+                    if initprot & VM_PROT_EXECUTE != 0 {
+                        ranges.push(((vmaddr as isize + slide) as usize, vmsize as usize));
+                    }
+                }
+                command = command.add(cmdsize as usize);
+            }
+            break;
+        }
+    }
+    ranges
+}
+
+#[cfg(windows)]
+fn code_ranges(library: &Path) -> Vec<(usize, usize)> {
+    use std::os::windows::ffi::OsStrExt;
+
+    const IMAGE_SCN_MEM_EXECUTE: u32 = 0x20000000;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetModuleHandleW(name: *const u16) -> *mut c_void;
+    }
+
+    // GetModuleHandleW only takes the file name of a library that's already loaded
+    let Some(file_name) = library.file_name() else {
+        return Vec::new();
+    };
+    let name: Vec<u16> = file_name.encode_wide().chain([0]).collect();
+    let base = unsafe { GetModuleHandleW(name.as_ptr()) } as *const u8;
+    if base.is_null() {
+        return Vec::new();
+    }
+
+    let mut ranges = Vec::new();
+    unsafe {
+        // IMAGE_DOS_HEADER::e_lfanew, then the "PE\0\0" signature and IMAGE_FILE_HEADER
+        let nt_headers = base.add((base.add(0x3C) as *const u32).read_unaligned() as usize);
+        let file_header = nt_headers.add(4);
+        let sections = (file_header.add(2) as *const u16).read_unaligned();
+        let optional_header_size = (file_header.add(16) as *const u16).read_unaligned();
+        let mut section = file_header.add(20 + optional_header_size as usize);
+        for _ in 0..sections {
+            // IMAGE_SECTION_HEADER
+            let virtual_size = (section.add(8) as *const u32).read_unaligned();
+            let virtual_address = (section.add(12) as *const u32).read_unaligned();
+            let characteristics = (section.add(36) as *const u32).read_unaligned();
+            if characteristics & IMAGE_SCN_MEM_EXECUTE != 0 {
+                ranges.push((
+                    base as usize + virtual_address as usize,
+                    virtual_size as usize,
+                ));
+            }
+            section = section.add(40);
+        }
+    }
+    ranges
+}
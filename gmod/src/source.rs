@@ -0,0 +1,166 @@
+//! Source engine interfaces, through the `CreateInterface` export of the game's libraries.
+//!
+//! `create_interface` opens the library with `open_library_srv!` (or `open_library!` for the client library), looks up its `CreateInterface`, and asks it for the first of the given versions it knows about. Both the libraries and the interfaces are cached, so looking an interface up again is cheap.
+//!
+//! The returned pointer is to a C++ object, whose methods are called through its vtable: declaring that is up to you, see `IVEngineServer` in the example.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use gmod::source::{self, Library};
+//!
+//! #[repr(C)]
+//! struct IVEngineServer {
+//!     vtable: *const *const std::ffi::c_void,
+//! }
+//!
+//! # let _ = || -> anyhow::Result<()> {
+//! let engine = source::create_interface::<IVEngineServer>(Library::Engine, ["VEngineServer023", "VEngineServer021"])?;
+//! # Ok(()) };
+//! ```
+
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_int, c_void, CString},
+    ptr::NonNull,
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, bail, Result};
+use libloading::Library as SharedLibrary;
+
+type CreateInterfaceFn =
+    unsafe extern "C" fn(name: *const c_char, return_code: *mut c_int) -> *mut c_void;
+
+/// The game's libraries that export `CreateInterface`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Library {
+    Engine,
+    Server,
+    Client,
+    FileSystem,
+    MaterialSystem,
+    VStdLib,
+    DataCache,
+    VPhysics,
+    LuaShared,
+}
+
+impl Library {
+    /// The name of the library, without prefix or extension.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Library::Engine => "engine",
+            Library::Server => "server",
+            Library::Client => "client",
+            Library::FileSystem => "filesystem_stdio",
+            Library::MaterialSystem => "materialsystem",
+            Library::VStdLib => "vstdlib",
+            Library::DataCache => "datacache",
+            Library::VPhysics => "vphysics",
+            Library::LuaShared => "lua_shared",
+        }
+    }
+
+    unsafe fn open(self) -> Result<(SharedLibrary, &'static str), crate::OpenGmodLibraryErrs> {
+        match self {
+            Library::Engine => crate::open_library_srv!("engine"),
+            Library::Server => crate::open_library_srv!("server"),
+            Library::Client => crate::open_library!("client"),
+            Library::FileSystem => crate::open_library_srv!("filesystem_stdio"),
+            Library::MaterialSystem => crate::open_library_srv!("materialsystem"),
+            Library::VStdLib => crate::open_library_srv!("vstdlib"),
+            Library::DataCache => crate::open_library_srv!("datacache"),
+            Library::VPhysics => crate::open_library_srv!("vphysics"),
+            Library::LuaShared => crate::open_library_srv!("lua_shared"),
+        }
+    }
+}
+
+/// The versions of an interface to try, newest first: a single version, or an array or slice of them.
+pub trait InterfaceVersions {
+    fn versions(&self) -> &[&str];
+}
+
+impl InterfaceVersions for &str {
+    fn versions(&self) -> &[&str] {
+        std::slice::from_ref(self)
+    }
+}
+
+impl InterfaceVersions for &[&str] {
+    fn versions(&self) -> &[&str] {
+        self
+    }
+}
+
+impl<const N: usize> InterfaceVersions for [&str; N] {
+    fn versions(&self) -> &[&str] {
+        self
+    }
+}
+
+/// The opened libraries, kept open for their `CreateInterface`.
+static LIBRARIES: Mutex<Vec<(Library, SharedLibrary, CreateInterfaceFn)>> = Mutex::new(Vec::new());
+/// The interfaces found, by library and version.
+static INTERFACES: Mutex<Option<HashMap<(Library, String), usize>>> = Mutex::new(None);
+
+fn create_interface_fn(library: Library) -> Result<CreateInterfaceFn> {
+    let mut libraries = LIBRARIES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, _, create_interface)) = libraries.iter().find(|(opened, ..)| *opened == library)
+    {
+        return Ok(*create_interface);
+    }
+
+    let (shared_library, path) = unsafe { library.open() }
+        .map_err(|err| anyhow!("couldn't open {}: {err}", library.name()))?;
+    let create_interface = unsafe { shared_library.get::<CreateInterfaceFn>(b"CreateInterface\0") }
+        .map_err(|err| anyhow!("{path} doesn't export CreateInterface: {err}"))?;
+    let create_interface = *create_interface;
+    libraries.push((library, shared_library, create_interface));
+    Ok(create_interface)
+}
+
+/// Returns the interface of `library` for the first of `versions` it has, e.g. `create_interface::<IVEngineServer>(Library::Engine, "VEngineServer021")`.
+///
+/// `T` is only used for the pointer's type: nothing checks it matches the interface.
+pub fn create_interface<T>(
+    library: Library,
+    versions: impl InterfaceVersions,
+) -> Result<NonNull<T>> {
+    let versions = versions.versions();
+    {
+        let interfaces = INTERFACES.lock().unwrap_or_else(|e| e.into_inner());
+        let cached = versions.iter().find_map(|version| {
+            interfaces
+                .as_ref()?
+                .get(&(library, version.to_string()))
+                .copied()
+        });
+        if let Some(interface) = cached {
+            return Ok(NonNull::new(interface as *mut T).expect("cached interfaces aren't null"));
+        }
+    }
+
+    let create_interface = create_interface_fn(library)?;
+    for &version in versions {
+        let Ok(name) = CString::new(version) else {
+            continue;
+        };
+        let mut return_code = 0;
+        let interface = unsafe { create_interface(name.as_ptr(), &mut return_code) };
+        if let Some(interface) = NonNull::new(interface) {
+            INTERFACES
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get_or_insert_with(HashMap::new)
+                .insert((library, version.to_owned()), interface.as_ptr() as usize);
+            return Ok(interface.cast());
+        }
+    }
+    bail!(
+        "{} has none of the interfaces {}",
+        library.name(),
+        versions.join(", ")
+    )
+}
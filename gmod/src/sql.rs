@@ -0,0 +1,524 @@
+//! Queries to the game's SQLite database (`sv.db` on servers, `cl.db` on clients) through GMod's `sql` library, with rows decoded into Rust types with serde. Enabled by the `sql` feature.
+//!
+//! Parameters are written as `?` in the query. They're bound by `sql.QueryTyped` where the game has it, and otherwise escaped like `sql.SQLStr` does and inlined into a `sql.Query`. Either way, values are never interpreted as SQL.
+//!
+//! `sql.Query` returns every value as a string, so decoding accepts numbers and booleans written as strings, e.g. `"42"` for a `u32` field.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::sql;
+//!
+//! #[derive(serde::Deserialize)]
+//! struct Ban {
+//!     steamid: String,
+//!     reason: Option<String>,
+//!     expires: i64,
+//! }
+//!
+//! sql::execute(lua, "CREATE TABLE IF NOT EXISTS bans (steamid TEXT PRIMARY KEY, reason TEXT, expires INTEGER)", &[])?;
+//! sql::execute(lua, "INSERT OR REPLACE INTO bans VALUES (?, ?, ?)", &[&steamid, &reason, &expires])?;
+//!
+//! let bans: Vec<Ban> = sql::query_as(lua, "SELECT * FROM bans WHERE expires > ?", &[&now])?;
+//! ```
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{
+    de::{
+        self,
+        value::{Error as DeError, MapDeserializer},
+        DeserializeOwned, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any,
+};
+
+use crate::lua::{LuaCStr, State, LUA_TBOOLEAN, LUA_TNUMBER, LUA_TSTRING};
+
+/// A value of a column, or a parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl SqlValue {
+    /// Returns the value as a literal that can be inlined into a query.
+    pub fn to_literal(&self) -> String {
+        match self {
+            SqlValue::Null => "NULL".to_owned(),
+            SqlValue::Integer(int) => int.to_string(),
+            SqlValue::Real(real) if real.is_finite() => format!("{real:?}"),
+            SqlValue::Real(_) => "NULL".to_owned(),
+            SqlValue::Text(text) => escape(text),
+            SqlValue::Blob(blob) => {
+                let mut literal = String::with_capacity(blob.len() * 2 + 3);
+                literal.push_str("X'");
+                for byte in blob {
+                    literal.push_str(&format!("{byte:02X}"));
+                }
+                literal.push('\'');
+                literal
+            }
+        }
+    }
+
+    fn push(&self, lua: State) {
+        match self {
+            SqlValue::Null => lua.push_nil(),
+            SqlValue::Integer(int) => lua.push_number(*int),
+            SqlValue::Real(real) => lua.push_number(*real),
+            SqlValue::Text(text) => lua.push_string(text),
+            SqlValue::Blob(blob) => lua.push_binary_string(blob),
+        }
+    }
+
+    fn read(lua: State, index: i32) -> Self {
+        match lua.lua_type(index) {
+            LUA_TNUMBER => {
+                let number = lua.to_number(index);
+                if number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+                    SqlValue::Integer(number as i64)
+                } else {
+                    SqlValue::Real(number)
+                }
+            }
+            LUA_TBOOLEAN => SqlValue::Integer(lua.get_boolean(index) as i64),
+            LUA_TSTRING => {
+                let bytes = lua.get_binary_string(index).unwrap_or_default();
+                match std::str::from_utf8(bytes) {
+                    Ok(text) => SqlValue::Text(text.to_owned()),
+                    Err(_) => SqlValue::Blob(bytes.to_vec()),
+                }
+            }
+            _ => SqlValue::Null,
+        }
+    }
+}
+
+/// Values that can be passed as query parameters.
+pub trait ToSql {
+    fn to_sql(&self) -> SqlValue;
+}
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> SqlValue {
+        self.clone()
+    }
+}
+
+impl ToSql for String {
+    fn to_sql(&self) -> SqlValue {
+        SqlValue::Text(self.clone())
+    }
+}
+
+impl ToSql for &str {
+    fn to_sql(&self) -> SqlValue {
+        SqlValue::Text((*self).to_owned())
+    }
+}
+
+impl ToSql for &[u8] {
+    fn to_sql(&self) -> SqlValue {
+        SqlValue::Blob(self.to_vec())
+    }
+}
+
+impl ToSql for Vec<u8> {
+    fn to_sql(&self) -> SqlValue {
+        SqlValue::Blob(self.clone())
+    }
+}
+
+impl ToSql for bool {
+    fn to_sql(&self) -> SqlValue {
+        SqlValue::Integer(*self as i64)
+    }
+}
+
+impl ToSql for f32 {
+    fn to_sql(&self) -> SqlValue {
+        SqlValue::Real(*self as f64)
+    }
+}
+
+impl ToSql for f64 {
+    fn to_sql(&self) -> SqlValue {
+        SqlValue::Real(*self)
+    }
+}
+
+macro_rules! impl_to_sql_integer {
+    ($($ty:ty),+) => {
+        $(
+            impl ToSql for $ty {
+                fn to_sql(&self) -> SqlValue {
+                    match i64::try_from(*self) {
+                        Ok(int) => SqlValue::Integer(int),
+                        // SQLite has no unsigned 64-bit integers
+                        Err(_) => SqlValue::Text(self.to_string()),
+                    }
+                }
+            }
+        )+
+    };
+}
+impl_to_sql_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn to_sql(&self) -> SqlValue {
+        match self {
+            Some(value) => value.to_sql(),
+            None => SqlValue::Null,
+        }
+    }
+}
+
+/// Quotes a string like `sql.SQLStr`: wrapped in single quotes, which are doubled. SQLite strings can't contain NUL, so the string is cut at the first one.
+pub fn escape(str: &str) -> String {
+    let str = str.split('\0').next().unwrap_or_default();
+    format!("'{}'", str.replace('\'', "''"))
+}
+
+/// Replaces every `?` outside of string literals and quoted identifiers with the literal of the next parameter.
+fn inline_params(query: &str, params: &[&dyn ToSql]) -> Result<String> {
+    let mut inlined = String::with_capacity(query.len());
+    let mut params = params.iter();
+    let mut quote = None;
+    for c in query.chars() {
+        match (quote, c) {
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '?') => {
+                let Some(param) = params.next() else {
+                    bail!("the query has more parameters than were given");
+                };
+                inlined.push_str(&param.to_sql().to_literal());
+                continue;
+            }
+            _ => {}
+        }
+        inlined.push(c);
+    }
+    if params.next().is_some() {
+        bail!("the query has fewer parameters than were given");
+    }
+    Ok(inlined)
+}
+
+/// A row returned by a query, by column name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Row {
+    columns: HashMap<String, SqlValue>,
+}
+
+impl Row {
+    /// Returns the value of a column, or `None` if the row doesn't have it.
+    pub fn get(&self, column: &str) -> Option<&SqlValue> {
+        self.columns.get(column)
+    }
+
+    /// Decodes the value of a column, missing columns being `NULL`.
+    pub fn get_as<T: DeserializeOwned>(&self, column: &str) -> Result<T> {
+        let value = self.columns.get(column).cloned().unwrap_or(SqlValue::Null);
+        T::deserialize(ValueDeserializer(value)).map_err(|err| anyhow!("column {column}: {err}"))
+    }
+
+    /// Decodes the row into a struct (or map) with a field per column.
+    pub fn decode<T: DeserializeOwned>(self) -> Result<T> {
+        let map = MapDeserializer::new(
+            self.columns
+                .into_iter()
+                .map(|(k, v)| (k, ValueDeserializer(v))),
+        );
+        Ok(T::deserialize(map)?)
+    }
+
+    pub fn into_map(self) -> HashMap<String, SqlValue> {
+        self.columns
+    }
+}
+
+/// Calls `sql[name]`, leaving 1 result on the stack.
+fn call_sql(lua: State, name: LuaCStr, push_args: impl FnOnce() -> i32) -> Result<()> {
+    lua.get_global(c"sql");
+    if !lua.is_table(-1) {
+        lua.pop();
+        bail!("the sql library isn't available");
+    }
+    lua.get_field(-1, name);
+    unsafe { lua.remove(-2) };
+    if !lua.is_function(-1) {
+        lua.pop();
+        bail!("sql.{} isn't available", name.to_string_lossy());
+    }
+    let nargs = push_args();
+    if let Err(err) = lua.pcall(nargs, 1, 0) {
+        lua.pop();
+        bail!("sql.{} failed: {err}", name.to_string_lossy());
+    }
+    Ok(())
+}
+
+fn last_error(lua: State) -> String {
+    let top = lua.get_top();
+    let err = match call_sql(lua, c"LastError", || 0) {
+        Ok(()) => lua.get_string(-1).map(|err| err.into_owned()),
+        Err(_) => None,
+    };
+    lua.set_top(top);
+    err.unwrap_or_else(|| "unknown error".to_owned())
+}
+
+fn has_query_typed(lua: State) -> bool {
+    lua.get_global(c"sql");
+    let has = lua.is_table(-1) && {
+        lua.get_field(-1, c"QueryTyped");
+        let has = lua.is_function(-1);
+        lua.pop();
+        has
+    };
+    lua.pop();
+    has
+}
+
+/// Runs a query, returning its rows.
+pub fn query(lua: State, query: &str, params: &[&dyn ToSql]) -> Result<Vec<Row>> {
+    let top = lua.get_top();
+    let result = run_query(lua, query, params);
+    lua.set_top(top);
+    result
+}
+
+fn run_query(lua: State, query: &str, params: &[&dyn ToSql]) -> Result<Vec<Row>> {
+    lua.ensure_stack(params.len() as i32 + 2)?;
+    if has_query_typed(lua) {
+        call_sql(lua, c"QueryTyped", || {
+            lua.push_string(query);
+            for param in params {
+                param.to_sql().push(lua);
+            }
+            params.len() as i32 + 1
+        })?;
+    } else {
+        let query = inline_params(query, params)?;
+        call_sql(lua, c"Query", || {
+            lua.push_string(&query);
+            1
+        })?;
+    }
+
+    // false on errors, nil (`sql.Query`) or an empty table when there are no rows
+    if lua.is_boolean(-1) && !lua.get_boolean(-1) {
+        bail!("{} (in {query})", last_error(lua));
+    }
+    if !lua.is_table(-1) {
+        return Ok(Vec::new());
+    }
+
+    let mut rows = Vec::with_capacity(lua.len(-1) as usize);
+    for i in 1..=lua.len(-1) {
+        lua.raw_geti(-1, i);
+        let mut row = Row::default();
+        if lua.is_table(-1) {
+            lua.push_nil();
+            while unsafe { lua.next(-2) } != 0 {
+                if let Some(column) = lua.get_string(-2) {
+                    row.columns
+                        .insert(column.into_owned(), SqlValue::read(lua, -1));
+                }
+                lua.pop();
+            }
+        }
+        lua.pop();
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Runs a query and decodes its rows, see `Row::decode`.
+pub fn query_as<T: DeserializeOwned>(
+    lua: State,
+    query: &str,
+    params: &[&dyn ToSql],
+) -> Result<Vec<T>> {
+    self::query(lua, query, params)?
+        .into_iter()
+        .map(Row::decode)
+        .collect()
+}
+
+/// Runs a query and decodes its first row, if any.
+pub fn query_row_as<T: DeserializeOwned>(
+    lua: State,
+    query: &str,
+    params: &[&dyn ToSql],
+) -> Result<Option<T>> {
+    self::query(lua, query, params)?
+        .into_iter()
+        .next()
+        .map(Row::decode)
+        .transpose()
+}
+
+/// Runs a query, ignoring the rows it returns.
+pub fn execute(lua: State, query: &str, params: &[&dyn ToSql]) -> Result<()> {
+    self::query(lua, query, params).map(drop)
+}
+
+/// Runs `f` in a transaction, which is committed if it returns `Ok` and rolled back otherwise.
+pub fn transaction<R>(lua: State, f: impl FnOnce() -> Result<R>) -> Result<R> {
+    execute(lua, "BEGIN", &[])?;
+    match f() {
+        Ok(result) => {
+            execute(lua, "COMMIT", &[])?;
+            Ok(result)
+        }
+        Err(err) => {
+            let _ = execute(lua, "ROLLBACK", &[]);
+            Err(err)
+        }
+    }
+}
+
+/// Deserializes a column, accepting numbers and booleans written as text.
+struct ValueDeserializer(SqlValue);
+
+impl<'de> IntoDeserializer<'de, DeError> for ValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl ValueDeserializer {
+    fn invalid(&self, expected: &str) -> DeError {
+        de::Error::custom(format_args!("expected {expected}, got {:?}", self.0))
+    }
+
+    fn integer(&self) -> Result<i128, DeError> {
+        match &self.0 {
+            SqlValue::Integer(int) => Ok(*int as i128),
+            SqlValue::Real(real) if real.fract() == 0.0 => Ok(*real as i128),
+            SqlValue::Text(text) => text.trim().parse().map_err(|_| self.invalid("an integer")),
+            _ => Err(self.invalid("an integer")),
+        }
+    }
+
+    fn real(&self) -> Result<f64, DeError> {
+        match &self.0 {
+            SqlValue::Integer(int) => Ok(*int as f64),
+            SqlValue::Real(real) => Ok(*real),
+            SqlValue::Text(text) => text.trim().parse().map_err(|_| self.invalid("a number")),
+            _ => Err(self.invalid("a number")),
+        }
+    }
+}
+
+macro_rules! deserialize_integer {
+    ($($method:ident => $visit:ident: $ty:ty),+) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+                let int = self.integer()?;
+                match <$ty>::try_from(int) {
+                    Ok(int) => visitor.$visit(int),
+                    Err(_) => Err(de::Error::custom(format_args!("{int} is out of range for {}", stringify!($ty)))),
+                }
+            }
+        )+
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.0 {
+            SqlValue::Null => visitor.visit_unit(),
+            SqlValue::Integer(int) => visitor.visit_i64(int),
+            SqlValue::Real(real) => visitor.visit_f64(real),
+            SqlValue::Text(text) => visitor.visit_string(text),
+            SqlValue::Blob(blob) => visitor.visit_byte_buf(blob),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.0 {
+            SqlValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match &self.0 {
+            SqlValue::Text(text) if text.eq_ignore_ascii_case("true") => visitor.visit_bool(true),
+            SqlValue::Text(text) if text.eq_ignore_ascii_case("false") => visitor.visit_bool(false),
+            _ => match self.integer() {
+                Ok(int) => visitor.visit_bool(int != 0),
+                Err(_) => Err(self.invalid("a boolean")),
+            },
+        }
+    }
+
+    deserialize_integer!(
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64
+    );
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_f32(self.real()? as f32)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_f64(self.real()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.0 {
+            SqlValue::Integer(int) => visitor.visit_string(int.to_string()),
+            SqlValue::Real(real) => visitor.visit_string(real.to_string()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        match self.0 {
+            SqlValue::Text(text) => visitor.visit_enum(text.into_deserializer()),
+            _ => Err(self.invalid("the name of a variant")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
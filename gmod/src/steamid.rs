@@ -0,0 +1,205 @@
+//! Steam IDs of players, converted between their formats.
+//!
+//! | Format | Example | In Lua |
+//! |--------|---------|--------|
+//! | SteamID | `STEAM_0:1:4491990` | `ply:SteamID()` |
+//! | SteamID64 | `76561197969249709` | `ply:SteamID64()` |
+//! | SteamID3 | `[U:1:8983981]` | |
+//! | Account ID | `8983981` | `ply:AccountID()` |
+//!
+//! Only the IDs of individual accounts in the public universe are handled, which is what players have. `SteamId::parse` takes any of the formats, as do the Lua functions of `register` and `#[lua_function]` arguments of type `SteamId`.
+//!
+//! ## Example
+//!
+//! ```
+//! use gmod::steamid::SteamId;
+//!
+//! let id = SteamId::parse("STEAM_0:1:4491990").unwrap();
+//! assert_eq!(id.steamid64(), 76561197969249709);
+//! assert_eq!(id.steamid3(), "[U:1:8983981]");
+//! assert_eq!(SteamId::parse("[U:1:8983981]").unwrap(), id);
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, Result};
+
+use crate::lua::{self, HandleLuaFunctionReturn, LuaCheck, LuaPush, LuaReg, State};
+
+/// The SteamID64 of account ID 0: individual account, public universe, desktop instance.
+const STEAMID64_BASE: u64 = 76561197960265728;
+
+/// The Steam ID of a player, stored as its account ID. Displayed as a SteamID64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SteamId(u32);
+
+impl SteamId {
+    pub fn from_account_id(account_id: u32) -> Self {
+        Self(account_id)
+    }
+
+    /// Returns `None` if it's not the SteamID64 of an individual account in the public universe.
+    pub fn from_steamid64(steamid64: u64) -> Option<Self> {
+        let account_id = steamid64.checked_sub(STEAMID64_BASE)?;
+        u32::try_from(account_id).ok().map(Self)
+    }
+
+    /// Parses a SteamID (`STEAM_0:1:4491990`, also with universe 1), a SteamID64 (`76561197969249709`), or a SteamID3 (`[U:1:8983981]`, also without brackets).
+    pub fn parse(str: &str) -> Result<Self> {
+        let str = str.trim();
+        let invalid = || anyhow!("{str:?} isn't a valid Steam ID");
+
+        if let Some(rest) = str.strip_prefix("STEAM_") {
+            let mut parts = rest.split(':');
+            let (Some(universe), Some(y), Some(z), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(invalid());
+            };
+            // older games write the public universe as 0
+            if !matches!(universe, "0" | "1") || !matches!(y, "0" | "1") {
+                return Err(invalid());
+            }
+            let z: u32 = z.parse().map_err(|_| invalid())?;
+            let account_id = z
+                .checked_mul(2)
+                .and_then(|z| z.checked_add(y.parse().unwrap()))
+                .ok_or_else(invalid)?;
+            return Ok(Self(account_id));
+        }
+
+        let steamid3 = str
+            .strip_prefix('[')
+            .and_then(|str| str.strip_suffix(']'))
+            .unwrap_or(str);
+        if let Some(account_id) = steamid3.strip_prefix("U:1:") {
+            return account_id.parse().map(Self).map_err(|_| invalid());
+        }
+
+        if !str.is_empty() && str.bytes().all(|b| b.is_ascii_digit()) {
+            if let Some(id) = str.parse().ok().and_then(Self::from_steamid64) {
+                return Ok(id);
+            }
+        }
+        Err(invalid())
+    }
+
+    pub fn account_id(self) -> u32 {
+        self.0
+    }
+
+    pub fn steamid64(self) -> u64 {
+        STEAMID64_BASE + self.0 as u64
+    }
+
+    /// Returns the ID as `STEAM_0:Y:Z`, like `ply:SteamID()`.
+    pub fn steamid(self) -> String {
+        format!("STEAM_0:{}:{}", self.0 & 1, self.0 >> 1)
+    }
+
+    /// Returns the ID as `[U:1:account ID]`.
+    pub fn steamid3(self) -> String {
+        format!("[U:1:{}]", self.0)
+    }
+
+    /// Returns the URL of the account's Steam community profile.
+    pub fn profile_url(self) -> String {
+        format!("https://steamcommunity.com/profiles/{}", self.steamid64())
+    }
+}
+
+impl fmt::Display for SteamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.steamid64())
+    }
+}
+
+impl FromStr for SteamId {
+    type Err = anyhow::Error;
+
+    fn from_str(str: &str) -> Result<Self> {
+        Self::parse(str)
+    }
+}
+
+/// Takes a string in any format. Numbers aren't taken, as a SteamID64 doesn't fit in a Lua number.
+impl LuaCheck for SteamId {
+    fn lua_check(l: State, arg: i32) -> Result<Self> {
+        let str = l.check_string(arg)?;
+        SteamId::parse(&str).map_err(|_| anyhow!(l.err_argmsg(arg, "invalid Steam ID")))
+    }
+}
+
+/// Pushed as a SteamID64 string, like `ply:SteamID64()`.
+impl LuaPush for SteamId {
+    fn lua_push(self, l: State) {
+        l.push_string(&self.steamid64().to_string());
+    }
+}
+
+/// Registers the conversion functions into a global table named `libname`. They take a Steam ID in any format, and raise an error if it's invalid:
+///
+/// - `To64(id)` returns the SteamID64, as a string
+/// - `ToSteamID(id)` returns the `STEAM_0:Y:Z` form
+/// - `ToSteamID3(id)` returns the `[U:1:account ID]` form
+/// - `ToAccountID(id)` returns the account ID
+/// - `FromAccountID(account_id)` returns the SteamID64, as a string
+/// - `IsValid(id)` returns whether `id` is a Steam ID in any format, without raising errors
+pub fn register(lua: State, libname: lua::LuaCStr) {
+    lua.register(
+        libname.as_ptr(),
+        crate::lua_regs![
+            "To64" => lua_to_64,
+            "ToSteamID" => lua_to_steamid,
+            "ToSteamID3" => lua_to_steamid3,
+            "ToAccountID" => lua_to_account_id,
+            "FromAccountID" => lua_from_account_id,
+            "IsValid" => lua_is_valid,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+}
+
+/// Checks the Steam ID at argument 1, and pushes what `f` returns for it.
+fn convert<T: LuaPush>(lua: State, f: impl FnOnce(SteamId) -> T) -> i32 {
+    SteamId::lua_check(lua, 1)
+        .map(|id| {
+            f(id).lua_push(lua);
+            1
+        })
+        .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_to_64(lua: State) -> i32 {
+    convert(lua, |id| id)
+}
+
+extern "C-unwind" fn lua_to_steamid(lua: State) -> i32 {
+    convert(lua, SteamId::steamid)
+}
+
+extern "C-unwind" fn lua_to_steamid3(lua: State) -> i32 {
+    convert(lua, SteamId::steamid3)
+}
+
+extern "C-unwind" fn lua_to_account_id(lua: State) -> i32 {
+    convert(lua, SteamId::account_id)
+}
+
+extern "C-unwind" fn lua_from_account_id(lua: State) -> i32 {
+    u32::lua_check(lua, 1)
+        .map(|account_id| {
+            SteamId::from_account_id(account_id).lua_push(lua);
+            1
+        })
+        .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_is_valid(lua: State) -> i32 {
+    let valid = lua
+        .get_string(1)
+        .is_some_and(|str| SteamId::parse(&str).is_ok());
+    lua.push_boolean(valid);
+    1
+}
@@ -0,0 +1,271 @@
+//! Template rendering with [minijinja](https://docs.rs/minijinja), for generated text like MOTDs, HTML panels and config files.
+//!
+//! With the `sanitize` feature, the `safe_url` filter (`<a href="{{ link | safe_url }}">`) drops URLs that aren't plain `http`/`https` links to public hosts.
+//!
+//! Templates are HTML-escaped when their name ends with `.html`, `.htm` or `.xml`, and when rendered inline (`render_str`), so values pulled from players can't inject markup. Other named templates (e.g. `server.cfg`) are rendered as-is.
+
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, RwLock},
+};
+
+use anyhow::{bail, Result};
+use minijinja::{AutoEscape, Environment, Value};
+use serde::Serialize;
+
+use crate::lua::{
+    self, task_queue, HandleLuaFunctionReturn, LuaRef, LuaReg, StackIndex, State, LUA_TBOOLEAN,
+    LUA_TNIL, LUA_TNONE, LUA_TNUMBER, LUA_TSTRING, LUA_TTABLE,
+};
+
+/// How deeply Lua tables can be nested when converted, which also guards against cyclic tables.
+const MAX_DEPTH: usize = 64;
+
+static ENV: LazyLock<RwLock<Environment<'static>>> = LazyLock::new(|| {
+    let mut env = Environment::new();
+    env.set_auto_escape_callback(|name| {
+        let html = name == "<string>"
+            || [".html", ".htm", ".xml"]
+                .iter()
+                .any(|ext| name.ends_with(ext));
+        if html {
+            AutoEscape::Html
+        } else {
+            AutoEscape::None
+        }
+    });
+    #[cfg(feature = "sanitize")]
+    env.add_filter("safe_url", safe_url);
+    RwLock::new(env)
+});
+
+/// The `safe_url` filter: passes URLs accepted by `sanitize::validate_url` with the default policy through, and replaces any other with `about:blank`, so player-provided links can be put in `href`s.
+#[cfg(feature = "sanitize")]
+fn safe_url(url: &str) -> String {
+    use crate::sanitize::{validate_url, UrlPolicy};
+
+    match validate_url(url, &UrlPolicy::default()) {
+        Ok(url) => url.into(),
+        Err(_) => "about:blank".to_string(),
+    }
+}
+
+/// Adds (or replaces) a named template, which can then be rendered with `render` and included or extended by other templates.
+pub fn add_template(name: &str, source: &str) -> Result<()> {
+    ENV.write()
+        .unwrap_or_else(|e| e.into_inner())
+        .add_template_owned(name.to_string(), source.to_string())?;
+    Ok(())
+}
+
+/// Removes a named template.
+pub fn remove_template(name: &str) {
+    ENV.write()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove_template(name);
+}
+
+/// Renders a named template added with `add_template`.
+pub fn render<S: Serialize>(name: &str, ctx: S) -> Result<String> {
+    let env = ENV.read().unwrap_or_else(|e| e.into_inner());
+    Ok(env.get_template(name)?.render(ctx)?)
+}
+
+/// Renders an inline template. The output is HTML-escaped.
+///
+/// ## Example
+///
+/// ```
+/// let html = gmod::template::render_str(
+///     "<h1>{{ hostname }}</h1>{% for rule in rules %}<li>{{ rule }}</li>{% endfor %}",
+///     minijinja::context! { hostname => "My <Server>", rules => vec!["Be nice"] },
+/// ).unwrap();
+///
+/// assert_eq!(html, "<h1>My &lt;Server&gt;</h1><li>Be nice</li>");
+/// ```
+pub fn render_str<S: Serialize>(source: &str, ctx: S) -> Result<String> {
+    let env = ENV.read().unwrap_or_else(|e| e.into_inner());
+    Ok(env.render_str(source, ctx)?)
+}
+
+/// What `render_async` renders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A named template added with `add_template`.
+    Named(String),
+    /// An inline template.
+    Inline(String),
+}
+
+/// Renders a template on a background thread, then calls `callback` on the Lua thread with the output. Useful for large outputs.
+///
+/// The context must already be converted to a `Value`, e.g. with `Value::from_serialize` or `value_from_lua`.
+pub fn render_async<F>(source: Source, ctx: Value, callback: F)
+where
+    F: FnOnce(State, Result<String>) + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let output = match &source {
+            Source::Named(name) => render(name, ctx),
+            Source::Inline(source) => render_str(source, ctx),
+        };
+        let _ = task_queue::wait_lua_tick(String::new(), move |l| callback(l, output));
+    });
+}
+
+/// Converts the Lua value at the given stack index into a template value.
+///
+/// Tables with consecutive integer keys starting at 1 become sequences, other tables become maps (keys are converted to strings). Values that have no template equivalent (functions, entities, ...) are converted with `tostring`.
+pub fn value_from_lua(lua: State, index: impl Into<StackIndex>) -> Result<Value> {
+    convert(lua, index.into().abs(lua).0, 0)
+}
+
+fn convert(lua: State, index: i32, depth: usize) -> Result<Value> {
+    Ok(match lua.lua_type(index) {
+        LUA_TNIL | LUA_TNONE => Value::from(()),
+        LUA_TBOOLEAN => Value::from(lua.get_boolean(index)),
+        LUA_TNUMBER => {
+            let n = lua.to_number(index);
+            if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+                Value::from(n as i64)
+            } else {
+                Value::from(n)
+            }
+        }
+        LUA_TSTRING => Value::from(lua.get_string(index).unwrap_or_default().into_owned()),
+        LUA_TTABLE => {
+            if depth >= MAX_DEPTH {
+                bail!("table is nested too deeply (or contains itself)");
+            }
+
+            let mut entries = Vec::new();
+            lua.push_nil();
+            while unsafe { lua.next(index) } != 0 {
+                let top = lua.get_top();
+                // reading a number key with get_string would convert it in place and break `next`
+                let key = match lua.lua_type(top - 1) {
+                    LUA_TNUMBER => Err(lua.to_number(top - 1)),
+                    _ => Ok(lua.get_string(top - 1).unwrap_or_default().into_owned()),
+                };
+                let value = convert(lua, top, depth + 1);
+                lua.pop();
+                match value {
+                    Ok(value) => entries.push((key, value)),
+                    Err(err) => {
+                        lua.pop();
+                        return Err(err);
+                    }
+                }
+            }
+
+            let is_sequence = entries.iter().all(|(key, _)| {
+                matches!(key, Err(n) if n.fract() == 0.0 && *n >= 1.0 && *n <= entries.len() as f64)
+            });
+
+            if is_sequence {
+                let mut values = vec![Value::from(()); entries.len()];
+                for (key, value) in entries {
+                    if let Err(n) = key {
+                        values[n as usize - 1] = value;
+                    }
+                }
+                Value::from(values)
+            } else {
+                let map: BTreeMap<String, Value> = entries
+                    .into_iter()
+                    .map(|(key, value)| (key.unwrap_or_else(|n| n.to_string()), value))
+                    .collect();
+                Value::from(map)
+            }
+        }
+        _ => {
+            lua.get_global(c"tostring");
+            lua.push_value(index);
+            if lua.pcall(1, 1, 0).is_err() {
+                lua.pop();
+                Value::from(())
+            } else {
+                let value = lua.get_string(-1).unwrap_or_default().into_owned();
+                lua.pop();
+                Value::from(value)
+            }
+        }
+    })
+}
+
+/// Registers the template functions into a global table named `libname`:
+///
+/// - `AddTemplate(name, source)`
+/// - `RenderTemplate(source, data[, callback])` renders an inline template
+/// - `RenderNamedTemplate(name, data[, callback])` renders a template added with `AddTemplate`
+///
+/// Without a callback, the rendered string is returned and errors are raised. With a callback, rendering happens on a background thread, and the callback is called with `(output, error)`.
+pub fn register(lua: State, libname: lua::LuaCStr) {
+    lua.register(
+        libname.as_ptr(),
+        crate::lua_regs![
+            "AddTemplate" => lua_add_template,
+            "RenderTemplate" => lua_render_inline,
+            "RenderNamedTemplate" => lua_render_named,
+        ]
+        .as_ptr(),
+    );
+    lua.pop();
+}
+
+extern "C-unwind" fn lua_add_template(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let name = lua.check_string(1)?;
+        let source = lua.check_string(2)?;
+        add_template(&name, &source)?;
+        Ok(0)
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_render_inline(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let source = Source::Inline(lua.check_string(1)?.into_owned());
+        lua_render(lua, source)
+    })()
+    .handle_result(lua)
+}
+
+extern "C-unwind" fn lua_render_named(lua: State) -> i32 {
+    (|| -> Result<i32> {
+        let source = Source::Named(lua.check_string(1)?.into_owned());
+        lua_render(lua, source)
+    })()
+    .handle_result(lua)
+}
+
+fn lua_render(lua: State, source: Source) -> Result<i32> {
+    let ctx = value_from_lua(lua, 2)?;
+
+    if lua.is_none_or_nil(3) {
+        let output = match &source {
+            Source::Named(name) => render(name, ctx)?,
+            Source::Inline(source) => render_str(source, ctx)?,
+        };
+        lua.push_string(&output);
+        return Ok(1);
+    }
+
+    lua.check_function(3)?;
+    let callback = LuaRef::from_index(lua, 3);
+    render_async(source, ctx, move |l, output| {
+        callback.push(l);
+        match output {
+            Ok(output) => {
+                l.push_string(&output);
+                l.push_nil();
+            }
+            Err(err) => {
+                l.push_nil();
+                l.push_string(&format!("{err:#}"));
+            }
+        }
+        l.pcall_ignore(2, 0);
+    });
+    Ok(0)
+}
@@ -0,0 +1,172 @@
+//! Standalone Lua states for testing Lua-facing code with `cargo test`, without the game. Enabled by the `testing` feature.
+//!
+//! A `TestState` is a fresh LuaJIT state with the standard libraries, and stand-ins for the GMod globals most code relies on: `timer`, `hook`, `util.AddNetworkString`, `CurTime`, `ErrorNoHalt`, `ErrorNoHaltWithStack`, `PrintTable`, `IsValid`... Time only moves when `tick` is called, which runs the timers that are due (including the task queue's) and the `Tick` and `Think` hooks.
+//!
+//! ## Finding LuaJIT
+//!
+//! The game's lua_shared isn't available outside of it, so the LuaJIT library is loaded from the path in the `GMOD_RS_LUA_SHARED` environment variable, or from the system's LuaJIT (`libluajit-5.1.so.2`, `libluajit-5.1.2.dylib` or `lua51.dll`). GMod's own lua_shared works too, as long as it's run with its dependencies next to it.
+//!
+//! Tests using a `TestState` run one at a time, as gmod-rs has a single global lua_shared.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! #[test]
+//! fn add() {
+//!     let Some(test) = gmod::testing::TestState::new_or_skip() else { return };
+//!     my_module::register(test.lua());
+//!
+//!     assert_eq!(test.eval::<f64>("my_module.Add(1, 2)").unwrap(), 3.0);
+//!
+//!     test.exec("my_module.AddLater(1, 2, function(sum) RESULT = sum end)").unwrap();
+//!     test.tick(Duration::from_millis(15));
+//!     assert_eq!(test.eval::<f64>("RESULT").unwrap(), 3.0);
+//!     assert!(test.errors().is_empty());
+//! }
+//! ```
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::Path,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::lua::{self, LuaCallResults, State};
+
+const SHIMS: &str = include_str!("testing/shims.lua");
+
+/// Names of the system's LuaJIT library, tried when `GMOD_RS_LUA_SHARED` isn't set.
+#[cfg(target_os = "linux")]
+const SYSTEM_LUAJIT: &[&str] = &["libluajit-5.1.so.2", "libluajit-5.1.so"];
+#[cfg(target_os = "macos")]
+const SYSTEM_LUAJIT: &[&str] = &["libluajit-5.1.2.dylib", "libluajit-5.1.dylib"];
+#[cfg(target_os = "windows")]
+const SYSTEM_LUAJIT: &[&str] = &["lua51.dll"];
+
+/// Held by every `TestState`, as lua_shared and the task queue are global.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Loads the LuaJIT library used by test states, if it isn't already. `TestState::new` does this.
+pub fn load_lua_shared() -> Result<()> {
+    if lua::is_loaded() {
+        return Ok(());
+    }
+
+    let candidates: Vec<OsString> = match std::env::var_os("GMOD_RS_LUA_SHARED") {
+        Some(path) => vec![path],
+        None => SYSTEM_LUAJIT.iter().map(OsString::from).collect(),
+    };
+    let mut errors = Vec::new();
+    for path in &candidates {
+        match unsafe { lua::lua_shared().load_from(OsStr::new(path)) } {
+            Ok(()) => return Ok(()),
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+    bail!(
+        "couldn't load LuaJIT, set GMOD_RS_LUA_SHARED to the path of a LuaJIT library\n{}",
+        errors.join("\n")
+    )
+}
+
+/// A standalone Lua state with GMod stand-ins. See the module documentation.
+pub struct TestState {
+    lua: State,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestState {
+    /// Creates a state, and opens the task queue on it like `#[gmod13_open]` does.
+    pub fn new() -> Result<Self> {
+        let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        load_lua_shared()?;
+        // every test runs on its own thread
+        unsafe { lua::lua_shared().rebind_thread() };
+
+        let lua = unsafe { State::new() }.map_err(|err| anyhow!("{err}"))?;
+        let test = Self { lua, _lock: lock };
+        test.lua
+            .eval_named::<()>(SHIMS.as_bytes(), c"=gmod-rs/testing/shims.lua", "shims")?;
+        lua::task_queue::load(lua);
+        Ok(test)
+    }
+
+    /// Same as `new`, but returns `None` (and says so on stderr) if LuaJIT can't be found, for tests that should be skipped on machines without it.
+    pub fn new_or_skip() -> Option<Self> {
+        match Self::new() {
+            Ok(test) => Some(test),
+            Err(err) => {
+                eprintln!("skipping test: {err:#}");
+                None
+            }
+        }
+    }
+
+    pub fn lua(&self) -> State {
+        self.lua
+    }
+
+    /// Runs a chunk of Lua code.
+    pub fn exec(&self, code: &str) -> Result<()> {
+        self.lua.eval_named(code.as_bytes(), c"=test", "test")
+    }
+
+    /// Runs a Lua file. Errors name the file like `require` would.
+    pub fn exec_file(&self, path: &Path) -> Result<()> {
+        self.lua.eval_file(path)
+    }
+
+    /// Evaluates a Lua expression, and converts its values like `LuaCall::call` does.
+    pub fn eval<R: LuaCallResults>(&self, expr: &str) -> Result<R> {
+        self.lua.eval(&format!("return {expr}"))
+    }
+
+    /// Advances the game time by `dt`, running the timers that are due, then the `Tick` and `Think` hooks.
+    pub fn tick(&self, dt: Duration) {
+        let lua = self.lua;
+        lua.get_global(c"_GMOD_RS_TEST");
+        lua.get_field(-1, c"tick");
+        lua.push_number(dt.as_secs_f64());
+        if let Err(err) = lua.pcall(1, 0, 0) {
+            panic!("tick failed: {err}");
+        }
+        lua.pop();
+    }
+
+    /// Returns the errors reported through `ErrorNoHalt` and `ErrorNoHaltWithStack`, and by timers, since the last call.
+    pub fn errors(&self) -> Vec<String> {
+        let lua = self.lua;
+        lua.get_global(c"_GMOD_RS_TEST");
+        lua.get_field(-1, c"errors");
+        let errors = (1..=lua.len(-1))
+            .map(|i| {
+                lua.raw_geti(-1, i);
+                let error = lua.get_string(-1).unwrap_or_default().into_owned();
+                lua.pop();
+                error
+            })
+            .collect();
+        lua.pop();
+        lua.new_table();
+        lua.set_field(-2, c"errors");
+        lua.pop();
+        errors
+    }
+}
+
+impl Drop for TestState {
+    fn drop(&mut self) {
+        lua::task_queue::unload(self.lua);
+        crate::http::unload(self.lua);
+        lua::intern::clear(self.lua);
+        lua::debug_hook::clear(self.lua);
+        unsafe {
+            if let Some(close) = &lua::lua_shared().lua_close {
+                close(self.lua);
+            }
+        }
+    }
+}
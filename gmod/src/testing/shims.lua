@@ -0,0 +1,179 @@
+-- Stand-ins for the GMod globals that gmod-rs and most modules rely on, loaded by gmod::testing::TestState.
+-- They only cover what's needed to run code outside the game, not every behaviour of the real functions.
+
+local test = { time = 0, frametime = 0, errors = {} }
+_GMOD_RS_TEST = test
+
+SERVER = true
+CLIENT = false
+MENU_DLL = false
+
+function CurTime() return test.time end
+RealTime = CurTime
+SysTime = CurTime
+UnPredictedCurTime = CurTime
+function FrameTime() return test.frametime end
+
+local function record_error(msg)
+	table.insert(test.errors, tostring(msg))
+end
+
+local function concat(...)
+	local parts = {}
+	for i = 1, select("#", ...) do
+		parts[i] = tostring((select(i, ...)))
+	end
+	return table.concat(parts)
+end
+
+function ErrorNoHalt(...) record_error(concat(...)) end
+function ErrorNoHaltWithStack(msg) record_error(debug.traceback(tostring(msg), 2)) end
+
+function Msg(...) io.write(concat(...)) end
+function MsgN(...) io.write(concat(...), "\n") end
+function MsgC(...)
+	local parts = {}
+	for i = 1, select("#", ...) do
+		local part = select(i, ...)
+		if type(part) ~= "table" then table.insert(parts, tostring(part)) end
+	end
+	io.write(table.concat(parts))
+end
+
+function PrintTable(t, indent)
+	indent = indent or 0
+	for k, v in pairs(t) do
+		if type(v) == "table" then
+			print(string.rep("\t", indent) .. tostring(k) .. ":")
+			PrintTable(v, indent + 1)
+		else
+			print(string.rep("\t", indent) .. tostring(k) .. "\t=\t" .. tostring(v))
+		end
+	end
+end
+
+function isstring(v) return type(v) == "string" end
+function isnumber(v) return type(v) == "number" end
+function istable(v) return type(v) == "table" end
+function isfunction(v) return type(v) == "function" end
+function isbool(v) return type(v) == "boolean" end
+
+function IsValid(v)
+	if type(v) ~= "table" and type(v) ~= "userdata" then return false end
+	local is_valid = v.IsValid
+	return is_valid ~= nil and is_valid(v) == true
+end
+
+hook = {}
+local hooks = {}
+
+function hook.Add(event, id, fn)
+	hooks[event] = hooks[event] or {}
+	hooks[event][id] = fn
+end
+
+function hook.Remove(event, id)
+	if hooks[event] then hooks[event][id] = nil end
+end
+
+function hook.GetTable() return hooks end
+
+function hook.Call(event, gm, ...)
+	local event_hooks = hooks[event]
+	if event_hooks then
+		for _, fn in pairs(event_hooks) do
+			local a, b, c, d, e, f = fn(...)
+			if a ~= nil then return a, b, c, d, e, f end
+		end
+	end
+end
+
+function hook.Run(event, ...) return hook.Call(event, nil, ...) end
+
+timer = {}
+local timers = {}
+local simple_timers = {}
+
+function timer.Create(name, delay, reps, fn)
+	timers[name] = { delay = delay, reps = reps, fn = fn, next = test.time + delay, count = 0 }
+end
+
+function timer.Simple(delay, fn)
+	table.insert(simple_timers, { next = test.time + delay, fn = fn })
+end
+
+function timer.Remove(name) timers[name] = nil end
+function timer.Exists(name) return timers[name] ~= nil end
+
+function timer.Adjust(name, delay, reps, fn)
+	local t = timers[name]
+	if not t then return false end
+	t.delay = delay
+	t.next = test.time + delay
+	if reps ~= nil then t.reps = reps end
+	if fn ~= nil then t.fn = fn end
+	return true
+end
+
+function timer.TimeLeft(name)
+	local t = timers[name]
+	return t and t.next - test.time
+end
+
+function timer.RepsLeft(name)
+	local t = timers[name]
+	return t and t.reps - t.count
+end
+
+util = {}
+local network_strings = {}
+local network_string_count = 0
+
+function util.AddNetworkString(name)
+	if not network_strings[name] then
+		network_string_count = network_string_count + 1
+		network_strings[name] = network_string_count
+	end
+	return network_strings[name]
+end
+
+function util.NetworkStringToID(name) return network_strings[name] or 0 end
+
+function util.NetworkIDToString(id)
+	for name, name_id in pairs(network_strings) do
+		if name_id == id then return name end
+	end
+end
+
+-- Advances time by `dt` seconds, running the timers that are due, then the Tick and Think hooks.
+function test.tick(dt)
+	test.time = test.time + dt
+	test.frametime = dt
+
+	local due = simple_timers
+	simple_timers = {}
+	for _, t in ipairs(due) do
+		if t.next <= test.time then
+			local ok, err = pcall(t.fn)
+			if not ok then record_error(err) end
+		else
+			table.insert(simple_timers, t)
+		end
+	end
+
+	local names = {}
+	for name in pairs(timers) do table.insert(names, name) end
+	for _, name in ipairs(names) do
+		local t = timers[name]
+		if t and t.next <= test.time then
+			t.count = t.count + 1
+			t.next = test.time + t.delay
+			local ok, err = pcall(t.fn)
+			if not ok then record_error(err) end
+			if t.reps > 0 and t.count >= t.reps and timers[name] == t then timers[name] = nil end
+		end
+	end
+
+	hook.Run("Tick")
+	hook.Run("Think")
+end
@@ -0,0 +1,191 @@
+use std::{iter::repeat_with, time::Duration};
+
+use crate::{
+    callbacks::{self, Registry, Removal},
+    lua::State,
+};
+
+type Callback = Box<dyn FnMut(State)>;
+
+struct Timer {
+    name: String,
+    /// Taken out while the callback is running.
+    callback: Option<Callback>,
+    /// Remaining repetitions, or `None` for infinite.
+    remaining: Option<u32>,
+}
+
+thread_local! {
+    static TIMERS: Registry<Timer> = Registry::new();
+}
+
+/// A handle to a timer created with `create`. The timer is removed when the handle is dropped, unless it is detached.
+#[must_use = "the timer is removed as soon as the handle is dropped"]
+#[derive(Debug)]
+pub struct TimerHandle {
+    removal: Removal,
+    name: String,
+}
+
+impl TimerHandle {
+    /// Returns the name of the timer, as known to the `timer` library.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns whether the timer still exists (it hasn't finished its repetitions nor been removed). Must be called from the Lua thread.
+    pub fn is_active(&self) -> bool {
+        let id = self.removal.id();
+        TIMERS.with(|timers| timers.with(|timers| timers.contains_key(&id)))
+    }
+
+    /// Removes the timer immediately. Must be called from the Lua thread.
+    pub fn remove(self, lua: State) {
+        self.removal.remove(lua);
+    }
+
+    /// Keeps the timer running after the handle is dropped, until it finishes its repetitions or the module is closed.
+    pub fn detach(self) {
+        self.removal.detach();
+    }
+}
+
+fn remove_by_id(lua: State, id: u64) {
+    if let Some(timer) = TIMERS.with(|timers| timers.remove(id)) {
+        lua.get_global(c"timer");
+        lua.get_field(-1, c"Remove");
+        lua.push_string(&timer.name);
+        lua.pcall_ignore(1, 0);
+        lua.pop();
+    }
+}
+
+fn start(lua: State, interval: Duration, repetitions: u32, callback: Callback) -> (u64, String) {
+    let id = TIMERS.with(Registry::next_id);
+
+    let random_str: String = repeat_with(fastrand::alphanumeric).take(10).collect();
+    let name = format!("gmod_rs_timer_{id}_{random_str}");
+
+    TIMERS.with(|timers| {
+        timers.insert(
+            id,
+            Timer {
+                name: name.clone(),
+                callback: Some(callback),
+                remaining: (repetitions > 0).then_some(repetitions),
+            },
+        )
+    });
+
+    lua.get_global(c"timer");
+    lua.get_field(-1, c"Create");
+    lua.push_string(&name);
+    lua.push_number(interval.as_secs_f64());
+    lua.push_number(repetitions);
+    lua.push_number(id);
+    lua.push_closure(timer_callback, 1);
+    if !lua.pcall_ignore(4, 0) {
+        TIMERS.with(|timers| timers.remove(id));
+    }
+    lua.pop();
+
+    (id, name)
+}
+
+/// Creates a timer that calls `callback` every `interval`, `repetitions` times (or forever, if `repetitions` is 0).
+///
+/// The timer is removed when the returned handle is dropped, or when the module is closed.
+///
+/// ## Example
+///
+/// ```ignore
+/// let handle = gmod::timer::create(lua, Duration::from_secs(1), 0, |lua| {
+///     println!("tick");
+/// });
+/// ```
+pub fn create<F>(lua: State, interval: Duration, repetitions: u32, callback: F) -> TimerHandle
+where
+    F: FnMut(State) + 'static,
+{
+    let (id, name) = start(lua, interval, repetitions, Box::new(callback));
+    TimerHandle {
+        removal: Removal::new(id, remove_by_id),
+        name,
+    }
+}
+
+/// Calls `callback` once after `delay`, like `timer.Simple`.
+///
+/// Unlike `timer.Simple`, the timer is removed if the module is closed before it fires.
+pub fn simple<F>(lua: State, delay: Duration, callback: F)
+where
+    F: FnOnce(State) + 'static,
+{
+    let mut callback = Some(callback);
+    start(
+        lua,
+        delay,
+        1,
+        Box::new(move |l| {
+            if let Some(callback) = callback.take() {
+                callback(l)
+            }
+        }),
+    );
+}
+
+/// Removes a timer by name, like `timer.Remove`.
+pub fn remove(lua: State, name: &str) {
+    let id = TIMERS.with(|timers| {
+        timers.with(|timers| {
+            timers
+                .iter()
+                .find_map(|(id, timer)| (timer.name == name).then_some(*id))
+        })
+    });
+
+    match id {
+        Some(id) => remove_by_id(lua, id),
+        None => {
+            lua.get_global(c"timer");
+            lua.get_field(-1, c"Remove");
+            lua.push_string(name);
+            lua.pcall_ignore(1, 0);
+            lua.pop();
+        }
+    }
+}
+
+/// Removes every timer created through this module. This is called for you by `#[gmod13_close]`.
+pub fn unload(lua: State) {
+    for id in TIMERS.with(Registry::ids) {
+        remove_by_id(lua, id);
+    }
+}
+
+extern "C-unwind" fn timer_callback(lua: State) -> i32 {
+    let id = lua.to_number(lua.upvalue_index(1)) as u64;
+
+    let result = TIMERS.with(|timers| {
+        let finished = timers.with(|timers| {
+            let timer = timers.get_mut(&id)?;
+            if let Some(remaining) = &mut timer.remaining {
+                *remaining -= 1;
+            }
+            Some(timer.remaining == Some(0))
+        })?;
+
+        if finished {
+            // the timer library removes finished timers by itself
+            let mut callback = timers.remove(id)?.callback?;
+            Some(crate::panic::catch(|| callback(lua)))
+        } else {
+            timers.run(id, |timer| &mut timer.callback, |callback| callback(lua))
+        }
+    });
+
+    match result {
+        Some(Err(panic)) => callbacks::raise(lua, panic),
+        _ => 0,
+    }
+}
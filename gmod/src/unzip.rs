@@ -0,0 +1,419 @@
+//! Archive extraction (zip, tar and tar.gz), e.g. for content bundles downloaded at runtime.
+//!
+//! Entries are never written outside of the destination directory: absolute paths and `..` components make the extraction fail, and symbolic links are skipped.
+
+use std::{
+    fs::File,
+    io::{self, Cursor, Read, Seek},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::lua::{task_queue, State};
+
+/// How often progress is reported to the Lua thread at most.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where the archive is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Bytes(Vec<u8>),
+    Path(PathBuf),
+}
+
+impl From<Vec<u8>> for Source {
+    fn from(bytes: Vec<u8>) -> Self {
+        Source::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Source {
+    fn from(bytes: &[u8]) -> Self {
+        Source::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<PathBuf> for Source {
+    fn from(path: PathBuf) -> Self {
+        Source::Path(path)
+    }
+}
+
+impl From<&Path> for Source {
+    fn from(path: &Path) -> Self {
+        Source::Path(path.to_path_buf())
+    }
+}
+
+/// The format of an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Detected from the first bytes of the archive.
+    #[default]
+    Auto,
+    Zip,
+    Tar,
+    TarGz,
+}
+
+/// Progress of an extraction, reported after each entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Entries processed so far.
+    pub entries: usize,
+    /// Total number of entries, if the format makes it known upfront (zip does, tar doesn't).
+    pub total_entries: Option<usize>,
+    /// Bytes written so far.
+    pub bytes: u64,
+}
+
+/// The result of a successful extraction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of files written.
+    pub files: usize,
+    /// Number of bytes written.
+    pub bytes: u64,
+    /// Entries that were not extracted: symbolic links, and existing files when not overwriting.
+    pub skipped: Vec<PathBuf>,
+}
+
+type ProgressCallback = Box<dyn FnMut(State, Progress) + Send>;
+type CompleteCallback = Box<dyn FnOnce(State, Result<Summary>) + Send>;
+
+/// Options for `extract`.
+#[derive(Default)]
+#[must_use]
+pub struct ExtractOptions {
+    format: Format,
+    overwrite: bool,
+    strip_components: usize,
+    max_size: Option<u64>,
+    max_entries: Option<usize>,
+    on_progress: Option<ProgressCallback>,
+    on_complete: Option<CompleteCallback>,
+}
+
+impl ExtractOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the format of the archive. Defaults to `Format::Auto`.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Overwrites existing files instead of skipping them.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Removes the first `n` components of every entry's path, like `tar --strip-components`.
+    pub fn strip_components(mut self, n: usize) -> Self {
+        self.strip_components = n;
+        self
+    }
+
+    /// Fails the extraction once more than `bytes` would be written, to protect against zip bombs.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Fails the extraction if the archive has more than `entries` entries.
+    pub fn max_entries(mut self, entries: usize) -> Self {
+        self.max_entries = Some(entries);
+        self
+    }
+
+    /// Calls `f` on the Lua thread as the extraction progresses. Ignored by `extract_blocking`.
+    pub fn on_progress<F: FnMut(State, Progress) + Send + 'static>(mut self, f: F) -> Self {
+        self.on_progress = Some(Box::new(f));
+        self
+    }
+
+    /// Calls `f` on the Lua thread once the extraction has finished. Ignored by `extract_blocking`.
+    pub fn on_complete<F: FnOnce(State, Result<Summary>) + Send + 'static>(mut self, f: F) -> Self {
+        self.on_complete = Some(Box::new(f));
+        self
+    }
+}
+
+/// Extracts an archive into `dest` on a background thread.
+///
+/// ## Example
+///
+/// ```ignore
+/// gmod::unzip::extract(
+///     bytes,
+///     "garrysmod/data/my_addon/content",
+///     ExtractOptions::new()
+///         .overwrite(true)
+///         .max_size(512 * 1024 * 1024)
+///         .on_progress(|lua, progress| println!("{}/{:?}", progress.entries, progress.total_entries))
+///         .on_complete(|lua, result| match result {
+///             Ok(summary) => println!("extracted {} files", summary.files),
+///             Err(err) => eprintln!("{err:?}"),
+///         }),
+/// );
+/// ```
+pub fn extract<S, P>(source: S, dest: P, mut options: ExtractOptions)
+where
+    S: Into<Source>,
+    P: Into<PathBuf>,
+{
+    let source = source.into();
+    let dest = dest.into();
+    std::thread::spawn(move || {
+        let on_progress = options.on_progress.take().map(|f| Arc::new(Mutex::new(f)));
+        let mut last_report: Option<Instant> = None;
+        let result = run(source, &dest, &options, &mut |progress| {
+            let Some(on_progress) = &on_progress else {
+                return;
+            };
+            let finished = progress.total_entries == Some(progress.entries);
+            if !finished && last_report.is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL) {
+                return;
+            }
+            last_report = Some(Instant::now());
+
+            let on_progress = on_progress.clone();
+            let _ = task_queue::wait_lua_tick(String::new(), move |l| {
+                (on_progress.lock().unwrap_or_else(|e| e.into_inner()))(l, progress)
+            });
+        });
+
+        if let Some(on_complete) = options.on_complete.take() {
+            let _ = task_queue::wait_lua_tick(String::new(), move |l| on_complete(l, result));
+        }
+    });
+}
+
+/// Extracts an archive into `dest` on the current thread. Progress and completion callbacks are not called.
+///
+/// ## Example
+///
+/// ```
+/// # use std::io::Write;
+/// use gmod::unzip::{extract_blocking, ExtractOptions};
+///
+/// let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+/// zip.start_file("bundle/readme.txt", zip::write::SimpleFileOptions::default()).unwrap();
+/// zip.write_all(b"hello").unwrap();
+/// let bytes = zip.finish().unwrap().into_inner();
+///
+/// let dest = std::env::temp_dir().join("gmod_rs_unzip_doctest");
+/// let summary = extract_blocking(bytes, &dest, &ExtractOptions::new().overwrite(true).strip_components(1)).unwrap();
+/// assert_eq!(summary.files, 1);
+/// assert_eq!(std::fs::read_to_string(dest.join("readme.txt")).unwrap(), "hello");
+///
+/// let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+/// zip.start_file("../escape.txt", zip::write::SimpleFileOptions::default()).unwrap();
+/// let bytes = zip.finish().unwrap().into_inner();
+/// assert!(extract_blocking(bytes, &dest, &ExtractOptions::new()).is_err());
+/// # std::fs::remove_dir_all(&dest).unwrap();
+/// ```
+pub fn extract_blocking<S, P>(source: S, dest: P, options: &ExtractOptions) -> Result<Summary>
+where
+    S: Into<Source>,
+    P: AsRef<Path>,
+{
+    run(source.into(), dest.as_ref(), options, &mut |_| {})
+}
+
+fn detect(header: &[u8]) -> Format {
+    match header {
+        [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => Format::Zip,
+        [0x1f, 0x8b, ..] => Format::TarGz,
+        _ => Format::Tar,
+    }
+}
+
+fn run(
+    source: Source,
+    dest: &Path,
+    options: &ExtractOptions,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<Summary> {
+    std::fs::create_dir_all(dest).with_context(|| format!("failed to create {dest:?}"))?;
+
+    let mut extractor = Extractor {
+        dest,
+        options,
+        summary: Summary::default(),
+        progress,
+    };
+
+    match source {
+        Source::Bytes(bytes) => extractor.extract(Cursor::new(bytes)),
+        Source::Path(path) => {
+            let file = File::open(&path).with_context(|| format!("failed to open {path:?}"))?;
+            extractor.extract(io::BufReader::new(file))
+        }
+    }?;
+
+    Ok(extractor.summary)
+}
+
+struct Extractor<'a> {
+    dest: &'a Path,
+    options: &'a ExtractOptions,
+    summary: Summary,
+    progress: &'a mut dyn FnMut(Progress),
+}
+
+impl Extractor<'_> {
+    fn extract<R: Read + Seek>(&mut self, mut reader: R) -> Result<()> {
+        let format = match self.options.format {
+            Format::Auto => {
+                let mut header = [0u8; 4];
+                let n = reader.read(&mut header)?;
+                reader.rewind()?;
+                detect(&header[..n])
+            }
+            format => format,
+        };
+
+        match format {
+            Format::Zip => self.extract_zip(reader),
+            Format::Tar => self.extract_tar(reader),
+            Format::TarGz | Format::Auto => self.extract_tar(flate2::read::GzDecoder::new(reader)),
+        }
+    }
+
+    /// Returns where an entry should be written, or `None` if it has nothing left after stripping components.
+    fn target(&self, name: &Path) -> Result<Option<PathBuf>> {
+        let mut path = PathBuf::new();
+        let mut strip = self.options.strip_components;
+        for component in name.components() {
+            match component {
+                Component::Normal(_) if strip > 0 => strip -= 1,
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                    bail!("refusing to extract {name:?}: it would be written outside of the destination")
+                }
+            }
+        }
+        Ok((!path.as_os_str().is_empty()).then(|| self.dest.join(path)))
+    }
+
+    fn check_entries(&self, entries: usize) -> Result<()> {
+        if let Some(max) = self.options.max_entries {
+            if entries > max {
+                bail!("archive has more than {max} entries");
+            }
+        }
+        Ok(())
+    }
+
+    fn write_entry(&mut self, name: &Path, is_dir: bool, reader: &mut dyn Read) -> Result<()> {
+        let Some(target) = self.target(name)? else {
+            return Ok(());
+        };
+
+        if is_dir {
+            std::fs::create_dir_all(&target)
+                .with_context(|| format!("failed to create {target:?}"))?;
+            return Ok(());
+        }
+
+        if target.exists() && !self.options.overwrite {
+            self.summary.skipped.push(name.to_path_buf());
+            return Ok(());
+        }
+
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {parent:?}"))?;
+        }
+
+        let remaining = self
+            .options
+            .max_size
+            .map(|max| max.saturating_sub(self.summary.bytes));
+
+        let mut file =
+            File::create(&target).with_context(|| format!("failed to create {target:?}"))?;
+        let written = match remaining {
+            // read one byte more than allowed, to tell a file that fits exactly from one that's too big
+            Some(remaining) => io::copy(&mut reader.take(remaining + 1), &mut file),
+            None => io::copy(reader, &mut file),
+        }
+        .with_context(|| format!("failed to extract {name:?}"))?;
+
+        if remaining.is_some_and(|remaining| written > remaining) {
+            drop(file);
+            let _ = std::fs::remove_file(&target);
+            bail!(
+                "archive is larger than the limit of {} bytes",
+                self.options.max_size.unwrap_or_default()
+            );
+        }
+
+        self.summary.files += 1;
+        self.summary.bytes += written;
+        Ok(())
+    }
+
+    fn extract_zip<R: Read + Seek>(&mut self, reader: R) -> Result<()> {
+        let mut archive = zip::ZipArchive::new(reader).context("invalid zip archive")?;
+        let total = archive.len();
+        self.check_entries(total)?;
+
+        for i in 0..total {
+            let mut entry = archive.by_index(i)?;
+            let name = PathBuf::from(entry.name());
+            if entry.is_symlink() {
+                self.summary.skipped.push(name);
+            } else {
+                let is_dir = entry.is_dir();
+                self.write_entry(&name, is_dir, &mut entry)?;
+            }
+
+            (self.progress)(Progress {
+                entries: i + 1,
+                total_entries: Some(total),
+                bytes: self.summary.bytes,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn extract_tar<R: Read>(&mut self, reader: R) -> Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        for (i, entry) in archive
+            .entries()
+            .context("invalid tar archive")?
+            .enumerate()
+        {
+            self.check_entries(i + 1)?;
+
+            let mut entry = entry.context("invalid tar archive")?;
+            let name = entry.path()?.into_owned();
+            match entry.header().entry_type() {
+                tar::EntryType::Regular | tar::EntryType::Continuous => {
+                    self.write_entry(&name, false, &mut entry)?
+                }
+                tar::EntryType::Directory => self.write_entry(&name, true, &mut entry)?,
+                // links could point outside of the destination, and metadata entries aren't files
+                _ => self.summary.skipped.push(name),
+            }
+
+            (self.progress)(Progress {
+                entries: i + 1,
+                total_entries: None,
+                bytes: self.summary.bytes,
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -1,3 +1,14 @@
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crate::lua::{LuaCStr, State};
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum UserData {
@@ -53,6 +64,26 @@ pub enum UserData {
     MAX,
 }
 
+/// A GMod `Vector`. Use `LuaState::push_vector` and `LuaState::check_vector` to move it to and from Lua.
+///
+/// Operators behave like in Lua: `*` and `/` with another vector are component-wise.
+///
+/// ## Example
+///
+/// ```
+/// use gmod::userdata::{Angle, Vector};
+///
+/// let a = Vector::new(1.0, 2.0, 3.0);
+/// let b = Vector::new(4.0, 5.0, 6.0);
+///
+/// assert_eq!(a + b, Vector::new(5.0, 7.0, 9.0));
+/// assert_eq!(a.dot(b), 32.0);
+/// assert_eq!(Vector::new(1.0, 0.0, 0.0).cross(Vector::new(0.0, 1.0, 0.0)), Vector::new(0.0, 0.0, 1.0));
+/// assert_eq!(Vector::new(3.0, 4.0, 0.0).length(), 5.0);
+///
+/// let forward = Angle::new(0.0, 90.0, 0.0).forward();
+/// assert!((forward - Vector::new(0.0, 1.0, 0.0)).length() < 1e-6);
+/// ```
 #[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
 #[repr(C)]
 pub struct Vector {
@@ -61,6 +92,77 @@ pub struct Vector {
     pub z: f32,
 }
 
+impl Vector {
+    pub const ZERO: Vector = Vector::new(0.0, 0.0, 0.0);
+
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn dot(self, other: Vector) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vector) -> Vector {
+        Vector::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn length(self) -> f32 {
+        self.length_sqr().sqrt()
+    }
+
+    pub fn length_sqr(self) -> f32 {
+        self.dot(self)
+    }
+
+    /// Length of the vector, ignoring `z`.
+    pub fn length_2d(self) -> f32 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+
+    pub fn distance(self, other: Vector) -> f32 {
+        (self - other).length()
+    }
+
+    pub fn distance_sqr(self, other: Vector) -> f32 {
+        (self - other).length_sqr()
+    }
+
+    /// Returns the vector scaled to a length of 1, or the zero vector if its length is 0, like `Vector:GetNormalized()`.
+    pub fn normalize(self) -> Vector {
+        let length = self.length();
+        if length == 0.0 {
+            Vector::ZERO
+        } else {
+            self / length
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        self == Vector::ZERO
+    }
+
+    /// Returns the angle pointing in the direction of the vector, like `Vector:Angle()`.
+    pub fn angle(self) -> Angle {
+        let (pitch, yaw) = if self.x == 0.0 && self.y == 0.0 {
+            (if self.z > 0.0 { 270.0 } else { 90.0 }, 0.0)
+        } else {
+            let yaw = self.y.atan2(self.x).to_degrees();
+            let pitch = (-self.z).atan2(self.length_2d()).to_degrees();
+            (
+                if pitch < 0.0 { pitch + 360.0 } else { pitch },
+                if yaw < 0.0 { yaw + 360.0 } else { yaw },
+            )
+        };
+        Angle::new(pitch, yaw, 0.0)
+    }
+}
+
+/// A GMod `Angle`, in degrees. Use `LuaState::push_angle` and `LuaState::check_angle` to move it to and from Lua.
 #[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
 #[repr(C)]
 pub struct Angle {
@@ -69,6 +171,156 @@ pub struct Angle {
     pub r: f32,
 }
 
+impl Angle {
+    pub const ZERO: Angle = Angle::new(0.0, 0.0, 0.0);
+
+    pub const fn new(p: f32, y: f32, r: f32) -> Self {
+        Self { p, y, r }
+    }
+
+    fn sin_cos(self) -> [(f32, f32); 3] {
+        [self.p, self.y, self.r].map(|degrees| degrees.to_radians().sin_cos())
+    }
+
+    /// Returns the direction the angle is facing, like `Angle:Forward()`.
+    pub fn forward(self) -> Vector {
+        let [(sp, cp), (sy, cy), _] = self.sin_cos();
+        Vector::new(cp * cy, cp * sy, -sp)
+    }
+
+    /// Returns the direction to the right of the angle, like `Angle:Right()`.
+    pub fn right(self) -> Vector {
+        let [(sp, cp), (sy, cy), (sr, cr)] = self.sin_cos();
+        Vector::new(-sr * sp * cy + cr * sy, -sr * sp * sy - cr * cy, -sr * cp)
+    }
+
+    /// Returns the direction above the angle, like `Angle:Up()`.
+    pub fn up(self) -> Vector {
+        let [(sp, cp), (sy, cy), (sr, cr)] = self.sin_cos();
+        Vector::new(cr * sp * cy + sr * sy, cr * sp * sy - sr * cy, cr * cp)
+    }
+
+    /// Returns the angle with every component wrapped to `[-180, 180)`, like `Angle:Normalize()`.
+    pub fn normalize(self) -> Angle {
+        let wrap = |degrees: f32| (degrees + 180.0).rem_euclid(360.0) - 180.0;
+        Angle::new(wrap(self.p), wrap(self.y), wrap(self.r))
+    }
+
+    pub fn is_zero(self) -> bool {
+        self == Angle::ZERO
+    }
+}
+
+macro_rules! impl_ops {
+	($ty:ident { $($field:ident),+ }) => {
+		impl std::ops::Add for $ty {
+			type Output = $ty;
+			fn add(self, rhs: $ty) -> $ty {
+				$ty { $($field: self.$field + rhs.$field),+ }
+			}
+		}
+
+		impl std::ops::Sub for $ty {
+			type Output = $ty;
+			fn sub(self, rhs: $ty) -> $ty {
+				$ty { $($field: self.$field - rhs.$field),+ }
+			}
+		}
+
+		impl std::ops::Mul<f32> for $ty {
+			type Output = $ty;
+			fn mul(self, rhs: f32) -> $ty {
+				$ty { $($field: self.$field * rhs),+ }
+			}
+		}
+
+		impl std::ops::Mul<$ty> for f32 {
+			type Output = $ty;
+			fn mul(self, rhs: $ty) -> $ty {
+				rhs * self
+			}
+		}
+
+		impl std::ops::Div<f32> for $ty {
+			type Output = $ty;
+			fn div(self, rhs: f32) -> $ty {
+				$ty { $($field: self.$field / rhs),+ }
+			}
+		}
+
+		impl std::ops::Neg for $ty {
+			type Output = $ty;
+			fn neg(self) -> $ty {
+				$ty { $($field: -self.$field),+ }
+			}
+		}
+
+		impl std::ops::AddAssign for $ty {
+			fn add_assign(&mut self, rhs: $ty) {
+				*self = *self + rhs;
+			}
+		}
+
+		impl std::ops::SubAssign for $ty {
+			fn sub_assign(&mut self, rhs: $ty) {
+				*self = *self - rhs;
+			}
+		}
+
+		impl std::ops::MulAssign<f32> for $ty {
+			fn mul_assign(&mut self, rhs: f32) {
+				*self = *self * rhs;
+			}
+		}
+
+		impl std::ops::DivAssign<f32> for $ty {
+			fn div_assign(&mut self, rhs: f32) {
+				*self = *self / rhs;
+			}
+		}
+	};
+}
+impl_ops!(Vector { x, y, z });
+impl_ops!(Angle { p, y, r });
+
+impl std::ops::Mul for Vector {
+    type Output = Vector;
+    fn mul(self, rhs: Vector) -> Vector {
+        Vector::new(self.x * rhs.x, self.y * rhs.y, self.z * rhs.z)
+    }
+}
+
+impl std::ops::Div for Vector {
+    type Output = Vector;
+    fn div(self, rhs: Vector) -> Vector {
+        Vector::new(self.x / rhs.x, self.y / rhs.y, self.z / rhs.z)
+    }
+}
+
+impl From<[f32; 3]> for Vector {
+    fn from([x, y, z]: [f32; 3]) -> Self {
+        Vector::new(x, y, z)
+    }
+}
+
+impl From<Vector> for [f32; 3] {
+    fn from(vec: Vector) -> Self {
+        [vec.x, vec.y, vec.z]
+    }
+}
+
+impl From<[f32; 3]> for Angle {
+    fn from([p, y, r]: [f32; 3]) -> Self {
+        Angle::new(p, y, r)
+    }
+}
+
+impl From<Angle> for [f32; 3] {
+    fn from(ang: Angle) -> Self {
+        [ang.p, ang.y, ang.r]
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct TaggedUserData {
@@ -76,20 +328,40 @@ pub struct TaggedUserData {
     pub r#type: UserData,
 }
 
-pub trait CoercibleUserData {}
+/// A Rust type for the object behind a GMod userdata type.
+pub trait CoercibleUserData {
+    /// The type tag of the userdata.
+    const TYPE: UserData;
+    /// The name of the userdata's metatable.
+    const METATABLE: LuaCStr<'static>;
+}
 
 macro_rules! userdata {
-	($(UserData::$enum:ident => $struct:ident),+) => {
-		$(impl CoercibleUserData for $struct {})+
+	($(UserData::$enum:ident => $struct:ident ($metatable:literal)),+) => {
+		$(impl CoercibleUserData for $struct {
+			const TYPE: UserData = UserData::$enum;
+			const METATABLE: LuaCStr<'static> = $metatable;
+		})+
 
 		impl TaggedUserData {
 			/// Coerce this tagged UserData into its corresponding Rust struct, if possible.
 			///
-			/// This will perform a type check to ensure that the tagged userdata matches the user data you are coercing to.
+			/// This will perform a type check to ensure that the tagged userdata matches the user data you are coercing to, returning the actual type otherwise.
+			///
+			/// ```
+			/// use gmod::userdata::{Angle, TaggedUserData, UserData, Vector};
+			///
+			/// let mut vec = Vector::new(1.0, 2.0, 3.0);
+			/// let tagged = TaggedUserData { data: &mut vec as *mut Vector as *mut _, r#type: UserData::Vector };
+			/// assert_eq!(tagged.coerce::<Vector>().copied(), Ok(vec));
+			/// assert_eq!(tagged.coerce::<Angle>().copied(), Err(UserData::Vector));
+			/// ```
+			#[allow(clippy::mut_from_ref)]
 			pub fn coerce<T: CoercibleUserData>(&self) -> Result<&mut T, UserData> {
-				match self.r#type {
-					$(UserData::$enum => Ok(unsafe { &mut *(self.data as *mut T) }),)+
-					_ => Err(self.r#type)
+				if self.r#type == T::TYPE && !self.data.is_null() {
+					Ok(unsafe { &mut *(self.data as *mut T) })
+				} else {
+					Err(self.r#type)
 				}
 			}
 
@@ -106,12 +378,348 @@ macro_rules! userdata {
 	};
 }
 userdata! {
-    UserData::Vector => Vector,
-    UserData::Angle => Angle
+    UserData::Vector => Vector(c"Vector"),
+    UserData::Angle => Angle(c"Angle"),
+    UserData::UserCmd => CUserCmd(c"CUserCmd"),
+    UserData::MoveData => CMoveData(c"CMoveData"),
+    UserData::DamageInfo => CTakeDamageInfo(c"CTakeDamageInfo")
+}
+
+/// A handle to an entity (`CBaseHandle`), as stored by the engine's objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct EntityHandle(pub u32);
+
+impl EntityHandle {
+    pub const INVALID: EntityHandle = EntityHandle(0xFFFFFFFF);
+    /// How many of the low bits are the index of the entity in the entity list (`NUM_ENT_ENTRY_BITS`), which GMod raised along with its edict limit of 8192.
+    pub const ENTRY_BITS: u32 = 14;
+
+    pub fn is_valid(self) -> bool {
+        self != Self::INVALID
+    }
+
+    /// Returns the index of the entity in the entity list, which is its `EntIndex` for networked entities.
+    pub fn entry_index(self) -> u32 {
+        self.0 & ((1 << Self::ENTRY_BITS) - 1)
+    }
+
+    /// Returns the serial number of the entity, which tells apart the entities that used the same index over time.
+    pub fn serial_number(self) -> u32 {
+        self.0 >> Self::ENTRY_BITS
+    }
+}
+
+/// The `IN_` flags of `CUserCmd::buttons` and `CMoveData::buttons`.
+pub mod in_keys {
+    pub const ATTACK: i32 = 1 << 0;
+    pub const JUMP: i32 = 1 << 1;
+    pub const DUCK: i32 = 1 << 2;
+    pub const FORWARD: i32 = 1 << 3;
+    pub const BACK: i32 = 1 << 4;
+    pub const USE: i32 = 1 << 5;
+    pub const CANCEL: i32 = 1 << 6;
+    pub const LEFT: i32 = 1 << 7;
+    pub const RIGHT: i32 = 1 << 8;
+    pub const MOVELEFT: i32 = 1 << 9;
+    pub const MOVERIGHT: i32 = 1 << 10;
+    pub const ATTACK2: i32 = 1 << 11;
+    pub const RUN: i32 = 1 << 12;
+    pub const RELOAD: i32 = 1 << 13;
+    pub const ALT1: i32 = 1 << 14;
+    pub const ALT2: i32 = 1 << 15;
+    pub const SCORE: i32 = 1 << 16;
+    pub const SPEED: i32 = 1 << 17;
+    pub const WALK: i32 = 1 << 18;
+    pub const ZOOM: i32 = 1 << 19;
+    pub const WEAPON1: i32 = 1 << 20;
+    pub const WEAPON2: i32 = 1 << 21;
+    pub const BULLRUSH: i32 = 1 << 22;
+    pub const GRENADE1: i32 = 1 << 23;
+    pub const GRENADE2: i32 = 1 << 24;
+}
+
+// The engine's objects below map the fields of their Source SDK 2013 declarations, which GMod's binaries start with. GMod's objects can be larger, so they can't be created or copied from Rust, only used through the references given by `TaggedUserData::coerce` and `LuaState::get_userdata_object`.
+
+/// The `CUserCmd` behind a `CUserCmd` userdata: a player's input for one tick, as given to the `StartCommand`, `SetupMove`, `CreateMove`... hooks.
+#[derive(Debug)]
+#[repr(C)]
+pub struct CUserCmd {
+    vtable: *const std::ffi::c_void,
+    pub command_number: i32,
+    pub tick_count: i32,
+    pub view_angles: Angle,
+    pub forward_move: f32,
+    pub side_move: f32,
+    pub up_move: f32,
+    /// The `in_keys` held down.
+    pub buttons: i32,
+    pub impulse: u8,
+    pub weapon_select: i32,
+    pub weapon_subtype: i32,
+    pub random_seed: i32,
+    pub mouse_dx: i16,
+    pub mouse_dy: i16,
+    pub has_been_predicted: bool,
+}
+
+impl CUserCmd {
+    /// Returns whether one of `in_keys` is held down, like `CUserCmd:KeyDown`.
+    pub fn key_down(&self, key: i32) -> bool {
+        self.buttons & key != 0
+    }
+
+    pub fn set_key(&mut self, key: i32, down: bool) {
+        if down {
+            self.buttons |= key;
+        } else {
+            self.buttons &= !key;
+        }
+    }
+}
+
+/// The `CMoveData` behind a `CMoveData` userdata: the state of a player's movement, as given to the `SetupMove`, `Move` and `FinishMove` hooks.
+#[derive(Debug)]
+#[repr(C)]
+pub struct CMoveData {
+    /// The `m_bFirstRunOfFunctions` and `m_bGameCodeMovedPlayer` bitfields.
+    flags: u8,
+    pub player_handle: EntityHandle,
+    pub impulse_command: i32,
+    pub view_angles: Angle,
+    pub abs_view_angles: Angle,
+    /// The `in_keys` held down.
+    pub buttons: i32,
+    pub old_buttons: i32,
+    pub forward_move: f32,
+    pub side_move: f32,
+    pub up_move: f32,
+    pub max_speed: f32,
+    pub client_max_speed: f32,
+    pub velocity: Vector,
+    pub angles: Angle,
+    pub old_angles: Angle,
+    pub out_step_height: f32,
+    pub out_wish_vel: Vector,
+    pub out_jump_vel: Vector,
+    pub constraint_center: Vector,
+    pub constraint_radius: f32,
+    pub constraint_width: f32,
+    pub constraint_speed_factor: f32,
+    pub origin: Vector,
+}
+
+impl CMoveData {
+    /// Returns whether this is the first time this tick's movement is run, like `IsFirstTimePredicted`.
+    pub fn is_first_run(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    pub fn game_code_moved_player(&self) -> bool {
+        self.flags & 2 != 0
+    }
+
+    /// Returns whether one of `in_keys` is held down, like `CMoveData:KeyDown`.
+    pub fn key_down(&self, key: i32) -> bool {
+        self.buttons & key != 0
+    }
+
+    /// Returns whether one of `in_keys` was pressed this tick, like `CMoveData:KeyPressed`.
+    pub fn key_pressed(&self, key: i32) -> bool {
+        self.buttons & key != 0 && self.old_buttons & key == 0
+    }
+
+    /// Returns whether one of `in_keys` was released this tick, like `CMoveData:KeyReleased`.
+    pub fn key_released(&self, key: i32) -> bool {
+        self.buttons & key == 0 && self.old_buttons & key != 0
+    }
 }
 
+/// The `CTakeDamageInfo` behind a `CTakeDamageInfo` userdata, as given to the `EntityTakeDamage` hook.
+#[derive(Debug)]
+#[repr(C)]
+pub struct CTakeDamageInfo {
+    pub damage_force: Vector,
+    pub damage_position: Vector,
+    pub reported_position: Vector,
+    pub inflictor: EntityHandle,
+    pub attacker: EntityHandle,
+    pub weapon: EntityHandle,
+    pub damage: f32,
+    pub max_damage: f32,
+    pub base_damage: f32,
+    /// The `DMG_` flags.
+    pub damage_type: i32,
+    pub damage_custom: i32,
+    pub damage_stats: i32,
+    pub ammo_type: i32,
+    _opaque: [u8; 0],
+}
+
+impl CTakeDamageInfo {
+    /// Returns whether the damage has any of the `DMG_` flags in `damage_type`, like `CTakeDamageInfo:IsDamageType`.
+    pub fn is_damage_type(&self, damage_type: i32) -> bool {
+        self.damage_type & damage_type != 0
+    }
+}
+
+const _: () = {
+    use std::mem::{offset_of, size_of};
+    let ptr = size_of::<*const ()>();
+    assert!(offset_of!(CUserCmd, command_number) == ptr);
+    assert!(offset_of!(CUserCmd, buttons) == ptr + 32);
+    assert!(offset_of!(CUserCmd, weapon_select) == ptr + 40);
+    assert!(offset_of!(CUserCmd, has_been_predicted) == ptr + 56);
+    assert!(offset_of!(CTakeDamageInfo, inflictor) == 36);
+};
+
 pub(crate) unsafe extern "C-unwind" fn __gc<T: Sized>(lua: crate::lua::State) -> i32 {
     let userdata = lua.to_userdata(1) as *mut T;
     std::ptr::read(userdata);
     0
 }
+
+/// Live instances of userdata created by `LuaState::new_userdata` with a metatable, per metatable name. Only filled while tracking is enabled.
+static LIVE_COUNTS: Mutex<BTreeMap<String, usize>> = Mutex::new(BTreeMap::new());
+
+/// Metatables whose `__gc` was wrapped to count collections, restored by `unload`.
+static TRACKED_METATABLES: Mutex<Vec<CString>> = Mutex::new(Vec::new());
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+
+/// Metatable fields holding the metatable's name and its original `__gc` while it's tracked.
+const COUNT_KEY: LuaCStr = c"__gmodrs_count";
+const ORIGINAL_GC_KEY: LuaCStr = c"__gmodrs_gc";
+
+/// Starts counting live instances of userdata created by `LuaState::new_userdata` with a metatable, and registers the global Lua function `debug_function`, which returns a table of the counts by metatable name.
+///
+/// An instance is counted when it's created, and uncounted when it's garbage collected: the `__gc` of its metatable is wrapped for that, and restored by `#[gmod13_close]`. A count that keeps growing while the objects should be unreachable points to a reference leak, e.g. a `LuaRef` that's never dropped.
+///
+/// Tracking has a cost on every `new_userdata` and collection, so it's meant for debugging. Instances created before tracking started aren't counted.
+///
+/// ## Example
+///
+/// ```ignore
+/// #[gmod13_open]
+/// fn gmod13_open(lua: State) -> i32 {
+///     #[cfg(debug_assertions)]
+///     gmod::userdata::track_instances(lua, c"my_module_userdata");
+///     0
+/// }
+/// ```
+///
+/// ```lua
+/// PrintTable(my_module_userdata()) -- { MyModule.Connection = 2, MyModule.Query = 38 }
+/// ```
+pub fn track_instances(lua: State, debug_function: LuaCStr) {
+    TRACKING.store(true, Ordering::Release);
+    lua.push_function(lua_live_counts);
+    lua.set_global(debug_function);
+}
+
+pub fn is_tracking_instances() -> bool {
+    TRACKING.load(Ordering::Acquire)
+}
+
+/// Returns how many instances of each metatable are alive, if tracking was started by `track_instances`.
+pub fn live_counts() -> BTreeMap<String, usize> {
+    LIVE_COUNTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter(|(_, count)| **count > 0)
+        .map(|(name, count)| (name.clone(), *count))
+        .collect()
+}
+
+/// Counts a new instance of the metatable on top of the stack, registered as `name`. Called by `LuaState::new_userdata`.
+pub(crate) fn track_new(lua: State, name: LuaCStr) {
+    if !is_tracking_instances() || !lua.is_table(-1) {
+        return;
+    }
+
+    *LIVE_COUNTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(name.to_string_lossy().into_owned())
+        .or_default() += 1;
+
+    lua.get_field(-1, COUNT_KEY);
+    let tracked = !lua.is_nil(-1);
+    lua.pop();
+    if tracked {
+        return;
+    }
+
+    lua.get_field(-1, c"__gc");
+    lua.set_field(-2, ORIGINAL_GC_KEY);
+    lua.push_string(&name.to_string_lossy());
+    lua.set_field(-2, COUNT_KEY);
+    lua.push_function(counted_gc);
+    lua.set_field(-2, c"__gc");
+
+    TRACKED_METATABLES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(name.to_owned());
+}
+
+/// `__gc` of tracked metatables: uncounts the instance, then calls the original `__gc`.
+extern "C-unwind" fn counted_gc(lua: State) -> i32 {
+    if lua.get_metatable(1) == 0 {
+        return 0;
+    }
+
+    lua.get_field(-1, COUNT_KEY);
+    if let Some(name) = lua.get_string(-1) {
+        if let Some(count) = LIVE_COUNTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(name.as_ref())
+        {
+            *count = count.saturating_sub(1);
+        }
+    }
+    lua.pop();
+
+    lua.get_field(-1, ORIGINAL_GC_KEY);
+    if lua.is_function(-1) {
+        lua.push_value(1);
+        unsafe { lua.call(1, 0) };
+    }
+    0
+}
+
+extern "C-unwind" fn lua_live_counts(lua: State) -> i32 {
+    let counts = live_counts();
+    lua.create_table(0, counts.len() as i32);
+    for (name, count) in counts {
+        lua.push_number(count as f64);
+        lua.set_field(-2, &crate::lua::cstr::cached(&name));
+    }
+    1
+}
+
+/// Stops tracking and restores the `__gc` of tracked metatables, which must not point into the module once it's unloaded. Called by `#[gmod13_close]`.
+pub fn unload(lua: State) {
+    TRACKING.store(false, Ordering::Release);
+    LIVE_COUNTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+
+    let metatables =
+        std::mem::take(&mut *TRACKED_METATABLES.lock().unwrap_or_else(|e| e.into_inner()));
+    for name in metatables {
+        lua.get_metatable_name(&name);
+        if lua.is_table(-1) {
+            lua.get_field(-1, ORIGINAL_GC_KEY);
+            lua.set_field(-2, c"__gc");
+            lua.push_nil();
+            lua.set_field(-2, ORIGINAL_GC_KEY);
+            lua.push_nil();
+            lua.set_field(-2, COUNT_KEY);
+        }
+        lua.pop();
+    }
+}
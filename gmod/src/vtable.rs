@@ -0,0 +1,139 @@
+//! Inspecting and swapping the virtual methods of C++ objects, such as the interfaces returned by `source::create_interface`.
+//!
+//! Unlike a detour, swapping a vtable entry doesn't touch any code: the replacement is only called for the objects sharing that vtable (every object of the same class), and the original stays callable as is. The index of a method is its position among the virtual methods of the class and its bases, in declaration order.
+//!
+//! As with detours, the hooks are restored when dropped, and all of them are restored by `#[gmod13_close]`.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::vtable::{VTable, VTableHook};
+//!
+//! type GetMaxClients = unsafe extern "C" fn(this: *mut c_void) -> c_int;
+//!
+//! static HOOK: OnceLock<VTableHook<GetMaxClients>> = OnceLock::new();
+//!
+//! unsafe extern "C" fn get_max_clients(this: *mut c_void) -> c_int {
+//!     (HOOK.get().unwrap().original())(this) + 1
+//! }
+//!
+//! let engine = gmod::source::create_interface::<c_void>(Library::Engine, "VEngineServer021")?;
+//! let vtable = unsafe { VTable::from_instance(engine.as_ptr()) };
+//! HOOK.set(unsafe { vtable.hook_method(GET_MAX_CLIENTS_INDEX, get_max_clients as GetMaxClients)? });
+//! ```
+
+use std::{collections::HashMap, ffi::c_void, sync::Mutex};
+
+use anyhow::{bail, Result};
+
+use crate::detour::{write_read_only, Function};
+
+/// The vtable entries swapped and not restored yet, by address, with the function they held.
+static HOOKS: Mutex<Option<HashMap<usize, usize>>> = Mutex::new(None);
+
+/// The virtual method table of a C++ object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VTable(*mut *const c_void);
+
+unsafe impl Send for VTable {}
+unsafe impl Sync for VTable {}
+
+impl VTable {
+    /// Returns the vtable of an object, which is pointed to by its first field.
+    ///
+    /// # Safety
+    /// `instance` must point to an object of a class with virtual methods.
+    pub unsafe fn from_instance(instance: *const c_void) -> Self {
+        Self(*(instance as *const *mut *const c_void))
+    }
+
+    /// # Safety
+    /// `ptr` must point to a vtable.
+    pub unsafe fn from_ptr(ptr: *mut *const c_void) -> Self {
+        Self(ptr)
+    }
+
+    pub fn as_ptr(&self) -> *mut *const c_void {
+        self.0
+    }
+
+    /// Returns the method at `index`.
+    ///
+    /// # Safety
+    /// The vtable must have more than `index` methods, and the method at `index` must have the signature `T`, which takes the object as its first argument (with the `thiscall` convention for 32-bit Windows).
+    pub unsafe fn get_method<T: Function>(&self, index: usize) -> T {
+        T::from_addr(*self.0.add(index) as *const ())
+    }
+
+    /// Makes the method at `index` call `replacement` instead, for every object sharing this vtable. Fails if it's already hooked.
+    ///
+    /// # Safety
+    /// Same as `get_method`.
+    pub unsafe fn hook_method<T: Function>(
+        &self,
+        index: usize,
+        replacement: T,
+    ) -> Result<VTableHook<T>> {
+        let slot = self.0.add(index);
+        let mut hooks = HOOKS.lock().unwrap_or_else(|e| e.into_inner());
+        let hooks = hooks.get_or_insert_with(HashMap::new);
+        if hooks.contains_key(&(slot as usize)) {
+            bail!(
+                "method {index} of the vtable at {:p} is already hooked",
+                self.0
+            );
+        }
+        let original = *slot as usize;
+        write_read_only(slot as usize, &(replacement.addr() as usize).to_ne_bytes())?;
+        hooks.insert(slot as usize, original);
+        Ok(VTableHook {
+            slot: slot as usize,
+            original: T::from_addr(original as *const ()),
+        })
+    }
+}
+
+/// A swapped vtable entry, restored when dropped.
+#[must_use = "the method is restored as soon as the hook is dropped"]
+#[derive(Debug)]
+pub struct VTableHook<T: Function> {
+    slot: usize,
+    original: T,
+}
+
+impl<T: Function> VTableHook<T> {
+    /// Returns the method that was replaced, which can be called from the replacement.
+    pub fn original(&self) -> T {
+        self.original
+    }
+
+    /// Returns whether the method is still replaced, i.e. `unload` wasn't called.
+    pub fn is_active(&self) -> bool {
+        HOOKS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .is_some_and(|hooks| hooks.contains_key(&self.slot))
+    }
+}
+
+impl<T: Function> Drop for VTableHook<T> {
+    fn drop(&mut self) {
+        let original = HOOKS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+            .and_then(|hooks| hooks.remove(&self.slot));
+        if let Some(original) = original {
+            let _ = unsafe { write_read_only(self.slot, &original.to_ne_bytes()) };
+        }
+    }
+}
+
+/// Restores every hooked method. This is called for you by `#[gmod13_close]`.
+pub fn unload() {
+    let hooks = HOOKS.lock().unwrap_or_else(|e| e.into_inner()).take();
+    for (slot, original) in hooks.into_iter().flatten() {
+        let _ = unsafe { write_read_only(slot, &original.to_ne_bytes()) };
+    }
+}
@@ -0,0 +1,122 @@
+//! Time budgets for calls into Lua, so that a callback stuck in an infinite loop doesn't hang the server.
+//!
+//! `guard` arms a watchdog until the returned guard is dropped. While armed, a count hook (see `lua::debug_hook`) checks the time every few thousand instructions, and once the budget is exceeded either raises a Lua error where the code is running, or reports the timeout with a traceback and lets it go on.
+//!
+//! The watchdog replaces any debug hook installed by something else while it's armed, including `debug.sethook`. As with every count hook, code already compiled by LuaJIT isn't checked.
+//!
+//! ## Example
+//!
+//! ```ignore
+//! use gmod::watchdog::{self, OnTimeout};
+//!
+//! let _watchdog = watchdog::guard(lua, Duration::from_millis(50), OnTimeout::Error)?;
+//! lua.call_global("hook.Run").arg("MyModuleEvent").call::<()>()?;
+//! ```
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::lua::{
+    debug_hook::{self, HookAction, HookEvents},
+    State,
+};
+
+/// How many instructions run between two checks of the time.
+const CHECK_INTERVAL: u32 = 1000;
+
+/// The armed watchdogs, innermost last.
+static WATCHDOGS: Mutex<Vec<Watchdog>> = Mutex::new(Vec::new());
+
+struct Watchdog {
+    deadline: Instant,
+    budget: Duration,
+    on_timeout: OnTimeout,
+    reported: bool,
+}
+
+/// What happens once a guarded call exceeds its budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnTimeout {
+    /// Raises a Lua error, aborting the running code up to the nearest `pcall`. Code still running past its budget after catching the error gets another one at the next check.
+    Error,
+    /// Reports the timeout once with `ErrorNoHalt`, with the traceback of where it happened, and lets the code go on.
+    Log,
+}
+
+/// Disarms its watchdog when dropped.
+#[must_use = "the watchdog is disarmed as soon as the guard is dropped"]
+pub struct WatchdogGuard {
+    lua: State,
+}
+
+impl Drop for WatchdogGuard {
+    fn drop(&mut self) {
+        let mut watchdogs = WATCHDOGS.lock().unwrap_or_else(|e| e.into_inner());
+        watchdogs.pop();
+        if watchdogs.is_empty() {
+            drop(watchdogs);
+            debug_hook::clear(self.lua);
+        }
+    }
+}
+
+/// Arms a watchdog giving the Lua code run until the guard is dropped `budget` to finish. Guards can be nested, each checking its own budget.
+///
+/// Fails if lua_shared doesn't export `lua_sethook`.
+pub fn guard(lua: State, budget: Duration, on_timeout: OnTimeout) -> Result<WatchdogGuard> {
+    let mut watchdogs = WATCHDOGS.lock().unwrap_or_else(|e| e.into_inner());
+    if watchdogs.is_empty() {
+        debug_hook::set(lua, HookEvents::count(CHECK_INTERVAL), |lua, _| check(lua))?;
+    }
+    watchdogs.push(Watchdog {
+        deadline: Instant::now() + budget,
+        budget,
+        on_timeout,
+        reported: false,
+    });
+    Ok(WatchdogGuard { lua })
+}
+
+/// Returns whether a watchdog is armed.
+pub fn is_armed() -> bool {
+    !WATCHDOGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_empty()
+}
+
+fn check(lua: State) -> HookAction {
+    let now = Instant::now();
+    let mut log = None;
+    {
+        let mut watchdogs = WATCHDOGS.lock().unwrap_or_else(|e| e.into_inner());
+        for watchdog in watchdogs.iter_mut() {
+            if now < watchdog.deadline {
+                continue;
+            }
+            let msg = format!(
+                "watchdog: Lua ran for longer than its budget of {:?}",
+                watchdog.budget
+            );
+            match watchdog.on_timeout {
+                OnTimeout::Error => return HookAction::Error(msg),
+                OnTimeout::Log if !watchdog.reported => {
+                    watchdog.reported = true;
+                    log = Some(msg);
+                }
+                OnTimeout::Log => {}
+            }
+        }
+    }
+
+    // ErrorNoHalt can't be called with the lock held, in case it runs Lua arming another watchdog
+    if let Some(msg) = log {
+        let traceback = lua.get_traceback(lua, 0).into_owned();
+        lua.error_no_halt(&msg, Some(&traceback));
+    }
+    HookAction::Continue
+}
@@ -0,0 +1,65 @@
+//! `gmod::audit` logs whose newest file was damaged, e.g. by a crash while writing.
+
+use std::{fs, io::Write, path::PathBuf};
+
+use gmod::audit::{AuditLog, Query};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gmod_rs_audit_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn damage(dir: &PathBuf, bytes: &[u8]) {
+    let log = AuditLog::new(dir);
+    log.log("Console", "kick", "a", "").unwrap();
+    log.log("Console", "kick", "b", "").unwrap();
+    drop(log);
+
+    fs::OpenOptions::new()
+        .append(true)
+        .open(dir.join("audit-1.log"))
+        .unwrap()
+        .write_all(bytes)
+        .unwrap();
+}
+
+#[test]
+fn continues_after_a_torn_record() {
+    let dir = temp_dir("torn");
+    damage(&dir, b"3\t1700000000\tConsole\tki");
+
+    let log = AuditLog::new(&dir);
+    assert_eq!(log.log("Console", "kick", "c", "").unwrap(), 3);
+    assert!(dir.join("audit-2.log").exists());
+
+    let targets: Vec<_> = log
+        .query(&Query::default())
+        .unwrap()
+        .into_iter()
+        .map(|record| record.target)
+        .collect();
+    assert_eq!(targets, ["c", "b", "a"]);
+    assert!(log.verify().is_err());
+
+    // once the torn record is cut off, the chain is whole again
+    let path = dir.join("audit-1.log");
+    let contents = fs::read(&path).unwrap();
+    let end = contents.iter().rposition(|&b| b == b'\n').unwrap();
+    fs::write(&path, &contents[..=end]).unwrap();
+    assert_eq!(log.verify().unwrap(), 3);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn continues_after_a_record_that_isnt_utf8() {
+    let dir = temp_dir("utf8");
+    damage(&dir, b"\xff\n");
+
+    let log = AuditLog::new(&dir);
+    assert_eq!(log.log("Console", "kick", "c", "").unwrap(), 3);
+    assert_eq!(log.log("Console", "kick", "d", "").unwrap(), 4);
+    assert_eq!(log.query(&Query::default()).unwrap().len(), 4);
+    assert!(log.verify().is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
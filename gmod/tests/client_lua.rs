@@ -0,0 +1,141 @@
+//! `client_lua`, with the net library looped back into the same state, which plays the client.
+
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use gmod::{
+    client_lua,
+    scripts::{Bundle, Script},
+    testing::TestState,
+};
+
+const SETUP: &str = r#"
+local Player = {}
+Player.__index = Player
+function Player:IsValid() return true end
+function Player:IsPlayer() return true end
+function Player:UserID() return self.id or error("disconnected") end
+function Player:SendLua(code) table.insert(self.sent_lua, code) end
+
+function isentity(v) return getmetatable(v) == Player end
+players = {
+    setmetatable({ id = 1, sent_lua = {} }, Player),
+    setmetatable({ id = 2, sent_lua = {} }, Player),
+    setmetatable({ sent_lua = {} }, Player),
+}
+player = { GetAll = function() return players end }
+
+function util.Compress(s) return "lzma" .. s end
+function util.Decompress(s) return s:match("^lzma(.*)$") end
+function RunString(code, name) assert(loadstring(code, "=" .. name))() end
+
+net = { receivers = {}, sent = {} }
+local message
+function net.Start(name) message = { name = name, values = {} } end
+function net.WriteUInt(v) table.insert(message.values, v) end
+function net.WriteData(s) table.insert(message.values, s) end
+function net.Send(target) message.target = target table.insert(net.sent, message) end
+function net.Receive(name, fn) net.receivers[name] = fn end
+
+local reading
+local function read() return table.remove(reading, 1) end
+net.ReadUInt, net.ReadData = read, read
+
+-- receives the messages sent so far, as the client would
+function deliver()
+    local sent = net.sent
+    net.sent = {}
+    for _, m in ipairs(sent) do
+        reading = m.values
+        net.receivers[m.name]()
+    end
+    return #sent
+end
+"#;
+
+fn client(test: &TestState) -> usize {
+    test.tick(Duration::from_millis(15));
+    test.eval::<f64>("deliver()").unwrap() as usize
+}
+
+#[test]
+fn send_installs_the_receiver_once() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(SETUP).unwrap();
+
+    let players = gmod::player::all(lua);
+    assert_eq!(players.len(), 3);
+    client_lua::send(lua, &players[..1], "ran = (ran or 0) + 1").unwrap();
+    client_lua::send(lua, &players[..1], "ran = ran * 10").unwrap();
+    assert!(test
+        .eval::<bool>("#players[1].sent_lua == 1 and #players[2].sent_lua == 0")
+        .unwrap());
+    assert!(test.eval::<bool>("#players[1].sent_lua[1] <= 254").unwrap());
+    assert!(test
+        .eval::<bool>("util.NetworkStringToID('gmod_rs_lua') ~= 0")
+        .unwrap());
+
+    test.exec("RunString(players[1].sent_lua[1], 'SendLua')")
+        .unwrap();
+    // the second stream waits for the first one, so they run in order
+    assert_eq!(client(&test), 1);
+    assert_eq!(client(&test), 1);
+    assert_eq!(test.eval::<f64>("ran").unwrap(), 10.0);
+    assert!(test.errors().is_empty());
+
+    // the disconnected player is skipped, and the others get one stream
+    client_lua::broadcast(lua, "ran = ran + 1").unwrap();
+    assert!(test
+        .eval::<bool>("#players[2].sent_lua == 1 and #players[3].sent_lua == 0")
+        .unwrap());
+    assert_eq!(client(&test), 1);
+    assert!(test.eval::<bool>("#net.sent == 0 and ran == 11").unwrap());
+}
+
+#[test]
+fn send_chunks_bundles() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(SETUP).unwrap();
+    let players = gmod::player::all(lua);
+    client_lua::send(lua, &players[..1], "").unwrap();
+    test.exec("RunString(players[1].sent_lua[1], 'SendLua')")
+        .unwrap();
+    client(&test);
+
+    let big = format!("big = '{}'\n-- ]]", "x".repeat(100 * 1024));
+    let scripts = Box::leak(Box::new([
+        Script {
+            path: "a.lua",
+            source: "order = { 'a' } error_name = select(2, pcall(function() error('oops') end))",
+        },
+        Script {
+            path: "b.lua",
+            source: Box::leak(big.into_boxed_str()),
+        },
+        Script {
+            path: "c.lua",
+            source: "table.insert(order, 'c') -- ]=]",
+        },
+    ]));
+    let bundle = Bundle::new("mymodule", scripts);
+    client_lua::send(lua, &players[..1], &bundle).unwrap();
+
+    // 100KB takes two chunks, sent one per tick
+    assert_eq!(client(&test), 1);
+    assert!(test.eval::<bool>("order == nil").unwrap());
+    assert_eq!(client(&test), 1);
+    assert!(test
+        .eval::<bool>("#big == 100 * 1024 and table.concat(order) == 'ac'")
+        .unwrap());
+    assert_eq!(
+        test.eval::<String>("error_name").unwrap(),
+        "mymodule/a.lua:1: oops"
+    );
+}
@@ -0,0 +1,145 @@
+//! `gmod::codec` round trips, and payloads it must refuse.
+
+#![cfg(feature = "testing")]
+
+use gmod::{
+    codec::{decode, encode, Limits},
+    testing::TestState,
+};
+
+#[test]
+fn encodes_messagepack() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    let limits = Limits::default();
+
+    for (code, bytes) in [
+        (
+            "{ 1, -1, 300, 1.5 }",
+            &[
+                0x94, 0x01, 0xff, 0xcd, 0x01, 0x2c, 0xca, 0x3f, 0xc0, 0x00, 0x00,
+            ][..],
+        ),
+        ("{ a = true }", &[0x81, 0xa1, b'a', 0xc3]),
+        ("'\\255'", &[0xc4, 0x01, 0xff]),
+        (
+            "-2^40",
+            &[0xd3, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ),
+    ] {
+        test.exec(&format!("value = {code}")).unwrap();
+        lua.get_global(c"value");
+        assert_eq!(encode(lua, -1, &limits).unwrap(), bytes, "{code}");
+        lua.pop();
+    }
+}
+
+#[test]
+fn round_trips() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    let limits = Limits::default();
+
+    test.exec(
+        r#"value = {
+            list = { 1, 2.25, "three", { nested = false } },
+            [5] = "sparse",
+            [true] = 0.1,
+            big = 2^53,
+        }"#,
+    )
+    .unwrap();
+    lua.get_global(c"value");
+    let data = encode(lua, -1, &limits).unwrap();
+    lua.pop();
+
+    decode(lua, &data, &limits).unwrap();
+    lua.set_global(c"decoded");
+    assert!(test
+        .eval::<bool>(
+            "decoded.list[3] == 'three' and decoded.list[4].nested == false and decoded[5] == 'sparse' \
+             and decoded[true] == 0.1 and decoded.big == 2^53 and #decoded.list == 4"
+        )
+        .unwrap());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn keeps_keys_of_tables_with_holes() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    let limits = Limits::default();
+
+    // `#value` is 4 and there are 4 keys, but it isn't a sequence
+    test.exec("value = { 1, 2, nil, 4 } value.x = 1").unwrap();
+    assert_eq!(test.eval::<f64>("#value").unwrap(), 4.0);
+    lua.get_global(c"value");
+    let data = encode(lua, -1, &limits).unwrap();
+    lua.pop();
+    assert_eq!(data[0], 0x84);
+
+    decode(lua, &data, &limits).unwrap();
+    lua.set_global(c"decoded");
+    assert!(test
+        .eval::<bool>(
+            "decoded[1] == 1 and decoded[2] == 2 and decoded[3] == nil and decoded[4] == 4 and decoded.x == 1"
+        )
+        .unwrap());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn enforces_limits() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    let limits = Limits {
+        max_depth: 2,
+        max_bytes: 16,
+        max_values: 8,
+    };
+
+    test.exec("value = { { { 1 } } }").unwrap();
+    lua.get_global(c"value");
+    assert!(encode(lua, -1, &limits).is_err());
+    lua.pop();
+    test.exec("value = {} value.self = value").unwrap();
+    lua.get_global(c"value");
+    assert!(encode(lua, -1, &Limits::default()).is_err());
+    lua.pop();
+
+    for (bytes, error) in [
+        (
+            &[0x91, 0x91, 0x91, 0x01][..],
+            "tables are nested more than 2 deep",
+        ),
+        (&[0xa5, b'a'], "the payload is truncated"),
+        // claims 65535 elements
+        (&[0xdc, 0xff, 0xff, 0x01], "the payload is truncated"),
+        (
+            &[0x98, 1, 2, 3, 4, 5, 6, 7, 8],
+            "the payload holds more than 8 values",
+        ),
+        (&[0x01, 0x02], "1 bytes are left after the value"),
+        (&[0x81, 0xc0, 0x01], "a table key can't be nil or NaN"),
+        (
+            &[0xc7, 0x01, 0x05, 0x00],
+            "unknown extension type 5 of 1 bytes",
+        ),
+        (&[0xc1], "unsupported MessagePack marker 0xc1"),
+        (
+            &[0; 17],
+            "the payload is 17 bytes long, more than the limit of 16",
+        ),
+    ] {
+        assert_eq!(decode(lua, bytes, &limits).unwrap_err().to_string(), error);
+        assert_eq!(lua.get_top(), 0);
+    }
+}
@@ -0,0 +1,50 @@
+//! Hex colors, and `Color` tables pushed to and read from Lua.
+
+use gmod::color::{palette, Color};
+
+#[test]
+fn hex() {
+    for (hex, color) in [
+        ("#fff", Color::WHITE),
+        ("000", Color::BLACK),
+        ("#FFA500", palette::ORANGE),
+        ("#ffffff00", palette::TRANSPARENT),
+        ("#6ebeff80", Color::rgba(110, 190, 255, 128)),
+    ] {
+        assert_eq!(hex.parse::<Color>().unwrap(), color, "{hex}");
+    }
+    assert_eq!(palette::ORANGE.to_string(), "#ffa500");
+    assert_eq!(Color::RED.with_alpha(16).to_hex(), "#ff000010");
+
+    for hex in ["", "#", "#ff", "#fffff", "#ggg", "#+ff", "#ffé"] {
+        assert!(Color::from_hex(hex).is_err(), "{hex}");
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn lua_tables() {
+    let Some(test) = gmod::testing::TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec("debug.getregistry().Color = { __index = { IsColor = function() return true end } }")
+        .unwrap();
+
+    lua.push_color(Color::rgba(1, 2, 3, 4));
+    lua.set_global(c"pushed");
+    assert!(test
+        .eval::<bool>("pushed:IsColor() and pushed.r == 1 and pushed.g == 2 and pushed.b == 3 and pushed.a == 4")
+        .unwrap());
+
+    test.exec("plain = { r = 300, g = -5, b = 7.9 }").unwrap();
+    unsafe { lua.push_globals() };
+    lua.get_field(-1, c"plain");
+    assert_eq!(lua.get_color(-1), Some(Color::rgb(255, 0, 7)));
+    lua.get_field(-2, c"pushed");
+    assert_eq!(lua.get_color(-1), Some(Color::rgba(1, 2, 3, 4)));
+    lua.push_string("#fff");
+    assert_eq!(lua.get_color(-1), None);
+    assert!(lua.check_color(-1).is_err());
+    lua.pop_n(4);
+}
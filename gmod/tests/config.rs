@@ -0,0 +1,210 @@
+//! `Config`, with the convar, command, file and JSON libraries faked in Lua.
+
+#![cfg(feature = "testing")]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use gmod::{
+    config::{Config, GmodConfig},
+    testing::TestState,
+};
+
+const SETUP: &str = r#"
+local convars, callbacks = {}, {}
+local ConVar = {}
+ConVar.__index = ConVar
+function ConVar:GetString() return self.value end
+function ConVar:SetString(value)
+    local old = self.value
+    self.value = tostring(value)
+    for _, fn in pairs(callbacks[self.name] or {}) do
+        if old ~= self.value then fn(self.name, old, self.value) end
+    end
+end
+
+function CreateConVar(name, default, flags, help)
+    convars[name] = convars[name] or setmetatable({ name = name, value = default, help = help }, ConVar)
+    return convars[name]
+end
+function GetConVar(name) return convars[name] end
+function ConVarExists(name) return convars[name] ~= nil end
+function RunConsoleCommand(name, value) convars[name]:SetString(value) end
+test_convars = convars
+
+cvars = {}
+function cvars.AddChangeCallback(name, fn, id)
+    callbacks[name] = callbacks[name] or {}
+    callbacks[name][id] = fn
+end
+function cvars.RemoveChangeCallback(name, id) callbacks[name][id] = nil end
+
+concommand = { commands = {} }
+function concommand.Add(name, fn) concommand.commands[name] = fn end
+function concommand.Remove(name) concommand.commands[name] = nil end
+function concommand.Run(name) concommand.commands[name](nil, name, {}, "") end
+
+files = {}
+file = {}
+function file.Exists(path) return files[path] ~= nil end
+function file.Read(path) return files[path] end
+function file.CreateDir() end
+function file.Write(path, contents) files["data/" .. path] = contents end
+
+-- flat objects only
+function util.TableToJSON(t)
+    local keys = {}
+    for key in pairs(t) do table.insert(keys, key) end
+    table.sort(keys)
+    for i, key in ipairs(keys) do
+        local value = t[key]
+        keys[i] = string.format("%q:%s", key, type(value) == "string" and string.format("%q", value) or tostring(value))
+    end
+    return "{" .. table.concat(keys, ",") .. "}"
+end
+function util.JSONToTable(json)
+    local chunk = loadstring("return " .. (json:gsub('"([%w_]+)":', '["%1"]=')))
+    return chunk and chunk()
+end
+"#;
+
+#[derive(GmodConfig, Debug, Clone, PartialEq)]
+struct Settings {
+    /// Enables the module
+    enabled: bool,
+    #[config(help = "Seconds between two saves")]
+    interval: u32,
+    #[config(name = "webhook_url")]
+    webhook: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 60,
+            webhook: String::new(),
+        }
+    }
+}
+
+fn record(config: &Config<Settings>) -> Arc<Mutex<Vec<Settings>>> {
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    config.on_change({
+        let changes = changes.clone();
+        move |_, settings| changes.lock().unwrap().push(settings.clone())
+    });
+    changes
+}
+
+#[test]
+fn derive_lists_the_fields() {
+    let names: Vec<_> = Settings::FIELDS.iter().map(|field| field.name).collect();
+    assert_eq!(names, ["enabled", "interval", "webhook_url"]);
+    assert_eq!(Settings::FIELDS[0].help, "Enables the module");
+    assert_eq!(Settings::FIELDS[1].help, "Seconds between two saves");
+    assert_eq!(Settings::FIELDS[2].help, "");
+}
+
+#[test]
+fn convars() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(SETUP).unwrap();
+    // archived from a previous session
+    test.exec("CreateConVar('mymod_interval', '30')").unwrap();
+
+    let config = Config::<Settings>::convars(lua, "mymod", 0);
+    assert_eq!(config.get().interval, 30);
+    assert!(config.get().enabled);
+    assert_eq!(
+        test.eval::<String>("test_convars.mymod_enabled.help")
+            .unwrap(),
+        "Enables the module"
+    );
+    let changes = record(&config);
+
+    // changed from the console, notified on the next tick
+    test.exec("RunConsoleCommand('mymod_enabled', '0')")
+        .unwrap();
+    assert!(!config.get().enabled);
+    assert!(changes.lock().unwrap().is_empty());
+    test.tick(Duration::from_millis(15));
+    assert_eq!(changes.lock().unwrap().len(), 1);
+
+    // invalid values are ignored
+    test.exec("RunConsoleCommand('mymod_interval', 'soon')")
+        .unwrap();
+    assert_eq!(config.get().interval, 30);
+
+    config
+        .set(lua, |settings| {
+            settings.webhook = "https://example.com".into()
+        })
+        .unwrap();
+    assert_eq!(
+        test.eval::<String>("test_convars.mymod_webhook_url.value")
+            .unwrap(),
+        "https://example.com"
+    );
+    test.tick(Duration::from_millis(15));
+    let last = changes.lock().unwrap().last().cloned().unwrap();
+    assert_eq!(last.webhook, "https://example.com");
+    assert_eq!(changes.lock().unwrap().len(), 2);
+
+    // changed behind the callbacks' back, picked up by the reload command
+    test.exec("test_convars.mymod_interval.value = '5' concommand.Run('mymod_reload_config')")
+        .unwrap();
+    assert_eq!(config.get().interval, 5);
+    assert!(!config.load(lua).unwrap());
+}
+
+#[test]
+fn json() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(SETUP).unwrap();
+
+    // written with the defaults
+    let config = Config::<Settings>::json(lua, "mymod").unwrap();
+    assert_eq!(config.get(), Settings::default());
+    assert_eq!(
+        test.eval::<String>("files['data/mymod/config.json']")
+            .unwrap(),
+        r#"{"enabled":true,"interval":60,"webhook_url":""}"#
+    );
+    let changes = record(&config);
+
+    // missing keys keep their value
+    test.exec(r#"files['data/mymod/config.json'] = '{"interval":120}' concommand.Run('mymod_reload_config')"#)
+        .unwrap();
+    assert_eq!(config.get().interval, 120);
+    assert!(config.get().enabled);
+    test.tick(Duration::from_millis(15));
+    assert_eq!(changes.lock().unwrap().len(), 1);
+
+    test.exec(r#"files['data/mymod/config.json'] = 'not json'"#)
+        .unwrap();
+    let err = config.load(lua).unwrap_err();
+    assert!(err.to_string().contains("data/mymod/config.json"), "{err}");
+    assert_eq!(config.get().interval, 120);
+
+    config
+        .set(lua, |settings| settings.enabled = false)
+        .unwrap();
+    assert_eq!(
+        test.eval::<String>("files['data/mymod/config.json']")
+            .unwrap(),
+        r#"{"enabled":false,"interval":120,"webhook_url":""}"#
+    );
+
+    // the next load reads the same file again
+    let config = Config::<Settings>::json(lua, "mymod").unwrap();
+    assert!(!config.get().enabled);
+}
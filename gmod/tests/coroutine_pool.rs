@@ -0,0 +1,92 @@
+//! Running `hook` and `eventlog` dispatch through a `CoroutinePool`.
+
+#![cfg(feature = "testing")]
+
+use gmod::{
+    eventlog::{Event, EventLog},
+    hook,
+    lua::CoroutinePool,
+    testing::TestState,
+};
+
+#[test]
+fn hook_call_survives_a_yielding_hook() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    static POOL: CoroutinePool = CoroutinePool::new(2);
+    hook::set_coroutine_pool(Some(&POOL));
+
+    test.exec(
+        r#"
+        hook.Add("Example", "Sum", function(a, b) return a + b end)
+        hook.Add("Suspend", "Yield", function() coroutine.yield() end)
+        "#,
+    )
+    .unwrap();
+
+    lua.push_number(1);
+    lua.push_number(2);
+    assert!(hook::call(lua, "Example", 2, 1));
+    assert_eq!(lua.to_number(-1), 3.0);
+    lua.pop();
+    assert_eq!(POOL.idle(), 1);
+
+    assert!(hook::call(lua, "Suspend", 0, 1));
+    assert!(lua.is_nil(-1));
+    lua.pop();
+    assert_eq!(lua.get_top(), 0);
+    assert!(test.errors().is_empty());
+
+    hook::set_coroutine_pool(None);
+    POOL.clear(lua);
+}
+
+#[derive(Clone)]
+struct Joined(u32);
+
+impl Event for Joined {
+    fn name(&self) -> &str {
+        "Joined"
+    }
+
+    fn push_lua(&self, lua: gmod::lua::State) -> i32 {
+        lua.push_number(self.0);
+        1
+    }
+}
+
+#[test]
+fn replay_moves_past_a_yielding_callback() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    static POOL: CoroutinePool = CoroutinePool::new(2);
+    static JOINS: EventLog<Joined> = EventLog::new(8);
+    JOINS.set_coroutine_pool(Some(&POOL));
+    JOINS.register(lua, c"Joins");
+    JOINS.publish(Joined(1));
+    JOINS.publish(Joined(2));
+
+    test.exec(
+        r#"
+        seen = {}
+        last = Joins.Replay(function(seq, name, id)
+            seen[#seen + 1] = id
+            if id == 1 then coroutine.yield() end
+        end)
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(test.eval::<f64>("#seen").unwrap(), 2.0);
+    assert_eq!(test.eval::<f64>("last").unwrap(), 2.0);
+    assert!(test.errors().is_empty());
+
+    JOINS.set_coroutine_pool(None);
+    POOL.clear(lua);
+}
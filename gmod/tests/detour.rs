@@ -0,0 +1,50 @@
+#![cfg(all(feature = "detour", any(target_arch = "x86_64", target_arch = "x86")))]
+
+use std::{hint::black_box, sync::OnceLock};
+
+use gmod::detour::{self, Detour};
+
+type Target = extern "C" fn(u64) -> u64;
+
+static DETOUR: OnceLock<Detour<Target>> = OnceLock::new();
+
+// long enough to be patched, and not inlined into its callers
+#[inline(never)]
+extern "C" fn collatz_steps(mut n: u64) -> u64 {
+    let mut steps = 0;
+    while n > 1 {
+        n = if n.is_multiple_of(2) { n / 2 } else { 3 * n + 1 };
+        steps += 1;
+    }
+    steps
+}
+
+extern "C" fn detoured(n: u64) -> u64 {
+    DETOUR.get().unwrap().call_original(|original| original(n)) + 1000
+}
+
+#[test]
+fn detour() {
+    let target: Target = black_box(collatz_steps);
+    assert_eq!(target(27), 111);
+
+    let detour = unsafe { Detour::new(collatz_steps as Target, detoured as Target) }.unwrap();
+    assert!(unsafe { Detour::new(collatz_steps as Target, detoured as Target) }.is_err());
+    let detour = DETOUR.get_or_init(|| detour);
+    assert_eq!(target(27), 111);
+
+    detour.enable().unwrap();
+    assert!(detour.is_enabled());
+    assert_eq!(target(27), 1111);
+    assert!(detour.is_enabled());
+
+    detour.disable().unwrap();
+    assert_eq!(target(27), 111);
+
+    // what #[gmod13_close] does
+    detour.enable().unwrap();
+    detour::unload();
+    assert_eq!(target(27), 111);
+    assert!(!detour.is_enabled());
+    assert!(detour.enable().is_err());
+}
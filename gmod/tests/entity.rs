@@ -0,0 +1,85 @@
+//! Resolving entity userdata to engine handles and pointers, with stand-ins for what GMod pushes.
+
+#![cfg(feature = "testing")]
+
+use std::ffi::c_void;
+
+use gmod::{
+    entity::{Entity, EntityList},
+    testing::TestState,
+    userdata::{EntityHandle, TaggedUserData, UserData},
+};
+
+#[repr(C)]
+struct EntInfo {
+    entity: *mut c_void,
+    serial_number: i32,
+    prev: *mut c_void,
+    next: *mut c_void,
+}
+
+#[repr(C)]
+struct FakeEntityList {
+    vtable: *const c_void,
+    entries: [EntInfo; 8],
+}
+
+#[test]
+fn entity_handles() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec("function isentity(value) return type(value) == 'userdata' end")
+        .unwrap();
+
+    let mut handles = [
+        EntityHandle(5 | (3 << EntityHandle::ENTRY_BITS)),
+        EntityHandle::INVALID,
+    ];
+    let [ent, null] = [0, 1].map(|i| {
+        lua.new_userdata(
+            TaggedUserData {
+                data: (&mut handles[i] as *mut EntityHandle).cast(),
+                r#type: UserData::Entity,
+            },
+            None,
+        );
+        let ent = Entity::from_index(lua, -1).unwrap();
+        lua.pop();
+        ent
+    });
+
+    let handle = ent.handle(lua).unwrap();
+    assert_eq!((handle.entry_index(), handle.serial_number()), (5, 3));
+    assert_eq!(null.handle(lua), None);
+
+    let mut object = 0u64;
+    let mut list = FakeEntityList {
+        vtable: std::ptr::null(),
+        entries: std::array::from_fn(|_| EntInfo {
+            entity: std::ptr::null_mut(),
+            serial_number: 0,
+            prev: std::ptr::null_mut(),
+            next: std::ptr::null_mut(),
+        }),
+    };
+    list.entries[5] = EntInfo {
+        entity: (&mut object as *mut u64).cast(),
+        serial_number: 3,
+        prev: std::ptr::null_mut(),
+        next: std::ptr::null_mut(),
+    };
+    let list = &mut list as *mut FakeEntityList;
+    let entity_list = unsafe { EntityList::from_ptr(list.cast()) }.unwrap();
+    assert_eq!(
+        unsafe { ent.base_entity(lua, &entity_list) }.map(|ptr| ptr.as_ptr()),
+        Some((&mut object as *mut u64).cast())
+    );
+    assert!(unsafe { null.base_entity(lua, &entity_list) }.is_none());
+
+    // the index was reused by another entity
+    unsafe { (*list).entries[5].serial_number = 4 };
+    assert!(unsafe { ent.base_entity(lua, &entity_list) }.is_none());
+    assert_eq!(lua.get_top(), 0);
+}
@@ -0,0 +1,79 @@
+//! `errors::subscribe`, with `OnLuaError` run by hand as the game would.
+
+#![cfg(feature = "testing")]
+
+use std::{cell::RefCell, rc::Rc};
+
+use gmod::{
+    errors::{self, Addon, ScriptError, StackFrame},
+    testing::TestState,
+};
+
+#[test]
+fn subscribe() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    let received: Rc<RefCell<Vec<ScriptError>>> = Rc::default();
+    let id = errors::subscribe(lua, {
+        let received = received.clone();
+        move |_, err| received.borrow_mut().push(err.clone())
+    });
+    let second = errors::subscribe(lua, |_, _| {});
+    assert!(test
+        .eval::<bool>("hook.GetTable().OnLuaError.gmod_rs_errors ~= nil")
+        .unwrap());
+
+    test.exec(
+        r#"
+        hook.Run("OnLuaError", "addons/legacy/lua/a.lua:3: boom", "server", {
+            { File = "addons/legacy/lua/a.lua", Function = "explode", Line = 3 },
+            { File = "[C]", Function = "pcall", Line = -1 },
+        }, "", "0")
+        hook.Run("OnLuaError", "lua/b.lua:1: bad", "client", {}, "Workshop Addon", "123456789")
+        "#,
+    )
+    .unwrap();
+
+    let received = received.borrow();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].message, "addons/legacy/lua/a.lua:3: boom");
+    assert_eq!(received[0].realm, "server");
+    assert_eq!(
+        received[0].stack[0],
+        StackFrame {
+            source: "addons/legacy/lua/a.lua".to_string(),
+            function: "explode".to_string(),
+            line: 3,
+        }
+    );
+    assert_eq!(received[0].stack[1].to_string(), "pcall - [C]:-1");
+    assert_eq!(
+        received[0].addon,
+        Some(Addon {
+            title: "legacy".to_string(),
+            workshop_id: None,
+        })
+    );
+    assert_eq!(
+        received[1].addon,
+        Some(Addon {
+            title: "Workshop Addon".to_string(),
+            workshop_id: Some(123456789),
+        })
+    );
+    assert_ne!(received[0].fingerprint(), received[1].fingerprint());
+    assert_eq!(received[0].fingerprint(), received[0].clone().fingerprint());
+
+    assert!(errors::unsubscribe(lua, id));
+    assert!(!errors::unsubscribe(lua, id));
+    assert!(test
+        .eval::<bool>("hook.GetTable().OnLuaError.gmod_rs_errors ~= nil")
+        .unwrap());
+    assert!(errors::unsubscribe(lua, second));
+    assert!(test
+        .eval::<bool>("hook.GetTable().OnLuaError.gmod_rs_errors == nil")
+        .unwrap());
+}
@@ -0,0 +1,46 @@
+//! `gmod::eventlog` subscribers racing with publishers.
+
+use std::sync::{Arc, Mutex};
+
+use gmod::eventlog::{Event, EventLog};
+
+#[derive(Clone)]
+struct Tick;
+
+impl Event for Tick {
+    fn name(&self) -> &str {
+        "Tick"
+    }
+}
+
+#[test]
+fn backlog_is_delivered_before_live_events() {
+    static TICKS: EventLog<Tick> = EventLog::new(1000);
+    for _ in 0..1000 {
+        TICKS.publish(Tick);
+    }
+
+    let publisher = std::thread::spawn(|| {
+        for _ in 0..1000 {
+            TICKS.publish(Tick);
+        }
+    });
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let id = TICKS.subscribe({
+        let seen = seen.clone();
+        move |seq, _| {
+            std::thread::yield_now();
+            seen.lock().unwrap().push(seq);
+        }
+    });
+    publisher.join().unwrap();
+    TICKS.unsubscribe(id);
+
+    let seen = seen.lock().unwrap();
+    assert!(
+        seen.windows(2).all(|pair| pair[1] == pair[0] + 1),
+        "events were delivered out of order"
+    );
+    assert_eq!(seen.last(), Some(&2000));
+}
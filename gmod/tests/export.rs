@@ -0,0 +1,40 @@
+//! Functions exported with `#[lua_export]`, registered like `gmod13_open` does.
+
+#![cfg(all(feature = "testing", feature = "export"))]
+
+use gmod::{lua::State, testing::TestState};
+
+#[gmod::lua_export(namespace = "exported", name = "Add")]
+fn add(_lua: State, a: f64, b: f64) -> (f64,) {
+    (a + b,)
+}
+
+#[gmod::lua_export(namespace = "exported.util")]
+fn shout(_lua: State, text: String) -> (String,) {
+    (text.to_uppercase(),)
+}
+
+#[test]
+fn register() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    let names: Vec<_> = gmod::export::exports()
+        .iter()
+        .map(|export| (export.namespace, export.name))
+        .collect();
+    assert_eq!(names, [("exported", "Add"), ("exported.util", "shout")]);
+
+    test.exec("exported = { Version = 2 }").unwrap();
+    gmod::export::register(lua);
+    assert_eq!(lua.get_top(), 0);
+
+    assert_eq!(test.eval::<f64>("exported.Add(1, 2)").unwrap(), 3.0);
+    assert_eq!(test.eval::<f64>("exported.Version").unwrap(), 2.0);
+    assert_eq!(
+        test.eval::<String>("exported.util.shout('hi')").unwrap(),
+        "HI"
+    );
+}
@@ -0,0 +1,23 @@
+//! The Lua fuzz targets on a few hand-picked inputs.
+
+#![cfg(all(feature = "testing", feature = "fuzz", feature = "json"))]
+
+use gmod::{fuzz_targets, testing::TestState};
+
+#[test]
+fn lua_targets() {
+    // the targets keep their own state, this only checks LuaJIT can be found
+    if TestState::new_or_skip().is_none() {
+        return;
+    }
+
+    assert!(fuzz_targets::codec_decode(&[0x92, 0x01, 0xa1, b'a']));
+    assert!(!fuzz_targets::codec_decode(&[0x92, 0x01]));
+    assert!(!fuzz_targets::codec_decode(&[0xdf, 0xff, 0xff, 0xff, 0xff]));
+
+    assert!(fuzz_targets::json(br#"{"a": [1, null, {"b": 1.5}]}"#));
+    assert!(!fuzz_targets::json(&[b'['; 4096]));
+    assert!(fuzz_targets::json(
+        format!("{}{}", "[".repeat(127), "]".repeat(127)).as_bytes()
+    ));
+}
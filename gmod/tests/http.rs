@@ -0,0 +1,88 @@
+//! `gmod::http` against a stand-in of `HTTP`, which keeps the requests it gets so the test can answer them.
+
+#![cfg(feature = "testing")]
+
+use std::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use gmod::{
+    http::{self, Request, Response},
+    testing::TestState,
+};
+
+const FAKE_HTTP: &str = r#"
+requests = {}
+function HTTP(request)
+    if request.url == "refused" then return false end
+    table.insert(requests, request)
+    return true
+end
+"#;
+
+fn poll(fetch: &mut http::Fetch) -> Poll<anyhow::Result<Response>> {
+    pin!(fetch).poll(&mut Context::from_waker(Waker::noop()))
+}
+
+#[test]
+fn http() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(FAKE_HTTP).unwrap();
+
+    let mut ok = http::fetch(
+        lua,
+        Request::post("https://example.com/api")
+            .header("Accept", "text/plain")
+            .body("application/json", "{}")
+            .timeout(Duration::from_secs(5)),
+    );
+    let mut failed = http::fetch(lua, Request::get("https://example.com/down"));
+    let mut pending = http::fetch(lua, Request::get("https://example.com/slow"));
+    assert!(poll(&mut ok).is_pending());
+    assert_eq!(lua.get_top(), 0);
+
+    assert_eq!(
+        test.eval::<(String, String, String, String, f64)>(
+            "requests[1].method, requests[1].headers.Accept, requests[1].type, requests[1].body, requests[1].timeout"
+        )
+        .unwrap(),
+        ("POST".to_owned(), "text/plain".to_owned(), "application/json".to_owned(), "{}".to_owned(), 5.0)
+    );
+
+    test.exec(
+        r#"
+        requests[1].success(200, "hello", { ["Content-Type"] = "text/plain" })
+        requests[2].failed("unsuccessful")
+        "#,
+    )
+    .unwrap();
+    let Poll::Ready(Ok(response)) = poll(&mut ok) else {
+        panic!("the request didn't succeed");
+    };
+    assert!(response.is_success());
+    assert_eq!(response.text().unwrap(), "hello");
+    assert_eq!(response.header("content-type"), Some("text/plain"));
+    let Poll::Ready(Err(err)) = poll(&mut failed) else {
+        panic!("the request didn't fail");
+    };
+    assert_eq!(
+        err.to_string(),
+        "HTTP request to https://example.com/down failed: unsuccessful"
+    );
+
+    let Poll::Ready(Err(err)) = poll(&mut http::fetch(lua, Request::get("refused"))) else {
+        panic!("the request wasn't refused");
+    };
+    assert!(err.to_string().contains("refused"));
+
+    // the callbacks don't reach the module anymore once it's unloaded
+    http::unload(lua);
+    assert!(matches!(poll(&mut pending), Poll::Ready(Err(_))));
+    test.exec("requests[3].success(200, '', {})").unwrap();
+}
@@ -0,0 +1,123 @@
+//! `gmod::ipc` over a unix socket in a temporary directory.
+
+#![cfg(all(feature = "testing", feature = "ipc", unix))]
+
+use std::{
+    io::{Read, Write},
+    os::unix::{fs::PermissionsExt, net::UnixStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use gmod::{
+    ipc::{self, Framing, MAX_MESSAGE_SIZE},
+    testing::TestState,
+};
+use serde_json::{json, Value};
+
+fn socket_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gmod-rs-ipc-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn wait_until(mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn messages_are_delivered_on_the_tick() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let path = socket_path("messages.sock");
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let server = ipc::listen(path.to_str().unwrap())
+        .on_message({
+            let received = received.clone();
+            move |_, peer, message| {
+                let _ = peer.send(&json!({ "echo": message }));
+                received.lock().unwrap().push(message);
+            }
+        })
+        .start()
+        .unwrap();
+
+    // only the user running the game can connect
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let mut client = UnixStream::connect(&path).unwrap();
+    client.write_all(b"{\"type\":\"ping\"}\n").unwrap();
+    wait_until(|| {
+        test.tick(Duration::from_millis(15));
+        !received.lock().unwrap().is_empty()
+    });
+    assert_eq!(*received.lock().unwrap(), [json!({ "type": "ping" })]);
+
+    let mut reply = [0; 64];
+    let len = client.read(&mut reply).unwrap();
+    let reply: Value = serde_json::from_slice(&reply[..len]).unwrap();
+    assert_eq!(reply, json!({ "echo": { "type": "ping" } }));
+
+    drop(server);
+}
+
+#[test]
+fn broadcasts_use_the_server_framing() {
+    let Some(_test) = TestState::new_or_skip() else {
+        return;
+    };
+    let path = socket_path("broadcast.sock");
+    let server = ipc::listen(path.to_str().unwrap())
+        .framing(Framing::LengthPrefixed)
+        .start()
+        .unwrap();
+
+    let mut client = UnixStream::connect(&path).unwrap();
+    wait_until(|| server.peers().len() == 1);
+    assert_eq!(server.broadcast(&json!({ "map": "gm_construct" })), 1);
+
+    let mut len = [0; 4];
+    client.read_exact(&mut len).unwrap();
+    let mut message = vec![0; u32::from_le_bytes(len) as usize];
+    client.read_exact(&mut message).unwrap();
+    let message: Value = serde_json::from_slice(&message).unwrap();
+    assert_eq!(message, json!({ "map": "gm_construct" }));
+}
+
+#[test]
+fn endless_lines_close_the_connection() {
+    let Some(_test) = TestState::new_or_skip() else {
+        return;
+    };
+    let path = socket_path("endless.sock");
+    let server = ipc::listen(path.to_str().unwrap()).start().unwrap();
+
+    let mut client = UnixStream::connect(&path).unwrap();
+    wait_until(|| server.peers().len() == 1);
+    let chunk = vec![b'a'; 64 * 1024];
+    let mut sent = 0;
+    while sent <= MAX_MESSAGE_SIZE {
+        if client.write_all(&chunk).is_err() {
+            break;
+        }
+        sent += chunk.len();
+    }
+    wait_until(|| server.peers().is_empty());
+}
+
+#[test]
+fn files_that_are_not_sockets_are_left_alone() {
+    let path = socket_path("not-a-socket.sock");
+    std::fs::write(&path, "keep me").unwrap();
+
+    assert!(ipc::listen(path.to_str().unwrap()).start().is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+    std::fs::remove_file(&path).unwrap();
+}
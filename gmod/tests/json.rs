@@ -0,0 +1,85 @@
+//! `push_json` and `table_to_json`.
+
+#![cfg(all(feature = "testing", feature = "json"))]
+
+use gmod::testing::TestState;
+use serde_json::json;
+
+#[test]
+fn push_json() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    let value = json!({ "players": [1, 2.5, "three"], "nested": { "a\u{0}b": true, "1": null } });
+    lua.push_json(&value).unwrap();
+    lua.set_global(c"value");
+    assert_eq!(test.eval::<f64>("#value.players").unwrap(), 3.0);
+    assert_eq!(test.eval::<f64>("value.players[2]").unwrap(), 2.5);
+    assert!(test.eval::<bool>("value.nested['a\\0b']").unwrap());
+    assert!(test
+        .eval::<bool>("next(value.nested, next(value.nested)) == nil")
+        .unwrap());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn table_to_json() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    test.exec(
+        r#"value = {
+            list = { 1, 2, 3.5 },
+            sparse = { [1] = "a", [3] = "c" },
+            empty = {},
+            flags = { [true] = 1 },
+            name = "gm_construct",
+        }"#,
+    )
+    .unwrap();
+    lua.get_global(c"value");
+    assert_eq!(
+        lua.table_to_json(-1).unwrap(),
+        json!({
+            "list": [1, 2, 3.5],
+            "sparse": { "1": "a", "3": "c" },
+            "empty": [],
+            "flags": { "true": 1 },
+            "name": "gm_construct",
+        })
+    );
+    lua.pop();
+
+    // `#value` is 4 and there are 4 keys, but it isn't an array
+    test.exec("value = { 1, 2, nil, 4 } value.x = 1").unwrap();
+    assert_eq!(test.eval::<f64>("#value").unwrap(), 4.0);
+    lua.get_global(c"value");
+    assert_eq!(
+        lua.table_to_json(-1).unwrap(),
+        json!({ "1": 1, "2": 2, "4": 4, "x": 1 })
+    );
+    lua.pop();
+
+    // what JSON can't hold
+    for (code, error) in [
+        ("{ print }", "a function can't be converted to JSON"),
+        ("{ 0/0 }", "NaN can't be converted to JSON"),
+        (
+            "{ '\\255' }",
+            "a string that isn't UTF-8 can't be converted to JSON",
+        ),
+    ] {
+        test.exec(&format!("value = {code}")).unwrap();
+        lua.get_global(c"value");
+        assert_eq!(lua.table_to_json(-1).unwrap_err().to_string(), error);
+        lua.pop();
+    }
+    test.exec("value = {} value.self = value").unwrap();
+    lua.get_global(c"value");
+    assert!(lua.table_to_json(-1).is_err());
+    assert_eq!(lua.get_top(), 1);
+}
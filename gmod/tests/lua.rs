@@ -0,0 +1,374 @@
+//! `State` helpers that need a real Lua state. Skipped when LuaJIT can't be found, see `gmod::testing`.
+
+#![cfg(feature = "testing")]
+
+use std::{cell::Cell, rc::Rc};
+
+use gmod::{
+    lua::{
+        debug_hook::{self, HookAction, HookEvent, HookEvents},
+        intern, CachedLuaFunction,
+    },
+    testing::TestState,
+    userdata::{in_keys, CMoveData, CUserCmd, TaggedUserData, UserData},
+};
+
+#[test]
+fn push_interned() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    const NAME: &str = "PlayerTick";
+    for _ in 0..3 {
+        lua.push_interned(NAME);
+        assert_eq!(lua.get_string(-1).as_deref(), Some(NAME));
+        lua.pop();
+    }
+    lua.push_interned("Think");
+    lua.pop();
+    assert_eq!(lua.get_top(), 0);
+
+    let stats = intern::stats();
+    assert_eq!((stats.entries, stats.hits, stats.misses), (2, 2, 2));
+
+    intern::clear(lua);
+    assert_eq!(intern::stats(), intern::InternStats::default());
+    lua.push_interned(NAME);
+    assert_eq!(lua.get_string(-1).as_deref(), Some(NAME));
+}
+
+#[test]
+fn cached_lua_function() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    test.exec("lib = { add = function(a, b) return a + b end }")
+        .unwrap();
+    let mut add = CachedLuaFunction::new(lua, &["lib", "add"]).unwrap();
+    assert_eq!(add.call(lua).arg(1).arg(2).call::<f64>().unwrap(), 3.0);
+
+    test.exec("lib.add = function(a, b) return a * b end")
+        .unwrap();
+    assert_eq!(add.call(lua).arg(2).arg(5).call::<f64>().unwrap(), 7.0);
+    add.refresh(lua).unwrap();
+    assert_eq!(add.call(lua).arg(2).arg(5).call::<f64>().unwrap(), 10.0);
+
+    assert!(CachedLuaFunction::new(lua, &["lib", "missing"]).is_err());
+    assert!(CachedLuaFunction::new(lua, &["nope", "add"]).is_err());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn ensure_stack() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    assert!(lua.ensure_stack(1000).is_ok());
+    assert!(lua.ensure_stack(1_000_000).is_err());
+
+    test.exec("function count(...) return select('#', ...) end")
+        .unwrap();
+    let mut call = lua.call_global("count");
+    for i in 0..100_000 {
+        call = call.arg(i);
+    }
+    let err = call.call::<f64>().unwrap_err();
+    assert!(format!("{err:#}").contains("too many arguments to count: stack overflow"));
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn debug_hook_aborts_runaway_lua() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    let mut counts = 0;
+    debug_hook::set(lua, HookEvents::count(1000), move |_, event| {
+        assert_eq!(event, HookEvent::Count);
+        counts += 1;
+        if counts == 10 {
+            HookAction::Error("took too long".to_owned())
+        } else {
+            HookAction::Continue
+        }
+    })
+    .unwrap();
+    assert!(debug_hook::is_set(lua));
+
+    let err = test.exec("jit.off() while true do end").unwrap_err();
+    assert!(err.to_string().contains("took too long"));
+
+    debug_hook::clear(lua);
+    assert!(!debug_hook::is_set(lua));
+    test.exec("for i = 1, 100000 do end").unwrap();
+}
+
+#[test]
+fn gc() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    lua.gc_collect();
+    let before = lua.gc_count_kb();
+    test.exec("GARBAGE = {} for i = 1, 10000 do GARBAGE[i] = { i } end")
+        .unwrap();
+    assert!(lua.gc_count_kb() > before + 100.0);
+
+    test.exec("GARBAGE = nil").unwrap();
+    lua.gc_collect();
+    assert!(lua.gc_count_kb() < before + 100.0);
+
+    let pause = lua.gc_set_pause(150);
+    assert_eq!(lua.gc_set_pause(pause), 150);
+}
+
+#[gmod::lua_function]
+fn greet(lua: gmod::lua::State) -> anyhow::Result<(String,)> {
+    Ok((format!("Hello, {}!", lua.check_string(1)?),))
+}
+
+#[test]
+fn errors_point_at_the_lua_caller() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    lua.push_function(greet);
+    lua.set_global(c"Greet");
+
+    assert_eq!(
+        test.eval::<String>("Greet('Garry')").unwrap(),
+        "Hello, Garry!"
+    );
+    let err = test.exec("\nGreet({})").unwrap_err();
+    // followed by the Rust backtrace, when enabled
+    assert_eq!(
+        err.to_string().lines().next().unwrap(),
+        "test:2: bad argument #1 to 'Greet' (string expected, got table)"
+    );
+}
+
+#[gmod::lua_function]
+unsafe fn upvalues(lua: gmod::lua::State) -> i32 {
+    unsafe {
+        lua.push_closure_arg(1);
+        lua.push_closure_arg(2);
+    }
+    2
+}
+
+#[test]
+fn lua_methods() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    gmod::lua_methods!(lua => {
+        "Greet" => greet,
+        "Double" => |lua| {
+            lua.push_number(lua.to_number(1) * 2.0);
+            1
+        },
+    });
+    lua.set_global(c"methods");
+    test.exec("extended = {}").unwrap();
+    lua.get_global(c"extended");
+    lua.push_nil();
+    gmod::lua_methods!(lua, -2 => {
+        "Upvalues" [lua.push_number(1.0), lua.push_string("two")] => upvalues,
+    });
+    lua.pop_n(2);
+    assert_eq!(lua.get_top(), 0);
+
+    assert_eq!(
+        test.eval::<String>("methods.Greet('Garry')").unwrap(),
+        "Hello, Garry!"
+    );
+    assert_eq!(test.eval::<f64>("methods.Double(21)").unwrap(), 42.0);
+    assert_eq!(
+        test.eval::<String>("(function() local a, b = extended.Upvalues() return a .. b end)()")
+            .unwrap(),
+        "1two"
+    );
+}
+
+#[test]
+fn get_userdata_object() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    // what GMod pushes for a CUserCmd: a tagged pointer to the engine's object
+    let mut cmd = [0u64; 16];
+    lua.new_metatable(c"CUserCmd");
+    lua.pop();
+    lua.new_userdata(
+        TaggedUserData {
+            data: cmd.as_mut_ptr().cast(),
+            r#type: UserData::UserCmd,
+        },
+        Some(c"CUserCmd"),
+    );
+
+    let user_cmd = unsafe { lua.get_userdata_object::<CUserCmd>(-1) }.unwrap();
+    user_cmd.set_key(in_keys::JUMP | in_keys::DUCK, true);
+    user_cmd.set_key(in_keys::DUCK, false);
+    assert!(user_cmd.key_down(in_keys::JUMP));
+    assert!(!user_cmd.key_down(in_keys::DUCK));
+    assert!(unsafe { lua.get_userdata_object::<CMoveData>(-1) }.is_none());
+    lua.pop();
+
+    lua.push_number(1);
+    assert!(unsafe { lua.get_userdata_object::<CUserCmd>(-1) }.is_none());
+    lua.pop();
+    let buttons = unsafe {
+        (cmd.as_ptr() as *const u8)
+            .add(std::mem::size_of::<usize>() + 32)
+            .cast::<i32>()
+            .read()
+    };
+    assert_eq!(buttons, in_keys::JUMP);
+}
+
+#[test]
+fn eval() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    let top = lua.get_top();
+
+    assert_eq!(lua.eval::<f64>("return 1 + 1").unwrap(), 2.0);
+    assert_eq!(
+        lua.eval::<(String, Option<f64>)>("local s = 'a' .. 'b' return s")
+            .unwrap(),
+        ("ab".to_owned(), None)
+    );
+    lua.eval::<()>("evaluated = true").unwrap();
+    assert!(test.eval::<bool>("evaluated").unwrap());
+
+    let err = lua.eval::<()>("error('nope')").unwrap_err();
+    assert!(err.to_string().contains("eval:1: nope"), "{err}");
+    let err = lua.eval::<()>("return +").unwrap_err();
+    assert!(err.to_string().contains("eval:1:"), "{err}");
+    let err = lua.eval::<f64>("return 'x'").unwrap_err();
+    assert!(err.to_string().contains("result #1 of eval"), "{err}");
+
+    let path = std::env::temp_dir().join(format!("gmod-rs-eval-{}.lua", std::process::id()));
+    std::fs::write(&path, "local a, b = 20, 22\nreturn a + b").unwrap();
+    let answer = lua.eval_file::<f64>(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(answer.unwrap(), 42.0);
+    assert!(lua.eval_file::<()>(&path).is_err());
+
+    assert_eq!(lua.get_top(), top);
+}
+
+#[test]
+fn push_rust_closure() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    struct DropFlag(Rc<Cell<bool>>);
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    let calls = Rc::new(Cell::new(0));
+    let dropped = Rc::new(Cell::new(false));
+    let flag = DropFlag(dropped.clone());
+    lua.push_rust_closure({
+        let calls = calls.clone();
+        move |lua| -> anyhow::Result<(String,)> {
+            let _ = &flag;
+            calls.set(calls.get() + 1);
+            let name = lua.check_string(1)?;
+            if name == "again" {
+                // Lua calling the closure while it runs
+                return Ok((lua.eval::<String>("return Greet('nested')")?,));
+            }
+            Ok((format!("hello {name} #{}", calls.get()),))
+        }
+    });
+    lua.set_global(c"Greet");
+
+    assert_eq!(
+        test.eval::<String>("Greet('there')").unwrap(),
+        "hello there #1"
+    );
+    assert_eq!(
+        test.eval::<String>("Greet('again')").unwrap(),
+        "hello nested #3"
+    );
+    let err = test.exec("Greet({})").unwrap_err();
+    assert!(err.to_string().contains("bad argument #1"), "{err}");
+    assert_eq!(calls.get(), 4);
+
+    test.exec("Greet = nil collectgarbage() collectgarbage()")
+        .unwrap();
+    assert!(dropped.get());
+
+    // after unloading, closures are leaked instead of calling into the unloaded module
+    let dropped = Rc::new(Cell::new(false));
+    let flag = DropFlag(dropped.clone());
+    lua.push_rust_closure(move |_| {
+        let _ = &flag;
+    });
+    lua.pop();
+    gmod::lua::unload_closures(lua);
+    test.exec("collectgarbage() collectgarbage()").unwrap();
+    assert!(!dropped.get());
+}
+
+#[test]
+fn module_data() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Settings(u32);
+
+    assert_eq!(lua.get_module_data::<Settings>(), None);
+    assert_eq!(lua.set_module_data(Settings(1)), None);
+    assert_eq!(lua.set_module_data(Settings(2)), Some(Settings(1)));
+    // each type has its own slot
+    lua.set_module_data(String::from("name"));
+    assert_eq!(lua.get_module_data::<Settings>(), Some(Settings(2)));
+    assert_eq!(lua.get_module_data::<String>().as_deref(), Some("name"));
+
+    let doubled = lua.with_module_data(|settings: &mut Settings| {
+        // taken out while borrowed
+        assert_eq!(lua.get_module_data::<Settings>(), None);
+        settings.0 *= 2;
+        settings.0
+    });
+    assert_eq!(doubled, Some(4));
+    assert_eq!(lua.take_module_data::<Settings>(), Some(Settings(4)));
+    assert_eq!(lua.get_module_data::<Settings>(), None);
+
+    // unloading drops what's left
+    let shared = Rc::new(());
+    lua.set_module_data(shared.clone());
+    assert_eq!(Rc::strong_count(&shared), 2);
+    gmod::lua::unload_module_data(lua);
+    assert_eq!(Rc::strong_count(&shared), 1);
+    assert_eq!(lua.get_module_data::<String>(), None);
+}
@@ -0,0 +1,64 @@
+//! `#[gmod_module]` structs, opened and closed like the generated exports do.
+
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use gmod::{
+    lua::State,
+    module::{self, GmodModule, OpenResult},
+    testing::TestState,
+};
+
+#[gmod::gmod_module]
+#[derive(Default)]
+struct Counter {
+    ticks: u32,
+}
+
+impl GmodModule for Counter {
+    const THINK: bool = true;
+
+    fn open(&mut self, lua: State) -> OpenResult {
+        lua.push_function(ticks);
+        lua.set_global(c"CounterTicks");
+        Ok(())
+    }
+
+    fn close(&mut self, lua: State) {
+        lua.push_number(self.ticks as f64);
+        lua.set_global(c"CounterClosedAt");
+    }
+
+    fn think(&mut self, _lua: State) {
+        self.ticks += 1;
+        // the module is lent to `think`
+        assert!(module::with(|counter: &mut Counter| counter.ticks).is_none());
+    }
+}
+
+#[gmod::lua_function]
+fn ticks(_lua: State) -> (f64,) {
+    (module::with(|counter: &mut Counter| counter.ticks).unwrap_or(0) as f64,)
+}
+
+#[test]
+fn open_think_close() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    module::open::<Counter>(lua).unwrap();
+    test.tick(Duration::from_millis(15));
+    test.tick(Duration::from_millis(15));
+    assert_eq!(test.eval::<f64>("CounterTicks()").unwrap(), 2.0);
+    assert_eq!(module::with(|counter: &mut Counter| counter.ticks), Some(2));
+
+    module::close::<Counter>(lua);
+    gmod::panic::uninstall();
+    assert_eq!(test.eval::<f64>("CounterClosedAt").unwrap(), 2.0);
+    assert_eq!(module::with(|counter: &mut Counter| counter.ticks), None);
+    test.tick(Duration::from_millis(15));
+    assert!(test.errors().is_empty());
+}
@@ -0,0 +1,83 @@
+//! `net::receive_with` and `NetReader`, with `net` faked in Lua over a list of bytes.
+
+#![cfg(feature = "testing")]
+
+use std::{cell::RefCell, rc::Rc};
+
+use gmod::{net, testing::TestState};
+
+const FAKE_NET: &str = r#"
+net = { Receivers = {} }
+function net.Receive(name, func) net.Receivers[name] = func end
+function net.BytesLeft() return #message - offset end
+function net.ReadUInt(bits)
+    local value = 0
+    for i = bits / 8, 1, -1 do value = value * 256 + (message:byte(offset + i) or 0) end
+    offset = offset + bits / 8
+    return value
+end
+function net.ReadData(len)
+    asked = len
+    local data = message:sub(offset + 1, offset + len)
+    offset = offset + len
+    return data
+end
+function receive(name, data)
+    message, offset = data, 0
+    net.Receivers[name](#data * 8, nil)
+end
+"#;
+
+#[test]
+fn read_bytes_is_capped_at_what_is_left() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(FAKE_NET).unwrap();
+
+    let read = Rc::new(RefCell::new(Vec::new()));
+    let reads = read.clone();
+    net::receive_with(lua, "bytes", move |net| {
+        reads.borrow_mut().push(net.read_bytes())
+    });
+
+    test.exec(r#"receive("bytes", "\3\0\0\0abcdef")"#).unwrap();
+    assert_eq!(test.eval::<f64>("asked").unwrap(), 3.0);
+
+    // the prefix claims far more than the message has
+    test.exec(r#"receive("bytes", "\255\255\255\0xyz")"#)
+        .unwrap();
+    assert_eq!(test.eval::<f64>("asked").unwrap(), 3.0);
+
+    assert_eq!(*read.borrow(), [b"abc".to_vec(), b"xyz".to_vec()]);
+    net::unload(lua);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn a_panicking_receiver_keeps_receiving() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(FAKE_NET).unwrap();
+    gmod::panic::install();
+
+    let calls = Rc::new(RefCell::new(0));
+    let counted = calls.clone();
+    net::receive_with(lua, "panics", move |_| {
+        *counted.borrow_mut() += 1;
+        if *counted.borrow() == 1 {
+            panic!("first message");
+        }
+    });
+
+    let err = test.exec(r#"receive("panics", "")"#).unwrap_err();
+    assert!(err.to_string().contains("first message"), "{err}");
+    test.exec(r#"receive("panics", "")"#).unwrap();
+    assert_eq!(*calls.borrow(), 2);
+
+    net::unload(lua);
+    gmod::panic::uninstall();
+}
@@ -0,0 +1,99 @@
+//! `NetRegistry`, with `net` faked in Lua and warnings captured from `MsgC`.
+
+#![cfg(feature = "testing")]
+
+use gmod::{
+    net::{self, NetRegistry},
+    testing::TestState,
+};
+
+const SETUP: &str = r#"
+added, warnings, sent = {}, {}, {}
+local add = util.AddNetworkString
+function util.AddNetworkString(name)
+    table.insert(added, name)
+    return add(name)
+end
+function MsgC(_, text) table.insert(warnings, text) end
+
+net = {}
+function net.Start(name) sent.name = name end
+function net.Broadcast() table.insert(sent, sent.name) end
+"#;
+
+fn add_twice(lua: gmod::lua::State) {
+    NetRegistry::add(lua, &["my_addon_update"]);
+}
+
+#[test]
+fn warns_on_strings_added_from_two_places() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(SETUP).unwrap();
+
+    // the same place, e.g. a function called for every message, is fine
+    add_twice(lua);
+    add_twice(lua);
+    assert_eq!(test.eval::<usize>("#warnings").unwrap(), 0);
+    assert_eq!(test.eval::<usize>("#added").unwrap(), 1);
+
+    net::add_network_strings(lua, &["My_Addon_Update", "my_addon_request"]);
+    let warning = test.eval::<String>("warnings[1]").unwrap();
+    assert!(warning.contains("\"My_Addon_Update\""), "{warning}");
+    assert!(warning.contains("net_registry.rs"), "{warning}");
+    assert_eq!(test.eval::<usize>("#added").unwrap(), 2);
+
+    let names: Vec<_> = NetRegistry::entries()
+        .into_iter()
+        .map(|string| (string.name, string.added))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            ("my_addon_request".to_string(), true),
+            ("my_addon_update".to_string(), true)
+        ]
+    );
+    assert!(NetRegistry::contains("MY_ADDON_REQUEST"));
+    assert!(!NetRegistry::contains("my_addon_debug"));
+}
+
+#[test]
+fn lazy_strings_are_added_on_first_send() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(SETUP).unwrap();
+
+    NetRegistry::add_lazy(&["my_addon_debug"]);
+    assert!(NetRegistry::contains("my_addon_debug"));
+    assert!(!NetRegistry::entries()[0].added);
+    assert_eq!(test.eval::<usize>("#added").unwrap(), 0);
+
+    net::start("my_addon_debug").broadcast(lua).unwrap();
+    net::start("my_addon_debug").broadcast(lua).unwrap();
+    assert_eq!(test.eval::<usize>("#added").unwrap(), 1);
+    assert_eq!(test.eval::<usize>("#sent").unwrap(), 2);
+    assert!(NetRegistry::entries()[0].added);
+    assert!(test.errors().is_empty());
+
+    net::unload(lua);
+    assert!(NetRegistry::entries().is_empty());
+}
+
+#[test]
+fn failing_to_add_is_reported() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec("function util.AddNetworkString() error('too many network strings') end")
+        .unwrap();
+
+    NetRegistry::add(lua, &["my_addon_update"]);
+    let errors = test.errors();
+    assert!(errors[0].contains("too many network strings"), "{errors:?}");
+}
@@ -0,0 +1,123 @@
+//! `net::send` and `Targets`, with the send functions of `net` faked in Lua.
+
+#![cfg(feature = "testing")]
+
+use gmod::{
+    net::{self, Targets},
+    testing::TestState,
+    userdata::Vector,
+};
+
+const SETUP: &str = r#"
+local Player = {}
+Player.__index = Player
+Player.__tostring = function(self) return "player " .. self.id end
+function Player:IsValid() return true end
+function Player:IsPlayer() return true end
+
+function isentity(v) return getmetatable(v) == Player end
+players = {
+    setmetatable({ id = 1 }, Player),
+    setmetatable({ id = 2 }, Player),
+}
+player = { GetAll = function() return players end }
+
+function Vector(x, y, z) return x .. " " .. y .. " " .. z end
+
+local function describe(target)
+    if type(target) ~= "table" or getmetatable(target) then return tostring(target) end
+    local names = {}
+    for i, ply in ipairs(target) do names[i] = tostring(ply) end
+    return "{" .. table.concat(names, ", ") .. "}"
+end
+
+sent = {}
+local message
+net = {}
+function net.Start(name, unreliable)
+    if util.NetworkStringToID(name) == 0 then error("Calling net.Start with unpooled message name") end
+    message = { name = name, values = {} }
+end
+function net.WriteUInt(v) table.insert(message.values, v) end
+function net.WriteString(v) table.insert(message.values, v) end
+for _, send in ipairs({ "Send", "SendOmit", "SendPVS", "SendPAS", "Broadcast", "SendToServer" }) do
+    net[send] = function(target)
+        table.insert(sent, message.name .. " " .. send .. " " .. describe(target) .. " " .. table.concat(message.values, ","))
+    end
+end
+"#;
+
+#[test]
+fn send_calls_the_function_of_the_targets() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(SETUP).unwrap();
+    net::add_network_strings(lua, &["update"]);
+
+    let players = gmod::player::all(lua);
+    let pos = Vector {
+        x: 1.0,
+        y: 2.0,
+        z: 3.0,
+    };
+    let targets = [
+        Targets::All,
+        Targets::Player(&players[1]),
+        Targets::Players(&players),
+        Targets::AllExcept(&players[..1]),
+        Targets::Pvs(pos),
+        Targets::Pas(pos),
+        Targets::Server,
+    ];
+    for (i, targets) in targets.into_iter().enumerate() {
+        net::send(lua, "update", targets, |msg| {
+            msg.write_u8(i as u8).write_string("hi")
+        })
+        .unwrap();
+    }
+
+    let sent: Vec<String> = (1..=7)
+        .map(|i| test.eval(&format!("sent[{i}]")).unwrap())
+        .collect();
+    assert_eq!(
+        sent,
+        [
+            "update Broadcast nil 0,hi",
+            "update Send player 2 1,hi",
+            "update Send {player 1, player 2} 2,hi",
+            "update SendOmit {player 1} 3,hi",
+            "update SendPVS 1 2 3 4,hi",
+            "update SendPAS 1 2 3 5,hi",
+            "update SendToServer nil 6,hi",
+        ]
+    );
+    assert!(test.errors().is_empty());
+}
+
+#[test]
+fn lua_errors_are_returned() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(SETUP).unwrap();
+    test.exec("function net.Send(ply) if not ply:IsValid() then error('Tried to send to a NULL player') end end")
+        .unwrap();
+    net::add_network_strings(lua, &["update"]);
+
+    let err = net::send(lua, "unknown", Targets::All, |msg| msg).unwrap_err();
+    assert!(err.to_string().contains("unpooled message name"), "{err}");
+
+    test.exec("players[1].IsValid = function() return false end")
+        .unwrap();
+    let players = gmod::player::all(lua);
+    let err = net::send(lua, "update", Targets::Player(&players[0]), |msg| {
+        msg.write_u8(1)
+    })
+    .unwrap_err();
+    assert!(err.to_string().contains("NULL player"), "{err}");
+    assert!(err.to_string().contains("\"update\""), "{err}");
+    assert_eq!(lua.get_top(), 0);
+}
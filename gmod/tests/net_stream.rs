@@ -0,0 +1,105 @@
+//! `net::stream::receive`, with `net` faked in Lua over a list of bytes and players faked as tables.
+
+#![cfg(feature = "testing")]
+
+use std::{cell::RefCell, rc::Rc};
+
+use gmod::{
+    net::{self, stream},
+    testing::TestState,
+};
+
+const FAKE_NET: &str = r#"
+net = { Receivers = {} }
+function net.Receive(name, func) net.Receivers[name] = func end
+function net.BytesLeft() return #message - offset end
+function net.ReadUInt(bits)
+    local value = 0
+    for i = bits / 8, 1, -1 do value = value * 256 + (message:byte(offset + i) or 0) end
+    offset = offset + bits / 8
+    return value
+end
+function net.ReadData(len)
+    local data = message:sub(offset + 1, offset + len)
+    offset = offset + len
+    return data
+end
+local Player = {}
+Player.__index = Player
+function Player:UserID() return self.id end
+function player(id) return setmetatable({ id = id }, Player) end
+function receive(name, data, ply)
+    message, offset = data, 0
+    net.Receivers[name](#data * 8, ply)
+end
+"#;
+
+fn chunk(id: u32, index: u32, count: u32, compression: u8, data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::new();
+    for field in [id, index, count] {
+        chunk.extend_from_slice(&field.to_le_bytes());
+    }
+    chunk.push(compression);
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    chunk
+}
+
+fn deliver(test: &TestState, name: &str, chunk: &[u8], sender: u32) {
+    let lua = test.lua();
+    lua.push_binary_string(chunk);
+    lua.set_global(c"data");
+    test.exec(&format!("receive({name:?}, data, player({sender}))"))
+        .unwrap();
+}
+
+#[test]
+fn incomplete_streams_are_forgotten_when_their_sender_leaves() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(FAKE_NET).unwrap();
+
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let receiving = received.clone();
+    stream::receive(lua, "blob", move |_, data| {
+        receiving.borrow_mut().push(data)
+    });
+
+    deliver(&test, "blob", &chunk(1, 0, 2, 0, b"a"), 5);
+    deliver(&test, "blob", &chunk(1, 1, 2, 0, b"b"), 5);
+    assert_eq!(*received.borrow(), [b"ab".to_vec()]);
+
+    deliver(&test, "blob", &chunk(2, 0, 2, 0, b"a"), 5);
+    test.exec("hook.Run('PlayerDisconnected', player(5))")
+        .unwrap();
+    deliver(&test, "blob", &chunk(2, 1, 2, 0, b"b"), 5);
+    assert_eq!(received.borrow().len(), 1);
+
+    net::unload(lua);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_streams_are_capped_while_decompressing() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(FAKE_NET).unwrap();
+
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let receiving = received.clone();
+    stream::receive_with_limit(lua, "small", 64, move |_, data| {
+        receiving.borrow_mut().push(data)
+    });
+
+    let fits = zstd::bulk::compress(&[1; 64], 0).unwrap();
+    deliver(&test, "small", &chunk(1, 0, 1, 2, &fits), 5);
+    let too_large = zstd::bulk::compress(&[1; 4096], 0).unwrap();
+    deliver(&test, "small", &chunk(2, 0, 1, 2, &too_large), 5);
+    assert_eq!(*received.borrow(), [vec![1; 64]]);
+
+    net::unload(lua);
+}
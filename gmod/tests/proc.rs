@@ -0,0 +1,94 @@
+//! `gmod::proc` with a shell whose background job outlives it.
+
+#![cfg(all(feature = "testing", unix))]
+
+use std::{
+    process::Command,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use gmod::{
+    proc::{self, Exit},
+    testing::TestState,
+};
+
+#[test]
+fn exit_is_delivered_while_a_grandchild_holds_the_pipes_open() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let exit = Arc::new(Mutex::new(None));
+    let mut command = Command::new("sh");
+    command.args(["-c", "sleep 10 & echo hello; echo world >&2"]);
+    let handle = {
+        let (out, err, exit) = (lines.clone(), lines.clone(), exit.clone());
+        proc::spawn(command)
+            .on_stdout(move |_, line| out.lock().unwrap().push(line))
+            .on_stderr(move |_, line| err.lock().unwrap().push(line))
+            .on_exit(move |_, status| *exit.lock().unwrap() = Some(status))
+            .start()
+            .unwrap()
+    };
+
+    let started = Instant::now();
+    while exit.lock().unwrap().is_none() {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "on_exit wasn't called"
+        );
+        test.tick(Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    assert!(!handle.is_running());
+    assert!(matches!(*exit.lock().unwrap(), Some(Exit::Exited(status)) if status.success()));
+    let mut lines = lines.lock().unwrap().clone();
+    lines.sort();
+    assert_eq!(lines, ["hello", "world"]);
+
+    assert!(gmod::shutdown::run().is_empty());
+}
+
+#[test]
+fn output_that_isnt_utf8_keeps_being_read() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+
+    // the invalid line comes first, then more than a pipe can buffer
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let exit = Arc::new(Mutex::new(None));
+    let mut command = Command::new("sh");
+    command.args([
+        "-c",
+        "printf 'caf\\351\\r\\n'; head -c 200000 /dev/zero | tr '\\0' a; echo; echo done",
+    ]);
+    let _handle = {
+        let (out, exit) = (lines.clone(), exit.clone());
+        proc::spawn(command)
+            .on_stdout(move |_, line| out.lock().unwrap().push(line))
+            .on_exit(move |_, status| *exit.lock().unwrap() = Some(status))
+            .start()
+            .unwrap()
+    };
+
+    let started = Instant::now();
+    while exit.lock().unwrap().is_none() || lines.lock().unwrap().len() < 3 {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "the output wasn't drained"
+        );
+        test.tick(Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let lines = lines.lock().unwrap().clone();
+    assert_eq!(lines[0], "caf\u{fffd}");
+    assert_eq!(lines[1].len(), 200_000);
+    assert_eq!(lines[2], "done");
+
+    assert!(gmod::shutdown::run().is_empty());
+}
@@ -0,0 +1,147 @@
+//! A module loaded into two states at once: the test state, which is the server, and a second state playing the menu.
+
+#![cfg(feature = "testing")]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use gmod::{
+    lifecycle,
+    lua::{intern, task_queue, LuaRef, State, LUA_SHARED},
+    realm::Realm,
+    testing::TestState,
+};
+
+/// A state with just enough of the menu's globals for the task queue.
+fn menu_state() -> State {
+    let menu = unsafe { State::new() }.unwrap();
+    menu.eval::<()>(
+        "MENU_DLL = true
+        timer = {
+            Create = function(_, _, _, f) think = f end,
+            Remove = function() think = nil end,
+        }",
+    )
+    .unwrap();
+    menu
+}
+
+#[allow(static_mut_refs)]
+fn close(lua: State) {
+    unsafe { (LUA_SHARED.lua_close.as_ref().unwrap())(lua) };
+}
+
+#[test]
+fn queues_are_kept_per_realm() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    let menu = menu_state();
+    assert_eq!(lua.realm(), Realm::Server);
+    assert_eq!(menu.realm(), Realm::Menu);
+
+    task_queue::load(menu);
+    assert_eq!(task_queue::open_realms(), [Realm::Server, Realm::Menu]);
+    assert_eq!(task_queue::default_realm(), Realm::Server);
+
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    let record = |ran: &Arc<Mutex<Vec<Realm>>>| {
+        let ran = ran.clone();
+        move |lua: State| ran.lock().unwrap().push(lua.realm())
+    };
+    gmod::wait_lua_tick(String::new(), record(&ran)).unwrap();
+    task_queue::wait_lua_tick_in(Realm::Menu, String::new(), record(&ran)).unwrap();
+    assert!(task_queue::wait_lua_tick_in(Realm::Client, String::new(), |_| {}).is_err());
+    assert_eq!(task_queue::len(), 2);
+
+    test.tick(Duration::from_millis(15));
+    assert_eq!(*ran.lock().unwrap(), [Realm::Server]);
+    menu.eval::<()>("think()").unwrap();
+    assert_eq!(*ran.lock().unwrap(), [Realm::Server, Realm::Menu]);
+
+    // closing the default realm moves `wait_lua_tick` to the next one open
+    task_queue::unload(lua);
+    assert_eq!(task_queue::default_realm(), Realm::Menu);
+    gmod::wait_lua_tick(String::new(), record(&ran)).unwrap();
+    menu.eval::<()>("think()").unwrap();
+    assert_eq!(ran.lock().unwrap().last(), Some(&Realm::Menu));
+
+    task_queue::load(lua);
+    task_queue::unload(menu);
+    assert_eq!(task_queue::default_realm(), Realm::Server);
+    assert!(menu.eval::<bool>("return think == nil").unwrap());
+    assert!(task_queue::wait_lua_tick_in(Realm::Menu, String::new(), |_| {}).is_err());
+
+    close(menu);
+}
+
+#[test]
+fn lifecycle_counts_one_load_for_every_state() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    let menu = menu_state();
+
+    let ctx = lifecycle::opened(lua);
+    assert_eq!(lifecycle::opened(menu), ctx);
+    assert_eq!(lifecycle::open_realms(), [Realm::Server, Realm::Menu]);
+    assert!(lifecycle::is_open_in(Realm::Menu));
+
+    assert!(!lifecycle::closing(menu));
+    assert!(lifecycle::closing(lua));
+    assert!(lifecycle::open_realms().is_empty());
+
+    // opening again after every state closed is a reload
+    assert_eq!(lifecycle::opened(menu).load_count, ctx.load_count + 1);
+    assert!(lifecycle::closing(menu));
+
+    close(menu);
+}
+
+#[test]
+fn references_belong_to_the_state_they_were_made_in() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    let menu = menu_state();
+    task_queue::load(menu);
+
+    // the registry slots of the two states don't line up
+    menu.push_string("taken");
+    let _taken = LuaRef::new(menu);
+
+    const NAME: &str = "PlayerTick";
+    for state in [lua, menu, lua, menu] {
+        state.push_interned(NAME);
+        assert_eq!(state.get_string(-1).as_deref(), Some(NAME));
+        state.pop();
+    }
+    assert_eq!(intern::stats().entries, 2);
+
+    // cleared with its own state only
+    intern::clear(menu);
+    assert_eq!(intern::stats().entries, 1);
+    lua.push_interned(NAME);
+    assert_eq!(lua.get_string(-1).as_deref(), Some(NAME));
+    lua.pop();
+
+    // released in the menu state, on its next tick
+    menu.push_string("released");
+    let r#ref = LuaRef::new(menu);
+    assert_eq!(r#ref.realm(), Realm::Menu);
+    assert_eq!(menu.sendable().realm(), Realm::Menu);
+    let raw = r#ref.raw();
+    drop(r#ref);
+    test.tick(Duration::from_millis(15));
+    menu.eval::<()>("think()").unwrap();
+    menu.push_string("reused");
+    assert_eq!(menu.reference(), raw);
+
+    task_queue::unload(menu);
+    close(menu);
+}
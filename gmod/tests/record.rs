@@ -0,0 +1,97 @@
+//! `gmod::record`, recording operations on the test state and replaying them against it.
+
+#![cfg(all(feature = "testing", feature = "record"))]
+
+use gmod::{
+    lua::{State, LUA_TNUMBER, LUA_TSTRING},
+    record,
+    testing::TestState,
+};
+
+#[gmod::lua_function]
+fn twice(lua: State) -> i32 {
+    // recorded one call deep, and run again by replaying the call
+    let n = lua.to_number(1);
+    lua.push_number(n * 2.0);
+    1
+}
+
+/// Describes the values on the stack.
+fn stack(lua: State) -> Vec<String> {
+    (1..=lua.get_top())
+        .map(|i| match lua.lua_type(i) {
+            LUA_TNUMBER => lua.to_number(i).to_string(),
+            LUA_TSTRING => lua.get_string(i).unwrap().into_owned(),
+            ty => lua.lua_type_name(ty).into_owned(),
+        })
+        .collect()
+}
+
+unsafe fn record_operations(lua: State) {
+    let thread = lua.coroutine_new();
+    thread.push_number(1.0);
+
+    lua.new_table();
+    lua.push_string("value");
+    lua.set_field(-2, c"key");
+    lua.new_table();
+    lua.set_metatable(-2);
+    lua.push_value(-1);
+    let r#ref = lua.reference();
+    lua.from_reference(r#ref);
+    lua.dereference(r#ref);
+
+    lua.get_global(c"twice");
+    lua.push_number(21.0);
+    lua.call(1, 1);
+
+    lua.get_global(c"fail");
+    if lua.pcall(0, 0, 0).is_err() {
+        lua.push_string("caught");
+    }
+
+    lua.new_table();
+    lua.push_nil();
+    while lua.next(-2) != 0 {
+        lua.pop();
+    }
+}
+
+#[test]
+fn replays_the_stack_of_each_state() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(r#"function fail() error("boom") end"#).unwrap();
+    lua.push_function(twice);
+    lua.set_global(c"twice");
+
+    let path = std::env::temp_dir().join(format!("gmod-rs-record-test-{}", std::process::id()));
+    record::start(&path).unwrap();
+    unsafe { record_operations(lua) };
+    record::stop().unwrap();
+
+    let recorded = stack(lua);
+    assert_eq!(recorded.last().map(String::as_str), Some("table"));
+    assert!(recorded.iter().any(|value| value == "42"));
+    assert!(recorded.iter().any(|value| value.contains("caught")));
+    assert_eq!(record::states(&path).unwrap(), [0, 1]);
+
+    lua.set_top(0);
+    unsafe { record::replay(lua, &path, 0) }.unwrap();
+    assert_eq!(stack(lua), recorded);
+
+    // the error `fail` raised when recorded is missing when replayed
+    lua.set_top(0);
+    test.exec("function fail() end").unwrap();
+    let err = unsafe { record::replay(lua, &path, 0) }.unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("raised an error when it was recorded, but succeeded"),
+        "{err}"
+    );
+
+    lua.set_top(0);
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,120 @@
+//! `gmod::serde` round trips and edge cases.
+
+#![cfg(all(feature = "testing", feature = "serde"))]
+
+use std::{collections::BTreeMap, time::Duration};
+
+use gmod::{
+    serde::{from_lua, to_lua},
+    testing::TestState,
+};
+
+/// Tables of tables, as deep as they go.
+#[derive(Debug)]
+struct Nested;
+
+impl<'de> serde::Deserialize<'de> for Nested {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BTreeMap::<String, Nested>::deserialize(deserializer).map(|_| Nested)
+    }
+}
+
+#[test]
+fn round_trips() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    let map = BTreeMap::from([
+        ("a".to_string(), vec![1.5, -2.0]),
+        ("b".to_string(), vec![]),
+    ]);
+    to_lua(lua, &map).unwrap();
+    assert_eq!(
+        from_lua::<BTreeMap<String, Vec<f64>>>(lua, -1).unwrap(),
+        map
+    );
+    lua.pop();
+
+    // structs, and i64s as far as doubles are exact
+    let duration = Duration::new(1 << 40, 5);
+    to_lua(lua, &duration).unwrap();
+    assert_eq!(from_lua::<Duration>(lua, -1).unwrap(), duration);
+    lua.pop();
+
+    to_lua(lua, &(-((1i64 << 53) - 1), f64::INFINITY, 'é', None::<u8>)).unwrap();
+    let (min, inf, char, none) = from_lua::<(i64, f64, char, Option<u8>)>(lua, -1).unwrap();
+    assert_eq!(
+        (min, inf, char, none),
+        (-((1i64 << 53) - 1), f64::INFINITY, 'é', None)
+    );
+    lua.pop();
+
+    // enums are { Variant = value }
+    to_lua(lua, &Err::<u32, _>("nope")).unwrap();
+    lua.set_global(c"result");
+    assert_eq!(test.eval::<String>("result.Err").unwrap(), "nope");
+    lua.get_global(c"result");
+    assert_eq!(
+        from_lua::<Result<u32, String>>(lua, -1).unwrap(),
+        Err("nope".to_string())
+    );
+    lua.pop();
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn reads_lua_tables() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    test.exec("value = { alice = { 20, true }, [\"bob\"] = { 3, false } }")
+        .unwrap();
+    lua.get_global(c"value");
+    assert_eq!(
+        from_lua::<BTreeMap<String, (u8, bool)>>(lua, -1).unwrap(),
+        BTreeMap::from([
+            ("alice".to_string(), (20, true)),
+            ("bob".to_string(), (3, false))
+        ])
+    );
+    assert_eq!(lua.get_top(), 1);
+}
+
+#[test]
+fn edge_cases() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    let err = to_lua(lua, &vec![1, u64::MAX]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "18446744073709551615 can't be represented exactly by a Lua number"
+    );
+    assert_eq!(lua.get_top(), 0);
+
+    lua.push_number(1.5);
+    assert!(from_lua::<u8>(lua, -1).is_err());
+    lua.push_number(300.0);
+    assert!(from_lua::<u8>(lua, -1).is_err());
+    lua.push_number(f64::NAN);
+    assert!(from_lua::<f64>(lua, -1).unwrap().is_nan());
+    lua.pop_n(3);
+
+    test.exec("recursive = {} recursive.self = recursive")
+        .unwrap();
+    lua.get_global(c"recursive");
+    let err = from_lua::<BTreeMap<String, BTreeMap<String, ()>>>(lua, -1).unwrap_err();
+    assert_eq!(err.to_string(), "expected nil, got table");
+    let err = from_lua::<Nested>(lua, -1).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "tables are nested too deeply, or contain themselves"
+    );
+    assert_eq!(lua.get_top(), 1);
+}
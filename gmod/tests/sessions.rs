@@ -0,0 +1,90 @@
+//! `gmod::sessions`, persisting to a temporary directory, with players faked in Lua.
+
+#![cfg(feature = "testing")]
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gmod::{sessions, testing::TestState};
+
+const SETUP: &str = r#"
+local Player = {}
+Player.__index = Player
+function Player:SteamID64() return self.steamid64 end
+function spawn(steamid64) hook.Run("PlayerInitialSpawn", setmetatable({ steamid64 = steamid64 }, Player)) end
+function leave(steamid64) hook.Run("PlayerDisconnected", setmetatable({ steamid64 = steamid64 }, Player)) end
+"#;
+
+const RECENT: u64 = 76561197960265729;
+const STALE: u64 = 76561197960265730;
+const OLD: u64 = 76561197960265731;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[test]
+fn sessions_resume_within_the_grace_period_only() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(SETUP).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("gmod-rs-sessions-test-{}", std::process::id()));
+    let persisted = dir.join(sessions::PERSIST_PATH);
+    std::fs::create_dir_all(persisted.parent().unwrap()).unwrap();
+    let now = now();
+    std::fs::write(
+        &persisted,
+        format!(
+            "{RECENT} {} {}\n{STALE} {} {}\ntotal {OLD} 50\n",
+            now - 100,
+            now - 10,
+            now - 5000,
+            now - 4000,
+        ),
+    )
+    .unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+    sessions::load(lua);
+
+    // the offline time of the stale session isn't counted
+    assert_eq!(sessions::total_playtime(STALE), 1000);
+    assert_eq!(sessions::total_playtime(OLD), 50);
+
+    test.exec(&format!("spawn('{RECENT}') spawn('{STALE}')"))
+        .unwrap();
+    // the resumed session carries its 90 seconds of playtime, without the 10 seconds spent away
+    let resumed = sessions::current(RECENT).unwrap();
+    assert!(resumed.joined_at >= now);
+    assert_eq!(resumed.accumulated, 90);
+    assert!(sessions::current(STALE).unwrap().joined_at >= now);
+    assert!(sessions::total_playtime(STALE) >= 1000);
+
+    // the playtime is kept when leaving
+    test.exec(&format!("leave('{RECENT}')")).unwrap();
+    assert_eq!(sessions::current(RECENT), None);
+    assert!((90..100).contains(&sessions::total_playtime(RECENT)));
+
+    sessions::flush().unwrap();
+    let contents = std::fs::read_to_string(&persisted).unwrap();
+    let recent = contents
+        .lines()
+        .find(|line| line.starts_with(&format!("{RECENT} ")))
+        .unwrap();
+    assert!(recent.ends_with(" 90"), "{contents}");
+    assert!(
+        contents.contains(&format!("total {STALE} 1000\n")),
+        "{contents}"
+    );
+    assert!(
+        contents.contains(&format!("total {OLD} 50\n")),
+        "{contents}"
+    );
+
+    sessions::unload(lua);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
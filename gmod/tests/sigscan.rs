@@ -0,0 +1,38 @@
+#![cfg(all(feature = "sigscan", target_os = "linux"))]
+
+use gmod::sigscan::{self, Pattern};
+
+#[test]
+fn sigscan() {
+    // the test binary links to libc, whose path is something like /lib/x86_64-linux-gnu/libc.so.6
+    let strlen = libc::strlen as *const u8;
+    let bytes = unsafe { std::slice::from_raw_parts(strlen, 16) };
+    let signature: Vec<String> = bytes
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| {
+            if i % 4 == 3 {
+                "??".to_owned()
+            } else {
+                format!("{byte:02X}")
+            }
+        })
+        .collect();
+    let signature = signature.join(" ");
+
+    let found = sigscan::find("libc.so.6", &signature)
+        .unwrap()
+        .expect("strlen wasn't found in libc");
+    assert!(found as *const u8 <= strlen);
+    let found = unsafe { std::slice::from_raw_parts(found, 16) };
+    assert!(found
+        .iter()
+        .zip(bytes)
+        .enumerate()
+        .all(|(i, (a, b))| i % 4 == 3 || a == b));
+
+    assert_eq!(sigscan::find("not_loaded.so", &signature).unwrap(), None);
+    assert!(sigscan::find("libc.so.6", "55 8B XX").is_err());
+    assert!(Pattern::parse("55 8B XX").is_err());
+    assert!(Pattern::parse("?? ??").is_err());
+}
@@ -0,0 +1,71 @@
+//! `gmod::sql` against a stand-in of the `sql` library, which records the queries it gets.
+
+#![cfg(all(feature = "testing", feature = "sql"))]
+
+use gmod::{sql, testing::TestState};
+
+const FAKE_SQL: &str = r#"
+sql = { queries = {} }
+function sql.Query(query)
+    table.insert(sql.queries, query)
+    if query:find("^SELECT") then
+        return {
+            { steamid = "STEAM_0:1:1", reason = "it's a test", expires = "1700000000", permanent = "0" },
+            { steamid = "STEAM_0:1:2", expires = "0", permanent = "1" },
+        }
+    elseif query:find("^BROKEN") then
+        return false
+    end
+end
+function sql.LastError() return "near \"BROKEN\": syntax error" end
+"#;
+
+#[test]
+fn sql() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec(FAKE_SQL).unwrap();
+
+    let rows = sql::query(
+        lua,
+        "SELECT * FROM bans WHERE reason = ? AND note = '?'",
+        &[&"it's"],
+    )
+    .unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].get_as::<String>("steamid").unwrap(), "STEAM_0:1:1");
+    assert_eq!(
+        rows[0]
+            .get_as::<Option<String>>("reason")
+            .unwrap()
+            .as_deref(),
+        Some("it's a test")
+    );
+    assert_eq!(rows[0].get_as::<i64>("expires").unwrap(), 1700000000);
+    assert!(!rows[0].get_as::<bool>("permanent").unwrap());
+    assert_eq!(rows[1].get_as::<Option<String>>("reason").unwrap(), None);
+    assert!(rows[1].get_as::<bool>("permanent").unwrap());
+    assert!(rows[1].get_as::<i64>("steamid").is_err());
+
+    sql::execute(
+        lua,
+        "INSERT INTO bans VALUES (?, ?, ?)",
+        &[&"a\0b", &None::<String>, &1.5],
+    )
+    .unwrap();
+    assert_eq!(
+        test.eval::<(String, String)>("sql.queries[1], sql.queries[2]")
+            .unwrap(),
+        (
+            "SELECT * FROM bans WHERE reason = 'it''s' AND note = '?'".to_owned(),
+            "INSERT INTO bans VALUES ('a', NULL, 1.5)".to_owned()
+        )
+    );
+
+    assert!(sql::execute(lua, "INSERT INTO bans VALUES (?)", &[]).is_err());
+    let err = sql::execute(lua, "BROKEN", &[]).unwrap_err();
+    assert_eq!(err.to_string(), "near \"BROKEN\": syntax error (in BROKEN)");
+    assert_eq!(lua.get_top(), 0);
+}
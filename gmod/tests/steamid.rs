@@ -0,0 +1,66 @@
+//! Steam ID parsing, and the Lua functions of `steamid::register`.
+
+use gmod::steamid::SteamId;
+
+#[test]
+fn parse() {
+    let id = SteamId::from_account_id(8983981);
+    for str in [
+        "STEAM_0:1:4491990",
+        "STEAM_1:1:4491990",
+        "76561197969249709",
+        "[U:1:8983981]",
+        "U:1:8983981",
+        " 76561197969249709\n",
+    ] {
+        assert_eq!(SteamId::parse(str).unwrap(), id, "{str}");
+    }
+    assert_eq!(id.steamid(), "STEAM_0:1:4491990");
+    assert_eq!(id.to_string(), "76561197969249709");
+
+    for str in [
+        "",
+        "STEAM_0:2:4491990",
+        "STEAM_0:1:4491990:1",
+        "STEAM_0:1:4294967295",
+        "[G:1:8983981]",
+        "76561197960265727",
+        "80000000000000000",
+        "-1",
+    ] {
+        assert!(SteamId::parse(str).is_err(), "{str}");
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn lua_functions() {
+    let Some(test) = gmod::testing::TestState::new_or_skip() else {
+        return;
+    };
+    gmod::steamid::register(test.lua(), c"steamid");
+
+    assert_eq!(
+        test.eval::<String>("steamid.To64('STEAM_0:1:4491990')")
+            .unwrap(),
+        "76561197969249709"
+    );
+    assert_eq!(
+        test.eval::<String>("steamid.ToSteamID('76561197969249709')")
+            .unwrap(),
+        "STEAM_0:1:4491990"
+    );
+    assert_eq!(
+        test.eval::<String>("steamid.ToSteamID3(steamid.FromAccountID(8983981))")
+            .unwrap(),
+        "[U:1:8983981]"
+    );
+    assert_eq!(
+        test.eval::<f64>("steamid.ToAccountID('[U:1:8983981]')")
+            .unwrap(),
+        8983981.0
+    );
+    assert!(!test.eval::<bool>("steamid.IsValid('BOT')").unwrap());
+    let err = test.exec("steamid.To64('BOT')").unwrap_err();
+    assert!(err.to_string().contains("invalid Steam ID"), "{err}");
+}
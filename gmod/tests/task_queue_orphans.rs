@@ -0,0 +1,37 @@
+//! `DrainPolicy::Orphan` with handlers that register more handlers.
+
+#![cfg(feature = "testing")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use gmod::{
+    lua::task_queue::{self, DrainPolicy},
+    testing::TestState,
+};
+
+static CLAIMED: AtomicUsize = AtomicUsize::new(0);
+
+#[test]
+fn handlers_can_add_handlers() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+
+    task_queue::set_drain_policy(DrainPolicy::Orphan);
+    task_queue::add_orphan_handler(|_, callback| {
+        // would deadlock if the handlers were still locked
+        task_queue::add_orphan_handler(|_, callback| Some(callback));
+        CLAIMED.fetch_add(1, Ordering::Relaxed);
+        drop(callback);
+        None
+    });
+
+    for _ in 0..2 {
+        gmod::wait_lua_tick(String::new(), |_| {}).unwrap();
+    }
+    task_queue::unload(lua);
+    assert_eq!(CLAIMED.load(Ordering::Relaxed), 2);
+
+    task_queue::set_drain_policy(DrainPolicy::Drop);
+}
@@ -0,0 +1,106 @@
+//! Queues callbacks from many threads while the task queue is opened and closed over and over, like a module being loaded and unloaded while its background threads keep running.
+//!
+//! Every callback must either run while the queue is open or be dropped, nothing may deadlock, and nothing may run after the queue closed.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use gmod::lua::{task_queue, State};
+
+const THREADS: usize = 8;
+const CYCLES: usize = 200;
+const TICKS_PER_CYCLE: usize = 20;
+
+/// Counts live callbacks, and queues a callback when dropped like `LuaRef` does.
+struct Tracked(Arc<AtomicUsize>);
+
+impl Tracked {
+    fn new(alive: &Arc<AtomicUsize>) -> Self {
+        alive.fetch_add(1, Ordering::SeqCst);
+        Self(alive.clone())
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        let _ = task_queue::wait_lua_tick(String::new(), |_| {});
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn soak() {
+    let alive = Arc::new(AtomicUsize::new(0));
+    let ran = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let producers: Vec<_> = (0..THREADS)
+        .map(|i| {
+            let (alive, ran, stop) = (alive.clone(), ran.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut n = 0usize;
+                while !stop.load(Ordering::SeqCst) {
+                    n += 1;
+                    let tracked = Tracked::new(&alive);
+                    let ran = ran.clone();
+                    let requeue = (n + i).is_multiple_of(7);
+                    let _ = task_queue::wait_lua_tick(String::new(), move |_| {
+                        assert!(task_queue::is_open(), "callback ran after unload");
+                        if requeue {
+                            let tracked = tracked;
+                            let _ = task_queue::wait_lua_tick(String::new(), move |_| drop(tracked));
+                        }
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                    if n.is_multiple_of(64) {
+                        thread::yield_now();
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let (done_tx, done_rx) = mpsc::channel();
+    let driver = thread::spawn(move || {
+        // there's no Lua state to run the queue with, the callbacks never touch it
+        let lua = State(std::ptr::null_mut());
+        for cycle in 0..CYCLES {
+            task_queue::open();
+            for tick in 0..TICKS_PER_CYCLE {
+                if cycle.is_multiple_of(10) && tick == TICKS_PER_CYCLE / 2 {
+                    // unloading from inside a callback must stop the rest of the batch
+                    let _ = task_queue::wait_lua_tick(String::new(), |_| {
+                        task_queue::close();
+                    });
+                }
+                task_queue::run_callbacks_unprotected(lua);
+                thread::yield_now();
+            }
+            task_queue::close();
+            assert!(!task_queue::is_open());
+            assert_eq!(task_queue::len(), 0);
+        }
+        done_tx.send(()).unwrap();
+    });
+
+    done_rx
+        .recv_timeout(Duration::from_secs(120))
+        .expect("the task queue deadlocked");
+    driver.join().unwrap();
+
+    stop.store(true, Ordering::SeqCst);
+    for producer in producers {
+        producer.join().unwrap();
+    }
+
+    // callbacks queued after the last close were dropped right away
+    task_queue::close();
+    assert_eq!(alive.load(Ordering::SeqCst), 0, "callbacks leaked");
+    assert!(ran.load(Ordering::SeqCst) > 0);
+}
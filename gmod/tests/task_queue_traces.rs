@@ -0,0 +1,65 @@
+//! Errors raised by queued callbacks, printed with where they were queued from.
+
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use gmod::{
+    lua::{self, task_queue, State},
+    testing::TestState,
+};
+
+#[gmod::lua_function]
+fn queue_failing(_lua: State) {
+    let _ = gmod::wait_lua_tick(String::new(), |lua| {
+        lua.error_with_location("queued from a Lua call")
+    });
+}
+
+#[test]
+fn errors_print_where_callbacks_were_queued_from() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    // the only test of this binary, so this thread can be the Lua thread
+    lua::set_main_thread();
+
+    // without capture, only the given traceback is printed
+    let _ = gmod::wait_lua_tick("given traceback".to_string(), |lua| {
+        lua.error_with_location("not captured")
+    });
+    test.tick(Duration::from_millis(15));
+    let errors = test.errors();
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert!(
+        errors[0].contains("not captured\ngiven traceback"),
+        "{}",
+        errors[0]
+    );
+    assert!(!errors[0].contains("queued from"), "{}", errors[0]);
+
+    task_queue::set_capture_tracebacks(true);
+    lua.push_function(queue_failing);
+    lua.set_global(c"QueueFailing");
+    test.exec("local function caller() QueueFailing() end caller()")
+        .unwrap();
+    let _ = gmod::wait_lua_tick(String::new(), |_| {});
+    test.tick(Duration::from_millis(15));
+    task_queue::set_capture_tracebacks(false);
+
+    let errors = test.errors();
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    let error = &errors[0];
+    assert!(error.contains("queued from a Lua call"), "{error}");
+    assert!(
+        error.contains("queued from Lua, stack traceback:"),
+        "{error}"
+    );
+    assert!(error.contains("in function 'caller'"), "{error}");
+    assert!(
+        error.contains("queued from Rust, stack traceback:"),
+        "{error}"
+    );
+    assert!(error.contains("queue_failing"), "{error}");
+}
@@ -0,0 +1,101 @@
+//! Runs the GMod stand-ins of `gmod::testing` on a real LuaJIT. Skipped when LuaJIT can't be found, see `gmod::testing`.
+
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use gmod::testing::TestState;
+
+#[test]
+fn eval_and_exec() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+
+    assert_eq!(test.eval::<f64>("1 + 2").unwrap(), 3.0);
+    assert_eq!(
+        test.eval::<(String, bool)>("'a' .. 'b', SERVER").unwrap(),
+        ("ab".to_owned(), true)
+    );
+
+    test.exec("GLOBAL = 42").unwrap();
+    assert_eq!(test.eval::<f64>("GLOBAL").unwrap(), 42.0);
+
+    assert!(test
+        .exec("error('boom')")
+        .unwrap_err()
+        .to_string()
+        .contains("boom"));
+    assert!(test.exec("this isn't lua").is_err());
+    assert_eq!(test.lua().get_top(), 0);
+}
+
+#[test]
+fn timers_and_hooks() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+
+    test.exec(
+        r#"
+        THINKS, SIMPLE, REPEATED = 0, 0, 0
+        hook.Add("Think", "count", function() THINKS = THINKS + 1 end)
+        timer.Simple(1, function() SIMPLE = SIMPLE + 1 end)
+        timer.Create("repeat", 0.5, 3, function() REPEATED = REPEATED + 1 end)
+        "#,
+    )
+    .unwrap();
+
+    test.tick(Duration::from_millis(500));
+    assert_eq!(
+        test.eval::<(f64, f64, f64)>("THINKS, SIMPLE, REPEATED")
+            .unwrap(),
+        (1.0, 0.0, 1.0)
+    );
+
+    for _ in 0..4 {
+        test.tick(Duration::from_millis(500));
+    }
+    assert_eq!(
+        test.eval::<(f64, f64, f64)>("THINKS, SIMPLE, REPEATED")
+            .unwrap(),
+        (5.0, 1.0, 3.0)
+    );
+    assert_eq!(test.eval::<f64>("CurTime()").unwrap(), 2.5);
+    assert!(!test.eval::<bool>("timer.Exists('repeat')").unwrap());
+}
+
+#[test]
+fn task_queue_runs_on_tick() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+
+    gmod::wait_lua_tick(String::new(), |lua| {
+        lua.push_string("from rust");
+        lua.set_global(c"QUEUED");
+    })
+    .unwrap();
+    assert!(test.eval::<Option<String>>("QUEUED").unwrap().is_none());
+
+    test.tick(Duration::from_millis(15));
+    assert_eq!(test.eval::<String>("QUEUED").unwrap(), "from rust");
+}
+
+#[test]
+fn errors_are_collected() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+
+    test.exec("ErrorNoHalt('first')").unwrap();
+    test.exec("timer.Simple(0, function() error('second') end)")
+        .unwrap();
+    test.tick(Duration::ZERO);
+
+    let errors = test.errors();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0], "first");
+    assert!(errors[1].contains("second"));
+    assert!(test.errors().is_empty());
+}
@@ -0,0 +1,42 @@
+//! `gmod::timer` on the timer stand-in of the test state.
+
+#![cfg(feature = "testing")]
+
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+use gmod::{testing::TestState, timer};
+
+#[test]
+fn a_panicking_callback_keeps_running() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    gmod::panic::install();
+
+    let calls = Rc::new(Cell::new(0));
+    let counted = calls.clone();
+    let handle = timer::create(lua, Duration::from_secs(1), 0, move |_| {
+        counted.set(counted.get() + 1);
+        if counted.get() == 1 {
+            panic!("first call");
+        }
+    });
+
+    test.tick(Duration::from_secs(1));
+    let errors = test.errors();
+    assert!(
+        errors
+            .iter()
+            .any(|err| err.contains("panicked at") && err.contains("first call")),
+        "{errors:?}"
+    );
+
+    test.tick(Duration::from_secs(1));
+    assert_eq!(calls.get(), 2);
+    assert!(handle.is_active());
+    assert!(test.errors().is_empty());
+
+    handle.remove(lua);
+    gmod::panic::uninstall();
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "detour")]
+
+use std::{ffi::c_void, sync::OnceLock};
+
+use gmod::vtable::{self, VTable, VTableHook};
+
+type Speak = extern "C" fn(*const Animal) -> u32;
+
+#[repr(C)]
+struct Animal {
+    vtable: &'static [Speak; 2],
+    legs: u32,
+}
+
+extern "C" fn legs(this: *const Animal) -> u32 {
+    unsafe { (*this).legs }
+}
+
+extern "C" fn eyes(_this: *const Animal) -> u32 {
+    2
+}
+
+// relocated, then read-only like the vtables of C++ classes
+static ANIMAL_VTABLE: [Speak; 2] = [legs, eyes];
+
+static HOOK: OnceLock<VTableHook<Speak>> = OnceLock::new();
+
+extern "C" fn more_legs(this: *const Animal) -> u32 {
+    (HOOK.get().unwrap().original())(this) * 10
+}
+
+fn call(animal: &Animal, index: usize) -> u32 {
+    let vtable = unsafe { VTable::from_instance(animal as *const Animal as *const c_void) };
+    let method: Speak = unsafe { vtable.get_method(index) };
+    method(animal)
+}
+
+#[test]
+fn vtable() {
+    let dog = Animal {
+        vtable: &ANIMAL_VTABLE,
+        legs: 4,
+    };
+    assert_eq!((call(&dog, 0), call(&dog, 1)), (4, 2));
+
+    let vtable = unsafe { VTable::from_instance(&dog as *const Animal as *const c_void) };
+    let hook = unsafe { vtable.hook_method(0, more_legs as Speak) }.unwrap();
+    assert!(unsafe { vtable.hook_method(0, more_legs as Speak) }.is_err());
+    let hook = HOOK.get_or_init(|| hook);
+    assert!(hook.is_active());
+    assert_eq!((call(&dog, 0), call(&dog, 1)), (40, 2));
+
+    // what #[gmod13_close] does
+    vtable::unload();
+    assert!(!hook.is_active());
+    assert_eq!(call(&dog, 0), 4);
+
+    let hook = unsafe { vtable.hook_method(1, more_legs as Speak) }.unwrap();
+    drop(hook);
+    assert_eq!(call(&dog, 1), 2);
+}
+
+extern "C" fn no_legs(_this: *const Animal) -> u32 {
+    0
+}
+
+#[test]
+fn writable_vtable_stays_writable() {
+    // a vtable sharing its page with data that's written to afterwards
+    #[repr(C, align(64))]
+    struct Shared {
+        vtable: [Speak; 2],
+        counter: u32,
+    }
+    let shared = Box::leak(Box::new(Shared {
+        vtable: [legs, eyes],
+        counter: 0,
+    }));
+    let cat = Animal {
+        vtable: unsafe { &*std::ptr::addr_of!(shared.vtable) },
+        legs: 4,
+    };
+
+    let vtable = unsafe { VTable::from_instance(&cat as *const Animal as *const c_void) };
+    let hook = unsafe { vtable.hook_method(0, no_legs as Speak) }.unwrap();
+    assert_eq!(call(&cat, 0), 0);
+    shared.counter += 1;
+
+    drop(hook);
+    assert_eq!(call(&cat, 0), 4);
+    shared.counter += 1;
+    assert_eq!(shared.counter, 2);
+}
@@ -0,0 +1,36 @@
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use gmod::{
+    testing::TestState,
+    watchdog::{self, OnTimeout},
+};
+
+#[test]
+fn watchdog() {
+    let Some(test) = TestState::new_or_skip() else {
+        return;
+    };
+    let lua = test.lua();
+    test.exec("jit.off()").unwrap();
+
+    {
+        let _watchdog = watchdog::guard(lua, Duration::from_millis(20), OnTimeout::Error).unwrap();
+        let err = test.exec("while true do end").unwrap_err();
+        assert!(err.to_string().contains("longer than its budget of 20ms"));
+    }
+    assert!(!watchdog::is_armed());
+
+    {
+        let _watchdog = watchdog::guard(lua, Duration::from_millis(20), OnTimeout::Log).unwrap();
+        test.exec("local start = os.clock() while os.clock() - start < 0.1 do end")
+            .unwrap();
+    }
+    let errors = test.errors();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("longer than its budget of 20ms"));
+
+    let _watchdog = watchdog::guard(lua, Duration::from_secs(60), OnTimeout::Error).unwrap();
+    test.exec("for i = 1, 100000 do end").unwrap();
+}